@@ -0,0 +1,371 @@
+//
+// Dumps inside zip and tar archives, as found in diagnostic bundles, named
+// `<archive>!<member>` (e.g. support-bundle.zip!heap/app.hprof) or by the
+// archive alone when it holds a single .hprof (or .phd) file. Members stored as they
+// are (all of those of a tar, and uncompressed ones of a zip) are read in
+// place, as the range of the archive they take up: every offset of the
+// parser is then one into the archive. Deflated zip members have to be
+// inflated to a temporary file first, since the parser seeks around.
+//
+// https://pkware.cachefly.net/webdocs/casestudies/APPNOTE.TXT
+// https://www.gnu.org/software/tar/manual/html_node/Standard.html
+//
+use crate::external::TempDir;
+use crate::inflate;
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::Path;
+
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub name: String,
+    // Where its data is in the archive, and how long it is there.
+    pub offset: u64,
+    pub len: u64,
+    // Whether it is deflated, with the size and CRC-32 once inflated.
+    pub deflated: bool,
+    pub size: u64,
+    pub crc: u32,
+}
+
+// Where the bytes of a dump are: a range of the file, or all of it.
+#[derive(Debug, Clone)]
+pub struct Location {
+    pub path: String,
+    pub range: Option<Range<u64>>,
+}
+
+fn le(bytes: &[u8]) -> u64 {
+    bytes.iter().rev().fold(0, |n, &b| (n << 8) | b as u64)
+}
+
+fn read_at(file: &mut File, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0; len];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+// The members of a zip, from its central directory.
+fn zip_members(file: &mut File) -> Result<Vec<Member>, String> {
+    let io = |e: io::Error| e.to_string();
+    let file_len = file.metadata().map_err(io)?.len();
+    let tail_len = file_len.min(22 + 0xffff);
+    let tail = read_at(file, file_len - tail_len, tail_len as usize).map_err(io)?;
+    let end = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&i| tail[i..i + 4] == [b'P', b'K', 5, 6])
+        .ok_or("no end of central directory")?;
+    let eocd = &tail[end..];
+    let (mut count, mut directory) = (le(&eocd[10..12]), le(&eocd[16..20]));
+    // Zip64 archives (past 4 GiB or 65535 members) keep them elsewhere.
+    if end >= 20 && tail[end - 20..end - 16] == [b'P', b'K', 6, 7] {
+        let zip64 = le(&tail[end - 12..end - 4]);
+        let record = read_at(file, zip64, 56).map_err(io)?;
+        if record[..4] != [b'P', b'K', 6, 6] {
+            return Err("bad zip64 end of central directory".to_string());
+        }
+        count = le(&record[32..40]);
+        directory = le(&record[48..56]);
+    }
+
+    let mut reader = BufReader::new(&mut *file);
+    reader.seek(SeekFrom::Start(directory)).map_err(io)?;
+    let mut members = Vec::new();
+    for _ in 0..count {
+        let mut header = [0u8; 46];
+        reader.read_exact(&mut header).map_err(io)?;
+        if header[..4] != [b'P', b'K', 1, 2] {
+            return Err("bad central directory entry".to_string());
+        }
+        let method = le(&header[10..12]);
+        let crc = le(&header[16..20]) as u32;
+        let mut len = le(&header[20..24]);
+        let mut size = le(&header[24..28]);
+        let (name_len, extra_len, comment_len) = (
+            le(&header[28..30]) as usize,
+            le(&header[30..32]) as usize,
+            le(&header[32..34]) as usize,
+        );
+        let mut local = le(&header[42..46]);
+        let mut rest = vec![0u8; name_len + extra_len + comment_len];
+        reader.read_exact(&mut rest).map_err(io)?;
+        let name = String::from_utf8_lossy(&rest[..name_len]).into_owned();
+        // The zip64 extra field has the 64-bit values of those set to ~0.
+        let mut extra = &rest[name_len..name_len + extra_len];
+        while extra.len() >= 4 {
+            let (id, data_len) = (le(&extra[..2]), le(&extra[2..4]) as usize);
+            let data = &extra[4..(4 + data_len).min(extra.len())];
+            if id == 1 {
+                let mut values = data.chunks_exact(8).map(le);
+                for field in [&mut size, &mut len, &mut local] {
+                    if *field == 0xffffffff {
+                        *field = values.next().ok_or("short zip64 extra field")?;
+                    }
+                }
+            }
+            extra = &extra[(4 + data_len).min(extra.len())..];
+        }
+        if method != 0 && method != 8 {
+            continue;
+        }
+        members.push(Member {
+            name,
+            offset: local,
+            len,
+            deflated: method == 8,
+            size,
+            crc,
+        });
+    }
+    drop(reader);
+    // The data follows the local header, whose extra field may differ.
+    for member in &mut members {
+        let local = read_at(file, member.offset, 30).map_err(io)?;
+        if local[..4] != [b'P', b'K', 3, 4] {
+            return Err(format!("bad local header of {}", member.name));
+        }
+        member.offset += 30 + le(&local[26..28]) + le(&local[28..30]);
+    }
+    Ok(members)
+}
+
+// A number of a tar header: octal, or base-256 for large ones (GNU).
+fn tar_number(field: &[u8]) -> u64 {
+    if field[0] & 0x80 != 0 {
+        return field[1..].iter().fold(0, |n, &b| (n << 8) | b as u64);
+    }
+    field
+        .iter()
+        .filter(|&&b| (b'0'..=b'7').contains(&b))
+        .fold(0, |n, &b| n * 8 + (b - b'0') as u64)
+}
+
+fn tar_string(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+//
+// The regular files of a tar, with their names from the ustar prefix, GNU
+// long name records or pax extended headers.
+//
+fn tar_members(file: &mut File) -> Result<Vec<Member>, String> {
+    let io = |e: io::Error| e.to_string();
+    let file_len = file.metadata().map_err(io)?.len();
+    let mut members = Vec::new();
+    let (mut offset, mut long_name, mut pax_size) = (0u64, None, None);
+    while offset + 512 <= file_len {
+        let header = read_at(file, offset, 512).map_err(io)?;
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        let len = pax_size
+            .take()
+            .unwrap_or_else(|| tar_number(&header[124..136]));
+        let data = offset + 512;
+        match header[156] {
+            b'L' => {
+                let name = read_at(file, data, len as usize).map_err(io)?;
+                long_name = Some(tar_string(&name));
+            }
+            b'x' => {
+                // Records of "<length> <key>=<value>\n".
+                let records = read_at(file, data, len as usize).map_err(io)?;
+                for record in String::from_utf8_lossy(&records).lines() {
+                    let (key, value) =
+                        match record.split_once(' ').and_then(|(_, r)| r.split_once('=')) {
+                            Some(kv) => kv,
+                            None => continue,
+                        };
+                    match key {
+                        "path" => long_name = Some(value.to_string()),
+                        "size" => pax_size = value.parse().ok(),
+                        _ => {}
+                    }
+                }
+            }
+            b'0' | 0 => {
+                let name = long_name.take().unwrap_or_else(|| {
+                    let (prefix, name) =
+                        (tar_string(&header[345..500]), tar_string(&header[..100]));
+                    if header[257..262] == *b"ustar" && !prefix.is_empty() {
+                        format!("{}/{}", prefix, name)
+                    } else {
+                        name
+                    }
+                });
+                members.push(Member {
+                    name,
+                    offset: data,
+                    len,
+                    deflated: false,
+                    size: len,
+                    crc: 0,
+                });
+            }
+            _ => long_name = None,
+        }
+        offset = data + len.div_ceil(512) * 512;
+    }
+    Ok(members)
+}
+
+//
+// The members of the archive at `path`, None if it is not one: zips start
+// with a local header (or are empty), tars have "ustar" in their first
+// header.
+//
+pub fn members(path: &str) -> Result<Option<Vec<Member>>, String> {
+    let mut file = File::open(path).map_err(|e| e.to_string())?;
+    let mut start = [0u8; 512];
+    let read = file.read(&mut start).map_err(|e| e.to_string())?;
+    if read >= 4 && (start[..4] == [b'P', b'K', 3, 4] || start[..4] == [b'P', b'K', 5, 6]) {
+        return zip_members(&mut file).map(Some);
+    }
+    if read == 512 && start[257..262] == *b"ustar" {
+        return tar_members(&mut file).map(Some);
+    }
+    Ok(None)
+}
+
+// The archive and member of `<archive>!<member>`, if the archive exists.
+pub fn split(spec: &str) -> Option<(&str, &str)> {
+    spec.match_indices('!')
+        .map(|(i, _)| (&spec[..i], &spec[i + 1..]))
+        .find(|(archive, _)| Path::new(archive).is_file())
+}
+
+// The member of an archive a spec names, the single .hprof (or .phd) one by
+// default.
+fn find(archive: &str, member: Option<&str>) -> Result<Option<Member>, String> {
+    let members = match members(archive)? {
+        Some(members) => members,
+        None if member.is_some() => return Err(format!("{} is not a zip or tar", archive)),
+        None => return Ok(None),
+    };
+    if let Some(name) = member {
+        return members
+            .into_iter()
+            .find(|m| m.name == name)
+            .map(Some)
+            .ok_or_else(|| format!("no {} in {}", name, archive));
+    }
+    let mut dumps: Vec<Member> = members
+        .into_iter()
+        .filter(|m| m.name.ends_with(".hprof") || m.name.ends_with(".phd"))
+        .collect();
+    match dumps.len() {
+        1 => Ok(dumps.pop()),
+        0 => Err(format!("no .hprof or .phd file in {}", archive)),
+        _ => Err(format!(
+            "several .hprof files in {}, name one as {}!<member>: {}",
+            archive,
+            archive,
+            dumps
+                .iter()
+                .map(|m| m.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+// Where the dump of a spec is, stored members being ranges of the archive.
+pub fn locate(spec: &str) -> Result<Location, String> {
+    let (path, member) = match split(spec) {
+        Some((archive, member)) => (archive, find(archive, Some(member))?),
+        None => (spec, None),
+    };
+    match member {
+        Some(m) if m.deflated => Err(format!(
+            "{} is compressed in the archive, give it as the dump to inflate it",
+            m.name
+        )),
+        Some(m) => Ok(Location {
+            path: path.to_string(),
+            range: Some(m.offset..m.offset + m.len),
+        }),
+        None => Ok(Location {
+            path: path.to_string(),
+            range: None,
+        }),
+    }
+}
+
+// A dump ready to be read, see prepare().
+pub struct Prepared {
+    // The spec to open it by.
+    pub spec: String,
+    // Its name for the results kept next to it (see cache.rs).
+    pub name: String,
+    // The directory of an inflated member, removed when dropped.
+    pub inflated: Option<TempDir>,
+}
+
+//
+// Resolves the dump given on the command line: an archive holding a single
+// dump stands for it, and deflated members are inflated to a temporary
+// directory (under `temp`) for the lifetime of the result.
+//
+pub fn prepare(spec: &str, temp: &Path) -> Result<Prepared, String> {
+    let (archive, member) = match split(spec) {
+        Some((archive, member)) => (archive, find(archive, Some(member))?),
+        None if Path::new(spec).is_file() => (spec, find(spec, None)?),
+        None => (spec, None),
+    };
+    let member = match member {
+        Some(member) => member,
+        None => {
+            return Ok(Prepared {
+                spec: spec.to_string(),
+                name: spec.to_string(),
+                inflated: None,
+            })
+        }
+    };
+    let spec = format!("{}!{}", archive, member.name);
+    // The sidecar of a member is next to the archive.
+    let name = format!("{}.{}", archive, member.name.replace('/', "."));
+    if !member.deflated {
+        return Ok(Prepared {
+            spec,
+            name,
+            inflated: None,
+        });
+    }
+
+    let dir = TempDir::new(temp);
+    let path = dir.file("dump.hprof");
+    let mut input = File::open(archive).map_err(|e| e.to_string())?;
+    input
+        .seek(SeekFrom::Start(member.offset))
+        .map_err(|e| e.to_string())?;
+    let input = input.take(member.len);
+    let mut output =
+        BufWriter::new(File::create(&path).map_err(|e| format!("{}: {}", path.display(), e))?);
+    eprintln!("inflating {} to {} ...", spec, path.display());
+    let (size, crc) =
+        inflate::inflate(input, &mut output).map_err(|e| format!("{}: {}", spec, e))?;
+    io::Write::flush(&mut output).map_err(|e| e.to_string())?;
+    if size != member.size || crc != member.crc {
+        return Err(format!("{}: inflated to a different size or CRC-32", spec));
+    }
+    Ok(Prepared {
+        spec: path.to_string_lossy().into_owned(),
+        name,
+        inflated: Some(dir),
+    })
+}
+
+// Opens the dump of a spec, positioned where it starts.
+pub fn open(spec: &str) -> io::Result<(File, Location)> {
+    let location = locate(spec).map_err(io::Error::other)?;
+    let mut file = File::open(&location.path)?;
+    if let Some(range) = &location.range {
+        file.seek(SeekFrom::Start(range.start))?;
+    }
+    Ok((file, location))
+}
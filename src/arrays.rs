@@ -0,0 +1,356 @@
+//
+// Analyses of array contents.
+//
+use crate::dominator::DominatorTree;
+use crate::heap::{FieldTag, HeapObject};
+use crate::snapshot::Snapshot;
+
+use std::collections::HashMap;
+
+// Number of bytes of zero elements at the end of a primitive array.
+pub fn zero_tail(data: &[u8], element_size: usize) -> usize {
+    let zeros = data.iter().rev().take_while(|&&b| b == 0).count();
+    zeros - zeros % element_size
+}
+
+#[derive(Debug)]
+pub struct ZeroTail {
+    // Class of the immediate dominator of the arrays.
+    pub owner: String,
+    pub class_name: String,
+    pub arrays: u64,
+    pub bytes: u64,
+    // Zero bytes at the end of the arrays.
+    pub wasted: u64,
+}
+
+//
+// The byte[], char[] and int[] arrays ending with at least `min_bytes` of
+// zeros (typically oversized buffers), grouped by owner and array type.
+//
+pub fn zero_tails(snapshot: &Snapshot, tree: &DominatorTree, min_bytes: u64) -> Vec<ZeroTail> {
+    let mut groups: HashMap<(String, String), ZeroTail> = HashMap::new();
+    for (node, object) in snapshot.objects.iter().enumerate() {
+        let array = match object {
+            HeapObject::PrimitiveArray(a) => a,
+            _ => continue,
+        };
+        if !matches!(
+            array.element_tag,
+            FieldTag::Byte | FieldTag::Char | FieldTag::Int
+        ) || !tree.is_reachable(node as u32)
+        {
+            continue;
+        }
+        let wasted = zero_tail(
+            &array.data,
+            array.element_tag.size(snapshot.id_size()) as usize,
+        );
+        if (wasted as u64) < min_bytes.max(1) {
+            continue;
+        }
+        let idom = tree.idom[node];
+        let owner = if idom == tree.virtual_root() {
+            "<GC root>".to_string()
+        } else {
+            snapshot.object_label(&snapshot.objects[idom as usize])
+        };
+        let class_name = snapshot.object_class_name(object);
+        let group = groups
+            .entry((owner.clone(), class_name.clone()))
+            .or_insert_with(|| ZeroTail {
+                owner,
+                class_name,
+                arrays: 0,
+                bytes: 0,
+                wasted: 0,
+            });
+        group.arrays += 1;
+        group.bytes += snapshot.shallow_size(object);
+        group.wasted += wasted as u64;
+    }
+    let mut groups: Vec<ZeroTail> = groups.into_values().collect();
+    groups.sort_by(|a, b| {
+        b.wasted
+            .cmp(&a.wasted)
+            .then_with(|| a.owner.cmp(&b.owner))
+            .then_with(|| a.class_name.cmp(&b.class_name))
+    });
+    groups
+}
+
+#[derive(Debug)]
+pub struct LargeArray {
+    pub node: u32,
+    pub length: u64,
+    pub bytes: u64,
+    // The immediate dominator, None for arrays held directly by GC roots.
+    pub owner: Option<u32>,
+    // For object arrays, the most common class of the non-null elements
+    // and how many elements are of it.
+    pub dominant_element: Option<(String, u64)>,
+}
+
+pub fn array_length(object: &HeapObject) -> Option<u64> {
+    match object {
+        HeapObject::ObjectArray(a) => Some(a.elements.len() as u64),
+        HeapObject::PrimitiveArray(a) => Some(a.length as u64),
+        _ => None,
+    }
+}
+
+fn dominant_element(snapshot: &Snapshot, elements: &[u64]) -> Option<(String, u64)> {
+    let mut classes: HashMap<String, u64> = HashMap::new();
+    for object in elements.iter().filter_map(|&id| snapshot.object(id)) {
+        *classes.entry(snapshot.object_label(object)).or_insert(0) += 1;
+    }
+    classes
+        .into_iter()
+        .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(&a.0)))
+}
+
+//
+// The `top` reachable arrays with the most elements (`by_length`) or the
+// most bytes.
+//
+pub fn large_arrays(
+    snapshot: &Snapshot,
+    tree: &DominatorTree,
+    by_length: bool,
+    top: usize,
+) -> Vec<LargeArray> {
+    let mut nodes: Vec<(u64, u32)> = snapshot
+        .objects
+        .iter()
+        .enumerate()
+        .filter(|&(node, _)| tree.is_reachable(node as u32))
+        .filter_map(|(node, object)| {
+            let length = array_length(object)?;
+            let key = if by_length {
+                length
+            } else {
+                snapshot.shallow_size(object)
+            };
+            Some((key, node as u32))
+        })
+        .collect();
+    nodes.sort_by(|a, b| b.cmp(a));
+    nodes
+        .into_iter()
+        .take(top)
+        .map(|(_, node)| {
+            let object = &snapshot.objects[node as usize];
+            let idom = tree.idom[node as usize];
+            LargeArray {
+                node,
+                length: array_length(object).unwrap(),
+                bytes: snapshot.shallow_size(object),
+                owner: Some(idom).filter(|&d| d != tree.virtual_root()),
+                dominant_element: match object {
+                    HeapObject::ObjectArray(a) => dominant_element(snapshot, &a.elements),
+                    _ => None,
+                },
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct SparseArray {
+    pub node: u32,
+    pub length: u64,
+    pub nulls: u64,
+    pub owner: Option<u32>,
+    // Bytes saved by shrinking the array to its non-null elements.
+    pub reclaimable: u64,
+}
+
+impl SparseArray {
+    pub fn null_ratio(&self) -> f64 {
+        self.nulls as f64 / self.length as f64
+    }
+}
+
+//
+// Reachable object arrays of at least `min_length` elements of which at
+// least `min_null_ratio` are null, by reclaimable bytes. Hash tables are
+// sparse by design and are left out.
+//
+pub fn sparse_arrays(
+    snapshot: &Snapshot,
+    tree: &DominatorTree,
+    min_length: u64,
+    min_null_ratio: f64,
+) -> Vec<SparseArray> {
+    let id_size = snapshot.id_size() as u64;
+    let mut sparse: Vec<SparseArray> = snapshot
+        .objects
+        .iter()
+        .enumerate()
+        .filter(|&(node, _)| tree.is_reachable(node as u32))
+        .filter_map(|(node, object)| {
+            let array = match object {
+                HeapObject::ObjectArray(a) if a.elements.len() as u64 >= min_length.max(1) => a,
+                _ => return None,
+            };
+            let class_name = snapshot.class_name(array.class_id);
+            if class_name.ends_with("HashMap$Node[]") || class_name.ends_with("Hashtable$Entry[]") {
+                return None;
+            }
+            let length = array.elements.len() as u64;
+            let nulls = array.elements.iter().filter(|&&id| id == 0).count() as u64;
+            let idom = tree.idom[node];
+            Some(SparseArray {
+                node: node as u32,
+                length,
+                nulls,
+                owner: Some(idom).filter(|&d| d != tree.virtual_root()),
+                reclaimable: nulls * id_size,
+            })
+        })
+        .filter(|a| a.null_ratio() >= min_null_ratio)
+        .collect();
+    sparse.sort_by(|a, b| {
+        b.reclaimable
+            .cmp(&a.reclaimable)
+            .then_with(|| a.node.cmp(&b.node))
+    });
+    sparse
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dominator;
+    use crate::graph::Graph;
+    use crate::heap::{GcRootKind, Value};
+    use crate::testing::Dump;
+
+    fn tree(snapshot: &Snapshot) -> DominatorTree {
+        dominator::build(&Graph::build(snapshot))
+    }
+
+    #[test]
+    fn zero_tails_are_whole_elements() {
+        assert_eq!(zero_tail(&[1, 0, 0, 0, 0], 1), 4);
+        assert_eq!(zero_tail(&[1, 0, 0, 0, 0], 2), 4);
+        assert_eq!(zero_tail(&[0, 1, 0, 0, 0], 2), 2);
+        assert_eq!(zero_tail(&[0, 0, 0, 1], 4), 0);
+        assert_eq!(zero_tail(&[], 4), 0);
+    }
+
+    #[test]
+    fn zero_tails_are_grouped_by_owner() {
+        let mut dump = Dump::new();
+        let buffer = dump.class(
+            "test/Buffer",
+            dump.object,
+            &[("data", FieldTag::ArrayObject)],
+        );
+        let mut data = vec![0; 100];
+        data[..10].fill(7);
+        for _ in 0..2 {
+            let array = dump.primitive_array(FieldTag::Byte, &data);
+            let instance = dump.instance(buffer, &[Value::Object(array)]);
+            dump.root(GcRootKind::StickyClass, instance);
+        }
+        let ints = dump.primitive_array(FieldTag::Int, &[0; 80]);
+        dump.root(GcRootKind::JniGlobal, ints);
+        // Too short a tail, a long array and garbage.
+        let short = dump.primitive_array(FieldTag::Byte, &data[..30]);
+        dump.root(GcRootKind::JniGlobal, short);
+        let longs = dump.primitive_array(FieldTag::Long, &[0; 80]);
+        dump.root(GcRootKind::JniGlobal, longs);
+        dump.primitive_array(FieldTag::Byte, &data);
+        let snapshot = dump.load();
+
+        let groups = zero_tails(&snapshot, &tree(&snapshot), 50);
+        let found: Vec<(&str, &str, u64, u64)> = groups
+            .iter()
+            .map(|g| (g.owner.as_str(), g.class_name.as_str(), g.arrays, g.wasted))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("test.Buffer", "byte[]", 2, 180),
+                ("<GC root>", "int[]", 1, 80),
+            ]
+        );
+    }
+
+    #[test]
+    fn large_arrays_by_length_and_bytes() {
+        let mut dump = Dump::new();
+        let holder = dump.class(
+            "test/Holder",
+            dump.object,
+            &[("items", FieldTag::ArrayObject)],
+        );
+        let item = dump.class("test/Item", dump.object, &[]);
+        let mut elements: Vec<u64> = (0..3).map(|_| dump.instance(item, &[])).collect();
+        elements.push(dump.string("ab"));
+        elements.push(0);
+        let array = dump.object_array;
+        let items = dump.object_array(array, &elements);
+        let instance = dump.instance(holder, &[Value::Object(items)]);
+        dump.root(GcRootKind::StickyClass, instance);
+        let ints = dump.primitive_array(FieldTag::Int, &[0; 400]);
+        dump.root(GcRootKind::JniGlobal, ints);
+        // Garbage, however large, is left out.
+        dump.primitive_array(FieldTag::Byte, &[0; 1000]);
+        let snapshot = dump.load();
+        let tree = tree(&snapshot);
+
+        let by_length = large_arrays(&snapshot, &tree, true, 2);
+        let found: Vec<(u32, u64, Option<u32>)> = by_length
+            .iter()
+            .map(|a| (a.node, a.length, a.owner))
+            .collect();
+        let node = |id| snapshot.index_of(id).unwrap();
+        assert_eq!(
+            found,
+            vec![
+                (node(ints), 100, None),
+                (node(items), 5, Some(node(instance))),
+            ]
+        );
+        assert_eq!(by_length[0].dominant_element, None);
+        assert_eq!(
+            by_length[1].dominant_element,
+            Some(("test.Item".to_string(), 3))
+        );
+
+        let by_bytes = large_arrays(&snapshot, &tree, false, 1);
+        assert_eq!(by_bytes[0].node, node(ints));
+        assert_eq!(by_bytes[0].bytes, 400 + 2 * 8 + 4);
+    }
+
+    #[test]
+    fn sparse_arrays_leave_hash_tables_out() {
+        let mut dump = Dump::new();
+        let node_array = dump.class("[Ljava/util/HashMap$Node;", dump.object, &[]);
+        let item = dump.class("test/Item", dump.object, &[]);
+        let a = dump.instance(item, &[]);
+        let b = dump.instance(item, &[]);
+        let array = dump.object_array;
+        let mut arrays = vec![
+            dump.object_array(array, &[a, b, 0, 0, 0, 0, 0, 0, 0, 0]),
+            dump.object_array(array, &[a, b, a, b, a, b, a, b, 0, 0]),
+            dump.object_array(array, &[0, 0, 0]),
+            dump.object_array(node_array, &[0; 10]),
+        ];
+        arrays.push(dump.object_array(array, &arrays));
+        for &array in &arrays {
+            dump.root(GcRootKind::JniGlobal, array);
+        }
+        let snapshot = dump.load();
+
+        let sparse = sparse_arrays(&snapshot, &tree(&snapshot), 5, 0.5);
+        assert_eq!(sparse.len(), 1);
+        assert_eq!(sparse[0].node, snapshot.index_of(arrays[0]).unwrap());
+        assert_eq!((sparse[0].length, sparse[0].nulls), (10, 8));
+        assert_eq!(sparse[0].owner, None);
+        assert_eq!(sparse[0].reclaimable, 64);
+        assert!((sparse[0].null_ratio() - 0.8).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,239 @@
+//
+// Boxed primitives (Integer, Long, ...) and how much memory they cost
+// compared to storing the primitive values directly.
+//
+use crate::heap::{FieldTag, HeapObject, Value};
+use crate::snapshot::Snapshot;
+
+use std::collections::{HashMap, HashSet};
+
+const BOX_CLASSES: &[(&str, FieldTag)] = &[
+    ("java.lang.Boolean", FieldTag::Boolean),
+    ("java.lang.Byte", FieldTag::Byte),
+    ("java.lang.Character", FieldTag::Char),
+    ("java.lang.Short", FieldTag::Short),
+    ("java.lang.Integer", FieldTag::Int),
+    ("java.lang.Long", FieldTag::Long),
+    ("java.lang.Float", FieldTag::Float),
+    ("java.lang.Double", FieldTag::Double),
+];
+
+// The primitive value of a boxed primitive, None for other objects.
+pub fn unbox(snapshot: &Snapshot, object: &HeapObject) -> Option<Value> {
+    match object {
+        HeapObject::Instance(i) => {
+            let class_name = snapshot.class_name(i.class_id);
+            if !BOX_CLASSES.iter().any(|(name, _)| *name == class_name) {
+                return None;
+            }
+            snapshot.field_value(i, "value")
+        }
+        _ => None,
+    }
+}
+
+//
+// Whether valueOf() returns a shared instance for a value, using the
+// default cache bounds (Integer's upper bound can be raised with
+// -XX:AutoBoxCacheMax, which isn't recorded in the dump).
+//
+pub fn in_box_cache(value: Value) -> bool {
+    match value {
+        Value::Boolean(_) | Value::Byte(_) => true,
+        Value::Char(c) => c <= 127,
+        Value::Short(v) => (-128..=127).contains(&v),
+        Value::Int(v) => (-128..=127).contains(&v),
+        Value::Long(v) => (-128..=127).contains(&v),
+        _ => false,
+    }
+}
+
+#[derive(Debug)]
+pub struct BoxedClass {
+    pub class_name: &'static str,
+    pub primitive: FieldTag,
+    pub instances: u64,
+    // Instances whose value is in the valueOf() cache range. More of these
+    // than distinct values means boxes were created with `new`.
+    pub cached_range: u64,
+    pub distinct: u64,
+    pub shallow: u64,
+    // Shallow bytes minus the bytes of the primitive values themselves.
+    pub wasted: u64,
+}
+
+#[derive(Debug)]
+pub struct BoxedArrays {
+    // The box class of the elements.
+    pub class_name: &'static str,
+    pub arrays: u64,
+    pub elements: u64,
+    // Bytes of the arrays and their boxes minus those of the equivalent
+    // primitive arrays.
+    pub wasted: u64,
+}
+
+fn box_classes(snapshot: &Snapshot) -> HashMap<u64, usize> {
+    BOX_CLASSES
+        .iter()
+        .enumerate()
+        .filter_map(|(i, (name, _))| snapshot.find_class(name).map(|id| (id, i)))
+        .collect()
+}
+
+pub fn boxed_classes(snapshot: &Snapshot) -> Vec<BoxedClass> {
+    let classes = box_classes(snapshot);
+    let mut report: Vec<BoxedClass> = BOX_CLASSES
+        .iter()
+        .map(|&(class_name, primitive)| BoxedClass {
+            class_name,
+            primitive,
+            instances: 0,
+            cached_range: 0,
+            distinct: 0,
+            shallow: 0,
+            wasted: 0,
+        })
+        .collect();
+    let mut values: Vec<HashSet<String>> = vec![HashSet::new(); BOX_CLASSES.len()];
+
+    for object in &snapshot.objects {
+        let (instance, i) = match object {
+            HeapObject::Instance(inst) => match classes.get(&inst.class_id) {
+                Some(&i) => (inst, i),
+                None => continue,
+            },
+            _ => continue,
+        };
+        let entry = &mut report[i];
+        let shallow = snapshot.shallow_size(object);
+        entry.instances += 1;
+        entry.shallow += shallow;
+        entry.wasted += shallow.saturating_sub(entry.primitive.size(snapshot.id_size()) as u64);
+        if let Some(value) = snapshot.field_value(instance, "value") {
+            if in_box_cache(value) {
+                entry.cached_range += 1;
+            }
+            values[i].insert(value.to_string());
+        }
+    }
+    for (entry, values) in report.iter_mut().zip(values) {
+        entry.distinct = values.len() as u64;
+    }
+    report.retain(|c| c.instances > 0);
+    report.sort_by_key(|c| std::cmp::Reverse(c.wasted));
+    report
+}
+
+//
+// Object arrays whose non-null elements are all boxes of the same type,
+// whatever the declared type of the array (an ArrayList<Integer> holds an
+// Object[]). Boxes referenced from several arrays are charged to each.
+//
+pub fn boxed_arrays(snapshot: &Snapshot) -> Vec<BoxedArrays> {
+    let classes = box_classes(snapshot);
+    let id_size = snapshot.id_size() as u64;
+    let mut report: Vec<BoxedArrays> = BOX_CLASSES
+        .iter()
+        .map(|&(class_name, _)| BoxedArrays {
+            class_name,
+            arrays: 0,
+            elements: 0,
+            wasted: 0,
+        })
+        .collect();
+
+    for object in &snapshot.objects {
+        let array = match object {
+            HeapObject::ObjectArray(a) => a,
+            _ => continue,
+        };
+        let mut kind = None;
+        let mut boxes = 0;
+        for &id in array.elements.iter().filter(|&&id| id != 0) {
+            let i = match snapshot.object(id) {
+                Some(HeapObject::Instance(inst)) => classes.get(&inst.class_id).copied(),
+                _ => None,
+            };
+            match (i, kind) {
+                (None, _) => {
+                    kind = None;
+                    break;
+                }
+                (Some(i), Some(k)) if i != k => {
+                    kind = None;
+                    break;
+                }
+                (Some(i), _) => {
+                    kind = Some(i);
+                    boxes += snapshot.shallow_size(snapshot.object(id).unwrap());
+                }
+            }
+        }
+        let i = match kind {
+            Some(i) => i,
+            None => continue,
+        };
+        let length = array.elements.len() as u64;
+        let primitive = BOX_CLASSES[i].1.size(snapshot.id_size()) as u64 * length;
+        let entry = &mut report[i];
+        entry.arrays += 1;
+        entry.elements += length;
+        entry.wasted += (length * id_size + boxes).saturating_sub(primitive);
+    }
+    report.retain(|a| a.arrays > 0);
+    report.sort_by_key(|a| std::cmp::Reverse(a.wasted));
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Dump;
+
+    #[test]
+    fn box_cache_has_the_default_bounds() {
+        assert!(in_box_cache(Value::Int(-128)));
+        assert!(!in_box_cache(Value::Int(128)));
+        assert!(in_box_cache(Value::Char(127)));
+        assert!(in_box_cache(Value::Boolean(true)));
+        assert!(!in_box_cache(Value::Double(0.0)));
+    }
+
+    #[test]
+    fn boxes_and_arrays_of_boxes_are_counted() {
+        let mut dump = Dump::new();
+        let integer = dump.class(
+            "java/lang/Integer",
+            dump.object,
+            &[("value", FieldTag::Int)],
+        );
+        let small = dump.instance(integer, &[Value::Int(5)]);
+        let copy = dump.instance(integer, &[Value::Int(5)]);
+        let large = dump.instance(integer, &[Value::Int(1000)]);
+        let array = dump.object_array;
+        dump.object_array(array, &[small, copy, 0]);
+        let string = dump.string("five");
+        dump.object_array(array, &[large, string]);
+        let snapshot = dump.load();
+        let size = |id| snapshot.shallow_size(snapshot.object(id).unwrap());
+
+        let classes = boxed_classes(&snapshot);
+        assert_eq!(classes.len(), 1);
+        let c = &classes[0];
+        assert_eq!(c.class_name, "java.lang.Integer");
+        assert_eq!((c.instances, c.cached_range, c.distinct), (3, 2, 2));
+        assert_eq!(c.shallow, 3 * size(small));
+        assert_eq!(c.wasted, 3 * (size(small) - 4));
+
+        // The array holding a String is left out.
+        let arrays = boxed_arrays(&snapshot);
+        assert_eq!(arrays.len(), 1);
+        let a = &arrays[0];
+        assert_eq!(
+            (a.class_name, a.arrays, a.elements),
+            ("java.lang.Integer", 1, 3)
+        );
+        assert_eq!(a.wasted, 3 * 8 + 2 * size(small) - 3 * 4);
+    }
+}
@@ -0,0 +1,388 @@
+//
+// Analysis results saved next to the dump for the next run. The files are
+// in a sidecar directory (`<dump>.hprof-cat`) and start with the
+// fingerprint of the dump they were computed from, so that a dump
+// overwritten in place doesn't reuse stale results. Dominator trees and
+// retained sizes are keyed by the hash of the graph they come from, which
+// covers the reference kinds excluded from it, and retained sizes also by
+// the size model. Dominator trees being computed are checkpointed in the
+// same directory, for an interrupted run to resume from.
+//
+use crate::dominator::{self, DominatorTree};
+use crate::graph::{Adjacency, Graph};
+use crate::idhash::IdHasher;
+use crate::retained;
+use crate::snapshot::Snapshot;
+
+use std::convert::TryInto;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+
+const MAGIC: &[u8; 8] = b"HPCCACHE";
+const VERSION: u32 = 1;
+
+//
+// A hash of what makes a dump: its header, how many records of each kind
+// it has, its classes (by their names as the dump has them) and its GC
+// roots (by the tags of their sub-records). Dumps of the same heap hash the
+// same wherever they are copied to. Printed by `fingerprint` for others
+// to key dumps by, so it must not change from one version to the next:
+// integers are hashed as their little-endian bytes, each of a fixed
+// width, and strings as their length and bytes, to leave nothing to how
+// std hashes them.
+//
+pub fn fingerprint(snapshot: &Snapshot) -> u64 {
+    let mut hasher = IdHasher::default();
+    let header = &snapshot.header;
+    hasher.write(&(header.format.len() as u64).to_le_bytes());
+    hasher.write(header.format.as_bytes());
+    hasher.write(&header.identifier_size.to_le_bytes());
+    hasher.write(&header.timestamp_ms().to_le_bytes());
+    let mut counts: Vec<(u8, u64)> = snapshot
+        .record_counts
+        .iter()
+        .map(|(&t, &c)| (t, c))
+        .collect();
+    counts.sort_unstable();
+    hasher.write(&(counts.len() as u64).to_le_bytes());
+    for (tag, count) in counts {
+        hasher.write(&[tag]);
+        hasher.write(&count.to_le_bytes());
+    }
+    let mut classes: Vec<(u64, &str)> = snapshot
+        .classes
+        .values()
+        .map(|c| (c.object_id, snapshot.string(c.strname_id)))
+        .collect();
+    classes.sort_unstable();
+    hasher.write(&(classes.len() as u64).to_le_bytes());
+    for (id, name) in classes {
+        hasher.write(&id.to_le_bytes());
+        hasher.write(&(name.len() as u64).to_le_bytes());
+        hasher.write(name.as_bytes());
+    }
+    let mut roots: Vec<(u64, u8)> = snapshot
+        .roots
+        .iter()
+        .map(|r| (r.object_id, r.kind.tag() as u8))
+        .collect();
+    roots.sort_unstable();
+    hasher.write(&(roots.len() as u64).to_le_bytes());
+    for (id, tag) in roots {
+        hasher.write(&id.to_le_bytes());
+        hasher.write(&[tag]);
+    }
+    hasher.write(&(snapshot.objects.len() as u64).to_le_bytes());
+    hasher.finish()
+}
+
+fn graph_hash(graph: &Graph) -> u64 {
+    let mut hasher = IdHasher::default();
+    let (offsets, bytes) = graph.successors.parts();
+    offsets.hash(&mut hasher);
+    hasher.write(bytes);
+    graph.roots.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub struct Cache {
+    dir: PathBuf,
+    fingerprint: u64,
+}
+
+// Little-endian arrays, each prefixed by its length.
+pub struct Reader<'a>(pub &'a [u8]);
+
+impl Reader<'_> {
+    fn u64(&mut self) -> Option<u64> {
+        if self.0.len() < 8 {
+            return None;
+        }
+        let (word, rest) = self.0.split_at(8);
+        self.0 = rest;
+        Some(u64::from_le_bytes(word.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self, width: usize) -> Option<&[u8]> {
+        let len = self.u64()? as usize * width;
+        if self.0.len() < len {
+            return None;
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(bytes)
+    }
+
+    pub fn u32s(&mut self) -> Option<Vec<u32>> {
+        Some(
+            self.bytes(4)?
+                .chunks_exact(4)
+                .map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+                .collect(),
+        )
+    }
+
+    fn u64s(&mut self) -> Option<Vec<u64>> {
+        Some(
+            self.bytes(8)?
+                .chunks_exact(8)
+                .map(|c| u64::from_le_bytes(c.try_into().unwrap()))
+                .collect(),
+        )
+    }
+}
+
+pub fn push_u32s(out: &mut Vec<u8>, values: &[u32]) {
+    out.extend((values.len() as u64).to_le_bytes());
+    for v in values {
+        out.extend(v.to_le_bytes());
+    }
+}
+
+fn push_u64s(out: &mut Vec<u8>, values: &[u64]) {
+    out.extend((values.len() as u64).to_le_bytes());
+    for v in values {
+        out.extend(v.to_le_bytes());
+    }
+}
+
+impl Cache {
+    pub fn open(dump: &str, snapshot: &Snapshot) -> Cache {
+        Cache {
+            dir: PathBuf::from(format!("{}.hprof-cat", dump)),
+            fingerprint: fingerprint(snapshot),
+        }
+    }
+
+    // The payload of an artifact, None if missing or from another dump.
+    fn load(&self, name: &str) -> Option<Vec<u8>> {
+        let data = fs::read(self.dir.join(name)).ok()?;
+        let header = MAGIC.len() + 4 + 8;
+        if data.len() < header
+            || &data[..8] != MAGIC
+            || data[8..12] != VERSION.to_le_bytes()
+            || data[12..20] != self.fingerprint.to_le_bytes()
+        {
+            return None;
+        }
+        Some(data[header..].to_vec())
+    }
+
+    // Failing to save only costs the next run the time to recompute.
+    fn save(&self, name: &str, payload: &[u8]) {
+        let mut data = Vec::with_capacity(20 + payload.len());
+        data.extend(MAGIC);
+        data.extend(VERSION.to_le_bytes());
+        data.extend(self.fingerprint.to_le_bytes());
+        data.extend(payload);
+        // Written aside then renamed, so that readers never see half a file.
+        let path = self.dir.join(name);
+        let partial = self.dir.join(format!("{}.partial", name));
+        let saved = fs::create_dir_all(&self.dir)
+            .and_then(|_| fs::write(&partial, &data))
+            .and_then(|_| fs::rename(&partial, &path));
+        if let Err(e) = saved {
+            eprintln!("warning: can't save {}: {}", path.display(), e);
+        }
+    }
+
+    pub fn dominators(&self, graph: &Graph) -> DominatorTree {
+        self.dominators_of(graph, graph_hash(graph))
+    }
+
+    fn dominators_of(&self, graph: &Graph, hash: u64) -> DominatorTree {
+        let name = format!("dominators-{:016x}", hash);
+        if let Some(payload) = self.load(&name) {
+            let mut reader = Reader(&payload);
+            if let (Some(idom), Some(order)) = (reader.u32s(), reader.u32s()) {
+                if idom.len() == graph.len() {
+                    return DominatorTree { idom, order };
+                }
+            }
+        }
+        // Long computations resume from where an interrupted run left off.
+        let checkpoint = format!("{}.checkpoint", name);
+        let resume = self
+            .load(&checkpoint)
+            .and_then(|p| dominator::Progress::decode(&p, graph.len()));
+        if resume.is_some() {
+            eprintln!(
+                "resuming the dominator tree from {}",
+                self.dir.join(&checkpoint).display()
+            );
+        }
+        let tree = dominator::build_resumable(graph, resume, |progress| {
+            self.save(&checkpoint, &progress.encode())
+        });
+        let mut payload = Vec::new();
+        push_u32s(&mut payload, &tree.idom);
+        push_u32s(&mut payload, &tree.order);
+        self.save(&name, &payload);
+        let _ = fs::remove_file(self.dir.join(&checkpoint));
+        tree
+    }
+
+    pub fn retained_sizes(&self, snapshot: &Snapshot, graph: &Graph) -> (DominatorTree, Vec<u64>) {
+        let hash = graph_hash(graph);
+        let tree = self.dominators_of(graph, hash);
+        let name = format!("retained-{:016x}-{}", hash, snapshot.size_model.name());
+        if let Some(retained) = self.load(&name).and_then(|p| Reader(&p).u64s()) {
+            if retained.len() == graph.len() {
+                return (tree, retained);
+            }
+        }
+        let retained = retained::retained_sizes(snapshot, &tree);
+        let mut payload = Vec::new();
+        push_u64s(&mut payload, &retained);
+        self.save(&name, &payload);
+        (tree, retained)
+    }
+
+    pub fn predecessors(&self, graph: &Graph) -> Adjacency {
+        let name = format!("predecessors-{:016x}", graph_hash(graph));
+        if let Some(payload) = self.load(&name) {
+            let mut reader = Reader(&payload);
+            if let (Some(offsets), Some(bytes)) = (reader.u64s(), reader.bytes(1)) {
+                if offsets.len() == graph.len() + 1 {
+                    return Adjacency::from_parts(offsets, bytes.to_vec());
+                }
+            }
+        }
+        let preds = graph.predecessors();
+        let (offsets, bytes) = preds.parts();
+        let mut payload = Vec::new();
+        push_u64s(&mut payload, offsets);
+        payload.extend((bytes.len() as u64).to_le_bytes());
+        payload.extend(bytes);
+        self.save(&name, &payload);
+        preds
+    }
+}
+
+// The dominator tree of a graph, from the cache of the snapshot if it has one.
+pub fn dominators(snapshot: &Snapshot, graph: &Graph) -> DominatorTree {
+    match &snapshot.cache {
+        Some(cache) => cache.dominators(graph),
+        None => dominator::build(graph),
+    }
+}
+
+// The dominator tree and the retained sizes of a graph, like dominators().
+pub fn retained_sizes(snapshot: &Snapshot, graph: &Graph) -> (DominatorTree, Vec<u64>) {
+    match &snapshot.cache {
+        Some(cache) => cache.retained_sizes(snapshot, graph),
+        None => {
+            let tree = dominator::build(graph);
+            let retained = retained::retained_sizes(snapshot, &tree);
+            (tree, retained)
+        }
+    }
+}
+
+// The predecessors of a graph, like dominators().
+pub fn predecessors(snapshot: &Snapshot, graph: &Graph) -> Adjacency {
+    match &snapshot.cache {
+        Some(cache) => cache.predecessors(graph),
+        None => graph.predecessors(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::{FieldTag, GcRootKind, Value};
+    use crate::synthetic::{self, Options};
+    use crate::testing::{Dump, TempFile};
+
+    fn generated(objects: u64) -> Snapshot {
+        let options = Options {
+            classes: 5,
+            objects,
+            ..Options::default()
+        };
+        let file = format!("hprof-cat-cache-{}-{}.hprof", std::process::id(), objects);
+        let path = std::env::temp_dir().join(file);
+        let path = path.to_str().unwrap();
+        synthetic::generate(&options, fs::File::create(path).unwrap()).unwrap();
+        let snapshot = Snapshot::load(path);
+        fs::remove_file(path).unwrap();
+        snapshot
+    }
+
+    // The fingerprint is what others key dumps by: it must stay the same.
+    #[test]
+    fn fingerprints_are_stable() {
+        assert_eq!(fingerprint(&generated(200)), 0x2831_0346_2d88_0291);
+        assert_ne!(fingerprint(&generated(201)), fingerprint(&generated(200)));
+    }
+
+    #[test]
+    fn results_are_saved_and_read_back() {
+        let mut dump = Dump::new();
+        let node = dump.class(
+            "test/Node",
+            dump.object,
+            &[
+                ("next", FieldTag::NormalObject),
+                ("other", FieldTag::NormalObject),
+            ],
+        );
+        let mut nodes: Vec<u64> = Vec::new();
+        for i in 0..2_000 {
+            let next = nodes.last().copied().unwrap_or(0);
+            let other = nodes.get(i * 7 % 11 * i / 11).copied().unwrap_or(0);
+            nodes.push(dump.instance(node, &[Value::Object(next), Value::Object(other)]));
+            if i % 300 == 0 {
+                dump.root(GcRootKind::JniGlobal, nodes[i]);
+            }
+        }
+        let file = TempFile::new(&dump.bytes());
+        let snapshot = Snapshot::load(file.path());
+        let graph = Graph::build(&snapshot);
+        let cache = Cache::open(file.path(), &snapshot);
+        let hash = graph_hash(&graph);
+
+        let (tree, retained) = cache.retained_sizes(&snapshot, &graph);
+        let expected = dominator::build(&graph);
+        assert_eq!(tree.idom, expected.idom);
+        assert_eq!(retained, retained::retained_sizes(&snapshot, &expected));
+        let preds = cache.predecessors(&graph);
+        for name in [
+            format!("dominators-{:016x}", hash),
+            format!("retained-{:016x}-raw-hprof", hash),
+            format!("predecessors-{:016x}", hash),
+        ] {
+            assert!(cache.load(&name).is_some(), "{}", name);
+            assert!(!cache.dir.join(format!("{}.partial", name)).exists());
+        }
+
+        // What is saved is what is read, not recomputed.
+        let name = format!("retained-{:016x}-raw-hprof", hash);
+        let mut payload = Vec::new();
+        push_u64s(&mut payload, &vec![7; graph.len()]);
+        cache.save(&name, &payload);
+        assert_eq!(
+            cache.retained_sizes(&snapshot, &graph).1,
+            vec![7; graph.len()]
+        );
+        assert_eq!(cache.dominators(&graph).idom, expected.idom);
+        let cached = cache.predecessors(&graph);
+        for node in 0..graph.len() as u32 {
+            assert!(cached.neighbors(node).eq(preds.neighbors(node)));
+        }
+
+        // Nor from another dump, nor of the wrong length.
+        let other = Cache {
+            dir: cache.dir.clone(),
+            fingerprint: cache.fingerprint + 1,
+        };
+        assert!(other.load(&name).is_none());
+        let mut short = Vec::new();
+        push_u64s(&mut short, &[7]);
+        cache.save(&name, &short);
+        assert_eq!(cache.retained_sizes(&snapshot, &graph).1, retained);
+
+        fs::remove_dir_all(&cache.dir).unwrap();
+    }
+}
@@ -0,0 +1,181 @@
+//
+// Minimal command line handling shared by all the subcommands.
+//
+use hprof_cat::archive::{self, Prepared};
+use hprof_cat::follow::Follow;
+use hprof_cat::signature::Names;
+use hprof_cat::units::Units;
+use hprof_cat::window::{self, Window};
+
+use std::collections::HashMap;
+
+// The options of every command taking a value, like those listed to parse().
+const COMMON: [&str; 4] = ["--since", "--until", "--units", "--names"];
+
+pub struct Args {
+    pub positional: Vec<String>,
+    options: HashMap<String, String>,
+    flags: Vec<String>,
+}
+
+impl Args {
+    //
+    // Splits arguments into positional ones, `--name value` options (only
+    // for the names listed in `with_values`, `--name=value` always works),
+    // and plain `--flag`s. Short options like `-o` are taken when listed.
+    //
+    pub fn parse(args: &[String], with_values: &[&str]) -> Args {
+        let mut parsed = Args {
+            positional: Vec::new(),
+            options: HashMap::new(),
+            flags: Vec::new(),
+        };
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if with_values.contains(&arg.as_str()) || COMMON.contains(&arg.as_str()) {
+                let value = iter
+                    .next()
+                    .unwrap_or_else(|| die(&format!("{} requires a value", arg)));
+                parsed.options.insert(arg.clone(), value.clone());
+            } else if !arg.starts_with("--") {
+                parsed.positional.push(arg.clone());
+            } else if let Some(eq) = arg.find('=') {
+                parsed
+                    .options
+                    .insert(arg[..eq].to_string(), arg[eq + 1..].to_string());
+            } else {
+                parsed.flags.push(arg.clone());
+            }
+        }
+        parsed
+    }
+
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(|s| s.as_str())
+    }
+
+    pub fn number(&self, name: &str, default: u64) -> u64 {
+        match self.value(name) {
+            Some(v) => v
+                .parse()
+                .unwrap_or_else(|_| die(&format!("{}: not a number: {}", name, v))),
+            None => default,
+        }
+    }
+
+    // A number that can be written with a k, M or G suffix, like 10M.
+    pub fn count(&self, name: &str, default: u64) -> u64 {
+        let v = match self.value(name) {
+            Some(v) => v,
+            None => return default,
+        };
+        let (digits, scale) = match v.char_indices().last() {
+            Some((i, 'k')) | Some((i, 'K')) => (&v[..i], 1_000),
+            Some((i, 'M')) => (&v[..i], 1_000_000),
+            Some((i, 'G')) => (&v[..i], 1_000_000_000),
+            _ => (v, 1),
+        };
+        match digits
+            .parse::<u64>()
+            .ok()
+            .and_then(|n| n.checked_mul(scale))
+        {
+            Some(n) => n,
+            None => die(&format!("{}: not a number: {}", name, v)),
+        }
+    }
+
+    pub fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|f| f == name)
+    }
+
+    // The units of the byte quantities of the report (see units.rs).
+    pub fn units(&self) -> Units {
+        match self.value("--units") {
+            Some(s) => Units::parse(s).unwrap_or_else(|| die(&format!("unknown units: {}", s))),
+            None => Units::default(),
+        }
+    }
+
+    // How the class tables of the report name classes (see signature.rs).
+    pub fn names(&self) -> Names {
+        match self.value("--names") {
+            Some(s) => Names::parse(s).unwrap_or_else(|| die(&format!("unknown names: {}", s))),
+            None => Names::default(),
+        }
+    }
+
+    // The window of record times of --since and --until (see window.rs).
+    pub fn window(&self) -> Window {
+        let bound = |name: &str| {
+            self.value(name)
+                .map(|s| window::parse_bound(s).unwrap_or_else(|e| die(&e)))
+        };
+        Window {
+            since: bound("--since"),
+            until: bound("--until"),
+        }
+    }
+}
+
+// Removes a flag from the arguments, returning whether it was there.
+pub fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    let found = args.iter().any(|a| a == name);
+    args.retain(|a| a != name);
+    found
+}
+
+// Removes a `--name value` (or `--name=value`) option from the arguments.
+pub fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let prefix = format!("{}=", name);
+    let i = args
+        .iter()
+        .position(|a| a == name || a.starts_with(&prefix))?;
+    let arg = args.remove(i);
+    match arg.strip_prefix(&prefix) {
+        Some(value) => Some(value.to_string()),
+        None if i < args.len() => Some(args.remove(i)),
+        None => die(&format!("{} requires a value", name)),
+    }
+}
+
+pub fn die(msg: &str) -> ! {
+    eprintln!("error: {}", msg);
+    std::process::exit(1);
+}
+
+//
+// Dies unless the dump opens as a file, before it is handed to the
+// loaders of the library, which panic. With --follow a dump that isn't
+// there yet is waited for first.
+//
+pub fn check_dump(spec: &str) {
+    let opened = Follow::open(spec).and_then(|_| archive::open(spec));
+    match opened {
+        Ok((file, _)) if file.metadata().is_ok_and(|m| m.is_file()) => {}
+        Ok(_) => die(&format!("{}: not a file", spec)),
+        Err(e) => die(&format!("{}: {}", spec, e)),
+    }
+}
+
+// The dump of the command line, out of its archive if it is in one.
+pub fn prepare(spec: &str) -> Prepared {
+    let prepared = archive::prepare(spec, &std::env::temp_dir()).unwrap_or_else(|e| die(&e));
+    check_dump(&prepared.spec);
+    prepared
+}
+
+// Object ids are printed in hex but decimal input is accepted too.
+pub fn parse_object_id(s: &str) -> u64 {
+    object_id(s).unwrap_or_else(|e| die(&e))
+}
+
+// An object id in hex (with 0x) or decimal.
+pub fn object_id(s: &str) -> Result<u64, String> {
+    let parsed = if let Some(hex) = s.strip_prefix("0x") {
+        u64::from_str_radix(hex, 16)
+    } else {
+        s.parse()
+    };
+    parsed.map_err(|_| format!("invalid object id: {}", s))
+}
@@ -0,0 +1,978 @@
+//
+// Decoding of the common JDK collections, enough to tell how many elements
+// they hold compared to the space they have allocated for them, and what
+// those elements are.
+//
+use crate::dominator::DominatorTree;
+use crate::heap::{FieldTag, HeapObject, InstanceDump, Value};
+use crate::snapshot::Snapshot;
+use crate::strings::string_content;
+
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum CollectionKind {
+    HashMap,
+    HashSet,
+    ArrayList,
+    ConcurrentHashMap,
+    StringBuilder,
+}
+
+// The collection classes, subclasses (e.g. LinkedHashMap) are handled too.
+const KINDS: &[(&str, CollectionKind)] = &[
+    ("java.util.HashMap", CollectionKind::HashMap),
+    ("java.util.HashSet", CollectionKind::HashSet),
+    ("java.util.ArrayList", CollectionKind::ArrayList),
+    (
+        "java.util.concurrent.ConcurrentHashMap",
+        CollectionKind::ConcurrentHashMap,
+    ),
+    (
+        "java.lang.AbstractStringBuilder",
+        CollectionKind::StringBuilder,
+    ),
+];
+
+#[derive(Debug)]
+pub struct Collection {
+    pub node: u32,
+    pub kind: CollectionKind,
+    pub size: u64,
+    // Number of slots of the backing array (0 if not allocated yet).
+    pub capacity: u64,
+    // The backing array, if allocated.
+    pub array: Option<u64>,
+    // Bytes per slot of the backing array.
+    pub slot_size: u64,
+}
+
+impl Collection {
+    pub fn slack(&self) -> u64 {
+        self.capacity.saturating_sub(self.size) * self.slot_size
+    }
+
+    // Fraction of the capacity in use, 1.0 for collections without storage.
+    pub fn fill_ratio(&self) -> f64 {
+        if self.capacity == 0 {
+            1.0
+        } else {
+            self.size as f64 / self.capacity as f64
+        }
+    }
+}
+
+// Maps the ids of the collection classes (and their subclasses) to their kind.
+pub fn collection_classes(snapshot: &Snapshot) -> HashMap<u64, CollectionKind> {
+    let mut kinds = HashMap::new();
+    for &class_id in snapshot.class_serials.keys() {
+        let mut current = class_id;
+        while let Some(class) = snapshot.class_dump(current) {
+            let name = snapshot.class_name(current);
+            if let Some(&(_, kind)) = KINDS.iter().find(|(n, _)| *n == name) {
+                kinds.insert(class_id, kind);
+                break;
+            }
+            current = class.super_class_id;
+        }
+    }
+    kinds
+}
+
+fn int_field(snapshot: &Snapshot, instance: &InstanceDump, name: &str) -> Option<i64> {
+    match snapshot.field_value(instance, name)? {
+        Value::Int(v) => Some(v as i64),
+        Value::Long(v) => Some(v),
+        Value::Byte(v) => Some(v as i64),
+        _ => None,
+    }
+}
+
+fn object_field<'a>(
+    snapshot: &'a Snapshot,
+    instance: &InstanceDump,
+    name: &str,
+) -> Option<&'a HeapObject> {
+    snapshot
+        .field_value(instance, name)?
+        .as_object()
+        .and_then(|id| snapshot.object(id))
+}
+
+fn array_length(object: Option<&HeapObject>) -> u64 {
+    match object {
+        Some(HeapObject::ObjectArray(a)) => a.elements.len() as u64,
+        Some(HeapObject::PrimitiveArray(a)) => a.length as u64,
+        _ => 0,
+    }
+}
+
+// The number of entries in a ConcurrentHashMap, as computed by sumCount().
+fn concurrent_map_size(snapshot: &Snapshot, instance: &InstanceDump) -> u64 {
+    let mut size = int_field(snapshot, instance, "baseCount").unwrap_or(0);
+    if let Some(HeapObject::ObjectArray(cells)) = object_field(snapshot, instance, "counterCells") {
+        for &cell in &cells.elements {
+            if let Some(HeapObject::Instance(cell)) = snapshot.object(cell) {
+                size += int_field(snapshot, cell, "value").unwrap_or(0);
+            }
+        }
+    }
+    size.max(0) as u64
+}
+
+pub fn decode(
+    snapshot: &Snapshot,
+    kinds: &HashMap<u64, CollectionKind>,
+    node: u32,
+) -> Option<Collection> {
+    let instance = match &snapshot.objects[node as usize] {
+        HeapObject::Instance(i) => i,
+        _ => return None,
+    };
+    let kind = *kinds.get(&instance.class_id)?;
+    let id_size = snapshot.id_size() as u64;
+    let (size, array, slot_size) = match kind {
+        CollectionKind::HashMap => (
+            int_field(snapshot, instance, "size")?.max(0) as u64,
+            object_field(snapshot, instance, "table"),
+            id_size,
+        ),
+        CollectionKind::HashSet => {
+            let map = match object_field(snapshot, instance, "map") {
+                Some(HeapObject::Instance(map)) => map,
+                _ => return None,
+            };
+            (
+                int_field(snapshot, map, "size")?.max(0) as u64,
+                object_field(snapshot, map, "table"),
+                id_size,
+            )
+        }
+        CollectionKind::ArrayList => (
+            int_field(snapshot, instance, "size")?.max(0) as u64,
+            object_field(snapshot, instance, "elementData"),
+            id_size,
+        ),
+        CollectionKind::ConcurrentHashMap => (
+            concurrent_map_size(snapshot, instance),
+            object_field(snapshot, instance, "table"),
+            id_size,
+        ),
+        CollectionKind::StringBuilder => {
+            let count = int_field(snapshot, instance, "count")?.max(0) as u64;
+            let value = object_field(snapshot, instance, "value");
+            // Before JDK 9 the value is a char[], after it's a byte[] holding
+            // two bytes per char when the coder is UTF-16.
+            let (capacity, slot_size) = match value {
+                Some(HeapObject::PrimitiveArray(a)) => match a.element_tag {
+                    FieldTag::Byte if int_field(snapshot, instance, "coder") == Some(1) => {
+                        (a.length as u64 / 2, 2)
+                    }
+                    FieldTag::Byte => (a.length as u64, 1),
+                    _ => (a.length as u64, 2),
+                },
+                _ => (0, 2),
+            };
+            return Some(Collection {
+                node,
+                kind,
+                size: count,
+                capacity,
+                array: value.map(|v| v.object_id()),
+                slot_size,
+            });
+        }
+    };
+    Some(Collection {
+        node,
+        kind,
+        size,
+        capacity: array_length(array),
+        array: array.map(|a| a.object_id()),
+        slot_size,
+    })
+}
+
+//
+// All the collections of the heap that could be decoded. The HashMaps
+// backing HashSets are left out since the sets already account for them.
+//
+pub fn collections(snapshot: &Snapshot) -> Vec<Collection> {
+    let kinds = collection_classes(snapshot);
+    let decoded: Vec<Collection> = (0..snapshot.objects.len() as u32)
+        .filter_map(|node| decode(snapshot, &kinds, node))
+        .collect();
+    let set_maps: HashSet<u64> = decoded
+        .iter()
+        .filter(|c| c.kind == CollectionKind::HashSet)
+        .filter_map(|c| match &snapshot.objects[c.node as usize] {
+            HeapObject::Instance(i) => snapshot.field_value(i, "map")?.as_object(),
+            _ => None,
+        })
+        .collect();
+    decoded
+        .into_iter()
+        .filter(|c| !set_maps.contains(&snapshot.objects[c.node as usize].object_id()))
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct FillSummary {
+    pub class_name: String,
+    pub instances: u64,
+    pub size: u64,
+    pub capacity: u64,
+    pub slack: u64,
+    // Number of instances by fill ratio: empty, below 25%, 50%, 75% and above.
+    pub buckets: [u64; 5],
+}
+
+pub fn fill_bucket(collection: &Collection) -> usize {
+    let ratio = collection.fill_ratio();
+    if collection.size == 0 {
+        0
+    } else if ratio < 0.25 {
+        1
+    } else if ratio < 0.5 {
+        2
+    } else if ratio < 0.75 {
+        3
+    } else {
+        4
+    }
+}
+
+// Fill ratios per collection class, sorted by slack bytes.
+pub fn fill_summary(snapshot: &Snapshot, collections: &[Collection]) -> Vec<FillSummary> {
+    let mut summaries: HashMap<String, FillSummary> = HashMap::new();
+    for c in collections {
+        let name = snapshot.object_class_name(&snapshot.objects[c.node as usize]);
+        let summary = summaries
+            .entry(name.clone())
+            .or_insert_with(|| FillSummary {
+                class_name: name,
+                instances: 0,
+                size: 0,
+                capacity: 0,
+                slack: 0,
+                buckets: [0; 5],
+            });
+        summary.instances += 1;
+        summary.size += c.size;
+        summary.capacity += c.capacity;
+        summary.slack += c.slack();
+        summary.buckets[fill_bucket(c)] += 1;
+    }
+    let mut summaries: Vec<FillSummary> = summaries.into_values().collect();
+    summaries.sort_by(|a, b| {
+        b.slack
+            .cmp(&a.slack)
+            .then_with(|| a.class_name.cmp(&b.class_name))
+    });
+    summaries
+}
+
+#[derive(Debug)]
+pub struct EmptyCollections {
+    // Class of the immediate dominator of the collections.
+    pub owner: String,
+    pub class_name: String,
+    pub instances: u64,
+    // Shallow bytes of the collections and everything they dominate.
+    pub bytes: u64,
+}
+
+//
+// Collections without elements that still have a non-empty backing array
+// (shared empty arrays don't count), grouped by owner (the class of their
+// immediate dominator) and collection class.
+//
+pub fn empty_collections(
+    snapshot: &Snapshot,
+    collections: &[Collection],
+    tree: &DominatorTree,
+    retained: &[u64],
+) -> Vec<EmptyCollections> {
+    let mut groups: HashMap<(String, String), EmptyCollections> = HashMap::new();
+    for c in collections {
+        if c.size != 0 || c.capacity == 0 || !tree.is_reachable(c.node) {
+            continue;
+        }
+        let idom = tree.idom[c.node as usize];
+        let owner = if idom == tree.virtual_root() {
+            "<GC root>".to_string()
+        } else {
+            snapshot.object_label(&snapshot.objects[idom as usize])
+        };
+        let class_name = snapshot.object_class_name(&snapshot.objects[c.node as usize]);
+        let group = groups
+            .entry((owner.clone(), class_name.clone()))
+            .or_insert_with(|| EmptyCollections {
+                owner,
+                class_name,
+                instances: 0,
+                bytes: 0,
+            });
+        group.instances += 1;
+        group.bytes += retained[c.node as usize];
+    }
+    let mut groups: Vec<EmptyCollections> = groups.into_values().collect();
+    groups.sort_by(|a, b| {
+        b.bytes
+            .cmp(&a.bytes)
+            .then_with(|| a.owner.cmp(&b.owner))
+            .then_with(|| a.class_name.cmp(&b.class_name))
+    });
+    groups
+}
+
+#[derive(Debug)]
+pub struct BucketStats {
+    pub node: u32,
+    pub size: u64,
+    pub capacity: u64,
+    // Non-empty buckets, and how many of them a uniform hash would fill.
+    pub occupied: u64,
+    pub expected_occupied: f64,
+    pub longest_chain: u64,
+    // Buckets turned into trees because of too many collisions.
+    pub tree_bins: u64,
+    // Class of the keys in the longest chain, whose hashCode() is to blame.
+    pub worst_key_class: Option<String>,
+}
+
+impl BucketStats {
+    // Occupied buckets relative to what a uniform hash would give.
+    pub fn spread(&self) -> f64 {
+        if self.expected_occupied == 0.0 {
+            1.0
+        } else {
+            self.occupied as f64 / self.expected_occupied
+        }
+    }
+}
+
+//
+// The entries of one bucket of a HashMap or ConcurrentHashMap table: the
+// bin's nodes are linked through `next`, including in tree bins (whose
+// nodes ConcurrentHashMap keeps behind a TreeBin's `first`).
+//
+fn bucket_entries<'a>(
+    snapshot: &'a Snapshot,
+    head: &'a InstanceDump,
+) -> (Vec<&'a InstanceDump>, bool) {
+    let class_name = snapshot.class_name(head.class_id);
+    let (mut current, tree) = match class_name.as_str() {
+        "java.util.concurrent.ConcurrentHashMap$TreeBin" => {
+            match object_field(snapshot, head, "first") {
+                Some(HeapObject::Instance(first)) => (Some(first), true),
+                _ => (None, true),
+            }
+        }
+        "java.util.concurrent.ConcurrentHashMap$ForwardingNode"
+        | "java.util.concurrent.ConcurrentHashMap$ReservationNode" => (None, false),
+        _ => (Some(head), class_name.ends_with("$TreeNode")),
+    };
+    let mut entries = Vec::new();
+    while let Some(node) = current {
+        entries.push(node);
+        current = match object_field(snapshot, node, "next") {
+            Some(HeapObject::Instance(next)) => Some(next),
+            _ => None,
+        };
+    }
+    (entries, tree)
+}
+
+pub fn bucket_stats(snapshot: &Snapshot, collection: &Collection) -> Option<BucketStats> {
+    if !matches!(
+        collection.kind,
+        CollectionKind::HashMap | CollectionKind::HashSet | CollectionKind::ConcurrentHashMap
+    ) {
+        return None;
+    }
+    let table = match snapshot.object(collection.array?) {
+        Some(HeapObject::ObjectArray(table)) => table,
+        _ => return None,
+    };
+    let mut stats = BucketStats {
+        node: collection.node,
+        size: collection.size,
+        capacity: collection.capacity,
+        occupied: 0,
+        expected_occupied: 0.0,
+        longest_chain: 0,
+        tree_bins: 0,
+        worst_key_class: None,
+    };
+    for &head in table.elements.iter().filter(|&&id| id != 0) {
+        let head = match snapshot.object(head) {
+            Some(HeapObject::Instance(head)) => head,
+            _ => continue,
+        };
+        let (entries, tree) = bucket_entries(snapshot, head);
+        if entries.is_empty() {
+            continue;
+        }
+        stats.occupied += 1;
+        if tree {
+            stats.tree_bins += 1;
+        }
+        if entries.len() as u64 > stats.longest_chain {
+            stats.longest_chain = entries.len() as u64;
+            stats.worst_key_class = object_field(snapshot, entries[0], "key")
+                .map(|key| snapshot.object_class_name(key));
+        }
+    }
+    // Expected number of non-empty buckets for n keys spread uniformly.
+    let (n, m) = (stats.size as f64, stats.capacity as f64);
+    if m > 0.0 {
+        stats.expected_occupied = m * (1.0 - (-n / m).exp());
+    }
+    Some(stats)
+}
+
+//
+// The logical contents of a collection, as the application sees them
+// rather than as tables of nodes.
+//
+#[derive(Debug, PartialEq)]
+pub enum Contents {
+    // Key and value ids of the entries of a map.
+    Entries(Vec<(u64, u64)>),
+    // Element ids of a list, set, deque or Optional.
+    Elements(Vec<u64>),
+    // The characters of a StringBuilder or StringBuffer.
+    Text(String),
+}
+
+// The classes whose contents are decoded (subclasses are handled too).
+pub const CONTENT_CLASSES: &[&str] = &[
+    "java.util.LinkedHashMap",
+    "java.util.HashMap",
+    "java.util.Hashtable",
+    "java.util.concurrent.ConcurrentHashMap",
+    "java.util.TreeMap",
+    "java.util.HashSet",
+    "java.util.TreeSet",
+    "java.util.ArrayList",
+    "java.util.Vector",
+    "java.util.ArrayDeque",
+    "java.util.LinkedList",
+    "java.util.Optional",
+    "java.lang.AbstractStringBuilder",
+];
+
+fn id_field(snapshot: &Snapshot, instance: &InstanceDump, name: &str) -> u64 {
+    snapshot
+        .field_value(instance, name)
+        .and_then(|v| v.as_object())
+        .unwrap_or(0)
+}
+
+fn instance_field<'a>(
+    snapshot: &'a Snapshot,
+    instance: &InstanceDump,
+    name: &str,
+) -> Option<&'a InstanceDump> {
+    match object_field(snapshot, instance, name)? {
+        HeapObject::Instance(i) => Some(i),
+        _ => None,
+    }
+}
+
+// Follows a linked list of nodes through their `next` field.
+fn linked<'a>(
+    snapshot: &'a Snapshot,
+    mut current: Option<&'a InstanceDump>,
+    next: &str,
+    limit: usize,
+) -> Vec<&'a InstanceDump> {
+    let mut nodes = Vec::new();
+    while let Some(node) = current {
+        if nodes.len() == limit {
+            break;
+        }
+        nodes.push(node);
+        current = instance_field(snapshot, node, next);
+    }
+    nodes
+}
+
+fn hash_entries(snapshot: &Snapshot, map: &InstanceDump, limit: usize) -> Vec<(u64, u64)> {
+    let table = match object_field(snapshot, map, "table") {
+        Some(HeapObject::ObjectArray(table)) => table,
+        _ => return Vec::new(),
+    };
+    let mut entries = Vec::new();
+    for head in table.elements.iter().filter_map(|&id| snapshot.object(id)) {
+        let head = match head {
+            HeapObject::Instance(head) => head,
+            _ => continue,
+        };
+        for node in bucket_entries(snapshot, head).0 {
+            if entries.len() == limit {
+                return entries;
+            }
+            let value = match snapshot.field_value(node, "value") {
+                Some(value) => value,
+                // ConcurrentHashMap$Node
+                None => snapshot
+                    .field_value(node, "val")
+                    .unwrap_or(Value::Object(0)),
+            };
+            entries.push((
+                id_field(snapshot, node, "key"),
+                value.as_object().unwrap_or(0),
+            ));
+        }
+    }
+    entries
+}
+
+// The entries of a TreeMap in key order.
+fn tree_entries(snapshot: &Snapshot, map: &InstanceDump, limit: usize) -> Vec<(u64, u64)> {
+    let mut entries = Vec::new();
+    let mut stack = Vec::new();
+    let mut current = instance_field(snapshot, map, "root");
+    while entries.len() < limit {
+        while let Some(node) = current {
+            // Red-black trees are shallow, deeper ones can't be trees.
+            if stack.len() > 128 {
+                return entries;
+            }
+            stack.push(node);
+            current = instance_field(snapshot, node, "left");
+        }
+        let node = match stack.pop() {
+            Some(node) => node,
+            None => break,
+        };
+        entries.push((
+            id_field(snapshot, node, "key"),
+            id_field(snapshot, node, "value"),
+        ));
+        current = instance_field(snapshot, node, "right");
+    }
+    entries
+}
+
+fn map_contents(snapshot: &Snapshot, map: &InstanceDump, limit: usize) -> Option<Vec<(u64, u64)>> {
+    match instance_contents(snapshot, map, limit)? {
+        Contents::Entries(entries) => Some(entries),
+        _ => None,
+    }
+}
+
+pub fn instance_contents(
+    snapshot: &Snapshot,
+    instance: &InstanceDump,
+    limit: usize,
+) -> Option<Contents> {
+    let mut class_id = instance.class_id;
+    while let Some(class) = snapshot.class_dump(class_id) {
+        let contents = match snapshot.class_name(class_id).as_str() {
+            "java.util.LinkedHashMap" => {
+                let head = instance_field(snapshot, instance, "head");
+                let entries = linked(snapshot, head, "after", limit)
+                    .into_iter()
+                    .map(|e| (id_field(snapshot, e, "key"), id_field(snapshot, e, "value")))
+                    .collect();
+                Contents::Entries(entries)
+            }
+            "java.util.HashMap"
+            | "java.util.Hashtable"
+            | "java.util.concurrent.ConcurrentHashMap" => {
+                Contents::Entries(hash_entries(snapshot, instance, limit))
+            }
+            "java.util.TreeMap" => Contents::Entries(tree_entries(snapshot, instance, limit)),
+            "java.util.HashSet" | "java.util.TreeSet" => {
+                let map = instance_field(snapshot, instance, "map")
+                    .or_else(|| instance_field(snapshot, instance, "m"))?;
+                let keys = map_contents(snapshot, map, limit)?;
+                Contents::Elements(keys.into_iter().map(|(key, _)| key).collect())
+            }
+            "java.util.ArrayList" | "java.util.Vector" => {
+                let size = int_field(snapshot, instance, "size")
+                    .or_else(|| int_field(snapshot, instance, "elementCount"))?
+                    .max(0) as usize;
+                match object_field(snapshot, instance, "elementData")? {
+                    HeapObject::ObjectArray(a) => Contents::Elements(
+                        a.elements.iter().take(size.min(limit)).copied().collect(),
+                    ),
+                    _ => return None,
+                }
+            }
+            "java.util.ArrayDeque" => {
+                let elements = match object_field(snapshot, instance, "elements")? {
+                    HeapObject::ObjectArray(a) => &a.elements,
+                    _ => return None,
+                };
+                let length = elements.len();
+                let head = int_field(snapshot, instance, "head")?.max(0) as usize;
+                let tail = int_field(snapshot, instance, "tail")?.max(0) as usize;
+                if length == 0 || head >= length || tail >= length {
+                    return None;
+                }
+                let count = (tail + length - head) % length;
+                Contents::Elements(
+                    (0..count.min(limit))
+                        .map(|i| elements[(head + i) % length])
+                        .collect(),
+                )
+            }
+            "java.util.LinkedList" => {
+                let first = instance_field(snapshot, instance, "first");
+                let items = linked(snapshot, first, "next", limit)
+                    .into_iter()
+                    .map(|node| id_field(snapshot, node, "item"))
+                    .collect();
+                Contents::Elements(items)
+            }
+            "java.util.Optional" => {
+                let value = id_field(snapshot, instance, "value");
+                Contents::Elements(if value == 0 { Vec::new() } else { vec![value] })
+            }
+            "java.lang.AbstractStringBuilder" => {
+                let content = string_content(snapshot, instance)?;
+                let count = int_field(snapshot, instance, "count")?.max(0) as usize;
+                let text = content.decode();
+                Contents::Text(text.chars().take(count).collect())
+            }
+            _ => {
+                class_id = class.super_class_id;
+                continue;
+            }
+        };
+        return Some(contents);
+    }
+    None
+}
+
+//
+// The contents of the common JDK collections (HashMap, LinkedHashMap,
+// TreeMap, ConcurrentHashMap, their sets, ArrayList, ArrayDeque,
+// LinkedList, Optional, StringBuilder), at most `limit` entries of them.
+//
+pub fn contents(snapshot: &Snapshot, object: &HeapObject, limit: usize) -> Option<Contents> {
+    match object {
+        HeapObject::Instance(i) => instance_contents(snapshot, i, limit),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dominator;
+    use crate::graph::Graph;
+    use crate::heap::GcRootKind;
+    use crate::retained::retained_sizes;
+    use crate::testing::Dump;
+
+    struct Classes {
+        list: u64,
+        map: u64,
+        set: u64,
+        node: u64,
+    }
+
+    fn classes(dump: &mut Dump) -> Classes {
+        let object = dump.object;
+        let list = dump.class(
+            "java/util/ArrayList",
+            object,
+            &[
+                ("elementData", FieldTag::ArrayObject),
+                ("size", FieldTag::Int),
+            ],
+        );
+        let map = dump.class(
+            "java/util/HashMap",
+            object,
+            &[("table", FieldTag::ArrayObject), ("size", FieldTag::Int)],
+        );
+        let set = dump.class(
+            "java/util/HashSet",
+            object,
+            &[("map", FieldTag::NormalObject)],
+        );
+        let node = dump.class(
+            "java/util/HashMap$Node",
+            object,
+            &[
+                ("hash", FieldTag::Int),
+                ("key", FieldTag::NormalObject),
+                ("value", FieldTag::NormalObject),
+                ("next", FieldTag::NormalObject),
+            ],
+        );
+        Classes {
+            list,
+            map,
+            set,
+            node,
+        }
+    }
+
+    // An ArrayList of `size` elements out of `capacity` slots.
+    fn list(dump: &mut Dump, classes: &Classes, size: i32, capacity: usize) -> u64 {
+        let array = dump.object_array;
+        let data = dump.object_array(array, &vec![0; capacity]);
+        dump.instance(classes.list, &[Value::Object(data), Value::Int(size)])
+    }
+
+    #[test]
+    fn collections_are_decoded_with_their_capacity() {
+        let mut dump = Dump::new();
+        let classes = classes(&mut dump);
+        let full = list(&mut dump, &classes, 10, 10);
+        let sparse = list(&mut dump, &classes, 1, 10);
+        let unallocated = dump.instance(classes.list, &[]);
+        let array = dump.object_array;
+        let table = dump.object_array(array, &[0; 16]);
+        let map = dump.instance(classes.map, &[Value::Object(table), Value::Int(5)]);
+        let set = dump.instance(classes.set, &[Value::Object(map)]);
+        let snapshot = dump.load();
+
+        let id = |c: &Collection| snapshot.objects[c.node as usize].object_id();
+        let mut decoded: Vec<(u64, CollectionKind, u64, u64)> = collections(&snapshot)
+            .iter()
+            .map(|c| (id(c), c.kind, c.size, c.capacity))
+            .collect();
+        decoded.sort_by_key(|c| c.0);
+        // The HashMap of the set is only counted as the set.
+        assert_eq!(
+            decoded,
+            vec![
+                (full, CollectionKind::ArrayList, 10, 10),
+                (sparse, CollectionKind::ArrayList, 1, 10),
+                (unallocated, CollectionKind::ArrayList, 0, 0),
+                (set, CollectionKind::HashSet, 5, 16),
+            ]
+        );
+
+        let summaries = fill_summary(&snapshot, &collections(&snapshot));
+        assert_eq!(summaries[0].class_name, "java.util.HashSet");
+        assert_eq!(summaries[0].slack, 11 * 8);
+        let lists = &summaries[1];
+        assert_eq!(lists.class_name, "java.util.ArrayList");
+        assert_eq!((lists.instances, lists.size, lists.capacity), (3, 11, 20));
+        assert_eq!(lists.slack, 9 * 8);
+        assert_eq!(lists.buckets, [1, 1, 0, 0, 1]);
+    }
+
+    #[test]
+    fn empty_collections_are_grouped_by_owner() {
+        let mut dump = Dump::new();
+        let classes = classes(&mut dump);
+        let owner = dump.class(
+            "test/Owner",
+            dump.object,
+            &[("a", FieldTag::NormalObject), ("b", FieldTag::NormalObject)],
+        );
+        let mut owned = Vec::new();
+        for _ in 0..2 {
+            let empty = list(&mut dump, &classes, 0, 10);
+            let unallocated = dump.instance(classes.list, &[]);
+            let instance =
+                dump.instance(owner, &[Value::Object(empty), Value::Object(unallocated)]);
+            dump.root(GcRootKind::StickyClass, instance);
+            owned.push(empty);
+        }
+        let rooted = list(&mut dump, &classes, 0, 4);
+        dump.root(GcRootKind::JniGlobal, rooted);
+        let used = list(&mut dump, &classes, 1, 10);
+        dump.root(GcRootKind::JniGlobal, used);
+        // Garbage isn't reported.
+        list(&mut dump, &classes, 0, 10);
+        let snapshot = dump.load();
+
+        let tree = dominator::build(&Graph::build(&snapshot));
+        let retained = retained_sizes(&snapshot, &tree);
+        let groups = empty_collections(&snapshot, &collections(&snapshot), &tree, &retained);
+        let size = |id| snapshot.shallow_size(snapshot.object(id).unwrap());
+        // The shallow sizes of a list and its array.
+        let bytes = |id| match snapshot.object(id) {
+            Some(HeapObject::Instance(i)) => size(id) + size(id_field(&snapshot, i, "elementData")),
+            _ => unreachable!(),
+        };
+        let found: Vec<(&str, &str, u64, u64)> = groups
+            .iter()
+            .map(|g| {
+                (
+                    g.owner.as_str(),
+                    g.class_name.as_str(),
+                    g.instances,
+                    g.bytes,
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("test.Owner", "java.util.ArrayList", 2, 2 * bytes(owned[0])),
+                ("<GC root>", "java.util.ArrayList", 1, bytes(rooted)),
+            ]
+        );
+    }
+
+    #[test]
+    fn buckets_show_colliding_keys() {
+        let mut dump = Dump::new();
+        let classes = classes(&mut dump);
+        let node = classes.node;
+        let tree_node = dump.class("java/util/HashMap$TreeNode", node, &[]);
+        let bad_key = dump.class("test/BadKey", dump.object, &[]);
+        let mut chain = 0;
+        for _ in 0..3 {
+            let key = dump.instance(bad_key, &[]);
+            chain = dump.instance(
+                node,
+                &[
+                    Value::Int(0),
+                    Value::Object(key),
+                    Value::Object(0),
+                    Value::Object(chain),
+                ],
+            );
+        }
+        let key = dump.string("key");
+        let single = dump.instance(node, &[Value::Int(1), Value::Object(key)]);
+        let tree = dump.instance(tree_node, &[Value::Int(2), Value::Object(key)]);
+        let array = dump.object_array;
+        let table = dump.object_array(array, &[chain, single, tree, 0, 0, 0, 0, 0]);
+        dump.instance(classes.map, &[Value::Object(table), Value::Int(5)]);
+        let snapshot = dump.load();
+
+        let maps = collections(&snapshot);
+        assert_eq!(maps.len(), 1);
+        let stats = bucket_stats(&snapshot, &maps[0]).unwrap();
+        assert_eq!((stats.size, stats.capacity), (5, 8));
+        assert_eq!(
+            (stats.occupied, stats.longest_chain, stats.tree_bins),
+            (3, 3, 1)
+        );
+        assert_eq!(stats.worst_key_class.as_deref(), Some("test.BadKey"));
+        let expected = 8.0 * (1.0 - (-5.0f64 / 8.0).exp());
+        assert!((stats.expected_occupied - expected).abs() < 1e-9);
+        assert!(stats.spread() < 1.0);
+    }
+
+    #[test]
+    fn contents_are_decoded_in_iteration_order() {
+        let mut dump = Dump::new();
+        let classes = classes(&mut dump);
+        let object = dump.object;
+        let my_list = dump.class("test/MyList", classes.list, &[]);
+        let tree_map = dump.class(
+            "java/util/TreeMap",
+            object,
+            &[("root", FieldTag::NormalObject)],
+        );
+        let tree_entry = dump.class(
+            "java/util/TreeMap$Entry",
+            object,
+            &[
+                ("key", FieldTag::NormalObject),
+                ("value", FieldTag::NormalObject),
+                ("left", FieldTag::NormalObject),
+                ("right", FieldTag::NormalObject),
+            ],
+        );
+        let deque = dump.class(
+            "java/util/ArrayDeque",
+            object,
+            &[
+                ("elements", FieldTag::ArrayObject),
+                ("head", FieldTag::Int),
+                ("tail", FieldTag::Int),
+            ],
+        );
+        let optional = dump.class(
+            "java/util/Optional",
+            object,
+            &[("value", FieldTag::NormalObject)],
+        );
+        let builder = dump.class(
+            "java/lang/AbstractStringBuilder",
+            object,
+            &[
+                ("value", FieldTag::ArrayObject),
+                ("coder", FieldTag::Byte),
+                ("count", FieldTag::Int),
+            ],
+        );
+        let string_builder = dump.class("java/lang/StringBuilder", builder, &[]);
+        let e: Vec<u64> = (0..4).map(|_| dump.instance(object, &[])).collect();
+        let array = dump.object_array;
+        let mut collections = Vec::new();
+
+        let data = dump.object_array(array, &[e[0], e[1], e[2], 0]);
+        collections.push(dump.instance(my_list, &[Value::Object(data), Value::Int(3)]));
+
+        let node = |dump: &mut Dump, key, next| {
+            let values = [
+                Value::Int(0),
+                Value::Object(key),
+                Value::Object(0),
+                Value::Object(next),
+            ];
+            dump.instance(classes.node, &values)
+        };
+        let chained = node(&mut dump, e[2], 0);
+        let chain = node(&mut dump, e[1], chained);
+        let single = node(&mut dump, e[0], 0);
+        let table = dump.object_array(array, &[0, chain, 0, single]);
+        let map = dump.instance(classes.map, &[Value::Object(table), Value::Int(3)]);
+        collections.push(dump.instance(classes.set, &[Value::Object(map)]));
+
+        let entry = |dump: &mut Dump, key, left, right| {
+            let values = [key, e[3], left, right].map(Value::Object);
+            dump.instance(tree_entry, &values)
+        };
+        let left = entry(&mut dump, e[0], 0, 0);
+        let right = entry(&mut dump, e[2], 0, 0);
+        let root = entry(&mut dump, e[1], left, right);
+        collections.push(dump.instance(tree_map, &[Value::Object(root)]));
+
+        let elements = dump.object_array(array, &[e[1], 0, 0, e[0]]);
+        let values = [Value::Object(elements), Value::Int(3), Value::Int(1)];
+        collections.push(dump.instance(deque, &values));
+
+        collections.push(dump.instance(optional, &[Value::Object(e[3])]));
+        collections.push(dump.instance(optional, &[]));
+
+        let text = dump.primitive_array(FieldTag::Byte, b"hello\0\0\0");
+        let values = [Value::Object(text), Value::Byte(0), Value::Int(3)];
+        collections.push(dump.instance(string_builder, &values));
+        let snapshot = dump.load();
+
+        let decoded: Vec<Option<Contents>> = collections
+            .iter()
+            .map(|&id| contents(&snapshot, snapshot.object(id).unwrap(), 10))
+            .collect();
+        assert_eq!(
+            decoded,
+            vec![
+                Some(Contents::Elements(vec![e[0], e[1], e[2]])),
+                Some(Contents::Elements(vec![e[1], e[2], e[0]])),
+                Some(Contents::Entries(vec![
+                    (e[0], e[3]),
+                    (e[1], e[3]),
+                    (e[2], e[3])
+                ])),
+                Some(Contents::Elements(vec![e[0], e[1]])),
+                Some(Contents::Elements(vec![e[3]])),
+                Some(Contents::Elements(Vec::new())),
+                Some(Contents::Text("hel".to_string())),
+            ]
+        );
+        let limited = contents(&snapshot, snapshot.object(collections[1]).unwrap(), 2);
+        assert_eq!(limited, Some(Contents::Elements(vec![e[1], e[2]])));
+        assert_eq!(
+            contents(&snapshot, snapshot.object(e[0]).unwrap(), 10),
+            None
+        );
+    }
+}
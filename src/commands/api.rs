@@ -0,0 +1,388 @@
+//
+// The JSON API of serve: queries over the snapshot loaded once, answered as
+// JSON values. The reference graph, its reverse, the reachability and the
+// dominator tree are only computed by the first query that needs them and
+// kept for the next.
+//
+use super::graphql;
+use crate::cli::{self, Args};
+use hprof_cat::decoders::Decoders;
+use hprof_cat::dominator::DominatorTree;
+use hprof_cat::graph::{self, Adjacency, Graph};
+use hprof_cat::heap::HeapObject;
+use hprof_cat::histogram::HistogramEntry;
+use hprof_cat::http::{self, Request, Response};
+use hprof_cat::json::{self, Json};
+use hprof_cat::paths::Hop;
+use hprof_cat::predicate::{self, FieldValue, Predicate};
+use hprof_cat::reference::{self, RetentionFilter};
+use hprof_cat::snapshot::Snapshot;
+use hprof_cat::threads::{self, ThreadInfo};
+use hprof_cat::{cache, histogram, paths, reachability};
+
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::sync::OnceLock;
+
+// An error of a query: the HTTP status and what went wrong.
+pub type Error = (u16, String);
+
+pub struct Heap {
+    pub snapshot: Snapshot,
+    graph: OnceLock<(Graph, Adjacency)>,
+    live: OnceLock<Vec<bool>>,
+    retained: OnceLock<(DominatorTree, Vec<u64>)>,
+    threads: OnceLock<Vec<ThreadInfo>>,
+    class_totals: OnceLock<HashMap<u64, (u64, u64)>>,
+}
+
+fn id_json(id: u64) -> Json {
+    Json::Str(format!("{:#x}", id))
+}
+
+fn member(name: &str, value: Json) -> (String, Json) {
+    (name.to_string(), value)
+}
+
+fn field_json(value: &FieldValue) -> Json {
+    match value {
+        FieldValue::Int(v) => Json::number(v),
+        FieldValue::Float(v) => Json::float(*v),
+        FieldValue::Str(v) => Json::Str(v.clone()),
+        FieldValue::Bool(v) => Json::Bool(*v),
+        FieldValue::Null => Json::Null,
+        FieldValue::Object(id) => id_json(*id),
+    }
+}
+
+impl Heap {
+    pub fn new(snapshot: Snapshot) -> Heap {
+        Heap {
+            snapshot,
+            graph: OnceLock::new(),
+            live: OnceLock::new(),
+            retained: OnceLock::new(),
+            threads: OnceLock::new(),
+            class_totals: OnceLock::new(),
+        }
+    }
+
+    // The reference graph and its predecessors.
+    pub fn graph(&self) -> &(Graph, Adjacency) {
+        self.graph.get_or_init(|| {
+            let graph = Graph::build(&self.snapshot);
+            let preds = cache::predecessors(&self.snapshot, &graph);
+            (graph, preds)
+        })
+    }
+
+    pub fn live(&self) -> &[bool] {
+        self.live
+            .get_or_init(|| reachability::mark(&self.graph().0))
+    }
+
+    pub fn retained(&self) -> &(DominatorTree, Vec<u64>) {
+        self.retained
+            .get_or_init(|| cache::retained_sizes(&self.snapshot, &self.graph().0))
+    }
+
+    pub fn threads(&self) -> &[ThreadInfo] {
+        self.threads
+            .get_or_init(|| threads::threads(&self.snapshot))
+    }
+
+    // Instances and their shallow bytes by class id.
+    pub fn class_totals(&self) -> &HashMap<u64, (u64, u64)> {
+        self.class_totals.get_or_init(|| {
+            let snapshot = &self.snapshot;
+            let mut totals: HashMap<u64, (u64, u64)> = HashMap::new();
+            for object in &snapshot.objects {
+                if let Some(class_id) = snapshot.class_of(object) {
+                    let total = totals.entry(class_id).or_default();
+                    total.0 += 1;
+                    total.1 += snapshot.shallow_size(object);
+                }
+            }
+            totals
+        })
+    }
+
+    pub fn index(&self, id: &str) -> Result<u32, Error> {
+        let id = cli::object_id(id).map_err(|e| (400, e))?;
+        self.snapshot
+            .index_of(id)
+            .ok_or_else(|| (404, format!("no object with id {:#x}", id)))
+    }
+
+    // The shallow bytes of all the objects.
+    pub fn bytes(&self) -> u64 {
+        let snapshot = &self.snapshot;
+        snapshot
+            .objects
+            .iter()
+            .map(|o| snapshot.shallow_size(o))
+            .sum()
+    }
+
+    pub fn summary(&self) -> Json {
+        let snapshot = &self.snapshot;
+        Json::Object(vec![
+            member(
+                "format",
+                Json::Str(snapshot.header.format.trim_end_matches('\0').to_string()),
+            ),
+            member("id_size", Json::number(snapshot.id_size())),
+            member("timestamp_ms", Json::number(snapshot.header.timestamp_ms())),
+            member("objects", Json::number(snapshot.objects.len())),
+            member("bytes", Json::number(self.bytes())),
+            member("classes", Json::number(snapshot.classes.len())),
+            member("gc_roots", Json::number(snapshot.roots.len())),
+            member("threads", Json::number(snapshot.threads.len())),
+            member("strings", Json::number(snapshot.strings.len())),
+            member(
+                "size_model",
+                Json::Str(snapshot.size_model.name().to_string()),
+            ),
+        ])
+    }
+
+    // Instances and shallow bytes per class, of the live objects if `live`.
+    pub fn entries(&self, live: bool) -> Vec<HistogramEntry> {
+        if live {
+            let marked = self.live();
+            histogram::histogram(&self.snapshot, |i| marked[i as usize])
+        } else {
+            histogram::histogram(&self.snapshot, |_| true)
+        }
+    }
+
+    pub fn histogram(&self, top: usize, live: bool) -> Json {
+        Json::Array(
+            self.entries(live)
+                .iter()
+                .take(top)
+                .map(|e| {
+                    Json::Object(vec![
+                        member("class", Json::Str(e.class_name.clone())),
+                        member("instances", Json::number(e.instances)),
+                        member("shallow", Json::number(e.shallow)),
+                    ])
+                })
+                .collect(),
+        )
+    }
+
+    pub fn object(&self, id: &str, depth: usize, width: usize) -> Result<Json, Error> {
+        let object = &self.snapshot.objects[self.index(id)? as usize];
+        Ok(json::object_json(
+            &self.snapshot,
+            &Decoders::builtin(),
+            object,
+            depth,
+            width,
+        ))
+    }
+
+    //
+    // The shortest path from an object to a GC root, the object first,
+    // ignoring the reference kinds in the `exclude` list.
+    //
+    pub fn root_path(&self, index: u32, exclude: Option<&str>) -> Result<Option<Vec<Hop>>, Error> {
+        let snapshot = &self.snapshot;
+        let excluded = match exclude {
+            Some(list) => reference::parse_kinds(list).map_err(|e| (400, e))?,
+            None => Vec::new(),
+        };
+        let filter = RetentionFilter::new(snapshot, excluded);
+        let (graph, preds) = self.graph();
+        Ok(paths::shortest_path_to_root(
+            snapshot, graph, preds, &filter, index,
+        ))
+    }
+
+    // The path of root_path(), with how each object refers to the previous one.
+    pub fn path(&self, id: &str, exclude: Option<&str>) -> Result<Json, Error> {
+        let snapshot = &self.snapshot;
+        let path = match self.root_path(self.index(id)?, exclude)? {
+            Some(path) => path,
+            None => return Ok(Json::Null),
+        };
+        let hops = path
+            .iter()
+            .map(|hop| {
+                let object = &snapshot.objects[hop.node as usize];
+                Json::Object(vec![
+                    member("id", id_json(object.object_id())),
+                    member("class", Json::Str(snapshot.object_label(object))),
+                    member(
+                        "via",
+                        hop.via.map_or(Json::Null, |via| {
+                            Json::Str(graph::qualified_via_name(snapshot, via))
+                        }),
+                    ),
+                ])
+            })
+            .collect();
+        let root_id = snapshot.objects[path.last().unwrap().node as usize].object_id();
+        let roots = snapshot
+            .roots
+            .iter()
+            .filter(|r| r.object_id == root_id)
+            .map(|r| {
+                Json::Object(vec![
+                    member("kind", Json::Str(r.kind.name().to_string())),
+                    member(
+                        "thread",
+                        r.thread_serial_num.map_or(Json::Null, Json::number),
+                    ),
+                ])
+            })
+            .collect();
+        Ok(Json::Object(vec![
+            member("chain", Json::Str(paths::render_chain(snapshot, &path))),
+            member("path", Json::Array(hops)),
+            member("roots", Json::Array(roots)),
+        ]))
+    }
+
+    //
+    // The instances of a class (and its subclasses) matching a predicate as
+    // in find: how many there are and the first `limit` of them.
+    //
+    pub fn matching(
+        &self,
+        class: &str,
+        predicate: Option<&Predicate>,
+        limit: usize,
+    ) -> Result<(usize, Vec<u32>), Error> {
+        let snapshot = &self.snapshot;
+        let classes = snapshot.subclasses(class);
+        if classes.is_empty() {
+            return Err((404, format!("no class named {}", class)));
+        }
+        let (mut found, mut nodes) = (0, Vec::new());
+        for (node, object) in snapshot.objects.iter().enumerate() {
+            let instance = match object {
+                HeapObject::Instance(i) if classes.contains(&i.class_id) => i,
+                _ => continue,
+            };
+            if predicate.is_none_or(|p| p.matches(snapshot, instance)) {
+                found += 1;
+                if nodes.len() < limit {
+                    nodes.push(node as u32);
+                }
+            }
+        }
+        Ok((found, nodes))
+    }
+
+    // Like matching(), with the values of the fields the predicate looks at.
+    pub fn query(&self, class: &str, filter: Option<&str>, limit: usize) -> Result<Json, Error> {
+        let snapshot = &self.snapshot;
+        let predicate = match filter {
+            Some(text) => Some(Predicate::parse(text).map_err(|e| (400, e))?),
+            None => None,
+        };
+        let (found, nodes) = self.matching(class, predicate.as_ref(), limit)?;
+        let objects = nodes
+            .iter()
+            .filter_map(|&node| match &snapshot.objects[node as usize] {
+                HeapObject::Instance(instance) => Some(instance),
+                _ => None,
+            })
+            .map(|instance| {
+                let fields = predicate
+                    .iter()
+                    .flat_map(|p| p.paths())
+                    .map(|path| {
+                        let value = predicate::resolve(snapshot, instance, path);
+                        (
+                            path.join("."),
+                            value.as_ref().map_or(Json::Null, field_json),
+                        )
+                    })
+                    .collect();
+                Json::Object(vec![
+                    member("id", id_json(instance.object_id)),
+                    member("class", Json::Str(snapshot.class_name(instance.class_id))),
+                    member("fields", Json::Object(fields)),
+                ])
+            })
+            .collect();
+        Ok(Json::Object(vec![
+            member("matches", Json::number(found)),
+            member("objects", Json::Array(objects)),
+        ]))
+    }
+}
+
+fn number(request: &Request, name: &str, default: usize) -> Result<usize, Error> {
+    match request.param(name) {
+        Some(value) => value
+            .parse()
+            .map_err(|_| (400, format!("bad {}: {}", name, value))),
+        None => Ok(default),
+    }
+}
+
+fn flag(request: &Request, name: &str) -> bool {
+    matches!(request.param(name), Some("") | Some("1") | Some("true"))
+}
+
+// Answers one request of the API.
+pub fn route(heap: &Heap, request: &Request) -> Result<Json, Error> {
+    if request.method != "GET" && request.method != "POST" {
+        return Err((405, format!("{} is not supported", request.method)));
+    }
+    let segments: Vec<&str> = request.path.split('/').filter(|s| !s.is_empty()).collect();
+    match segments[..] {
+        ["summary"] => Ok(heap.summary()),
+        ["histogram"] => Ok(heap.histogram(number(request, "top", 100)?, flag(request, "live"))),
+        ["object", id] => heap.object(
+            id,
+            number(request, "depth", 3)?,
+            number(request, "width", 10)?,
+        ),
+        ["paths", id] => heap.path(id, request.param("exclude")),
+        ["graphql"] => graphql::request(heap, request),
+        ["query"] => match request.param("class") {
+            Some(class) => heap.query(
+                class,
+                request.param("where"),
+                number(request, "limit", 100)?,
+            ),
+            None => Err((400, "query requires class".to_string())),
+        },
+        _ => Err((404, format!("no endpoint {}", request.path))),
+    }
+}
+
+fn error_json(message: &str) -> Json {
+    Json::Object(vec![member("error", Json::Str(message.to_string()))])
+}
+
+//
+// Serves the JSON API over HTTP on --listen (127.0.0.1:8080 by default)
+// until killed: GET /summary, /histogram?top=N&live=1, /object/<id>?depth=N
+// &width=N, /paths/<id>?exclude=... and /query?class=C&where=EXPR&limit=N,
+// and GraphQL queries at /graphql, whose schema is at /graphql/schema.
+//
+pub fn serve(snapshot: Snapshot, args: &Args) {
+    if !args.flag("--api") {
+        cli::die("serve only has a JSON API for now, give --api");
+    }
+    let address = args.value("--listen").unwrap_or("127.0.0.1:8080");
+    let listener =
+        TcpListener::bind(address).unwrap_or_else(|e| cli::die(&format!("{}: {}", address, e)));
+    eprintln!("serving the JSON API on http://{}", address);
+    let heap = Heap::new(snapshot);
+    http::serve(listener, |request| {
+        if request.path == "/graphql/schema" {
+            return Response::text(200, graphql::SCHEMA.to_string());
+        }
+        match route(&heap, request) {
+            Ok(json) => Response::json(200, json.pretty()),
+            Err((status, message)) => Response::json(status, error_json(&message).pretty()),
+        }
+    });
+}
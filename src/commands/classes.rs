@@ -0,0 +1,483 @@
+//
+// Commands about the classes themselves rather than individual objects.
+//
+use crate::cli::{self, Args};
+use hprof_cat::archive;
+use hprof_cat::external::DiskGraph;
+use hprof_cat::graph::Graph;
+use hprof_cat::heap::HeapObject;
+use hprof_cat::hierarchy::{Hierarchy, TypeGroups};
+use hprof_cat::jfr::{self, ClassAllocations};
+use hprof_cat::lazy::{LazyHeap, Mapping};
+use hprof_cat::phd;
+use hprof_cat::shard::{self, Shard};
+use hprof_cat::sizes::SizeModel;
+use hprof_cat::snapshot::Snapshot;
+use hprof_cat::{cache, diff, enums, histogram, reachability};
+
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+//
+// The subclass tree under a class (or all of them) with the instances of
+// each class and, in parentheses, of the class and all its subclasses.
+// Branches without any instance are left out unless --all is given.
+//
+pub fn print_hierarchy(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let hierarchy = Hierarchy::build(snapshot);
+    let max_depth = args.number("--depth", u64::MAX) as usize;
+    let all = args.flag("--all");
+    let starts: Vec<u64> = match args.positional.first() {
+        Some(name) => {
+            let mut found: Vec<u64> = hierarchy
+                .classes
+                .keys()
+                .copied()
+                .filter(|&id| snapshot.class_name(id) == *name)
+                .collect();
+            if found.is_empty() {
+                cli::die(&format!("no class named {}", name));
+            }
+            found.sort_unstable();
+            found
+        }
+        None => hierarchy.roots.clone(),
+    };
+
+    println!(
+        "{:>10} {:>14} {:>10} {:>14}  Class",
+        "Instances", "Shallow", "Total", "Total shallow"
+    );
+    let mut stack: Vec<(u64, usize)> = starts.iter().rev().map(|&id| (id, 0)).collect();
+    while let Some((id, depth)) = stack.pop() {
+        let node = &hierarchy.classes[&id];
+        if node.total_instances == 0 && !all && (depth > 0 || args.positional.is_empty()) {
+            continue;
+        }
+        println!(
+            "{:>10} {:>14} {:>10} {:>14}  {}{}",
+            node.instances,
+            units.bytes(node.shallow),
+            node.total_instances,
+            units.bytes(node.total_shallow),
+            "  ".repeat(depth),
+            snapshot.class_name(id)
+        );
+        if depth < max_depth {
+            stack.extend(node.subclasses.iter().rev().map(|&s| (s, depth + 1)));
+        }
+    }
+}
+
+//
+// The declared and inherited instance fields of classes with their offsets
+// in the instance dumps, checked against the instance size recorded in the
+// class dump and against the actual instances.
+//
+pub fn print_layout(snapshot: &Snapshot, args: &Args) {
+    if args.positional.is_empty() {
+        cli::die("layout: no class given");
+    }
+    let id_size = snapshot.id_size();
+    for (n, name) in args.positional.iter().enumerate() {
+        let class_id = snapshot
+            .find_class(name)
+            .unwrap_or_else(|| cli::die(&format!("no class named {}", name)));
+        let class = snapshot.class_dump(class_id).unwrap();
+        let layout = snapshot.field_layout(class_id);
+        if n > 0 {
+            println!();
+        }
+        println!("{} ({:#x})", name, class_id);
+        println!("{:>8} {:>6}  Field", "Offset", "Size");
+        let mut declaring_class = 0;
+        for field in &layout {
+            if field.class_id != declaring_class {
+                declaring_class = field.class_id;
+                println!("  {}:", snapshot.class_name(declaring_class));
+            }
+            println!(
+                "{:>8} {:>6}    {} {}",
+                field.offset,
+                field.tag.size(id_size),
+                field.tag.java_name(),
+                snapshot.string(field.name_id)
+            );
+        }
+        let fields_size: u32 = layout.iter().map(|f| f.tag.size(id_size)).sum();
+        println!(
+            "fields: {} bytes, class dump instance size: {} bytes{}",
+            fields_size,
+            class.instance_size,
+            if fields_size == class.instance_size {
+                ""
+            } else {
+                " (mismatch)"
+            }
+        );
+        println!(
+            "shallow size in this dump: {} bytes (with a {} byte header)",
+            2 * id_size + fields_size,
+            2 * id_size
+        );
+
+        let mut instances = 0;
+        let mut mismatched = 0;
+        for object in &snapshot.objects {
+            if let HeapObject::Instance(i) = object {
+                if i.class_id == class_id {
+                    instances += 1;
+                    if i.data.len() != fields_size as usize {
+                        mismatched += 1;
+                    }
+                }
+            }
+        }
+        println!(
+            "{} instances, {} with a different data length",
+            instances, mismatched
+        );
+    }
+}
+
+//
+// Enum classes with their number of constants and instances, those with
+// more instances than constants first.
+//
+pub fn print_enums(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let top = args.number("--top", 25) as usize;
+    let hierarchy = Hierarchy::build(snapshot);
+    let (_, retained) = cache::retained_sizes(snapshot, &Graph::build(snapshot));
+    let audits = enums::audit_enums(snapshot, &hierarchy, &retained);
+
+    let suspicious = audits.iter().filter(|a| a.has_extra_instances()).count();
+    println!(
+        "{} enum classes, {} with more instances than constants",
+        audits.len(),
+        suspicious
+    );
+    println!(
+        "{:>10} {:>10} {:>14}  Class",
+        "Constants", "Instances", "Retained"
+    );
+    for a in audits.iter().take(top.max(suspicious)) {
+        println!(
+            "{:>10} {:>10} {:>14}  {}{}",
+            a.constants
+                .map(|c| c.to_string())
+                .unwrap_or_else(|| "?".to_string()),
+            a.instances,
+            units.bytes(a.retained),
+            a.class_name,
+            if a.has_extra_instances() {
+                "  [extra instances]"
+            } else {
+                ""
+            }
+        );
+    }
+}
+
+//
+// The class histogram folded up the superclass chains: into the --base
+// classes for their subclasses, and otherwise into the superclass --depth
+// levels below java.lang.Object.
+//
+pub fn print_rollup(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let top = args.number("--top", 25) as usize;
+    let hierarchy = Hierarchy::build(snapshot);
+    let mut groups = match args.value("--base") {
+        Some(bases) => {
+            let bases: Vec<&str> = bases.split(',').collect();
+            for base in &bases {
+                if snapshot.find_class(base).is_none() {
+                    cli::die(&format!("no class named {}", base));
+                }
+            }
+            hierarchy.supertype_groups(snapshot, &bases)
+        }
+        None => TypeGroups::default(),
+    };
+    if args.value("--base").is_none() || args.value("--depth").is_some() {
+        let depth = args.number("--depth", 1) as usize;
+        for (id, group) in hierarchy.superclass_groups(snapshot, depth) {
+            groups.classes.entry(id).or_insert(group);
+        }
+    }
+
+    let mut classes: HashMap<String, HashSet<u64>> = HashMap::new();
+    for object in &snapshot.objects {
+        if let Some(id) = snapshot.class_of(object) {
+            classes
+                .entry(groups.name(snapshot, object))
+                .or_default()
+                .insert(id);
+        }
+    }
+    let histogram =
+        histogram::histogram_by(snapshot, |_| true, |object| groups.name(snapshot, object));
+    println!(
+        "{:>10} {:>14} {:>8}  Group",
+        "Instances", "Shallow", "Classes"
+    );
+    for entry in histogram.iter().take(top) {
+        println!(
+            "{:>10} {:>14} {:>8}  {}",
+            entry.instances,
+            units.bytes(entry.shallow),
+            classes.get(&entry.class_name).map_or(1, |c| c.len()),
+            entry.class_name
+        );
+    }
+}
+
+//
+// The class histogram straight from the mapped file: only the object
+// headers are read, so it is ready long before a full load would be. It
+// includes the garbage and uses the sizes in the dump, unless --live is
+// given: the reference graph is then written to temporary files (under
+// --temp-dir) and marked from there, for dumps too large to load.
+//
+pub fn print_quick_histogram(filename: &str, args: &Args) {
+    let units = args.units();
+    let names = args.names();
+    let top = args.number("--top", 25) as usize;
+    let heap = LazyHeap::open(filename, &args.window()).unwrap_or_else(|e| cli::die(&e));
+    let histogram = if args.flag("--live") {
+        let parent = args
+            .value("--temp-dir")
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir);
+        let marked = DiskGraph::build(&heap, &parent).mark();
+        heap.histogram(|i| marked[i as usize])
+    } else {
+        heap.histogram(|_| true)
+    };
+    println!(
+        "{} objects, {} in {} classes",
+        histogram.iter().map(|e| e.instances).sum::<u64>(),
+        units.amount(histogram.iter().map(|e| e.shallow).sum::<u64>()),
+        histogram.len()
+    );
+    println!("{:>10} {:>14}  Class", "Instances", "Shallow");
+    for entry in histogram.iter().take(top) {
+        println!(
+            "{:>10} {:>14}  {}",
+            entry.instances,
+            units.bytes(entry.shallow),
+            names.class(&entry.class_name)
+        );
+    }
+}
+
+// Exports the shard of a dump that one of --shards processes parses.
+pub fn print_shard(filename: &str, args: &Args) {
+    let count = args.number("--shards", 0) as usize;
+    let index = args.number("--index", 0) as usize;
+    if count == 0 || index >= count {
+        cli::die("shard requires --shards N and --index K with K < N");
+    }
+    let (snapshot, extent) = Snapshot::load_shard(filename, index, count, &args.window())
+        .unwrap_or_else(|e| cli::die(&e));
+    let text = Shard::build(&snapshot, &extent, index, count).to_text();
+    match args.value("--out") {
+        Some(out) => {
+            std::fs::write(out, text).unwrap_or_else(|e| cli::die(&format!("{}: {}", out, e)))
+        }
+        None => print!("{}", text),
+    }
+}
+
+// The histogram and graph statistics of a dump from all its shards.
+pub fn print_merged_shards(files: &[String], args: &Args) {
+    let units = args.units();
+    let names = args.names();
+    let top = args.number("--top", 25) as usize;
+    let shards = files
+        .iter()
+        .map(|file| {
+            let text = std::fs::read_to_string(file)
+                .unwrap_or_else(|e| cli::die(&format!("{}: {}", file, e)));
+            Shard::parse(&text).unwrap_or_else(|e| cli::die(&format!("{}: {}", file, e)))
+        })
+        .collect();
+    let merged = shard::merge(shards).unwrap_or_else(|e| cli::die(&e));
+    println!(
+        "{} objects, {} in {} classes, from {} shards",
+        merged.objects,
+        units.amount(merged.shallow),
+        merged.histogram.len(),
+        merged.shards
+    );
+    println!(
+        "{} references, {} within a shard, {} GC roots",
+        merged.references, merged.local, merged.roots
+    );
+    println!("{:>10} {:>14}  Class", "Instances", "Shallow");
+    for entry in merged.histogram.iter().take(top) {
+        println!(
+            "{:>10} {:>14}  {}",
+            entry.instances,
+            units.bytes(entry.shallow),
+            names.class(&entry.class_name)
+        );
+    }
+}
+
+//
+// Compares the class histogram of the dump with an earlier one, either of
+// another dump or the text of jmap -histo (or GC.class_histogram), which is
+// often all that was captured before an incident. jmap counts the sizes of
+// the running JVM: --size-model auto comes closer to them than the sizes in
+// the dump, which are warned about. Both can be in archives, like the dump.
+// --live only counts the reachable objects, like jmap -histo:live.
+//
+pub fn print_histogram_diff(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let names = args.names();
+    let path = match args.positional.first() {
+        Some(path) => path,
+        None => cli::die("histogram-diff: no earlier dump or histogram given"),
+    };
+    let top = args.number("--top", 25) as usize;
+    let prepared = cli::prepare(path);
+    let failed = |e: io::Error| format!("{}: {}", path, e);
+    let (file, location) = archive::open(&prepared.spec).unwrap_or_else(|e| cli::die(&failed(e)));
+    let mut input = file.take(location.range.map_or(u64::MAX, |r| r.end - r.start));
+    // Enough to tell a dump from text.
+    let mut bytes = Vec::new();
+    (&mut input)
+        .take(12)
+        .read_to_end(&mut bytes)
+        .unwrap_or_else(|e| cli::die(&failed(e)));
+    let before = if bytes.starts_with(b"JAVA PROFILE") || phd::is_phd(&prepared.spec) {
+        let mut other =
+            Snapshot::load_window(&prepared.spec, &args.window()).unwrap_or_else(|e| cli::die(&e));
+        other.set_size_model(snapshot.size_model);
+        diff::histogram_keys(&histogram::histogram(&other, |_| true))
+    } else {
+        if snapshot.size_model == SizeModel::RawHprof {
+            eprintln!(
+                "warning: {} has the sizes of the running JVM, give --size-model auto (or that of the JVM) to compare them with those of the dump",
+                path
+            );
+        }
+        input
+            .read_to_end(&mut bytes)
+            .unwrap_or_else(|e| cli::die(&failed(e)));
+        diff::parse_class_histogram(&String::from_utf8_lossy(&bytes))
+            .unwrap_or_else(|e| cli::die(&format!("{}: {}", path, e)))
+    };
+    let after = if args.flag("--live") {
+        let live = reachability::mark(&Graph::build(snapshot));
+        histogram::histogram(snapshot, |i| live[i as usize])
+    } else {
+        histogram::histogram(snapshot, |_| true)
+    };
+    let deltas = diff::diff_keys(&before, &diff::histogram_keys(&after));
+
+    println!(
+        "{:>14} {:>14} {:>14} {:>21}  Class",
+        "Before", "After", "Growth", "Instances"
+    );
+    for d in deltas
+        .iter()
+        .filter(|d| d.growth() != 0 || d.before.objects != d.after.objects)
+        .take(top)
+    {
+        println!(
+            "{:>14} {:>14} {:>14} {:>21}  {}",
+            units.bytes(d.before.retained),
+            units.bytes(d.after.retained),
+            units.signed_bytes(d.growth()),
+            format!("{}->{}", d.before.objects, d.after.objects),
+            names.class(&d.key)
+        );
+    }
+}
+
+//
+// The live classes of the dump next to what a flight recording made around
+// it says of their allocations: the samples, the bytes they stand for and
+// the rate over the recording, and the old object samples (objects alive for
+// long) of each class. --sort allocated lists the classes allocated most
+// first, whether much of them is left or not.
+//
+pub fn print_jfr_allocations(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let path = match args.positional.first() {
+        Some(path) => path,
+        None => cli::die("jfr-allocations: no recording given"),
+    };
+    let top = args.number("--top", 25) as usize;
+    let recording = Mapping::open(path);
+    let allocations =
+        jfr::allocations(&recording).unwrap_or_else(|e| cli::die(&format!("{}: {}", path, e)));
+    let live = reachability::mark(&Graph::build(snapshot));
+    let histogram = histogram::histogram(snapshot, |i| live[i as usize]);
+
+    let mut rows: Vec<(&str, u64, u64, ClassAllocations)> = histogram
+        .iter()
+        .map(|e| {
+            let allocated = allocations.classes.get(&e.class_name).copied();
+            (
+                e.class_name.as_str(),
+                e.instances,
+                e.shallow,
+                allocated.unwrap_or_default(),
+            )
+        })
+        .collect();
+    for (name, allocated) in &allocations.classes {
+        if !histogram.iter().any(|e| e.class_name == *name) {
+            rows.push((name, 0, 0, *allocated));
+        }
+    }
+    match args.value("--sort").unwrap_or("live") {
+        "live" => {}
+        "allocated" => rows.sort_by(|a, b| b.3.bytes.cmp(&a.3.bytes).then(a.0.cmp(b.0))),
+        other => cli::die(&format!("unknown sort: {}", other)),
+    }
+
+    let seconds = allocations.duration_nanos as f64 / 1e9;
+    let samples: u64 = allocations.classes.values().map(|c| c.samples).sum();
+    let bytes: u64 = allocations.classes.values().map(|c| c.bytes).sum();
+    let still_there = allocations
+        .old_objects
+        .iter()
+        .filter(|&&address| snapshot.index_of(address).is_some_and(|i| live[i as usize]))
+        .count();
+    println!(
+        "{:.1}s recorded: {} allocation samples for {}, {} old object samples ({} live at the same address in the dump)",
+        seconds,
+        samples,
+        units.amount(bytes),
+        allocations.old_objects.len(),
+        still_there
+    );
+    println!(
+        "{:>10} {:>14} {:>8} {:>14} {:>12} {:>5}  Class",
+        "Live", "Shallow", "Samples", "Allocated", "Per second", "Old"
+    );
+    for (name, instances, shallow, allocated) in rows.iter().take(top) {
+        let rate = if seconds > 0.0 {
+            (allocated.bytes as f64 / seconds) as u64
+        } else {
+            0
+        };
+        println!(
+            "{:>10} {:>14} {:>8} {:>14} {:>12} {:>5}  {}",
+            instances,
+            units.bytes(*shallow),
+            allocated.samples,
+            units.bytes(allocated.bytes),
+            units.bytes(rate),
+            allocated.old_objects,
+            name
+        );
+    }
+}
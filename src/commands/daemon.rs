@@ -0,0 +1,560 @@
+//
+// The daemon: snapshots loaded once and kept, answering JSON-RPC 2.0
+// requests (one per line, batches included) on stdin or on the connections
+// to a unix socket, for editors and UIs that ask many questions of the same
+// dumps. The methods are those of serve's API, on the snapshot named by the
+// `snapshot` parameter, which can be left out while only one is loaded:
+//
+//   load {dump, name?, since?, until?}  unload {snapshot}  snapshots  shutdown
+//   summary  histogram {top?, live?}  object {id, depth?, width?}
+//   paths {id, exclude?}  query {class, where?, limit?}
+//   graphql {query, operationName?, variables?}
+//
+use super::api::{Error, Heap};
+use super::{graphql, grpc};
+use crate::cli::{self, Args};
+use hprof_cat::archive::{self, Prepared};
+use hprof_cat::json::{self, Json};
+use hprof_cat::snapshot::Snapshot;
+use hprof_cat::window::{self, Window};
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, RwLock};
+use std::thread;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+// Queries that fail for another reason, with the HTTP status of serve as data.
+const QUERY_FAILED: i64 = -32000;
+
+// Longer lines are refused (and skipped up to their end).
+const MAX_LINE: u64 = 16 << 20;
+
+struct Loaded {
+    name: String,
+    heap: Arc<Heap>,
+    // Keeps the dump inflated out of its archive, if it was.
+    _prepared: Prepared,
+}
+
+// The snapshots loaded, by name, shared by the JSON-RPC and gRPC services.
+#[derive(Default)]
+pub struct Snapshots {
+    loaded: RwLock<Vec<Loaded>>,
+}
+
+impl Snapshots {
+    //
+    // Loads the records of a dump (or its archive) within `window` as
+    // `name`, by default its path.
+    //
+    pub fn load(
+        &self,
+        dump: &str,
+        name: Option<&str>,
+        window: &Window,
+    ) -> Result<(String, Arc<Heap>), Error> {
+        let name = name.unwrap_or(dump).to_string();
+        let taken = |loaded: &[Loaded]| {
+            if loaded.iter().any(|s| s.name == name) {
+                return Err((400, format!("{} is already loaded", name)));
+            }
+            Ok(())
+        };
+        taken(&self.loaded.read().unwrap())?;
+        let prepared = archive::prepare(dump, &std::env::temp_dir()).map_err(|e| (400, e))?;
+        archive::open(&prepared.spec).map_err(|e| (400, format!("{}: {}", dump, e)))?;
+        // A dump that fails to parse must not take the other ones down.
+        let snapshot = panic::catch_unwind(AssertUnwindSafe(|| {
+            Snapshot::load_window(&prepared.spec, window)
+        }))
+        .map_err(|payload| {
+            let message = match payload.downcast_ref::<String>() {
+                Some(message) => message.clone(),
+                None => format!("{}: cannot load the dump", dump),
+            };
+            (500, message)
+        })?
+        .map_err(|e| (500, e))?;
+        let heap = Arc::new(Heap::new(snapshot));
+        let mut loaded = self.loaded.write().unwrap();
+        taken(&loaded)?;
+        loaded.push(Loaded {
+            name: name.clone(),
+            heap: heap.clone(),
+            _prepared: prepared,
+        });
+        Ok((name, heap))
+    }
+
+    pub fn unload(&self, name: &str) -> Result<(), Error> {
+        let mut loaded = self.loaded.write().unwrap();
+        let before = loaded.len();
+        loaded.retain(|s| s.name != name);
+        if loaded.len() == before {
+            return Err((404, format!("no snapshot named {}", name)));
+        }
+        Ok(())
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let loaded = self.loaded.read().unwrap();
+        loaded.iter().map(|s| s.name.clone()).collect()
+    }
+
+    // The snapshot named, or the only one loaded.
+    pub fn get(&self, name: Option<&str>) -> Result<Arc<Heap>, Error> {
+        let loaded = self.loaded.read().unwrap();
+        match name {
+            Some(name) => loaded
+                .iter()
+                .find(|s| s.name == name)
+                .map(|s| s.heap.clone())
+                .ok_or_else(|| (404, format!("no snapshot named {}", name))),
+            None if loaded.len() == 1 => Ok(loaded[0].heap.clone()),
+            None if loaded.is_empty() => Err((400, "no snapshot is loaded".to_string())),
+            None => Err((
+                400,
+                "several snapshots are loaded, give snapshot".to_string(),
+            )),
+        }
+    }
+}
+
+struct Daemon {
+    snapshots: Snapshots,
+}
+
+// A failed request: the JSON-RPC error code, its message and data.
+type Failure = (i64, String, Option<Json>);
+
+fn invalid(message: String) -> Failure {
+    (INVALID_PARAMS, message, None)
+}
+
+fn query_failed((status, message): Error) -> Failure {
+    if status == 400 {
+        return invalid(message);
+    }
+    let data = Json::Object(vec![("status".to_string(), Json::number(status))]);
+    (QUERY_FAILED, message, Some(data))
+}
+
+fn string<'a>(params: &'a Json, name: &str) -> Result<Option<&'a str>, Failure> {
+    match params.get(name) {
+        None | Some(Json::Null) => Ok(None),
+        Some(Json::Str(s)) => Ok(Some(s)),
+        Some(_) => Err(invalid(format!("{} must be a string", name))),
+    }
+}
+
+fn required<'a>(params: &'a Json, name: &str) -> Result<&'a str, Failure> {
+    string(params, name)?.ok_or_else(|| invalid(format!("missing {}", name)))
+}
+
+fn number(params: &Json, name: &str, default: usize) -> Result<usize, Failure> {
+    match params.get(name) {
+        None | Some(Json::Null) => Ok(default),
+        Some(Json::Number(n)) => n
+            .parse()
+            .map_err(|_| invalid(format!("{} must be a non-negative integer", name))),
+        Some(_) => Err(invalid(format!("{} must be a number", name))),
+    }
+}
+
+// Object ids are accepted as strings ("0x..." or decimal) and numbers.
+fn object_id(params: &Json) -> Result<&str, Failure> {
+    match params.get("id") {
+        Some(Json::Str(id)) | Some(Json::Number(id)) => Ok(id),
+        _ => Err(invalid("missing id".to_string())),
+    }
+}
+
+impl Daemon {
+    fn call(&self, method: &str, params: &Json) -> Result<Json, Failure> {
+        match method {
+            "load" => {
+                let dump = required(params, "dump")?;
+                let bound = |name: &str| -> Result<_, Failure> {
+                    string(params, name)?
+                        .map(|s| window::parse_bound(s).map_err(invalid))
+                        .transpose()
+                };
+                let window = Window {
+                    since: bound("since")?,
+                    until: bound("until")?,
+                };
+                let (name, heap) = self
+                    .snapshots
+                    .load(dump, string(params, "name")?, &window)
+                    .map_err(query_failed)?;
+                return Ok(Json::Object(vec![
+                    ("snapshot".to_string(), Json::Str(name)),
+                    ("summary".to_string(), heap.summary()),
+                ]));
+            }
+            "unload" => {
+                let name = required(params, "snapshot")?;
+                self.snapshots.unload(name).map_err(query_failed)?;
+                return Ok(Json::Bool(true));
+            }
+            "snapshots" => {
+                let names = self.snapshots.names();
+                return Ok(Json::Array(names.into_iter().map(Json::Str).collect()));
+            }
+            _ => {}
+        }
+        let heap = match method {
+            "summary" | "histogram" | "object" | "paths" | "query" | "graphql" => self
+                .snapshots
+                .get(string(params, "snapshot")?)
+                .map_err(query_failed)?,
+            _ => return Err((METHOD_NOT_FOUND, format!("no method {}", method), None)),
+        };
+        match method {
+            "summary" => Ok(heap.summary()),
+            "histogram" => Ok(heap.histogram(
+                number(params, "top", 100)?,
+                matches!(params.get("live"), Some(Json::Bool(true))),
+            )),
+            "object" => heap
+                .object(
+                    object_id(params)?,
+                    number(params, "depth", 3)?,
+                    number(params, "width", 10)?,
+                )
+                .map_err(query_failed),
+            "paths" => heap
+                .path(object_id(params)?, string(params, "exclude")?)
+                .map_err(query_failed),
+            "query" => heap
+                .query(
+                    required(params, "class")?,
+                    string(params, "where")?,
+                    number(params, "limit", 100)?,
+                )
+                .map_err(query_failed),
+            _ => Ok(graphql::execute(
+                &heap,
+                required(params, "query")?,
+                string(params, "operationName")?,
+                params.get("variables"),
+            )),
+        }
+    }
+
+    // The response to one request, None for notifications.
+    fn respond(&self, request: &Json, shutdown: &mut bool) -> Option<Json> {
+        let id = request.get("id").cloned();
+        let method = request.get("method").and_then(Json::as_str);
+        let result = match (request, method) {
+            (Json::Object(_), Some(method)) => {
+                let params = request
+                    .get("params")
+                    .cloned()
+                    .unwrap_or(Json::Object(Vec::new()));
+                if !matches!(params, Json::Object(_)) {
+                    Err(invalid("params must be an object".to_string()))
+                } else if method == "shutdown" {
+                    *shutdown = true;
+                    Ok(Json::Bool(true))
+                } else {
+                    self.call(method, &params)
+                }
+            }
+            _ => Err((INVALID_REQUEST, "not a JSON-RPC request".to_string(), None)),
+        };
+        let id = match id {
+            Some(id) => id,
+            None if method.is_some() => return None,
+            None => Json::Null,
+        };
+        Some(response(id, result))
+    }
+
+    // Answers a line of input: a request or a batch of them.
+    fn line(&self, line: &str, shutdown: &mut bool) -> Option<Json> {
+        match json::parse(line) {
+            Err(e) => Some(response(
+                Json::Null,
+                Err((PARSE_ERROR, format!("parse error: {}", e), None)),
+            )),
+            Ok(Json::Array(requests)) if !requests.is_empty() => {
+                let responses: Vec<Json> = requests
+                    .iter()
+                    .filter_map(|r| self.respond(r, shutdown))
+                    .collect();
+                (!responses.is_empty()).then_some(Json::Array(responses))
+            }
+            Ok(Json::Array(_)) => Some(response(
+                Json::Null,
+                Err((INVALID_REQUEST, "empty batch".to_string(), None)),
+            )),
+            Ok(request) => self.respond(&request, shutdown),
+        }
+    }
+
+    // Serves one client until it hangs up or asks for a shutdown.
+    fn serve<R: BufRead, W: Write>(&self, mut reader: R, mut writer: W) -> bool {
+        let mut shutdown = false;
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let whole = match read_line(&mut reader, &mut line) {
+                Ok(_) if line.is_empty() => break,
+                Ok(whole) => whole,
+                Err(_) => break,
+            };
+            let answer = match std::str::from_utf8(&line) {
+                _ if !whole => Some(response(
+                    Json::Null,
+                    Err((INVALID_REQUEST, "request too large".to_string(), None)),
+                )),
+                Ok(line) if line.trim().is_empty() => continue,
+                Ok(line) => self.line(line, &mut shutdown),
+                Err(_) => Some(response(
+                    Json::Null,
+                    Err((PARSE_ERROR, "parse error: not UTF-8".to_string(), None)),
+                )),
+            };
+            if let Some(response) = answer {
+                let written =
+                    writeln!(writer, "{}", response.compact()).and_then(|_| writer.flush());
+                if written.is_err() {
+                    break;
+                }
+            }
+            if shutdown {
+                break;
+            }
+        }
+        shutdown
+    }
+}
+
+//
+// Reads the next line into `line`, up to MAX_LINE bytes of it. Returns
+// whether it was whole; the rest of a longer one is skipped.
+//
+fn read_line<R: BufRead>(reader: &mut R, line: &mut Vec<u8>) -> io::Result<bool> {
+    reader.take(MAX_LINE).read_until(b'\n', line)?;
+    if line.len() as u64 != MAX_LINE || line.ends_with(b"\n") {
+        return Ok(true);
+    }
+    let mut rest = Vec::new();
+    loop {
+        rest.clear();
+        reader.take(MAX_LINE).read_until(b'\n', &mut rest)?;
+        if rest.is_empty() || rest.ends_with(b"\n") {
+            return Ok(false);
+        }
+    }
+}
+
+fn response(id: Json, result: Result<Json, Failure>) -> Json {
+    let mut members = vec![
+        ("jsonrpc".to_string(), Json::Str("2.0".to_string())),
+        ("id".to_string(), id),
+    ];
+    match result {
+        Ok(result) => members.push(("result".to_string(), result)),
+        Err((code, message, data)) => {
+            let mut error = vec![
+                ("code".to_string(), Json::number(code)),
+                ("message".to_string(), Json::Str(message)),
+            ];
+            if let Some(data) = data {
+                error.push(("data".to_string(), data));
+            }
+            members.push(("error".to_string(), Json::Object(error)));
+        }
+    }
+    Json::Object(members)
+}
+
+#[cfg(unix)]
+fn serve_socket(daemon: &Daemon, path: &str) {
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    // A socket left behind by a daemon that is gone is replaced.
+    if std::path::Path::new(path).exists() {
+        if UnixStream::connect(path).is_ok() {
+            cli::die(&format!("{}: a daemon is already listening", path));
+        }
+        let _ = std::fs::remove_file(path);
+    }
+    let listener =
+        UnixListener::bind(path).unwrap_or_else(|e| cli::die(&format!("{}: {}", path, e)));
+    eprintln!("listening on {}", path);
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("accept: {}", e);
+                    continue;
+                }
+            };
+            scope.spawn(move || {
+                let reader = match stream.try_clone() {
+                    Ok(s) => BufReader::new(s),
+                    Err(_) => return,
+                };
+                if daemon.serve(reader, &stream) {
+                    let _ = std::fs::remove_file(path);
+                    std::process::exit(0);
+                }
+            });
+        }
+    });
+}
+
+#[cfg(not(unix))]
+fn serve_socket(_: &Daemon, _: &str) {
+    cli::die("--socket needs unix sockets");
+}
+
+//
+// Loads the dumps given and answers requests on stdin, or on the unix
+// socket --socket, until shut down (or stdin is closed). With --grpc, the
+// gRPC service of proto/heap_analysis.proto is served instead.
+//
+pub fn run(args: &Args) {
+    let daemon = Daemon {
+        snapshots: Snapshots::default(),
+    };
+    for dump in &args.positional {
+        if let Err((_, message)) = daemon.snapshots.load(dump, None, &args.window()) {
+            cli::die(&message);
+        }
+    }
+    if args.value("--socket").is_some() && args.value("--grpc").is_some() {
+        cli::die("give either --socket or --grpc");
+    }
+    if let Some(address) = args.value("--grpc") {
+        return grpc::serve(&daemon.snapshots, address);
+    }
+    match args.value("--socket") {
+        Some(path) => serve_socket(&daemon, path),
+        None => {
+            let stdin = io::stdin();
+            daemon.serve(stdin.lock(), io::stdout().lock());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hprof_cat::synthetic::{self, Options};
+
+    // The responses of a daemon to the lines given, and whether it shut down.
+    fn session(daemon: &Daemon, input: &str) -> (Vec<Json>, bool) {
+        let mut output = Vec::new();
+        let shutdown = daemon.serve(input.as_bytes(), &mut output);
+        let output = String::from_utf8(output).unwrap();
+        let responses = output.lines().map(|l| json::parse(l).unwrap()).collect();
+        (responses, shutdown)
+    }
+
+    fn daemon() -> Daemon {
+        Daemon {
+            snapshots: Snapshots::default(),
+        }
+    }
+
+    fn code(response: &Json) -> Option<&str> {
+        match response.get("error")?.get("code")? {
+            Json::Number(code) => Some(code),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn requests_that_fail_get_their_error() {
+        let daemon = daemon();
+        let input = concat!(
+            "{\"jsonrpc\": \"2.0\", \"id\": 1, \"method\": \"summary\"}\n",
+            "not json\n",
+            "[]\n",
+            "\n",
+            "{\"jsonrpc\": \"2.0\", \"id\": 2, \"method\": \"nope\"}\n",
+            "{\"jsonrpc\": \"2.0\", \"id\": 3, \"method\": \"load\", \"params\": []}\n",
+            "{\"jsonrpc\": \"2.0\", \"method\": \"snapshots\"}\n",
+            "[{\"jsonrpc\": \"2.0\", \"id\": 4, \"method\": \"snapshots\"}, 5]\n",
+        );
+        let (responses, shutdown) = session(&daemon, input);
+        assert!(!shutdown);
+        let codes: Vec<Option<&str>> = responses.iter().map(code).collect();
+        assert_eq!(
+            codes,
+            vec![
+                Some("-32602"),
+                Some("-32700"),
+                Some("-32600"),
+                Some("-32601"),
+                Some("-32602"),
+                None
+            ]
+        );
+        match &responses[5] {
+            Json::Array(batch) => {
+                assert_eq!(batch.len(), 2);
+                assert_eq!(batch[0].get("result"), Some(&Json::Array(Vec::new())));
+                assert_eq!(code(&batch[1]), Some("-32600"));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn long_lines_are_skipped() {
+        let input = format!(
+            "{}\n{}\n",
+            "x".repeat(MAX_LINE as usize + 10),
+            "{\"jsonrpc\": \"2.0\", \"id\": 1, \"method\": \"shutdown\"}"
+        );
+        let (responses, shutdown) = session(&daemon(), &input);
+        assert_eq!(responses.len(), 2);
+        assert_eq!(code(&responses[0]), Some("-32600"));
+        assert_eq!(responses[1].get("result"), Some(&Json::Bool(true)));
+        assert!(shutdown);
+    }
+
+    #[test]
+    fn snapshots_are_loaded_by_name() {
+        let options = Options {
+            objects: 1_000,
+            ..Options::default()
+        };
+        let path =
+            std::env::temp_dir().join(format!("hprof-cat-daemon-{}.hprof", std::process::id()));
+        std::fs::write(&path, synthetic::generate(&options, Vec::new()).unwrap()).unwrap();
+        let dump = json::escape(path.to_str().unwrap());
+        let daemon = daemon();
+        let input = format!(
+            concat!(
+                "{{\"jsonrpc\": \"2.0\", \"id\": 1, \"method\": \"load\", \"params\": {{\"dump\": {}, \"name\": \"a\"}}}}\n",
+                "{{\"jsonrpc\": \"2.0\", \"id\": 2, \"method\": \"load\", \"params\": {{\"dump\": {}, \"name\": \"a\"}}}}\n",
+                "{{\"jsonrpc\": \"2.0\", \"id\": 3, \"method\": \"histogram\", \"params\": {{\"top\": 1}}}}\n",
+                "{{\"jsonrpc\": \"2.0\", \"id\": 4, \"method\": \"unload\", \"params\": {{\"snapshot\": \"a\"}}}}\n",
+                "{{\"jsonrpc\": \"2.0\", \"id\": 5, \"method\": \"summary\", \"params\": {{\"snapshot\": \"a\"}}}}\n",
+            ),
+            dump, dump
+        );
+        let (responses, _) = session(&daemon, &input);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(responses.len(), 5);
+        assert_eq!(
+            responses[0].get("result").and_then(|r| r.get("snapshot")),
+            Some(&Json::Str("a".to_string()))
+        );
+        assert_eq!(code(&responses[1]), Some("-32602"));
+        assert!(responses[2].get("result").is_some());
+        assert_eq!(responses[3].get("result"), Some(&Json::Bool(true)));
+        assert_eq!(code(&responses[4]), Some("-32000"));
+    }
+}
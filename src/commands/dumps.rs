@@ -0,0 +1,213 @@
+//
+// Commands on the dump files themselves: writing synthetic ones, rewritten
+// or repaired copies of others and the pieces of split dumps, checking
+// their health and fingerprinting them.
+//
+use crate::cli::{self, Args};
+use hprof_cat::cache;
+use hprof_cat::doctor;
+use hprof_cat::extract;
+use hprof_cat::heap::HeapObject;
+use hprof_cat::idhash::IdSet;
+use hprof_cat::lazy::Mapping;
+use hprof_cat::records::RecordTag;
+use hprof_cat::regex::Regex;
+use hprof_cat::repair;
+use hprof_cat::rewrite;
+use hprof_cat::snapshot::Snapshot;
+use hprof_cat::split;
+use hprof_cat::synthetic::{self, Options, Shape};
+use hprof_cat::writer::{self, Renumbering};
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::BufWriter;
+
+// The file of -o (or --out), created.
+fn output(args: &Args, command: &str) -> (String, BufWriter<File>) {
+    let path = args
+        .value("-o")
+        .or_else(|| args.value("--out"))
+        .unwrap_or_else(|| cli::die(&format!("{}: no output file given (-o FILE)", command)));
+    let file = File::create(path).unwrap_or_else(|e| cli::die(&format!("{}: {}", path, e)));
+    (path.to_string(), BufWriter::new(file))
+}
+
+// A synthetic dump of the shape given by the options.
+pub fn generate(args: &Args) {
+    let defaults = Options::default();
+    let shape = args.value("--shape").unwrap_or("mixed");
+    let options = Options {
+        classes: args.count("--classes", defaults.classes),
+        objects: args.count("--objects", defaults.objects),
+        seed: args.number("--seed", defaults.seed),
+        shape: Shape::parse(shape)
+            .unwrap_or_else(|| cli::die(&format!("unknown shape: {}", shape))),
+        id_size: args.number("--id-size", defaults.id_size as u64) as u32,
+        depth: args.count("--depth", defaults.depth),
+    };
+    if options.id_size != 4 && options.id_size != 8 {
+        cli::die("--id-size must be 4 or 8");
+    }
+    if options.classes > 1_000_000 {
+        cli::die("at most 1M classes");
+    }
+    if options.id_size == 4 && options.objects > 400_000_000 {
+        cli::die("at most 400M objects with 4-byte identifiers");
+    }
+    let (path, out) = output(args, "gen");
+    synthetic::generate(&options, out).unwrap_or_else(|e| cli::die(&format!("{}: {}", path, e)));
+}
+
+//
+// A copy of the dump with --id-size 4 or 8 byte identifiers (its own by
+// default), those that don't fit in 4 bytes being renumbered, and with
+// --compact-symbols its symbols deduplicated and the unreferenced ones
+// left out.
+//
+pub fn rewrite(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let id_size = args.number("--id-size", snapshot.id_size() as u64) as u32;
+    let mut renumbering = match id_size {
+        4 => rewrite::narrow_ids(snapshot).unwrap_or_else(|e| cli::die(&e)),
+        8 => Renumbering::default(),
+        _ => cli::die("--id-size must be 4 or 8"),
+    };
+    if args.flag("--compact-symbols") {
+        let compaction = rewrite::compact_symbols(snapshot, &mut renumbering);
+        println!(
+            "{} of {} symbols merged into others of the same text, {} unreferenced left out: {} less",
+            compaction.duplicates,
+            snapshot.strings.len(),
+            compaction.unreferenced,
+            units.amount(compaction.bytes)
+        );
+    }
+    let (path, out) = output(args, "rewrite");
+    writer::write_snapshot_with(snapshot, out, id_size, renumbering)
+        .unwrap_or_else(|e| cli::die(&format!("{}: {}", path, e)));
+}
+
+// One dump per heap dump segment, as <-o prefix>-<n>.hprof.
+pub fn split(dump: &str, args: &Args) {
+    let units = args.units();
+    let prefix = args
+        .value("-o")
+        .or_else(|| args.value("--out"))
+        .unwrap_or_else(|| cli::die("split: no output prefix given (-o PREFIX)"));
+    let pieces = split::split(dump, prefix).unwrap_or_else(|e| cli::die(&e.to_string()));
+    for (piece, bytes) in &pieces {
+        println!("{:>14}  {}", units.bytes(*bytes), piece);
+    }
+    println!("{} pieces", pieces.len());
+}
+
+// The dump that pieces of split came from.
+pub fn join(args: &Args) {
+    if args.positional.is_empty() {
+        cli::die("join: no pieces given");
+    }
+    let (_, out) = output(args, "join");
+    split::join(&args.positional, out).unwrap_or_else(|e| cli::die(&e.to_string()));
+}
+
+// The dump with its partial last record trimmed and its lengths fixed.
+pub fn repair(dump: &str, args: &Args) {
+    let data = Mapping::open(dump);
+    let (path, out) = output(args, "repair");
+    let (_, notes) =
+        repair::repair(&data, out).unwrap_or_else(|e| cli::die(&format!("{}: {}", dump, e)));
+    if notes.is_empty() {
+        println!("nothing to repair, {} is a copy", path);
+    }
+    for note in notes {
+        println!("{}", note);
+    }
+}
+
+//
+// The instances and arrays of the classes whose names match --class (a
+// regex), with stubs of what they reference, as a dump of their own. Those
+// that their kept GC roots don't reach are given an unknown root.
+//
+pub fn extract(snapshot: &Snapshot, args: &Args) {
+    let pattern = args
+        .value("--class")
+        .unwrap_or_else(|| cli::die("extract requires --class <regex>"));
+    let regex = Regex::new(pattern).unwrap_or_else(|e| cli::die(&e));
+    let classes: IdSet<u64> = snapshot
+        .objects
+        .iter()
+        .filter(|o| matches!(o, HeapObject::Class(_)))
+        .map(HeapObject::object_id)
+        .filter(|&id| regex.is_match(&snapshot.class_name(id)))
+        .collect();
+    let selected = |object: &HeapObject| match object {
+        HeapObject::Instance(i) => classes.contains(&i.class_id),
+        HeapObject::ObjectArray(a) => classes.contains(&a.class_id),
+        HeapObject::PrimitiveArray(_) => regex.is_match(&snapshot.object_class_name(object)),
+        HeapObject::Class(_) => false,
+    };
+    if !snapshot.objects.iter().any(selected) {
+        cli::die(&format!("no objects of a class matching {}", pattern));
+    }
+    let (path, out) = output(args, "extract");
+    let (_, extract) = extract::extract(snapshot, selected, out)
+        .unwrap_or_else(|e| cli::die(&format!("{}: {}", path, e)));
+    println!(
+        "{} objects ({} given an unknown root), {} stubs of what they reference, {} class dumps",
+        extract.objects, extract.unrooted, extract.stubs, extract.classes
+    );
+}
+
+//
+// The health report of a dump: its records, then every problem found with
+// how many times and the offsets of the first ones.
+//
+pub fn print_doctor(dump: &str, args: &Args) {
+    let units = args.units();
+    let data = Mapping::open(dump);
+    let report = doctor::examine(&data);
+    println!(
+        "{}: {}, {}-byte identifiers, {}",
+        dump,
+        report.format,
+        report.id_size,
+        units.amount(report.bytes)
+    );
+    println!("{:>10} {:>14}  Record", "Count", "Bytes");
+    for &(tag, count, bytes) in &report.records {
+        let name = RecordTag::try_from(tag).map_or(format!("{:#04x}", tag), |t| format!("{:?}", t));
+        println!("{:>10} {:>14}  {}", count, units.bytes(bytes), name);
+    }
+    println!("{} objects, {} GC roots", report.objects, report.roots);
+    if let Some(offset) = report.stopped_at {
+        println!("read up to offset {} of {}", offset, report.bytes);
+    }
+    println!();
+    if report.findings.is_empty() {
+        println!("no problems found: the dump is sound, a command failing on it is a bug");
+        return;
+    }
+    println!("{:>10}  Problem (at offsets)", "Count");
+    for finding in &report.findings {
+        let offsets: Vec<String> = finding.offsets.iter().map(u64::to_string).collect();
+        let more = if finding.count > finding.offsets.len() as u64 {
+            ", ..."
+        } else {
+            ""
+        };
+        println!(
+            "{:>10}  {} ({}{})",
+            finding.count,
+            finding.problem,
+            offsets.join(", "),
+            more
+        );
+    }
+}
+
+// The fingerprint of the dump, by which copies of it are the same dump.
+pub fn print_fingerprint(snapshot: &Snapshot, dump: &str) {
+    println!("{:016x}  {}", cache::fingerprint(snapshot), dump);
+}
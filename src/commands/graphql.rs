@@ -0,0 +1,697 @@
+//
+// The GraphQL endpoint of serve: the classes, objects, references, threads
+// and frames of the snapshot as the types of SCHEMA, so that a client can
+// fetch an object with its referrers and their classes in one request.
+// Objects are resolved lazily like the rest of the API: asking for
+// retainedSize or referrers builds what they need on the first request.
+//
+use super::api::{Error, Heap};
+use crate::cli;
+use hprof_cat::decoders::Decoders;
+use hprof_cat::graph::{self, Via};
+use hprof_cat::graphql::{self, Document, Field, Value};
+use hprof_cat::heap::{self, FieldTag, HeapObject};
+use hprof_cat::http::Request;
+use hprof_cat::json::{self, Json};
+use hprof_cat::paths;
+use hprof_cat::predicate::Predicate;
+use hprof_cat::records::StackFrameRecord;
+use hprof_cat::reference::{self, RetentionFilter};
+use hprof_cat::strings::as_string;
+use hprof_cat::threads::{self, ThreadInfo};
+
+use std::collections::HashMap;
+
+pub const SCHEMA: &str = r#"# 64-bit integers: sizes, counts and timestamps
+scalar Long
+
+type Query {
+  summary: Summary!
+  class(name: String!): Class
+  # Classes by the shallow size of their instances, largest first
+  classes(top: Int = 100): [Class!]!
+  object(id: ID!): Object
+  # Instances of a class and its subclasses, `where` as in find
+  objects(class: String!, where: String, limit: Int = 100): [Object!]!
+  threads: [Thread!]!
+  thread(name: String, serial: Int): Thread
+}
+
+type Summary {
+  objects: Long!
+  bytes: Long!
+  classes: Int!
+  gcRoots: Int!
+  threads: Int!
+  strings: Int!
+  idSize: Int!
+  timestamp: Long!
+}
+
+type Class {
+  id: ID!
+  name: String!
+  superclass: Class
+  subclasses: [Class!]!
+  loader: Object
+  # The java.lang.Class object
+  object: Object
+  instanceSize: Int!
+  instanceCount: Long!
+  shallowSize: Long!
+  instances(limit: Int = 100): [Object!]!
+  staticFields: [Field!]!
+}
+
+type Object {
+  id: ID!
+  class: Class
+  className: String!
+  label: String!
+  shallowSize: Long!
+  retainedSize: Long!
+  reachable: Boolean!
+  # The immediate dominator, null for objects only dominated by the roots
+  dominator: Object
+  gcRoots: [String!]!
+  # The value of strings (and char[] and byte[] holding text)
+  string: String
+  length: Int
+  fields: [Field!]!
+  elements(limit: Int = 100): [Field!]!
+  references(limit: Int = 100): [Reference!]!
+  referrers(limit: Int = 100): [Reference!]!
+  # From the object to a GC root, null if there is none
+  pathToRoot(exclude: [String!]): [Reference!]
+  # The object rendered as by the json command
+  json(depth: Int = 1, width: Int = 10): String!
+}
+
+type Field {
+  name: String!
+  type: String!
+  value: String!
+  object: Object
+}
+
+type Reference {
+  # How `from` refers to `to`, e.g. java.util.HashMap.table or [3]
+  name: String!
+  from: Object!
+  to: Object!
+}
+
+type Thread {
+  serial: Int!
+  name: String!
+  state: String
+  daemon: Boolean
+  object: Object!
+  # Innermost first
+  frames: [Frame!]!
+}
+
+type Frame {
+  method: String
+  signature: String
+  source: String
+  line: Int
+  class: Class
+  text: String!
+  locals: [Object!]!
+}
+"#;
+
+enum Node<'a> {
+    Query,
+    Summary,
+    Class(u64),
+    Object(u32),
+    Field(String, FieldTag, heap::Value),
+    Reference(u32, u32, Via),
+    Thread(&'a ThreadInfo),
+    Frame(Option<&'a StackFrameRecord>, Vec<u32>),
+}
+
+impl Node<'_> {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Node::Query => "Query",
+            Node::Summary => "Summary",
+            Node::Class(_) => "Class",
+            Node::Object(_) => "Object",
+            Node::Field(..) => "Field",
+            Node::Reference(..) => "Reference",
+            Node::Thread(_) => "Thread",
+            Node::Frame(..) => "Frame",
+        }
+    }
+}
+
+// What a field resolves to: a value, or objects to select fields of.
+enum Output<'a> {
+    Leaf(Json),
+    One(Node<'a>),
+    Many(Vec<Node<'a>>),
+}
+
+fn string(s: &str) -> Output<'static> {
+    Output::Leaf(Json::Str(s.to_string()))
+}
+
+fn number<T: ToString>(value: T) -> Output<'static> {
+    Output::Leaf(Json::number(value))
+}
+
+fn id(id: u64) -> Output<'static> {
+    string(&format!("{:#x}", id))
+}
+
+struct Executor<'a> {
+    heap: &'a Heap,
+    document: &'a Document,
+    variables: HashMap<String, Value>,
+    errors: Vec<Json>,
+}
+
+impl<'a> Executor<'a> {
+    fn argument(&self, field: &Field, name: &str) -> Value {
+        field
+            .arguments
+            .iter()
+            .find(|(n, _)| n == name)
+            .map_or(Value::Null, |(_, v)| {
+                graphql::substitute(v, &self.variables)
+            })
+    }
+
+    fn int(&self, field: &Field, name: &str, default: usize) -> Result<usize, String> {
+        match self.argument(field, name) {
+            Value::Null => Ok(default),
+            Value::Int(v) if v >= 0 => Ok(v as usize),
+            v => Err(format!("{} must be a non-negative Int, not {:?}", name, v)),
+        }
+    }
+
+    fn string(&self, field: &Field, name: &str) -> Result<Option<String>, String> {
+        match self.argument(field, name) {
+            Value::Null => Ok(None),
+            Value::Str(s) => Ok(Some(s)),
+            v => Err(format!("{} must be a String, not {:?}", name, v)),
+        }
+    }
+
+    fn required(&self, field: &Field, name: &str) -> Result<String, String> {
+        self.string(field, name)?
+            .ok_or_else(|| format!("{} requires {}", field.name, name))
+    }
+
+    // Objects found by id, null for ids that aren't in the dump.
+    fn object(&self, id: u64) -> Output<'a> {
+        match self.heap.snapshot.index_of(id) {
+            Some(node) => Output::One(Node::Object(node)),
+            None => Output::Leaf(Json::Null),
+        }
+    }
+
+    fn class(&self, class_id: u64) -> Output<'a> {
+        match self.heap.snapshot.class_dump(class_id) {
+            Some(_) => Output::One(Node::Class(class_id)),
+            None => Output::Leaf(Json::Null),
+        }
+    }
+
+    fn resolve(&self, node: &Node<'a>, field: &Field) -> Result<Output<'a>, String> {
+        let heap = self.heap;
+        let snapshot = &heap.snapshot;
+        if field.name == "__typename" {
+            return Ok(string(node.type_name()));
+        }
+        let unknown = || {
+            let hint = if field.name.starts_with("__") {
+                ", introspection is not supported: the schema is at /graphql/schema"
+            } else {
+                ""
+            };
+            Err(format!(
+                "no field {} on type {}{}",
+                field.name,
+                node.type_name(),
+                hint
+            ))
+        };
+        Ok(match node {
+            Node::Query => match field.name.as_str() {
+                "summary" => Output::One(Node::Summary),
+                "class" => match snapshot.find_class(&self.required(field, "name")?) {
+                    Some(class_id) => self.class(class_id),
+                    None => Output::Leaf(Json::Null),
+                },
+                "classes" => {
+                    let mut classes: Vec<(&u64, &(u64, u64))> =
+                        heap.class_totals().iter().collect();
+                    classes.sort_by(|a, b| b.1 .1.cmp(&a.1 .1).then(a.0.cmp(b.0)));
+                    Output::Many(
+                        classes
+                            .into_iter()
+                            .take(self.int(field, "top", 100)?)
+                            .map(|(&class_id, _)| Node::Class(class_id))
+                            .collect(),
+                    )
+                }
+                "object" => match self.argument(field, "id") {
+                    Value::Str(s) => self.object(cli::object_id(&s)?),
+                    Value::Int(v) => self.object(v as u64),
+                    _ => return Err("object requires an id".to_string()),
+                },
+                "objects" => {
+                    let predicate = match self.string(field, "where")? {
+                        Some(text) => Some(Predicate::parse(&text)?),
+                        None => None,
+                    };
+                    let class = self.required(field, "class")?;
+                    let limit = self.int(field, "limit", 100)?;
+                    let (_, nodes) = heap
+                        .matching(&class, predicate.as_ref(), limit)
+                        .map_err(|(_, message)| message)?;
+                    Output::Many(nodes.into_iter().map(Node::Object).collect())
+                }
+                "threads" => Output::Many(heap.threads().iter().map(Node::Thread).collect()),
+                "thread" => {
+                    let name = self.string(field, "name")?;
+                    let serial = match self.argument(field, "serial") {
+                        Value::Int(v) => Some(v),
+                        _ => None,
+                    };
+                    heap.threads()
+                        .iter()
+                        .find(|t| {
+                            name.as_ref().is_none_or(|n| *n == t.name)
+                                && serial.is_none_or(|s| s == t.serial as i64)
+                        })
+                        .map_or(Output::Leaf(Json::Null), |t| Output::One(Node::Thread(t)))
+                }
+                _ => return unknown(),
+            },
+            Node::Summary => match field.name.as_str() {
+                "objects" => number(snapshot.objects.len()),
+                "bytes" => number(
+                    snapshot
+                        .objects
+                        .iter()
+                        .map(|o| snapshot.shallow_size(o))
+                        .sum::<u64>(),
+                ),
+                "classes" => number(snapshot.classes.len()),
+                "gcRoots" => number(snapshot.roots.len()),
+                "threads" => number(heap.threads().len()),
+                "strings" => number(snapshot.strings.len()),
+                "idSize" => number(snapshot.id_size()),
+                "timestamp" => number(snapshot.header.timestamp_ms()),
+                _ => return unknown(),
+            },
+            Node::Class(class_id) => {
+                let class = snapshot.class_dump(*class_id).unwrap();
+                let totals = heap.class_totals().get(class_id).copied();
+                match field.name.as_str() {
+                    "id" => id(*class_id),
+                    "name" => string(&snapshot.class_name(*class_id)),
+                    "superclass" => self.class(class.super_class_id),
+                    "subclasses" => Output::Many(
+                        snapshot
+                            .objects
+                            .iter()
+                            .filter_map(|o| match o {
+                                HeapObject::Class(c) if c.super_class_id == *class_id => {
+                                    Some(Node::Class(c.class_id))
+                                }
+                                _ => None,
+                            })
+                            .collect(),
+                    ),
+                    "loader" => self.object(class.class_loader_id),
+                    "object" => self.object(*class_id),
+                    "instanceSize" => number(class.instance_size),
+                    "instanceCount" => number(totals.map_or(0, |t| t.0)),
+                    "shallowSize" => number(totals.map_or(0, |t| t.1)),
+                    "instances" => Output::Many(
+                        snapshot
+                            .objects
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, o)| snapshot.class_of(o) == Some(*class_id))
+                            .take(self.int(field, "limit", 100)?)
+                            .map(|(node, _)| Node::Object(node as u32))
+                            .collect(),
+                    ),
+                    "staticFields" => Output::Many(
+                        class
+                            .static_fields()
+                            .iter()
+                            .map(|f| {
+                                Node::Field(snapshot.string(f.name_id).to_string(), f.tag, f.value)
+                            })
+                            .collect(),
+                    ),
+                    _ => return unknown(),
+                }
+            }
+            Node::Object(node) => self.resolve_object(*node, field).or_else(|e| match e {
+                None => unknown(),
+                Some(message) => Err(message),
+            })?,
+            Node::Field(name, tag, value) => match field.name.as_str() {
+                "name" => string(name),
+                "type" => string(tag.java_name()),
+                "value" => string(&value.to_string()),
+                "object" => match value.as_object() {
+                    Some(target) if target != 0 => self.object(target),
+                    _ => Output::Leaf(Json::Null),
+                },
+                _ => return unknown(),
+            },
+            Node::Reference(from, to, via) => match field.name.as_str() {
+                "name" => string(&graph::qualified_via_name(snapshot, *via)),
+                "from" => Output::One(Node::Object(*from)),
+                "to" => Output::One(Node::Object(*to)),
+                _ => return unknown(),
+            },
+            Node::Thread(thread) => match field.name.as_str() {
+                "serial" => number(thread.serial),
+                "name" => string(&thread.name),
+                "state" => threads::thread_state(snapshot, thread.node)
+                    .map_or(Output::Leaf(Json::Null), |s| string(s.name())),
+                "daemon" => Output::Leaf(
+                    threads::is_daemon(snapshot, thread.node).map_or(Json::Null, Json::Bool),
+                ),
+                "object" => Output::One(Node::Object(thread.node)),
+                "frames" => Output::Many(
+                    threads::stack_with_locals(snapshot, thread)
+                        .into_iter()
+                        .map(|f| Node::Frame(f.frame, f.locals))
+                        .collect(),
+                ),
+                _ => return unknown(),
+            },
+            Node::Frame(frame, locals) => {
+                let leaf = |f: &dyn Fn(&StackFrameRecord) -> Output<'a>| {
+                    frame.map_or(Output::Leaf(Json::Null), f)
+                };
+                match field.name.as_str() {
+                    "method" => leaf(&|f| string(snapshot.string(f.method_name_id))),
+                    "signature" => leaf(&|f| string(snapshot.string(f.method_sign_id))),
+                    "source" => leaf(&|f| match f.source_name_id {
+                        0 => Output::Leaf(Json::Null),
+                        id => string(snapshot.string(id)),
+                    }),
+                    "line" => leaf(&|f| number(f.line_num)),
+                    "class" => leaf(&|f| match snapshot.classes.get(&f.class_serial_num) {
+                        Some(class) => self.class(class.object_id),
+                        None => Output::Leaf(Json::Null),
+                    }),
+                    "text" => match frame {
+                        Some(f) => string(&threads::describe_frame(snapshot, f)),
+                        None => string("<unknown frame>"),
+                    },
+                    "locals" => Output::Many(locals.iter().map(|&n| Node::Object(n)).collect()),
+                    _ => return unknown(),
+                }
+            }
+        })
+    }
+
+    // The fields of Object, Err(None) for a field it doesn't have.
+    fn resolve_object(&self, node: u32, field: &Field) -> Result<Output<'a>, Option<String>> {
+        let heap = self.heap;
+        let snapshot = &heap.snapshot;
+        let object = &snapshot.objects[node as usize];
+        let limit = |name| self.int(field, name, 100).map_err(Some);
+        Ok(match field.name.as_str() {
+            "id" => id(object.object_id()),
+            "class" => match snapshot.class_of(object) {
+                Some(class_id) => self.class(class_id),
+                None => Output::Leaf(Json::Null),
+            },
+            "className" => string(&snapshot.object_class_name(object)),
+            "label" => string(&snapshot.object_label(object)),
+            "shallowSize" => number(snapshot.shallow_size(object)),
+            "retainedSize" => number(heap.retained().1[node as usize]),
+            "reachable" => Output::Leaf(Json::Bool(heap.live()[node as usize])),
+            "dominator" => {
+                let tree = &heap.retained().0;
+                match tree.idom[node as usize] {
+                    idom if idom == tree.virtual_root() || idom == paths::NONE => {
+                        Output::Leaf(Json::Null)
+                    }
+                    idom => Output::One(Node::Object(idom)),
+                }
+            }
+            "gcRoots" => {
+                let id = object.object_id();
+                Output::Leaf(Json::Array(
+                    snapshot
+                        .roots
+                        .iter()
+                        .filter(|r| r.object_id == id)
+                        .map(|r| Json::Str(r.kind.name().to_string()))
+                        .collect(),
+                ))
+            }
+            "string" => Output::Leaf(as_string(snapshot, object).map_or(Json::Null, Json::Str)),
+            "length" => match object {
+                HeapObject::ObjectArray(a) => number(a.elements.len()),
+                HeapObject::PrimitiveArray(a) => number(a.length),
+                _ => Output::Leaf(Json::Null),
+            },
+            "fields" => Output::Many(match object {
+                HeapObject::Instance(i) => snapshot
+                    .instance_fields(i)
+                    .into_iter()
+                    .map(|f| Node::Field(snapshot.string(f.name_id).to_string(), f.tag, f.value))
+                    .collect(),
+                HeapObject::Class(c) => c
+                    .static_fields()
+                    .iter()
+                    .map(|f| Node::Field(snapshot.string(f.name_id).to_string(), f.tag, f.value))
+                    .collect(),
+                _ => Vec::new(),
+            }),
+            "elements" => Output::Many(match object {
+                HeapObject::ObjectArray(a) => a
+                    .elements
+                    .iter()
+                    .take(limit("limit")?)
+                    .enumerate()
+                    .map(|(n, &e)| {
+                        Node::Field(
+                            format!("[{}]", n),
+                            FieldTag::NormalObject,
+                            heap::Value::Object(e),
+                        )
+                    })
+                    .collect(),
+                _ => Vec::new(),
+            }),
+            "references" => Output::Many(
+                graph::references(snapshot, object)
+                    .into_iter()
+                    .filter_map(|r| {
+                        Some(Node::Reference(node, snapshot.index_of(r.target)?, r.via))
+                    })
+                    .take(limit("limit")?)
+                    .collect(),
+            ),
+            "referrers" => Output::Many(
+                graph::incoming_references(snapshot, &heap.graph().1, node)
+                    .into_iter()
+                    .take(limit("limit")?)
+                    .map(|(from, via)| Node::Reference(from, node, via))
+                    .collect(),
+            ),
+            "pathToRoot" => {
+                let excluded = match self.argument(field, "exclude") {
+                    Value::Null => Vec::new(),
+                    Value::List(kinds) => {
+                        let mut names = Vec::new();
+                        for kind in kinds {
+                            match kind {
+                                Value::Str(name) | Value::Enum(name) => names.push(name),
+                                v => return Err(Some(format!("bad reference kind {:?}", v))),
+                            }
+                        }
+                        reference::parse_kinds(&names.join(",")).map_err(Some)?
+                    }
+                    Value::Str(list) => reference::parse_kinds(&list).map_err(Some)?,
+                    v => return Err(Some(format!("exclude must be a list, not {:?}", v))),
+                };
+                let filter = RetentionFilter::new(snapshot, excluded);
+                let (graph, preds) = heap.graph();
+                match paths::shortest_path_to_root(snapshot, graph, preds, &filter, node) {
+                    Some(path) => Output::Many(
+                        path.windows(2)
+                            .map(|hops| {
+                                Node::Reference(hops[1].node, hops[0].node, hops[1].via.unwrap())
+                            })
+                            .collect(),
+                    ),
+                    None => Output::Leaf(Json::Null),
+                }
+            }
+            "json" => {
+                let depth = self.int(field, "depth", 1).map_err(Some)?;
+                let width = self.int(field, "width", 10).map_err(Some)?;
+                let decoders = Decoders::builtin();
+                string(&json::object_json(snapshot, &decoders, object, depth, width).compact())
+            }
+            _ => return Err(None),
+        })
+    }
+
+    fn error(&mut self, message: String, path: &[Json]) {
+        self.errors.push(Json::Object(vec![
+            ("message".to_string(), Json::Str(message)),
+            ("path".to_string(), Json::Array(path.to_vec())),
+        ]));
+    }
+
+    fn select(
+        &mut self,
+        node: &Node<'a>,
+        selections: &'a [graphql::Selection],
+        path: &mut Vec<Json>,
+    ) -> Json {
+        let document = self.document;
+        let mut fields = Vec::new();
+        graphql::collect_fields(
+            document,
+            selections,
+            node.type_name(),
+            &self.variables,
+            &mut fields,
+        );
+        let mut members: Vec<(String, Json)> = Vec::new();
+        for field in fields {
+            if members.iter().any(|(key, _)| key == field.key()) {
+                continue;
+            }
+            path.push(Json::Str(field.key().to_string()));
+            let value = match self.resolve(node, field) {
+                Ok(output) => self.complete(output, field, path),
+                Err(message) => {
+                    self.error(message, path);
+                    Json::Null
+                }
+            };
+            path.pop();
+            members.push((field.key().to_string(), value));
+        }
+        Json::Object(members)
+    }
+
+    fn complete(&mut self, output: Output<'a>, field: &'a Field, path: &mut Vec<Json>) -> Json {
+        match output {
+            Output::Leaf(json) if field.selections.is_empty() => json,
+            Output::Leaf(Json::Null) => Json::Null,
+            Output::Leaf(_) => {
+                self.error(format!("{} has no fields to select", field.name), path);
+                Json::Null
+            }
+            _ if field.selections.is_empty() => {
+                self.error(format!("{} needs a selection of fields", field.name), path);
+                Json::Null
+            }
+            Output::One(node) => self.select(&node, &field.selections, path),
+            Output::Many(nodes) => Json::Array(
+                nodes
+                    .iter()
+                    .enumerate()
+                    .map(|(i, node)| {
+                        path.push(Json::number(i));
+                        let value = self.select(node, &field.selections, path);
+                        path.pop();
+                        value
+                    })
+                    .collect(),
+            ),
+        }
+    }
+}
+
+fn errors(message: String) -> Json {
+    Json::Object(vec![(
+        "errors".to_string(),
+        Json::Array(vec![Json::Object(vec![(
+            "message".to_string(),
+            Json::Str(message),
+        )])]),
+    )])
+}
+
+//
+// Runs a query, returning the response: its data and errors, or only the
+// errors if it couldn't be run at all.
+//
+pub fn execute(
+    heap: &Heap,
+    query: &str,
+    operation_name: Option<&str>,
+    variables: Option<&Json>,
+) -> Json {
+    let document = match graphql::parse(query) {
+        Ok(document) => document,
+        Err(e) => return errors(format!("syntax error: {}", e)),
+    };
+    let operation = match document.operation(operation_name) {
+        Ok(operation) => operation,
+        Err(e) => return errors(e),
+    };
+    if operation.kind != "query" {
+        return errors(format!(
+            "{}s are not supported, only queries",
+            operation.kind
+        ));
+    }
+    let mut executor = Executor {
+        heap,
+        document: &document,
+        variables: graphql::variables(operation, variables),
+        errors: Vec::new(),
+    };
+    let data = executor.select(&Node::Query, &operation.selections, &mut Vec::new());
+    let mut response = vec![("data".to_string(), data)];
+    if !executor.errors.is_empty() {
+        response.push(("errors".to_string(), Json::Array(executor.errors)));
+    }
+    Json::Object(response)
+}
+
+//
+// A GraphQL request over HTTP: a JSON body with query, operationName and
+// variables, the same as parameters (GET or a form), or the query alone
+// as the body (application/graphql).
+//
+pub fn request(heap: &Heap, request: &Request) -> Result<Json, Error> {
+    if let Ok(body @ Json::Object(_)) = json::parse(&request.body) {
+        let query = body
+            .get("query")
+            .and_then(Json::as_str)
+            .ok_or((400, "no query in the body".to_string()))?;
+        let operation = body.get("operationName").and_then(Json::as_str);
+        return Ok(execute(heap, query, operation, body.get("variables")));
+    }
+    let variables = match request.param("variables") {
+        Some(text) => Some(json::parse(text).map_err(|e| (400, format!("bad variables: {}", e)))?),
+        None => None,
+    };
+    let query = match request.param("query") {
+        Some(query) => query,
+        None if !request.body.trim().is_empty() => &request.body,
+        None => return Err((400, "no query given".to_string())),
+    };
+    Ok(execute(
+        heap,
+        query,
+        request.param("operationName"),
+        variables.as_ref(),
+    ))
+}
@@ -0,0 +1,290 @@
+//
+// The gRPC service of the daemon (proto/heap_analysis.proto) over HTTP/2,
+// on the snapshots it has loaded. Each call is one length-prefixed message
+// each way; a failed call has no message, and its status is in the
+// trailers, mapped from that of the JSON API.
+//
+use super::api::{Error, Heap};
+use super::daemon::Snapshots;
+use crate::cli;
+use hprof_cat::decoders::Decoders;
+use hprof_cat::diff;
+use hprof_cat::graph;
+use hprof_cat::http2::{self, Request, Response};
+use hprof_cat::json;
+use hprof_cat::paths;
+use hprof_cat::protobuf::{Message, Writer};
+use hprof_cat::window::Window;
+
+use std::net::TcpListener;
+use std::sync::Arc;
+
+const SERVICE: &str = "/hprofcat.HeapAnalysis/";
+
+const OK: u32 = 0;
+const INVALID_ARGUMENT: u32 = 3;
+const NOT_FOUND: u32 = 5;
+const UNIMPLEMENTED: u32 = 12;
+const INTERNAL: u32 = 13;
+
+// A failed call: the gRPC status and its message.
+type Status = (u32, String);
+
+fn status((code, message): Error) -> Status {
+    let code = match code {
+        400 => INVALID_ARGUMENT,
+        404 => NOT_FOUND,
+        _ => INTERNAL,
+    };
+    (code, message)
+}
+
+fn invalid(message: String) -> Status {
+    (INVALID_ARGUMENT, message)
+}
+
+fn or_default(value: u64, default: usize) -> usize {
+    if value == 0 {
+        default
+    } else {
+        value as usize
+    }
+}
+
+fn heap(snapshots: &Snapshots, request: &Message, field: u32) -> Result<Arc<Heap>, Status> {
+    let name = request.string(field).map_err(invalid)?;
+    snapshots
+        .get(Some(name.as_str()).filter(|n| !n.is_empty()))
+        .map_err(status)
+}
+
+fn summary(heap: &Heap) -> Writer {
+    let snapshot = &heap.snapshot;
+    let mut summary = Writer::new();
+    summary
+        .string(1, snapshot.header.format.trim_end_matches('\0'))
+        .uint(2, snapshot.id_size() as u64)
+        .uint(3, snapshot.header.timestamp_ms())
+        .uint(4, snapshot.objects.len() as u64)
+        .uint(5, heap.bytes())
+        .uint(6, snapshot.classes.len() as u64)
+        .uint(7, snapshot.roots.len() as u64)
+        .uint(8, snapshot.threads.len() as u64)
+        .uint(9, snapshot.strings.len() as u64)
+        .string(10, snapshot.size_model.name());
+    summary
+}
+
+fn load_dump(snapshots: &Snapshots, request: &Message) -> Result<Writer, Status> {
+    let dump = request.string(1).map_err(invalid)?;
+    if dump.is_empty() {
+        return Err(invalid("missing dump".to_string()));
+    }
+    let name = request.string(2).map_err(invalid)?;
+    let (name, heap) = snapshots
+        .load(
+            &dump,
+            Some(name.as_str()).filter(|n| !n.is_empty()),
+            &Window::default(),
+        )
+        .map_err(status)?;
+    let mut response = Writer::new();
+    response.string(1, &name).message(2, &summary(&heap));
+    Ok(response)
+}
+
+fn get_histogram(snapshots: &Snapshots, request: &Message) -> Result<Writer, Status> {
+    let heap = heap(snapshots, request, 1)?;
+    let top = or_default(request.uint(2).map_err(invalid)?, 100);
+    let mut response = Writer::new();
+    for entry in heap
+        .entries(request.bool(3).map_err(invalid)?)
+        .iter()
+        .take(top)
+    {
+        let mut message = Writer::new();
+        message
+            .string(1, &entry.class_name)
+            .uint(2, entry.instances)
+            .uint(3, entry.shallow);
+        response.message(1, &message);
+    }
+    Ok(response)
+}
+
+fn get_object(snapshots: &Snapshots, request: &Message) -> Result<Writer, Status> {
+    let heap = heap(snapshots, request, 1)?;
+    let snapshot = &heap.snapshot;
+    let id = request.uint(2).map_err(invalid)?;
+    let index = heap.index(&id.to_string()).map_err(status)?;
+    let object = &snapshot.objects[index as usize];
+    let rendered = json::object_json(
+        snapshot,
+        &Decoders::builtin(),
+        object,
+        or_default(request.uint(3).map_err(invalid)?, 3),
+        or_default(request.uint(4).map_err(invalid)?, 10),
+    );
+    let mut response = Writer::new();
+    response
+        .uint(1, id)
+        .string(2, &snapshot.object_label(object))
+        .uint(3, snapshot.shallow_size(object))
+        .uint(4, heap.retained().1[index as usize])
+        .string(5, &rendered.pretty());
+    Ok(response)
+}
+
+fn find_paths(snapshots: &Snapshots, request: &Message) -> Result<Writer, Status> {
+    let heap = heap(snapshots, request, 1)?;
+    let snapshot = &heap.snapshot;
+    let id = request.uint(2).map_err(invalid)?;
+    let index = heap.index(&id.to_string()).map_err(status)?;
+    let exclude = request.strings(3).map_err(invalid)?.join(",");
+    let exclude = Some(exclude.as_str()).filter(|e| !e.is_empty());
+    let mut response = Writer::new();
+    let path = match heap.root_path(index, exclude).map_err(status)? {
+        Some(path) => path,
+        None => return Ok(response),
+    };
+    response
+        .bool(1, true)
+        .string(2, &paths::render_chain(snapshot, &path));
+    for hop in &path {
+        let object = &snapshot.objects[hop.node as usize];
+        let via = hop.via.map(|via| graph::qualified_via_name(snapshot, via));
+        let mut message = Writer::new();
+        message
+            .uint(1, object.object_id())
+            .string(2, &snapshot.object_label(object))
+            .string(3, via.as_deref().unwrap_or(""));
+        response.message(3, &message);
+    }
+    let root_id = snapshot.objects[path.last().unwrap().node as usize].object_id();
+    for root in snapshot.roots.iter().filter(|r| r.object_id == root_id) {
+        response.string(4, root.kind.name());
+    }
+    Ok(response)
+}
+
+fn diff(snapshots: &Snapshots, request: &Message) -> Result<Writer, Status> {
+    let after = heap(snapshots, request, 1)?;
+    let baseline = request.string(2).map_err(invalid)?;
+    if baseline.is_empty() {
+        return Err(invalid("missing baseline".to_string()));
+    }
+    let before = snapshots.get(Some(&baseline)).map_err(status)?;
+    let top = or_default(request.uint(3).map_err(invalid)?, 100);
+    let live = request.bool(4).map_err(invalid)?;
+    let deltas = diff::diff_keys(
+        &diff::histogram_keys(&before.entries(live)),
+        &diff::histogram_keys(&after.entries(live)),
+    );
+    let mut response = Writer::new();
+    for delta in deltas.iter().take(top) {
+        let mut message = Writer::new();
+        message
+            .string(1, &delta.key)
+            .uint(2, delta.before.objects)
+            .uint(3, delta.after.objects)
+            .uint(4, delta.before.retained)
+            .uint(5, delta.after.retained)
+            .int(6, delta.growth());
+        response.message(1, &message);
+    }
+    Ok(response)
+}
+
+// The message of a call, out of its length-prefixed frame.
+fn unframe(body: &[u8]) -> Result<&[u8], Status> {
+    if body.len() < 5 {
+        return Err(invalid("missing request message".to_string()));
+    }
+    if body[0] != 0 {
+        return Err((
+            UNIMPLEMENTED,
+            "compressed messages are not supported".to_string(),
+        ));
+    }
+    let len = u32::from_be_bytes([body[1], body[2], body[3], body[4]]) as usize;
+    if body.len() != 5 + len {
+        return Err(invalid("one request message expected".to_string()));
+    }
+    Ok(&body[5..])
+}
+
+fn call(snapshots: &Snapshots, method: &str, body: &[u8]) -> Result<Writer, Status> {
+    let handler = match method {
+        "LoadDump" => load_dump,
+        "GetHistogram" => get_histogram,
+        "GetObject" => get_object,
+        "FindPaths" => find_paths,
+        "Diff" => diff,
+        _ => return Err((UNIMPLEMENTED, format!("no method {}", method))),
+    };
+    let request = Message::parse(unframe(body)?).map_err(invalid)?;
+    handler(snapshots, &request)
+}
+
+// grpc-message is percent-encoded, as are all but printable ASCII.
+fn percent_encode(message: &str) -> String {
+    let mut encoded = String::new();
+    for byte in message.bytes() {
+        if (0x20..0x7f).contains(&byte) && byte != b'%' {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+fn header(name: &str, value: &str) -> (String, String) {
+    (name.to_string(), value.to_string())
+}
+
+fn respond(snapshots: &Snapshots, request: &Request) -> Response {
+    let grpc = request
+        .header("content-type")
+        .is_some_and(|t| t.starts_with("application/grpc"));
+    if request.method != "POST" || !grpc {
+        return Response {
+            status: 415,
+            ..Response::default()
+        };
+    }
+    let result = match request.path.strip_prefix(SERVICE) {
+        Some(method) => call(snapshots, method, &request.body),
+        None => Err((UNIMPLEMENTED, format!("no service at {}", request.path))),
+    };
+    let mut response = Response {
+        status: 200,
+        headers: vec![header("content-type", "application/grpc")],
+        ..Response::default()
+    };
+    match result {
+        Ok(message) => {
+            response.body.push(0);
+            response
+                .body
+                .extend_from_slice(&(message.bytes.len() as u32).to_be_bytes());
+            response.body.extend_from_slice(&message.bytes);
+            response.trailers = vec![header("grpc-status", &OK.to_string())];
+        }
+        Err((code, message)) => {
+            response.trailers = vec![
+                header("grpc-status", &code.to_string()),
+                header("grpc-message", &percent_encode(&message)),
+            ];
+        }
+    }
+    response
+}
+
+// Serves the gRPC service on `address` until killed.
+pub fn serve(snapshots: &Snapshots, address: &str) {
+    let listener =
+        TcpListener::bind(address).unwrap_or_else(|e| cli::die(&format!("{}: {}", address, e)));
+    eprintln!("serving gRPC on {}", address);
+    http2::serve(listener, |request| respond(snapshots, request));
+}
@@ -0,0 +1,371 @@
+//
+// Commands looking for the usual suspects of memory leaks.
+//
+use super::{print_hops, thread_selector};
+use crate::cli::{self, Args};
+use hprof_cat::graph::Graph;
+use hprof_cat::reference::RetentionFilter;
+use hprof_cat::snapshot::Snapshot;
+use hprof_cat::suspects::{self, SuspectKind};
+use hprof_cat::{cache, finalizers, leaks, offheap, paths, reachability, threads};
+
+use std::collections::HashSet;
+
+pub fn print_classloader_leaks(snapshot: &Snapshot) {
+    let graph = Graph::build(snapshot);
+    let leaks = leaks::classloader_leaks(snapshot, &graph);
+    println!("{} suspicious class loaders", leaks.len());
+    for leak in &leaks {
+        let loader = &snapshot.objects[leak.loader as usize];
+        let edges: Vec<&str> = leak.edges.iter().map(|e| e.name()).collect();
+        println!();
+        println!(
+            "{:#x} {} ({} classes) kept alive by {}",
+            loader.object_id(),
+            snapshot.object_label(loader),
+            leak.classes,
+            edges.join(", ")
+        );
+        print_hops(snapshot, &leak.path);
+    }
+}
+
+//
+// Thread local map entries per thread. Entries that look like leaks (a
+// stale key, a key class from a foreign or leaked class loader) are always
+// listed, the others only if their value retains at least --min-bytes.
+//
+pub fn print_thread_local_leaks(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let min_bytes = args.number("--min-bytes", 10240);
+    let selector = thread_selector(snapshot, args);
+    let graph = Graph::build(snapshot);
+    let (_, retained) = cache::retained_sizes(snapshot, &graph);
+    let leaked: HashSet<u64> = leaks::classloader_leaks(snapshot, &graph)
+        .iter()
+        .map(|l| snapshot.objects[l.loader as usize].object_id())
+        .collect();
+    let threads = threads::threads(snapshot);
+    let mut entries = leaks::thread_local_entries(snapshot, &threads, &leaked, &retained);
+    entries.sort_by_key(|e| (e.thread, std::cmp::Reverse(e.retained)));
+
+    let label = |node: Option<u32>| match node {
+        Some(node) => {
+            let object = &snapshot.objects[node as usize];
+            format!(
+                "{:#x} {}",
+                object.object_id(),
+                snapshot.object_label(object)
+            )
+        }
+        None => "null".to_string(),
+    };
+    for (t, thread) in threads.iter().enumerate() {
+        let own: Vec<_> = entries.iter().filter(|e| e.thread == t).collect();
+        let shown: Vec<_> = own
+            .iter()
+            .filter(|e| e.is_suspicious() || e.retained >= min_bytes)
+            .collect();
+        let selected = selector
+            .as_ref()
+            .is_none_or(|s| s.matches(Some(thread.serial), &thread.name));
+        if shown.is_empty() || !selected {
+            continue;
+        }
+        println!(
+            "thread {} \"{}\": {} entries ({} shown) retaining {}",
+            thread.serial,
+            thread.name,
+            own.len(),
+            shown.len(),
+            units.amount(own.iter().map(|e| e.retained).sum::<u64>())
+        );
+        for e in shown {
+            let mut flags = Vec::new();
+            if e.key.is_none() {
+                flags.push("stale key".to_string());
+            }
+            if let Some(loader) = e.foreign_loader {
+                flags.push(format!("key from loader {:#x}", loader));
+            }
+            if e.leaked_loader {
+                flags.push("leaked class loader".to_string());
+            }
+            println!(
+                "  {:>12}  key {}  value {}{}",
+                units.bytes(e.retained),
+                label(e.key),
+                label(e.value),
+                if flags.is_empty() {
+                    String::new()
+                } else {
+                    format!("  [{}]", flags.join(", "))
+                }
+            );
+        }
+    }
+}
+
+pub fn print_finalizers(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let top = args.number("--top", 25) as usize;
+    let graph = Graph::build(snapshot);
+    let (_, retained) = cache::retained_sizes(snapshot, &graph);
+    let groups = finalizers::registered_objects(snapshot, &retained);
+
+    if let Some(length) = finalizers::finalizer_queue_length(snapshot) {
+        println!("finalizer queue length: {}", length);
+    }
+    println!(
+        "{:>10} {:>10} {:>14}  {:<10} Class",
+        "Registered", "Pending", "Retained", "Mechanism"
+    );
+    for g in groups.iter().take(top) {
+        println!(
+            "{:>10} {:>10} {:>14}  {:<10} {}",
+            g.registered,
+            g.pending,
+            units.bytes(g.pending_retained),
+            g.mechanism.name(),
+            g.class_name
+        );
+    }
+}
+
+// How MAT names class loaders: the bootstrap loader has no object.
+fn loader_label(snapshot: &Snapshot, class_id: Option<u64>) -> String {
+    match class_id
+        .and_then(|id| snapshot.class_dump(id))
+        .map(|c| c.class_loader_id)
+    {
+        Some(0) | None => "<bootstrap class loader>".to_string(),
+        Some(loader) => match snapshot.object(loader) {
+            Some(object) => format!("{} @ {:#x}", snapshot.object_label(object), loader),
+            None => format!("{:#x}", loader),
+        },
+    }
+}
+
+fn percent(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        100.0 * part as f64 / total as f64
+    }
+}
+
+//
+// A narrative report of the objects and classes retaining a large share
+// of the heap, and of the classes whose instances pile up in one place,
+// with the retaining path of each suspect object.
+//
+pub fn print_leak_suspects(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let threshold = match args.value("--threshold") {
+        Some(v) => v
+            .parse::<f64>()
+            .unwrap_or_else(|_| cli::die(&format!("--threshold: not a number: {}", v))),
+        None => 10.0,
+    };
+    let graph = Graph::build(snapshot);
+    let (tree, retained) = cache::retained_sizes(snapshot, &graph);
+    let total: u64 = tree
+        .order
+        .iter()
+        .map(|&n| snapshot.shallow_size(&snapshot.objects[n as usize]))
+        .sum();
+    let suspects = suspects::leak_suspects(snapshot, &tree, &retained, threshold / 100.0);
+    let everything = RetentionFilter::new(snapshot, Vec::new());
+    let parents = paths::bfs_parents(snapshot, &graph, &everything);
+
+    if suspects.is_empty() {
+        println!(
+            "No object or class retains {}% or more of the {} reachable.",
+            threshold,
+            units.amount(total)
+        );
+    }
+    for (n, suspect) in suspects.iter().enumerate() {
+        println!("Problem suspect {}", n + 1);
+        println!();
+        match &suspect.kind {
+            SuspectKind::Object(node) => {
+                let object = &snapshot.objects[*node as usize];
+                println!(
+                    "One instance of {} loaded by {} retains {} ({:.1}%) of the reachable heap.",
+                    snapshot.object_class_name(object),
+                    loader_label(snapshot, snapshot.class_of(object)),
+                    units.amount(suspect.retained),
+                    percent(suspect.retained, total)
+                );
+                match paths::path_from_parents(snapshot, &everything, &parents, *node) {
+                    Some(path) if path.len() > 1 => println!(
+                        "It is referenced by {}.",
+                        paths::render_chain(snapshot, &path)
+                    ),
+                    _ => println!("It is a GC root."),
+                }
+            }
+            SuspectKind::Class {
+                name,
+                instances,
+                common_dominator,
+            } => {
+                println!(
+                    "{} instances of {} loaded by {} retain {} ({:.1}%) of the reachable heap.",
+                    instances,
+                    name,
+                    loader_label(snapshot, snapshot.find_class(name)),
+                    units.amount(suspect.retained),
+                    percent(suspect.retained, total)
+                );
+                if let Some(d) = common_dominator {
+                    let dominator = &snapshot.objects[*d as usize];
+                    println!(
+                        "All of them are kept alive by one instance of {} ({:#x}).",
+                        snapshot.object_label(dominator),
+                        dominator.object_id()
+                    );
+                }
+            }
+            SuspectKind::Growth {
+                name,
+                instances,
+                median,
+                held,
+            } => {
+                println!(
+                    "There are {} instances of {} loaded by {}, {} times as many as a class \
+                     usually has, and {} of them are in one place.",
+                    instances,
+                    name,
+                    loader_label(snapshot, snapshot.find_class(name)),
+                    instances / median.max(&1),
+                    held
+                );
+            }
+        }
+        if let Some(point) = suspect.accumulation_point {
+            let object = &snapshot.objects[point as usize];
+            if !matches!(suspect.kind, SuspectKind::Object(node) if node == point) {
+                println!(
+                    "The memory is accumulated in one instance of {} ({:#x}) retaining {}.",
+                    snapshot.object_label(object),
+                    object.object_id(),
+                    units.amount(retained[point as usize])
+                );
+            }
+        }
+        if !suspect.contents.is_empty() {
+            println!("Biggest contents:");
+            for (class_name, objects, bytes) in &suspect.contents {
+                println!(
+                    "  {:>10} objects {:>20}  {}",
+                    objects,
+                    units.amount(*bytes),
+                    class_name
+                );
+            }
+        }
+        println!();
+    }
+}
+
+//
+// Native memory held by direct and mapped buffers. Unreachable buffers
+// still hold theirs until the GC collects them and their cleaner runs.
+//
+pub fn print_direct_buffers(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let top = args.number("--top", 10) as usize;
+    let graph = Graph::build(snapshot);
+    let live = reachability::mark(&graph);
+    let buffers = offheap::direct_buffers(snapshot);
+    let owners: Vec<_> = buffers.iter().filter(|b| !b.view).collect();
+    let mut totals = [(0u64, 0u64); 3];
+    for b in &owners {
+        let total = &mut totals[if b.mapped {
+            2
+        } else if live[b.node as usize] {
+            0
+        } else {
+            1
+        }];
+        total.0 += 1;
+        total.1 += b.capacity;
+    }
+    println!(
+        "{} direct buffers owning {} of native memory ({} slices and duplicates)",
+        owners.len(),
+        units.amount(owners.iter().map(|b| b.capacity).sum::<u64>()),
+        buffers.len() - owners.len()
+    );
+    let labels = [
+        "allocated, reachable",
+        "allocated, unreachable",
+        "mapped files",
+    ];
+    for (label, (count, bytes)) in labels.iter().zip(totals) {
+        println!(
+            "  {:<24} {:>8} buffers {:>20}",
+            label,
+            count,
+            units.amount(bytes)
+        );
+    }
+
+    let everything = RetentionFilter::new(snapshot, Vec::new());
+    let parents = paths::bfs_parents(snapshot, &graph, &everything);
+    for b in owners.iter().take(top) {
+        let object = &snapshot.objects[b.node as usize];
+        println!();
+        println!(
+            "{:#x} {} capacity {}{}",
+            object.object_id(),
+            snapshot.object_label(object),
+            units.amount(b.capacity),
+            if b.mapped { " (mapped)" } else { "" }
+        );
+        match paths::path_from_parents(snapshot, &everything, &parents, b.node) {
+            Some(path) => print_hops(snapshot, &path),
+            None => println!("  unreachable"),
+        }
+    }
+}
+
+//
+// JNI global references by referenced class. Classes with at least
+// --min-count globals are flagged: native code rarely needs that many of
+// one kind, and the ones it leaks are invisible from Java.
+//
+pub fn print_jni_globals(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let top = args.number("--top", 25) as usize;
+    let min_count = args.number("--min-count", 100);
+    let graph = Graph::build(snapshot);
+    let (tree, retained) = cache::retained_sizes(snapshot, &graph);
+    let report = leaks::jni_globals(snapshot, &tree, &retained);
+    println!(
+        "{} JNI global references to {} objects",
+        report.iter().map(|g| g.globals).sum::<u64>(),
+        report.iter().map(|g| g.objects).sum::<u64>()
+    );
+    println!(
+        "{:>10} {:>10} {:>14}  Class",
+        "Globals", "Objects", "Retained"
+    );
+    for g in report.iter().take(top) {
+        println!(
+            "{:>10} {:>10} {:>14}  {}{}",
+            g.globals,
+            g.objects,
+            units.bytes(g.retained),
+            g.class_name,
+            if g.globals >= min_count {
+                "  <- suspicious"
+            } else {
+                ""
+            }
+        );
+    }
+}
@@ -0,0 +1,201 @@
+//
+// The implementation of every subcommand, grouped by area. Each command
+// takes the loaded snapshot and its parsed arguments and prints a report.
+//
+pub mod api;
+pub mod classes;
+pub mod daemon;
+pub mod dumps;
+pub mod graphql;
+pub mod grpc;
+pub mod leaks;
+pub mod objects;
+pub mod retention;
+pub mod strings;
+pub mod threads;
+pub mod traces;
+pub mod waste;
+
+use crate::cli::{self, parse_object_id, Args};
+use hprof_cat::collections::{self, CollectionKind};
+use hprof_cat::graph;
+use hprof_cat::heap::{HeapObject, Value};
+use hprof_cat::hierarchy::{Hierarchy, TypeGroups};
+use hprof_cat::paths::{self, Hop};
+use hprof_cat::reference::{self, RetentionFilter};
+use hprof_cat::snapshot::Snapshot;
+
+use hprof_cat::strings::as_string;
+use hprof_cat::threads::ThreadSelector;
+use std::collections::HashMap;
+
+pub fn object_index(snapshot: &Snapshot, arg: &str) -> u32 {
+    let id = parse_object_id(arg);
+    snapshot
+        .index_of(id)
+        .unwrap_or_else(|| cli::die(&format!("no object with id {:#x}", id)))
+}
+
+// Describes the target of a reference for the object view.
+pub fn describe_value(snapshot: &Snapshot, value: Value) -> String {
+    match value.as_object().and_then(|id| snapshot.object(id)) {
+        Some(object) => match as_string(snapshot, object) {
+            Some(s) => format!(
+                "{} {} {}",
+                value,
+                snapshot.object_label(object),
+                quote(&s, 60)
+            ),
+            None => format!("{} {}", value, snapshot.object_label(object)),
+        },
+        None => value.to_string(),
+    }
+}
+
+// Quotes a string for display, shortening it to at most `max` chars.
+pub fn quote(value: &str, max: usize) -> String {
+    let mut quoted = String::new();
+    for c in value.chars().take(max) {
+        if c == '"' || c == '\\' || c.is_control() {
+            quoted.extend(c.escape_default());
+        } else {
+            quoted.push(c);
+        }
+    }
+    if value.chars().count() > max {
+        quoted.push_str("...");
+    }
+    format!("\"{}\"", quoted)
+}
+
+pub fn retention_filter(snapshot: &Snapshot, args: &Args) -> RetentionFilter {
+    let excluded = match args.value("--exclude") {
+        Some(list) => reference::parse_kinds(list).unwrap_or_else(|e| cli::die(&e)),
+        None => Vec::new(),
+    };
+    RetentionFilter::new(snapshot, excluded)
+}
+
+//
+// The threads that --thread selects, None for all of them. Exits if it
+// selects none.
+//
+pub fn thread_selector(snapshot: &Snapshot, args: &Args) -> Option<ThreadSelector> {
+    let value = args.value("--thread")?;
+    let selector = ThreadSelector::parse(value).unwrap_or_else(|e| cli::die(&e));
+    if !hprof_cat::threads::threads(snapshot)
+        .iter()
+        .any(|t| selector.matches(Some(t.serial), &t.name))
+    {
+        cli::die(&format!("no thread matches --thread {}", value));
+    }
+    Some(selector)
+}
+
+//
+// Prints a path to a GC root as a chain of field accesses followed by one
+// line per hop and the kinds of GC root at the end of it.
+//
+pub fn print_hops(snapshot: &Snapshot, path: &[Hop]) {
+    println!("{}", paths::render_chain(snapshot, path));
+    for hop in path {
+        let object = &snapshot.objects[hop.node as usize];
+        match hop.via {
+            None => println!(
+                "{:#x} {}",
+                object.object_id(),
+                snapshot.object_label(object)
+            ),
+            Some(via) => println!(
+                "  <- {} of {:#x} {}",
+                graph::qualified_via_name(snapshot, via),
+                object.object_id(),
+                snapshot.object_label(object)
+            ),
+        }
+    }
+    let root_id = snapshot.objects[path.last().unwrap().node as usize].object_id();
+    for root in snapshot.roots.iter().filter(|r| r.object_id == root_id) {
+        match root.thread_serial_num {
+            Some(serial) => println!("     GC root: {} (thread {})", root.kind.name(), serial),
+            None => println!("     GC root: {}", root.kind.name()),
+        }
+    }
+}
+
+//
+// A one-line summary of what an object holds: the contents of a string,
+// the size of a collection, the length of an array...
+//
+pub fn summarize(snapshot: &Snapshot, kinds: &HashMap<u64, CollectionKind>, node: u32) -> String {
+    let object = &snapshot.objects[node as usize];
+    if let Some(s) = as_string(snapshot, object) {
+        return quote(&s, 40);
+    }
+    if let Some(c) = collections::decode(snapshot, kinds, node) {
+        return format!("size {} capacity {}", c.size, c.capacity);
+    }
+    match object {
+        HeapObject::ObjectArray(a) => format!(
+            "length {}, {} non-null",
+            a.elements.len(),
+            a.elements.iter().filter(|&&e| e != 0).count()
+        ),
+        HeapObject::PrimitiveArray(a) => format!("length {}", a.length),
+        _ => String::new(),
+    }
+}
+
+//
+// The --supertype option: objects assignable to one of the listed types are
+// grouped under that type instead of their class.
+//
+pub fn supertype_groups(snapshot: &Snapshot, args: &Args) -> TypeGroups {
+    match args.value("--supertype") {
+        Some(types) => {
+            let types: Vec<&str> = types.split(',').collect();
+            Hierarchy::build(snapshot).supertype_groups(snapshot, &types)
+        }
+        None => TypeGroups::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hprof_cat::heap::FieldTag;
+    use hprof_cat::testing::Dump;
+
+    #[test]
+    fn quotes_are_escaped_and_cut() {
+        assert_eq!(quote("a \"b\"\n", 10), "\"a \\\"b\\\"\\n\"");
+        assert_eq!(quote("abcdef", 3), "\"abc...\"");
+    }
+
+    #[test]
+    fn objects_are_summarized_by_what_they_hold() {
+        let mut dump = Dump::new();
+        let list = dump.class(
+            "java/util/ArrayList",
+            dump.object,
+            &[
+                ("elementData", FieldTag::ArrayObject),
+                ("size", FieldTag::Int),
+            ],
+        );
+        let string = dump.string("a \"quoted\" string");
+        let array = dump.object_array(dump.object_array, &[string, 0, string, 0]);
+        let list = dump.instance(list, &[Value::Object(array), Value::Int(2)]);
+        let ints = dump.primitive_array(FieldTag::Int, &[0; 12]);
+        let other = dump.instance(dump.object, &[]);
+        let snapshot = dump.load();
+
+        let kinds = collections::collection_classes(&snapshot);
+        let summary = |id| summarize(&snapshot, &kinds, snapshot.index_of(id).unwrap());
+        assert_eq!(summary(string), "\"a \\\"quoted\\\" string\"");
+        assert_eq!(summary(list), "size 2 capacity 4");
+        assert_eq!(summary(array), "length 4, 2 non-null");
+        assert_eq!(summary(ints), "length 3");
+        assert_eq!(summary(other), "");
+    }
+}
@@ -0,0 +1,263 @@
+use super::{describe_value, object_index, quote};
+use crate::cli::{self, Args};
+use hprof_cat::collections::Contents;
+use hprof_cat::decoders::{Decoded, Decoders};
+use hprof_cat::heap::{self, HeapObject};
+use hprof_cat::predicate::{self, FieldValue, Predicate};
+use hprof_cat::snapshot::Snapshot;
+use hprof_cat::{json, reachability, strings};
+
+use std::collections::HashSet;
+
+pub fn print_object(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let limit = args.number("--limit", 32) as usize;
+    let decoders = Decoders::builtin();
+    for arg in &args.positional {
+        let index = object_index(snapshot, arg);
+        let object = &snapshot.objects[index as usize];
+        println!(
+            "{:#x} {} ({})",
+            object.object_id(),
+            snapshot.object_label(object),
+            units.amount(snapshot.shallow_size(object))
+        );
+        if let Some(s) = strings::as_string(snapshot, object) {
+            println!("  {}", quote(&s, usize::MAX));
+        }
+        match object {
+            HeapObject::Class(c) => {
+                println!(
+                    "  super: {}",
+                    describe_value(snapshot, heap::Value::Object(c.super_class_id))
+                );
+                println!(
+                    "  loader: {}",
+                    describe_value(snapshot, heap::Value::Object(c.class_loader_id))
+                );
+                println!("  instance size: {}", c.instance_size);
+                for f in c.static_fields() {
+                    println!(
+                        "  static {} {} = {}",
+                        f.tag.java_name(),
+                        snapshot.string(f.name_id),
+                        describe_value(snapshot, f.value)
+                    );
+                }
+            }
+            HeapObject::Instance(i) => {
+                //
+                // Fields are grouped by declaring class, from the class of
+                // the object up its superclass chain.
+                //
+                let mut declaring_class = 0;
+                for f in snapshot.instance_fields(i) {
+                    if f.class_id != declaring_class {
+                        declaring_class = f.class_id;
+                        println!("  {}:", snapshot.class_name(declaring_class));
+                    }
+                    println!(
+                        "    {} {} = {}",
+                        f.tag.java_name(),
+                        snapshot.string(f.name_id),
+                        describe_value(snapshot, f.value)
+                    );
+                }
+            }
+            HeapObject::ObjectArray(a) => {
+                println!("  length: {}", a.elements.len());
+                for (n, &element) in a.elements.iter().enumerate().take(limit) {
+                    println!(
+                        "    [{}] = {}",
+                        n,
+                        describe_value(snapshot, heap::Value::Object(element))
+                    );
+                }
+                if a.elements.len() > limit {
+                    println!("    ...");
+                }
+            }
+            HeapObject::PrimitiveArray(a) => {
+                println!("  length: {}", a.length);
+                let size = a.element_tag.size(snapshot.id_size()) as usize;
+                for (n, mut chunk) in a.data.chunks(size).enumerate().take(limit) {
+                    let value = heap::read_value(&mut chunk, a.element_tag, snapshot.id_size());
+                    println!("    [{}] = {}", n, value);
+                }
+                if a.length as usize > limit {
+                    println!("    ...");
+                }
+            }
+        }
+        if let Some(decoded) = decoders.decode(snapshot, object, limit + 1) {
+            print_decoded(snapshot, &decoded, limit);
+        }
+        println!();
+    }
+}
+
+fn print_decoded(snapshot: &Snapshot, decoded: &Decoded, limit: usize) {
+    if let Some(summary) = &decoded.summary {
+        println!("  {}", summary);
+    }
+    if !decoded.fields.is_empty() {
+        println!("  logical fields:");
+        for (name, value) in &decoded.fields {
+            println!("    {} = {}", name, describe_value(snapshot, *value));
+        }
+    }
+    for (name, size) in &decoded.sizes {
+        println!("  {}: {}", name, size);
+    }
+    let object = |id| describe_value(snapshot, heap::Value::Object(id));
+    let contents = match &decoded.contents {
+        Some(contents) => contents,
+        None => return,
+    };
+    let more = match contents {
+        Contents::Entries(entries) => {
+            println!("  entries:");
+            for &(key, value) in entries.iter().take(limit) {
+                println!("    {} => {}", object(key), object(value));
+            }
+            entries.len() > limit
+        }
+        Contents::Elements(elements) => {
+            println!("  elements:");
+            for (n, &element) in elements.iter().enumerate().take(limit) {
+                println!("    [{}] {}", n, object(element));
+            }
+            elements.len() > limit
+        }
+        Contents::Text(text) => {
+            println!("  text: {}", quote(text, usize::MAX));
+            false
+        }
+    };
+    if more {
+        println!("    ...");
+    }
+}
+
+//
+// The bytes reachable from objects, stopping at the classes given with
+// --stop (and their subclasses) and at --depth references from the object.
+//
+pub fn print_deep_size(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let max_depth = args.number("--depth", u64::MAX) as usize;
+    let mut stop_classes = HashSet::new();
+    for name in args.value("--stop").into_iter().flat_map(|v| v.split(',')) {
+        let classes = snapshot.subclasses(name);
+        if classes.is_empty() {
+            cli::die(&format!("no class named {}", name));
+        }
+        stop_classes.extend(classes);
+    }
+
+    println!(
+        "{:>18} {:>10} {:>14} {:>10}  Class",
+        "Object", "Objects", "Bytes", "Stopped"
+    );
+    for arg in &args.positional {
+        let index = object_index(snapshot, arg);
+        let object = &snapshot.objects[index as usize];
+        let size = reachability::deep_size(snapshot, index, max_depth, &stop_classes);
+        println!(
+            "{:>#18x} {:>10} {:>14} {:>10}  {}{}",
+            object.object_id(),
+            size.objects,
+            units.bytes(size.bytes),
+            size.stopped,
+            snapshot.object_label(object),
+            if size.truncated {
+                " (depth limit reached)"
+            } else {
+                ""
+            }
+        );
+    }
+}
+
+fn show_field(value: &FieldValue) -> String {
+    match value {
+        FieldValue::Int(v) => v.to_string(),
+        FieldValue::Float(v) => v.to_string(),
+        FieldValue::Str(v) => quote(v, 60),
+        FieldValue::Bool(v) => v.to_string(),
+        FieldValue::Null => "null".to_string(),
+        FieldValue::Object(id) => format!("{:#x}", id),
+    }
+}
+
+//
+// The instances of a class (and its subclasses) whose fields satisfy the
+// --where predicate, with the values of the fields it looks at.
+//
+pub fn print_find(snapshot: &Snapshot, args: &Args) {
+    let class_name = match args.value("--class") {
+        Some(name) => name,
+        None => cli::die("find requires --class"),
+    };
+    let predicate = args
+        .value("--where")
+        .map(|text| Predicate::parse(text).unwrap_or_else(|e| cli::die(&e)));
+    let limit = args.number("--limit", 100) as usize;
+    let classes = snapshot.subclasses(class_name);
+    if classes.is_empty() {
+        cli::die(&format!("no class named {}", class_name));
+    }
+
+    let mut found = 0;
+    for object in &snapshot.objects {
+        let instance = match object {
+            HeapObject::Instance(i) if classes.contains(&i.class_id) => i,
+            _ => continue,
+        };
+        if !predicate
+            .as_ref()
+            .is_none_or(|p| p.matches(snapshot, instance))
+        {
+            continue;
+        }
+        found += 1;
+        if found > limit {
+            continue;
+        }
+        let fields: Vec<String> = predicate
+            .iter()
+            .flat_map(|p| p.paths())
+            .map(|path| {
+                let value = predicate::resolve(snapshot, instance, path);
+                format!(
+                    "{}={}",
+                    path.join("."),
+                    value.as_ref().map_or("?".to_string(), show_field)
+                )
+            })
+            .collect();
+        println!(
+            "{:#x} {}  {}",
+            object.object_id(),
+            snapshot.object_label(object),
+            fields.join(" ")
+        );
+    }
+    println!("{} matching instances", found);
+}
+
+pub fn print_json(snapshot: &Snapshot, args: &Args) {
+    let depth = args.number("--depth", 3) as usize;
+    let width = args.number("--width", 10) as usize;
+    let decoders = Decoders::builtin();
+    if args.positional.is_empty() {
+        cli::die("json requires an object id");
+    }
+    for arg in &args.positional {
+        let object = &snapshot.objects[object_index(snapshot, arg) as usize];
+        println!(
+            "{}",
+            json::object_json(snapshot, &decoders, object, depth, width).pretty()
+        );
+    }
+}
@@ -0,0 +1,804 @@
+//
+// Commands about what keeps objects alive: retained sizes, dominators,
+// reachability and paths to the GC roots.
+//
+use super::{object_index, print_hops, retention_filter, summarize, supertype_groups};
+use crate::cli::{self, Args};
+use hprof_cat::collections::CollectionKind;
+use hprof_cat::graph::{self, Graph};
+use hprof_cat::reference::{self, ReferenceKind, RetentionFilter};
+use hprof_cat::snapshot::Snapshot;
+use hprof_cat::units::Units;
+use hprof_cat::{
+    cache, collections, cycles, diff, histogram, paths, reachability, retained, sampling,
+};
+
+use std::collections::HashMap;
+
+pub fn print_retained(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let names = args.names();
+    let graph = Graph::build_filtered(snapshot, &retention_filter(snapshot, args));
+    let (tree, retained) = cache::retained_sizes(snapshot, &graph);
+
+    if !args.positional.is_empty() {
+        println!(
+            "{:>18} {:>14} {:>14}  Class",
+            "Object", "Shallow", "Retained"
+        );
+        for arg in &args.positional {
+            let index = object_index(snapshot, arg);
+            let object = &snapshot.objects[index as usize];
+            let id = object.object_id();
+            println!(
+                "{:>#18x} {:>14} {:>14}  {}",
+                id,
+                units.bytes(snapshot.shallow_size(object)),
+                units.bytes(retained[index as usize]),
+                names.class(&snapshot.object_class_name(object))
+            );
+        }
+        return;
+    }
+
+    let top = args.number("--top", 25) as usize;
+    println!(
+        "{:>12} {:>14} {:>14}  Class",
+        "Instances", "Shallow", "Retained"
+    );
+    let groups = supertype_groups(snapshot, args);
+    for class in retained::retained_by(snapshot, &tree, &retained, |o| groups.name(snapshot, o))
+        .iter()
+        .take(top)
+    {
+        println!(
+            "{:>12} {:>14} {:>14}  {}",
+            class.instances,
+            units.bytes(class.shallow),
+            units.bytes(class.retained),
+            names.class(&class.name)
+        );
+    }
+}
+
+//
+// Instances, shallow and retained bytes per package, so that memory can be
+// attributed to the code (and teams) owning it rather than to classes.
+//
+pub fn print_packages(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let graph = Graph::build_filtered(snapshot, &retention_filter(snapshot, args));
+    let (tree, retained) = cache::retained_sizes(snapshot, &graph);
+    let depth = args.number("--depth", 0) as usize;
+    let top = args.number("--top", 25) as usize;
+
+    println!(
+        "{:>12} {:>14} {:>14}  Package",
+        "Instances", "Shallow", "Retained"
+    );
+    for package in retained::retained_by_package(snapshot, &tree, &retained, depth)
+        .iter()
+        .take(top)
+    {
+        println!(
+            "{:>12} {:>14} {:>14}  {}",
+            package.instances,
+            units.bytes(package.shallow),
+            units.bytes(package.retained),
+            package.name
+        );
+    }
+}
+
+//
+// The individual objects with the largest retained sizes, with a summary
+// of their contents and their immediate dominator.
+//
+pub fn print_top_objects(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let top = args.number("--top", 25) as usize;
+    let graph = Graph::build_filtered(snapshot, &retention_filter(snapshot, args));
+    let (tree, retained) = cache::retained_sizes(snapshot, &graph);
+    let kinds = collections::collection_classes(snapshot);
+
+    let mut nodes = tree.order.clone();
+    nodes.sort_by_key(|&n| std::cmp::Reverse(retained[n as usize]));
+    println!(
+        "{:>18} {:>14} {:>14}  Object",
+        "Object", "Retained", "Shallow"
+    );
+    for &node in nodes.iter().take(top) {
+        let object = &snapshot.objects[node as usize];
+        let summary = summarize(snapshot, &kinds, node);
+        println!(
+            "{:>#18x} {:>14} {:>14}  {}{}{}",
+            object.object_id(),
+            units.bytes(retained[node as usize]),
+            units.bytes(snapshot.shallow_size(object)),
+            snapshot.object_label(object),
+            if summary.is_empty() { "" } else { " " },
+            summary
+        );
+        let idom = tree.idom[node as usize];
+        if idom == tree.virtual_root() {
+            println!("    dominated by the GC roots");
+        } else {
+            let dominator = &snapshot.objects[idom as usize];
+            println!(
+                "    dominated by {:#x} {}",
+                dominator.object_id(),
+                snapshot.object_label(dominator)
+            );
+        }
+    }
+}
+
+//
+// Retained bytes per kind of GC root: static state (sticky classes), thread
+// stacks, JNI references, monitors...
+//
+pub fn print_root_retained(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let graph = Graph::build_filtered(snapshot, &retention_filter(snapshot, args));
+    let (tree, retained) = cache::retained_sizes(snapshot, &graph);
+
+    println!("{:>10} {:>14}  Root kind", "Objects", "Retained");
+    for r in retained::retained_by_root_kind(snapshot, &tree, &retained) {
+        println!(
+            "{:>10} {:>14}  {}",
+            r.objects,
+            units.bytes(r.retained),
+            r.kind.name()
+        );
+    }
+    // The rest is kept alive by roots of several kinds together.
+    let reachable: u64 = tree
+        .order
+        .iter()
+        .map(|&n| snapshot.shallow_size(&snapshot.objects[n as usize]))
+        .sum();
+    println!("{:>10} {:>14}  total reachable", "", units.bytes(reachable));
+}
+
+//
+// Answers "who owns this": the chain of dominators from an object up to
+// the GC roots, each of which would free the object if it were collected.
+//
+pub fn print_dominators(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let graph = Graph::build_filtered(snapshot, &retention_filter(snapshot, args));
+    let (tree, retained) = cache::retained_sizes(snapshot, &graph);
+
+    for arg in &args.positional {
+        let index = object_index(snapshot, arg);
+        let id = snapshot.objects[index as usize].object_id();
+        if !tree.is_reachable(index) {
+            println!("{:#x} is unreachable", id);
+            continue;
+        }
+        println!("{:>18} {:>14}  Class", "Object", "Retained");
+        for node in std::iter::once(index).chain(tree.dominators(index)) {
+            let object = &snapshot.objects[node as usize];
+            println!(
+                "{:>#18x} {:>14}  {}",
+                object.object_id(),
+                units.bytes(retained[node as usize]),
+                snapshot.object_class_name(object)
+            );
+        }
+        println!("{:>18}", "<GC roots>");
+        println!();
+    }
+}
+
+//
+// The histogram of the garbage in the dump, with the live bytes of each
+// class for comparison: classes that are mostly garbage are churning
+// rather than leaking.
+//
+pub fn print_unreachable(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let names = args.names();
+    let graph = Graph::build_filtered(snapshot, &retention_filter(snapshot, args));
+    let marked = reachability::mark(&graph);
+
+    let mut totals = [(0u64, 0u64); 2];
+    for (i, object) in snapshot.objects.iter().enumerate() {
+        let total = &mut totals[marked[i] as usize];
+        total.0 += 1;
+        total.1 += snapshot.shallow_size(object);
+    }
+    println!(
+        "reachable:   {} objects, {}",
+        totals[1].0,
+        units.amount(totals[1].1)
+    );
+    println!(
+        "unreachable: {} objects, {}",
+        totals[0].0,
+        units.amount(totals[0].1)
+    );
+    if totals[0].1 > totals[1].1 {
+        println!("Most of the heap is garbage that the next GC would free.");
+    }
+    println!();
+
+    let top = args.number("--top", 25) as usize;
+    let groups = supertype_groups(snapshot, args);
+    let live: HashMap<String, u64> = histogram::histogram_by(
+        snapshot,
+        |i| marked[i as usize],
+        |o| groups.name(snapshot, o),
+    )
+    .into_iter()
+    .map(|e| (e.class_name, e.shallow))
+    .collect();
+    println!(
+        "{:>12} {:>14} {:>14} {:>9}  Class",
+        "Instances", "Shallow", "Live shallow", "Garbage"
+    );
+    for entry in histogram::histogram_by(
+        snapshot,
+        |i| !marked[i as usize],
+        |o| groups.name(snapshot, o),
+    )
+    .iter()
+    .take(top)
+    {
+        let live = live.get(&entry.class_name).copied().unwrap_or(0);
+        println!(
+            "{:>12} {:>14} {:>14} {:>8.1}%  {}",
+            entry.instances,
+            units.bytes(entry.shallow),
+            units.bytes(live),
+            100.0 * entry.shallow as f64 / (entry.shallow + live) as f64,
+            names.class(&entry.class_name)
+        );
+    }
+}
+
+pub fn print_path(snapshot: &Snapshot, args: &Args) {
+    let graph = Graph::build(snapshot);
+    let preds = cache::predecessors(snapshot, &graph);
+    let filter = retention_filter(snapshot, args);
+
+    for arg in &args.positional {
+        let index = object_index(snapshot, arg);
+        let id = snapshot.objects[index as usize].object_id();
+        let path = match paths::shortest_path_to_root(snapshot, &graph, &preds, &filter, index) {
+            Some(path) => path,
+            None => {
+                println!("{:#x}: no path to a GC root", id);
+                continue;
+            }
+        };
+
+        print_hops(snapshot, &path);
+        println!();
+    }
+}
+
+pub fn print_incoming(snapshot: &Snapshot, args: &Args) {
+    let graph = Graph::build(snapshot);
+    let preds = cache::predecessors(snapshot, &graph);
+
+    for arg in &args.positional {
+        let index = object_index(snapshot, arg);
+        let object = &snapshot.objects[index as usize];
+        let incoming = graph::incoming_references(snapshot, &preds, index);
+        println!(
+            "{:#x} {} is referenced by {} objects:",
+            object.object_id(),
+            snapshot.object_label(object),
+            preds.degree(index)
+        );
+        for (referrer, via) in incoming {
+            let referrer = &snapshot.objects[referrer as usize];
+            println!(
+                "  {:>#18x}  {:<24}  {}",
+                referrer.object_id(),
+                graph::via_name(snapshot, via),
+                snapshot.object_label(referrer)
+            );
+        }
+        println!();
+    }
+}
+
+fn print_merged_node(
+    snapshot: &Snapshot,
+    filter: &RetentionFilter,
+    tree: &[paths::MergedNode],
+    current: usize,
+    depth: usize,
+    args: &Args,
+) {
+    let units = args.units();
+    let max_depth = args.number("--depth", 12) as usize;
+    let width = args.number("--width", 5) as usize;
+    let node = &tree[current];
+    for &child in node.children.iter().take(width) {
+        let child_node = &tree[child];
+        let object = &snapshot.objects[child_node.node as usize];
+        let via = if current == 0 {
+            String::new()
+        } else {
+            match paths::retaining_via(snapshot, filter, node.node, child_node.node) {
+                Some(via) => format!("{} -> ", graph::via_name(snapshot, via)),
+                None => String::new(),
+            }
+        };
+        println!(
+            "{:>10} {:>14}  {}{}{:#x} {}",
+            child_node.objects,
+            units.bytes(child_node.bytes),
+            "  ".repeat(depth),
+            via,
+            object.object_id(),
+            snapshot.object_label(object)
+        );
+        if depth + 1 < max_depth {
+            print_merged_node(snapshot, filter, tree, child, depth + 1, args);
+        }
+    }
+    if node.children.len() > width {
+        let rest = &node.children[width..];
+        println!(
+            "{:>10} {:>14}  {}... {} more",
+            rest.iter().map(|&c| tree[c].objects).sum::<u64>(),
+            units.bytes(rest.iter().map(|&c| tree[c].bytes).sum::<u64>()),
+            "  ".repeat(depth),
+            rest.len()
+        );
+    }
+}
+
+//
+// Merges the shortest paths to the GC roots of all the instances of a
+// class (or of the given objects) into one tree, printed from the roots
+// down with the number of objects and bytes reached through each node.
+//
+pub fn print_merged_paths(snapshot: &Snapshot, args: &Args) {
+    let targets: Vec<u32> = match args.value("--class") {
+        Some(name) => snapshot.objects_of_class(name),
+        None => args
+            .positional
+            .iter()
+            .map(|arg| object_index(snapshot, arg))
+            .collect(),
+    };
+    if targets.is_empty() {
+        cli::die("no objects given");
+    }
+
+    let graph = Graph::build(snapshot);
+    let filter = retention_filter(snapshot, args);
+    let parents = paths::bfs_parents(snapshot, &graph, &filter);
+    let tree = paths::merge_paths(snapshot, &parents, &targets);
+
+    println!(
+        "{} of {} objects have a path to a GC root",
+        tree[0].objects,
+        targets.len()
+    );
+    println!("{:>10} {:>14}  Path", "Objects", "Bytes");
+    print_merged_node(snapshot, &filter, &tree, 0, 0, args);
+}
+
+//
+// The dominator tree below an object (or the whole heap) as an indented
+// tree, largest retained sizes first, to drill down from the heap to the
+// objects and fields responsible for its size.
+//
+pub fn print_dominator_tree(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let filter = retention_filter(snapshot, args);
+    let graph = Graph::build_filtered(snapshot, &filter);
+    let (tree, retained) = cache::retained_sizes(snapshot, &graph);
+    let children = retained::dominator_children(&tree);
+    let kinds = collections::collection_classes(snapshot);
+    let browser = TreeBrowser {
+        snapshot,
+        filter: &filter,
+        children: &children,
+        retained: &retained,
+        kinds: &kinds,
+        max_depth: args.number("--depth", 3) as usize,
+        width: args.number("--width", 10) as usize,
+        units,
+    };
+
+    println!("{:>14} {:>12}  Object", "Retained", "Shallow");
+    match args.positional.first() {
+        Some(arg) => {
+            let node = object_index(snapshot, arg);
+            if !tree.is_reachable(node) {
+                cli::die(&format!("{} is unreachable", arg));
+            }
+            browser.print(None, node, 0);
+        }
+        None => {
+            let total: u64 = children[tree.virtual_root() as usize]
+                .iter()
+                .map(|&c| retained[c as usize])
+                .sum();
+            println!("{:>14} {:>12}  <heap>", units.bytes(total), "");
+            browser.print_children(tree.virtual_root(), 1);
+        }
+    }
+}
+
+struct TreeBrowser<'a> {
+    snapshot: &'a Snapshot,
+    filter: &'a RetentionFilter,
+    children: &'a [Vec<u32>],
+    retained: &'a [u64],
+    kinds: &'a HashMap<u64, CollectionKind>,
+    max_depth: usize,
+    width: usize,
+    units: Units,
+}
+
+impl<'a> TreeBrowser<'a> {
+    fn print(&self, parent: Option<u32>, node: u32, depth: usize) {
+        let snapshot = self.snapshot;
+        let object = &snapshot.objects[node as usize];
+        // Dominated objects aren't necessarily referenced by their dominator.
+        let via = parent
+            .and_then(|p| paths::retaining_via(snapshot, self.filter, p, node))
+            .map(|via| format!("{} -> ", graph::via_name(snapshot, via)))
+            .unwrap_or_default();
+        let summary = summarize(snapshot, self.kinds, node);
+        println!(
+            "{:>14} {:>12}  {}{}{:#x} {}{}{}",
+            self.units.bytes(self.retained[node as usize]),
+            self.units.bytes(snapshot.shallow_size(object)),
+            "  ".repeat(depth),
+            via,
+            object.object_id(),
+            snapshot.object_label(object),
+            if summary.is_empty() { "" } else { " " },
+            summary
+        );
+        if depth < self.max_depth {
+            self.print_children(node, depth + 1);
+        }
+    }
+
+    fn print_children(&self, node: u32, depth: usize) {
+        let sorted = retained::children_by_retained(self.children, self.retained, node);
+        let parent = if (node as usize) < self.snapshot.objects.len() {
+            Some(node)
+        } else {
+            None
+        };
+        for &child in sorted.iter().take(self.width) {
+            self.print(parent, child, depth);
+        }
+        if sorted.len() > self.width {
+            let rest = &sorted[self.width..];
+            println!(
+                "{:>14} {:>12}  {}... {} more",
+                self.units
+                    .bytes(rest.iter().map(|&c| self.retained[c as usize]).sum::<u64>()),
+                "",
+                "  ".repeat(depth),
+                rest.len()
+            );
+        }
+    }
+}
+
+//
+// Compares the dominator tree of the dump with that of an earlier one,
+// matching nodes by the shape of their dominator path (see diff.rs), to
+// find the subtrees that grew.
+//
+pub fn print_dominator_diff(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let prepared = match args.positional.first() {
+        Some(path) => cli::prepare(path),
+        None => cli::die("dominator-diff: no earlier dump given"),
+    };
+    let mut other =
+        Snapshot::load_window(&prepared.spec, &args.window()).unwrap_or_else(|e| cli::die(&e));
+    other.set_size_model(snapshot.size_model);
+    let max_depth = args.number("--depth", 4) as usize;
+    let top = args.number("--top", 25) as usize;
+    let keys = |snapshot: &Snapshot| {
+        let graph = Graph::build_filtered(snapshot, &retention_filter(snapshot, args));
+        let (tree, retained) = cache::retained_sizes(snapshot, &graph);
+        diff::dominator_keys(snapshot, &tree, &retained, max_depth)
+    };
+    let deltas = diff::diff_keys(&keys(&other), &keys(snapshot));
+
+    println!(
+        "{:>14} {:>14} {:>14} {:>9}  Dominator path",
+        "Before", "After", "Growth", "Objects"
+    );
+    for d in deltas.iter().filter(|d| d.growth() != 0).take(top) {
+        println!(
+            "{:>14} {:>14} {:>14} {:>9}  {}",
+            units.bytes(d.before.retained),
+            units.bytes(d.after.retained),
+            units.signed_bytes(d.growth()),
+            format!("{}->{}", d.before.objects, d.after.objects),
+            d.key
+        );
+    }
+}
+
+//
+// Small reference cycles between live objects grouped by the classes and
+// fields involved, e.g. listeners pointing back to what they listen to.
+//
+pub fn print_cycles(snapshot: &Snapshot, args: &Args) {
+    let top = args.number("--top", 25) as usize;
+    let max_length = args.number("--max-length", 6) as usize;
+    let live = reachability::mark(&Graph::build(snapshot));
+    let edges = cycles::object_edges(snapshot, &live);
+    let components = cycles::strongly_connected_components(&edges);
+    let shapes = cycles::cycle_shapes(snapshot, &edges, &components, max_length, 1000);
+
+    println!(
+        "{} objects in {} strongly connected components, {} cycle shapes up to {} references long",
+        components.iter().map(|c| c.len()).sum::<usize>(),
+        components.len(),
+        shapes.len(),
+        max_length
+    );
+    println!();
+    println!("{:>8}  Shape", "Cycles");
+    for shape in shapes.iter().take(top) {
+        let example: Vec<String> = shape
+            .example
+            .iter()
+            .map(|&n| format!("{:#x}", snapshot.objects[n as usize].object_id()))
+            .collect();
+        println!("{:>8}  {}", shape.cycles, shape.shape);
+        println!("{:>8}  e.g. {}", "", example.join(" -> "));
+    }
+}
+
+//
+// The largest strongly connected components of live objects with the
+// classes making them up.
+//
+pub fn print_components(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let top = args.number("--top", 10) as usize;
+    let live = reachability::mark(&Graph::build(snapshot));
+    let edges = cycles::object_edges(snapshot, &live);
+    let components = cycles::strongly_connected_components(&edges);
+    let summaries = cycles::summarize_components(snapshot, &components);
+
+    println!("{} strongly connected components", summaries.len());
+    for (n, summary) in summaries.iter().enumerate().take(top) {
+        let first = &snapshot.objects[summary.first as usize];
+        println!();
+        println!(
+            "component {}: {} objects, {}, including {:#x} {}",
+            n + 1,
+            summary.objects,
+            units.amount(summary.bytes),
+            first.object_id(),
+            snapshot.object_label(first)
+        );
+        for class in summary.classes.iter().take(8) {
+            println!(
+                "  {:>10} {:>14}  {}",
+                class.instances,
+                units.bytes(class.shallow),
+                class.class_name
+            );
+        }
+        if summary.classes.len() > 8 {
+            println!("  ... {} more classes", summary.classes.len() - 8);
+        }
+    }
+}
+
+//
+// What keeps the instances of a class alive: their immediate dominators
+// grouped by class and field.
+//
+pub fn print_retainers(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let name = match args.positional.first() {
+        Some(name) => name,
+        None => cli::die("retainers: no class given"),
+    };
+    let top = args.number("--top", 25) as usize;
+    let nodes = snapshot.objects_of_class(name);
+    if nodes.is_empty() {
+        cli::die(&format!("no instances of {}", name));
+    }
+    let graph = Graph::build_filtered(snapshot, &retention_filter(snapshot, args));
+    let (tree, retained) = cache::retained_sizes(snapshot, &graph);
+    let retainers = retained::retainers_of(snapshot, &tree, &retained, &nodes);
+
+    println!("{} instances of {}", nodes.len(), name);
+    println!("{:>12} {:>14}  Dominator", "Instances", "Retained");
+    for r in retainers.iter().take(top) {
+        println!(
+            "{:>12} {:>14}  {}{}",
+            r.instances,
+            units.bytes(r.retained),
+            r.dominator,
+            r.via
+                .as_ref()
+                .map(|v| format!(" ({})", v))
+                .unwrap_or_default()
+        );
+    }
+}
+
+//
+// What each static field keeps alive. Objects also reachable some other
+// way are marked as shared: clearing the field alone wouldn't free them.
+//
+pub fn print_statics(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let top = args.number("--top", 25) as usize;
+    let graph = Graph::build_filtered(snapshot, &retention_filter(snapshot, args));
+    let (tree, retained) = cache::retained_sizes(snapshot, &graph);
+    let kinds = collections::collection_classes(snapshot);
+
+    println!("{:>14}  {:<6}  Field", "Retained", "");
+    for f in retained::static_field_retained(snapshot, &tree, &retained)
+        .iter()
+        .take(top)
+    {
+        let target = &snapshot.objects[f.target as usize];
+        let summary = summarize(snapshot, &kinds, f.target);
+        println!(
+            "{:>14}  {:<6}  {}.{} -> {:#x} {}{}{}",
+            units.bytes(f.retained),
+            if f.exclusive { "" } else { "shared" },
+            snapshot.class_name(f.class_id),
+            snapshot.string(f.name_id),
+            target.object_id(),
+            snapshot.object_label(target),
+            if summary.is_empty() { "" } else { " " },
+            summary
+        );
+    }
+}
+
+//
+// The java.lang.ref references of the heap and how much of it would be
+// freed if each kind of reference stopped keeping its referents alive.
+//
+pub fn print_references(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let top = args.number("--top", 25) as usize;
+    let classes = reference::reference_instances(snapshot);
+    println!(
+        "{:>10} {:>14} {:>12}  {:<8} Class",
+        "Instances", "With referent", "Shallow", "Kind"
+    );
+    for c in classes.iter().take(top) {
+        println!(
+            "{:>10} {:>14} {:>12}  {:<8} {}",
+            c.instances,
+            c.with_referent,
+            units.bytes(c.shallow),
+            c.kind.name(),
+            c.class_name
+        );
+    }
+
+    let reachable = reachability::mark(&Graph::build(snapshot));
+    println!();
+    println!("only reachable through:");
+    for kind in ReferenceKind::all() {
+        let (objects, bytes) = reference::only_reachable_through(snapshot, &reachable, vec![kind]);
+        println!(
+            "  {:<8} {:>10} objects {:>20}",
+            kind.name(),
+            objects,
+            units.amount(bytes)
+        );
+    }
+    let (objects, bytes) =
+        reference::only_reachable_through(snapshot, &reachable, ReferenceKind::all());
+    println!(
+        "  {:<8} {:>10} objects {:>20}",
+        "any",
+        objects,
+        units.amount(bytes)
+    );
+}
+
+//
+// Retained size per class estimated from samples of the live bytes rather
+// than computed from the dominator tree of the whole heap.
+//
+pub fn print_retained_estimate(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let samples = args.number("--samples", 1000).max(1);
+    let budget = args.number("--budget", 100_000) as usize;
+    let seed = args.number("--seed", 1);
+    let top = args.number("--top", 25) as usize;
+    let graph = Graph::build_filtered(snapshot, &retention_filter(snapshot, args));
+    let report = sampling::sampled_retained_by_class(snapshot, &graph, samples, budget, seed);
+    println!(
+        "ESTIMATES from {} samples of {} live, 95% confidence intervals",
+        report.samples,
+        units.amount(report.live_bytes)
+    );
+    if report.unresolved > 0 {
+        println!(
+            "{} samples had more than {} ancestors and are unresolved (--budget)",
+            report.unresolved, budget
+        );
+    }
+    println!(
+        "{:>14} {:>14} {:>14} {:>8}  Class",
+        "~Retained", "Low", "High", "Samples"
+    );
+    for c in report.classes.iter().take(top) {
+        println!(
+            "{:>14} {:>14} {:>14} {:>8}  {}",
+            units.bytes(c.bytes),
+            units.bytes(c.low),
+            units.bytes(c.high),
+            c.hits,
+            c.class_name
+        );
+    }
+}
+
+//
+// Who holds the instances of a class: their shortest paths to the GC roots
+// grouped by kind of root and by the class and field at the end of the
+// path, with the share of the instances and bytes of each group.
+//
+pub fn print_holders(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let name = match args.positional.first() {
+        Some(name) => name,
+        None => cli::die("holders: no class given"),
+    };
+    let top = args.number("--top", 25) as usize;
+    let nodes = snapshot.objects_of_class(name);
+    if nodes.is_empty() {
+        cli::die(&format!("no instances of {}", name));
+    }
+    let filter = retention_filter(snapshot, args);
+    let graph = Graph::build_filtered(snapshot, &filter);
+    let (tree, retained) = cache::retained_sizes(snapshot, &graph);
+    let parents = paths::bfs_parents(snapshot, &graph, &filter);
+    let groups = retained::root_path_groups(snapshot, &filter, &parents, &tree, &retained, &nodes);
+    let live: u64 = groups.iter().map(|g| g.instances).sum();
+    let total = retained::retained_by_set(&tree, &retained, &nodes);
+
+    println!(
+        "{} instances of {}, {} reachable retaining {}",
+        nodes.len(),
+        name,
+        live,
+        units.amount(total)
+    );
+    println!(
+        "{:>10} {:>6} {:>14} {:>6}  {:<24} Holder",
+        "Instances", "%", "Retained", "%", "Root"
+    );
+    let percent = |part: u64, whole: u64| 100.0 * part as f64 / whole.max(1) as f64;
+    for g in groups.iter().take(top) {
+        let holder = match (&g.holder, &g.via) {
+            (Some(holder), Some(via)) => format!("{}.{}", holder, via),
+            (Some(holder), None) => holder.clone(),
+            (None, _) => "<GC root>".to_string(),
+        };
+        println!(
+            "{:>10} {:>5.1}% {:>14} {:>5.1}%  {:<24} {}",
+            g.instances,
+            percent(g.instances, live),
+            units.bytes(g.retained),
+            percent(g.retained, total),
+            g.root_kinds,
+            holder
+        );
+    }
+}
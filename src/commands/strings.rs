@@ -0,0 +1,178 @@
+use super::{quote, retention_filter};
+use crate::cli::{self, Args};
+use hprof_cat::cache;
+use hprof_cat::graph::{self, Graph};
+use hprof_cat::paths;
+use hprof_cat::regex::Regex;
+use hprof_cat::secrets;
+use hprof_cat::snapshot::Snapshot;
+use hprof_cat::strings;
+
+pub fn print_duplicate_strings(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let top = args.number("--top", 25) as usize;
+    let duplicates = strings::duplicate_strings(snapshot);
+    println!(
+        "{} duplicated values, {} wasted",
+        duplicates.len(),
+        units.amount(duplicates.iter().map(|d| d.wasted).sum::<u64>())
+    );
+    println!(
+        "{:>10} {:>8} {:>14}  Value",
+        "Instances", "Arrays", "Wasted"
+    );
+    for d in duplicates.iter().take(top) {
+        println!(
+            "{:>10} {:>8} {:>14}  {}",
+            d.count,
+            d.arrays,
+            units.bytes(d.wasted),
+            quote(&d.value, 80)
+        );
+    }
+}
+
+//
+// Duplicated strings worth interning: those with a copy that is already
+// interned (held by a static field, e.g. a literal) and those repeated at
+// least --min-count times, along with the savings of interning them versus
+// letting the GC deduplicate their arrays (-XX:+UseStringDeduplication).
+//
+pub fn print_interning(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let top = args.number("--top", 25) as usize;
+    let min_count = args.number("--min-count", 10);
+    let candidates: Vec<_> = strings::duplicate_strings(snapshot)
+        .into_iter()
+        .filter(|d| d.interned || d.count >= min_count)
+        .collect();
+    println!(
+        "{} candidates: interning would save {}, deduplication {}",
+        candidates.len(),
+        units.amount(candidates.iter().map(|d| d.wasted).sum::<u64>()),
+        units.amount(candidates.iter().map(|d| d.dedup_savings).sum::<u64>())
+    );
+    println!(
+        "{:>10} {:>14} {:>14}  {:<9} Value",
+        "Instances", "Intern saves", "Dedup saves", "Interned"
+    );
+    for d in candidates.iter().take(top) {
+        println!(
+            "{:>10} {:>14} {:>14}  {:<9} {}",
+            d.count,
+            units.bytes(d.wasted),
+            units.bytes(d.dedup_savings),
+            if d.interned { "yes" } else { "no" },
+            quote(&d.value, 80)
+        );
+    }
+}
+
+//
+// Every match of the built-in secret patterns, with the shortest path that
+// keeps the containing object alive. Matches are masked unless --reveal.
+//
+pub fn print_secrets(snapshot: &Snapshot, args: &Args) {
+    let findings = secrets::scan(snapshot, &secrets::builtin_patterns());
+    let graph = Graph::build(snapshot);
+    let filter = retention_filter(snapshot, args);
+    let parents = paths::bfs_parents(snapshot, &graph, &filter);
+
+    println!("{} possible secrets", findings.len());
+    for f in &findings {
+        let object = &snapshot.objects[f.node as usize];
+        let text = if args.flag("--reveal") {
+            f.text.clone()
+        } else {
+            secrets::mask(&f.text)
+        };
+        println!(
+            "{} in {} {:#x}: {}",
+            f.pattern,
+            f.source.name(),
+            object.object_id(),
+            quote(&text, 80)
+        );
+        match paths::path_from_parents(snapshot, &filter, &parents, f.node) {
+            Some(path) => println!("    {}", paths::render_chain(snapshot, &path)),
+            None => println!("    unreachable"),
+        }
+    }
+}
+
+//
+// Matches of a regex in the text of the heap. Arrays that aren't the value
+// of a String are listed with the objects that refer to them, since that
+// is usually what tells a buffer apart from a StringBuilder and so on.
+//
+pub fn print_search(snapshot: &Snapshot, args: &Args) {
+    let pattern = match args.positional.first() {
+        Some(pattern) => pattern,
+        None => cli::die("search requires a pattern"),
+    };
+    let regex = Regex::new(pattern).unwrap_or_else(|e| cli::die(&e));
+    let limit = args.number("--limit", 100) as usize;
+    let matches = strings::search(snapshot, &regex);
+    let graph = Graph::build(snapshot);
+    let preds = cache::predecessors(snapshot, &graph);
+
+    println!("{} matches", matches.len());
+    for m in matches.iter().take(limit) {
+        let object = &snapshot.objects[m.node as usize];
+        println!(
+            "{} {:#x} @{}: {}",
+            m.source.name(),
+            object.object_id(),
+            m.offset,
+            quote(&format!("{}{}{}", m.before, m.text, m.after), 80)
+        );
+        if m.source == strings::TextSource::String {
+            continue;
+        }
+        for (referrer, via) in graph::incoming_references(snapshot, &preds, m.node) {
+            let referrer = &snapshot.objects[referrer as usize];
+            println!(
+                "    <- {} of {:#x} {}",
+                graph::qualified_via_name(snapshot, via),
+                referrer.object_id(),
+                snapshot.object_label(referrer)
+            );
+        }
+    }
+}
+
+// Arrays that look like cryptographic keys or tokens because of their entropy.
+pub fn print_high_entropy(snapshot: &Snapshot, args: &Args) {
+    let top = args.number("--top", 25) as usize;
+    let findings = secrets::high_entropy_arrays(snapshot);
+    let graph = Graph::build(snapshot);
+    let preds = cache::predecessors(snapshot, &graph);
+    let filter = retention_filter(snapshot, args);
+    let parents = paths::bfs_parents(snapshot, &graph, &filter);
+
+    println!("{} high entropy arrays", findings.len());
+    for f in findings.iter().take(top) {
+        let object = &snapshot.objects[f.node as usize];
+        println!(
+            "{} {:#x} length {}: {:.2} of {:.2} bits",
+            f.source.name(),
+            object.object_id(),
+            f.length,
+            f.entropy,
+            f.max_entropy
+        );
+        for (referrer, via) in graph::incoming_references(snapshot, &preds, f.node) {
+            let referrer = &snapshot.objects[referrer as usize];
+            println!(
+                "    <- {} of {:#x} {}",
+                graph::qualified_via_name(snapshot, via),
+                referrer.object_id(),
+                snapshot.object_label(referrer)
+            );
+        }
+        match paths::path_from_parents(snapshot, &filter, &parents, f.node) {
+            Some(path) => println!("    {}", paths::render_chain(snapshot, &path)),
+            None => println!("    unreachable"),
+        }
+    }
+}
@@ -0,0 +1,583 @@
+use super::{describe_value, thread_selector};
+use crate::cli::{self, Args};
+use hprof_cat::graph::Graph;
+use hprof_cat::heap::Value;
+use hprof_cat::snapshot::Snapshot;
+use hprof_cat::threaddump::{self, DumpedThread, Lock, LockRole};
+use hprof_cat::threads::ThreadInfo;
+use hprof_cat::{cache, locks, retained, threads};
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+//
+// The jstack output or javacore of --thread-dump, with its threads tied to
+// those of the heap dump.
+//
+struct ThreadDump {
+    threads: Vec<DumpedThread>,
+    // The dumped thread of each heap thread, and the other way round, by
+    // position in the lists.
+    links: Vec<Option<usize>>,
+    heap: Vec<Option<usize>>,
+    holders: HashMap<u64, usize>,
+}
+
+impl ThreadDump {
+    fn load(snapshot: &Snapshot, all: &[ThreadInfo], args: &Args) -> Option<ThreadDump> {
+        let path = args.value("--thread-dump")?;
+        let text = std::fs::read(path).unwrap_or_else(|e| cli::die(&format!("{}: {}", path, e)));
+        let threads = threaddump::parse(&String::from_utf8_lossy(&text))
+            .unwrap_or_else(|e| cli::die(&format!("{}: {}", path, e)));
+        let links = threaddump::link(snapshot, all, &threads);
+        let mut heap = vec![None; threads.len()];
+        for (t, link) in links.iter().enumerate() {
+            if let Some(i) = *link {
+                heap[i] = Some(t);
+            }
+        }
+        let holders = threaddump::holders(&threads);
+        Some(ThreadDump {
+            threads,
+            links,
+            heap,
+            holders,
+        })
+    }
+
+    fn dumped(&self, t: usize) -> Option<&DumpedThread> {
+        self.links[t].map(|i| &self.threads[i])
+    }
+
+    // A dumped thread by its heap serial number and name if it has one.
+    fn thread_name(&self, all: &[ThreadInfo], i: usize) -> String {
+        match self.heap[i] {
+            Some(t) => format!("{} \"{}\"", all[t].serial, all[t].name),
+            None => format!("\"{}\" (not in the heap dump)", self.threads[i].name),
+        }
+    }
+
+    fn lock_name(&self, snapshot: &Snapshot, lock: &Lock) -> String {
+        match threaddump::lock_object(snapshot, lock) {
+            Some(_) => format!("{:#x} {}", lock.address, lock.class_name),
+            None => format!(
+                "{:#x} {} (not in the heap dump)",
+                lock.address, lock.class_name
+            ),
+        }
+    }
+}
+
+fn state_detail(thread: &DumpedThread) -> String {
+    let state = thread.state.map_or("?", |s| s.name());
+    match &thread.detail {
+        Some(detail) => format!("{} ({})", state, detail),
+        None => state.to_string(),
+    }
+}
+
+//
+// Every thread with its stack and, like the variables pane of a debugger,
+// the objects that each frame keeps alive through its local variables.
+// With --thread-dump, also the state and locks of the thread in a jstack
+// output or javacore taken at about the same time. --thread narrows them
+// down to those of a serial number or name.
+//
+// Threads with the same stack, the hundreds of a pool waiting for work,
+// are folded into one with their stack printed once, without the locals
+// which differ from one thread to the next. Not with --no-fold, nor with
+// --thread-dump, whose states and locks differ too.
+//
+pub fn print_threads(snapshot: &Snapshot, args: &Args) {
+    let selector = thread_selector(snapshot, args);
+    let selected =
+        |serial: Option<u32>, name: &str| selector.as_ref().is_none_or(|s| s.matches(serial, name));
+    let all = threads::threads(snapshot);
+    let dump = ThreadDump::load(snapshot, &all, args);
+    let fold = dump.is_none() && !args.flag("--no-fold");
+    let groups = fold_threads(snapshot, &all, |t| selected(Some(t.serial), &t.name), fold);
+    for group in &groups {
+        match group[..] {
+            [t] => print_thread(snapshot, &all, dump.as_ref(), t),
+            _ => print!("{}", folded_threads(snapshot, &all, group)),
+        }
+    }
+
+    let dump = match dump {
+        Some(dump) => dump,
+        None => return,
+    };
+    let missing: Vec<&DumpedThread> = (0..dump.threads.len())
+        .filter(|&i| dump.heap[i].is_none())
+        .map(|i| &dump.threads[i])
+        .filter(|thread| selected(None, &thread.name))
+        .collect();
+    if missing.is_empty() {
+        return;
+    }
+    println!(
+        "{} threads of the thread dump not in the heap dump",
+        missing.len()
+    );
+    for thread in missing {
+        println!(
+            "  \"{}\"{} {}",
+            thread.name,
+            thread.id.map(|id| format!(" #{}", id)).unwrap_or_default(),
+            state_detail(thread)
+        );
+    }
+}
+
+//
+// The frames of the stack of a thread, innermost first. Those of HotSpot
+// are recorded for each thread, so threads at the same place have frames
+// of different ids.
+//
+fn stack(snapshot: &Snapshot, thread: &ThreadInfo) -> Vec<String> {
+    let frame_ids = match snapshot.trace_index.get(&thread.strace_num) {
+        Some(&trace) => &snapshot.traces[trace].frame_ids[..],
+        None => &[],
+    };
+    frame_ids
+        .iter()
+        .map(|id| match snapshot.frames.get(id) {
+            Some(f) => threads::describe_frame(snapshot, f),
+            None => format!("<unknown frame {:#x}>", id),
+        })
+        .collect()
+}
+
+//
+// The selected threads (by position in `all`) in groups of the same stack
+// when folding, in the order of the first of each. Threads without a stack
+// have nothing to be folded by.
+//
+fn fold_threads<F>(
+    snapshot: &Snapshot,
+    all: &[ThreadInfo],
+    selected: F,
+    fold: bool,
+) -> Vec<Vec<usize>>
+where
+    F: Fn(&ThreadInfo) -> bool,
+{
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut by_stack: HashMap<Vec<String>, usize> = HashMap::new();
+    for (t, thread) in all.iter().enumerate() {
+        if !selected(thread) {
+            continue;
+        }
+        let stack = stack(snapshot, thread);
+        if fold && !stack.is_empty() {
+            if let Some(&group) = by_stack.get(&stack) {
+                groups[group].push(t);
+                continue;
+            }
+            by_stack.insert(stack, groups.len());
+        }
+        groups.push(vec![t]);
+    }
+    groups
+}
+
+fn print_thread(snapshot: &Snapshot, all: &[ThreadInfo], dump: Option<&ThreadDump>, t: usize) {
+    let thread = &all[t];
+    let object = &snapshot.objects[thread.node as usize];
+    println!(
+        "thread {} \"{}\" {:#x}",
+        thread.serial,
+        thread.name,
+        object.object_id()
+    );
+    if let Some(dump) = dump {
+        print_dumped_thread(snapshot, all, dump, t);
+    }
+    for frame in threads::stack_with_locals(snapshot, thread) {
+        match frame.frame {
+            Some(f) => println!("  at {}", threads::describe_frame(snapshot, f)),
+            None => println!("  <in no particular frame>"),
+        }
+        for &local in &frame.locals {
+            let id = snapshot.objects[local as usize].object_id();
+            println!(
+                "      local {}",
+                describe_value(snapshot, Value::Object(id))
+            );
+        }
+    }
+    println!();
+}
+
+// Threads of the same stack, by the names of the first and the last.
+fn folded_threads(snapshot: &Snapshot, all: &[ThreadInfo], group: &[usize]) -> String {
+    let (first, last) = (&all[group[0]], &all[group[group.len() - 1]]);
+    let mut text = String::new();
+    writeln!(
+        text,
+        "\u{d7} {} threads: {}..{}",
+        group.len(),
+        first.name,
+        last.name
+    )
+    .unwrap();
+    for frame in stack(snapshot, first) {
+        writeln!(text, "  at {}", frame).unwrap();
+    }
+    writeln!(text).unwrap();
+    text
+}
+
+fn print_dumped_thread(snapshot: &Snapshot, all: &[ThreadInfo], dump: &ThreadDump, t: usize) {
+    let thread = match dump.dumped(t) {
+        Some(thread) => thread,
+        None => {
+            println!("  (not in the thread dump)");
+            return;
+        }
+    };
+    println!("  {} in the thread dump", state_detail(thread));
+    let mut seen = Vec::new();
+    for lock in &thread.locks {
+        let released = lock.role == LockRole::Holds && !threaddump::holds(thread, lock);
+        if released || seen.contains(&(lock.role, lock.address)) {
+            continue;
+        }
+        seen.push((lock.role, lock.address));
+        let holder = match dump.holders.get(&lock.address) {
+            Some(&i) if lock.role != LockRole::Holds => {
+                format!(", held by {}", dump.thread_name(all, i))
+            }
+            _ => String::new(),
+        };
+        println!(
+            "  {} {}{}",
+            lock.role.name(),
+            dump.lock_name(snapshot, lock),
+            holder
+        );
+    }
+}
+
+//
+// Memory attributed to each thread: the thread object, the objects its
+// frames refer to and everything dominated by them.
+//
+pub fn print_thread_retained(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let selector = thread_selector(snapshot, args);
+    let graph = Graph::build(snapshot);
+    let (tree, retained) = cache::retained_sizes(snapshot, &graph);
+
+    let mut rows = Vec::new();
+    for thread in threads::threads(snapshot) {
+        if selector
+            .as_ref()
+            .is_some_and(|s| !s.matches(Some(thread.serial), &thread.name))
+        {
+            continue;
+        }
+        let mut locals: Vec<u32> = threads::stack_with_locals(snapshot, &thread)
+            .iter()
+            .flat_map(|f| f.locals.iter().copied())
+            .filter(|&n| n != thread.node)
+            .collect();
+        locals.sort_unstable();
+        locals.dedup();
+        let mut nodes = locals.clone();
+        nodes.push(thread.node);
+        let total = retained::retained_by_set(&tree, &retained, &nodes);
+        rows.push((thread, locals.len(), total));
+    }
+    rows.sort_by_key(|r| std::cmp::Reverse(r.2));
+
+    println!(
+        "{:>8} {:>14} {:>8} {:>14}  Thread",
+        "Serial", "Thread", "Locals", "Retained"
+    );
+    for (thread, locals, total) in &rows {
+        println!(
+            "{:>8} {:>14} {:>8} {:>14}  {}",
+            thread.serial,
+            units.bytes(retained[thread.node as usize]),
+            locals,
+            units.bytes(*total),
+            thread.name
+        );
+    }
+}
+
+//
+// Threads counted by state and daemon flag, as far as the Thread objects
+// (or the thread dump of --thread-dump) tell, followed by the threads with
+// the deepest stacks (runaway recursion, deep framework call chains).
+//
+pub fn print_thread_states(snapshot: &Snapshot, args: &Args) {
+    let top = args.number("--top", 10) as usize;
+    let mut roots: HashMap<u32, u64> = HashMap::new();
+    for root in &snapshot.roots {
+        if let Some(serial) = root.thread_serial_num {
+            *roots.entry(serial).or_insert(0) += 1;
+        }
+    }
+
+    let mut rows = Vec::new();
+    let mut groups: BTreeMap<(Option<threads::ThreadState>, Option<bool>), (u64, u64)> =
+        BTreeMap::new();
+    let all = threads::threads(snapshot);
+    let dump = ThreadDump::load(snapshot, &all, args);
+    for (t, thread) in all.into_iter().enumerate() {
+        let dumped = dump.as_ref().and_then(|d| d.dumped(t));
+        let state = dumped
+            .and_then(|d| d.state)
+            .or_else(|| threads::thread_state(snapshot, thread.node));
+        let daemon = dumped
+            .and_then(|d| d.daemon)
+            .or_else(|| threads::is_daemon(snapshot, thread.node));
+        let depth = threads::stack_depth(snapshot, &thread);
+        let group = groups.entry((state, daemon)).or_insert((0, 0));
+        group.0 += 1;
+        group.1 += depth as u64;
+        rows.push((thread, state, daemon, depth));
+    }
+
+    let state_name = |state: Option<threads::ThreadState>| state.map_or("?", |s| s.name());
+    let daemon_name = |daemon: Option<bool>| match daemon {
+        Some(true) => "daemon",
+        Some(false) => "",
+        None => "?",
+    };
+    println!(
+        "{:<14} {:<7} {:>8} {:>10}",
+        "State", "Daemon", "Threads", "Frames"
+    );
+    for (&(state, daemon), &(count, frames)) in &groups {
+        println!(
+            "{:<14} {:<7} {:>8} {:>10}",
+            state_name(state),
+            daemon_name(daemon),
+            count,
+            frames
+        );
+    }
+
+    rows.sort_by(|a, b| b.3.cmp(&a.3).then_with(|| a.0.serial.cmp(&b.0.serial)));
+    println!();
+    println!("deepest stacks:");
+    println!(
+        "{:>8} {:>7} {:>6}  {:<14} {:<7} Thread",
+        "Serial", "Frames", "Roots", "State", "Daemon"
+    );
+    for (thread, state, daemon, depth) in rows.iter().take(top) {
+        println!(
+            "{:>8} {:>7} {:>6}  {:<14} {:<7} {}",
+            thread.serial,
+            depth,
+            roots.get(&thread.serial).copied().unwrap_or(0),
+            state_name(*state),
+            daemon_name(*daemon),
+            thread.name
+        );
+    }
+}
+
+//
+// Objects used as monitors, threads parked on java.util.concurrent locks
+// and conditions with the owners of the locks, and the deadlocks between
+// those owners. With --thread-dump, the locks of the thread dump too, with
+// the threads holding and waiting for them.
+//
+pub fn print_monitors(snapshot: &Snapshot, args: &Args) {
+    let all = threads::threads(snapshot);
+    let names: HashMap<u32, &str> = all.iter().map(|t| (t.serial, t.name.as_str())).collect();
+    let thread_name =
+        |serial: u32| format!("{} \"{}\"", serial, names.get(&serial).unwrap_or(&"?"));
+    let thread_list = |serials: &[u32]| {
+        serials
+            .iter()
+            .map(|&s| thread_name(s))
+            .collect::<Vec<String>>()
+            .join(", ")
+    };
+    let label = |node: u32| {
+        let object = &snapshot.objects[node as usize];
+        format!(
+            "{:#x} {}",
+            object.object_id(),
+            snapshot.object_label(object)
+        )
+    };
+
+    let monitors = locks::monitors(snapshot, &all);
+    println!("{} monitors", monitors.len());
+    for m in &monitors {
+        println!(
+            "  {}{}",
+            label(m.node),
+            if m.used { " (in use)" } else { "" }
+        );
+        if !m.blocked.is_empty() {
+            println!("      blocked: {}", thread_list(&m.blocked));
+        }
+        if !m.referenced_by.is_empty() {
+            println!("      referenced by: {}", thread_list(&m.referenced_by));
+        }
+    }
+
+    // HotSpot doesn't write MONITOR_USED roots, but the threads waiting to
+    // enter a synchronized block are still in the BLOCKED state.
+    let blocked: Vec<u32> = all
+        .iter()
+        .filter(|t| threads::thread_state(snapshot, t.node) == Some(threads::ThreadState::Blocked))
+        .map(|t| t.serial)
+        .collect();
+    if !blocked.is_empty() {
+        println!();
+        println!("{} threads blocked on monitor entry", blocked.len());
+        println!("  {}", thread_list(&blocked));
+    }
+
+    let parked = locks::parked_threads(snapshot, &all);
+    println!();
+    println!("{} parked threads", parked.len());
+    let mut waiters: BTreeMap<u32, Vec<&locks::ParkedThread>> = BTreeMap::new();
+    for p in &parked {
+        waiters.entry(p.blocker).or_default().push(p);
+    }
+    for (&blocker, waiting) in &waiters {
+        let owner = waiting[0].owner;
+        println!(
+            "  {}{}",
+            label(blocker),
+            owner
+                .map(|o| format!(" owned by {}", thread_name(o)))
+                .unwrap_or_default()
+        );
+        let serials: Vec<u32> = waiting.iter().map(|p| p.serial).collect();
+        println!("      waiting: {}", thread_list(&serials));
+    }
+
+    let deadlocks = locks::deadlocks(&parked);
+    if !deadlocks.is_empty() {
+        println!();
+        println!("{} deadlocks", deadlocks.len());
+        for cycle in &deadlocks {
+            for &serial in cycle {
+                let blocker = parked.iter().find(|p| p.serial == serial).unwrap().blocker;
+                println!("  {} waits for {}", thread_name(serial), label(blocker));
+            }
+            println!();
+        }
+    }
+
+    if let Some(dump) = ThreadDump::load(snapshot, &all, args) {
+        print_dumped_locks(snapshot, &all, &dump);
+    }
+}
+
+fn print_dumped_locks(snapshot: &Snapshot, all: &[ThreadInfo], dump: &ThreadDump) {
+    // The lock and the threads (by index) in each role, by address.
+    let mut locks = BTreeMap::new();
+    for (i, thread) in dump.threads.iter().enumerate() {
+        for lock in &thread.locks {
+            let holding = lock.role == LockRole::Holds;
+            if holding && dump.holders.get(&lock.address) != Some(&i) {
+                continue;
+            }
+            let entry = locks.entry(lock.address).or_insert((lock, Vec::new()));
+            if !entry.1.contains(&(lock.role, i)) {
+                entry.1.push((lock.role, i));
+            }
+        }
+    }
+    println!();
+    println!("{} locks in the thread dump", locks.len());
+    for (lock, threads) in locks.values() {
+        println!("  {}", dump.lock_name(snapshot, lock));
+        for role in [
+            LockRole::Holds,
+            LockRole::WaitingToLock,
+            LockRole::WaitingOn,
+            LockRole::Parked,
+        ] {
+            let names: Vec<String> = threads
+                .iter()
+                .filter(|r| r.0 == role)
+                .map(|r| dump.thread_name(all, r.1))
+                .collect();
+            if !names.is_empty() {
+                println!("      {}: {}", role.name(), names.join(", "));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hprof_cat::heap::{FieldTag, GcRootKind};
+    use hprof_cat::testing::Dump;
+
+    #[test]
+    fn threads_of_the_same_stack_are_folded() {
+        let mut dump = Dump::new();
+        let thread_class = dump.class(
+            "java/lang/Thread",
+            dump.object,
+            &[("name", FieldTag::NormalObject)],
+        );
+        let worker = dump.class("test/Worker", dump.object, &[]);
+        // Each thread has frames of its own, at the same place or not.
+        let thread = |dump: &mut Dump, serial: u32, name: &str, methods: &[&str]| {
+            let frames: Vec<u64> = methods
+                .iter()
+                .map(|m| dump.frame(worker, m, "()V", 7))
+                .collect();
+            let trace = dump.trace(serial, &frames);
+            let name = dump.string(name);
+            let thread = dump.instance(thread_class, &[Value::Object(name)]);
+            dump.root_at(
+                GcRootKind::ThreadObject,
+                thread,
+                Some(serial),
+                None,
+                Some(trace),
+            );
+        };
+        thread(&mut dump, 1, "main", &["main"]);
+        thread(&mut dump, 2, "pool-1-thread-1", &["take", "run"]);
+        thread(&mut dump, 3, "pool-1-thread-2", &["take", "run"]);
+        thread(&mut dump, 4, "idle-1", &[]);
+        thread(&mut dump, 5, "idle-2", &[]);
+        thread(&mut dump, 6, "pool-1-thread-3", &["take", "run"]);
+        let snapshot = dump.load();
+        let all = threads::threads(&snapshot);
+        let names = |groups: &[Vec<usize>]| -> Vec<Vec<&str>> {
+            groups
+                .iter()
+                .map(|g| g.iter().map(|&t| all[t].name.as_str()).collect())
+                .collect()
+        };
+
+        let groups = fold_threads(&snapshot, &all, |_| true, true);
+        assert_eq!(
+            names(&groups),
+            vec![
+                vec!["main"],
+                vec!["pool-1-thread-1", "pool-1-thread-2", "pool-1-thread-3"],
+                vec!["idle-1"],
+                vec!["idle-2"],
+            ]
+        );
+        assert_eq!(
+            folded_threads(&snapshot, &all, &groups[1]),
+            "\u{d7} 3 threads: pool-1-thread-1..pool-1-thread-3\n\
+             \x20 at void test.Worker.take() [Unknown]\n\
+             \x20 at void test.Worker.run() [Unknown]\n\n"
+        );
+
+        assert_eq!(fold_threads(&snapshot, &all, |_| true, false).len(), 6);
+        let pool = fold_threads(&snapshot, &all, |t| t.serial != 3, true);
+        assert_eq!(names(&pool)[1], vec!["pool-1-thread-1", "pool-1-thread-3"]);
+    }
+}
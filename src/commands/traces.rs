@@ -0,0 +1,376 @@
+use crate::cli::{self, Args};
+use hprof_cat::graph::Graph;
+use hprof_cat::heap::FieldTag;
+use hprof_cat::records::{AllocSite, RecordTag};
+use hprof_cat::snapshot::Snapshot;
+use hprof_cat::{histogram, reachability, threads};
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+pub fn print_stack_traces(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    for trace in &snapshot.traces {
+        println!("Thread {}:", trace.thread_serial_num);
+        for frame_id in &trace.frame_ids {
+            let frame = snapshot.frames.get(frame_id).unwrap();
+
+            let class = snapshot.classes.get(&frame.class_serial_num).unwrap();
+            let class_name = snapshot.class_name(class.object_id);
+            let method = threads::frame_method(snapshot, frame, &class_name);
+            if frame.source_name_id != 0 {
+                println!(
+                    "\t{} [{}:{}]",
+                    method,
+                    snapshot.string(frame.source_name_id),
+                    frame.line_num
+                );
+            } else if frame.line_num == -1 {
+                println!("\t{} [Unknown]", method);
+            } else if frame.line_num == -2 {
+                // XXX: Haven't seen that yet, potentially unimplemented
+                println!("\t{} [Compiled]", method);
+                println!("{:?}", frame);
+            } else if frame.line_num == -3 {
+                // XXX: Haven't seen that yet, potentially unimplemented
+                println!("\t{} [Native]", method);
+                println!("{:?}", frame);
+            } else {
+                // XXX: skip here maybe with a debug msg
+                println!("{:?}", frame);
+            }
+        }
+        println!();
+    }
+
+    let count = |tag: RecordTag| snapshot.record_counts.get(&(tag as u8)).unwrap_or(&0);
+    println!(
+        "entries: {} string {} load {} unload {} frame {} trace",
+        count(RecordTag::Utf8String),
+        count(RecordTag::LoadClass),
+        count(RecordTag::UnloadClass),
+        count(RecordTag::StackFrame),
+        count(RecordTag::StackTrace)
+    );
+    println!(
+        "strings: {} distinct of {}, {} stored, {} of duplicates shared",
+        snapshot.strings.unique(),
+        snapshot.strings.len(),
+        units.amount(snapshot.strings.text_bytes()),
+        units.amount(snapshot.strings.shared_bytes())
+    );
+}
+
+//
+// The stack traces grouped by their frames, the most common first: a
+// server's thousands of traces are those of a few call patterns. Frames
+// are compared by what they are, HotSpot recording those of every thread
+// under ids of their own.
+//
+pub fn print_grouped_traces(snapshot: &Snapshot) {
+    let groups = group_traces(snapshot);
+    for (frames, threads) in &groups {
+        let shown: Vec<String> = threads.iter().take(8).map(u32::to_string).collect();
+        println!(
+            "{} {}, thread{} {}{}:",
+            threads.len(),
+            if threads.len() == 1 {
+                "trace"
+            } else {
+                "traces"
+            },
+            if threads.len() == 1 { "" } else { "s" },
+            shown.join(", "),
+            if threads.len() > shown.len() {
+                ", ..."
+            } else {
+                ""
+            }
+        );
+        if frames.is_empty() {
+            println!("\t<no frames>");
+        }
+        for frame in frames {
+            println!("\t{}", frame);
+        }
+        println!();
+    }
+    let (traces, stacks) = (snapshot.traces.len(), groups.len());
+    println!(
+        "{} trace{}, {} distinct stack{}",
+        traces,
+        if traces == 1 { "" } else { "s" },
+        stacks,
+        if stacks == 1 { "" } else { "s" }
+    );
+}
+
+// The frames of each distinct stack and the thread serials of its traces.
+fn group_traces(snapshot: &Snapshot) -> Vec<(Vec<String>, Vec<u32>)> {
+    let mut groups: Vec<(Vec<String>, Vec<u32>)> = Vec::new();
+    let mut by_frames: HashMap<Vec<String>, usize> = HashMap::new();
+    for trace in &snapshot.traces {
+        let frames = trace_frames(snapshot, trace.serial_num);
+        match by_frames.get(&frames) {
+            Some(&group) => groups[group].1.push(trace.thread_serial_num),
+            None => {
+                by_frames.insert(frames.clone(), groups.len());
+                groups.push((frames, vec![trace.thread_serial_num]));
+            }
+        }
+    }
+    groups.sort_by_key(|(_, threads)| std::cmp::Reverse(threads.len()));
+    groups
+}
+
+//
+// Live objects by allocation site: the stack trace recorded when each
+// reachable object was allocated, with the classes allocated there.
+//
+pub fn print_allocation_traces(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let top = args.number("--top", 25) as usize;
+    let max_frames = args.number("--frames", 8) as usize;
+    let marked = reachability::mark(&Graph::build(snapshot));
+    let groups = histogram::by_allocation_trace(snapshot, |i| marked[i as usize]);
+
+    for group in groups.iter().take(top) {
+        println!(
+            "{} live objects, {} allocated at trace {}:",
+            group.instances,
+            units.amount(group.shallow),
+            group.strace_num
+        );
+        let frame_ids = snapshot
+            .trace_index
+            .get(&group.strace_num)
+            .map(|&t| &snapshot.traces[t].frame_ids[..])
+            .unwrap_or(&[]);
+        if frame_ids.is_empty() {
+            println!("\t<no frames recorded>");
+        }
+        for id in frame_ids.iter().take(max_frames) {
+            match snapshot.frames.get(id) {
+                Some(frame) => println!("\t{}", threads::describe_frame(snapshot, frame)),
+                None => println!("\t<unknown frame {:#x}>", id),
+            }
+        }
+        if frame_ids.len() > max_frames {
+            println!("\t... {} more frames", frame_ids.len() - max_frames);
+        }
+        for class in group.classes.iter().take(5) {
+            println!(
+                "  {:>10} {:>14}  {}",
+                class.instances,
+                units.bytes(class.shallow),
+                class.class_name
+            );
+        }
+        println!();
+    }
+    if groups.len() == 1 {
+        println!("All the objects share one trace: the JVM wasn't recording allocation sites.");
+    }
+}
+
+//
+// Live objects by the thread that allocated them, from the thread serials
+// of their allocation stack traces: the pool whose tasks allocate what
+// stays alive.
+//
+pub fn print_allocating_threads(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let top = args.number("--top", 25) as usize;
+    let classes = args.number("--classes", 5) as usize;
+    let marked = reachability::mark(&Graph::build(snapshot));
+    let groups = histogram::by_allocating_thread(snapshot, |i| marked[i as usize]);
+    let names = threads::thread_names(snapshot);
+    let total: u64 = groups.iter().map(|g| g.shallow).sum();
+
+    println!(
+        "{:>10} {:>14} {:>6} {:>7}  Thread",
+        "Objects", "Live bytes", "%", "Traces"
+    );
+    for group in groups.iter().take(top) {
+        let thread = match group.thread_serial {
+            Some(serial) => match names.get(&serial) {
+                Some(name) => format!("#{} \"{}\"", serial, name),
+                None => format!("#{} <exited>", serial),
+            },
+            None => "<no thread recorded>".to_string(),
+        };
+        println!(
+            "{:>10} {:>14} {:>5.1}% {:>7}  {}",
+            group.instances,
+            units.bytes(group.shallow),
+            100.0 * group.shallow as f64 / total.max(1) as f64,
+            group.traces,
+            thread
+        );
+        for class in group.classes.iter().take(classes) {
+            println!(
+                "  {:>10} {:>14}  {}",
+                class.instances,
+                units.bytes(class.shallow),
+                class.class_name
+            );
+        }
+    }
+    if groups.len() == 1 && groups[0].thread_serial.is_none() {
+        println!("No allocation traces name a thread: the JVM wasn't recording allocation sites.");
+    }
+}
+
+// The frames of a stack trace, or none if the trace is unknown.
+fn trace_frames(snapshot: &Snapshot, strace_num: u32) -> Vec<String> {
+    let frame_ids = match snapshot.trace_index.get(&strace_num) {
+        Some(&t) => &snapshot.traces[t].frame_ids[..],
+        None => &[],
+    };
+    frame_ids
+        .iter()
+        .map(|id| match snapshot.frames.get(id) {
+            Some(frame) => threads::describe_frame(snapshot, frame),
+            None => format!("<unknown frame {:#x}>", id),
+        })
+        .collect()
+}
+
+fn site_class_name(snapshot: &Snapshot, site: &AllocSite) -> String {
+    match snapshot.classes.get(&site.class_serial_num) {
+        Some(class) => snapshot.class_name(class.object_id),
+        None => match FieldTag::try_from(site.array_indicator) {
+            Ok(tag) if !tag.is_object() => format!("{}[]", tag.java_name()),
+            _ => format!("<unknown class #{}>", site.class_serial_num),
+        },
+    }
+}
+
+//
+// The allocation sites recorded by the HPROF agent (heap=sites), sorted by
+// live bytes, bytes allocated, or by the live / allocated ratio: sites
+// whose objects mostly stay alive are accumulating, those with a low ratio
+// are only churning.
+//
+pub fn print_alloc_sites(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    if snapshot.alloc_sites.is_empty() {
+        println!(
+            "No ALLOC_SITES records: the dump wasn't written by the HPROF agent with heap=sites."
+        );
+        return;
+    }
+    let top = args.number("--top", 25) as usize;
+    let max_frames = args.number("--frames", 3) as usize;
+    let record = snapshot.alloc_sites.last().unwrap();
+    let ratio = |site: &AllocSite| {
+        if site.bytes_allocated == 0 {
+            0.0
+        } else {
+            site.live_bytes as f64 / site.bytes_allocated as f64
+        }
+    };
+
+    let mut sites: Vec<(&AllocSite, String, Vec<String>)> = record
+        .sites
+        .iter()
+        .map(|site| {
+            (
+                site,
+                site_class_name(snapshot, site),
+                trace_frames(snapshot, site.strace_num),
+            )
+        })
+        .filter(|(_, class_name, _)| args.value("--class").is_none_or(|c| class_name.contains(c)))
+        .filter(|(_, _, frames)| {
+            args.value("--frame")
+                .is_none_or(|f| frames.iter().any(|frame| frame.contains(f)))
+        })
+        .collect();
+    match args.value("--sort").unwrap_or("live") {
+        "live" => sites.sort_by_key(|(s, _, _)| std::cmp::Reverse(s.live_bytes)),
+        "allocated" => sites.sort_by_key(|(s, _, _)| std::cmp::Reverse(s.bytes_allocated)),
+        "ratio" => sites.sort_by(|a, b| ratio(b.0).total_cmp(&ratio(a.0))),
+        other => cli::die(&format!(
+            "--sort: expected live, allocated or ratio, not {}",
+            other
+        )),
+    }
+
+    println!(
+        "live: {} in {} objects, allocated: {} in {} objects",
+        units.amount(record.total_live_bytes as u64),
+        record.total_live_instances,
+        units.amount(record.total_bytes_allocated),
+        record.total_instances_allocated
+    );
+    println!(
+        "{:>12} {:>10} {:>12} {:>10} {:>6}  Class",
+        "Live bytes", "Live objs", "Alloc bytes", "Alloc objs", "Live"
+    );
+    for (site, class_name, frames) in sites.iter().take(top) {
+        println!(
+            "{:>12} {:>10} {:>12} {:>10} {:>5.0}%  {}",
+            units.bytes(site.live_bytes as u64),
+            site.live_instances,
+            units.bytes(site.bytes_allocated as u64),
+            site.instances_allocated,
+            100.0 * ratio(site),
+            class_name
+        );
+        for frame in frames.iter().take(max_frames) {
+            println!("\t{}", frame);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hprof_cat::testing::Dump;
+
+    #[test]
+    fn traces_are_counted_by_stack() {
+        let mut dump = Dump::new();
+        let worker = dump.class("test/Worker", dump.object, &[]);
+        // The frames of each trace are its own, as HotSpot writes them.
+        let trace = |dump: &mut Dump, thread: u32, methods: &[&str]| {
+            let frames: Vec<u64> = methods
+                .iter()
+                .map(|m| dump.frame(worker, m, "()V", 1))
+                .collect();
+            dump.trace(thread, &frames);
+        };
+        trace(&mut dump, 1, &["main"]);
+        for thread in 2..6 {
+            trace(&mut dump, thread, &["take", "run"]);
+        }
+        trace(&mut dump, 6, &[]);
+        trace(&mut dump, 7, &["poll", "run"]);
+        trace(&mut dump, 8, &["poll", "run"]);
+        let snapshot = dump.load();
+
+        let groups = group_traces(&snapshot);
+        let counts: Vec<(usize, Vec<u32>)> = groups
+            .iter()
+            .map(|(frames, threads)| (frames.len(), threads.clone()))
+            .collect();
+        // The most common first, ties in the order of the dump.
+        assert_eq!(
+            counts,
+            vec![
+                (2, vec![2, 3, 4, 5]),
+                (2, vec![7, 8]),
+                (1, vec![1]),
+                (0, vec![6]),
+            ]
+        );
+        assert_eq!(
+            groups[1].0,
+            vec![
+                "void test.Worker.poll() [Unknown]",
+                "void test.Worker.run() [Unknown]"
+            ]
+        );
+    }
+}
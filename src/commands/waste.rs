@@ -0,0 +1,276 @@
+//
+// Commands estimating memory spent on overhead rather than data: boxed
+// values, over-allocated collections and oversized buffers, as well as
+// hash maps made slow by collisions.
+//
+use crate::cli::Args;
+use hprof_cat::arrays;
+use hprof_cat::boxed;
+use hprof_cat::cache;
+use hprof_cat::collections;
+use hprof_cat::graph::Graph;
+use hprof_cat::snapshot::Snapshot;
+
+pub fn print_boxed(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let classes = boxed::boxed_classes(snapshot);
+    println!(
+        "{:<20} {:>10} {:>12} {:>10} {:>14} {:>14}",
+        "Class", "Instances", "Cache range", "Distinct", "Shallow", "Wasted"
+    );
+    for c in &classes {
+        println!(
+            "{:<20} {:>10} {:>12} {:>10} {:>14} {:>14}",
+            c.class_name,
+            c.instances,
+            c.cached_range,
+            c.distinct,
+            units.bytes(c.shallow),
+            units.bytes(c.wasted)
+        );
+    }
+
+    let arrays = boxed::boxed_arrays(snapshot);
+    if arrays.is_empty() {
+        return;
+    }
+    println!();
+    println!("arrays of boxed values:");
+    println!(
+        "{:<20} {:>10} {:>12} {:>14}",
+        "Element", "Arrays", "Elements", "Wasted"
+    );
+    for a in &arrays {
+        println!(
+            "{:<20} {:>10} {:>12} {:>14}",
+            a.class_name,
+            a.arrays,
+            a.elements,
+            units.bytes(a.wasted)
+        );
+    }
+}
+
+//
+// Capacity vs. size of the common collections per class, followed by the
+// instances with the most unused slots.
+//
+pub fn print_fill_ratio(snapshot: &Snapshot, args: &Args) {
+    let top = args.number("--top", 25) as usize;
+    let mut all = collections::collections(snapshot);
+    println!(
+        "{:<48} {:>9} {:>12} {:>12} {:>12}  {:>7} {:>7} {:>7} {:>7} {:>7}",
+        "Class", "Instances", "Size", "Capacity", "Slack", "empty", "<25%", "<50%", "<75%", ">=75%"
+    );
+    for s in collections::fill_summary(snapshot, &all) {
+        println!(
+            "{:<48} {:>9} {:>12} {:>12} {:>12}  {:>7} {:>7} {:>7} {:>7} {:>7}",
+            s.class_name,
+            s.instances,
+            s.size,
+            s.capacity,
+            s.slack,
+            s.buckets[0],
+            s.buckets[1],
+            s.buckets[2],
+            s.buckets[3],
+            s.buckets[4]
+        );
+    }
+
+    all.sort_by_key(|c| std::cmp::Reverse(c.slack()));
+    println!();
+    println!("most over-allocated:");
+    println!(
+        "{:>18} {:>10} {:>10} {:>12}  Class",
+        "Object", "Size", "Capacity", "Slack"
+    );
+    for c in all.iter().take(top).filter(|c| c.slack() > 0) {
+        let object = &snapshot.objects[c.node as usize];
+        println!(
+            "{:>#18x} {:>10} {:>10} {:>12}  {}",
+            object.object_id(),
+            c.size,
+            c.capacity,
+            c.slack(),
+            snapshot.object_class_name(object)
+        );
+    }
+}
+
+pub fn print_empty_collections(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let top = args.number("--top", 25) as usize;
+    let graph = Graph::build(snapshot);
+    let (tree, retained) = cache::retained_sizes(snapshot, &graph);
+    let all = collections::collections(snapshot);
+    let groups = collections::empty_collections(snapshot, &all, &tree, &retained);
+    println!(
+        "{} empty collections with allocated storage, {}",
+        groups.iter().map(|g| g.instances).sum::<u64>(),
+        units.amount(groups.iter().map(|g| g.bytes).sum::<u64>())
+    );
+    println!(
+        "{:>10} {:>14}  {:<40}  Owner",
+        "Instances", "Retained", "Class"
+    );
+    for g in groups.iter().take(top) {
+        println!(
+            "{:>10} {:>14}  {:<40}  {}",
+            g.instances,
+            units.bytes(g.bytes),
+            g.class_name,
+            g.owner
+        );
+    }
+}
+
+pub fn print_zero_tails(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let top = args.number("--top", 25) as usize;
+    let min_bytes = args.number("--min-bytes", 1024);
+    let graph = Graph::build(snapshot);
+    let tree = cache::dominators(snapshot, &graph);
+    let groups = arrays::zero_tails(snapshot, &tree, min_bytes);
+    println!(
+        "{} arrays ending with at least {} zero bytes, {} wasted",
+        groups.iter().map(|g| g.arrays).sum::<u64>(),
+        min_bytes,
+        units.amount(groups.iter().map(|g| g.wasted).sum::<u64>())
+    );
+    println!(
+        "{:>8} {:>14} {:>14}  {:<8}  Owner",
+        "Arrays", "Shallow", "Zero tail", "Type"
+    );
+    for g in groups.iter().take(top) {
+        println!(
+            "{:>8} {:>14} {:>14}  {:<8}  {}",
+            g.arrays,
+            units.bytes(g.bytes),
+            units.bytes(g.wasted),
+            g.class_name,
+            g.owner
+        );
+    }
+}
+
+//
+// The largest arrays by bytes and by length, with what owns them and, for
+// object arrays, what they mostly contain.
+//
+pub fn print_large_arrays(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let top = args.number("--top", 10) as usize;
+    let graph = Graph::build(snapshot);
+    let tree = cache::dominators(snapshot, &graph);
+    for (title, by_length) in [("by bytes", false), ("by length", true)] {
+        println!("Largest arrays {}:", title);
+        println!("{:>12} {:>14}  Array", "Length", "Shallow");
+        for a in arrays::large_arrays(snapshot, &tree, by_length, top) {
+            let object = &snapshot.objects[a.node as usize];
+            let owner = match a.owner {
+                Some(owner) => {
+                    let owner = &snapshot.objects[owner as usize];
+                    format!("{:#x} {}", owner.object_id(), snapshot.object_label(owner))
+                }
+                None => "<GC root>".to_string(),
+            };
+            println!(
+                "{:>12} {:>14}  {:#x} {} owned by {}",
+                a.length,
+                units.bytes(a.bytes),
+                object.object_id(),
+                snapshot.object_label(object),
+                owner
+            );
+            if let Some((class_name, count)) = a.dominant_element {
+                println!("{:>29}{} of {}", "", count, class_name);
+            }
+        }
+        println!();
+    }
+}
+
+//
+// Object arrays that are mostly null (over-provisioned lists, ring buffers)
+// with their owners and the bytes right-sizing them would save.
+//
+pub fn print_sparse_arrays(snapshot: &Snapshot, args: &Args) {
+    let units = args.units();
+    let top = args.number("--top", 25) as usize;
+    let min_length = args.number("--min-length", 64);
+    let min_nulls = args.number("--min-nulls", 50);
+    let graph = Graph::build(snapshot);
+    let tree = cache::dominators(snapshot, &graph);
+    let sparse = arrays::sparse_arrays(snapshot, &tree, min_length, min_nulls as f64 / 100.0);
+    println!(
+        "{} object arrays of {}+ elements at least {}% null, {} reclaimable",
+        sparse.len(),
+        min_length,
+        min_nulls,
+        units.amount(sparse.iter().map(|a| a.reclaimable).sum::<u64>())
+    );
+    println!(
+        "{:>10} {:>6} {:>12}  Array",
+        "Length", "Nulls", "Reclaimable"
+    );
+    for a in sparse.iter().take(top) {
+        let object = &snapshot.objects[a.node as usize];
+        let owner = match a.owner {
+            Some(owner) => snapshot.object_label(&snapshot.objects[owner as usize]),
+            None => "<GC root>".to_string(),
+        };
+        println!(
+            "{:>10} {:>5.0}% {:>12}  {:#x} {} owned by {}",
+            a.length,
+            100.0 * a.null_ratio(),
+            units.bytes(a.reclaimable),
+            object.object_id(),
+            snapshot.object_label(object),
+            owner
+        );
+    }
+}
+
+//
+// Bucket occupancy of the hash maps and sets with at least --min-size
+// entries, worst spread first: few occupied buckets compared to a uniform
+// hash, long chains and tree bins point to a poor hashCode() of the keys.
+//
+pub fn print_hash_buckets(snapshot: &Snapshot, args: &Args) {
+    let min_size = args.number("--min-size", 64);
+    let top = args.number("--top", 25) as usize;
+    let mut stats: Vec<collections::BucketStats> = collections::collections(snapshot)
+        .iter()
+        .filter(|c| c.size >= min_size)
+        .filter_map(|c| collections::bucket_stats(snapshot, c))
+        .collect();
+    stats.sort_by(|a, b| {
+        a.spread()
+            .total_cmp(&b.spread())
+            .then_with(|| b.longest_chain.cmp(&a.longest_chain))
+    });
+
+    println!(
+        "{:>10} {:>10} {:>10} {:>10} {:>8} {:>6}  Object",
+        "Size", "Capacity", "Occupied", "Expected", "Longest", "Trees"
+    );
+    for s in stats.iter().take(top) {
+        let object = &snapshot.objects[s.node as usize];
+        println!(
+            "{:>10} {:>10} {:>10} {:>10.0} {:>8} {:>6}  {:#x} {}{}",
+            s.size,
+            s.capacity,
+            s.occupied,
+            s.expected_occupied,
+            s.longest_chain,
+            s.tree_bins,
+            object.object_id(),
+            snapshot.object_label(object),
+            s.worst_key_class
+                .as_ref()
+                .map(|k| format!(" (longest chain of {} keys)", k))
+                .unwrap_or_default()
+        );
+    }
+}
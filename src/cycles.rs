@@ -0,0 +1,364 @@
+//
+// Reference cycles between objects. Only instance fields and array
+// elements are followed: every instance points to its class, which points
+// to whatever its static fields hold, so counting those references would
+// put most of the heap in one big cycle through the classes.
+//
+// Strongly connected components are the general form of cycles: webs of
+// objects that can all reach each other.
+//
+use crate::graph::{self, Via};
+use crate::heap::HeapObject;
+use crate::histogram::HistogramEntry;
+use crate::snapshot::Snapshot;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+// The targets of the field and element references of each object, as
+// (target, via) pairs.
+pub fn object_edges(snapshot: &Snapshot, live: &[bool]) -> Vec<Vec<(u32, Via)>> {
+    snapshot
+        .objects
+        .iter()
+        .enumerate()
+        .map(|(node, object)| {
+            if !live[node] || matches!(object, HeapObject::Class(_)) {
+                return Vec::new();
+            }
+            graph::references(snapshot, object)
+                .into_iter()
+                .filter(|r| matches!(r.via, Via::Field(..) | Via::Element(_)))
+                .filter_map(|r| snapshot.index_of(r.target).map(|t| (t, r.via)))
+                .filter(|&(t, _)| live[t as usize])
+                .collect()
+        })
+        .collect()
+}
+
+//
+// Tarjan's algorithm, iteratively since the graph can be arbitrarily deep.
+// Returns the components with more than one object or a self reference.
+//
+pub fn strongly_connected_components(edges: &[Vec<(u32, Via)>]) -> Vec<Vec<u32>> {
+    const UNVISITED: u32 = u32::MAX;
+    let n = edges.len();
+    let mut index = vec![UNVISITED; n];
+    let mut lowlink = vec![0u32; n];
+    let mut on_stack = vec![false; n];
+    let mut stack: Vec<u32> = Vec::new();
+    let mut components = Vec::new();
+    let mut next_index = 0;
+
+    for start in 0..n as u32 {
+        if index[start as usize] != UNVISITED {
+            continue;
+        }
+        // The DFS path as (node, position of the next edge to follow).
+        let mut path: Vec<(u32, usize)> = vec![(start, 0)];
+        index[start as usize] = next_index;
+        lowlink[start as usize] = next_index;
+        next_index += 1;
+        stack.push(start);
+        on_stack[start as usize] = true;
+
+        while let Some(&(node, edge)) = path.last() {
+            if let Some(&(succ, _)) = edges[node as usize].get(edge) {
+                path.last_mut().unwrap().1 += 1;
+                if index[succ as usize] == UNVISITED {
+                    index[succ as usize] = next_index;
+                    lowlink[succ as usize] = next_index;
+                    next_index += 1;
+                    stack.push(succ);
+                    on_stack[succ as usize] = true;
+                    path.push((succ, 0));
+                } else if on_stack[succ as usize] {
+                    lowlink[node as usize] = lowlink[node as usize].min(index[succ as usize]);
+                }
+                continue;
+            }
+            path.pop();
+            if let Some(&(parent, _)) = path.last() {
+                lowlink[parent as usize] = lowlink[parent as usize].min(lowlink[node as usize]);
+            }
+            if lowlink[node as usize] == index[node as usize] {
+                let mut component = Vec::new();
+                loop {
+                    let member = stack.pop().unwrap();
+                    on_stack[member as usize] = false;
+                    component.push(member);
+                    if member == node {
+                        break;
+                    }
+                }
+                let self_loop = edges[node as usize].iter().any(|&(t, _)| t == node);
+                if component.len() > 1 || self_loop {
+                    components.push(component);
+                }
+            }
+        }
+    }
+    components
+}
+
+#[derive(Debug)]
+pub struct CycleShape {
+    // The classes and fields around the cycle, e.g.
+    // `Listener.owner -> Window.listeners -> java.lang.Object[][] -> Listener`.
+    pub shape: String,
+    pub cycles: u64,
+    // One of the cycles, starting with the object the shape starts with.
+    pub example: Vec<u32>,
+}
+
+// The shortest cycle through `start` staying within `members`, if any
+// shorter than `max_length` references.
+fn shortest_cycle(
+    edges: &[Vec<(u32, Via)>],
+    members: &HashSet<u32>,
+    start: u32,
+    max_length: usize,
+) -> Option<Vec<(u32, Via)>> {
+    let mut parents: HashMap<u32, (u32, Via)> = HashMap::new();
+    let mut queue = VecDeque::from([(start, 0)]);
+    while let Some((node, length)) = queue.pop_front() {
+        if length == max_length {
+            continue;
+        }
+        for &(succ, via) in &edges[node as usize] {
+            if succ == start {
+                let mut cycle = vec![(node, via)];
+                let mut current = node;
+                while current != start {
+                    let (parent, via) = parents[&current];
+                    cycle.push((parent, via));
+                    current = parent;
+                }
+                cycle.reverse();
+                return Some(cycle);
+            }
+            if members.contains(&succ) && succ != start && !parents.contains_key(&succ) {
+                parents.insert(succ, (node, via));
+                queue.push_back((succ, length + 1));
+            }
+        }
+    }
+    None
+}
+
+//
+// Small cycles grouped by shape. For each object (at most `max_starts` per
+// component) the shortest cycle through it is found, and cycles are then
+// deduplicated by their objects and shapes by their rotations.
+//
+pub fn cycle_shapes(
+    snapshot: &Snapshot,
+    edges: &[Vec<(u32, Via)>],
+    components: &[Vec<u32>],
+    max_length: usize,
+    max_starts: usize,
+) -> Vec<CycleShape> {
+    let mut seen: HashSet<Vec<u32>> = HashSet::new();
+    let mut shapes: HashMap<String, CycleShape> = HashMap::new();
+    for component in components {
+        let members: HashSet<u32> = component.iter().copied().collect();
+        for &start in component.iter().take(max_starts) {
+            let cycle = match shortest_cycle(edges, &members, start, max_length) {
+                Some(cycle) => cycle,
+                None => continue,
+            };
+            let mut nodes: Vec<u32> = cycle.iter().map(|&(n, _)| n).collect();
+            nodes.sort_unstable();
+            if !seen.insert(nodes) {
+                continue;
+            }
+            let segments: Vec<String> = cycle
+                .iter()
+                .map(|&(node, via)| {
+                    let class_name = snapshot.object_class_name(&snapshot.objects[node as usize]);
+                    match via {
+                        Via::Element(_) => format!("{}[]", class_name),
+                        _ => format!("{}.{}", class_name, graph::via_name(snapshot, via)),
+                    }
+                })
+                .collect();
+            // The same shape may be entered at any of its objects.
+            let first = (0..segments.len())
+                .min_by_key(|&i| (&segments[i..], &segments[..i]))
+                .unwrap();
+            let mut rotated = segments[first..].to_vec();
+            rotated.extend_from_slice(&segments[..first]);
+            let back = snapshot.object_class_name(&snapshot.objects[cycle[first].0 as usize]);
+            let shape = format!("{} -> {}", rotated.join(" -> "), back);
+            let entry = shapes.entry(shape.clone()).or_insert_with(|| {
+                let mut example: Vec<u32> = cycle.iter().map(|&(n, _)| n).collect();
+                example.rotate_left(first);
+                CycleShape {
+                    shape,
+                    cycles: 0,
+                    example,
+                }
+            });
+            entry.cycles += 1;
+        }
+    }
+    let mut shapes: Vec<CycleShape> = shapes.into_values().collect();
+    shapes.sort_by(|a, b| b.cycles.cmp(&a.cycles).then_with(|| a.shape.cmp(&b.shape)));
+    shapes
+}
+
+#[derive(Debug)]
+pub struct ComponentSummary {
+    pub objects: u64,
+    pub bytes: u64,
+    // Number of objects and shallow bytes per class, most bytes first.
+    pub classes: Vec<HistogramEntry>,
+    // The object of the component with the lowest id, to find it again.
+    pub first: u32,
+}
+
+// The class composition of strongly connected components, largest first.
+pub fn summarize_components(snapshot: &Snapshot, components: &[Vec<u32>]) -> Vec<ComponentSummary> {
+    let mut summaries: Vec<ComponentSummary> = components
+        .iter()
+        .map(|component| {
+            let mut classes: HashMap<String, HistogramEntry> = HashMap::new();
+            for &node in component {
+                let object = &snapshot.objects[node as usize];
+                let name = snapshot.object_class_name(object);
+                let entry = classes
+                    .entry(name.clone())
+                    .or_insert_with(|| HistogramEntry {
+                        class_name: name,
+                        instances: 0,
+                        shallow: 0,
+                    });
+                entry.instances += 1;
+                entry.shallow += snapshot.shallow_size(object);
+            }
+            let mut classes: Vec<HistogramEntry> = classes.into_values().collect();
+            classes.sort_by(|a, b| {
+                b.shallow
+                    .cmp(&a.shallow)
+                    .then_with(|| a.class_name.cmp(&b.class_name))
+            });
+            ComponentSummary {
+                objects: component.len() as u64,
+                bytes: classes.iter().map(|c| c.shallow).sum(),
+                classes,
+                first: *component
+                    .iter()
+                    .min_by_key(|&&n| snapshot.objects[n as usize].object_id())
+                    .unwrap(),
+            }
+        })
+        .collect();
+    summaries.sort_by(|a, b| {
+        b.bytes
+            .cmp(&a.bytes)
+            .then_with(|| b.objects.cmp(&a.objects))
+    });
+    summaries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::{FieldTag, Value};
+    use crate::testing::Dump;
+
+    //
+    // Two windows and their listeners, each listener referring back to its
+    // window, a node referring to itself and a list without cycles.
+    //
+    fn cycles() -> Snapshot {
+        let mut dump = Dump::new();
+        let window = dump.class(
+            "test/Window",
+            dump.object,
+            &[("listeners", FieldTag::ArrayObject)],
+        );
+        let listener = dump.class(
+            "test/Listener",
+            dump.object,
+            &[("owner", FieldTag::NormalObject)],
+        );
+        let node = dump.class(
+            "test/Node",
+            dump.object,
+            &[("next", FieldTag::NormalObject)],
+        );
+        let array = dump.object_array;
+        for _ in 0..2 {
+            let ids = dump.next_ids(3);
+            dump.instance(window, &[Value::Object(ids[1])]);
+            dump.object_array(array, &[ids[2]]);
+            dump.instance(listener, &[Value::Object(ids[0])]);
+        }
+        let id = dump.next_ids(1)[0];
+        dump.instance(node, &[Value::Object(id)]);
+        let tail = dump.instance(node, &[]);
+        dump.instance(node, &[Value::Object(tail)]);
+        dump.load()
+    }
+
+    #[test]
+    fn cycles_are_grouped_by_shape() {
+        let snapshot = cycles();
+        let edges = object_edges(&snapshot, &vec![true; snapshot.objects.len()]);
+        let components = strongly_connected_components(&edges);
+        let mut sizes: Vec<usize> = components.iter().map(|c| c.len()).collect();
+        sizes.sort_unstable();
+        assert_eq!(sizes, vec![1, 3, 3]);
+
+        let shapes = cycle_shapes(&snapshot, &edges, &components, 10, 10);
+        let found: Vec<(&str, u64, usize)> = shapes
+            .iter()
+            .map(|s| (s.shape.as_str(), s.cycles, s.example.len()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    "java.lang.Object[][] -> test.Listener.owner -> test.Window.listeners -> java.lang.Object[]",
+                    2,
+                    3
+                ),
+                ("test.Node.next -> test.Node", 1, 1),
+            ]
+        );
+        // The window cycles are too long to be found.
+        let shapes = cycle_shapes(&snapshot, &edges, &components, 2, 10);
+        assert_eq!(shapes.len(), 1);
+        assert_eq!(shapes[0].shape, "test.Node.next -> test.Node");
+    }
+
+    #[test]
+    fn components_are_summarized_by_class() {
+        let snapshot = cycles();
+        let edges = object_edges(&snapshot, &vec![true; snapshot.objects.len()]);
+        let components = strongly_connected_components(&edges);
+        let summaries = summarize_components(&snapshot, &components);
+        assert_eq!(summaries.len(), 3);
+        let window = &summaries[0];
+        assert_eq!(window.objects, 3);
+        assert_eq!(window.bytes, window.classes.iter().map(|c| c.shallow).sum());
+        let mut classes: Vec<(&str, u64)> = window
+            .classes
+            .iter()
+            .map(|c| (c.class_name.as_str(), c.instances))
+            .collect();
+        classes.sort_unstable();
+        assert_eq!(
+            classes,
+            vec![
+                ("java.lang.Object[]", 1),
+                ("test.Listener", 1),
+                ("test.Window", 1),
+            ]
+        );
+        // The first window of the dump comes with its component.
+        let first = snapshot.objects_of_class("test.Window")[0];
+        assert!(summaries[..2].iter().any(|s| s.first == first));
+        assert_eq!(summaries[2].objects, 1);
+    }
+}
@@ -0,0 +1,499 @@
+//
+// Decoders turning framework objects into what they logically hold: the
+// entries of a cache, the readable bytes of a buffer, the fields of a
+// message. A decoder handles the classes it matches and their subclasses,
+// the first registered decoder matching a class (closest class first) wins.
+// Applications can register decoders for their own types next to the
+// built-in ones.
+//
+use crate::collections::{self, Contents};
+use crate::heap::{HeapObject, InstanceDump, Value};
+use crate::snapshot::Snapshot;
+
+#[derive(Debug, Default)]
+pub struct Decoded {
+    // A one line description of the state of the object.
+    pub summary: Option<String>,
+    pub contents: Option<Contents>,
+    // Named values standing for the raw fields of the object.
+    pub fields: Vec<(String, Value)>,
+    // Sizes attributed to the object that the heap doesn't show directly,
+    // like native memory or the serialized size of a message.
+    pub sizes: Vec<(&'static str, u64)>,
+}
+
+pub trait TypeDecoder {
+    fn name(&self) -> &'static str;
+
+    // Whether the decoder handles instances of the class with this name.
+    fn matches(&self, class_name: &str) -> bool;
+
+    // At most `limit` entries or elements of the contents.
+    fn decode(&self, snapshot: &Snapshot, instance: &InstanceDump, limit: usize)
+        -> Option<Decoded>;
+}
+
+pub struct Decoders {
+    decoders: Vec<Box<dyn TypeDecoder>>,
+}
+
+impl Decoders {
+    pub fn empty() -> Decoders {
+        Decoders {
+            decoders: Vec::new(),
+        }
+    }
+
+    // The JDK collections, Guava caches, Netty buffers and protobuf messages.
+    pub fn builtin() -> Decoders {
+        let mut decoders = Decoders::empty();
+        decoders.register(Box::new(JdkCollections));
+        decoders.register(Box::new(GuavaCache));
+        decoders.register(Box::new(NettyByteBuf));
+        decoders.register(Box::new(Protobuf));
+        decoders
+    }
+
+    pub fn register(&mut self, decoder: Box<dyn TypeDecoder>) {
+        self.decoders.push(decoder);
+    }
+
+    pub fn decode(
+        &self,
+        snapshot: &Snapshot,
+        object: &HeapObject,
+        limit: usize,
+    ) -> Option<Decoded> {
+        let instance = match object {
+            HeapObject::Instance(i) => i,
+            _ => return None,
+        };
+        let mut class_id = instance.class_id;
+        while let Some(class) = snapshot.class_dump(class_id) {
+            let class_name = snapshot.class_name(class_id);
+            if let Some(decoder) = self.decoders.iter().find(|d| d.matches(&class_name)) {
+                return decoder.decode(snapshot, instance, limit);
+            }
+            class_id = class.super_class_id;
+        }
+        None
+    }
+}
+
+fn int_field(snapshot: &Snapshot, instance: &InstanceDump, name: &str) -> Option<i64> {
+    match snapshot.field_value(instance, name)? {
+        Value::Int(v) => Some(v as i64),
+        Value::Long(v) => Some(v),
+        _ => None,
+    }
+}
+
+fn instance_field<'a>(
+    snapshot: &'a Snapshot,
+    instance: &InstanceDump,
+    name: &str,
+) -> Option<&'a InstanceDump> {
+    match snapshot.object(snapshot.field_value(instance, name)?.as_object()?)? {
+        HeapObject::Instance(i) => Some(i),
+        _ => None,
+    }
+}
+
+fn id_field(snapshot: &Snapshot, instance: &InstanceDump, name: &str) -> u64 {
+    snapshot
+        .field_value(instance, name)
+        .and_then(|v| v.as_object())
+        .unwrap_or(0)
+}
+
+struct JdkCollections;
+
+impl TypeDecoder for JdkCollections {
+    fn name(&self) -> &'static str {
+        "jdk-collections"
+    }
+
+    fn matches(&self, class_name: &str) -> bool {
+        collections::CONTENT_CLASSES.contains(&class_name)
+    }
+
+    fn decode(
+        &self,
+        snapshot: &Snapshot,
+        instance: &InstanceDump,
+        limit: usize,
+    ) -> Option<Decoded> {
+        Some(Decoded {
+            contents: Some(collections::instance_contents(snapshot, instance, limit)?),
+            ..Decoded::default()
+        })
+    }
+}
+
+//
+// com.google.common.cache caches: the LocalCache behind the Cache and
+// LoadingCache facades is split in segments, each a hash table of entries
+// whose values are behind a ValueReference (the `referent` of weak and soft
+// ones).
+//
+struct GuavaCache;
+
+impl TypeDecoder for GuavaCache {
+    fn name(&self) -> &'static str {
+        "guava-cache"
+    }
+
+    fn matches(&self, class_name: &str) -> bool {
+        matches!(
+            class_name,
+            "com.google.common.cache.LocalCache"
+                | "com.google.common.cache.LocalCache$LocalManualCache"
+        )
+    }
+
+    fn decode(
+        &self,
+        snapshot: &Snapshot,
+        instance: &InstanceDump,
+        limit: usize,
+    ) -> Option<Decoded> {
+        let cache = match instance_field(snapshot, instance, "localCache") {
+            Some(cache) => cache,
+            None => instance,
+        };
+        let segments = match snapshot.object(id_field(snapshot, cache, "segments"))? {
+            HeapObject::ObjectArray(a) => a,
+            _ => return None,
+        };
+        let mut count = 0;
+        let mut entries = Vec::new();
+        for segment in segments
+            .elements
+            .iter()
+            .filter_map(|&id| snapshot.object(id))
+        {
+            let segment = match segment {
+                HeapObject::Instance(s) => s,
+                _ => continue,
+            };
+            count += int_field(snapshot, segment, "count").unwrap_or(0).max(0) as u64;
+            // An AtomicReferenceArray of entry chains.
+            let table = instance_field(snapshot, segment, "table")
+                .and_then(|t| snapshot.object(id_field(snapshot, t, "array")));
+            let heads = match table {
+                Some(HeapObject::ObjectArray(a)) => &a.elements,
+                _ => continue,
+            };
+            for &head in heads {
+                let mut current = snapshot.object(head);
+                while let Some(HeapObject::Instance(entry)) = current {
+                    if entries.len() == limit {
+                        break;
+                    }
+                    // Weak keys are the referent of the entry itself.
+                    let key = match id_field(snapshot, entry, "key") {
+                        0 => id_field(snapshot, entry, "referent"),
+                        key => key,
+                    };
+                    let value = instance_field(snapshot, entry, "valueReference")
+                        .map(|v| id_field(snapshot, v, "referent"))
+                        .unwrap_or(0);
+                    entries.push((key, value));
+                    current = snapshot.object(id_field(snapshot, entry, "next"));
+                }
+            }
+        }
+        let mut sizes = vec![("size", count)];
+        if let Some(max) = int_field(snapshot, cache, "maxWeight").filter(|&m| m >= 0) {
+            sizes.push(("max weight", max as u64));
+        }
+        Some(Decoded {
+            summary: Some(format!(
+                "{} entries in {} segments",
+                count,
+                segments.elements.len()
+            )),
+            contents: Some(Contents::Entries(entries)),
+            fields: Vec::new(),
+            sizes,
+        })
+    }
+}
+
+//
+// io.netty.buffer.ByteBuf: reader and writer indices and the capacity, and
+// whether the bytes are off-heap. Since Netty 4.1.32 the reference count is
+// stored doubled, odd values meaning released.
+//
+struct NettyByteBuf;
+
+impl TypeDecoder for NettyByteBuf {
+    fn name(&self) -> &'static str {
+        "netty-bytebuf"
+    }
+
+    fn matches(&self, class_name: &str) -> bool {
+        class_name == "io.netty.buffer.AbstractByteBuf"
+    }
+
+    fn decode(
+        &self,
+        snapshot: &Snapshot,
+        instance: &InstanceDump,
+        _limit: usize,
+    ) -> Option<Decoded> {
+        let reader = int_field(snapshot, instance, "readerIndex")?;
+        let writer = int_field(snapshot, instance, "writerIndex")?;
+        let array = match snapshot.object(id_field(snapshot, instance, "array")) {
+            Some(HeapObject::PrimitiveArray(a)) => Some(a.length as i64),
+            _ => None,
+        };
+        let capacity = int_field(snapshot, instance, "capacity")
+            .or_else(|| int_field(snapshot, instance, "length"))
+            .or(array)
+            .unwrap_or(writer);
+        // Pooled buffers have their `memory` chunk, a byte[] for heap ones.
+        let heap = array.is_some()
+            || matches!(
+                snapshot.object(id_field(snapshot, instance, "memory")),
+                Some(HeapObject::PrimitiveArray(_))
+            );
+        let class_name = snapshot.class_name(instance.class_id);
+        let direct = !heap && (class_name.contains("Direct") || class_name.contains("Unsafe"));
+        let ref_count =
+            int_field(snapshot, instance, "refCnt").map(
+                |raw| {
+                    if raw & 1 == 1 {
+                        0
+                    } else {
+                        raw >> 1
+                    }
+                },
+            );
+        let mut fields = vec![
+            ("readerIndex".to_string(), Value::Int(reader as i32)),
+            ("writerIndex".to_string(), Value::Int(writer as i32)),
+            ("capacity".to_string(), Value::Int(capacity as i32)),
+        ];
+        if let Some(count) = ref_count {
+            fields.push(("refCnt".to_string(), Value::Int(count as i32)));
+        }
+        let mut sizes = vec![
+            ("readable", (writer - reader).max(0) as u64),
+            ("capacity", capacity.max(0) as u64),
+        ];
+        if direct {
+            sizes.push(("native", capacity.max(0) as u64));
+        }
+        Some(Decoded {
+            summary: Some(format!(
+                "{} readable of {} bytes{}{}",
+                (writer - reader).max(0),
+                capacity,
+                if direct { ", direct" } else { "" },
+                if ref_count == Some(0) {
+                    ", released"
+                } else {
+                    ""
+                }
+            )),
+            contents: None,
+            fields,
+            sizes,
+        })
+    }
+}
+
+//
+// Generated protobuf messages: their fields are named after the message
+// fields with a trailing underscore, next to bookkeeping fields (presence
+// bits, memoized hash and size, unknown fields). The memoized size is the
+// serialized size, once computed.
+//
+struct Protobuf;
+
+impl TypeDecoder for Protobuf {
+    fn name(&self) -> &'static str {
+        "protobuf"
+    }
+
+    fn matches(&self, class_name: &str) -> bool {
+        matches!(
+            class_name,
+            "com.google.protobuf.GeneratedMessageV3"
+                | "com.google.protobuf.GeneratedMessage"
+                | "com.google.protobuf.GeneratedMessageLite"
+        )
+    }
+
+    fn decode(
+        &self,
+        snapshot: &Snapshot,
+        instance: &InstanceDump,
+        _limit: usize,
+    ) -> Option<Decoded> {
+        let mut fields = Vec::new();
+        let mut serialized = None;
+        for f in snapshot.instance_fields(instance) {
+            let name = snapshot.string(f.name_id);
+            match name {
+                "memoizedSize" | "memoizedSerializedSize" => {
+                    if let Value::Int(size) = f.value {
+                        serialized = serialized.or(Some(size).filter(|&s| s >= 0));
+                    }
+                }
+                _ if name.starts_with("bitField") || name.starts_with("memoized") => {}
+                _ => {
+                    if let Some(field) = name.strip_suffix('_') {
+                        fields.push((field.to_string(), f.value));
+                    }
+                }
+            }
+        }
+        Some(Decoded {
+            summary: serialized.map(|s| format!("{} bytes serialized", s)),
+            contents: None,
+            fields,
+            sizes: serialized
+                .map(|s| vec![("serialized", s as u64)])
+                .unwrap_or_default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::FieldTag;
+    use crate::testing::Dump;
+
+    fn decode(snapshot: &Snapshot, decoders: &Decoders, id: u64) -> Option<Decoded> {
+        decoders.decode(snapshot, snapshot.object(id).unwrap(), 10)
+    }
+
+    #[test]
+    fn netty_buffers_are_told_apart() {
+        let mut dump = Dump::new();
+        let base = dump.class(
+            "io/netty/buffer/AbstractByteBuf",
+            dump.object,
+            &[
+                ("readerIndex", FieldTag::Int),
+                ("writerIndex", FieldTag::Int),
+            ],
+        );
+        let direct = dump.class(
+            "io/netty/buffer/UnpooledDirectByteBuf",
+            base,
+            &[("capacity", FieldTag::Int), ("refCnt", FieldTag::Int)],
+        );
+        let heap = dump.class(
+            "io/netty/buffer/UnpooledHeapByteBuf",
+            base,
+            &[("array", FieldTag::ArrayObject), ("refCnt", FieldTag::Int)],
+        );
+        let ints = |values: &[i32]| values.iter().map(|&v| Value::Int(v)).collect::<Vec<_>>();
+        let direct = dump.instance(direct, &ints(&[1024, 4, 10, 110]));
+        let array = dump.primitive_array(FieldTag::Byte, &[0; 64]);
+        let heap = dump.instance(
+            heap,
+            &[
+                Value::Object(array),
+                Value::Int(3),
+                Value::Int(0),
+                Value::Int(0),
+            ],
+        );
+        let snapshot = dump.load();
+        let decoders = Decoders::builtin();
+
+        let decoded = decode(&snapshot, &decoders, direct).unwrap();
+        assert_eq!(
+            decoded.summary.as_deref(),
+            Some("100 readable of 1024 bytes, direct")
+        );
+        assert_eq!(
+            decoded.fields,
+            vec![
+                ("readerIndex".to_string(), Value::Int(10)),
+                ("writerIndex".to_string(), Value::Int(110)),
+                ("capacity".to_string(), Value::Int(1024)),
+                ("refCnt".to_string(), Value::Int(2)),
+            ]
+        );
+        assert_eq!(
+            decoded.sizes,
+            vec![("readable", 100), ("capacity", 1024), ("native", 1024)]
+        );
+        let decoded = decode(&snapshot, &decoders, heap).unwrap();
+        assert_eq!(
+            decoded.summary.as_deref(),
+            Some("0 readable of 64 bytes, released")
+        );
+        assert_eq!(decoded.sizes, vec![("readable", 0), ("capacity", 64)]);
+    }
+
+    struct Requests;
+
+    impl TypeDecoder for Requests {
+        fn name(&self) -> &'static str {
+            "requests"
+        }
+
+        fn matches(&self, class_name: &str) -> bool {
+            class_name == "test.Request"
+        }
+
+        fn decode(&self, _: &Snapshot, _: &InstanceDump, _: usize) -> Option<Decoded> {
+            Some(Decoded {
+                summary: Some("a request".to_string()),
+                ..Decoded::default()
+            })
+        }
+    }
+
+    #[test]
+    fn the_closest_class_picks_the_decoder() {
+        let mut dump = Dump::new();
+        let message = dump.class(
+            "com/google/protobuf/GeneratedMessageV3",
+            dump.object,
+            &[("memoizedSize", FieldTag::Int)],
+        );
+        let fields = [
+            ("bitField0_", FieldTag::Int),
+            ("name_", FieldTag::NormalObject),
+            ("id_", FieldTag::Long),
+            ("memoizedHashCode", FieldTag::Int),
+        ];
+        let request = dump.class("test/Request", message, &fields);
+        let response = dump.class("test/Response", message, &fields);
+        let name = dump.string("get");
+        let values = [
+            Value::Int(3),
+            Value::Object(name),
+            Value::Long(7),
+            Value::Int(99),
+            Value::Int(42),
+        ];
+        let request = dump.instance(request, &values);
+        let response = dump.instance(response, &values);
+        let snapshot = dump.load();
+        let mut decoders = Decoders::builtin();
+        decoders.register(Box::new(Requests));
+
+        let decoded = decode(&snapshot, &decoders, response).unwrap();
+        assert_eq!(decoded.summary.as_deref(), Some("42 bytes serialized"));
+        assert_eq!(
+            decoded.fields,
+            vec![
+                ("name".to_string(), Value::Object(name)),
+                ("id".to_string(), Value::Long(7)),
+            ]
+        );
+        assert_eq!(decoded.sizes, vec![("serialized", 42)]);
+        let decoded = decode(&snapshot, &decoders, request).unwrap();
+        assert_eq!(decoded.summary.as_deref(), Some("a request"));
+        assert!(decode(&snapshot, &Decoders::empty(), response).is_none());
+    }
+}
@@ -0,0 +1,260 @@
+//
+// Comparison of two dumps of the same application. Objects of different
+// dumps have nothing in common (not even their ids, which change as the GC
+// moves them), so they are matched by the shape of their position in the
+// dominator tree instead: the chain of classes and fields leading to them.
+// Often all that was kept of the earlier state is a class histogram (from
+// jmap -histo), which is compared by class instead.
+//
+use crate::dominator::DominatorTree;
+use crate::graph::{self, Via};
+use crate::histogram::HistogramEntry;
+use crate::paths::retaining_via;
+use crate::reference::RetentionFilter;
+use crate::retained::dominator_children;
+use crate::signature;
+use crate::snapshot::Snapshot;
+
+use std::collections::HashMap;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct KeyTotals {
+    pub objects: u64,
+    pub retained: u64,
+}
+
+//
+// The key of an object below its immediate dominator: how the dominator
+// refers to it, if it does directly, and its class. Array indices are left
+// out so that all the elements of an array share one key.
+//
+fn segment(
+    snapshot: &Snapshot,
+    filter: &RetentionFilter,
+    parent: Option<u32>,
+    node: u32,
+) -> String {
+    let label = snapshot.object_label(&snapshot.objects[node as usize]);
+    match parent.and_then(|p| retaining_via(snapshot, filter, p, node)) {
+        Some(Via::Element(_)) => format!("[] -> {}", label),
+        Some(via) => format!("{} -> {}", graph::via_name(snapshot, via), label),
+        None => label,
+    }
+}
+
+//
+// Retained sizes of the dominator tree nodes down to `max_depth` levels
+// below the GC roots, summed per key. Keys are the segments of the path in
+// the dominator tree joined by " / ".
+//
+pub fn dominator_keys(
+    snapshot: &Snapshot,
+    tree: &DominatorTree,
+    retained: &[u64],
+    max_depth: usize,
+) -> HashMap<String, KeyTotals> {
+    let filter = RetentionFilter::new(snapshot, Vec::new());
+    let children = dominator_children(tree);
+    let mut keys: HashMap<String, KeyTotals> = HashMap::new();
+    let mut stack: Vec<(u32, String, usize)> = children[tree.virtual_root() as usize]
+        .iter()
+        .map(|&c| (c, segment(snapshot, &filter, None, c), 0))
+        .collect();
+    while let Some((node, key, depth)) = stack.pop() {
+        if depth < max_depth {
+            for &c in &children[node as usize] {
+                let child_key = format!("{} / {}", key, segment(snapshot, &filter, Some(node), c));
+                stack.push((c, child_key, depth + 1));
+            }
+        }
+        let totals = keys.entry(key).or_default();
+        totals.objects += 1;
+        totals.retained += retained[node as usize];
+    }
+    keys
+}
+
+#[derive(Debug)]
+pub struct KeyDelta {
+    pub key: String,
+    pub before: KeyTotals,
+    pub after: KeyTotals,
+}
+
+impl KeyDelta {
+    pub fn growth(&self) -> i64 {
+        self.after.retained as i64 - self.before.retained as i64
+    }
+}
+
+// The keys of both dumps ordered by retained size growth, largest first.
+pub fn diff_keys(
+    before: &HashMap<String, KeyTotals>,
+    after: &HashMap<String, KeyTotals>,
+) -> Vec<KeyDelta> {
+    let mut deltas: Vec<KeyDelta> = before
+        .keys()
+        .chain(after.keys().filter(|k| !before.contains_key(*k)))
+        .map(|key| KeyDelta {
+            key: key.clone(),
+            before: before.get(key).copied().unwrap_or_default(),
+            after: after.get(key).copied().unwrap_or_default(),
+        })
+        .collect();
+    deltas.sort_by(|a, b| b.growth().cmp(&a.growth()).then_with(|| a.key.cmp(&b.key)));
+    deltas
+}
+
+// The instances of each class of a histogram, and their shallow bytes as
+// `retained`.
+pub fn histogram_keys(histogram: &[HistogramEntry]) -> HashMap<String, KeyTotals> {
+    histogram
+        .iter()
+        .map(|e| {
+            let totals = KeyTotals {
+                objects: e.instances,
+                retained: e.shallow,
+            };
+            (e.class_name.clone(), totals)
+        })
+        .collect()
+}
+
+//
+// Parses the text of `jmap -histo` or `jcmd <pid> GC.class_histogram`,
+// where each class is a line like
+//
+//     1:         12345         678900  [B (java.base@17)
+//
+// with the module in parentheses since JDK 9. The other lines (headers,
+// totals, the pid jcmd starts with) are ignored. The bytes are those of the
+// running JVM, to compare with the sizes of a model (see sizes.rs) rather
+// than those of the dump.
+//
+pub fn parse_class_histogram(text: &str) -> Result<HashMap<String, KeyTotals>, String> {
+    let mut keys: HashMap<String, KeyTotals> = HashMap::new();
+    for line in text.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (rank, instances, bytes, name) = match fields[..] {
+            [rank, instances, bytes, name, ..] => (rank, instances, bytes, name),
+            _ => continue,
+        };
+        let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+        match rank.strip_suffix(':') {
+            Some(rank) if is_number(rank) => {}
+            _ => continue,
+        }
+        if !is_number(instances) || !is_number(bytes) {
+            return Err(format!("bad histogram line: {}", line));
+        }
+        // Classes of the same name from different loaders add up.
+        let totals = keys.entry(signature::class_name(name)).or_default();
+        totals.objects += instances.parse::<u64>().unwrap();
+        totals.retained += bytes.parse::<u64>().unwrap();
+    }
+    if keys.is_empty() {
+        return Err("no class histogram lines".to_string());
+    }
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dominator;
+    use crate::graph::Graph;
+    use crate::heap::{FieldTag, GcRootKind, Value};
+    use crate::retained::retained_sizes;
+    use crate::testing::Dump;
+
+    // A cache of `entries` entries, and a lone object.
+    fn cache(entries: usize) -> Snapshot {
+        let mut dump = Dump::new();
+        let cache = dump.class(
+            "test/Cache",
+            dump.object,
+            &[("entries", FieldTag::ArrayObject)],
+        );
+        let entry = dump.class("test/Entry", dump.object, &[("value", FieldTag::Long)]);
+        let array = dump.object_array;
+        let held: Vec<u64> = (0..entries).map(|_| dump.instance(entry, &[])).collect();
+        let held = dump.object_array(array, &held);
+        let cache = dump.instance(cache, &[Value::Object(held)]);
+        dump.root(GcRootKind::JniGlobal, cache);
+        let lone = dump.instance(dump.object, &[]);
+        dump.root(GcRootKind::JniGlobal, lone);
+        dump.load()
+    }
+
+    fn keys(snapshot: &Snapshot, max_depth: usize) -> HashMap<String, KeyTotals> {
+        let tree = dominator::build(&Graph::build(snapshot));
+        let retained = retained_sizes(snapshot, &tree);
+        dominator_keys(snapshot, &tree, &retained, max_depth)
+    }
+
+    #[test]
+    fn dominator_keys_match_across_dumps() {
+        let (before, after) = (cache(2), cache(5));
+        let entry = "test.Cache / entries -> java.lang.Object[] / [] -> test.Entry";
+        let before = keys(&before, 2);
+        assert_eq!(before[entry].objects, 2);
+        assert!(!keys(&after, 1).contains_key(entry));
+
+        let after = keys(&after, 2);
+        let deltas = diff_keys(&before, &after);
+        let entry_size = before[entry].retained / 2;
+        let growth: Vec<(&str, i64)> = deltas
+            .iter()
+            .take(3)
+            .map(|d| (d.key.as_str(), d.growth()))
+            .collect();
+        // The cache, its array and its entries, all by the three new entries.
+        assert_eq!(
+            growth,
+            vec![
+                ("test.Cache", 3 * (8 + entry_size) as i64),
+                (
+                    "test.Cache / entries -> java.lang.Object[]",
+                    3 * (8 + entry_size) as i64
+                ),
+                (entry, 3 * entry_size as i64),
+            ]
+        );
+        assert!(deltas[3..].iter().all(|d| d.growth() == 0));
+        assert_eq!(deltas[2].after.objects, 5);
+    }
+
+    #[test]
+    fn jmap_histograms_are_read_by_class() {
+        let text = "\
+12345:
+ num     #instances         #bytes  class name (module)
+-------------------------------------------------------
+   1:         12345         678900  [B (java.base@17.0.2)
+   2:          9000         216000  java.lang.String (java.base@17.0.2)
+   3:           100           4000  [Ljava.lang.Object; (java.base@17.0.2)
+   4:            10            240  com.example.Foo
+   5:             5            120  com.example.Foo
+Total         21460         899260
+";
+        let keys = parse_class_histogram(text).unwrap();
+        let mut totals: Vec<(&str, u64, u64)> = keys
+            .iter()
+            .map(|(k, t)| (k.as_str(), t.objects, t.retained))
+            .collect();
+        totals.sort();
+        assert_eq!(
+            totals,
+            vec![
+                ("byte[]", 12345, 678900),
+                ("com.example.Foo", 15, 360),
+                ("java.lang.Object[]", 100, 4000),
+                ("java.lang.String", 9000, 216000),
+            ]
+        );
+
+        let error = parse_class_histogram("   1:  12x  40  [B\n").unwrap_err();
+        assert_eq!(error, "bad histogram line:    1:  12x  40  [B");
+        assert!(parse_class_histogram("Total 0 0\n").is_err());
+    }
+}
@@ -0,0 +1,533 @@
+//
+// A health check of a dump, to tell a bad dump from a bad tool: the file is
+// walked record by record and sub-record by sub-record without trusting
+// anything (unknown tags are skipped over by their lengths where there are
+// lengths, a problem only stops the walk when nothing after it can be
+// found), then every identifier and serial number used is looked up among
+// those defined. Problems are counted by kind, with the offsets of the
+// first records or sub-records that have them.
+//
+use crate::heap::{
+    parse_object, parse_root, sub_record_bytes, ClassDump, DataDumpSubRecordTag, FieldTag,
+    HeapObject,
+};
+use crate::idhash::{IdMap, IdSet};
+use crate::records::{
+    parse_load_class_record, parse_stack_frame_record, parse_stack_trace_record,
+    parse_start_thread_record, LoadClassRecord, RecordTag, StackFrameRecord, StackTraceRecord,
+    StartThreadRecord,
+};
+
+use std::convert::TryFrom;
+use std::ops::Range;
+
+// Offsets kept per kind of problem.
+const OFFSETS: usize = 5;
+
+#[derive(Debug)]
+pub struct Finding {
+    pub problem: String,
+    pub count: u64,
+    // Of the first records or sub-records with the problem, each once.
+    pub offsets: Vec<u64>,
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub format: String,
+    pub id_size: u32,
+    pub bytes: u64,
+    // Top-level records by raw tag, and their bytes.
+    pub records: Vec<(u8, u64, u64)>,
+    pub objects: u64,
+    pub roots: u64,
+    // Where the walk stopped before the end of the file, if it did.
+    pub stopped_at: Option<u64>,
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    fn note(&mut self, problem: &str, offset: usize) {
+        let finding = match self.findings.iter_mut().find(|f| f.problem == problem) {
+            Some(finding) => finding,
+            None => {
+                self.findings.push(Finding {
+                    problem: problem.to_string(),
+                    count: 0,
+                    offsets: Vec::new(),
+                });
+                self.findings.last_mut().unwrap()
+            }
+        };
+        finding.count += 1;
+        if finding.offsets.len() < OFFSETS && finding.offsets.last() != Some(&(offset as u64)) {
+            finding.offsets.push(offset as u64);
+        }
+    }
+}
+
+// What the first pass found defined, and where.
+#[derive(Default)]
+struct Definitions {
+    strings: IdSet<u64>,
+    classes: Vec<(usize, LoadClassRecord)>,
+    class_serials: IdSet<u32>,
+    frames: Vec<(usize, StackFrameRecord)>,
+    frame_ids: IdSet<u64>,
+    traces: Vec<(usize, StackTraceRecord)>,
+    trace_serials: IdSet<u32>,
+    threads: Vec<(usize, StartThreadRecord)>,
+    objects: IdSet<u64>,
+    class_dumps: IdMap<u64, (usize, ClassDump)>,
+    // The bodies of the heap dump segments, as far as they could be walked.
+    segments: Vec<Range<usize>>,
+}
+
+fn u32_at(data: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+}
+
+fn id_at(data: &[u8], at: usize, id_size: u32) -> u64 {
+    match id_size {
+        4 => u32_at(data, at) as u64,
+        _ => u64::from_be_bytes(<[u8; 8]>::try_from(&data[at..at + 8]).unwrap()),
+    }
+}
+
+//
+// The whole sub-records of a segment body, as (offset in the file, tag,
+// bytes), noting what stops the walk.
+//
+fn sub_records(
+    data: &[u8],
+    body: Range<usize>,
+    id_size: u32,
+    report: &mut Report,
+) -> Vec<(usize, DataDumpSubRecordTag, usize)> {
+    let mut subs = Vec::new();
+    let mut at = body.start;
+    while at < body.end {
+        let tag = match DataDumpSubRecordTag::try_from(data[at]) {
+            Ok(tag) => tag,
+            Err(_) => {
+                report.note(
+                    &format!(
+                        "unknown heap dump sub-record tag {:#x} (rest of the segment skipped)",
+                        data[at]
+                    ),
+                    at,
+                );
+                break;
+            }
+        };
+        match sub_record_bytes(&data[at..body.end], id_size) {
+            Some(bytes) if at + bytes <= body.end => {
+                subs.push((at, tag, bytes));
+                at += bytes;
+            }
+            Some(_) => {
+                report.note("sub-record crossing the end of its segment", at);
+                break;
+            }
+            None => {
+                report.note("class or array dump with an unknown field type", at);
+                break;
+            }
+        }
+    }
+    subs
+}
+
+// The first pass: the records and sub-records, and what they define.
+fn walk(data: &[u8], header: usize, report: &mut Report) -> Definitions {
+    let id_size = report.id_size;
+    let id = id_size as usize;
+    let mut defined = Definitions::default();
+    let mut counts: IdMap<u8, (u64, u64)> = IdMap::default();
+    let mut ended = false;
+    let mut at = header;
+    while at < data.len() {
+        if data.len() - at < 9 {
+            report.note(
+                "partial record header at the end (the dump is cut short)",
+                at,
+            );
+            report.stopped_at = Some(at as u64);
+            break;
+        }
+        let raw_tag = data[at];
+        let declared = u32_at(data, at + 5) as usize;
+        let body = at + 9..at + 9 + declared;
+        if ended {
+            report.note("record after HEAP_DUMP_END", at);
+        }
+        let tag = RecordTag::try_from(raw_tag).ok();
+        if tag.is_none() {
+            report.note(&format!("unknown record tag {:#x}", raw_tag), at);
+        }
+        if body.end > data.len() {
+            if matches!(
+                tag,
+                Some(RecordTag::HeapDump) | Some(RecordTag::HeapDumpSegment)
+            ) {
+                report.note(
+                    "heap dump segment going past the end of the file (cut short)",
+                    at,
+                );
+                defined.segments.push(body.start..data.len());
+            } else {
+                report.note("record going past the end of the file (cut short)", at);
+            }
+            report.stopped_at = Some(at as u64);
+            break;
+        }
+        let entry = counts.entry(raw_tag).or_insert((0, 0));
+        entry.0 += 1;
+        entry.1 += declared as u64;
+        let mut reader = &data[body.clone()];
+        let expected = match tag {
+            Some(RecordTag::LoadClass) => Some(8 + 2 * id),
+            Some(RecordTag::StackFrame) => Some(8 + 4 * id),
+            Some(RecordTag::StartThread) => Some(8 + 4 * id),
+            Some(RecordTag::StackTrace) if declared >= 12 => {
+                Some(12 + u32_at(data, body.start + 8) as usize * id)
+            }
+            Some(RecordTag::StackTrace) => Some(12),
+            Some(RecordTag::Utf8String) if declared < id => Some(id),
+            Some(RecordTag::HeapDumpEnd) => Some(0),
+            _ => None,
+        };
+        if expected.is_some_and(|bytes| bytes != declared) {
+            report.note(
+                &format!(
+                    "{:?} record with a length that doesn't match its contents",
+                    tag.unwrap()
+                ),
+                at,
+            );
+        } else {
+            match tag {
+                Some(RecordTag::Utf8String) => {
+                    defined.strings.insert(id_at(data, body.start, id_size));
+                }
+                Some(RecordTag::LoadClass) => {
+                    let r = parse_load_class_record(&mut reader, id_size);
+                    defined.class_serials.insert(r.serial_num);
+                    defined.classes.push((at, r));
+                }
+                Some(RecordTag::StackFrame) => {
+                    let r = parse_stack_frame_record(&mut reader, id_size);
+                    if !defined.frame_ids.insert(r.frame_id) {
+                        report.note("stack frame id defined twice", at);
+                    }
+                    defined.frames.push((at, r));
+                }
+                Some(RecordTag::StackTrace) => {
+                    let r = parse_stack_trace_record(&mut reader, id_size);
+                    defined.trace_serials.insert(r.serial_num);
+                    defined.traces.push((at, r));
+                }
+                Some(RecordTag::StartThread) => {
+                    let r = parse_start_thread_record(&mut reader, id_size);
+                    defined.threads.push((at, r));
+                }
+                Some(RecordTag::HeapDump) | Some(RecordTag::HeapDumpSegment) => {
+                    defined.segments.push(body.clone());
+                }
+                Some(RecordTag::HeapDumpEnd) => ended = true,
+                _ => {}
+            }
+        }
+        at = body.end;
+    }
+    if !ended && report.stopped_at.is_none() {
+        report.note("no HEAP_DUMP_END (the dump may be cut short)", data.len());
+    }
+    let mut records: Vec<(u8, u64, u64)> =
+        counts.into_iter().map(|(t, (n, b))| (t, n, b)).collect();
+    records.sort_unstable();
+    report.records = records;
+
+    for body in defined.segments.clone() {
+        for (offset, tag, bytes) in sub_records(data, body, id_size, report) {
+            let sub = &data[offset + 1..offset + bytes];
+            match tag {
+                DataDumpSubRecordTag::ClassDump
+                | DataDumpSubRecordTag::InstanceDump
+                | DataDumpSubRecordTag::ObjectArrayDump
+                | DataDumpSubRecordTag::PrimitiveArrayDump => {
+                    report.objects += 1;
+                    if !defined.objects.insert(id_at(sub, 0, id_size)) {
+                        report.note("object id dumped twice", offset);
+                    }
+                    if tag == DataDumpSubRecordTag::ClassDump {
+                        if let Ok(HeapObject::Class(class)) =
+                            parse_object(&mut &sub[..], tag, id_size)
+                        {
+                            defined.class_dumps.insert(class.class_id, (offset, class));
+                        }
+                    }
+                }
+                _ => report.roots += 1,
+            }
+        }
+    }
+    defined
+}
+
+// The field types of the instances of a class, superclasses last.
+fn layout(defined: &Definitions, mut class_id: u64) -> Option<Vec<FieldTag>> {
+    let mut tags = Vec::new();
+    let mut seen = IdSet::default();
+    while class_id != 0 {
+        if !seen.insert(class_id) {
+            return None;
+        }
+        let (_, class) = defined.class_dumps.get(&class_id)?;
+        tags.extend(class.instance_fields.iter().map(|f| f.tag));
+        class_id = class.super_class_id;
+    }
+    Some(tags)
+}
+
+// The second pass: whether what is used is defined.
+fn check(data: &[u8], defined: &Definitions, report: &mut Report) {
+    let id_size = report.id_size;
+    let object = |id: u64| id == 0 || defined.objects.contains(&id);
+    let string = |id: u64| id == 0 || defined.strings.contains(&id);
+
+    let mut loaded = IdSet::default();
+    for (at, class) in &defined.classes {
+        loaded.insert(class.object_id);
+        if !string(class.strname_id) {
+            report.note("LOAD_CLASS with an unresolved name symbol", *at);
+        }
+        if !defined.class_dumps.contains_key(&class.object_id) && !defined.segments.is_empty() {
+            report.note("LOAD_CLASS of a class with no CLASS_DUMP", *at);
+        }
+    }
+    for (at, frame) in &defined.frames {
+        if !defined.class_serials.contains(&frame.class_serial_num) {
+            report.note("stack frame with an unresolved class serial", *at);
+        }
+        if ![
+            frame.method_name_id,
+            frame.method_sign_id,
+            frame.source_name_id,
+        ]
+        .iter()
+        .all(|&id| string(id))
+        {
+            report.note("stack frame with unresolved symbols", *at);
+        }
+    }
+    for (at, trace) in &defined.traces {
+        if !trace
+            .frame_ids
+            .iter()
+            .all(|id| defined.frame_ids.contains(id))
+        {
+            report.note("stack trace with unresolved stack frames", *at);
+        }
+    }
+    for (at, thread) in &defined.threads {
+        if !object(thread.thread_object_id) && !defined.segments.is_empty() {
+            report.note("thread whose Thread object isn't in the dump", *at);
+        }
+        if thread.strace_num != 0 && !defined.trace_serials.contains(&thread.strace_num) {
+            report.note("thread with an unresolved stack trace serial", *at);
+        }
+    }
+    let mut layouts: IdMap<u64, Option<Vec<FieldTag>>> = IdMap::default();
+    for body in &defined.segments {
+        let mut quiet = Report {
+            id_size,
+            ..Report::default()
+        };
+        for (offset, tag, bytes) in sub_records(data, body.clone(), id_size, &mut quiet) {
+            let mut sub = &data[offset + 1..offset + bytes];
+            match parse_object_or_root(&mut sub, tag, id_size) {
+                Ok(HeapObject::Instance(i)) => {
+                    let tags = layouts
+                        .entry(i.class_id)
+                        .or_insert_with(|| layout(defined, i.class_id));
+                    let tags = match tags {
+                        Some(tags) => tags,
+                        None => {
+                            report.note(
+                                "instance of a class with no (or a broken) CLASS_DUMP chain",
+                                offset,
+                            );
+                            continue;
+                        }
+                    };
+                    let size: usize = tags.iter().map(|t| t.size(id_size) as usize).sum();
+                    if size != i.data.len() {
+                        report.note(
+                            "instance data length doesn't match its class layout",
+                            offset,
+                        );
+                        continue;
+                    }
+                    let mut at = 0;
+                    for tag in tags.iter() {
+                        if tag.is_object() && !object(id_at(&i.data, at, id_size)) {
+                            report.note("dangling reference (not in the dump)", offset);
+                        }
+                        at += tag.size(id_size) as usize;
+                    }
+                }
+                Ok(HeapObject::ObjectArray(a)) => {
+                    if !defined.class_dumps.contains_key(&a.class_id) {
+                        report.note("object array of a class with no CLASS_DUMP", offset);
+                    }
+                    for &element in &a.elements {
+                        if !object(element) {
+                            report.note("dangling reference (not in the dump)", offset);
+                        }
+                    }
+                }
+                Ok(HeapObject::Class(class)) => {
+                    if !loaded.contains(&class.class_id) {
+                        report.note("CLASS_DUMP without LOAD_CLASS", offset);
+                    }
+                    if class.super_class_id != 0
+                        && !defined.class_dumps.contains_key(&class.super_class_id)
+                    {
+                        report.note("class dump with an unresolved superclass", offset);
+                    }
+                    let names = class.instance_fields.iter().map(|f| f.name_id);
+                    if !names
+                        .chain(class.static_fields().iter().map(|f| f.name_id))
+                        .all(string)
+                    {
+                        report.note("class dump with unresolved field name symbols", offset);
+                    }
+                    let statics = class.static_fields().iter().map(|f| f.value);
+                    let pool = class.constant_pool().iter().map(|&(_, v)| v);
+                    for value in statics.chain(pool) {
+                        if value.as_object().is_some_and(|id| !object(id)) {
+                            report.note("dangling reference (not in the dump)", offset);
+                        }
+                    }
+                }
+                Ok(HeapObject::PrimitiveArray(_)) => {}
+                Err(root) => {
+                    if !object(root) {
+                        report.note("GC root of an object not in the dump", offset);
+                    }
+                }
+            }
+        }
+    }
+}
+
+//
+// The object of a sub-record, or the object id of a GC root. The objects
+// parse: sub_records() made sure they are whole.
+//
+fn parse_object_or_root(
+    sub: &mut &[u8],
+    tag: DataDumpSubRecordTag,
+    id_size: u32,
+) -> Result<HeapObject, u64> {
+    match tag {
+        DataDumpSubRecordTag::ClassDump
+        | DataDumpSubRecordTag::InstanceDump
+        | DataDumpSubRecordTag::ObjectArrayDump
+        | DataDumpSubRecordTag::PrimitiveArrayDump => Ok(parse_object(sub, tag, id_size).unwrap()),
+        _ => Err(parse_root(sub, tag, id_size).object_id),
+    }
+}
+
+pub fn examine(data: &[u8]) -> Report {
+    let mut report = Report {
+        bytes: data.len() as u64,
+        ..Report::default()
+    };
+    let format = data
+        .iter()
+        .take(32)
+        .position(|&b| b == 0)
+        .filter(|&n| data[..n].starts_with(b"JAVA PROFILE "));
+    let format = match format {
+        Some(format) if data.len() >= format + 13 => format,
+        _ => {
+            report.note("no HPROF header", 0);
+            report.stopped_at = Some(0);
+            return report;
+        }
+    };
+    report.format = String::from_utf8_lossy(&data[..format]).to_string();
+    report.id_size = u32_at(data, format + 1);
+    if report.id_size != 4 && report.id_size != 8 {
+        report.note(
+            &format!("unsupported identifier size {}", report.id_size),
+            format + 1,
+        );
+        report.stopped_at = Some(format as u64 + 1);
+        return report;
+    }
+    let defined = walk(data, format + 13, &mut report);
+    check(data, &defined, &mut report);
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::{GcRootKind, Value};
+    use crate::testing::Dump;
+
+    fn findings(report: &Report) -> Vec<(&str, u64)> {
+        report
+            .findings
+            .iter()
+            .map(|f| (f.problem.as_str(), f.count))
+            .collect()
+    }
+
+    #[test]
+    fn truncated_dumps_are_counted_up_to_the_cut() {
+        let mut dump = Dump::new();
+        dump.set_segment_bytes(256);
+        let item = dump.class("test/Item", dump.object, &[("value", FieldTag::Long)]);
+        // An array of the items, written before them.
+        let ids = dump.next_ids(21);
+        let array = dump.object_array(dump.object_array, &ids[1..]);
+        for i in 0..20 {
+            dump.instance(item, &[Value::Long(i)]);
+        }
+        dump.root(GcRootKind::JniGlobal, array);
+        let bytes = dump.bytes();
+
+        let whole = examine(&bytes);
+        assert_eq!(whole.format, "JAVA PROFILE 1.0.2");
+        assert_eq!((whole.id_size, whole.bytes), (8, bytes.len() as u64));
+        // Four classes, the array and its items.
+        assert_eq!((whole.objects, whole.roots), (25, 1));
+        assert_eq!(whole.stopped_at, None);
+        assert!(whole.findings.is_empty(), "{:?}", whole.findings);
+
+        let k = 12;
+        // The INSTANCE_DUMP of an item, its tag before its id (which the
+        // array has too).
+        let item_id = ids[1 + k].to_be_bytes();
+        let start = bytes.windows(8).rposition(|w| w == item_id).unwrap() - 1;
+        let cut = examine(&bytes[..start + 5]);
+        assert_eq!((cut.objects, cut.roots), (5 + k as u64, 0));
+        assert!(cut.stopped_at.unwrap() < start as u64);
+        assert_eq!(
+            findings(&cut),
+            vec![
+                (
+                    "heap dump segment going past the end of the file (cut short)",
+                    1
+                ),
+                ("sub-record crossing the end of its segment", 1),
+                ("dangling reference (not in the dump)", 20 - k as u64),
+            ]
+        );
+        assert_eq!(cut.findings[1].offsets, vec![start as u64]);
+    }
+}
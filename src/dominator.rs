@@ -0,0 +1,460 @@
+//
+// Immediate dominators of the object graph. All GC roots hang off a single
+// virtual root node (index graph.len()) so that the result is one tree; the
+// children of the virtual root are the objects only kept alive by the heap
+// as a whole rather than by any single object.
+//
+// This is the Lengauer-Tarjan algorithm ("A Fast Algorithm for Finding
+// Dominators in a Flowgraph", 1979) with simple path compression. Both the
+// depth-first search and the path compression are iterative since object
+// graphs can easily be millions of nodes deep (e.g. long linked lists).
+// The algorithm itself is sequential; the predecessors it takes and the
+// retained sizes computed from its result are built on all the cores.
+//
+// On the largest dumps it runs for hours, so build_resumable() hands its
+// state out every few minutes to be saved, and can start again from it.
+//
+use crate::cache::{push_u32s, Reader};
+use crate::graph::{Adjacency, Graph};
+use crate::timings;
+
+use std::time::{Duration, Instant};
+
+pub const NONE: u32 = u32::MAX;
+
+pub struct DominatorTree {
+    // Immediate dominator of each object, the virtual root for objects only
+    // dominated by it, or NONE for objects unreachable from any GC root.
+    pub idom: Vec<u32>,
+    // Reachable objects in depth-first preorder. Every object appears after
+    // its immediate dominator.
+    pub order: Vec<u32>,
+}
+
+impl DominatorTree {
+    pub fn virtual_root(&self) -> u32 {
+        self.idom.len() as u32
+    }
+
+    pub fn is_reachable(&self, node: u32) -> bool {
+        self.idom[node as usize] != NONE
+    }
+
+    // The dominators of a node, closest first, excluding the virtual root.
+    pub fn dominators(&self, node: u32) -> Vec<u32> {
+        let mut chain = Vec::new();
+        let mut current = self.idom[node as usize];
+        while current != NONE && current != self.virtual_root() {
+            chain.push(current);
+            current = self.idom[current as usize];
+        }
+        chain
+    }
+}
+
+//
+// Node labels used during the computation. `semi` holds depth-first
+// numbers, everything else holds node indices.
+//
+struct State {
+    dfnum: Vec<u32>,
+    vertex: Vec<u32>,
+    parent: Vec<u32>,
+    semi: Vec<u32>,
+    ancestor: Vec<u32>,
+    label: Vec<u32>,
+}
+
+impl State {
+    fn compress(&mut self, v: u32) {
+        let mut path = Vec::new();
+        let mut u = v;
+        while self.ancestor[self.ancestor[u as usize] as usize] != NONE {
+            path.push(u);
+            u = self.ancestor[u as usize];
+        }
+        while let Some(u) = path.pop() {
+            let a = self.ancestor[u as usize] as usize;
+            let u = u as usize;
+            if self.semi[self.label[a] as usize] < self.semi[self.label[u] as usize] {
+                self.label[u] = self.label[a];
+            }
+            self.ancestor[u] = self.ancestor[a];
+        }
+    }
+
+    fn eval(&mut self, v: u32) -> u32 {
+        if self.ancestor[v as usize] == NONE {
+            return v;
+        }
+        self.compress(v);
+        self.label[v as usize]
+    }
+}
+
+// How often build_resumable() saves its progress.
+pub const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(300);
+
+//
+// How far the computation got: the depth-first search is done and the
+// semidominators of the nodes numbered `next` and above are known.
+//
+pub struct Progress {
+    next: u32,
+    state: State,
+    idom: Vec<u32>,
+    bucket_head: Vec<u32>,
+    bucket_next: Vec<u32>,
+}
+
+impl Progress {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        push_u32s(&mut out, &[self.next]);
+        for values in [
+            &self.state.vertex,
+            &self.state.parent,
+            &self.state.semi,
+            &self.state.ancestor,
+            &self.state.label,
+            &self.idom,
+            &self.bucket_head,
+            &self.bucket_next,
+        ] {
+            push_u32s(&mut out, values);
+        }
+        out
+    }
+
+    // The progress saved by encode(), None if it isn't for a graph of `len` nodes.
+    pub fn decode(bytes: &[u8], len: usize) -> Option<Progress> {
+        let mut reader = Reader(bytes);
+        let next = *reader.u32s()?.first()?;
+        let mut arrays = Vec::new();
+        for _ in 0..8 {
+            arrays.push(reader.u32s()?);
+        }
+        let n = len + 1;
+        let vertex = arrays.remove(0);
+        if arrays.iter().any(|a| a.len() != n) || vertex.len() > n || next as usize > vertex.len() {
+            return None;
+        }
+        let mut dfnum = vec![NONE; n];
+        for (i, &v) in vertex.iter().enumerate() {
+            *dfnum.get_mut(v as usize)? = i as u32;
+        }
+        let mut arrays = arrays.into_iter();
+        let mut next_array = || arrays.next().unwrap();
+        Some(Progress {
+            next,
+            state: State {
+                dfnum,
+                vertex,
+                parent: next_array(),
+                semi: next_array(),
+                ancestor: next_array(),
+                label: next_array(),
+            },
+            idom: next_array(),
+            bucket_head: next_array(),
+            bucket_next: next_array(),
+        })
+    }
+
+    // Computes the semidominator of the node numbered `next` - 1.
+    fn step(&mut self, preds: &Adjacency, is_root: &[bool], root: u32) {
+        let Progress {
+            next,
+            state,
+            idom,
+            bucket_head,
+            bucket_next,
+        } = self;
+        *next -= 1;
+        let w = state.vertex[*next as usize];
+        let virtual_root = Some(root).filter(|_| is_root[w as usize]);
+        for v in preds.neighbors(w).chain(virtual_root) {
+            if state.dfnum[v as usize] == NONE {
+                continue;
+            }
+            let u = state.eval(v);
+            if state.semi[u as usize] < state.semi[w as usize] {
+                state.semi[w as usize] = state.semi[u as usize];
+            }
+        }
+        let s = state.vertex[state.semi[w as usize] as usize];
+        bucket_next[w as usize] = bucket_head[s as usize];
+        bucket_head[s as usize] = w;
+
+        let p = state.parent[w as usize];
+        state.ancestor[w as usize] = p;
+
+        let mut v = bucket_head[p as usize];
+        while v != NONE {
+            let u = state.eval(v);
+            idom[v as usize] = if state.semi[u as usize] < state.semi[v as usize] {
+                u
+            } else {
+                p
+            };
+            v = bucket_next[v as usize];
+        }
+        bucket_head[p as usize] = NONE;
+    }
+}
+
+pub fn build(graph: &Graph) -> DominatorTree {
+    build_resumable(graph, None, |_| {})
+}
+
+//
+// Like build(), starting from `resume` if given, and calling `checkpoint`
+// with the progress every CHECKPOINT_INTERVAL.
+//
+pub fn build_resumable(
+    graph: &Graph,
+    resume: Option<Progress>,
+    mut checkpoint: impl FnMut(&Progress),
+) -> DominatorTree {
+    let mut phase = timings::start("dominators");
+    phase.add_bytes(graph.successors.parts().1.len() as u64);
+    let n = graph.len() + 1;
+    let root = graph.len() as u32;
+
+    let mut progress = match resume {
+        Some(progress) => progress,
+        None => search(graph),
+    };
+
+    // The GC roots are also referenced by the virtual root.
+    let preds = graph.predecessors();
+    let mut is_root = vec![false; n];
+    for &r in &graph.roots {
+        is_root[r as usize] = true;
+    }
+
+    let mut last_checkpoint = Instant::now();
+    while progress.next > 1 {
+        progress.step(&preds, &is_root, root);
+        if progress.next % 65536 == 0 && last_checkpoint.elapsed() >= CHECKPOINT_INTERVAL {
+            checkpoint(&progress);
+            last_checkpoint = Instant::now();
+        }
+    }
+    let Progress {
+        state, mut idom, ..
+    } = progress;
+
+    for i in 1..state.vertex.len() {
+        let w = state.vertex[i] as usize;
+        if idom[w] != state.vertex[state.semi[w] as usize] {
+            idom[w] = idom[idom[w] as usize];
+        }
+    }
+
+    idom.pop();
+    DominatorTree {
+        idom,
+        order: state.vertex.into_iter().skip(1).collect(),
+    }
+}
+
+// The depth-first search starting the computation.
+fn search(graph: &Graph) -> Progress {
+    let n = graph.len() + 1;
+    let root = graph.len() as u32;
+
+    let mut state = State {
+        dfnum: vec![NONE; n],
+        vertex: Vec::with_capacity(n),
+        parent: vec![NONE; n],
+        semi: vec![NONE; n],
+        ancestor: vec![NONE; n],
+        label: (0..n as u32).collect(),
+    };
+
+    // Number the nodes in depth-first preorder.
+    let mut stack: Vec<(u32, u32)> = vec![(root, NONE)];
+    let mut successors = Vec::new();
+    while let Some((node, parent)) = stack.pop() {
+        if state.dfnum[node as usize] != NONE {
+            continue;
+        }
+        state.dfnum[node as usize] = state.vertex.len() as u32;
+        state.semi[node as usize] = state.vertex.len() as u32;
+        state.parent[node as usize] = parent;
+        state.vertex.push(node);
+        successors.clear();
+        if node == root {
+            successors.extend_from_slice(&graph.roots);
+        } else {
+            successors.extend(graph.successors.neighbors(node));
+        }
+        for &s in successors.iter().rev() {
+            if state.dfnum[s as usize] == NONE {
+                stack.push((s, node));
+            }
+        }
+    }
+
+    Progress {
+        next: state.vertex.len() as u32,
+        state,
+        idom: vec![NONE; n],
+        bucket_head: vec![NONE; n],
+        bucket_next: vec![NONE; n],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A graph of `n` nodes with pseudo-random references, cycles and
+    // unreachable nodes included.
+    fn random_graph(n: usize, seed: u64) -> Graph {
+        let mut x = seed;
+        let mut next = move || {
+            x = x
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (x >> 33) as usize
+        };
+        let lists: Vec<Vec<u32>> = (0..n)
+            .map(|_| {
+                let mut list: Vec<u32> = (0..next() % 4).map(|_| (next() % n) as u32).collect();
+                list.sort_unstable();
+                list.dedup();
+                list
+            })
+            .collect();
+        let mut roots: Vec<u32> = (0..n / 50).map(|_| (next() % n) as u32).collect();
+        roots.sort_unstable();
+        roots.dedup();
+        Graph {
+            successors: Adjacency::from_lists(&lists),
+            roots,
+        }
+    }
+
+    // The tree of a computation interrupted after `steps` steps, saved and
+    // resumed from what was saved.
+    fn resumed(graph: &Graph, steps: u32) -> DominatorTree {
+        let mut progress = search(graph);
+        let preds = graph.predecessors();
+        let mut is_root = vec![false; graph.len() + 1];
+        for &r in &graph.roots {
+            is_root[r as usize] = true;
+        }
+        for _ in 0..steps.min(progress.next - 1) {
+            progress.step(&preds, &is_root, graph.len() as u32);
+        }
+        let saved = Progress::decode(&progress.encode(), graph.len()).unwrap();
+        build_resumable(graph, Some(saved), |_| {})
+    }
+
+    #[test]
+    fn resumes_to_the_same_tree() {
+        for seed in 1..4 {
+            let graph = random_graph(5000, seed);
+            let whole = build(&graph);
+            let reachable = whole.order.len() as u32;
+            assert!(reachable > 100 && reachable < 5000);
+            for steps in [0, 1, reachable / 3, reachable / 2, reachable - 1, reachable] {
+                let tree = resumed(&graph, steps);
+                assert_eq!(tree.idom, whole.idom, "seed {} steps {}", seed, steps);
+                assert_eq!(tree.order, whole.order);
+            }
+        }
+    }
+
+    #[test]
+    fn progress_of_another_graph_is_refused() {
+        let graph = random_graph(1000, 1);
+        let encoded = search(&graph).encode();
+        assert!(Progress::decode(&encoded, 1000).is_some());
+        assert!(Progress::decode(&encoded, 999).is_none());
+        assert!(Progress::decode(&encoded[..encoded.len() - 1], 1000).is_none());
+        assert!(Progress::decode(&[], 1000).is_none());
+    }
+
+    #[test]
+    fn dominators_of_a_diamond() {
+        // 0 -> 1, 2; 1 -> 3; 2 -> 3; 3 -> 4. 5 is unreachable.
+        let graph = Graph {
+            successors: Adjacency::from_lists(&[
+                vec![1, 2],
+                vec![3],
+                vec![3],
+                vec![4],
+                vec![],
+                vec![],
+            ]),
+            roots: vec![0],
+        };
+        let tree = build(&graph);
+        assert_eq!(tree.idom, vec![6, 0, 0, 0, 3, NONE]);
+        assert_eq!(tree.dominators(4), vec![3, 0]);
+        assert!(!tree.is_reachable(5));
+    }
+
+    // The nodes reachable from the roots of a graph without going through `removed`.
+    fn reachable_without(graph: &Graph, removed: u32) -> Vec<bool> {
+        let mut marked = vec![false; graph.len()];
+        let mut stack: Vec<u32> = graph
+            .roots
+            .iter()
+            .copied()
+            .filter(|&r| r != removed)
+            .collect();
+        for &r in &stack {
+            marked[r as usize] = true;
+        }
+        while let Some(node) = stack.pop() {
+            for s in graph.successors.neighbors(node) {
+                if s != removed && !marked[s as usize] {
+                    marked[s as usize] = true;
+                    stack.push(s);
+                }
+            }
+        }
+        marked
+    }
+
+    #[test]
+    fn dominators_match_their_definition() {
+        // d dominates n when n is unreachable without d.
+        for seed in 1..4 {
+            let graph = random_graph(300, seed);
+            let tree = build(&graph);
+            let all = reachable_without(&graph, NONE);
+            let mut dominators = vec![Vec::new(); graph.len()];
+            for d in 0..graph.len() as u32 {
+                for (n, reachable) in reachable_without(&graph, d).into_iter().enumerate() {
+                    if all[n] && !reachable && n as u32 != d {
+                        dominators[n].push(d);
+                    }
+                }
+            }
+            for n in 0..graph.len() as u32 {
+                assert_eq!(tree.is_reachable(n), all[n as usize]);
+                let mut chain = tree.dominators(n);
+                chain.sort_unstable();
+                assert_eq!(chain, dominators[n as usize], "node {} seed {}", n, seed);
+            }
+            let position: Vec<usize> = {
+                let mut position = vec![usize::MAX; graph.len()];
+                for (i, &n) in tree.order.iter().enumerate() {
+                    position[n as usize] = i;
+                }
+                position
+            };
+            for &n in &tree.order {
+                let idom = tree.idom[n as usize];
+                assert!(
+                    idom == tree.virtual_root() || position[idom as usize] < position[n as usize]
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//
+// Enum classes: a well-behaved enum has exactly one instance per constant,
+// more usually means instances were created by a broken deserializer or
+// through reflection and pile up instead of being shared.
+//
+use crate::heap::{HeapObject, Value};
+use crate::hierarchy::Hierarchy;
+use crate::snapshot::Snapshot;
+
+#[derive(Debug)]
+pub struct EnumAudit {
+    pub class_id: u64,
+    pub class_name: String,
+    // Length of the $VALUES array, None if the dump doesn't have it.
+    pub constants: Option<u64>,
+    // Instances of the enum and of its constant-specific subclasses.
+    pub instances: u64,
+    // Retained size of the class object: its static state and constants.
+    pub retained: u64,
+}
+
+impl EnumAudit {
+    pub fn has_extra_instances(&self) -> bool {
+        self.constants.is_some_and(|c| self.instances > c)
+    }
+}
+
+//
+// Every enum class (the direct subclasses of java.lang.Enum; constants with
+// a body are anonymous subclasses of those), sorted by retained size.
+//
+pub fn audit_enums(snapshot: &Snapshot, hierarchy: &Hierarchy, retained: &[u64]) -> Vec<EnumAudit> {
+    let enum_class = match snapshot.find_class("java.lang.Enum") {
+        Some(id) => id,
+        None => return Vec::new(),
+    };
+    let mut audits: Vec<EnumAudit> = hierarchy.classes[&enum_class]
+        .subclasses
+        .iter()
+        .map(|&class_id| {
+            let class = snapshot.class_dump(class_id).unwrap();
+            let constants = class
+                .static_fields()
+                .iter()
+                .find(|f| snapshot.string(f.name_id) == "$VALUES")
+                .and_then(|f| match f.value {
+                    Value::Object(id) => snapshot.object(id),
+                    _ => None,
+                })
+                .and_then(|values| match values {
+                    HeapObject::ObjectArray(a) => Some(a.elements.len() as u64),
+                    _ => None,
+                });
+            EnumAudit {
+                class_id,
+                class_name: snapshot.class_name(class_id),
+                constants,
+                instances: hierarchy.classes[&class_id].total_instances,
+                retained: snapshot
+                    .index_of(class_id)
+                    .map(|i| retained[i as usize])
+                    .unwrap_or(0),
+            }
+        })
+        .collect();
+    audits.sort_by(|a, b| {
+        b.has_extra_instances()
+            .cmp(&a.has_extra_instances())
+            .then_with(|| b.retained.cmp(&a.retained))
+            .then_with(|| a.class_name.cmp(&b.class_name))
+    });
+    audits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::FieldTag;
+    use crate::testing::Dump;
+
+    #[test]
+    fn enums_with_more_instances_than_constants_come_first() {
+        let mut dump = Dump::new();
+        let base = dump.class(
+            "java/lang/Enum",
+            dump.object,
+            &[("name", FieldTag::NormalObject), ("ordinal", FieldTag::Int)],
+        );
+        let values = dump.next_ids(1)[0];
+        let array = dump.object_array;
+        let color = dump.class_with("test/Color", base, &[], &[("$VALUES", values)], 0);
+        // A constant with a body is an instance of a subclass.
+        let blue = dump.class("test/Color$1", color, &[]);
+        // The $VALUES array is followed by the constants it holds.
+        let ids = dump.next_ids(4);
+        dump.object_array(array, &ids[1..]);
+        dump.instance(color, &[]);
+        dump.instance(color, &[Value::Object(0), Value::Int(1)]);
+        dump.instance(blue, &[Value::Object(0), Value::Int(2)]);
+        // Made by a deserializer.
+        dump.instance(color, &[]);
+        let values = dump.next_ids(1)[0];
+        let mode = dump.class_with("test/Mode", base, &[], &[("$VALUES", values)], 0);
+        let ids = dump.next_ids(2);
+        dump.object_array(array, &ids[1..]);
+        dump.instance(mode, &[]);
+        let snapshot = dump.load();
+        let hierarchy = Hierarchy::build(&snapshot);
+        let mut retained = vec![0; snapshot.objects.len()];
+        retained[snapshot.index_of(mode).unwrap() as usize] = 1000;
+
+        let audits = audit_enums(&snapshot, &hierarchy, &retained);
+        let found: Vec<(&str, Option<u64>, u64, bool, u64)> = audits
+            .iter()
+            .map(|a| {
+                let extra = a.has_extra_instances();
+                (
+                    a.class_name.as_str(),
+                    a.constants,
+                    a.instances,
+                    extra,
+                    a.retained,
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("test.Color", Some(3), 4, true, 0),
+                ("test.Mode", Some(1), 1, false, 1000),
+            ]
+        );
+    }
+}
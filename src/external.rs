@@ -0,0 +1,392 @@
+//
+// Disk-backed graph storage for dumps whose reference graph doesn't fit in
+// memory. Adjacency lists are written to temporary files in CSR form (an
+// offset per node, then all the neighbor lists one after the other) and
+// memory-mapped back, so that the OS pages them in and out as traversals
+// need them. Lists that come out of order, like the referrers of each
+// object, go through an external merge sort that keeps a bounded number of
+// edges in memory. Built from a LazyHeap, no object is ever materialized
+// for more than the time it takes to list its references.
+//
+// Only the edges live on disk: the index entries of the objects, the mark
+// bits and the frontier of traversals stay in memory.
+//
+use crate::lazy::{LazyHeap, Mapping};
+use crate::timings;
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// A directory for the files of one analysis, removed with everything in it
+// when dropped.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(parent: &Path) -> TempDir {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "hprof-cat.{}.{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = parent.join(name);
+        fs::create_dir_all(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        TempDir { path }
+    }
+
+    pub fn file(&self, name: &str) -> PathBuf {
+        self.path.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+fn create(path: &Path) -> BufWriter<File> {
+    BufWriter::new(File::create(path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e)))
+}
+
+fn read_u64(reader: &mut impl Read) -> Option<u64> {
+    let mut buf = [0u8; 8];
+    match reader.read_exact(&mut buf) {
+        Ok(()) => Some(u64::from_le_bytes(buf)),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => None,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+//
+// Sorts u64 records with at most `capacity` of them in memory: full runs
+// are sorted and written out, then merged.
+//
+pub struct ExternalSorter<'a> {
+    dir: &'a TempDir,
+    prefix: String,
+    run: Vec<u64>,
+    capacity: usize,
+    runs: Vec<PathBuf>,
+}
+
+impl ExternalSorter<'_> {
+    pub fn new<'a>(dir: &'a TempDir, prefix: &str, capacity: usize) -> ExternalSorter<'a> {
+        ExternalSorter {
+            dir,
+            prefix: prefix.to_string(),
+            run: Vec::new(),
+            capacity: capacity.max(1),
+            runs: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, record: u64) {
+        self.run.push(record);
+        if self.run.len() == self.capacity {
+            self.spill();
+        }
+    }
+
+    fn spill(&mut self) {
+        self.run.sort_unstable();
+        let path = self
+            .dir
+            .file(&format!("{}.{}", self.prefix, self.runs.len()));
+        let mut out = create(&path);
+        for record in &self.run {
+            out.write_all(&record.to_le_bytes()).unwrap();
+        }
+        out.flush().unwrap();
+        self.run.clear();
+        self.runs.push(path);
+    }
+
+    // All the records pushed, in ascending order.
+    pub fn finish(mut self) -> Box<dyn Iterator<Item = u64>> {
+        if self.runs.is_empty() {
+            self.run.sort_unstable();
+            return Box::new(std::mem::take(&mut self.run).into_iter());
+        }
+        if !self.run.is_empty() {
+            self.spill();
+        }
+        let mut readers: Vec<BufReader<File>> = self
+            .runs
+            .iter()
+            .map(|path| BufReader::new(File::open(path).unwrap()))
+            .collect();
+        let mut heads = BinaryHeap::new();
+        for (i, reader) in readers.iter_mut().enumerate() {
+            if let Some(record) = read_u64(reader) {
+                heads.push(Reverse((record, i)));
+            }
+        }
+        Box::new(std::iter::from_fn(move || {
+            let Reverse((record, i)) = heads.pop()?;
+            if let Some(next) = read_u64(&mut readers[i]) {
+                heads.push(Reverse((next, i)));
+            }
+            Some(record)
+        }))
+    }
+}
+
+// Adjacency lists of dense node indices, in CSR files mapped into memory.
+pub struct DiskAdjacency {
+    // len() + 1 little-endian u64 offsets into `neighbors`, in entries.
+    offsets: Mapping,
+    // Little-endian u32 node indices.
+    neighbors: Mapping,
+}
+
+impl DiskAdjacency {
+    pub fn len(&self) -> usize {
+        (self.offsets.len() / 8).saturating_sub(1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn offset(&self, node: usize) -> usize {
+        u64::from_le_bytes(self.offsets[8 * node..8 * node + 8].try_into().unwrap()) as usize
+    }
+
+    pub fn neighbors(&self, node: u32) -> impl Iterator<Item = u32> + '_ {
+        let start = self.offset(node as usize);
+        let end = self.offset(node as usize + 1);
+        self.neighbors[4 * start..4 * end]
+            .chunks_exact(4)
+            .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+    }
+}
+
+// Writes the lists of nodes 0, 1, 2... in order.
+struct AdjacencyWriter {
+    offsets_path: PathBuf,
+    neighbors_path: PathBuf,
+    offsets: BufWriter<File>,
+    neighbors: BufWriter<File>,
+    count: u64,
+}
+
+impl AdjacencyWriter {
+    fn new(dir: &TempDir, name: &str) -> AdjacencyWriter {
+        let offsets_path = dir.file(&format!("{}.offsets", name));
+        let neighbors_path = dir.file(&format!("{}.neighbors", name));
+        let mut offsets = create(&offsets_path);
+        offsets.write_all(&0u64.to_le_bytes()).unwrap();
+        AdjacencyWriter {
+            offsets,
+            neighbors: create(&neighbors_path),
+            offsets_path,
+            neighbors_path,
+            count: 0,
+        }
+    }
+
+    fn push_neighbor(&mut self, neighbor: u32) {
+        self.neighbors.write_all(&neighbor.to_le_bytes()).unwrap();
+        self.count += 1;
+    }
+
+    // Ends the list of the current node.
+    fn end_list(&mut self) {
+        self.offsets.write_all(&self.count.to_le_bytes()).unwrap();
+    }
+
+    fn finish(mut self) -> DiskAdjacency {
+        self.offsets.flush().unwrap();
+        self.neighbors.flush().unwrap();
+        drop(self.offsets);
+        drop(self.neighbors);
+        DiskAdjacency {
+            offsets: Mapping::open(&self.offsets_path.to_string_lossy()),
+            neighbors: Mapping::open(&self.neighbors_path.to_string_lossy()),
+        }
+    }
+}
+
+pub struct DiskGraph {
+    pub successors: DiskAdjacency,
+    // Objects referenced by at least one GC root, without duplicates.
+    pub roots: Vec<u32>,
+    dir: TempDir,
+}
+
+impl DiskGraph {
+    // The graph of a lazily loaded heap, with its files under `parent`.
+    pub fn build(heap: &LazyHeap, parent: &Path) -> DiskGraph {
+        let mut phase = timings::start("graph");
+        let dir = TempDir::new(parent);
+        let mut writer = AdjacencyWriter::new(&dir, "successors");
+        let mut succ = Vec::new();
+        for node in 0..heap.len() as u32 {
+            succ.clear();
+            succ.extend(
+                heap.outgoing_references(&heap.object(node))
+                    .into_iter()
+                    .filter_map(|id| heap.object_index.get(&id).copied()),
+            );
+            succ.sort_unstable();
+            succ.dedup();
+            for &s in &succ {
+                writer.push_neighbor(s);
+            }
+            writer.end_list();
+        }
+        let mut roots: Vec<u32> = heap
+            .roots
+            .iter()
+            .filter_map(|r| heap.object_index.get(&r.object_id).copied())
+            .collect();
+        roots.sort_unstable();
+        roots.dedup();
+        phase.add_bytes(4 * writer.count);
+        DiskGraph {
+            successors: writer.finish(),
+            roots,
+            dir,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.successors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.successors.is_empty()
+    }
+
+    //
+    // The referrers of each object, in ascending order, sorting the
+    // reversed edges with at most `memory` of them in memory at a time.
+    //
+    pub fn predecessors(&self, memory: usize) -> DiskAdjacency {
+        let mut phase = timings::start("reverse index");
+        phase.add_bytes(self.successors.neighbors.len() as u64);
+        let mut sorter = ExternalSorter::new(&self.dir, "reversed", memory);
+        for node in 0..self.len() as u32 {
+            for s in self.successors.neighbors(node) {
+                sorter.push((s as u64) << 32 | node as u64);
+            }
+        }
+        let mut writer = AdjacencyWriter::new(&self.dir, "predecessors");
+        let mut current = 0u32;
+        for edge in sorter.finish() {
+            let (target, source) = ((edge >> 32) as u32, edge as u32);
+            while current < target {
+                writer.end_list();
+                current += 1;
+            }
+            writer.push_neighbor(source);
+        }
+        while (current as usize) < self.len() {
+            writer.end_list();
+            current += 1;
+        }
+        writer.finish()
+    }
+
+    // Like reachability::mark().
+    pub fn mark(&self) -> Vec<bool> {
+        let _phase = timings::start("mark");
+        let mut marked = vec![false; self.len()];
+        let mut stack: Vec<u32> = Vec::new();
+        for &r in &self.roots {
+            if !marked[r as usize] {
+                marked[r as usize] = true;
+                stack.push(r);
+            }
+        }
+        while let Some(node) = stack.pop() {
+            for s in self.successors.neighbors(node) {
+                if !marked[s as usize] {
+                    marked[s as usize] = true;
+                    stack.push(s);
+                }
+            }
+        }
+        marked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::Graph;
+    use crate::heap::{FieldTag, GcRootKind, Value};
+    use crate::reachability;
+    use crate::snapshot::Snapshot;
+    use crate::testing::{Dump, TempFile};
+    use crate::window::Window;
+
+    #[test]
+    fn sorted_runs_merge_in_order() {
+        let dir = TempDir::new(&std::env::temp_dir());
+        let records: Vec<u64> = (0..1000u64).map(|i| i * 7919 % 1009).collect();
+        let mut expected = records.clone();
+        expected.sort_unstable();
+        for capacity in [10, 1000, 5000] {
+            let mut sorter = ExternalSorter::new(&dir, &format!("test{}", capacity), capacity);
+            for &record in &records {
+                sorter.push(record);
+            }
+            assert_eq!(sorter.finish().collect::<Vec<u64>>(), expected);
+        }
+        let path = dir.file("test10.0");
+        assert!(path.exists());
+        drop(dir);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn disk_graphs_are_the_graphs_in_memory() {
+        let mut dump = Dump::new();
+        let node = dump.class(
+            "test/Node",
+            dump.object,
+            &[
+                ("next", FieldTag::NormalObject),
+                ("other", FieldTag::NormalObject),
+            ],
+        );
+        let mut nodes: Vec<u64> = Vec::new();
+        for i in 0..2_000 {
+            let next = nodes.last().copied().unwrap_or(0);
+            let other = nodes.get(i / 3).copied().unwrap_or(0);
+            nodes.push(dump.instance(node, &[Value::Object(next), Value::Object(other)]));
+            if i % 500 == 0 {
+                dump.root(GcRootKind::JniGlobal, nodes[i]);
+            }
+        }
+        // Garbage, which nothing refers to.
+        dump.instance(node, &[Value::Object(nodes[10]), Value::Object(0)]);
+        let file = TempFile::new(&dump.bytes());
+        let heap = LazyHeap::open(file.path(), &Window::default()).unwrap();
+        let disk = DiskGraph::build(&heap, &std::env::temp_dir());
+        let graph = Graph::build(&Snapshot::load(file.path()));
+        assert_eq!(disk.len(), graph.len());
+        assert_eq!(disk.roots, graph.roots);
+
+        let preds = graph.predecessors();
+        let disk_preds = disk.predecessors(1000);
+        assert_eq!(disk_preds.len(), graph.len());
+        for node in 0..graph.len() as u32 {
+            assert!(disk
+                .successors
+                .neighbors(node)
+                .eq(graph.successors.neighbors(node)));
+            assert!(disk_preds.neighbors(node).eq(preds.neighbors(node)));
+        }
+        assert_eq!(disk.mark(), reachability::mark(&graph));
+    }
+}
@@ -0,0 +1,347 @@
+//
+// A smaller dump to share, around some of the objects of another: the
+// objects, stubs of the objects they reference (instances of the same
+// class with their fields zeroed, empty arrays), and the class dumps that
+// all of them and the stack frames need, superclasses included.
+// References to anything else, from the class dumps too, become nulls,
+// and the GC roots and symbols (class and field names) of what is left
+// out go with it. The objects that no kept root reaches then get a
+// ROOT_UNKNOWN root, for what they hold to be reachable in the extract.
+//
+use crate::graph;
+use crate::heap::{
+    GcRoot, GcRootKind, HeapObject, InstanceDump, ObjectArrayDump, PrimitiveArrayDump, Value,
+};
+use crate::idhash::IdSet;
+use crate::snapshot::Snapshot;
+use crate::writer::{Renumbering, Writer};
+
+use std::io::{self, Write};
+
+#[derive(Debug, Default)]
+pub struct Extract {
+    pub objects: usize,
+    pub stubs: usize,
+    pub classes: usize,
+    // The objects given a ROOT_UNKNOWN root.
+    pub unrooted: usize,
+}
+
+// The object with what it holds left out.
+fn stub(object: &HeapObject) -> HeapObject {
+    match object {
+        HeapObject::Instance(i) => HeapObject::Instance(InstanceDump {
+            object_id: i.object_id,
+            strace_num: i.strace_num,
+            class_id: i.class_id,
+            data: vec![0; i.data.len()],
+        }),
+        HeapObject::ObjectArray(a) => HeapObject::ObjectArray(ObjectArrayDump {
+            object_id: a.object_id,
+            strace_num: a.strace_num,
+            class_id: a.class_id,
+            elements: Vec::new(),
+        }),
+        HeapObject::PrimitiveArray(a) => HeapObject::PrimitiveArray(PrimitiveArrayDump {
+            object_id: a.object_id,
+            strace_num: a.strace_num,
+            element_tag: a.element_tag,
+            length: 0,
+            data: Vec::new(),
+        }),
+        HeapObject::Class(_) => unreachable!("classes are kept whole"),
+    }
+}
+
+// The class of the object and its superclasses, into `classes`.
+fn add_classes(snapshot: &Snapshot, mut class_id: u64, classes: &mut IdSet<u64>) {
+    while class_id != 0 && classes.insert(class_id) {
+        class_id = snapshot
+            .class_dump(class_id)
+            .map_or(0, |c| c.super_class_id);
+    }
+}
+
+//
+// The objects that the kept roots don't reach through the kept references,
+// to be given roots: those that no kept object references first, then one
+// for each cycle of objects only referencing each other.
+//
+fn unrooted(
+    snapshot: &Snapshot,
+    objects: &[&HeapObject],
+    class_dumps: &[&HeapObject],
+    kept: &IdSet<u64>,
+    roots: &[&GcRoot],
+) -> Vec<u64> {
+    // Stubs are written without their references.
+    let expanded: IdSet<u64> = objects
+        .iter()
+        .chain(class_dumps)
+        .map(|o| o.object_id())
+        .collect();
+    let referenced: IdSet<u64> = objects
+        .iter()
+        .chain(class_dumps)
+        .flat_map(|o| graph::outgoing_references(snapshot, o))
+        .collect();
+    let mut candidates = objects
+        .iter()
+        .filter(|o| !referenced.contains(&o.object_id()))
+        .chain(objects);
+
+    let mut unrooted = Vec::new();
+    let mut reached = IdSet::default();
+    let mut pending: Vec<u64> = roots.iter().map(|r| r.object_id).collect();
+    loop {
+        while let Some(id) = pending.pop() {
+            if !reached.insert(id) || !expanded.contains(&id) {
+                continue;
+            }
+            if let Some(object) = snapshot.object(id) {
+                let targets = graph::outgoing_references(snapshot, object);
+                pending.extend(targets.into_iter().filter(|t| kept.contains(t)));
+            }
+        }
+        match candidates.find(|o| !reached.contains(&o.object_id())) {
+            Some(object) => {
+                unrooted.push(object.object_id());
+                pending.push(object.object_id());
+            }
+            None => return unrooted,
+        }
+    }
+}
+
+//
+// Writes the objects (other than class dumps) for which `selected` is
+// true, with what they need.
+//
+pub fn extract<W: Write, F: Fn(&HeapObject) -> bool>(
+    snapshot: &Snapshot,
+    selected: F,
+    out: W,
+) -> io::Result<(W, Extract)> {
+    let mut objects = Vec::new();
+    let mut stubs = IdSet::default();
+    let mut classes = IdSet::default();
+    for object in &snapshot.objects {
+        if matches!(object, HeapObject::Class(_)) || !selected(object) {
+            continue;
+        }
+        objects.push(object);
+        if let Some(class_id) = snapshot.class_of(object) {
+            add_classes(snapshot, class_id, &mut classes);
+        }
+        for target in graph::outgoing_references(snapshot, object) {
+            match snapshot.object(target) {
+                Some(HeapObject::Class(c)) => add_classes(snapshot, c.class_id, &mut classes),
+                Some(o) if !selected(o) => {
+                    stubs.insert(target);
+                }
+                _ => {}
+            }
+        }
+    }
+    for &id in &stubs {
+        if let Some(class_id) = snapshot.object(id).and_then(|o| snapshot.class_of(o)) {
+            add_classes(snapshot, class_id, &mut classes);
+        }
+    }
+    // The classes of the stack frames, whose LOAD_CLASS records they need.
+    for frame in snapshot.frames.values() {
+        if let Some(class) = snapshot.classes.get(&frame.class_serial_num) {
+            add_classes(snapshot, class.object_id, &mut classes);
+        }
+    }
+    let kept: IdSet<u64> = objects
+        .iter()
+        .map(|o| o.object_id())
+        .chain(stubs.iter().copied())
+        .chain(classes.iter().copied())
+        .collect();
+
+    // What is referenced and left out becomes null.
+    let mut renumbering = Renumbering::default();
+    let mut null = |id: u64| {
+        if id != 0 && !kept.contains(&id) {
+            renumbering.objects.insert(id, 0);
+        }
+    };
+    let class_dumps: Vec<&HeapObject> = snapshot
+        .objects
+        .iter()
+        .filter(|o| matches!(o, HeapObject::Class(_)) && kept.contains(&o.object_id()))
+        .collect();
+    for &object in objects.iter().chain(&class_dumps) {
+        for target in graph::outgoing_references(snapshot, object) {
+            null(target);
+        }
+    }
+    for thread in &snapshot.threads {
+        null(thread.thread_object_id);
+    }
+
+    let roots: Vec<&GcRoot> = snapshot
+        .roots
+        .iter()
+        .filter(|r| kept.contains(&r.object_id))
+        .collect();
+    let unrooted = unrooted(snapshot, &objects, &class_dumps, &kept, &roots);
+
+    let mut symbols = IdSet::default();
+    let mut serials: Vec<u32> = Vec::new();
+    for (&serial, class) in &snapshot.classes {
+        if kept.contains(&class.object_id) {
+            serials.push(serial);
+            symbols.insert(class.strname_id);
+        }
+    }
+    serials.sort_unstable();
+    for object in &class_dumps {
+        if let HeapObject::Class(class) = object {
+            symbols.extend(class.static_fields().iter().map(|f| f.name_id));
+            symbols.extend(class.instance_fields.iter().map(|f| f.name_id));
+        }
+    }
+    for frame in snapshot.frames.values() {
+        symbols.extend([
+            frame.method_name_id,
+            frame.method_sign_id,
+            frame.source_name_id,
+        ]);
+    }
+    for thread in &snapshot.threads {
+        symbols.extend([
+            thread.thread_name_id,
+            thread.group_name_id,
+            thread.parent_group_name_id,
+        ]);
+    }
+    let mut strings: Vec<(u64, &str)> = snapshot
+        .strings
+        .iter()
+        .filter(|(id, _)| symbols.contains(id))
+        .collect();
+    strings.sort_unstable();
+    let mut frames: Vec<_> = snapshot.frames.values().collect();
+    frames.sort_unstable_by_key(|f| f.frame_id);
+
+    let mut writer = Writer::new(out, snapshot.id_size(), snapshot.header.timestamp_ms())?;
+    writer.renumber(renumbering);
+    for (id, value) in strings {
+        writer.utf8_string(id, value)?;
+    }
+    for serial in &serials {
+        writer.load_class(&snapshot.classes[serial])?;
+    }
+    for thread in &snapshot.threads {
+        writer.start_thread(thread)?;
+    }
+    for frame in frames {
+        writer.stack_frame(frame)?;
+    }
+    for trace in &snapshot.traces {
+        writer.stack_trace(trace)?;
+    }
+    for object in &class_dumps {
+        writer.object(object)?;
+    }
+    for root in roots {
+        writer.root(root)?;
+    }
+    for &object_id in &unrooted {
+        writer.root(&GcRoot {
+            kind: GcRootKind::Unknown,
+            object_id,
+            thread_serial_num: None,
+            frame_num: None,
+            strace_num: None,
+        })?;
+    }
+    for object in &objects {
+        match object {
+            HeapObject::Instance(i) => {
+                let values: Vec<Value> = snapshot
+                    .instance_fields(i)
+                    .into_iter()
+                    .map(|f| f.value)
+                    .collect();
+                writer.instance_dump_values(i, &values)?;
+            }
+            _ => writer.object(object)?,
+        }
+    }
+    let mut stubbed: Vec<u64> = stubs.iter().copied().collect();
+    stubbed.sort_unstable_by_key(|&id| snapshot.index_of(id));
+    for id in stubbed {
+        if let Some(object) = snapshot.object(id) {
+            writer.object(&stub(object))?;
+        }
+    }
+    let extract = Extract {
+        objects: objects.len(),
+        stubs: stubs.len(),
+        classes: class_dumps.len(),
+        unrooted: unrooted.len(),
+    };
+    Ok((writer.finish()?, extract))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dominator;
+    use crate::graph::Graph;
+    use crate::synthetic::{self, Options};
+
+    use std::fs;
+
+    fn load(bytes: &[u8], name: &str) -> Snapshot {
+        let file = format!("hprof-cat-extract-{}-{}.hprof", std::process::id(), name);
+        let path = std::env::temp_dir().join(file);
+        let path = path.to_str().unwrap();
+        fs::write(path, bytes).unwrap();
+        let snapshot = Snapshot::load(path);
+        fs::remove_file(path).unwrap();
+        snapshot
+    }
+
+    #[test]
+    fn extracted_objects_are_reachable() {
+        let options = Options {
+            classes: 10,
+            objects: 2000,
+            ..Options::default()
+        };
+        let original = load(
+            &synthetic::generate(&options, Vec::new()).unwrap(),
+            "original",
+        );
+        let strings = |o: &HeapObject| {
+            matches!(
+                original.object_class_name(o).as_str(),
+                "java.lang.String" | "byte[]"
+            )
+        };
+        let (bytes, extract) = super::extract(&original, strings, Vec::new()).unwrap();
+        let extracted = load(&bytes, "extracted");
+
+        let selected = original.objects.iter().filter(|o| strings(o)).count();
+        assert_eq!(extract.objects, selected);
+        // The byte[] of a string is reached through it.
+        assert!(extract.unrooted > 0 && extract.unrooted < selected);
+        let unknown = extracted
+            .roots
+            .iter()
+            .filter(|r| r.kind == GcRootKind::Unknown)
+            .count();
+        assert_eq!(unknown, extract.unrooted);
+        let tree = dominator::build(&Graph::build(&extracted));
+        for (node, object) in extracted.objects.iter().enumerate() {
+            if !matches!(object, HeapObject::Class(_)) {
+                assert!(tree.is_reachable(node as u32), "{:#x}", object.object_id());
+            }
+        }
+    }
+}
@@ -0,0 +1,193 @@
+//
+// Objects registered for finalization (java.lang.ref.Finalizer) or with a
+// cleaner (java.lang.ref.Cleaner, jdk.internal.ref.Cleaner). These can only
+// be freed once the finalizer or reference handler thread got to them, so a
+// backlog shows up as "garbage" that never goes away.
+//
+use crate::graph::Graph;
+use crate::heap::{HeapObject, Value};
+use crate::reachability;
+use crate::reference::{ReferenceKind, RetentionFilter};
+use crate::snapshot::Snapshot;
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Mechanism {
+    Finalizer,
+    Cleaner,
+}
+
+impl Mechanism {
+    pub fn name(self) -> &'static str {
+        match self {
+            Mechanism::Finalizer => "finalizer",
+            Mechanism::Cleaner => "cleaner",
+        }
+    }
+}
+
+const CLASSES: &[(&str, Mechanism)] = &[
+    ("java.lang.ref.Finalizer", Mechanism::Finalizer),
+    ("jdk.internal.ref.PhantomCleanable", Mechanism::Cleaner),
+    ("jdk.internal.ref.Cleaner", Mechanism::Cleaner),
+    ("sun.misc.Cleaner", Mechanism::Cleaner),
+];
+
+#[derive(Debug)]
+pub struct Registered {
+    pub mechanism: Mechanism,
+    pub class_name: String,
+    pub registered: u64,
+    // Objects that are no longer strongly reachable, i.e. waiting for their
+    // finalizer or cleaning action to run.
+    pub pending: u64,
+    // Retained bytes of the pending objects.
+    pub pending_retained: u64,
+}
+
+//
+// Registered objects grouped by mechanism and class, sorted by the bytes
+// pending. `retained` is the retained size of every object.
+//
+pub fn registered_objects(snapshot: &Snapshot, retained: &[u64]) -> Vec<Registered> {
+    let mut mechanisms = HashMap::new();
+    for &(name, mechanism) in CLASSES {
+        for class_id in snapshot.subclasses(name) {
+            mechanisms.insert(class_id, mechanism);
+        }
+    }
+    let filter = RetentionFilter::new(snapshot, ReferenceKind::all());
+    let strongly = reachability::mark(&Graph::build_filtered(snapshot, &filter));
+
+    let mut groups: HashMap<(Mechanism, String), Registered> = HashMap::new();
+    for (node, object) in snapshot.objects.iter().enumerate() {
+        // Registrations that are garbage themselves won't run any more.
+        if !strongly[node] {
+            continue;
+        }
+        let (instance, mechanism) = match object {
+            HeapObject::Instance(i) => match mechanisms.get(&i.class_id) {
+                Some(&m) => (i, m),
+                None => continue,
+            },
+            _ => continue,
+        };
+        let referent = match snapshot
+            .field_value(instance, "referent")
+            .and_then(|v| v.as_object())
+            .and_then(|id| snapshot.index_of(id))
+        {
+            Some(referent) => referent,
+            None => continue,
+        };
+        let class_name = snapshot.object_class_name(&snapshot.objects[referent as usize]);
+        let group = groups
+            .entry((mechanism, class_name.clone()))
+            .or_insert_with(|| Registered {
+                mechanism,
+                class_name,
+                registered: 0,
+                pending: 0,
+                pending_retained: 0,
+            });
+        group.registered += 1;
+        if !strongly[referent as usize] {
+            group.pending += 1;
+            group.pending_retained += retained[referent as usize];
+        }
+    }
+    let mut groups: Vec<Registered> = groups.into_values().collect();
+    groups.sort_by(|a, b| {
+        b.pending_retained
+            .cmp(&a.pending_retained)
+            .then(b.registered.cmp(&a.registered))
+            .then_with(|| a.class_name.cmp(&b.class_name))
+    });
+    groups
+}
+
+// The number of references waiting in the queue of the finalizer thread.
+pub fn finalizer_queue_length(snapshot: &Snapshot) -> Option<i64> {
+    let class = snapshot.class_dump(snapshot.find_class("java.lang.ref.Finalizer")?)?;
+    let queue = class
+        .static_fields()
+        .iter()
+        .find(|f| snapshot.string(f.name_id) == "queue")?
+        .value
+        .as_object()?;
+    match snapshot.object(queue)? {
+        HeapObject::Instance(q) => match snapshot.field_value(q, "queueLength")? {
+            Value::Long(n) => Some(n),
+            Value::Int(n) => Some(n as i64),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dominator;
+    use crate::heap::{FieldTag, GcRootKind};
+    use crate::retained::retained_sizes;
+    use crate::testing::Dump;
+
+    #[test]
+    fn unreachable_referents_are_pending() {
+        let mut dump = Dump::new();
+        let object = dump.object;
+        let reference = dump.class(
+            "java/lang/ref/Reference",
+            object,
+            &[("referent", FieldTag::NormalObject)],
+        );
+        let final_reference = dump.class("java/lang/ref/FinalReference", reference, &[]);
+        let queue_class = dump.class(
+            "java/lang/ref/ReferenceQueue",
+            object,
+            &[("queueLength", FieldTag::Long)],
+        );
+        let queue = dump.instance(queue_class, &[Value::Long(1)]);
+        let finalizer = dump.class_with(
+            "java/lang/ref/Finalizer",
+            final_reference,
+            &[],
+            &[("queue", queue)],
+            0,
+        );
+        let resource = dump.class(
+            "test/Resource",
+            object,
+            &[("buffer", FieldTag::ArrayObject)],
+        );
+        let mut resources = Vec::new();
+        for rooted in [false, true] {
+            let buffer = dump.primitive_array(FieldTag::Byte, &[0; 64]);
+            let instance = dump.instance(resource, &[Value::Object(buffer)]);
+            let registration = dump.instance(finalizer, &[Value::Object(instance)]);
+            dump.root(GcRootKind::JniGlobal, registration);
+            if rooted {
+                dump.root(GcRootKind::JniGlobal, instance);
+            }
+            resources.push(instance);
+        }
+        // A registration that is garbage itself.
+        let instance = dump.instance(resource, &[]);
+        dump.instance(finalizer, &[Value::Object(instance)]);
+        let snapshot = dump.load();
+        let tree = dominator::build(&Graph::build(&snapshot));
+        let retained = retained_sizes(&snapshot, &tree);
+
+        let groups = registered_objects(&snapshot, &retained);
+        assert_eq!(groups.len(), 1);
+        let group = &groups[0];
+        assert_eq!(group.mechanism, Mechanism::Finalizer);
+        assert_eq!(group.class_name, "test.Resource");
+        assert_eq!((group.registered, group.pending), (2, 1));
+        let pending = snapshot.index_of(resources[0]).unwrap();
+        assert_eq!(group.pending_retained, retained[pending as usize]);
+        assert_eq!(finalizer_queue_length(&snapshot), Some(1));
+    }
+}
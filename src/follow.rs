@@ -0,0 +1,227 @@
+//
+// Reading dumps that are still being written: jmap on a large heap takes
+// minutes to finish the file, and following it lets the scan of the
+// top-level records keep up with the writer instead of starting once it is
+// done. At the end of the file a following reader waits for more, and only
+// reports the end when the file has not grown for the quiet period (or it
+// was told to stop following, once HEAP_DUMP_END was read).
+//
+use crate::archive;
+use crate::records::{parse_header, parse_record, RecordTag};
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+
+// The quiet period in milliseconds, 0 when not following.
+static QUIET: AtomicU64 = AtomicU64::new(0);
+
+// How often a file at its end is looked at again.
+const POLL: Duration = Duration::from_millis(100);
+
+pub const DEFAULT_QUIET: Duration = Duration::from_secs(60);
+
+pub fn enable(quiet: Duration) {
+    QUIET.store(quiet.as_millis().max(1) as u64, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    QUIET.load(Ordering::Relaxed) > 0
+}
+
+pub struct Follow {
+    file: File,
+    quiet: Option<Duration>,
+    pos: u64,
+    // The end of a dump in an archive.
+    end: Option<u64>,
+}
+
+impl Follow {
+    //
+    // Opens a dump (see archive.rs), following it when enabled; a file
+    // that doesn't exist yet is waited for as long as it would be waited
+    // for to grow.
+    //
+    pub fn open(filename: &str) -> io::Result<Follow> {
+        let quiet = match QUIET.load(Ordering::Relaxed) {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        };
+        let mut waited = Duration::ZERO;
+        loop {
+            match archive::open(filename) {
+                Ok((file, location)) => {
+                    let range = location.range;
+                    return Ok(Follow {
+                        file,
+                        quiet,
+                        pos: range.as_ref().map_or(0, |r| r.start),
+                        end: range.map(|r| r.end),
+                    });
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound && quiet > Some(waited) => {
+                    thread::sleep(POLL);
+                    waited += POLL;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // The end of the file is the end of the dump from now on.
+    pub fn stop(&mut self) {
+        self.quiet = None;
+    }
+
+    // Where the dump ends in the file, for now when it is being written.
+    pub fn end(&self) -> io::Result<u64> {
+        match self.end {
+            Some(end) => Ok(end),
+            None => Ok(self.file.metadata()?.len()),
+        }
+    }
+
+    //
+    // The end of the dump once it goes to `pos` at least, waited for while
+    // following; before `pos` when it stopped growing for the quiet period
+    // (or when not following), that is when the dump is cut short there.
+    //
+    pub fn wait_for(&self, pos: u64) -> io::Result<u64> {
+        let mut end = self.end()?;
+        let mut idle = Duration::ZERO;
+        while let Some(quiet) = self.quiet {
+            if end >= pos || idle >= quiet {
+                break;
+            }
+            thread::sleep(POLL);
+            let grown = self.end()?;
+            if grown != end {
+                end = grown;
+                idle = Duration::ZERO;
+            } else {
+                idle += POLL;
+            }
+        }
+        Ok(end)
+    }
+}
+
+// The error of a dump cut short in the record at `pos`.
+pub fn cut_short(filename: &str, pos: u64) -> String {
+    format!(
+        "{}: the dump is cut short in the record at {}",
+        filename, pos
+    )
+}
+
+//
+// Waits for a dump being written to be complete, going through the record
+// headers as they come, for the readers that need all of the file at once.
+//
+pub fn wait_for_end(filename: &str) -> Result<(), String> {
+    let f = Follow::open(filename).map_err(|e| format!("{}: {}", filename, e))?;
+    let mut reader = BufReader::new(f);
+    parse_header(&mut reader).map_err(|e| format!("{}: {}", filename, e))?;
+    loop {
+        let pos = reader.stream_position().unwrap();
+        let record = match parse_record(&mut reader) {
+            Ok(Some(record)) => record,
+            Ok(None) => return Ok(()),
+            Err(_) => return Err(cut_short(filename, pos)),
+        };
+        if record.tag == Some(RecordTag::HeapDumpEnd) {
+            return Ok(());
+        }
+        let body = pos + 9 + record.bytes as u64;
+        if reader.get_ref().wait_for(body).map_err(|e| e.to_string())? < body {
+            return Err(cut_short(filename, pos));
+        }
+        reader.seek_relative(record.bytes as i64).unwrap();
+    }
+}
+
+impl Read for Follow {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let buf = match self.end {
+            Some(end) => {
+                let left = end.saturating_sub(self.pos).min(buf.len() as u64);
+                &mut buf[..left as usize]
+            }
+            None => buf,
+        };
+        // The file is only looked at again at its end, while following.
+        let mut len = None;
+        let mut idle = Duration::ZERO;
+        loop {
+            let n = self.file.read(buf)?;
+            self.pos += n as u64;
+            let quiet = match self.quiet {
+                Some(quiet) if n == 0 && !buf.is_empty() => quiet,
+                _ => return Ok(n),
+            };
+            let grown = self.file.metadata()?.len();
+            if len == Some(grown) {
+                idle += POLL;
+            } else {
+                len = Some(grown);
+                idle = Duration::ZERO;
+            }
+            if idle >= quiet {
+                return Ok(0);
+            }
+            thread::sleep(POLL);
+        }
+    }
+}
+
+impl Seek for Follow {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = self.file.seek(pos)?;
+        Ok(self.pos)
+    }
+
+    // Without a system call, for the position of every record.
+    fn stream_position(&mut self) -> io::Result<u64> {
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempFile;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    #[test]
+    fn files_are_waited_for_while_followed() {
+        let file = TempFile::new(&[0; 100]);
+        let follow = |quiet| Follow {
+            file: File::open(file.path()).unwrap(),
+            quiet,
+            pos: 0,
+            end: None,
+        };
+        assert_eq!(follow(None).wait_for(200).unwrap(), 100);
+
+        let path = file.path().to_string();
+        let writer = thread::spawn(move || {
+            thread::sleep(2 * POLL);
+            let mut f = OpenOptions::new().append(true).open(path).unwrap();
+            f.write_all(&[0; 150]).unwrap();
+        });
+        assert!(follow(Some(Duration::from_secs(10))).wait_for(200).unwrap() >= 200);
+        writer.join().unwrap();
+        // Short of it once the file stopped growing.
+        assert_eq!(follow(Some(2 * POLL)).wait_for(300).unwrap(), 250);
+
+        let mut reader = follow(Some(2 * POLL));
+        reader.seek(SeekFrom::Start(240)).unwrap();
+        let mut read = Vec::new();
+        assert_eq!(reader.read_to_end(&mut read).unwrap(), 10);
+        assert_eq!(reader.stream_position().unwrap(), 250);
+    }
+}
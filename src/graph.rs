@@ -0,0 +1,506 @@
+//
+// The object reference graph. Nodes are the dense indices of the objects
+// in Snapshot::objects so that per-object data can be kept in plain vectors.
+//
+use crate::heap::HeapObject;
+use crate::parallel;
+use crate::reference::RetentionFilter;
+use crate::snapshot::Snapshot;
+use crate::timings;
+
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+//
+// Adjacency lists in compressed sparse row form: the neighbors of all the
+// nodes one list after the other in a byte array, each sorted list stored
+// as the LEB128 varints of the differences between consecutive neighbors
+// (the first one from 0), with the offset of each list. Close neighbors,
+// the common case since dumps list objects in address order, take a byte
+// or two instead of four plus a Vec per node.
+//
+pub struct Adjacency {
+    // len() + 1 offsets into `bytes`.
+    offsets: Vec<u64>,
+    bytes: Vec<u8>,
+}
+
+fn encode_list(bytes: &mut Vec<u8>, list: &[u32]) {
+    let mut previous = 0;
+    for &n in list {
+        debug_assert!(n >= previous, "adjacency lists must be sorted");
+        let mut delta = n - previous;
+        while delta >= 0x80 {
+            bytes.push(delta as u8 | 0x80);
+            delta >>= 7;
+        }
+        bytes.push(delta as u8);
+        previous = n;
+    }
+}
+
+impl Adjacency {
+    //
+    // The adjacency of nodes 0..len whose sorted neighbors are given by
+    // `neighbors`, encoded one chunk of nodes per core.
+    //
+    pub fn build<F>(len: usize, neighbors: F) -> Adjacency
+    where
+        F: Fn(usize) -> Vec<u32> + Sync,
+    {
+        Adjacency::build_sized(len, 0, neighbors)
+    }
+
+    //
+    // The same, knowing there are about `edges` neighbors in all: each takes
+    // at least a byte, so each chunk starts with room for its share of them.
+    //
+    pub fn build_sized<F>(len: usize, edges: usize, neighbors: F) -> Adjacency
+    where
+        F: Fn(usize) -> Vec<u32> + Sync,
+    {
+        let chunks = parallel::map_chunks(len, |range| {
+            let mut offsets = Vec::with_capacity(range.len());
+            let mut bytes = Vec::with_capacity(edges / len.max(1) * range.len());
+            for n in range {
+                encode_list(&mut bytes, &neighbors(n));
+                offsets.push(bytes.len() as u64);
+            }
+            (offsets, bytes)
+        });
+        let mut offsets = Vec::with_capacity(len + 1);
+        offsets.push(0);
+        let mut bytes = Vec::with_capacity(chunks.iter().map(|(_, b)| b.len()).sum());
+        for (chunk_offsets, chunk_bytes) in chunks {
+            let base = bytes.len() as u64;
+            offsets.extend(chunk_offsets.into_iter().map(|o| base + o));
+            bytes.extend_from_slice(&chunk_bytes);
+        }
+        Adjacency { offsets, bytes }
+    }
+
+    // The offsets and bytes, to save them.
+    pub fn parts(&self) -> (&[u64], &[u8]) {
+        (&self.offsets, &self.bytes)
+    }
+
+    pub fn from_parts(offsets: Vec<u64>, bytes: Vec<u8>) -> Adjacency {
+        Adjacency { offsets, bytes }
+    }
+
+    // The lists must be sorted.
+    pub fn from_lists(lists: &[Vec<u32>]) -> Adjacency {
+        Adjacency::build(lists.len(), |n| lists[n].clone())
+    }
+
+    pub fn len(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // The neighbors of a node, in ascending order.
+    pub fn neighbors(&self, node: u32) -> Neighbors<'_> {
+        let start = self.offsets[node as usize] as usize;
+        let end = self.offsets[node as usize + 1] as usize;
+        Neighbors {
+            bytes: &self.bytes[start..end],
+            previous: 0,
+        }
+    }
+
+    pub fn degree(&self, node: u32) -> usize {
+        // Every varint ends with a byte below 0x80.
+        let start = self.offsets[node as usize] as usize;
+        let end = self.offsets[node as usize + 1] as usize;
+        self.bytes[start..end].iter().filter(|&&b| b < 0x80).count()
+    }
+}
+
+pub struct Neighbors<'a> {
+    bytes: &'a [u8],
+    previous: u32,
+}
+
+impl Iterator for Neighbors<'_> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        let mut delta = 0u32;
+        let mut shift = 0;
+        loop {
+            let (&byte, rest) = self.bytes.split_first()?;
+            self.bytes = rest;
+            delta |= ((byte & 0x7f) as u32) << shift;
+            if byte < 0x80 {
+                break;
+            }
+            shift += 7;
+        }
+        self.previous += delta;
+        Some(self.previous)
+    }
+}
+
+pub struct Graph {
+    pub successors: Adjacency,
+    // Objects referenced by at least one GC root, without duplicates.
+    pub roots: Vec<u32>,
+}
+
+// How an object holds on to one of the objects it points to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Via {
+    // An instance field as (declaring class id, field name id).
+    Field(u64, u64),
+    StaticField(u64),
+    Element(u32),
+    Class,
+    SuperClass,
+    ClassLoader,
+    Signers,
+    ProtectionDomain,
+    ConstantPool(u16),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Reference {
+    pub target: u64,
+    pub via: Via,
+}
+
+//
+// All the objects that an object points to. Instances also point to
+// their class and classes point to their superclass, class loader and
+// the objects referenced by their static fields and constant pool.
+//
+pub fn references(snapshot: &Snapshot, object: &HeapObject) -> Vec<Reference> {
+    let mut refs = Vec::new();
+    let mut push = |target: u64, via: Via| {
+        if target != 0 {
+            refs.push(Reference { target, via });
+        }
+    };
+    match object {
+        HeapObject::Class(c) => {
+            push(c.super_class_id, Via::SuperClass);
+            push(c.class_loader_id, Via::ClassLoader);
+            push(c.signers_id, Via::Signers);
+            push(c.protection_domain_id, Via::ProtectionDomain);
+            for f in c.static_fields() {
+                if let Some(target) = f.value.as_object() {
+                    push(target, Via::StaticField(f.name_id));
+                }
+            }
+            for (index, v) in c.constant_pool() {
+                if let Some(target) = v.as_object() {
+                    push(target, Via::ConstantPool(*index));
+                }
+            }
+        }
+        HeapObject::Instance(i) => {
+            push(i.class_id, Via::Class);
+            for f in snapshot.instance_fields(i) {
+                if let Some(target) = f.value.as_object() {
+                    push(target, Via::Field(f.class_id, f.name_id));
+                }
+            }
+        }
+        HeapObject::ObjectArray(a) => {
+            push(a.class_id, Via::Class);
+            for (n, &target) in a.elements.iter().enumerate() {
+                push(target, Via::Element(n as u32));
+            }
+        }
+        HeapObject::PrimitiveArray(_) => {}
+    }
+    refs
+}
+
+pub fn outgoing_references(snapshot: &Snapshot, object: &HeapObject) -> Vec<u64> {
+    references(snapshot, object)
+        .into_iter()
+        .map(|r| r.target)
+        .collect()
+}
+
+//
+// Every reference to an object, as (referrer, how it refers to the
+// object), using the reverse-reference index from Graph::predecessors().
+//
+pub fn incoming_references(snapshot: &Snapshot, preds: &Adjacency, node: u32) -> Vec<(u32, Via)> {
+    let target = snapshot.objects[node as usize].object_id();
+    let mut incoming = Vec::new();
+    for p in preds.neighbors(node) {
+        for r in references(snapshot, &snapshot.objects[p as usize]) {
+            if r.target == target {
+                incoming.push((p, r.via));
+            }
+        }
+    }
+    incoming
+}
+
+// Like via_name() but instance fields are qualified with their declaring class.
+pub fn qualified_via_name(snapshot: &Snapshot, via: Via) -> String {
+    match via {
+        Via::Field(class_id, name_id) => format!(
+            "{}.{}",
+            snapshot.class_name(class_id),
+            snapshot.string(name_id)
+        ),
+        _ => via_name(snapshot, via),
+    }
+}
+
+// A short description of a reference, e.g. `value`, `static cache` or `[13]`.
+pub fn via_name(snapshot: &Snapshot, via: Via) -> String {
+    match via {
+        Via::Field(_, name_id) => snapshot.string(name_id).to_string(),
+        Via::StaticField(name_id) => format!("static {}", snapshot.string(name_id)),
+        Via::Element(n) => format!("[{}]", n),
+        Via::Class => "<class>".to_string(),
+        Via::SuperClass => "<super>".to_string(),
+        Via::ClassLoader => "<classloader>".to_string(),
+        Via::Signers => "<signers>".to_string(),
+        Via::ProtectionDomain => "<protection domain>".to_string(),
+        Via::ConstantPool(n) => format!("<constant pool #{}>", n),
+    }
+}
+
+impl Graph {
+    pub fn build(snapshot: &Snapshot) -> Graph {
+        Graph::build_filtered(snapshot, &RetentionFilter::new(snapshot, Vec::new()))
+    }
+
+    //
+    // Like build() but without the references that the filter considers as
+    // not retaining, so that retained sizes and reachability computed from
+    // the graph treat e.g. weak references as if they weren't there.
+    //
+    pub fn build_filtered(snapshot: &Snapshot, filter: &RetentionFilter) -> Graph {
+        let mut phase = timings::start("graph");
+        // The references of each object only depend on the object.
+        let successors = Adjacency::build(snapshot.objects.len(), |i| {
+            let object = &snapshot.objects[i];
+            let targets: Vec<u64> = if filter.excluded_kind(object).is_some() {
+                references(snapshot, object)
+                    .into_iter()
+                    .filter(|r| filter.retains(snapshot, object, r.via))
+                    .map(|r| r.target)
+                    .collect()
+            } else {
+                outgoing_references(snapshot, object)
+            };
+            let mut succ: Vec<u32> = targets
+                .into_iter()
+                .filter_map(|id| snapshot.index_of(id))
+                .collect();
+            succ.sort_unstable();
+            succ.dedup();
+            succ
+        });
+
+        let mut roots: Vec<u32> = snapshot
+            .roots
+            .iter()
+            .filter_map(|r| snapshot.index_of(r.object_id))
+            .collect();
+        roots.sort_unstable();
+        roots.dedup();
+
+        phase.add_bytes(successors.bytes.len() as u64);
+        Graph { successors, roots }
+    }
+
+    pub fn len(&self) -> usize {
+        self.successors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.successors.is_empty()
+    }
+
+    //
+    // The referrers of each object, in ascending order. Edges are counted
+    // and scattered into one flat array in parallel, then each object's
+    // slice is sorted since the threads fill them in any order.
+    //
+    pub fn predecessors(&self) -> Adjacency {
+        let mut phase = timings::start("reverse index");
+        phase.add_bytes(self.successors.bytes.len() as u64);
+        let counts: Vec<AtomicUsize> = (0..self.len()).map(|_| AtomicUsize::new(0)).collect();
+        parallel::map_chunks(self.len(), |range| {
+            for n in range {
+                for s in self.successors.neighbors(n as u32) {
+                    counts[s as usize].fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        });
+        let mut offsets = Vec::with_capacity(self.len() + 1);
+        let mut total = 0usize;
+        offsets.push(0);
+        for count in &counts {
+            total += count.load(Ordering::Relaxed);
+            offsets.push(total);
+        }
+        // From here on, where the next referrer of each object goes.
+        for (count, &offset) in counts.iter().zip(&offsets) {
+            count.store(offset, Ordering::Relaxed);
+        }
+        let flat: Vec<AtomicU32> = (0..total).map(|_| AtomicU32::new(0)).collect();
+        parallel::map_chunks(self.len(), |range| {
+            for n in range {
+                for s in self.successors.neighbors(n as u32) {
+                    let slot = counts[s as usize].fetch_add(1, Ordering::Relaxed);
+                    flat[slot].store(n as u32, Ordering::Relaxed);
+                }
+            }
+        });
+        Adjacency::build_sized(self.len(), total, |n| {
+            let mut preds: Vec<u32> = flat[offsets[n]..offsets[n + 1]]
+                .iter()
+                .map(|p| p.load(Ordering::Relaxed))
+                .collect();
+            preds.sort_unstable();
+            preds
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::{FieldTag, GcRootKind, Value};
+    use crate::testing::Dump;
+
+    #[test]
+    fn incoming_references_list_every_referrer() {
+        let mut dump = Dump::new();
+        let pair = dump.class(
+            "test/Pair",
+            dump.object,
+            &[
+                ("first", FieldTag::NormalObject),
+                ("second", FieldTag::NormalObject),
+            ],
+        );
+        let target = dump.instance(dump.object, &[]);
+        let pair = dump.instance(pair, &[Value::Object(target), Value::Object(target)]);
+        let array = dump.object_array(dump.object_array, &[0, target]);
+        dump.root(GcRootKind::JniGlobal, pair);
+        dump.root(GcRootKind::JniGlobal, array);
+        let snapshot = dump.load();
+        let graph = Graph::build(&snapshot);
+        let preds = graph.predecessors();
+
+        let index = |id: u64| snapshot.index_of(id).unwrap();
+        let incoming: Vec<(u64, String)> = incoming_references(&snapshot, &preds, index(target))
+            .into_iter()
+            .map(|(from, via)| {
+                (
+                    snapshot.objects[from as usize].object_id(),
+                    via_name(&snapshot, via),
+                )
+            })
+            .collect();
+        assert_eq!(
+            incoming,
+            vec![
+                (pair, "first".to_string()),
+                (pair, "second".to_string()),
+                (array, "[1]".to_string()),
+            ]
+        );
+        // Nothing refers to the pair, only a root.
+        assert!(graph.roots.contains(&index(pair)));
+        assert!(preds.neighbors(index(pair)).next().is_none());
+    }
+
+    #[test]
+    fn fields_are_named_by_their_declaring_class() {
+        let mut dump = Dump::new();
+        let base = dump.class("test/Base", dump.object, &[("x", FieldTag::NormalObject)]);
+        let derived = dump.class("test/Derived", base, &[("x", FieldTag::NormalObject)]);
+        let own = dump.instance(dump.object, &[]);
+        let inherited = dump.instance(dump.object, &[]);
+        let object = dump.instance(derived, &[Value::Object(own), Value::Object(inherited)]);
+        let snapshot = dump.load();
+
+        let object = &snapshot.objects[snapshot.index_of(object).unwrap() as usize];
+        let named: Vec<(u64, String)> = references(&snapshot, object)
+            .into_iter()
+            .map(|r| (r.target, qualified_via_name(&snapshot, r.via)))
+            .collect();
+        assert_eq!(
+            named,
+            vec![
+                (derived, "<class>".to_string()),
+                (own, "test.Derived.x".to_string()),
+                (inherited, "test.Base.x".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn predecessors_reverse_the_successors() {
+        let mut dump = Dump::new();
+        let node = dump.class(
+            "test/Node",
+            dump.object,
+            &[
+                ("next", FieldTag::NormalObject),
+                ("other", FieldTag::NormalObject),
+            ],
+        );
+        let mut nodes: Vec<u64> = Vec::new();
+        for i in 0..10_000 {
+            let next = nodes.last().copied().unwrap_or(0);
+            let other = nodes.get(i / 2).copied().unwrap_or(0);
+            nodes.push(dump.instance(node, &[Value::Object(next), Value::Object(other)]));
+        }
+        dump.root(GcRootKind::JniGlobal, *nodes.last().unwrap());
+        let graph = Graph::build(&dump.load());
+        assert!(parallel::chunks(graph.len(), 4).len() > 1);
+
+        let mut expected = vec![Vec::new(); graph.len()];
+        for n in 0..graph.len() as u32 {
+            for s in graph.successors.neighbors(n) {
+                expected[s as usize].push(n);
+            }
+        }
+        let preds = graph.predecessors();
+        assert_eq!(preds.len(), graph.len());
+        for (n, expected) in expected.iter().enumerate() {
+            let found: Vec<u32> = preds.neighbors(n as u32).collect();
+            assert_eq!(&found, expected, "{}", n);
+            assert_eq!(preds.degree(n as u32), expected.len());
+        }
+    }
+
+    #[test]
+    fn adjacency_round_trips_its_lists() {
+        let lists: Vec<Vec<u32>> = (0..10_000u32)
+            .map(|n| match n % 4 {
+                0 => Vec::new(),
+                1 => vec![n, n, n + 1],
+                2 => vec![0, 127, 128, 16_384, u32::MAX],
+                _ => (n..n + 10).collect(),
+            })
+            .collect();
+        let adjacency = Adjacency::from_lists(&lists);
+        assert_eq!(adjacency.len(), lists.len());
+        for (n, list) in lists.iter().enumerate() {
+            let found: Vec<u32> = adjacency.neighbors(n as u32).collect();
+            assert_eq!(&found, list);
+            assert_eq!(adjacency.degree(n as u32), list.len());
+        }
+        // Deltas of 1 take a byte each.
+        let (offsets, bytes) = adjacency.parts();
+        assert_eq!(offsets[4] - offsets[3], 10);
+        let copy = Adjacency::from_parts(offsets.to_vec(), bytes.to_vec());
+        assert!(copy.neighbors(6).eq(lists[6].iter().copied()));
+        assert!(Adjacency::from_lists(&[]).is_empty());
+    }
+}
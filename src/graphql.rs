@@ -0,0 +1,919 @@
+//
+// GraphQL documents: the query language of serve's /graphql endpoint. The
+// executable part of the language is parsed (operations with variables,
+// fields with aliases and arguments, named and inline fragments, @skip and
+// @include) into selections that the executor resolves against the heap.
+// Type system definitions and subscriptions are not supported.
+//
+use crate::json::Json;
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Bool(bool),
+    Enum(String),
+    List(Vec<Value>),
+    Object(Vec<(String, Value)>),
+    Variable(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub alias: Option<String>,
+    pub name: String,
+    pub arguments: Vec<(String, Value)>,
+    pub directives: Vec<Directive>,
+    pub selections: Vec<Selection>,
+}
+
+impl Field {
+    // The name of the field in the response.
+    pub fn key(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Directive {
+    pub name: String,
+    pub arguments: Vec<(String, Value)>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Selection {
+    Field(Field),
+    FragmentSpread(String, Vec<Directive>),
+    InlineFragment(Option<String>, Vec<Directive>, Vec<Selection>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Operation {
+    pub kind: String,
+    pub name: Option<String>,
+    // The declared variables with their default values.
+    pub variables: Vec<(String, Option<Value>)>,
+    pub selections: Vec<Selection>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Fragment {
+    pub type_condition: String,
+    pub selections: Vec<Selection>,
+}
+
+#[derive(Debug, Default)]
+pub struct Document {
+    pub operations: Vec<Operation>,
+    pub fragments: HashMap<String, Fragment>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Punctuator(char),
+    Spread,
+    Name(String),
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            // Commas are insignificant, like whitespace.
+            _ if c.is_whitespace() || c == ',' || c == '\u{feff}' => i += 1,
+            '#' => {
+                while i < chars.len() && chars[i] != '\n' && chars[i] != '\r' {
+                    i += 1;
+                }
+            }
+            '!' | '$' | '&' | '(' | ')' | ':' | '=' | '@' | '[' | ']' | '{' | '|' | '}' => {
+                tokens.push(Token::Punctuator(c));
+                i += 1;
+            }
+            '.' if chars[i..].starts_with(&['.', '.', '.']) => {
+                tokens.push(Token::Spread);
+                i += 3;
+            }
+            '"' if chars[i..].starts_with(&['"', '"', '"']) => {
+                let start = i + 3;
+                let mut end = start;
+                while end < chars.len() && !chars[end..].starts_with(&['"', '"', '"']) {
+                    end += if chars[end..].starts_with(&['\\', '"', '"', '"']) {
+                        4
+                    } else {
+                        1
+                    };
+                }
+                if end >= chars.len() {
+                    return Err("unterminated block string".to_string());
+                }
+                let raw: String = chars[start..end].iter().collect();
+                tokens.push(Token::Str(block_string(&raw.replace("\\\"\"\"", "\"\"\""))));
+                i = end + 3;
+            }
+            '"' => {
+                let mut s = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None | Some('\n') | Some('\r') => {
+                            return Err("unterminated string".to_string())
+                        }
+                        Some('"') => break,
+                        Some('\\') => {
+                            let escaped = chars.get(i + 1).copied();
+                            i += 2;
+                            s.push(match escaped {
+                                Some('"') => '"',
+                                Some('\\') => '\\',
+                                Some('/') => '/',
+                                Some('b') => '\u{8}',
+                                Some('f') => '\u{c}',
+                                Some('n') => '\n',
+                                Some('r') => '\r',
+                                Some('t') => '\t',
+                                Some('u') => {
+                                    let mut code = hex4(&chars, i)?;
+                                    i += 4;
+                                    if (0xd800..0xdc00).contains(&code)
+                                        && chars[i..].starts_with(&['\\', 'u'])
+                                    {
+                                        let low = hex4(&chars, i + 2)?;
+                                        if (0xdc00..0xe000).contains(&low) {
+                                            code =
+                                                0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                                            i += 6;
+                                        }
+                                    }
+                                    char::from_u32(code)
+                                        .ok_or_else(|| format!("bad escape \\u{:04x}", code))?
+                                }
+                                other => return Err(format!("bad escape \\{:?}", other)),
+                            });
+                            continue;
+                        }
+                        Some(&c) => s.push(c),
+                    }
+                    i += 1;
+                }
+                tokens.push(Token::Str(s));
+                i += 1;
+            }
+            '-' | '0'..='9' => {
+                let start = i;
+                i += 1;
+                while i < chars.len()
+                    && (chars[i].is_ascii_digit()
+                        || matches!(chars[i], '.' | 'e' | 'E')
+                        || (matches!(chars[i], '+' | '-') && matches!(chars[i - 1], 'e' | 'E')))
+                {
+                    i += 1;
+                }
+                let number: String = chars[start..i].iter().collect();
+                tokens.push(if number.contains(['.', 'e', 'E']) {
+                    Token::Float(
+                        number
+                            .parse()
+                            .map_err(|_| format!("bad number {}", number))?,
+                    )
+                } else {
+                    Token::Int(
+                        number
+                            .parse()
+                            .map_err(|_| format!("bad number {}", number))?,
+                    )
+                });
+            }
+            _ if c == '_' || c.is_ascii_alphabetic() => {
+                let start = i;
+                while i < chars.len() && (chars[i] == '_' || chars[i].is_ascii_alphanumeric()) {
+                    i += 1;
+                }
+                tokens.push(Token::Name(chars[start..i].iter().collect()));
+            }
+            _ => return Err(format!("unexpected character {:?}", c)),
+        }
+    }
+    Ok(tokens)
+}
+
+// The four hex digits of a \\u escape at `i`.
+fn hex4(chars: &[char], i: usize) -> Result<u32, String> {
+    let hex: String = chars.iter().skip(i).take(4).collect();
+    if hex.len() == 4 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(u32::from_str_radix(&hex, 16).unwrap())
+    } else {
+        Err(format!("bad escape \\u{}", hex))
+    }
+}
+
+// The value of a block string: common indentation and blank first and
+// last lines removed.
+fn block_string(raw: &str) -> String {
+    let lines: Vec<&str> = raw.lines().collect();
+    let indent = lines
+        .iter()
+        .skip(1)
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.len() - l.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    let mut lines: Vec<&str> = lines
+        .iter()
+        .enumerate()
+        .map(|(i, l)| {
+            if i == 0 {
+                l
+            } else {
+                l.get(indent..).unwrap_or("")
+            }
+        })
+        .collect();
+    while lines.first().is_some_and(|l| l.trim().is_empty()) {
+        lines.remove(0);
+    }
+    while lines.last().is_some_and(|l| l.trim().is_empty()) {
+        lines.pop();
+    }
+    lines.join("\n")
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    // Selection sets and values being parsed, one inside the other.
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self.peek().cloned().ok_or("unexpected end of document")?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn at(&self, c: char) -> bool {
+        self.peek() == Some(&Token::Punctuator(c))
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        match self.next()? {
+            Token::Punctuator(p) if p == c => Ok(()),
+            token => Err(format!("expected {:?}, found {:?}", c, token)),
+        }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        match self.next()? {
+            Token::Name(name) => Ok(name),
+            token => Err(format!("expected a name, found {:?}", token)),
+        }
+    }
+
+    fn document(&mut self) -> Result<Document, String> {
+        let mut document = Document::default();
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Punctuator('{') => document.operations.push(Operation {
+                    kind: "query".to_string(),
+                    name: None,
+                    variables: Vec::new(),
+                    selections: self.selection_set()?,
+                }),
+                Token::Name(keyword) if keyword == "fragment" => {
+                    self.position += 1;
+                    let name = self.name()?;
+                    if self.name()? != "on" {
+                        return Err(format!("fragment {} needs a type condition", name));
+                    }
+                    let type_condition = self.name()?;
+                    self.directives()?;
+                    let selections = self.selection_set()?;
+                    document.fragments.insert(
+                        name,
+                        Fragment {
+                            type_condition,
+                            selections,
+                        },
+                    );
+                }
+                Token::Name(keyword)
+                    if matches!(keyword.as_str(), "query" | "mutation" | "subscription") =>
+                {
+                    let kind = keyword.clone();
+                    self.position += 1;
+                    let name = match self.peek() {
+                        Some(Token::Name(_)) => Some(self.name()?),
+                        _ => None,
+                    };
+                    let variables = self.variable_definitions()?;
+                    self.directives()?;
+                    document.operations.push(Operation {
+                        kind,
+                        name,
+                        variables,
+                        selections: self.selection_set()?,
+                    });
+                }
+                token => return Err(format!("unexpected {:?}", token)),
+            }
+        }
+        Ok(document)
+    }
+
+    fn variable_definitions(&mut self) -> Result<Vec<(String, Option<Value>)>, String> {
+        let mut variables = Vec::new();
+        if !self.at('(') {
+            return Ok(variables);
+        }
+        self.position += 1;
+        while !self.at(')') {
+            self.expect('$')?;
+            let name = self.name()?;
+            self.expect(':')?;
+            self.skip_type()?;
+            let default = if self.at('=') {
+                self.position += 1;
+                Some(self.value(true)?)
+            } else {
+                None
+            };
+            self.directives()?;
+            variables.push((name, default));
+        }
+        self.position += 1;
+        Ok(variables)
+    }
+
+    // Types of variables aren't checked, values are coerced where used.
+    fn skip_type(&mut self) -> Result<(), String> {
+        if self.at('[') {
+            self.position += 1;
+            self.skip_type()?;
+            self.expect(']')?;
+        } else {
+            self.name()?;
+        }
+        if self.at('!') {
+            self.position += 1;
+        }
+        Ok(())
+    }
+
+    fn nest(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > 256 {
+            return Err("nested too deeply".to_string());
+        }
+        Ok(())
+    }
+
+    fn selection_set(&mut self) -> Result<Vec<Selection>, String> {
+        self.expect('{')?;
+        self.nest()?;
+        let mut selections = Vec::new();
+        while !self.at('}') {
+            selections.push(self.selection()?);
+        }
+        self.position += 1;
+        self.depth -= 1;
+        if selections.is_empty() {
+            return Err("empty selection set".to_string());
+        }
+        Ok(selections)
+    }
+
+    fn selection(&mut self) -> Result<Selection, String> {
+        if self.peek() == Some(&Token::Spread) {
+            self.position += 1;
+            return match self.peek() {
+                Some(Token::Name(name)) if name != "on" => {
+                    let name = self.name()?;
+                    Ok(Selection::FragmentSpread(name, self.directives()?))
+                }
+                Some(Token::Name(_)) => {
+                    self.position += 1;
+                    let type_condition = self.name()?;
+                    let directives = self.directives()?;
+                    Ok(Selection::InlineFragment(
+                        Some(type_condition),
+                        directives,
+                        self.selection_set()?,
+                    ))
+                }
+                _ => {
+                    let directives = self.directives()?;
+                    Ok(Selection::InlineFragment(
+                        None,
+                        directives,
+                        self.selection_set()?,
+                    ))
+                }
+            };
+        }
+        let mut name = self.name()?;
+        let mut alias = None;
+        if self.at(':') {
+            self.position += 1;
+            alias = Some(name);
+            name = self.name()?;
+        }
+        let arguments = self.arguments(false)?;
+        let directives = self.directives()?;
+        let selections = if self.at('{') {
+            self.selection_set()?
+        } else {
+            Vec::new()
+        };
+        Ok(Selection::Field(Field {
+            alias,
+            name,
+            arguments,
+            directives,
+            selections,
+        }))
+    }
+
+    fn arguments(&mut self, constant: bool) -> Result<Vec<(String, Value)>, String> {
+        let mut arguments = Vec::new();
+        if !self.at('(') {
+            return Ok(arguments);
+        }
+        self.position += 1;
+        while !self.at(')') {
+            let name = self.name()?;
+            self.expect(':')?;
+            arguments.push((name, self.value(constant)?));
+        }
+        self.position += 1;
+        Ok(arguments)
+    }
+
+    fn directives(&mut self) -> Result<Vec<Directive>, String> {
+        let mut directives = Vec::new();
+        while self.at('@') {
+            self.position += 1;
+            let name = self.name()?;
+            directives.push(Directive {
+                name,
+                arguments: self.arguments(false)?,
+            });
+        }
+        Ok(directives)
+    }
+
+    fn value(&mut self, constant: bool) -> Result<Value, String> {
+        self.nest()?;
+        let value = match self.next()? {
+            Token::Punctuator('$') if !constant => Value::Variable(self.name()?),
+            Token::Int(v) => Value::Int(v),
+            Token::Float(v) => Value::Float(v),
+            Token::Str(s) => Value::Str(s),
+            Token::Name(name) => match name.as_str() {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                "null" => Value::Null,
+                _ => Value::Enum(name),
+            },
+            Token::Punctuator('[') => {
+                let mut items = Vec::new();
+                while !self.at(']') {
+                    items.push(self.value(constant)?);
+                }
+                self.position += 1;
+                Value::List(items)
+            }
+            Token::Punctuator('{') => {
+                let mut members = Vec::new();
+                while !self.at('}') {
+                    let name = self.name()?;
+                    self.expect(':')?;
+                    members.push((name, self.value(constant)?));
+                }
+                self.position += 1;
+                Value::Object(members)
+            }
+            token => return Err(format!("unexpected {:?} in a value", token)),
+        };
+        self.depth -= 1;
+        Ok(value)
+    }
+}
+
+pub fn parse(text: &str) -> Result<Document, String> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        position: 0,
+        depth: 0,
+    };
+    let document = parser.document()?;
+    if document.operations.is_empty() {
+        return Err("no operation in the document".to_string());
+    }
+    for name in document.fragments.keys() {
+        let mut spreading = vec![name.as_str()];
+        check_spreads(
+            &document,
+            &document.fragments[name].selections,
+            &mut spreading,
+        )?;
+    }
+    Ok(document)
+}
+
+// Fails if the fragments spread in `selections` end up spreading themselves.
+fn check_spreads<'a>(
+    document: &'a Document,
+    selections: &'a [Selection],
+    spreading: &mut Vec<&'a str>,
+) -> Result<(), String> {
+    for selection in selections {
+        match selection {
+            Selection::Field(field) => check_spreads(document, &field.selections, spreading)?,
+            Selection::InlineFragment(_, _, selections) => {
+                check_spreads(document, selections, spreading)?
+            }
+            Selection::FragmentSpread(name, _) => {
+                if spreading.contains(&name.as_str()) {
+                    return Err(format!("fragment {} spreads itself", name));
+                }
+                if let Some(fragment) = document.fragments.get(name) {
+                    spreading.push(name);
+                    check_spreads(document, &fragment.selections, spreading)?;
+                    spreading.pop();
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Document {
+    // The operation to run: the one named, or the only one.
+    pub fn operation(&self, name: Option<&str>) -> Result<&Operation, String> {
+        match name {
+            Some(name) => self
+                .operations
+                .iter()
+                .find(|o| o.name.as_deref() == Some(name))
+                .ok_or_else(|| format!("no operation named {}", name)),
+            None if self.operations.len() == 1 => Ok(&self.operations[0]),
+            None => Err("several operations, give operationName".to_string()),
+        }
+    }
+}
+
+// A variable from the JSON of a request.
+pub fn from_json(json: &Json) -> Value {
+    match json {
+        Json::Null => Value::Null,
+        Json::Bool(b) => Value::Bool(*b),
+        Json::Number(n) => match n.parse() {
+            Ok(v) => Value::Int(v),
+            Err(_) => Value::Float(n.parse().unwrap_or(f64::NAN)),
+        },
+        Json::Str(s) => Value::Str(s.clone()),
+        Json::Array(items) => Value::List(items.iter().map(from_json).collect()),
+        Json::Object(members) => Value::Object(
+            members
+                .iter()
+                .map(|(name, value)| (name.clone(), from_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+//
+// The variables of an operation: those given, else their defaults. Missing
+// ones are left out and read as null.
+//
+pub fn variables(operation: &Operation, given: Option<&Json>) -> HashMap<String, Value> {
+    let mut values = HashMap::new();
+    for (name, default) in &operation.variables {
+        match given.and_then(|g| g.get(name)) {
+            Some(value) => {
+                values.insert(name.clone(), from_json(value));
+            }
+            None => {
+                if let Some(default) = default {
+                    values.insert(name.clone(), default.clone());
+                }
+            }
+        }
+    }
+    values
+}
+
+// A value with its variables replaced by their values.
+pub fn substitute(value: &Value, variables: &HashMap<String, Value>) -> Value {
+    match value {
+        Value::Variable(name) => variables.get(name).cloned().unwrap_or(Value::Null),
+        Value::List(items) => Value::List(items.iter().map(|v| substitute(v, variables)).collect()),
+        Value::Object(members) => Value::Object(
+            members
+                .iter()
+                .map(|(name, v)| (name.clone(), substitute(v, variables)))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}
+
+//
+// Whether @skip or @include leave the selection out.
+//
+pub fn skipped(directives: &[Directive], variables: &HashMap<String, Value>) -> bool {
+    directives.iter().any(|d| {
+        let condition = d
+            .arguments
+            .iter()
+            .find(|(name, _)| name == "if")
+            .map(|(_, v)| substitute(v, variables));
+        match d.name.as_str() {
+            "skip" => condition == Some(Value::Bool(true)),
+            "include" => condition != Some(Value::Bool(true)),
+            _ => false,
+        }
+    })
+}
+
+//
+// The fields of a selection set for an object of type `type_name`, with the
+// fragments spread and the skipped selections left out, in order.
+//
+pub fn collect_fields<'a>(
+    document: &'a Document,
+    selections: &'a [Selection],
+    type_name: &str,
+    variables: &HashMap<String, Value>,
+    fields: &mut Vec<&'a Field>,
+) {
+    for selection in selections {
+        match selection {
+            Selection::Field(field) => {
+                if !skipped(&field.directives, variables) {
+                    fields.push(field);
+                }
+            }
+            Selection::FragmentSpread(name, directives) => {
+                if skipped(directives, variables) {
+                    continue;
+                }
+                if let Some(fragment) = document.fragments.get(name) {
+                    if fragment.type_condition == type_name {
+                        collect_fields(
+                            document,
+                            &fragment.selections,
+                            type_name,
+                            variables,
+                            fields,
+                        );
+                    }
+                }
+            }
+            Selection::InlineFragment(condition, directives, selections) => {
+                if skipped(directives, variables)
+                    || condition.as_ref().is_some_and(|c| c != type_name)
+                {
+                    continue;
+                }
+                collect_fields(document, selections, type_name, variables, fields);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(selections: &[Selection]) -> Vec<&Field> {
+        selections
+            .iter()
+            .map(|s| match s {
+                Selection::Field(field) => field,
+                other => panic!("{:?}", other),
+            })
+            .collect()
+    }
+
+    fn argument(text: &str) -> Result<Value, String> {
+        let document = parse(&format!("{{ f(a: {}) }}", text))?;
+        Ok(fields(&document.operations[0].selections)[0].arguments[0]
+            .1
+            .clone())
+    }
+
+    #[test]
+    fn queries() {
+        let document = parse(
+            r#"
+            # The biggest classes.
+            query Big($min: Int = 1024, $names: [String!]!) @cached {
+              top: classes(minBytes: $min, names: $names, order: BYTES) {
+                name
+                instances, bytes
+                ... on Class @include(if: true) { id }
+                ...More
+              }
+            }
+            fragment More on Class { loader { name } }
+            { heap { objects } }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(document.operations.len(), 2);
+        let big = document.operation(Some("Big")).unwrap();
+        assert_eq!(big.kind, "query");
+        assert_eq!(
+            big.variables,
+            vec![
+                ("min".to_string(), Some(Value::Int(1024))),
+                ("names".to_string(), None)
+            ]
+        );
+        let top = fields(&big.selections)[0];
+        assert_eq!((top.key(), top.name.as_str()), ("top", "classes"));
+        assert_eq!(
+            top.arguments,
+            vec![
+                ("minBytes".to_string(), Value::Variable("min".to_string())),
+                ("names".to_string(), Value::Variable("names".to_string())),
+                ("order".to_string(), Value::Enum("BYTES".to_string())),
+            ]
+        );
+        assert_eq!(top.selections.len(), 5);
+        let mut collected = Vec::new();
+        let variables = variables(big, None);
+        collect_fields(
+            &document,
+            &top.selections,
+            "Class",
+            &variables,
+            &mut collected,
+        );
+        let names: Vec<&str> = collected.iter().map(|f| f.key()).collect();
+        assert_eq!(names, ["name", "instances", "bytes", "id", "loader"]);
+        collected.clear();
+        collect_fields(
+            &document,
+            &top.selections,
+            "Object",
+            &variables,
+            &mut collected,
+        );
+        assert_eq!(collected.len(), 3);
+
+        assert_eq!(
+            document.operation(None).unwrap_err(),
+            "several operations, give operationName"
+        );
+        assert_eq!(
+            document.operation(Some("Small")).unwrap_err(),
+            "no operation named Small"
+        );
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(argument("-12"), Ok(Value::Int(-12)));
+        assert_eq!(argument("1.5e3"), Ok(Value::Float(1500.0)));
+        assert_eq!(argument("null"), Ok(Value::Null));
+        assert_eq!(
+            argument(r#"[1, "two", {three: false}]"#),
+            Ok(Value::List(vec![
+                Value::Int(1),
+                Value::Str("two".to_string()),
+                Value::Object(vec![("three".to_string(), Value::Bool(false))]),
+            ]))
+        );
+        assert_eq!(
+            argument("\"\"\"\n    block\n      indented \\\"\"\"\n    \"\"\""),
+            Ok(Value::Str("block\n  indented \"\"\"".to_string()))
+        );
+    }
+
+    #[test]
+    fn string_escapes() {
+        assert_eq!(
+            argument(r#""a\"b\\c\/d\b\f\n\r\t""#),
+            Ok(Value::Str("a\"b\\c/d\u{8}\u{c}\n\r\t".to_string()))
+        );
+        assert_eq!(
+            argument(r#""\u0041\u00E9\u20ac""#),
+            Ok(Value::Str("Aé€".to_string()))
+        );
+        assert_eq!(
+            argument(r#""\ud83d\ude00""#),
+            Ok(Value::Str("😀".to_string()))
+        );
+        assert_eq!(
+            argument(r#""\ud83d""#),
+            Err("bad escape \\ud83d".to_string())
+        );
+        assert_eq!(
+            argument(r#""\ude00""#),
+            Err("bad escape \\ude00".to_string())
+        );
+        assert_eq!(
+            argument(r#""\u41""#),
+            Err("bad escape \\u41\")".to_string())
+        );
+        assert_eq!(
+            argument(r#""\u+041""#),
+            Err("bad escape \\u+041".to_string())
+        );
+        assert_eq!(
+            argument(r#""\q""#),
+            Err("bad escape \\Some('q')".to_string())
+        );
+    }
+
+    #[test]
+    fn variables_and_directives() {
+        let document =
+            parse("query($s: Boolean = false, $n: Int) { a @skip(if: $s) b @include(if: $n) }")
+                .unwrap();
+        let operation = document.operation(None).unwrap();
+        let given = crate::json::parse(r#"{"s": true, "n": 3}"#).unwrap();
+        let variables = variables(operation, Some(&given));
+        assert_eq!(variables["s"], Value::Bool(true));
+        assert_eq!(variables["n"], Value::Int(3));
+        let selected = fields(&operation.selections);
+        assert!(skipped(&selected[0].directives, &variables));
+        // @include needs true itself, not a truthy value.
+        assert!(skipped(&selected[1].directives, &variables));
+        let defaults = super::variables(operation, None);
+        assert!(!skipped(&selected[0].directives, &defaults));
+        assert!(!defaults.contains_key("n"));
+    }
+
+    #[test]
+    fn nesting() {
+        let nested = |depth| "{ a ".repeat(depth) + &"}".repeat(depth);
+        let mut document = parse(&nested(200)).unwrap();
+        let mut selections = document.operations.pop().unwrap().selections;
+        for _ in 0..200 {
+            selections = match selections.pop() {
+                Some(Selection::Field(field)) => field.selections,
+                other => panic!("{:?}", other),
+            };
+        }
+        assert!(selections.is_empty());
+        assert_eq!(parse(&nested(100_000)).unwrap_err(), "nested too deeply");
+        let list = format!("{{ f(a: {}{}) }}", "[".repeat(100_000), "]".repeat(100_000));
+        assert_eq!(parse(&list).unwrap_err(), "nested too deeply");
+    }
+
+    #[test]
+    fn malformed() {
+        for (text, error) in [
+            ("", "no operation in the document"),
+            (
+                "fragment F on Class { name }",
+                "no operation in the document",
+            ),
+            ("{ }", "empty selection set"),
+            ("{ a", "unexpected end of document"),
+            ("{ a(x: ) }", "unexpected Punctuator(')') in a value"),
+            ("{ a(x 1) }", "expected ':', found Int(1)"),
+            ("query Q($x) { a }", "expected ':', found Punctuator(')')"),
+            ("{ a } }", "unexpected Punctuator('}')"),
+            ("{ \"a\" }", "expected a name, found Str(\"a\")"),
+            ("{ a(x: \"b) }", "unterminated string"),
+            ("{ a(x: \"\"\"b) }", "unterminated block string"),
+            ("{ a(x: 1.2.3) }", "bad number 1.2.3"),
+            (
+                "{ a(x: 99999999999999999999) }",
+                "bad number 99999999999999999999",
+            ),
+            ("{ a ? }", "unexpected character '?'"),
+            (
+                "fragment F Class { a } { b }",
+                "fragment F needs a type condition",
+            ),
+            (
+                "{ ...A } fragment A on T { ...B } fragment B on T { ...A }",
+                "fragment A spreads itself",
+            ),
+        ] {
+            let result = parse(text).map(|_| ());
+            let result = result.map_err(|e| e.replace("fragment B", "fragment A"));
+            assert_eq!(result, Err(error.to_string()), "{:?}", text);
+        }
+    }
+}
@@ -0,0 +1,842 @@
+//
+// Parsing of the sub-records found in the bodies of HEAP_DUMP and
+// HEAP_DUMP_SEGMENT records (GC roots, class/instance/array dumps).
+//
+use num_enum::TryFromPrimitive;
+
+use crate::records::{read_id, read_ids, read_u16, read_u32, read_u64, read_u8, skip_bytes};
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{BufRead, Read};
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, TryFromPrimitive)]
+#[repr(u8)]
+pub enum FieldTag {
+    ArrayObject = 0x01,
+    NormalObject = 0x02,
+    Boolean = 0x04,
+    Char = 0x05,
+    Float = 0x06,
+    Double = 0x07,
+    Byte = 0x08,
+    Short = 0x09,
+    Int = 0x0A,
+    Long = 0x0B,
+}
+
+impl FieldTag {
+    pub fn size(self, id_size: u32) -> u32 {
+        match self {
+            FieldTag::ArrayObject | FieldTag::NormalObject => id_size,
+            FieldTag::Boolean | FieldTag::Byte => 1,
+            FieldTag::Char | FieldTag::Short => 2,
+            FieldTag::Float | FieldTag::Int => 4,
+            FieldTag::Double | FieldTag::Long => 8,
+        }
+    }
+
+    pub fn is_object(self) -> bool {
+        matches!(self, FieldTag::ArrayObject | FieldTag::NormalObject)
+    }
+
+    // The character of the type in JVM descriptors (`[B` for byte[]).
+    pub fn descriptor(self) -> char {
+        match self {
+            FieldTag::ArrayObject => '[',
+            FieldTag::NormalObject => 'L',
+            FieldTag::Boolean => 'Z',
+            FieldTag::Char => 'C',
+            FieldTag::Float => 'F',
+            FieldTag::Double => 'D',
+            FieldTag::Byte => 'B',
+            FieldTag::Short => 'S',
+            FieldTag::Int => 'I',
+            FieldTag::Long => 'J',
+        }
+    }
+
+    // The Java name of the type (used for primitive arrays and field layouts).
+    pub fn java_name(self) -> &'static str {
+        match self {
+            FieldTag::ArrayObject | FieldTag::NormalObject => "Object",
+            FieldTag::Boolean => "boolean",
+            FieldTag::Char => "char",
+            FieldTag::Float => "float",
+            FieldTag::Double => "double",
+            FieldTag::Byte => "byte",
+            FieldTag::Short => "short",
+            FieldTag::Int => "int",
+            FieldTag::Long => "long",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, TryFromPrimitive)]
+#[repr(u8)]
+pub enum DataDumpSubRecordTag {
+    RootUnknown = 0xFF,
+    JniGlobal = 0x01,
+    JniLocal = 0x02,
+    JavaFrame = 0x03,
+    NativeStack = 0x04,
+    StickyClass = 0x05,
+    ThreadBlock = 0x06,
+    MonitorUsed = 0x07,
+    ThreadObject = 0x08,
+    ClassDump = 0x20,
+    InstanceDump = 0x21,
+    ObjectArrayDump = 0x22,
+    PrimitiveArrayDump = 0x23,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Object(u64),
+    Boolean(bool),
+    Char(u16),
+    Float(f32),
+    Double(f64),
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+}
+
+impl Value {
+    pub fn as_object(&self) -> Option<u64> {
+        match *self {
+            Value::Object(id) if id != 0 => Some(id),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Object(0) => write!(f, "null"),
+            Value::Object(id) => write!(f, "{:#x}", id),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::Char(c) => match std::char::from_u32(c as u32) {
+                Some(c) if !c.is_control() => write!(f, "'{}'", c),
+                _ => write!(f, "'\\u{:04x}'", c),
+            },
+            Value::Float(v) => write!(f, "{}", v),
+            Value::Double(v) => write!(f, "{}", v),
+            Value::Byte(v) => write!(f, "{}", v),
+            Value::Short(v) => write!(f, "{}", v),
+            Value::Int(v) => write!(f, "{}", v),
+            Value::Long(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+pub fn read_value<R: Read>(reader: &mut R, tag: FieldTag, id_size: u32) -> Value {
+    match tag {
+        FieldTag::ArrayObject | FieldTag::NormalObject => Value::Object(read_id(reader, id_size)),
+        FieldTag::Boolean => Value::Boolean(read_u8(reader) != 0),
+        FieldTag::Char => Value::Char(read_u16(reader)),
+        FieldTag::Float => Value::Float(f32::from_bits(read_u32(reader))),
+        FieldTag::Double => Value::Double(f64::from_bits(read_u64(reader))),
+        FieldTag::Byte => Value::Byte(read_u8(reader) as i8),
+        FieldTag::Short => Value::Short(read_u16(reader) as i16),
+        FieldTag::Int => Value::Int(read_u32(reader) as i32),
+        FieldTag::Long => Value::Long(read_u64(reader) as i64),
+    }
+}
+
+fn read_field_tag<R: Read>(reader: &mut R) -> FieldTag {
+    let raw = read_u8(reader);
+    FieldTag::try_from(raw).unwrap_or_else(|_| panic!("unknown field type: {:#x}", raw))
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub enum GcRootKind {
+    Unknown,
+    JniGlobal,
+    JniLocal,
+    JavaFrame,
+    NativeStack,
+    StickyClass,
+    ThreadBlock,
+    MonitorUsed,
+    ThreadObject,
+}
+
+impl GcRootKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            GcRootKind::Unknown => "unknown",
+            GcRootKind::JniGlobal => "jni-global",
+            GcRootKind::JniLocal => "jni-local",
+            GcRootKind::JavaFrame => "java-frame",
+            GcRootKind::NativeStack => "native-stack",
+            GcRootKind::StickyClass => "sticky-class",
+            GcRootKind::ThreadBlock => "thread-block",
+            GcRootKind::MonitorUsed => "monitor-used",
+            GcRootKind::ThreadObject => "thread-object",
+        }
+    }
+
+    // The tag of the sub-record of such roots.
+    pub fn tag(self) -> DataDumpSubRecordTag {
+        match self {
+            GcRootKind::Unknown => DataDumpSubRecordTag::RootUnknown,
+            GcRootKind::JniGlobal => DataDumpSubRecordTag::JniGlobal,
+            GcRootKind::JniLocal => DataDumpSubRecordTag::JniLocal,
+            GcRootKind::JavaFrame => DataDumpSubRecordTag::JavaFrame,
+            GcRootKind::NativeStack => DataDumpSubRecordTag::NativeStack,
+            GcRootKind::StickyClass => DataDumpSubRecordTag::StickyClass,
+            GcRootKind::ThreadBlock => DataDumpSubRecordTag::ThreadBlock,
+            GcRootKind::MonitorUsed => DataDumpSubRecordTag::MonitorUsed,
+            GcRootKind::ThreadObject => DataDumpSubRecordTag::ThreadObject,
+        }
+    }
+}
+
+//
+// Only some root kinds carry a thread serial, frame number, or stack trace
+// serial; the fields that don't apply are left as None.
+//
+#[derive(Debug, Clone)]
+pub struct GcRoot {
+    pub kind: GcRootKind,
+    pub object_id: u64,
+    pub thread_serial_num: Option<u32>,
+    pub frame_num: Option<u32>,
+    pub strace_num: Option<u32>,
+}
+
+#[derive(Debug)]
+pub struct FieldDescriptor {
+    pub name_id: u64,
+    pub tag: FieldTag,
+}
+
+#[derive(Debug)]
+pub struct StaticField {
+    pub name_id: u64,
+    pub tag: FieldTag,
+    pub value: Value,
+}
+
+#[derive(Debug)]
+pub struct ClassDump {
+    pub class_id: u64,
+    pub strace_num: u32,
+    pub super_class_id: u64,
+    pub class_loader_id: u64,
+    pub signers_id: u64,
+    pub protection_domain_id: u64,
+    pub instance_size: u32,
+    pub instance_fields: Vec<FieldDescriptor>,
+    // The constant pool and static fields as dumped, only decoded once
+    // asked for: most classes never are.
+    statics: Vec<u8>,
+    id_size: u32,
+    decoded: OnceLock<Statics>,
+}
+
+// The constant pool and the static fields of a class.
+type Statics = (Vec<(u16, Value)>, Vec<StaticField>);
+
+impl ClassDump {
+    fn decoded(&self) -> &Statics {
+        self.decoded.get_or_init(|| {
+            let mut reader = &self.statics[..];
+            let id_size = self.id_size;
+            let npool = read_u16(&mut reader);
+            let mut constant_pool = Vec::with_capacity(npool as usize);
+            for _ in 0..npool {
+                let index = read_u16(&mut reader);
+                let tag = read_field_tag(&mut reader);
+                constant_pool.push((index, read_value(&mut reader, tag, id_size)));
+            }
+            let nstatics = read_u16(&mut reader);
+            let mut static_fields = Vec::with_capacity(nstatics as usize);
+            for _ in 0..nstatics {
+                let name_id = read_id(&mut reader, id_size);
+                let tag = read_field_tag(&mut reader);
+                let value = read_value(&mut reader, tag, id_size);
+                static_fields.push(StaticField {
+                    name_id,
+                    tag,
+                    value,
+                });
+            }
+            (constant_pool, static_fields)
+        })
+    }
+
+    pub fn constant_pool(&self) -> &[(u16, Value)] {
+        &self.decoded().0
+    }
+
+    pub fn static_fields(&self) -> &[StaticField] {
+        &self.decoded().1
+    }
+
+    //
+    // A class dump for a class read from another format than HPROF, with
+    // static fields (object references by name id) but no constant pool.
+    //
+    pub fn new(
+        class_id: u64,
+        super_class_id: u64,
+        instance_size: u32,
+        instance_fields: Vec<FieldDescriptor>,
+        static_references: &[(u64, u64)],
+        id_size: u32,
+    ) -> ClassDump {
+        let id = |statics: &mut Vec<u8>, id: u64| match id_size {
+            4 => statics.extend((id as u32).to_be_bytes()),
+            _ => statics.extend(id.to_be_bytes()),
+        };
+        let mut statics = vec![0, 0];
+        statics.extend((static_references.len() as u16).to_be_bytes());
+        for &(name_id, object_id) in static_references {
+            id(&mut statics, name_id);
+            statics.push(FieldTag::NormalObject as u8);
+            id(&mut statics, object_id);
+        }
+        ClassDump {
+            class_id,
+            strace_num: 0,
+            super_class_id,
+            class_loader_id: 0,
+            signers_id: 0,
+            protection_domain_id: 0,
+            instance_size,
+            instance_fields,
+            statics,
+            id_size,
+            decoded: OnceLock::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct InstanceDump {
+    pub object_id: u64,
+    pub strace_num: u32,
+    pub class_id: u64,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct ObjectArrayDump {
+    pub object_id: u64,
+    pub strace_num: u32,
+    pub class_id: u64,
+    pub elements: Vec<u64>,
+}
+
+#[derive(Debug)]
+pub struct PrimitiveArrayDump {
+    pub object_id: u64,
+    pub strace_num: u32,
+    pub element_tag: FieldTag,
+    pub length: u32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum HeapObject {
+    Class(ClassDump),
+    Instance(InstanceDump),
+    ObjectArray(ObjectArrayDump),
+    PrimitiveArray(PrimitiveArrayDump),
+}
+
+impl HeapObject {
+    pub fn object_id(&self) -> u64 {
+        match self {
+            HeapObject::Class(c) => c.class_id,
+            HeapObject::Instance(i) => i.object_id,
+            HeapObject::ObjectArray(a) => a.object_id,
+            HeapObject::PrimitiveArray(a) => a.object_id,
+        }
+    }
+
+    pub fn strace_num(&self) -> u32 {
+        match self {
+            HeapObject::Class(c) => c.strace_num,
+            HeapObject::Instance(i) => i.strace_num,
+            HeapObject::ObjectArray(a) => a.strace_num,
+            HeapObject::PrimitiveArray(a) => a.strace_num,
+        }
+    }
+}
+
+fn parse_class_dump<R: BufRead>(reader: &mut R, id_size: u32) -> ClassDump {
+    let class_id = read_id(reader, id_size);
+    let strace_num = read_u32(reader);
+    let super_class_id = read_id(reader, id_size);
+    let class_loader_id = read_id(reader, id_size);
+    let signers_id = read_id(reader, id_size);
+    let protection_domain_id = read_id(reader, id_size);
+    let _reserved1 = read_id(reader, id_size);
+    let _reserved2 = read_id(reader, id_size);
+    let instance_size = read_u32(reader);
+
+    // Only the sizes of the constant pool and static values are looked at.
+    let mut statics = Vec::new();
+    let npool = read_u16(reader);
+    statics.extend(npool.to_be_bytes());
+    for _ in 0..npool {
+        read_into(reader, &mut statics, 2);
+        let tag = read_field_tag(reader);
+        statics.push(tag as u8);
+        read_into(reader, &mut statics, tag.size(id_size) as usize);
+    }
+    let nstatics = read_u16(reader);
+    statics.extend(nstatics.to_be_bytes());
+    for _ in 0..nstatics {
+        read_into(reader, &mut statics, id_size as usize);
+        let tag = read_field_tag(reader);
+        statics.push(tag as u8);
+        read_into(reader, &mut statics, tag.size(id_size) as usize);
+    }
+
+    let nfields = read_u16(reader);
+    let mut instance_fields = Vec::with_capacity(nfields as usize);
+    for _ in 0..nfields {
+        let name_id = read_id(reader, id_size);
+        let tag = read_field_tag(reader);
+        instance_fields.push(FieldDescriptor { name_id, tag });
+    }
+
+    ClassDump {
+        class_id,
+        strace_num,
+        super_class_id,
+        class_loader_id,
+        signers_id,
+        protection_domain_id,
+        instance_size,
+        instance_fields,
+        statics,
+        id_size,
+        decoded: OnceLock::new(),
+    }
+}
+
+// Appends the next `len` bytes of the reader to `out`.
+fn read_into<R: Read>(reader: &mut R, out: &mut Vec<u8>, len: usize) {
+    let start = out.len();
+    out.resize(start + len, 0);
+    reader.read_exact(&mut out[start..]).unwrap();
+}
+
+// As for primitive arrays below, the fields are only read as they are there.
+fn parse_instance_dump<R: BufRead>(reader: &mut R, id_size: u32) -> Result<InstanceDump, String> {
+    let object_id = read_id(reader, id_size);
+    let strace_num = read_u32(reader);
+    let class_id = read_id(reader, id_size);
+    let nbytes = read_u32(reader);
+    let mut data = Vec::with_capacity((nbytes as usize).min(1 << 20));
+    reader
+        .take(nbytes as u64)
+        .read_to_end(&mut data)
+        .map_err(|e| e.to_string())?;
+    if data.len() != nbytes as usize {
+        return Err(format!(
+            "instance {:#x} of {} bytes runs past the end of its record",
+            object_id, nbytes
+        ));
+    }
+
+    Ok(InstanceDump {
+        object_id,
+        strace_num,
+        class_id,
+        data,
+    })
+}
+
+fn parse_object_array_dump<R: BufRead>(
+    reader: &mut R,
+    id_size: u32,
+) -> Result<ObjectArrayDump, String> {
+    let object_id = read_id(reader, id_size);
+    let strace_num = read_u32(reader);
+    let nelems = read_u32(reader);
+    let class_id = read_id(reader, id_size);
+    let elements = read_ids(reader, id_size, nelems as usize).map_err(|_| {
+        format!(
+            "object array {:#x} of {} elements runs past the end of its record",
+            object_id, nelems
+        )
+    })?;
+
+    Ok(ObjectArrayDump {
+        object_id,
+        strace_num,
+        class_id,
+        elements,
+    })
+}
+
+//
+// The length of a primitive array comes from the dump, so its bytes are
+// only as many as there are in the record: garbage lengths of up to four
+// billion longs don't get allocated before the record runs out.
+//
+fn parse_primitive_array_dump<R: BufRead>(
+    reader: &mut R,
+    id_size: u32,
+) -> Result<PrimitiveArrayDump, String> {
+    let object_id = read_id(reader, id_size);
+    let strace_num = read_u32(reader);
+    let length = read_u32(reader);
+    let element_tag = read_field_tag(reader);
+    let bytes = (length as usize)
+        .checked_mul(element_tag.size(id_size) as usize)
+        .ok_or_else(|| {
+            format!(
+                "primitive array {:#x} of {} elements is too large",
+                object_id, length
+            )
+        })?;
+    let mut data = Vec::with_capacity(bytes.min(1 << 20));
+    reader
+        .take(bytes as u64)
+        .read_to_end(&mut data)
+        .map_err(|e| e.to_string())?;
+    if data.len() != bytes {
+        return Err(format!(
+            "primitive array {:#x} of {} elements runs past the end of its record",
+            object_id, length
+        ));
+    }
+
+    Ok(PrimitiveArrayDump {
+        object_id,
+        strace_num,
+        element_tag,
+        length,
+        data,
+    })
+}
+
+// The object dumped by a CLASS_DUMP, INSTANCE_DUMP or array dump sub-record.
+pub fn parse_object<R: BufRead>(
+    reader: &mut R,
+    tag: DataDumpSubRecordTag,
+    id_size: u32,
+) -> Result<HeapObject, String> {
+    match tag {
+        DataDumpSubRecordTag::ClassDump => Ok(HeapObject::Class(parse_class_dump(reader, id_size))),
+        DataDumpSubRecordTag::InstanceDump => {
+            parse_instance_dump(reader, id_size).map(HeapObject::Instance)
+        }
+        DataDumpSubRecordTag::ObjectArrayDump => {
+            parse_object_array_dump(reader, id_size).map(HeapObject::ObjectArray)
+        }
+        DataDumpSubRecordTag::PrimitiveArrayDump => {
+            parse_primitive_array_dump(reader, id_size).map(HeapObject::PrimitiveArray)
+        }
+        _ => panic!("not an object sub-record: {:?}", tag),
+    }
+}
+
+//
+// The bytes of the sub-record at the start of `data`, tag included, found
+// from its tag and counts: more than what `data` holds when it is cut
+// short, None for an unknown tag or field type.
+//
+pub fn sub_record_bytes(data: &[u8], id_size: u32) -> Option<usize> {
+    let id = id_size as usize;
+    let cut = data.len() + 1;
+    let u16_at = |at: usize| {
+        data.get(at..at + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]) as usize)
+    };
+    let u32_at = |at: usize| {
+        data.get(at..at + 4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize)
+    };
+    let field_size = |at: usize| match data.get(at) {
+        Some(&raw) => FieldTag::try_from(raw)
+            .ok()
+            .map(|t| t.size(id_size) as usize),
+        None => Some(cut),
+    };
+    let tag = DataDumpSubRecordTag::try_from(*data.first()?).ok()?;
+    let bytes = match tag {
+        DataDumpSubRecordTag::RootUnknown
+        | DataDumpSubRecordTag::StickyClass
+        | DataDumpSubRecordTag::MonitorUsed => 1 + id,
+        DataDumpSubRecordTag::JniGlobal => 1 + 2 * id,
+        DataDumpSubRecordTag::NativeStack | DataDumpSubRecordTag::ThreadBlock => 1 + id + 4,
+        DataDumpSubRecordTag::JniLocal
+        | DataDumpSubRecordTag::JavaFrame
+        | DataDumpSubRecordTag::ThreadObject => 1 + id + 8,
+        DataDumpSubRecordTag::ClassDump => {
+            let mut at = 1 + 7 * id + 8;
+            let pool = match u16_at(at) {
+                Some(count) => count,
+                None => return Some(cut),
+            };
+            at += 2;
+            for _ in 0..pool {
+                at += 3 + field_size(at + 2)?;
+            }
+            let statics = match u16_at(at) {
+                Some(count) => count,
+                None => return Some(cut),
+            };
+            at += 2;
+            for _ in 0..statics {
+                at += id + 1 + field_size(at + id)?;
+            }
+            match u16_at(at) {
+                Some(fields) => at + 2 + fields * (id + 1),
+                None => cut,
+            }
+        }
+        DataDumpSubRecordTag::InstanceDump => {
+            let at = 1 + 2 * id + 4;
+            u32_at(at).map_or(cut, |bytes| at + 4 + bytes)
+        }
+        DataDumpSubRecordTag::ObjectArrayDump => {
+            let at = 1 + id + 4;
+            u32_at(at).map_or(cut, |length| at + 4 + id + length * id)
+        }
+        DataDumpSubRecordTag::PrimitiveArrayDump => {
+            let at = 1 + id + 4;
+            match (u32_at(at), field_size(at + 4)?) {
+                (Some(length), size) if size != cut => at + 5 + length * size,
+                _ => cut,
+            }
+        }
+    };
+    Some(bytes)
+}
+
+pub fn parse_root<R: BufRead>(reader: &mut R, tag: DataDumpSubRecordTag, id_size: u32) -> GcRoot {
+    let object_id = read_id(reader, id_size);
+    let mut root = GcRoot {
+        kind: GcRootKind::Unknown,
+        object_id,
+        thread_serial_num: None,
+        frame_num: None,
+        strace_num: None,
+    };
+
+    match tag {
+        DataDumpSubRecordTag::RootUnknown => {}
+        DataDumpSubRecordTag::JniGlobal => {
+            root.kind = GcRootKind::JniGlobal;
+            let _jni_global_ref_id = read_id(reader, id_size);
+        }
+        DataDumpSubRecordTag::JniLocal => {
+            root.kind = GcRootKind::JniLocal;
+            root.thread_serial_num = Some(read_u32(reader));
+            root.frame_num = Some(read_u32(reader));
+        }
+        DataDumpSubRecordTag::JavaFrame => {
+            root.kind = GcRootKind::JavaFrame;
+            root.thread_serial_num = Some(read_u32(reader));
+            root.frame_num = Some(read_u32(reader));
+        }
+        DataDumpSubRecordTag::NativeStack => {
+            root.kind = GcRootKind::NativeStack;
+            root.thread_serial_num = Some(read_u32(reader));
+        }
+        DataDumpSubRecordTag::StickyClass => {
+            root.kind = GcRootKind::StickyClass;
+        }
+        DataDumpSubRecordTag::ThreadBlock => {
+            root.kind = GcRootKind::ThreadBlock;
+            root.thread_serial_num = Some(read_u32(reader));
+        }
+        DataDumpSubRecordTag::MonitorUsed => {
+            root.kind = GcRootKind::MonitorUsed;
+        }
+        DataDumpSubRecordTag::ThreadObject => {
+            root.kind = GcRootKind::ThreadObject;
+            root.thread_serial_num = Some(read_u32(reader));
+            root.strace_num = Some(read_u32(reader));
+        }
+        _ => unreachable!(),
+    }
+    root
+}
+
+//
+// Parses the body of a HEAP_DUMP or HEAP_DUMP_SEGMENT record that is
+// `bytes` long, appending everything found to `objects` and `roots`.
+//
+pub fn parse_heap_dump_segment<R: BufRead>(
+    reader: &mut R,
+    id_size: u32,
+    bytes: u64,
+    objects: &mut Vec<HeapObject>,
+    roots: &mut Vec<GcRoot>,
+) {
+    let mut segment = reader.take(bytes);
+    while segment.limit() > 0 {
+        let raw_tag = read_u8(&mut segment);
+        let tag = match DataDumpSubRecordTag::try_from(raw_tag) {
+            Ok(tag) => tag,
+            Err(_) => {
+                //
+                // There is no length prefix on sub-records, so there is no
+                // way to recover here. Skip the rest of the segment.
+                //
+                eprintln!(
+                    "warning: unknown heap dump sub-record {:#x}, skipping {} bytes",
+                    raw_tag,
+                    segment.limit()
+                );
+                let remaining = segment.limit();
+                skip_bytes(&mut segment, remaining);
+                break;
+            }
+        };
+
+        match tag {
+            DataDumpSubRecordTag::ClassDump
+            | DataDumpSubRecordTag::InstanceDump
+            | DataDumpSubRecordTag::ObjectArrayDump
+            | DataDumpSubRecordTag::PrimitiveArrayDump => {
+                match parse_object(&mut segment, tag, id_size) {
+                    Ok(object) => objects.push(object),
+                    Err(e) => {
+                        eprintln!("warning: {}, skipping {} bytes", e, segment.limit());
+                        let remaining = segment.limit();
+                        skip_bytes(&mut segment, remaining);
+                        break;
+                    }
+                }
+            }
+            _ => {
+                roots.push(parse_root(&mut segment, tag, id_size));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A PRIMITIVE_ARRAY_DUMP of longs without its tag: id 0x10, then the
+    // trace serial, the length, the element type and `data`.
+    fn long_array(length: u32, data: &[u8]) -> Vec<u8> {
+        let mut bytes = 0x10u64.to_be_bytes().to_vec();
+        bytes.extend(0u32.to_be_bytes());
+        bytes.extend(length.to_be_bytes());
+        bytes.push(FieldTag::Long as u8);
+        bytes.extend(data);
+        bytes
+    }
+
+    #[test]
+    fn primitive_arrays_stay_in_their_record() {
+        let tag = DataDumpSubRecordTag::PrimitiveArrayDump;
+        let bytes = long_array(2, &[7; 16]);
+        match parse_object(&mut &bytes[..], tag, 8) {
+            Ok(HeapObject::PrimitiveArray(a)) => assert_eq!((a.length, a.data), (2, vec![7; 16])),
+            other => panic!("{:?}", other),
+        }
+        let bytes = long_array(u32::MAX, &[7; 16]);
+        let error = parse_object(&mut &bytes[..], tag, 8).unwrap_err();
+        assert!(error.contains("runs past the end"), "{}", error);
+    }
+
+    #[test]
+    fn instances_and_object_arrays_stay_in_their_record() {
+        // Id 0x10, the trace serial, then the class id and the field bytes.
+        let mut instance = 0x10u64.to_be_bytes().to_vec();
+        instance.extend(0u32.to_be_bytes());
+        instance.extend(0x20u64.to_be_bytes());
+        instance.extend(u32::MAX.to_be_bytes());
+        instance.extend([1; 8]);
+        let error =
+            parse_object(&mut &instance[..], DataDumpSubRecordTag::InstanceDump, 8).unwrap_err();
+        assert!(error.contains("runs past the end"), "{}", error);
+
+        // Id 0x10, the trace serial, the length, then the class id and ids.
+        let array = |length: u32| {
+            let mut bytes = 0x10u64.to_be_bytes().to_vec();
+            bytes.extend(0u32.to_be_bytes());
+            bytes.extend(length.to_be_bytes());
+            bytes.extend(0x20u64.to_be_bytes());
+            bytes.extend(7u64.to_be_bytes());
+            bytes
+        };
+        let tag = DataDumpSubRecordTag::ObjectArrayDump;
+        match parse_object(&mut &array(1)[..], tag, 8) {
+            Ok(HeapObject::ObjectArray(a)) => assert_eq!(a.elements, vec![7]),
+            other => panic!("{:?}", other),
+        }
+        let error = parse_object(&mut &array(u32::MAX)[..], tag, 8).unwrap_err();
+        assert!(error.contains("runs past the end"), "{}", error);
+    }
+
+    #[test]
+    fn segments_stop_at_a_broken_array() {
+        let mut bytes = vec![DataDumpSubRecordTag::PrimitiveArrayDump as u8];
+        bytes.extend(long_array(1, &[1; 8]));
+        bytes.push(DataDumpSubRecordTag::PrimitiveArrayDump as u8);
+        bytes.extend(long_array(1 << 30, &[2; 8]));
+        let (mut objects, mut roots) = (Vec::new(), Vec::new());
+        let mut reader = &bytes[..];
+        parse_heap_dump_segment(&mut reader, 8, bytes.len() as u64, &mut objects, &mut roots);
+        assert_eq!(objects.len(), 1);
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn class_statics_are_decoded_when_asked_for() {
+        // Id 0x20, the trace serial, super, loader, signers, domain, two
+        // reserved ids and the instance size.
+        let mut bytes = 0x20u64.to_be_bytes().to_vec();
+        bytes.extend(0u32.to_be_bytes());
+        for _ in 0..6 {
+            bytes.extend(0u64.to_be_bytes());
+        }
+        bytes.extend(1u32.to_be_bytes());
+        // A constant pool of one int, and a long and an object static.
+        bytes.extend(1u16.to_be_bytes());
+        bytes.extend(3u16.to_be_bytes());
+        bytes.push(FieldTag::Int as u8);
+        bytes.extend(42i32.to_be_bytes());
+        bytes.extend(2u16.to_be_bytes());
+        bytes.extend(0x30u64.to_be_bytes());
+        bytes.push(FieldTag::Long as u8);
+        bytes.extend((-5i64).to_be_bytes());
+        bytes.extend(0x31u64.to_be_bytes());
+        bytes.push(FieldTag::NormalObject as u8);
+        bytes.extend(0x40u64.to_be_bytes());
+        // One boolean instance field.
+        bytes.extend(1u16.to_be_bytes());
+        bytes.extend(0x32u64.to_be_bytes());
+        bytes.push(FieldTag::Boolean as u8);
+
+        let mut reader = &bytes[..];
+        let class = match parse_object(&mut reader, DataDumpSubRecordTag::ClassDump, 8) {
+            Ok(HeapObject::Class(class)) => class,
+            other => panic!("{:?}", other),
+        };
+        assert!(reader.is_empty());
+        assert_eq!(class.instance_fields.len(), 1);
+        assert_eq!(class.instance_fields[0].name_id, 0x32);
+        assert!(class.decoded.get().is_none());
+
+        assert_eq!(class.constant_pool(), &[(3, Value::Int(42))]);
+        let statics: Vec<(u64, Value)> = class
+            .static_fields()
+            .iter()
+            .map(|f| (f.name_id, f.value))
+            .collect();
+        assert_eq!(
+            statics,
+            vec![(0x30, Value::Long(-5)), (0x31, Value::Object(0x40))]
+        );
+    }
+}
@@ -0,0 +1,398 @@
+//
+// The class hierarchy as recorded by the superclass links of the class
+// dumps, with the instances of each class folded in. The dump doesn't list
+// the interfaces a class implements, so this is the superclass tree only
+// (the implementers of a few JDK interfaces are known, see below).
+//
+use crate::heap::{FieldTag, HeapObject};
+use crate::snapshot::Snapshot;
+
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Default)]
+pub struct ClassNode {
+    pub instances: u64,
+    pub shallow: u64,
+    // The same including the instances of all the subclasses.
+    pub total_instances: u64,
+    pub total_shallow: u64,
+    pub subclasses: Vec<u64>,
+}
+
+#[derive(Debug)]
+pub struct Hierarchy {
+    pub classes: HashMap<u64, ClassNode>,
+    // The classes without a superclass: java.lang.Object and the interfaces.
+    pub roots: Vec<u64>,
+}
+
+impl Hierarchy {
+    pub fn build(snapshot: &Snapshot) -> Hierarchy {
+        let mut classes: HashMap<u64, ClassNode> = snapshot
+            .class_serials
+            .keys()
+            .filter(|&&id| snapshot.class_dump(id).is_some())
+            .map(|&id| (id, ClassNode::default()))
+            .collect();
+        let mut roots = Vec::new();
+        let ids: Vec<u64> = classes.keys().copied().collect();
+        for id in ids {
+            let super_id = snapshot.class_dump(id).unwrap().super_class_id;
+            match classes.get_mut(&super_id) {
+                Some(parent) => parent.subclasses.push(id),
+                None => roots.push(id),
+            }
+        }
+        for object in &snapshot.objects {
+            let node = snapshot
+                .class_of(object)
+                .and_then(|id| classes.get_mut(&id));
+            if let Some(node) = node {
+                node.instances += 1;
+                node.shallow += snapshot.shallow_size(object);
+            }
+        }
+
+        let mut hierarchy = Hierarchy { classes, roots };
+        for root in hierarchy.roots.clone() {
+            hierarchy.fold(root);
+        }
+        hierarchy.sort(snapshot);
+        hierarchy
+    }
+
+    // Computes the totals of a class and its subclasses, iteratively since
+    // hierarchies can be deep.
+    fn fold(&mut self, root: u64) {
+        let mut stack = vec![(root, false)];
+        while let Some((id, exiting)) = stack.pop() {
+            if !exiting {
+                stack.push((id, true));
+                stack.extend(self.classes[&id].subclasses.iter().map(|&s| (s, false)));
+                continue;
+            }
+            let node = &self.classes[&id];
+            let (mut instances, mut shallow) = (node.instances, node.shallow);
+            for s in &node.subclasses {
+                instances += self.classes[s].total_instances;
+                shallow += self.classes[s].total_shallow;
+            }
+            let node = self.classes.get_mut(&id).unwrap();
+            node.total_instances = instances;
+            node.total_shallow = shallow;
+        }
+    }
+
+    // Subclasses (and roots) by total shallow size, then by name.
+    fn sort(&mut self, snapshot: &Snapshot) {
+        let key = |classes: &HashMap<u64, ClassNode>, id: &u64| {
+            (
+                std::cmp::Reverse(classes[id].total_shallow),
+                snapshot.class_name(*id),
+            )
+        };
+        let ids: Vec<u64> = self.classes.keys().copied().collect();
+        for id in ids {
+            let mut subclasses = std::mem::take(&mut self.classes.get_mut(&id).unwrap().subclasses);
+            subclasses.sort_by_cached_key(|s| key(&self.classes, s));
+            self.classes.get_mut(&id).unwrap().subclasses = subclasses;
+        }
+        let mut roots = std::mem::take(&mut self.roots);
+        roots.sort_by_cached_key(|r| key(&self.classes, r));
+        self.roots = roots;
+    }
+}
+
+//
+// Heap dumps don't record the interfaces of a class, so the implementers
+// of the common JDK interfaces are approximated by their skeleton classes
+// and the few direct implementers that don't extend those.
+//
+const KNOWN_IMPLEMENTERS: &[(&str, &[&str])] = &[
+    (
+        "java.util.Map",
+        &[
+            "java.util.AbstractMap",
+            "java.util.Hashtable",
+            "java.util.Collections$UnmodifiableMap",
+            "java.util.Collections$SynchronizedMap",
+        ],
+    ),
+    (
+        "java.util.Collection",
+        &[
+            "java.util.AbstractCollection",
+            "java.util.Collections$UnmodifiableCollection",
+            "java.util.Collections$SynchronizedCollection",
+        ],
+    ),
+    (
+        "java.util.List",
+        &[
+            "java.util.AbstractList",
+            "java.util.Collections$UnmodifiableList",
+            "java.util.Collections$SynchronizedList",
+            "java.util.concurrent.CopyOnWriteArrayList",
+        ],
+    ),
+    (
+        "java.util.Set",
+        &[
+            "java.util.AbstractSet",
+            "java.util.ImmutableCollections$AbstractImmutableSet",
+            "java.util.Collections$UnmodifiableSet",
+            "java.util.Collections$SynchronizedSet",
+        ],
+    ),
+    (
+        "java.util.Queue",
+        &[
+            "java.util.AbstractQueue",
+            "java.util.ArrayDeque",
+            "java.util.LinkedList",
+        ],
+    ),
+    (
+        "java.lang.CharSequence",
+        &["java.lang.String", "java.lang.AbstractStringBuilder"],
+    ),
+    (
+        "java.lang.Runnable",
+        &["java.lang.Thread", "java.util.concurrent.FutureTask"],
+    ),
+];
+
+//
+// The java.lang.Class instances and the primitive arrays have no class id
+// to look up in the hierarchy (nor always a class dump), so their
+// supertypes are spelled out.
+//
+const CLASS_SUPERTYPES: &[&str] = &[
+    "java.lang.Class",
+    "java.lang.Object",
+    "java.io.Serializable",
+    "java.lang.reflect.Type",
+    "java.lang.reflect.GenericDeclaration",
+    "java.lang.reflect.AnnotatedElement",
+];
+const ARRAY_SUPERTYPES: &[&str] = &[
+    "java.lang.Object",
+    "java.lang.Cloneable",
+    "java.io.Serializable",
+];
+const PRIMITIVE_TYPES: [FieldTag; 8] = [
+    FieldTag::Boolean,
+    FieldTag::Char,
+    FieldTag::Float,
+    FieldTag::Double,
+    FieldTag::Byte,
+    FieldTag::Short,
+    FieldTag::Int,
+    FieldTag::Long,
+];
+
+//
+// The groups of objects by type: by class id for the instances and object
+// arrays, by type name for the objects without a class id.
+//
+#[derive(Debug, Default)]
+pub struct TypeGroups {
+    pub classes: HashMap<u64, String>,
+    pub others: HashMap<String, String>,
+}
+
+impl TypeGroups {
+    // The group of an object, the name of its type when it has none.
+    pub fn name(&self, snapshot: &Snapshot, object: &HeapObject) -> String {
+        match snapshot.class_of(object) {
+            Some(id) => match self.classes.get(&id) {
+                Some(group) => group.clone(),
+                None => snapshot.object_class_name(object),
+            },
+            None => {
+                let name = snapshot.object_class_name(object);
+                self.others.get(&name).cloned().unwrap_or(name)
+            }
+        }
+    }
+}
+
+impl Hierarchy {
+    //
+    // The ids of the classes whose instances are assignable to a type: the
+    // class with that name and its subclasses, or the known implementers of
+    // an interface.
+    //
+    pub fn assignable_to(&self, snapshot: &Snapshot, type_name: &str) -> HashSet<u64> {
+        let mut names = vec![type_name];
+        if let Some((_, implementers)) = KNOWN_IMPLEMENTERS.iter().find(|(n, _)| *n == type_name) {
+            names.extend(implementers.iter());
+        }
+        let mut found = HashSet::new();
+        let mut stack: Vec<u64> = self
+            .classes
+            .keys()
+            .copied()
+            .filter(|&id| names.contains(&snapshot.class_name(id).as_str()))
+            .collect();
+        while let Some(id) = stack.pop() {
+            if found.insert(id) {
+                stack.extend(self.classes[&id].subclasses.iter().copied());
+            }
+        }
+        found
+    }
+
+    //
+    // Groups the objects assignable to one of the given supertypes under the
+    // name of that supertype, the first one listed winning when several
+    // apply.
+    //
+    pub fn supertype_groups(&self, snapshot: &Snapshot, supertypes: &[&str]) -> TypeGroups {
+        let mut groups = TypeGroups::default();
+        for supertype in supertypes {
+            for id in self.assignable_to(snapshot, supertype) {
+                groups
+                    .classes
+                    .entry(id)
+                    .or_insert_with(|| supertype.to_string());
+            }
+            let mut others = Vec::new();
+            if CLASS_SUPERTYPES.contains(supertype) {
+                others.push("java.lang.Class".to_string());
+            }
+            for tag in PRIMITIVE_TYPES {
+                let name = format!("{}[]", tag.java_name());
+                if ARRAY_SUPERTYPES.contains(supertype) || *supertype == name {
+                    others.push(name);
+                }
+            }
+            for name in others {
+                groups
+                    .others
+                    .entry(name)
+                    .or_insert_with(|| supertype.to_string());
+            }
+        }
+        groups
+    }
+
+    //
+    // Maps every class to its superclass `depth` levels below
+    // java.lang.Object (1 for the direct subclasses of Object), classes
+    // closer to Object being their own group.
+    //
+    pub fn superclass_groups(&self, snapshot: &Snapshot, depth: usize) -> HashMap<u64, String> {
+        let mut groups = HashMap::new();
+        for &id in self.classes.keys() {
+            let mut chain = vec![id];
+            while let Some(class) = snapshot.class_dump(*chain.last().unwrap()) {
+                if class.super_class_id == 0 || chain.len() > 256 {
+                    break;
+                }
+                chain.push(class.super_class_id);
+            }
+            let group = chain[chain.len().saturating_sub(depth + 1)];
+            groups.insert(id, snapshot.class_name(group));
+        }
+        groups
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::{GcRootKind, Value};
+    use crate::histogram;
+    use crate::testing::Dump;
+
+    #[test]
+    fn supertypes_group_every_object() {
+        let mut dump = Dump::new();
+        let holder = dump.class(
+            "test/Holder",
+            dump.object,
+            &[("name", FieldTag::NormalObject)],
+        );
+        let name = dump.string("name");
+        let holder = dump.instance(holder, &[Value::Object(name)]);
+        let ints = dump.primitive_array(FieldTag::Int, &[0; 12]);
+        let array = dump.object_array(dump.object_array, &[holder, ints]);
+        dump.root(GcRootKind::StickyClass, array);
+        let snapshot = dump.load();
+        let hierarchy = Hierarchy::build(&snapshot);
+
+        let groups = hierarchy.supertype_groups(&snapshot, &["java.lang.Object"]);
+        let grouped = histogram::histogram_by(&snapshot, |_| true, |o| groups.name(&snapshot, o));
+        let shallow: u64 = snapshot
+            .objects
+            .iter()
+            .map(|o| snapshot.shallow_size(o))
+            .sum();
+        assert_eq!(grouped.len(), 1);
+        assert_eq!(grouped[0].class_name, "java.lang.Object");
+        assert_eq!(grouped[0].instances, snapshot.objects.len() as u64);
+        assert_eq!(grouped[0].shallow, shallow);
+
+        let groups = hierarchy.supertype_groups(&snapshot, &["int[]", "java.lang.Cloneable"]);
+        let name = |id: u64| {
+            let object = &snapshot.objects[snapshot.index_of(id).unwrap() as usize];
+            groups.name(&snapshot, object)
+        };
+        assert_eq!(name(ints), "int[]");
+        assert_eq!(name(holder), "test.Holder");
+        let bytes = snapshot.objects_of_class("byte[]");
+        assert_eq!(
+            groups.name(&snapshot, &snapshot.objects[bytes[0] as usize]),
+            "java.lang.Cloneable"
+        );
+        let ungrouped =
+            histogram::histogram_by(&snapshot, |_| true, |o| snapshot.object_class_name(o));
+        let grouped = histogram::histogram_by(&snapshot, |_| true, |o| groups.name(&snapshot, o));
+        let sum = |h: &[histogram::HistogramEntry]| h.iter().map(|e| e.shallow).sum::<u64>();
+        assert_eq!(sum(&grouped), sum(&ungrouped));
+    }
+
+    #[test]
+    fn subclasses_add_up_to_their_superclass() {
+        let mut dump = Dump::new();
+        let base = dump.class("test/Base", dump.object, &[("id", FieldTag::Long)]);
+        let small = dump.class("test/Small", base, &[]);
+        let big = dump.class("test/Big", base, &[("data", FieldTag::Long)]);
+        let object = dump.object;
+        let of_base = dump.instance(base, &[]);
+        dump.instance(small, &[]);
+        let of_big: Vec<u64> = (0..3).map(|_| dump.instance(big, &[])).collect();
+        let snapshot = dump.load();
+        let size = |id| snapshot.shallow_size(snapshot.object(id).unwrap());
+
+        let hierarchy = Hierarchy::build(&snapshot);
+        assert_eq!(hierarchy.roots, vec![object]);
+        let node = &hierarchy.classes[&base];
+        assert_eq!((node.instances, node.total_instances), (1, 5));
+        assert_eq!(node.total_shallow, 2 * size(of_base) + 3 * size(of_big[0]));
+        // The bigger subclass first.
+        assert_eq!(node.subclasses, vec![big, small]);
+        assert_eq!(hierarchy.classes[&big].total_instances, 3);
+    }
+
+    #[test]
+    fn classes_are_grouped_by_superclass_depth() {
+        let mut dump = Dump::new();
+        let base = dump.class("test/Base", dump.object, &[]);
+        let middle = dump.class("test/Middle", base, &[]);
+        let leaf = dump.class("test/Leaf", middle, &[]);
+        let object = dump.object;
+        let snapshot = dump.load();
+        let hierarchy = Hierarchy::build(&snapshot);
+
+        let groups = hierarchy.superclass_groups(&snapshot, 1);
+        assert_eq!(groups[&leaf], "test.Base");
+        assert_eq!(groups[&base], "test.Base");
+        assert_eq!(groups[&object], "java.lang.Object");
+        let groups = hierarchy.superclass_groups(&snapshot, 2);
+        assert_eq!(groups[&leaf], "test.Middle");
+        assert_eq!(groups[&middle], "test.Middle");
+        assert_eq!(groups[&base], "test.Base");
+    }
+}
@@ -0,0 +1,302 @@
+//
+// Class histograms: number of instances and shallow bytes per class.
+//
+use crate::heap::HeapObject;
+use crate::snapshot::Snapshot;
+
+use std::collections::HashMap;
+
+#[derive(Debug)]
+pub struct HistogramEntry {
+    pub class_name: String,
+    pub instances: u64,
+    pub shallow: u64,
+}
+
+//
+// Builds the histogram of the objects (by index) for which `filter` returns
+// true, sorted by shallow size in descending order.
+//
+pub fn histogram<F: Fn(u32) -> bool>(snapshot: &Snapshot, filter: F) -> Vec<HistogramEntry> {
+    histogram_by(snapshot, filter, |object| {
+        snapshot.object_class_name(object)
+    })
+}
+
+// Like histogram() but with objects grouped by `group_of` instead of class.
+pub fn histogram_by<F: Fn(u32) -> bool, G: Fn(&HeapObject) -> String>(
+    snapshot: &Snapshot,
+    filter: F,
+    group_of: G,
+) -> Vec<HistogramEntry> {
+    let mut entries: HashMap<String, HistogramEntry> = HashMap::new();
+    for (i, object) in snapshot.objects.iter().enumerate() {
+        if !filter(i as u32) {
+            continue;
+        }
+        let name = group_of(object);
+        let entry = entries
+            .entry(name.clone())
+            .or_insert_with(|| HistogramEntry {
+                class_name: name,
+                instances: 0,
+                shallow: 0,
+            });
+        entry.instances += 1;
+        entry.shallow += snapshot.shallow_size(object);
+    }
+
+    let mut entries: Vec<HistogramEntry> = entries.into_values().collect();
+    entries.sort_by(|a, b| {
+        b.shallow
+            .cmp(&a.shallow)
+            .then_with(|| a.class_name.cmp(&b.class_name))
+    });
+    entries
+}
+
+#[derive(Debug)]
+pub struct TraceGroup {
+    // The serial of the allocation stack trace.
+    pub strace_num: u32,
+    pub instances: u64,
+    pub shallow: u64,
+    // The histogram of the objects allocated there.
+    pub classes: Vec<HistogramEntry>,
+}
+
+//
+// The objects for which `filter` returns true grouped by the stack trace
+// recorded when they were allocated, sorted by shallow size. HotSpot only
+// records allocation traces when allocation profiling was on, otherwise all
+// the objects share one empty trace.
+//
+pub fn by_allocation_trace<F: Fn(u32) -> bool>(snapshot: &Snapshot, filter: F) -> Vec<TraceGroup> {
+    let mut traces: HashMap<u32, HashMap<String, HistogramEntry>> = HashMap::new();
+    for (i, object) in snapshot.objects.iter().enumerate() {
+        if !filter(i as u32) {
+            continue;
+        }
+        let name = snapshot.object_class_name(object);
+        let entry = traces
+            .entry(object.strace_num())
+            .or_default()
+            .entry(name.clone())
+            .or_insert_with(|| HistogramEntry {
+                class_name: name,
+                instances: 0,
+                shallow: 0,
+            });
+        entry.instances += 1;
+        entry.shallow += snapshot.shallow_size(object);
+    }
+
+    let mut groups: Vec<TraceGroup> = traces
+        .into_iter()
+        .map(|(strace_num, classes)| {
+            let mut classes: Vec<HistogramEntry> = classes.into_values().collect();
+            classes.sort_by(|a, b| {
+                b.shallow
+                    .cmp(&a.shallow)
+                    .then_with(|| a.class_name.cmp(&b.class_name))
+            });
+            TraceGroup {
+                strace_num,
+                instances: classes.iter().map(|c| c.instances).sum(),
+                shallow: classes.iter().map(|c| c.shallow).sum(),
+                classes,
+            }
+        })
+        .collect();
+    groups.sort_by(|a, b| {
+        b.shallow
+            .cmp(&a.shallow)
+            .then_with(|| a.strace_num.cmp(&b.strace_num))
+    });
+    groups
+}
+
+#[derive(Debug)]
+pub struct ThreadGroup {
+    // The serial of the thread the allocation traces belong to, None when
+    // they don't name one.
+    pub thread_serial: Option<u32>,
+    // The number of distinct allocation traces.
+    pub traces: usize,
+    pub instances: u64,
+    pub shallow: u64,
+    pub classes: Vec<HistogramEntry>,
+}
+
+//
+// The objects for which `filter` returns true grouped by the thread that
+// allocated them, as recorded in their allocation stack traces, sorted by
+// shallow size.
+//
+pub fn by_allocating_thread<F: Fn(u32) -> bool>(
+    snapshot: &Snapshot,
+    filter: F,
+) -> Vec<ThreadGroup> {
+    let mut threads: HashMap<Option<u32>, (usize, HashMap<String, HistogramEntry>)> =
+        HashMap::new();
+    for trace in by_allocation_trace(snapshot, filter) {
+        let thread_serial = snapshot
+            .trace_index
+            .get(&trace.strace_num)
+            .map(|&t| snapshot.traces[t].thread_serial_num)
+            .filter(|&serial| serial != 0);
+        let (traces, classes) = threads.entry(thread_serial).or_default();
+        *traces += 1;
+        for class in trace.classes {
+            match classes.get_mut(&class.class_name) {
+                Some(entry) => {
+                    entry.instances += class.instances;
+                    entry.shallow += class.shallow;
+                }
+                None => {
+                    classes.insert(class.class_name.clone(), class);
+                }
+            }
+        }
+    }
+
+    let mut groups: Vec<ThreadGroup> = threads
+        .into_iter()
+        .map(|(thread_serial, (traces, classes))| {
+            let mut classes: Vec<HistogramEntry> = classes.into_values().collect();
+            classes.sort_by(|a, b| {
+                b.shallow
+                    .cmp(&a.shallow)
+                    .then_with(|| a.class_name.cmp(&b.class_name))
+            });
+            ThreadGroup {
+                thread_serial,
+                traces,
+                instances: classes.iter().map(|c| c.instances).sum(),
+                shallow: classes.iter().map(|c| c.shallow).sum(),
+                classes,
+            }
+        })
+        .collect();
+    groups.sort_by(|a, b| {
+        b.shallow
+            .cmp(&a.shallow)
+            .then_with(|| a.thread_serial.cmp(&b.thread_serial))
+    });
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::Graph;
+    use crate::heap::{FieldTag, GcRootKind};
+    use crate::reachability;
+    use crate::testing::Dump;
+
+    #[test]
+    fn objects_are_grouped_by_allocation_trace() {
+        let mut dump = Dump::new();
+        let object = dump.object;
+        let worker = dump.class("test/Worker", object, &[]);
+        let frame = dump.frame(worker, "run", "()V", 1);
+        let here = dump.trace(1, &[frame]);
+        let there = dump.trace(1, &[frame, frame]);
+        for _ in 0..3 {
+            dump.instance_at(worker, &[], here);
+        }
+        dump.instance_at(object, &[], here);
+        let allocated = dump.instance_at(object, &[], there);
+        let snapshot = dump.load();
+        let size = |id| snapshot.shallow_size(snapshot.object(id).unwrap());
+        let each = size(allocated);
+
+        // Leaving out the class objects, which have no allocation trace.
+        let groups = by_allocation_trace(&snapshot, |n| {
+            snapshot.class_of(&snapshot.objects[n as usize]).is_some()
+        });
+        let found: Vec<(u32, u64, u64)> = groups
+            .iter()
+            .map(|g| (g.strace_num, g.instances, g.shallow))
+            .collect();
+        assert_eq!(found, vec![(here, 4, 4 * each), (there, 1, each)]);
+        let classes: Vec<(&str, u64)> = groups[0]
+            .classes
+            .iter()
+            .map(|c| (c.class_name.as_str(), c.instances))
+            .collect();
+        assert_eq!(classes, vec![("test.Worker", 3), ("java.lang.Object", 1)]);
+    }
+
+    #[test]
+    fn garbage_and_live_bytes_add_up_per_class() {
+        let mut dump = Dump::new();
+        for i in 0..5 {
+            let bytes = dump.primitive_array(FieldTag::Byte, &[0; 100]);
+            if i < 2 {
+                dump.root(GcRootKind::JniGlobal, bytes);
+            }
+        }
+        let live = dump.string("live");
+        dump.root(GcRootKind::JniGlobal, live);
+        let snapshot = dump.load();
+        let marked = reachability::mark(&Graph::build(&snapshot));
+        let shallow = |h: &[HistogramEntry], name: &str| {
+            h.iter()
+                .find(|e| e.class_name == name)
+                .map_or(0, |e| e.shallow)
+        };
+
+        let all = histogram(&snapshot, |_| true);
+        let live = histogram(&snapshot, |i| marked[i as usize]);
+        let garbage = histogram(&snapshot, |i| !marked[i as usize]);
+        let array = snapshot
+            .shallow_size(&snapshot.objects[snapshot.objects_of_class("byte[]")[0] as usize]);
+        // Three of the byte[]s are garbage, the String's is live.
+        assert_eq!(shallow(&garbage, "byte[]"), 3 * array);
+        assert!(shallow(&live, "byte[]") > 2 * array);
+        for entry in &all {
+            let name = entry.class_name.as_str();
+            assert_eq!(
+                entry.shallow,
+                shallow(&live, name) + shallow(&garbage, name)
+            );
+        }
+        assert!(all.windows(2).all(|w| w[0].shallow >= w[1].shallow));
+    }
+
+    #[test]
+    fn traces_are_grouped_by_allocating_thread() {
+        let mut dump = Dump::new();
+        let object = dump.object;
+        let worker = dump.class("test/Worker", object, &[]);
+        let frame = dump.frame(worker, "run", "()V", 1);
+        let first = dump.trace(1, &[frame]);
+        let second = dump.trace(1, &[frame, frame]);
+        let other = dump.trace(2, &[frame]);
+        let unknown = dump.trace(0, &[frame]);
+        for trace in [first, second, second, other, unknown] {
+            dump.instance_at(worker, &[], trace);
+        }
+        let snapshot = dump.load();
+        let each = snapshot
+            .shallow_size(&snapshot.objects[snapshot.objects_of_class("test.Worker")[0] as usize]);
+
+        let instances = |n: u32| snapshot.class_of(&snapshot.objects[n as usize]) == Some(worker);
+        let groups = by_allocating_thread(&snapshot, instances);
+        let found: Vec<(Option<u32>, usize, u64, u64)> = groups
+            .iter()
+            .map(|g| (g.thread_serial, g.traces, g.instances, g.shallow))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (Some(1), 2, 3, 3 * each),
+                (None, 1, 1, each),
+                (Some(2), 1, 1, each),
+            ]
+        );
+        assert_eq!(groups[0].classes.len(), 1);
+        assert_eq!(groups[0].classes[0].instances, 3);
+    }
+}
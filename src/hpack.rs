@@ -0,0 +1,639 @@
+//
+// HPACK (RFC 7541), the header compression of HTTP/2, for the gRPC service
+// of the daemon. Header blocks from clients are decoded with the static
+// and dynamic tables and the Huffman code of the RFC. Responses only have
+// a few short headers, which are encoded as literals that leave the
+// client's tables alone.
+//
+use std::collections::VecDeque;
+
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+// The lengths of the codes of the 256 octets and EOS. The code is
+// canonical, so the codes themselves follow from them.
+const CODE_LENGTHS: [u8; 257] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 30, 28,
+    28, 28, 28, 28, 28, 28, 28, 28, 6, 10, 10, 12, 13, 6, 8, 11, 10, 10, 8, 11, 8, 6, 6, 6, 5, 5,
+    5, 6, 6, 6, 6, 6, 6, 6, 7, 8, 15, 6, 12, 10, 13, 6, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 8, 7, 8, 13, 19, 13, 14, 6, 15, 5, 6, 5, 6, 5, 6, 6, 6, 5, 7, 7, 6, 6,
+    6, 5, 6, 7, 6, 5, 5, 6, 7, 7, 7, 7, 7, 15, 11, 14, 13, 28, 20, 22, 20, 20, 22, 22, 22, 23, 22,
+    23, 23, 23, 23, 23, 24, 23, 24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24, 22,
+    21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23, 21, 21, 22, 21, 23, 22, 23, 23, 20,
+    22, 22, 22, 23, 22, 22, 23, 26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25, 19,
+    21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27, 20, 24, 20, 21, 22, 21, 21, 23, 22,
+    22, 25, 25, 24, 24, 26, 23, 26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26, 30,
+];
+const EOS: u16 = 256;
+const MAX_CODE_LENGTH: usize = 30;
+
+struct Huffman {
+    counts: [u16; MAX_CODE_LENGTH + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new() -> Huffman {
+        let mut counts = [0u16; MAX_CODE_LENGTH + 1];
+        for &l in &CODE_LENGTHS {
+            counts[l as usize] += 1;
+        }
+        let mut symbols: Vec<u16> = (0..CODE_LENGTHS.len() as u16).collect();
+        symbols.sort_by_key(|&s| (CODE_LENGTHS[s as usize], s));
+        Huffman { counts, symbols }
+    }
+
+    //
+    // Decodes a Huffman-coded string. The last code is padded to a byte
+    // with the most significant bits of EOS (all ones), which must not be
+    // longer than 7 bits.
+    //
+    fn decode(&self, bytes: &[u8]) -> Result<Vec<u8>, String> {
+        let mut out = Vec::with_capacity(bytes.len() * 8 / 5);
+        let (mut code, mut first, mut index, mut len) = (0i64, 0i64, 0i64, 0usize);
+        let mut ones = true;
+        for &byte in bytes {
+            for shift in (0..8).rev() {
+                let bit = (byte >> shift) & 1;
+                code |= bit as i64;
+                ones &= bit == 1;
+                len += 1;
+                let count = self.counts[len] as i64;
+                if code - count < first {
+                    let symbol = self.symbols[(index + code - first) as usize];
+                    if symbol == EOS {
+                        return Err("EOS in a Huffman-coded string".to_string());
+                    }
+                    out.push(symbol as u8);
+                    code = 0;
+                    first = 0;
+                    index = 0;
+                    len = 0;
+                    ones = true;
+                    continue;
+                }
+                if len == MAX_CODE_LENGTH {
+                    return Err("bad Huffman code".to_string());
+                }
+                index += count;
+                first = (first + count) << 1;
+                code <<= 1;
+            }
+        }
+        if len > 7 || !ones {
+            return Err("bad Huffman padding".to_string());
+        }
+        Ok(out)
+    }
+}
+
+struct Input<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Input<'_> {
+    fn byte(&mut self) -> Result<u8, String> {
+        let byte = *self.bytes.get(self.pos).ok_or("truncated header block")?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    // An integer with an N-bit prefix, the rest of the first byte given.
+    fn integer(&mut self, first: u8, prefix: u32) -> Result<usize, String> {
+        let max = (1usize << prefix) - 1;
+        let mut value = first as usize & max;
+        if value < max {
+            return Ok(value);
+        }
+        let mut shift = 0;
+        loop {
+            let byte = self.byte()?;
+            if shift > 28 {
+                return Err("header integer too large".to_string());
+            }
+            value += ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn string(&mut self, huffman: &Huffman) -> Result<String, String> {
+        let first = self.byte()?;
+        let len = self.integer(first, 7)?;
+        let raw = self
+            .bytes
+            .get(self.pos..self.pos + len)
+            .ok_or("truncated header string")?;
+        self.pos += len;
+        let bytes = if first & 0x80 != 0 {
+            huffman.decode(raw)?
+        } else {
+            raw.to_vec()
+        };
+        String::from_utf8(bytes).map_err(|_| "header is not UTF-8".to_string())
+    }
+}
+
+// The decoding state of one HTTP/2 connection.
+pub struct Decoder {
+    huffman: Huffman,
+    // The dynamic table, newest entry first.
+    table: VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize,
+    // The largest size the client can set, from our SETTINGS.
+    limit: usize,
+    // The largest header list decoded, counted as its table entries would be.
+    max_list: usize,
+}
+
+fn entry_size(entry: &(String, String)) -> usize {
+    entry.0.len() + entry.1.len() + 32
+}
+
+impl Decoder {
+    pub fn new(limit: usize, max_list: usize) -> Decoder {
+        Decoder {
+            huffman: Huffman::new(),
+            table: VecDeque::new(),
+            size: 0,
+            max_size: limit,
+            limit,
+            max_list,
+        }
+    }
+
+    fn entry(&self, index: usize) -> Result<(String, String), String> {
+        match index {
+            0 => Err("header index 0".to_string()),
+            1..=61 => {
+                let (name, value) = STATIC_TABLE[index - 1];
+                Ok((name.to_string(), value.to_string()))
+            }
+            _ => self
+                .table
+                .get(index - 62)
+                .cloned()
+                .ok_or_else(|| format!("header index {} out of the table", index)),
+        }
+    }
+
+    fn evict(&mut self) {
+        while self.size > self.max_size {
+            let entry = self.table.pop_back().unwrap();
+            self.size -= entry_size(&entry);
+        }
+    }
+
+    fn insert(&mut self, entry: (String, String)) {
+        self.size += entry_size(&entry);
+        self.table.push_front(entry);
+        self.evict();
+    }
+
+    pub fn decode(&mut self, block: &[u8]) -> Result<Vec<(String, String)>, String> {
+        let mut input = Input {
+            bytes: block,
+            pos: 0,
+        };
+        let mut headers = Vec::new();
+        let mut list = 0;
+        while input.pos < block.len() {
+            // Indexed fields can make a small block a huge list.
+            if list > self.max_list {
+                return Err(format!("header list over {} bytes", self.max_list));
+            }
+            let first = input.byte()?;
+            if first & 0x80 != 0 {
+                let index = input.integer(first, 7)?;
+                let entry = self.entry(index)?;
+                list += entry_size(&entry);
+                headers.push(entry);
+            } else if first & 0xe0 == 0x20 {
+                let size = input.integer(first, 5)?;
+                if size > self.limit {
+                    return Err(format!("header table size {} over {}", size, self.limit));
+                }
+                self.max_size = size;
+                self.evict();
+            } else {
+                // With incremental indexing, without it, or never indexed.
+                let (prefix, indexed) = if first & 0x40 != 0 {
+                    (6, true)
+                } else {
+                    (4, false)
+                };
+                let name = match input.integer(first, prefix)? {
+                    0 => input.string(&self.huffman)?,
+                    index => self.entry(index)?.0,
+                };
+                let value = input.string(&self.huffman)?;
+                if indexed {
+                    self.insert((name.clone(), value.clone()));
+                }
+                let entry = (name, value);
+                list += entry_size(&entry);
+                headers.push(entry);
+            }
+        }
+        if list > self.max_list {
+            return Err(format!("header list over {} bytes", self.max_list));
+        }
+        Ok(headers)
+    }
+}
+
+fn push_integer(out: &mut Vec<u8>, first: u8, prefix: u32, value: usize) {
+    let max = (1usize << prefix) - 1;
+    if value < max {
+        out.push(first | value as u8);
+        return;
+    }
+    out.push(first | max as u8);
+    let mut rest = value - max;
+    while rest >= 0x80 {
+        out.push((rest & 0x7f) as u8 | 0x80);
+        rest >>= 7;
+    }
+    out.push(rest as u8);
+}
+
+fn push_string(out: &mut Vec<u8>, s: &str) {
+    push_integer(out, 0, 7, s.len());
+    out.extend_from_slice(s.as_bytes());
+}
+
+//
+// Encodes headers as literals without indexing, referring to the names of
+// the static table where they are in it (and to its entries for a status).
+//
+pub fn encode(headers: &[(String, String)]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (name, value) in headers {
+        if let Some(i) = STATIC_TABLE
+            .iter()
+            .position(|&(n, v)| n == name && v == value)
+        {
+            push_integer(&mut out, 0x80, 7, i + 1);
+            continue;
+        }
+        match STATIC_TABLE.iter().position(|&(n, _)| n == name) {
+            Some(i) => push_integer(&mut out, 0, 4, i + 1),
+            None => {
+                out.push(0);
+                push_string(&mut out, name);
+            }
+        }
+        push_string(&mut out, value);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(text: &str) -> Vec<u8> {
+        let digits: Vec<u8> = text.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+        digits
+            .chunks(2)
+            .map(|d| u8::from_str_radix(std::str::from_utf8(d).unwrap(), 16).unwrap())
+            .collect()
+    }
+
+    fn headers(list: &[(&str, &str)]) -> Vec<(String, String)> {
+        list.iter()
+            .map(|&(n, v)| (n.to_string(), v.to_string()))
+            .collect()
+    }
+
+    type Headers<'a> = &'a [(&'a str, &'a str)];
+
+    // Decodes the header blocks in turn, checking the headers and the
+    // dynamic table after each.
+    fn decode_all(decoder: &mut Decoder, blocks: &[(&str, Headers, Headers, usize)]) {
+        for (i, &(block, expected, table, size)) in blocks.iter().enumerate() {
+            assert_eq!(
+                decoder.decode(&hex(block)),
+                Ok(headers(expected)),
+                "block {}",
+                i
+            );
+            assert_eq!(
+                Vec::from(decoder.table.clone()),
+                headers(table),
+                "block {}",
+                i
+            );
+            assert_eq!(decoder.size, size, "block {}", i);
+        }
+    }
+
+    // RFC 7541 C.2.
+    #[test]
+    fn literal_and_indexed_fields() {
+        let mut decoder = Decoder::new(4096, usize::MAX);
+        decode_all(
+            &mut decoder,
+            &[(
+                "400a 6375 7374 6f6d 2d6b 6579 0d63 7573 746f 6d2d 6865 6164 6572",
+                &[("custom-key", "custom-header")],
+                &[("custom-key", "custom-header")],
+                55,
+            )],
+        );
+        let mut decoder = Decoder::new(4096, usize::MAX);
+        decode_all(
+            &mut decoder,
+            &[
+                (
+                    "040c 2f73 616d 706c 652f 7061 7468",
+                    &[(":path", "/sample/path")],
+                    &[],
+                    0,
+                ),
+                (
+                    "1008 7061 7373 776f 7264 0673 6563 7265 74",
+                    &[("password", "secret")],
+                    &[],
+                    0,
+                ),
+                ("82", &[(":method", "GET")], &[], 0),
+            ],
+        );
+    }
+
+    const REQUESTS: [&[(&str, &str)]; 3] = [
+        &[
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":path", "/"),
+            (":authority", "www.example.com"),
+        ],
+        &[
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":path", "/"),
+            (":authority", "www.example.com"),
+            ("cache-control", "no-cache"),
+        ],
+        &[
+            (":method", "GET"),
+            (":scheme", "https"),
+            (":path", "/index.html"),
+            (":authority", "www.example.com"),
+            ("custom-key", "custom-value"),
+        ],
+    ];
+
+    const REQUEST_TABLES: [&[(&str, &str)]; 3] = [
+        &[(":authority", "www.example.com")],
+        &[
+            ("cache-control", "no-cache"),
+            (":authority", "www.example.com"),
+        ],
+        &[
+            ("custom-key", "custom-value"),
+            ("cache-control", "no-cache"),
+            (":authority", "www.example.com"),
+        ],
+    ];
+
+    fn requests(blocks: [&str; 3]) {
+        let mut decoder = Decoder::new(4096, usize::MAX);
+        let sizes = [57, 110, 164];
+        let blocks: Vec<_> = (0..3)
+            .map(|i| (blocks[i], REQUESTS[i], REQUEST_TABLES[i], sizes[i]))
+            .collect();
+        decode_all(&mut decoder, &blocks);
+    }
+
+    // RFC 7541 C.3.
+    #[test]
+    fn requests_without_huffman() {
+        requests([
+            "8286 8441 0f77 7777 2e65 7861 6d70 6c65 2e63 6f6d",
+            "8286 84be 5808 6e6f 2d63 6163 6865",
+            "8287 85bf 400a 6375 7374 6f6d 2d6b 6579 0c63 7573 746f 6d2d 7661 6c75 65",
+        ]);
+    }
+
+    // RFC 7541 C.4.
+    #[test]
+    fn requests_with_huffman() {
+        requests([
+            "8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff",
+            "8286 84be 5886 a8eb 1064 9cbf",
+            "8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf",
+        ]);
+    }
+
+    const LOCATION: (&str, &str) = ("location", "https://www.example.com");
+    const DATE_21: (&str, &str) = ("date", "Mon, 21 Oct 2013 20:13:21 GMT");
+    const DATE_22: (&str, &str) = ("date", "Mon, 21 Oct 2013 20:13:22 GMT");
+    const PRIVATE: (&str, &str) = ("cache-control", "private");
+    const COOKIE: (&str, &str) = (
+        "set-cookie",
+        "foo=ASDJKHQKBZXOQWEOPIUAXQWEOIU; max-age=3600; version=1",
+    );
+
+    // With a table of 256 octets, which the responses overflow.
+    fn responses(blocks: [&str; 3]) {
+        let mut decoder = Decoder::new(256, usize::MAX);
+        decode_all(
+            &mut decoder,
+            &[
+                (
+                    blocks[0],
+                    &[(":status", "302"), PRIVATE, DATE_21, LOCATION],
+                    &[LOCATION, DATE_21, PRIVATE, (":status", "302")],
+                    222,
+                ),
+                (
+                    blocks[1],
+                    &[(":status", "307"), PRIVATE, DATE_21, LOCATION],
+                    &[(":status", "307"), LOCATION, DATE_21, PRIVATE],
+                    222,
+                ),
+                (
+                    blocks[2],
+                    &[
+                        (":status", "200"),
+                        PRIVATE,
+                        DATE_22,
+                        LOCATION,
+                        ("content-encoding", "gzip"),
+                        COOKIE,
+                    ],
+                    &[COOKIE, ("content-encoding", "gzip"), DATE_22],
+                    215,
+                ),
+            ],
+        );
+    }
+
+    // RFC 7541 C.5.
+    #[test]
+    fn responses_without_huffman() {
+        responses([
+            "4803 3330 3258 0770 7269 7661 7465 611d 4d6f 6e2c 2032 3120 4f63 7420 3230 3133
+             2032 303a 3133 3a32 3120 474d 546e 1768 7474 7073 3a2f 2f77 7777 2e65 7861 6d70
+             6c65 2e63 6f6d",
+            "4803 3330 37c1 c0bf",
+            "88c1 611d 4d6f 6e2c 2032 3120 4f63 7420 3230 3133 2032 303a 3133 3a32 3220 474d
+             54c0 5a04 677a 6970 7738 666f 6f3d 4153 444a 4b48 514b 425a 584f 5157 454f 5049
+             5541 5851 5745 4f49 553b 206d 6178 2d61 6765 3d33 3630 303b 2076 6572 7369 6f6e
+             3d31",
+        ]);
+    }
+
+    // RFC 7541 C.6.
+    #[test]
+    fn responses_with_huffman() {
+        responses([
+            "4882 6402 5885 aec3 771a 4b61 96d0 7abe 9410 54d4 44a8 2005 9504 0b81 66e0 82a6
+             2d1b ff6e 919d 29ad 1718 63c7 8f0b 97c8 e9ae 82ae 43d3",
+            "4883 640e ffc1 c0bf",
+            "88c1 6196 d07a be94 1054 d444 a820 0595 040b 8166 e084 a62d 1bff c05a 839b d9ab
+             77ad 94e7 821d d7f2 e6c7 b335 dfdf cd5b 3960 d5af 2708 7f36 72c1 ab27 0fb5 291f
+             9587 3160 65c0 03ed 4ee5 b106 3d50 07",
+        ]);
+    }
+
+    #[test]
+    fn table_size_updates() {
+        let mut decoder = Decoder::new(4096, usize::MAX);
+        decoder
+            .decode(&hex(
+                "400a 6375 7374 6f6d 2d6b 6579 0d63 7573 746f 6d2d 6865 6164 6572",
+            ))
+            .unwrap();
+        // Down to nothing, evicting the entry, then back up.
+        assert_eq!(decoder.decode(&hex("20 3fe1 1f")), Ok(Vec::new()));
+        assert!(decoder.table.is_empty());
+        assert_eq!(decoder.max_size, 4096);
+        assert_eq!(
+            decoder.decode(&hex("3fe2 1f")),
+            Err("header table size 4097 over 4096".to_string())
+        );
+    }
+
+    #[test]
+    fn encoded_headers_decode() {
+        let sent = headers(&[
+            (":status", "200"),
+            (":status", "418"),
+            ("content-type", "application/grpc"),
+            ("grpc-status", "0"),
+            ("grpc-message", &"long ".repeat(100)),
+        ]);
+        let mut decoder = Decoder::new(4096, usize::MAX);
+        assert_eq!(decoder.decode(&encode(&sent)), Ok(sent));
+        assert!(decoder.table.is_empty());
+    }
+
+    #[test]
+    fn bad_header_blocks() {
+        let mut decoder = Decoder::new(4096, usize::MAX);
+        for (block, error) in [
+            ("80", "header index 0"),
+            ("be", "header index 62 out of the table"),
+            ("ff", "truncated header block"),
+            ("ff ff ff ff ff ff 01", "header integer too large"),
+            ("40", "truncated header block"),
+            ("400a 6375 7374", "truncated header string"),
+            ("0f ff", "truncated header block"),
+            ("4084 ffff ffff 00", "EOS in a Huffman-coded string"),
+            ("4081 fe 00", "bad Huffman padding"),
+            ("4082 1fff 00", "bad Huffman padding"),
+            ("4081 18 00", "bad Huffman padding"),
+            ("4002 c3a9 01 ff", "header is not UTF-8"),
+        ] {
+            assert_eq!(
+                decoder.decode(&hex(block)),
+                Err(error.to_string()),
+                "{}",
+                block
+            );
+        }
+    }
+
+    #[test]
+    fn header_lists_are_bounded() {
+        // :method GET counts 42 bytes, as its table entry would.
+        let mut decoder = Decoder::new(4096, 84);
+        assert_eq!(decoder.decode(&hex("82 82")).unwrap().len(), 2);
+        assert_eq!(
+            decoder.decode(&hex("82 82 82")),
+            Err("header list over 84 bytes".to_string())
+        );
+        assert_eq!(
+            decoder.decode(&hex(&"82 ".repeat(100_000))),
+            Err("header list over 84 bytes".to_string())
+        );
+    }
+}
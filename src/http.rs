@@ -0,0 +1,285 @@
+//
+// A minimal HTTP/1.1 server for the JSON API of `serve`: each connection is
+// handled on a thread of its own and closed after one response, which is
+// all the clients of a local analysis service need. Query strings and
+// url-encoded bodies are decoded into parameters.
+//
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+// Requests with larger bodies are refused.
+const MAX_BODY: usize = 1 << 20;
+// And those with longer lines in their head, or more headers.
+const MAX_LINE: u64 = 8 << 10;
+const MAX_HEADERS: usize = 100;
+
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    // The decoded path, without the query string.
+    pub path: String,
+    pub params: HashMap<String, String>,
+    pub body: String,
+}
+
+impl Request {
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(|s| s.as_str())
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    pub fn json(status: u16, body: String) -> Response {
+        Response {
+            status,
+            content_type: "application/json",
+            body,
+        }
+    }
+
+    pub fn text(status: u16, body: String) -> Response {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body,
+        }
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        _ => "Error",
+    }
+}
+
+// Decodes %XX escapes, and + as a space.
+pub fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' => match (hex_digit(bytes.get(i + 1)), hex_digit(bytes.get(i + 2))) {
+                (Some(high), Some(low)) => {
+                    out.push(high << 4 | low);
+                    i += 2;
+                }
+                _ => out.push(b'%'),
+            },
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn hex_digit(c: Option<&u8>) -> Option<u8> {
+    (*c? as char).to_digit(16).map(|d| d as u8)
+}
+
+fn parse_params(text: &str, params: &mut HashMap<String, String>) {
+    for pair in text.split('&').filter(|p| !p.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        params.insert(percent_decode(key), percent_decode(value));
+    }
+}
+
+// A line of the head of a request, None if it is longer than MAX_LINE.
+fn read_line<R: BufRead>(reader: &mut R) -> std::io::Result<Option<String>> {
+    let mut line = String::new();
+    reader.take(MAX_LINE).read_line(&mut line)?;
+    if line.len() as u64 == MAX_LINE && !line.ends_with('\n') {
+        return Ok(None);
+    }
+    Ok(Some(line))
+}
+
+pub fn read_request<R: BufRead>(reader: &mut R) -> Result<Request, Response> {
+    let bad = |msg: &str| Response::json(400, format!("{{\"error\": \"{}\"}}", msg));
+    let too_large = || Response::json(431, "{\"error\": \"request head too large\"}".to_string());
+    let line = read_line(reader)
+        .map_err(|_| bad("unreadable request"))?
+        .ok_or_else(too_large)?;
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target),
+        _ => return Err(bad("bad request line")),
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut request = Request {
+        method,
+        path: percent_decode(path),
+        params: HashMap::new(),
+        body: String::new(),
+    };
+    parse_params(query, &mut request.params);
+
+    let mut content_length = 0;
+    let mut form = false;
+    for count in 0.. {
+        let header = read_line(reader)
+            .map_err(|_| bad("unreadable headers"))?
+            .ok_or_else(too_large)?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if count == MAX_HEADERS {
+            return Err(too_large());
+        }
+        let (name, value) = header.split_once(':').unwrap_or((header, ""));
+        match name.to_ascii_lowercase().as_str() {
+            "content-length" => {
+                content_length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| bad("bad content length"))?
+            }
+            "content-type" => {
+                form = value
+                    .trim()
+                    .starts_with("application/x-www-form-urlencoded")
+            }
+            _ => {}
+        }
+    }
+    if content_length > MAX_BODY {
+        return Err(Response::json(
+            413,
+            "{\"error\": \"body too large\"}".to_string(),
+        ));
+    }
+    let mut body = vec![0; content_length];
+    reader
+        .read_exact(&mut body)
+        .map_err(|_| bad("truncated body"))?;
+    request.body = String::from_utf8_lossy(&body).into_owned();
+    if form {
+        let body = request.body.clone();
+        parse_params(&body, &mut request.params);
+    }
+    Ok(request)
+}
+
+pub fn write_response<W: Write>(writer: &mut W, response: &Response) -> std::io::Result<()> {
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len()
+    )?;
+    writer.write_all(response.body.as_bytes())?;
+    writer.flush()
+}
+
+fn handle<F: Fn(&Request) -> Response>(stream: TcpStream, handler: &F) {
+    let mut reader = BufReader::new(match stream.try_clone() {
+        Ok(s) => s,
+        Err(_) => return,
+    });
+    let response = match read_request(&mut reader) {
+        Ok(request) => handler(&request),
+        Err(response) => response,
+    };
+    let mut stream = stream;
+    let _ = write_response(&mut stream, &response);
+}
+
+// Answers the connections to `listener` with `handler`, until it fails.
+pub fn serve<F>(listener: TcpListener, handler: F)
+where
+    F: Fn(&Request) -> Response + Sync,
+{
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    scope.spawn(|| handle(stream, &handler));
+                }
+                Err(e) => eprintln!("accept: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(text: &str) -> Result<Request, Response> {
+        read_request(&mut text.as_bytes())
+    }
+
+    #[test]
+    fn queries_and_forms_are_decoded() {
+        let request = read("GET /object/0x10?depth=2&name=a%20b+c HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/object/0x10");
+        assert_eq!(request.param("depth"), Some("2"));
+        assert_eq!(request.param("name"), Some("a b c"));
+
+        let request = read(concat!(
+            "POST /query HTTP/1.1\r\n",
+            "Content-Type: application/x-www-form-urlencoded\r\n",
+            "Content-Length: 22\r\n\r\n",
+            "class=java.lang.String"
+        ))
+        .unwrap();
+        assert_eq!(request.param("class"), Some("java.lang.String"));
+        assert_eq!(request.body, "class=java.lang.String");
+    }
+
+    #[test]
+    fn broken_escapes_are_kept() {
+        assert_eq!(percent_decode("%41%4"), "A%4");
+        assert_eq!(percent_decode("%+1%"), "% 1%");
+        assert_eq!(percent_decode("%e2%82%ac"), "€");
+    }
+
+    #[test]
+    fn bad_requests_are_refused() {
+        let status = |text: &str| read(text).unwrap_err().status;
+        assert_eq!(status("\r\n"), 400);
+        assert_eq!(status("GET / HTTP/1.1\r\nContent-Length: x\r\n\r\n"), 400);
+        assert_eq!(
+            status("POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort"),
+            400
+        );
+        assert_eq!(
+            status("POST / HTTP/1.1\r\nContent-Length: 2000000\r\n\r\n"),
+            413
+        );
+        let long = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(10_000));
+        assert_eq!(status(&long), 431);
+        let many = format!("GET / HTTP/1.1\r\n{}\r\n", "X-A: b\r\n".repeat(200));
+        assert_eq!(status(&many), 431);
+    }
+
+    #[test]
+    fn responses_have_their_length() {
+        let mut out = Vec::new();
+        write_response(&mut out, &Response::json(404, "{}".to_string())).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(out.contains("Content-Length: 2\r\n"));
+        assert!(out.ends_with("\r\n\r\n{}"));
+    }
+}
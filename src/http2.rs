@@ -0,0 +1,779 @@
+//
+// A minimal HTTP/2 server (RFC 9113) over cleartext TCP with prior
+// knowledge, which is how gRPC clients talk to a plaintext service. Each
+// connection has a thread of its own on which its streams are answered
+// one at a time, in the order their requests complete. The flow control
+// windows of the client are honoured when sending responses. The window of
+// a stream is given back as its request is received, but the window of the
+// connection only as requests are answered, which bounds what the bodies
+// waiting for an answer hold.
+//
+use crate::hpack::{self, Decoder};
+
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryInto;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY: u8 = 0x20;
+
+const PROTOCOL_ERROR: u32 = 0x1;
+const FLOW_CONTROL_ERROR: u32 = 0x3;
+const REFUSED_STREAM: u32 = 0x7;
+const FRAME_SIZE_ERROR: u32 = 0x6;
+const COMPRESSION_ERROR: u32 = 0x9;
+const ENHANCE_YOUR_CALM: u32 = 0xb;
+
+const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+// The largest frame accepted, the default of the protocol.
+const MAX_FRAME: usize = 16384;
+const HEADER_TABLE_SIZE: usize = 4096;
+// What we let clients send ahead on a stream.
+const WINDOW: u32 = 1 << 20;
+const DEFAULT_WINDOW: i64 = 65535;
+const MAX_WINDOW: i64 = 0x7fff_ffff;
+// Requests with larger bodies are reset.
+const MAX_BODY: usize = 64 << 20;
+// What the bodies of a connection can hold before they are answered.
+const CONNECTION_WINDOW: u32 = MAX_BODY as u32;
+// Larger header blocks, or lists once decoded, close the connection.
+const MAX_HEADER_LIST: usize = 64 << 10;
+const MAX_STREAMS: usize = 100;
+
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    // The regular headers, without the pseudo-headers.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    pub trailers: Vec<(String, String)>,
+}
+
+#[derive(Default)]
+struct Stream {
+    // Header block fragments until END_HEADERS.
+    block: Vec<u8>,
+    headers: Option<Vec<(String, String)>>,
+    body: Vec<u8>,
+    // What we may still send on it.
+    window: i64,
+    // Opened beyond MAX_STREAMS, to be reset once its headers are decoded.
+    refused: bool,
+}
+
+// Why a connection is closed: the peer went away, or a connection error.
+enum Close {
+    Io,
+    Error(u32, String),
+}
+
+impl From<io::Error> for Close {
+    fn from(_: io::Error) -> Close {
+        Close::Io
+    }
+}
+
+fn protocol_error<T>(message: &str) -> Result<T, Close> {
+    Err(Close::Error(PROTOCOL_ERROR, message.to_string()))
+}
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    decoder: Decoder,
+    streams: HashMap<u32, Stream>,
+    // Streams whose request is complete, to be answered.
+    ready: VecDeque<u32>,
+    // The stream of a header block continued by CONTINUATION frames.
+    continuing: Option<(u32, bool)>,
+    last_stream: u32,
+    window: i64,
+    // What the client may still send on the connection.
+    received: i64,
+    initial_window: i64,
+    max_frame: usize,
+}
+
+impl Connection {
+    fn write_frame(&mut self, kind: u8, flags: u8, stream: u32, payload: &[u8]) -> io::Result<()> {
+        let mut frame = Vec::with_capacity(9 + payload.len());
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+        frame.push(kind);
+        frame.push(flags);
+        frame.extend_from_slice(&stream.to_be_bytes());
+        frame.extend_from_slice(payload);
+        self.writer.write_all(&frame)
+    }
+
+    fn window_update(&mut self, stream: u32, increment: usize) -> io::Result<()> {
+        self.write_frame(WINDOW_UPDATE, 0, stream, &(increment as u32).to_be_bytes())
+    }
+
+    // Gives back the window of the connection used by `bytes` no longer held.
+    fn release(&mut self, bytes: usize) -> io::Result<()> {
+        if bytes == 0 {
+            return Ok(());
+        }
+        self.received += bytes as i64;
+        self.window_update(0, bytes)
+    }
+
+    // Forgets a stream, with what its body still holds.
+    fn remove(&mut self, id: u32) -> io::Result<()> {
+        match self.streams.remove(&id) {
+            Some(stream) => self.release(stream.body.len()),
+            None => Ok(()),
+        }
+    }
+
+    // Appends to the header block of a stream, which cannot grow without bound.
+    fn fragment(&mut self, id: u32, fragment: &[u8]) -> Result<(), Close> {
+        let block = &mut self.streams.get_mut(&id).unwrap().block;
+        if block.len() + fragment.len() > MAX_HEADER_LIST {
+            return protocol_error("header block too large");
+        }
+        block.extend_from_slice(fragment);
+        Ok(())
+    }
+
+    // The payload of a DATA or HEADERS frame without its padding.
+    fn unpad(flags: u8, payload: &[u8]) -> Result<&[u8], Close> {
+        if flags & PADDED == 0 {
+            return Ok(payload);
+        }
+        let pad = *payload
+            .first()
+            .ok_or(Close::Error(FRAME_SIZE_ERROR, String::new()))? as usize;
+        if pad + 1 > payload.len() {
+            return protocol_error("padding longer than the frame");
+        }
+        Ok(&payload[1..payload.len() - pad])
+    }
+
+    fn end_headers(&mut self, id: u32, end_stream: bool) -> Result<(), Close> {
+        let stream = self.streams.get_mut(&id).unwrap();
+        let block = std::mem::take(&mut stream.block);
+        let headers = self
+            .decoder
+            .decode(&block)
+            .map_err(|e| Close::Error(COMPRESSION_ERROR, e))?;
+        if stream.refused {
+            self.streams.remove(&id);
+            self.write_frame(RST_STREAM, 0, id, &REFUSED_STREAM.to_be_bytes())?;
+            return Ok(());
+        }
+        // A second header block is the trailers of the request, unused.
+        if stream.headers.is_none() {
+            stream.headers = Some(headers);
+        }
+        if end_stream {
+            self.ready.push_back(id);
+        }
+        Ok(())
+    }
+
+    // Reads and handles one frame, false at the end of the connection.
+    fn read_frame(&mut self) -> Result<bool, Close> {
+        let mut header = [0u8; 9];
+        match self.reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(e) => return Err(e.into()),
+        }
+        let len = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        let (kind, flags) = (header[3], header[4]);
+        let id = u32::from_be_bytes(header[5..9].try_into().unwrap()) & 0x7fff_ffff;
+        if len > MAX_FRAME {
+            return Err(Close::Error(
+                FRAME_SIZE_ERROR,
+                "frame too large".to_string(),
+            ));
+        }
+        let mut payload = vec![0; len];
+        self.reader.read_exact(&mut payload)?;
+
+        if let Some((continued, _)) = self.continuing {
+            if kind != CONTINUATION || id != continued {
+                return protocol_error("expected CONTINUATION");
+            }
+        }
+        match kind {
+            DATA => {
+                self.received -= len as i64;
+                if self.received < 0 {
+                    return Err(Close::Error(
+                        FLOW_CONTROL_ERROR,
+                        "connection window exceeded".to_string(),
+                    ));
+                }
+                let data = Self::unpad(flags, &payload)?;
+                // Only the data of the body is held until it is answered.
+                let kept = match self.streams.get(&id) {
+                    Some(stream) if stream.headers.is_some() => data.len(),
+                    _ => 0,
+                };
+                self.release(len - kept)?;
+                if kept == 0 {
+                    return Ok(true);
+                }
+                let stream = self.streams.get_mut(&id).unwrap();
+                if stream.body.len() + data.len() > MAX_BODY {
+                    self.remove(id)?;
+                    self.release(data.len())?;
+                    self.write_frame(RST_STREAM, 0, id, &ENHANCE_YOUR_CALM.to_be_bytes())?;
+                    return Ok(true);
+                }
+                stream.body.extend_from_slice(data);
+                if flags & END_STREAM != 0 {
+                    self.ready.push_back(id);
+                } else if len > 0 {
+                    self.window_update(id, len)?;
+                }
+            }
+            HEADERS => {
+                let mut fragment = Self::unpad(flags, &payload)?;
+                if flags & PRIORITY != 0 {
+                    fragment = fragment
+                        .get(5..)
+                        .ok_or(Close::Error(FRAME_SIZE_ERROR, String::new()))?;
+                }
+                if !self.streams.contains_key(&id) {
+                    if id % 2 == 0 || id <= self.last_stream {
+                        return protocol_error("bad stream id");
+                    }
+                    self.last_stream = id;
+                    // Its headers are still decoded, to keep the table in step.
+                    let refused = self.streams.len() >= MAX_STREAMS;
+                    let window = self.initial_window;
+                    self.streams.insert(
+                        id,
+                        Stream {
+                            window,
+                            refused,
+                            ..Stream::default()
+                        },
+                    );
+                }
+                let end_stream = flags & END_STREAM != 0;
+                self.fragment(id, fragment)?;
+                if flags & END_HEADERS != 0 {
+                    self.end_headers(id, end_stream)?;
+                } else {
+                    self.continuing = Some((id, end_stream));
+                }
+            }
+            CONTINUATION => {
+                let (continued, end_stream) = match self.continuing {
+                    Some(continuing) => continuing,
+                    None => return protocol_error("unexpected CONTINUATION"),
+                };
+                self.fragment(continued, &payload)?;
+                if flags & END_HEADERS != 0 {
+                    self.continuing = None;
+                    self.end_headers(continued, end_stream)?;
+                }
+            }
+            SETTINGS if flags & ACK != 0 => {}
+            SETTINGS => {
+                if !len.is_multiple_of(6) {
+                    return Err(Close::Error(FRAME_SIZE_ERROR, "bad SETTINGS".to_string()));
+                }
+                for setting in payload.chunks(6) {
+                    let value = u32::from_be_bytes(setting[2..6].try_into().unwrap());
+                    match u16::from_be_bytes([setting[0], setting[1]]) {
+                        SETTINGS_INITIAL_WINDOW_SIZE => {
+                            if value as i64 > MAX_WINDOW {
+                                return Err(Close::Error(FLOW_CONTROL_ERROR, String::new()));
+                            }
+                            let delta = value as i64 - self.initial_window;
+                            self.initial_window = value as i64;
+                            for stream in self.streams.values_mut() {
+                                stream.window += delta;
+                                if stream.window > MAX_WINDOW {
+                                    return Err(Close::Error(
+                                        FLOW_CONTROL_ERROR,
+                                        "stream window too large".to_string(),
+                                    ));
+                                }
+                            }
+                        }
+                        SETTINGS_MAX_FRAME_SIZE => {
+                            self.max_frame = (value as usize).clamp(MAX_FRAME, (1 << 24) - 1)
+                        }
+                        _ => {}
+                    }
+                }
+                self.write_frame(SETTINGS, ACK, 0, &[])?;
+            }
+            PING if flags & ACK == 0 => self.write_frame(PING, ACK, 0, &payload)?,
+            WINDOW_UPDATE => {
+                if len != 4 {
+                    return Err(Close::Error(
+                        FRAME_SIZE_ERROR,
+                        "bad WINDOW_UPDATE".to_string(),
+                    ));
+                }
+                let increment =
+                    (u32::from_be_bytes(payload[..].try_into().unwrap()) & 0x7fff_ffff) as i64;
+                if id == 0 {
+                    self.window += increment;
+                    if self.window > MAX_WINDOW {
+                        return Err(Close::Error(
+                            FLOW_CONTROL_ERROR,
+                            "connection window too large".to_string(),
+                        ));
+                    }
+                } else if let Some(stream) = self.streams.get_mut(&id) {
+                    stream.window += increment;
+                    if stream.window > MAX_WINDOW {
+                        self.remove(id)?;
+                        self.write_frame(RST_STREAM, 0, id, &FLOW_CONTROL_ERROR.to_be_bytes())?;
+                    }
+                }
+            }
+            RST_STREAM => {
+                self.remove(id)?;
+            }
+            PUSH_PROMISE => return protocol_error("clients cannot push"),
+            GOAWAY => return Ok(false),
+            // PRIORITY, acknowledgements and unknown frames.
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    fn write_headers(
+        &mut self,
+        id: u32,
+        headers: &[(String, String)],
+        end_stream: bool,
+    ) -> io::Result<()> {
+        let block = hpack::encode(headers);
+        let mut chunks = block.chunks(self.max_frame).peekable();
+        let mut kind = HEADERS;
+        let first = if end_stream { END_STREAM } else { 0 };
+        if chunks.peek().is_none() {
+            return self.write_frame(HEADERS, first | END_HEADERS, id, &[]);
+        }
+        while let Some(chunk) = chunks.next() {
+            let mut flags = if kind == HEADERS { first } else { 0 };
+            if chunks.peek().is_none() {
+                flags |= END_HEADERS;
+            }
+            self.write_frame(kind, flags, id, chunk)?;
+            kind = CONTINUATION;
+        }
+        Ok(())
+    }
+
+    fn respond(&mut self, id: u32, response: Response) -> Result<(), Close> {
+        let mut headers = vec![(":status".to_string(), response.status.to_string())];
+        headers.extend(response.headers);
+        let (body, mut trailers) = (response.body, response.trailers);
+        if body.is_empty() {
+            headers.append(&mut trailers);
+            self.write_headers(id, &headers, true)?;
+            self.remove(id)?;
+            return Ok(());
+        }
+        self.write_headers(id, &headers, false)?;
+        let mut sent = 0;
+        while sent < body.len() {
+            let window = match self.streams.get(&id) {
+                Some(stream) => stream.window.min(self.window),
+                // Reset by the client.
+                None => return Ok(()),
+            };
+            if window <= 0 {
+                if !self.read_frame()? {
+                    return Err(Close::Io);
+                }
+                continue;
+            }
+            let n = (body.len() - sent).min(window as usize).min(self.max_frame);
+            let last = sent + n == body.len() && trailers.is_empty();
+            self.write_frame(
+                DATA,
+                if last { END_STREAM } else { 0 },
+                id,
+                &body[sent..sent + n],
+            )?;
+            self.window -= n as i64;
+            self.streams.get_mut(&id).unwrap().window -= n as i64;
+            sent += n;
+        }
+        if !trailers.is_empty() {
+            self.write_headers(id, &trailers, true)?;
+        }
+        self.remove(id)?;
+        Ok(())
+    }
+
+    fn run<F: Fn(&Request) -> Response>(&mut self, handler: &F) -> Result<(), Close> {
+        let mut preface = [0u8; 24];
+        self.reader.read_exact(&mut preface)?;
+        if preface != PREFACE {
+            return Err(Close::Io);
+        }
+        let mut settings = Vec::new();
+        for (setting, value) in [
+            (SETTINGS_HEADER_TABLE_SIZE, HEADER_TABLE_SIZE as u32),
+            (SETTINGS_MAX_CONCURRENT_STREAMS, MAX_STREAMS as u32),
+            (SETTINGS_INITIAL_WINDOW_SIZE, WINDOW),
+            (SETTINGS_MAX_HEADER_LIST_SIZE, MAX_HEADER_LIST as u32),
+        ] {
+            settings.extend_from_slice(&setting.to_be_bytes());
+            settings.extend_from_slice(&value.to_be_bytes());
+        }
+        self.write_frame(SETTINGS, 0, 0, &settings)?;
+        self.window_update(0, CONNECTION_WINDOW as usize - DEFAULT_WINDOW as usize)?;
+        loop {
+            while let Some(id) = self.ready.pop_front() {
+                let stream = match self.streams.get_mut(&id) {
+                    Some(stream) => stream,
+                    None => continue,
+                };
+                let mut request = Request {
+                    method: String::new(),
+                    path: String::new(),
+                    headers: Vec::new(),
+                    body: std::mem::take(&mut stream.body),
+                };
+                let headers = stream.headers.take().unwrap_or_default();
+                self.release(request.body.len())?;
+                for (name, value) in headers {
+                    match name.as_str() {
+                        ":method" => request.method = value,
+                        ":path" => request.path = value,
+                        _ if name.starts_with(':') => {}
+                        _ => request.headers.push((name, value)),
+                    }
+                }
+                let response = handler(&request);
+                self.respond(id, response)?;
+            }
+            if !self.read_frame()? {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn handle<F: Fn(&Request) -> Response>(stream: TcpStream, handler: &F) {
+    let reader = match stream.try_clone() {
+        Ok(s) => BufReader::new(s),
+        Err(_) => return,
+    };
+    let _ = stream.set_nodelay(true);
+    let mut connection = Connection {
+        reader,
+        writer: stream,
+        decoder: Decoder::new(HEADER_TABLE_SIZE, MAX_HEADER_LIST),
+        streams: HashMap::new(),
+        ready: VecDeque::new(),
+        continuing: None,
+        last_stream: 0,
+        window: DEFAULT_WINDOW,
+        received: CONNECTION_WINDOW as i64,
+        initial_window: DEFAULT_WINDOW,
+        max_frame: MAX_FRAME,
+    };
+    if let Err(Close::Error(code, message)) = connection.run(handler) {
+        let mut payload = connection.last_stream.to_be_bytes().to_vec();
+        payload.extend_from_slice(&code.to_be_bytes());
+        payload.extend_from_slice(message.as_bytes());
+        let _ = connection.write_frame(GOAWAY, 0, 0, &payload);
+    }
+}
+
+// Answers the HTTP/2 connections to `listener` with `handler`, until it fails.
+pub fn serve<F>(listener: TcpListener, handler: F)
+where
+    F: Fn(&Request) -> Response + Sync,
+{
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    scope.spawn(|| handle(stream, &handler));
+                }
+                Err(e) => eprintln!("accept: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Shutdown, SocketAddr};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    // A server answering with the body of each request, counting them.
+    fn server() -> (SocketAddr, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counted = requests.clone();
+        thread::spawn(move || {
+            serve(listener, move |request: &Request| {
+                counted.fetch_add(1, Ordering::SeqCst);
+                Response {
+                    status: 200,
+                    headers: vec![("path".to_string(), request.path.clone())],
+                    body: request.body.clone(),
+                    trailers: vec![("grpc-status".to_string(), "0".to_string())],
+                }
+            })
+        });
+        (address, requests)
+    }
+
+    fn connect(address: SocketAddr) -> TcpStream {
+        let stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        (&stream).write_all(PREFACE).unwrap();
+        stream
+    }
+
+    fn frame(kind: u8, flags: u8, stream: u32, payload: &[u8]) -> Vec<u8> {
+        let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+        frame.extend_from_slice(&[kind, flags]);
+        frame.extend_from_slice(&stream.to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    // The frames sent until the server closes the connection.
+    fn frames(mut stream: &TcpStream) -> Vec<(u8, u8, u32, Vec<u8>)> {
+        let mut frames = Vec::new();
+        let mut header = [0u8; 9];
+        while stream.read_exact(&mut header).is_ok() {
+            let len = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+            let mut payload = vec![0; len];
+            stream.read_exact(&mut payload).unwrap();
+            let id = u32::from_be_bytes(header[5..9].try_into().unwrap());
+            frames.push((header[3], header[4], id, payload));
+        }
+        frames
+    }
+
+    fn goaway(frames: &[(u8, u8, u32, Vec<u8>)]) -> Option<u32> {
+        frames
+            .iter()
+            .find(|f| f.0 == GOAWAY)
+            .map(|f| u32::from_be_bytes(f.3[4..8].try_into().unwrap()))
+    }
+
+    #[test]
+    fn requests_are_answered() {
+        let (address, requests) = server();
+        let stream = connect(address);
+        let block = hpack::encode(&[
+            (":method".to_string(), "POST".to_string()),
+            (":path".to_string(), "/heap.Analysis/Histogram".to_string()),
+        ]);
+        let (first, rest) = block.split_at(3);
+        let mut sent = frame(SETTINGS, 0, 0, &[]);
+        sent.extend(frame(HEADERS, 0, 1, first));
+        sent.extend(frame(CONTINUATION, END_HEADERS, 1, rest));
+        sent.extend(frame(DATA, PADDED, 1, &[2, b'a', b'b', 0, 0]));
+        sent.extend(frame(DATA, END_STREAM, 1, b"cd"));
+        (&stream).write_all(&sent).unwrap();
+        (&stream).write_all(&frame(GOAWAY, 0, 0, &[0; 8])).unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+
+        let frames = frames(&stream);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert!(frames.iter().any(|f| f.0 == SETTINGS && f.1 == ACK));
+        // The window of the padded DATA frame is given back, padding included.
+        let updates: Vec<_> = frames
+            .iter()
+            .filter(|f| f.0 == WINDOW_UPDATE && f.2 == 1)
+            .map(|f| f.3.clone())
+            .collect();
+        assert_eq!(updates, [5u32.to_be_bytes()]);
+        // The connection gets the padding back at once, the body once answered.
+        let updates: Vec<_> = frames
+            .iter()
+            .filter(|f| f.0 == WINDOW_UPDATE && f.2 == 0)
+            .map(|f| u32::from_be_bytes(f.3[..].try_into().unwrap()))
+            .collect();
+        assert_eq!(updates, [CONNECTION_WINDOW - DEFAULT_WINDOW as u32, 3, 4]);
+        let answer: Vec<_> = frames
+            .iter()
+            .filter(|f| f.2 == 1 && f.0 != WINDOW_UPDATE)
+            .collect();
+        let kinds: Vec<_> = answer.iter().map(|f| (f.0, f.1)).collect();
+        assert_eq!(
+            kinds,
+            [
+                (HEADERS, END_HEADERS),
+                (DATA, 0),
+                (HEADERS, END_HEADERS | END_STREAM)
+            ]
+        );
+        let mut decoder = Decoder::new(HEADER_TABLE_SIZE, usize::MAX);
+        assert_eq!(
+            decoder.decode(&answer[0].3).unwrap(),
+            [
+                (":status".to_string(), "200".to_string()),
+                ("path".to_string(), "/heap.Analysis/Histogram".to_string()),
+            ]
+        );
+        assert_eq!(answer[1].3, b"abcd");
+        assert_eq!(
+            decoder.decode(&answer[2].3).unwrap(),
+            [("grpc-status".to_string(), "0".to_string())]
+        );
+        assert_eq!(goaway(&frames), None);
+    }
+
+    #[test]
+    fn oversized_frames_are_refused() {
+        let (address, requests) = server();
+        let stream = connect(address);
+        let mut header = frame(HEADERS, END_HEADERS | END_STREAM, 1, &[]);
+        header[..3].copy_from_slice(&(MAX_FRAME as u32 + 1).to_be_bytes()[1..]);
+        (&stream).write_all(&header).unwrap();
+        let frames = frames(&stream);
+        assert_eq!(goaway(&frames), Some(FRAME_SIZE_ERROR));
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn malformed_frames_are_refused() {
+        let (address, requests) = server();
+        for (sent, code) in [
+            (frame(SETTINGS, 0, 0, &[0; 5]), FRAME_SIZE_ERROR),
+            (frame(WINDOW_UPDATE, 0, 0, &[0; 3]), FRAME_SIZE_ERROR),
+            (
+                frame(HEADERS, END_HEADERS | PADDED, 1, &[]),
+                FRAME_SIZE_ERROR,
+            ),
+            (
+                frame(HEADERS, END_HEADERS | PADDED, 1, &[4, 0]),
+                PROTOCOL_ERROR,
+            ),
+            (frame(HEADERS, END_HEADERS, 2, &[0x82]), PROTOCOL_ERROR),
+            (frame(HEADERS, END_HEADERS, 1, &[0x80]), COMPRESSION_ERROR),
+            (frame(CONTINUATION, END_HEADERS, 1, &[0x82]), PROTOCOL_ERROR),
+            (frame(PUSH_PROMISE, END_HEADERS, 1, &[0; 4]), PROTOCOL_ERROR),
+            (
+                frame(HEADERS, END_HEADERS, 1, &[0x82; 2000]),
+                COMPRESSION_ERROR,
+            ),
+            (
+                frame(WINDOW_UPDATE, 0, 0, &0x7fff_ffffu32.to_be_bytes()),
+                FLOW_CONTROL_ERROR,
+            ),
+            (
+                [frame(HEADERS, 0, 1, &[0x82]), frame(DATA, 0, 1, b"x")].concat(),
+                PROTOCOL_ERROR,
+            ),
+        ] {
+            let stream = connect(address);
+            (&stream).write_all(&sent).unwrap();
+            assert_eq!(goaway(&frames(&stream)), Some(code), "{:?}", sent);
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn header_blocks_are_bounded() {
+        let (address, requests) = server();
+        let stream = connect(address);
+        let mut sent = frame(HEADERS, 0, 1, &[0x82]);
+        // The last fragment takes the block over the limit.
+        for _ in 0..MAX_HEADER_LIST / MAX_FRAME {
+            sent.extend(frame(CONTINUATION, 0, 1, &[0x82; MAX_FRAME]));
+        }
+        (&stream).write_all(&sent).unwrap();
+        assert_eq!(goaway(&frames(&stream)), Some(PROTOCOL_ERROR));
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn streams_beyond_the_limit_are_refused() {
+        let (address, requests) = server();
+        let stream = connect(address);
+        let block = hpack::encode(&[(":path".to_string(), "/".to_string())]);
+        let mut sent = Vec::new();
+        for i in 0..MAX_STREAMS as u32 + 1 {
+            sent.extend(frame(HEADERS, END_HEADERS, 2 * i + 1, &block));
+        }
+        // The refused stream is not answered when it ends.
+        let refused = 2 * MAX_STREAMS as u32 + 1;
+        sent.extend(frame(DATA, END_STREAM, refused, b"x"));
+        sent.extend(frame(DATA, END_STREAM, 1, b"y"));
+        (&stream).write_all(&sent).unwrap();
+        (&stream).write_all(&frame(GOAWAY, 0, 0, &[0; 8])).unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+
+        let frames = frames(&stream);
+        let resets: Vec<_> = frames.iter().filter(|f| f.0 == RST_STREAM).collect();
+        assert_eq!(resets.len(), 1);
+        assert_eq!(resets[0].2, refused);
+        assert_eq!(resets[0].3, REFUSED_STREAM.to_be_bytes());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert_eq!(goaway(&frames), None);
+    }
+
+    #[test]
+    fn truncated_frames_are_dropped() {
+        let (address, requests) = server();
+        let block = hpack::encode(&[(":path".to_string(), "/".to_string())]);
+        let whole = frame(HEADERS, END_HEADERS | END_STREAM, 1, &block);
+        for len in 1..whole.len() {
+            let stream = connect(address);
+            (&stream).write_all(&whole[..len]).unwrap();
+            stream.shutdown(Shutdown::Write).unwrap();
+            let frames = frames(&stream);
+            assert!(frames.iter().all(|f| f.2 == 0), "{} bytes", len);
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,98 @@
+//
+// Hash maps keyed by HPROF ids and serial numbers. SipHash, the default of
+// std, protects against adversarial keys at a cost that dominates lookups
+// in the symbol and object tables; ids are addresses that only need
+// spreading, which one folded multiply does (the low bits of aligned
+// addresses are always zero, so the high half of the product is mixed in).
+//
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::hash::{BuildHasherDefault, Hasher};
+
+const MULTIPLIER: u64 = 0x9e37_79b9_7f4a_7c15;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IdHasher(u64);
+
+impl IdHasher {
+    fn add(&mut self, word: u64) {
+        let product = (self.0 ^ word) as u128 * MULTIPLIER as u128;
+        self.0 = (product as u64) ^ ((product >> 64) as u64);
+    }
+}
+
+impl Hasher for IdHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    // Other keys are hashed 8 bytes at a time.
+    fn write(&mut self, bytes: &[u8]) {
+        let mut chunks = bytes.chunks_exact(8);
+        for chunk in &mut chunks {
+            self.add(u64::from_le_bytes(chunk.try_into().unwrap()));
+        }
+        let mut tail = [0u8; 8];
+        let rest = chunks.remainder();
+        tail[..rest.len()].copy_from_slice(rest);
+        self.add(u64::from_le_bytes(tail) ^ (rest.len() as u64) << 56);
+    }
+
+    fn write_u8(&mut self, n: u8) {
+        self.add(n as u64);
+    }
+
+    fn write_u16(&mut self, n: u16) {
+        self.add(n as u64);
+    }
+
+    fn write_u32(&mut self, n: u32) {
+        self.add(n as u64);
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.add(n);
+    }
+
+    fn write_usize(&mut self, n: usize) {
+        self.add(n as u64);
+    }
+}
+
+pub type BuildIdHasher = BuildHasherDefault<IdHasher>;
+pub type IdMap<K, V> = HashMap<K, V, BuildIdHasher>;
+pub type IdSet<K> = HashSet<K, BuildIdHasher>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::hash::BuildHasher;
+
+    #[test]
+    fn aligned_ids_spread_over_all_bits() {
+        let build = BuildIdHasher::default();
+        let hashes: Vec<u64> = (0..4096u64)
+            .map(|i| build.hash_one(0x7_f000_0000 + 16 * i))
+            .collect();
+        // hashbrown takes buckets from the low bits and tags from the top 7.
+        let low: HashSet<u64> = hashes.iter().map(|h| h & 0xfff).collect();
+        let top: HashSet<u64> = hashes.iter().map(|h| h >> 57).collect();
+        assert!(low.len() > 2000, "{}", low.len());
+        assert_eq!(top.len(), 128);
+    }
+
+    #[test]
+    fn byte_keys_hash_their_length() {
+        let build = BuildIdHasher::default();
+        assert_ne!(build.hash_one("ab"), build.hash_one("ab\0"));
+        assert_ne!(build.hash_one([0u8; 8]), build.hash_one([0u8; 9]));
+        assert_eq!(build.hash_one("abcdefghij"), build.hash_one("abcdefghij"));
+
+        let mut map: IdMap<u64, u32> = IdMap::default();
+        for i in 0..1000 {
+            map.insert(0x1000 + 8 * i, i as u32);
+        }
+        assert_eq!(map.len(), 1000);
+        assert_eq!(map[&(0x1000 + 8 * 999)], 999);
+    }
+}
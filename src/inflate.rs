@@ -0,0 +1,579 @@
+//
+// A DEFLATE decoder (RFC 1951), for the dumps compressed in zip archives.
+// The output is written as it is decoded, keeping only the 32 KiB window
+// that back references can reach, so that members far larger than memory
+// can be inflated to a file. Huffman codes up to FAST_BITS long are decoded
+// with one table lookup, longer ones bit by bit as in zlib's puff.c.
+//
+use std::io::{Read, Write};
+
+const WINDOW: usize = 32 << 10;
+// Decoded bytes are written out in blocks of this size.
+const FLUSH: usize = 1 << 20;
+const FAST_BITS: u32 = 10;
+const MAX_BITS: usize = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// The order in which the code lengths of the code length code are given.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+struct Bits<R> {
+    reader: R,
+    buffer: [u8; 8192],
+    pos: usize,
+    len: usize,
+    bits: u64,
+    count: u32,
+}
+
+impl<R: Read> Bits<R> {
+    fn byte(&mut self) -> Result<u8, String> {
+        if self.pos == self.len {
+            self.len = self
+                .reader
+                .read(&mut self.buffer)
+                .map_err(|e| e.to_string())?;
+            self.pos = 0;
+            if self.len == 0 {
+                return Err("truncated deflate stream".to_string());
+            }
+        }
+        self.pos += 1;
+        Ok(self.buffer[self.pos - 1])
+    }
+
+    fn need(&mut self, n: u32) -> Result<(), String> {
+        while self.count < n {
+            self.bits |= (self.byte()? as u64) << self.count;
+            self.count += 8;
+        }
+        Ok(())
+    }
+
+    fn take(&mut self, n: u32) -> Result<u32, String> {
+        self.need(n)?;
+        let value = (self.bits & ((1 << n) - 1)) as u32;
+        self.bits >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    // Up to `n` bits without consuming them, fewer at the end of the input.
+    fn peek(&mut self, n: u32) -> u32 {
+        while self.count < n {
+            match self.byte() {
+                Ok(b) => {
+                    self.bits |= (b as u64) << self.count;
+                    self.count += 8;
+                }
+                Err(_) => break,
+            }
+        }
+        (self.bits & ((1 << n) - 1)) as u32
+    }
+
+    fn align(&mut self) {
+        let extra = self.count % 8;
+        self.bits >>= extra;
+        self.count -= extra;
+    }
+}
+
+// A canonical Huffman code.
+struct Huffman {
+    // Symbols and code lengths by the first FAST_BITS bits (reversed).
+    fast: Vec<(u16, u8)>,
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Huffman, String> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &l in lengths {
+            counts[l as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for len in 1..=MAX_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0u16; offsets[MAX_BITS + 1] as usize];
+        for (symbol, &l) in lengths.iter().enumerate() {
+            if l != 0 {
+                symbols[offsets[l as usize] as usize] = symbol as u16;
+                offsets[l as usize] += 1;
+            }
+        }
+
+        let mut fast = vec![(0u16, 0u8); 1 << FAST_BITS];
+        let (mut code, mut index) = (0u32, 0usize);
+        for (len, &count) in counts.iter().enumerate().take(FAST_BITS as usize + 1) {
+            for _ in 0..count {
+                let reversed = code.reverse_bits() >> (32 - len);
+                let mut slot = reversed as usize;
+                while slot < fast.len() {
+                    fast[slot] = (symbols[index], len as u8);
+                    slot += 1 << len;
+                }
+                code += 1;
+                index += 1;
+            }
+            code <<= 1;
+        }
+        Ok(Huffman {
+            fast,
+            counts,
+            symbols,
+        })
+    }
+
+    fn decode<R: Read>(&self, bits: &mut Bits<R>) -> Result<u16, String> {
+        let (symbol, len) = self.fast[bits.peek(FAST_BITS) as usize];
+        if len > 0 && len as u32 <= bits.count {
+            bits.take(len as u32)?;
+            return Ok(symbol);
+        }
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..=MAX_BITS {
+            code |= bits.take(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("bad Huffman code".to_string())
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    (
+        Huffman::new(&lengths).unwrap(),
+        Huffman::new(&[5; 30]).unwrap(),
+    )
+}
+
+fn dynamic_codes<R: Read>(bits: &mut Bits<R>) -> Result<(Huffman, Huffman), String> {
+    let literals = bits.take(5)? as usize + 257;
+    let distances = bits.take(5)? as usize + 1;
+    let code_lengths = bits.take(4)? as usize + 4;
+    let mut lengths = [0u8; 19];
+    for &i in &CODE_LENGTH_ORDER[..code_lengths] {
+        lengths[i] = bits.take(3)? as u8;
+    }
+    let code = Huffman::new(&lengths)?;
+    let mut lengths = vec![0u8; literals + distances];
+    let mut i = 0;
+    while i < lengths.len() {
+        let (value, repeat) = match code.decode(bits)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 if i > 0 => (lengths[i - 1], 3 + bits.take(2)? as usize),
+            17 => (0, 3 + bits.take(3)? as usize),
+            18 => (0, 11 + bits.take(7)? as usize),
+            _ => return Err("bad code lengths".to_string()),
+        };
+        if i + repeat > lengths.len() {
+            return Err("too many code lengths".to_string());
+        }
+        lengths[i..i + repeat].fill(value);
+        i += repeat;
+    }
+    Ok((
+        Huffman::new(&lengths[..literals])?,
+        Huffman::new(&lengths[literals..])?,
+    ))
+}
+
+//
+// Inflates a raw DEFLATE stream from `input` into `output`, returning the
+// number of bytes written and the CRC-32 of them.
+//
+pub fn inflate<R: Read, W: Write>(input: R, output: &mut W) -> Result<(u64, u32), String> {
+    let mut bits = Bits {
+        reader: input,
+        buffer: [0; 8192],
+        pos: 0,
+        len: 0,
+        bits: 0,
+        count: 0,
+    };
+    let mut out: Vec<u8> = Vec::with_capacity(FLUSH + WINDOW + 258);
+    let (mut written, mut crc) = (0u64, Crc32::new());
+    let mut flush = |out: &mut Vec<u8>, keep: usize| -> Result<(), String> {
+        let end = out.len() - keep;
+        output.write_all(&out[..end]).map_err(|e| e.to_string())?;
+        crc.update(&out[..end]);
+        written += end as u64;
+        out.drain(..end);
+        Ok(())
+    };
+    loop {
+        let last = bits.take(1)? == 1;
+        match bits.take(2)? {
+            0 => {
+                bits.align();
+                let len = bits.take(16)?;
+                if bits.take(16)? != !len & 0xffff {
+                    return Err("bad stored block length".to_string());
+                }
+                for _ in 0..len {
+                    out.push(bits.take(8)? as u8);
+                }
+            }
+            kind @ (1 | 2) => {
+                let (literal, distance) = if kind == 1 {
+                    fixed_codes()
+                } else {
+                    dynamic_codes(&mut bits)?
+                };
+                loop {
+                    let symbol = literal.decode(&mut bits)? as usize;
+                    if symbol < 256 {
+                        out.push(symbol as u8);
+                    } else if symbol == 256 {
+                        break;
+                    } else {
+                        let i = symbol - 257;
+                        if i >= LENGTH_BASE.len() {
+                            return Err("bad length code".to_string());
+                        }
+                        let len =
+                            LENGTH_BASE[i] as usize + bits.take(LENGTH_EXTRA[i] as u32)? as usize;
+                        let d = distance.decode(&mut bits)? as usize;
+                        if d >= DISTANCE_BASE.len() {
+                            return Err("bad distance code".to_string());
+                        }
+                        let back = DISTANCE_BASE[d] as usize
+                            + bits.take(DISTANCE_EXTRA[d] as u32)? as usize;
+                        if back > out.len() {
+                            return Err("distance beyond the start of the output".to_string());
+                        }
+                        let start = out.len() - back;
+                        for k in 0..len {
+                            out.push(out[start + k]);
+                        }
+                    }
+                    if out.len() >= FLUSH + WINDOW {
+                        flush(&mut out, WINDOW)?;
+                    }
+                }
+            }
+            _ => return Err("bad block type".to_string()),
+        }
+        if out.len() >= FLUSH + WINDOW {
+            flush(&mut out, WINDOW)?;
+        }
+        if last {
+            flush(&mut out, 0)?;
+            return Ok((written, crc.finish()));
+        }
+    }
+}
+
+// The CRC-32 of zip and gzip.
+pub struct Crc32 {
+    table: [u32; 256],
+    crc: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Crc32 {
+        let mut table = [0u32; 256];
+        for (i, entry) in table.iter_mut().enumerate() {
+            let mut c = i as u32;
+            for _ in 0..8 {
+                c = if c & 1 != 0 {
+                    0xedb88320 ^ (c >> 1)
+                } else {
+                    c >> 1
+                };
+            }
+            *entry = c;
+        }
+        Crc32 {
+            table,
+            crc: 0xffffffff,
+        }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.crc = self.table[((self.crc ^ b as u32) & 0xff) as usize] ^ (self.crc >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.crc
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Crc32 {
+        Crc32::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A DEFLATE stream being written, bits from the lowest up.
+    #[derive(Default)]
+    struct BitWriter {
+        out: Vec<u8>,
+        bits: u64,
+        count: u32,
+    }
+
+    impl BitWriter {
+        fn put(&mut self, value: u32, n: u32) {
+            self.bits |= (value as u64) << self.count;
+            self.count += n;
+            while self.count >= 8 {
+                self.out.push(self.bits as u8);
+                self.bits >>= 8;
+                self.count -= 8;
+            }
+        }
+
+        // A Huffman code, which goes most significant bit first.
+        fn code(&mut self, code: u32, len: u32) {
+            for i in (0..len).rev() {
+                self.put((code >> i) & 1, 1);
+            }
+        }
+
+        fn finish(mut self) -> Vec<u8> {
+            if self.count > 0 {
+                self.out.push(self.bits as u8);
+            }
+            self.out
+        }
+
+        fn stored(&mut self, last: bool, data: &[u8]) {
+            self.put(last as u32, 1);
+            self.put(0, 2);
+            if self.count > 0 {
+                self.put(0, 8 - self.count);
+            }
+            self.put(data.len() as u32, 16);
+            self.put(!data.len() as u32 & 0xffff, 16);
+            for &b in data {
+                self.put(b as u32, 8);
+            }
+        }
+
+        // A symbol of the fixed literal/length code.
+        fn fixed(&mut self, symbol: u32) {
+            match symbol {
+                0..=143 => self.code(0x30 + symbol, 8),
+                144..=255 => self.code(0x190 + symbol - 144, 9),
+                256..=279 => self.code(symbol - 256, 7),
+                _ => self.code(0xc0 + symbol - 280, 8),
+            }
+        }
+
+        // A back reference of the fixed codes, for the lengths and
+        // distances without extra bits or with the largest ones.
+        fn fixed_match(&mut self, len: usize, back: usize) {
+            let i = LENGTH_BASE
+                .iter()
+                .rposition(|&b| b as usize <= len)
+                .unwrap();
+            self.fixed(257 + i as u32);
+            self.put(
+                (len - LENGTH_BASE[i] as usize) as u32,
+                LENGTH_EXTRA[i] as u32,
+            );
+            let d = DISTANCE_BASE
+                .iter()
+                .rposition(|&b| b as usize <= back)
+                .unwrap();
+            self.code(d as u32, 5);
+            self.put(
+                (back - DISTANCE_BASE[d] as usize) as u32,
+                DISTANCE_EXTRA[d] as u32,
+            );
+        }
+    }
+
+    fn inflated(stream: &[u8]) -> Result<Vec<u8>, String> {
+        let mut out = Vec::new();
+        let (written, crc) = inflate(stream, &mut out)?;
+        assert_eq!(written, out.len() as u64);
+        let mut expected = Crc32::new();
+        expected.update(&out);
+        assert_eq!(crc, expected.finish());
+        Ok(out)
+    }
+
+    // zlib -9 of 99..91 bottles of beer, a single dynamic Huffman block.
+    const BOTTLES: &[u8] = &[
+        0x85, 0xcb, 0xcb, 0x09, 0x80, 0x30, 0x10, 0x05, 0xc0, 0x56, 0x5e, 0x01, 0x22, 0xe4, 0x9f,
+        0x2d, 0xc7, 0xc0, 0x8a, 0x87, 0x60, 0x40, 0x03, 0xb6, 0x6f, 0x01, 0x59, 0x78, 0xe7, 0x61,
+        0x44, 0xd0, 0xc6, 0x9c, 0x5d, 0x5f, 0x8c, 0x13, 0x4d, 0xf5, 0xc1, 0xb8, 0x31, 0x2f, 0xc5,
+        0x77, 0xf4, 0xbe, 0x41, 0x16, 0xdf, 0x21, 0x95, 0x9c, 0x6a, 0x9c, 0x42, 0x4e, 0x31, 0x4e,
+        0x26, 0x27, 0x1b, 0x27, 0x91, 0x93, 0x8c, 0x13, 0xc9, 0x89, 0xc6, 0x09, 0xe4, 0x04, 0xe3,
+        0x78, 0x72, 0xbc, 0x71, 0x1c, 0x39, 0x6e, 0x3d, 0x3f,
+    ];
+
+    fn bottles() -> String {
+        (91..100)
+            .rev()
+            .map(|i| format!("{} bottles of beer on the wall, {} bottles of beer. ", i, i))
+            .collect()
+    }
+
+    #[test]
+    fn crc32() {
+        let mut crc = Crc32::new();
+        crc.update(b"123456789");
+        assert_eq!(crc.finish(), 0xcbf43926);
+    }
+
+    #[test]
+    fn stored_blocks() {
+        let mut w = BitWriter::default();
+        w.stored(false, b"hello, ");
+        w.stored(false, b"");
+        w.stored(true, b"world");
+        assert_eq!(inflated(&w.finish()).unwrap(), b"hello, world");
+    }
+
+    #[test]
+    fn fixed_huffman_block() {
+        let mut w = BitWriter::default();
+        w.put(1, 1);
+        w.put(1, 2);
+        for &b in b"ab\xff" {
+            w.fixed(b as u32);
+        }
+        // 7 bytes from 3 back, overlapping what it copies.
+        w.fixed_match(7, 3);
+        w.fixed(256);
+        assert_eq!(inflated(&w.finish()).unwrap(), b"ab\xffab\xffab\xffa");
+    }
+
+    #[test]
+    fn dynamic_huffman_block() {
+        assert_eq!((BOTTLES[0] >> 1) & 3, 2);
+        assert_eq!(inflated(BOTTLES).unwrap(), bottles().as_bytes());
+    }
+
+    #[test]
+    fn back_references_across_blocks() {
+        let mut w = BitWriter::default();
+        w.stored(false, b"abcdef");
+        w.put(1, 1);
+        w.put(1, 2);
+        w.fixed_match(6, 6);
+        w.fixed_match(258, 1);
+        w.fixed(256);
+        let mut expected = b"abcdefabcdef".to_vec();
+        expected.extend([b'f'; 258]);
+        assert_eq!(inflated(&w.finish()).unwrap(), expected);
+    }
+
+    #[test]
+    fn back_references_across_flushes() {
+        // More than FLUSH bytes, then a reference to the far end of the window.
+        let mut w = BitWriter::default();
+        let block: Vec<u8> = (0..65535u32).map(|i| (i * 7 % 251) as u8).collect();
+        let blocks = FLUSH / block.len() + 2;
+        for _ in 0..blocks {
+            w.stored(false, &block);
+        }
+        w.put(1, 1);
+        w.put(1, 2);
+        w.fixed_match(258, WINDOW);
+        w.fixed(256);
+        let out = inflated(&w.finish()).unwrap();
+        let mut expected = block.repeat(blocks);
+        let start = expected.len() - WINDOW;
+        expected.extend_from_within(start..start + 258);
+        assert_eq!(out.len(), expected.len());
+        assert!(out == expected);
+    }
+
+    #[test]
+    fn truncated_streams() {
+        for len in 0..BOTTLES.len() {
+            assert!(inflated(&BOTTLES[..len]).is_err(), "{} bytes", len);
+        }
+        let mut w = BitWriter::default();
+        w.stored(true, b"hello");
+        let stored = w.finish();
+        for len in 0..stored.len() {
+            assert!(inflated(&stored[..len]).is_err(), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn corrupt_streams() {
+        // Reserved block type.
+        assert_eq!(inflated(&[0x07]).unwrap_err(), "bad block type");
+        // Stored block lengths that don't match.
+        assert_eq!(
+            inflated(&[0x01, 0x05, 0x00, 0x00, 0x00]).unwrap_err(),
+            "bad stored block length"
+        );
+        // A back reference before the start of the output.
+        let mut w = BitWriter::default();
+        w.put(1, 1);
+        w.put(1, 2);
+        w.fixed(b'a' as u32);
+        w.fixed_match(3, 2);
+        w.fixed(256);
+        assert_eq!(
+            inflated(&w.finish()).unwrap_err(),
+            "distance beyond the start of the output"
+        );
+        // Length and distance symbols that don't exist.
+        for symbol in [286, 287] {
+            let mut w = BitWriter::default();
+            w.put(1, 1);
+            w.put(1, 2);
+            w.fixed(symbol);
+            assert_eq!(inflated(&w.finish()).unwrap_err(), "bad length code");
+        }
+        let mut w = BitWriter::default();
+        w.put(1, 1);
+        w.put(1, 2);
+        w.fixed(b'a' as u32);
+        w.fixed(257);
+        // The fixed distance code leaves 30 and 31 unused.
+        w.code(30, 5);
+        w.put(0, 16);
+        assert_eq!(inflated(&w.finish()).unwrap_err(), "bad Huffman code");
+        // Every single bit flipped decodes to something or fails, without panicking.
+        for i in 0..BOTTLES.len() * 8 {
+            let mut corrupt = BOTTLES.to_vec();
+            corrupt[i / 8] ^= 1 << (i % 8);
+            let _ = inflated(&corrupt);
+        }
+    }
+}
@@ -0,0 +1,747 @@
+//
+// Java Flight Recorder files, for the allocation side of a dump: the
+// recording samples the allocations made while it ran (by class, with an
+// estimate of the bytes each sample stands for) and the objects that stayed
+// alive longest, which say how the heap got to what the dump shows.
+//
+// A recording is a sequence of self-contained chunks:
+//
+//     "FLR\0" major(u16) minor(u16) size offset-of-constant-pools
+//     offset-of-metadata start-nanos duration-nanos start-ticks
+//     ticks-per-second (all i64) features(i32)
+//
+// followed by events, each starting with its size and type. The metadata
+// event (type 0) describes the fields of every type, which is all there is
+// to decode the others; checkpoint events (type 1) hold the constant pools
+// that fields like the class of an event refer to by key. With the
+// compressed integers feature, shorts, chars, ints and longs are LEB128
+// varints of up to 9 bytes (the last one taking 8 bits).
+//
+// https://github.com/openjdk/jdk/blob/master/src/jdk.jfr/share/classes/jdk/jfr/internal/consumer/ChunkParser.java
+//
+use std::collections::HashMap;
+
+const MAGIC: &[u8] = b"FLR\0";
+const HEADER_SIZE: usize = 68;
+const COMPRESSED_INTS: u32 = 1;
+
+const METADATA: u64 = 0;
+const CHECKPOINT: u64 = 1;
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Float(f64),
+    Str(String),
+    // The key of an entry of the constant pool of a type.
+    Ref(u64, u64),
+    // The values of the fields, in the order of the metadata.
+    Object(Vec<Value>),
+    Array(Vec<Value>),
+}
+
+#[derive(Debug)]
+struct Field {
+    name: String,
+    type_id: u64,
+    constant_pool: bool,
+    array: bool,
+}
+
+#[derive(Debug)]
+struct Type {
+    name: String,
+    fields: Vec<Field>,
+}
+
+struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+    compressed: bool,
+}
+
+impl<'a> Cursor<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or("truncated recording")?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn fixed(&mut self, len: usize) -> Result<u64, String> {
+        Ok(self.bytes(len)?.iter().fold(0, |n, &b| (n << 8) | b as u64))
+    }
+
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut n = 0;
+        for i in 0..8 {
+            let b = self.u8()?;
+            n |= ((b & 0x7f) as u64) << (7 * i);
+            if b & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Ok(n | (self.u8()? as u64) << 56)
+    }
+
+    // An integer of `len` bytes, unless compressed.
+    fn integer(&mut self, len: usize) -> Result<u64, String> {
+        if self.compressed {
+            self.varint()
+        } else {
+            self.fixed(len)
+        }
+    }
+
+    fn int(&mut self) -> Result<u64, String> {
+        self.integer(4)
+    }
+
+    fn long(&mut self) -> Result<u64, String> {
+        self.integer(8)
+    }
+
+    // A string, or the key of one in the constant pool of java.lang.String.
+    fn string(&mut self, string_type: u64) -> Result<Value, String> {
+        Ok(match self.u8()? {
+            0 => Value::Null,
+            1 => Value::Str(String::new()),
+            2 => Value::Ref(string_type, self.long()?),
+            3 => {
+                let len = self.int()? as usize;
+                Value::Str(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+            }
+            4 => {
+                let len = self.int()? as usize;
+                let mut chars = Vec::with_capacity(len.min(self.data.len()));
+                for _ in 0..len {
+                    chars.push(self.integer(2)? as u16);
+                }
+                Value::Str(String::from_utf16_lossy(&chars))
+            }
+            5 => {
+                let len = self.int()? as usize;
+                Value::Str(self.bytes(len)?.iter().map(|&b| b as char).collect())
+            }
+            encoding => return Err(format!("unknown string encoding {}", encoding)),
+        })
+    }
+}
+
+// An element of the metadata tree, e.g. a class with its fields.
+struct Element {
+    name: String,
+    attributes: HashMap<String, String>,
+    children: Vec<Element>,
+}
+
+fn parse_element(cursor: &mut Cursor, strings: &[String]) -> Result<Element, String> {
+    let string = |cursor: &mut Cursor| -> Result<String, String> {
+        let index = cursor.int()? as usize;
+        strings
+            .get(index)
+            .cloned()
+            .ok_or_else(|| format!("bad metadata string {}", index))
+    };
+    let name = string(cursor)?;
+    let mut attributes = HashMap::new();
+    for _ in 0..cursor.int()? {
+        let key = string(cursor)?;
+        attributes.insert(key, string(cursor)?);
+    }
+    let mut children = Vec::new();
+    for _ in 0..cursor.int()? {
+        children.push(parse_element(cursor, strings)?);
+    }
+    Ok(Element {
+        name,
+        attributes,
+        children,
+    })
+}
+
+// One chunk of a recording, with its types and constant pools.
+pub struct Chunk {
+    types: HashMap<u64, Type>,
+    type_ids: HashMap<String, u64>,
+    pools: HashMap<(u64, u64), Value>,
+    // The events of the types asked for, by type name.
+    pub events: Vec<(String, Value)>,
+    pub duration_nanos: u64,
+}
+
+impl Chunk {
+    fn type_named(&self, name: &str) -> Option<u64> {
+        self.type_ids.get(name).copied()
+    }
+
+    fn parse_value(&self, cursor: &mut Cursor, type_id: u64) -> Result<Value, String> {
+        let t = self
+            .types
+            .get(&type_id)
+            .ok_or_else(|| format!("unknown type {}", type_id))?;
+        Ok(match t.name.as_str() {
+            "boolean" | "byte" => Value::Integer(cursor.u8()? as i8 as i64),
+            "short" | "char" => Value::Integer(cursor.integer(2)? as i16 as i64),
+            "int" => Value::Integer(cursor.int()? as i32 as i64),
+            "long" => Value::Integer(cursor.long()? as i64),
+            "float" => Value::Float(f32::from_bits(cursor.fixed(4)? as u32) as f64),
+            "double" => Value::Float(f64::from_bits(cursor.fixed(8)?)),
+            "java.lang.String" => cursor.string(type_id)?,
+            _ => {
+                let mut fields = Vec::with_capacity(t.fields.len());
+                for field in &t.fields {
+                    fields.push(if field.array {
+                        let len = cursor.int()? as usize;
+                        let mut elements = Vec::with_capacity(len.min(cursor.data.len()));
+                        for _ in 0..len {
+                            elements.push(self.parse_field(cursor, field)?);
+                        }
+                        Value::Array(elements)
+                    } else {
+                        self.parse_field(cursor, field)?
+                    });
+                }
+                Value::Object(fields)
+            }
+        })
+    }
+
+    fn parse_field(&self, cursor: &mut Cursor, field: &Field) -> Result<Value, String> {
+        if field.constant_pool {
+            Ok(Value::Ref(field.type_id, cursor.long()?))
+        } else {
+            self.parse_value(cursor, field.type_id)
+        }
+    }
+
+    // The value itself of a constant pool entry.
+    fn resolve<'v>(&'v self, value: &'v Value) -> &'v Value {
+        match value {
+            Value::Ref(type_id, key) => self.pools.get(&(*type_id, *key)).unwrap_or(&Value::Null),
+            _ => value,
+        }
+    }
+
+    //
+    // The value at the end of a path of field names from an event of the
+    // given type, e.g. ["objectClass", "name"].
+    //
+    pub fn get<'v>(
+        &'v self,
+        event_type: &str,
+        event: &'v Value,
+        path: &[&str],
+    ) -> Option<&'v Value> {
+        let mut type_id = self.type_named(event_type)?;
+        let mut value = event;
+        for name in path {
+            let (i, field) = self.types[&type_id]
+                .fields
+                .iter()
+                .enumerate()
+                .find(|(_, f)| f.name == *name)?;
+            value = match self.resolve(value) {
+                Value::Object(fields) => self.resolve(fields.get(i)?),
+                _ => return None,
+            };
+            type_id = field.type_id;
+        }
+        Some(value)
+    }
+
+    //
+    // The text of a string, or of what stands for one: symbols are objects
+    // with the string in their only field.
+    //
+    pub fn text(&self, value: &Value) -> Option<String> {
+        match self.resolve(value) {
+            Value::Str(s) => Some(s.clone()),
+            Value::Object(fields) if fields.len() == 1 => self.text(&fields[0]),
+            _ => None,
+        }
+    }
+}
+
+fn parse_metadata(cursor: &mut Cursor, chunk: &mut Chunk) -> Result<(), String> {
+    cursor.int()?;
+    if cursor.long()? != METADATA {
+        return Err("no metadata event at the metadata offset".to_string());
+    }
+    // The start time, duration and id of the metadata.
+    for _ in 0..3 {
+        cursor.long()?;
+    }
+    let mut strings = Vec::new();
+    for _ in 0..cursor.int()? {
+        strings.push(match cursor.string(u64::MAX)? {
+            Value::Str(s) => s,
+            _ => String::new(),
+        });
+    }
+    let root = parse_element(cursor, &strings)?;
+    let classes = root
+        .children
+        .iter()
+        .filter(|e| e.name == "metadata")
+        .flat_map(|e| &e.children)
+        .filter(|e| e.name == "class");
+    for class in classes {
+        let number = |e: &Element, key: &str| -> Result<u64, String> {
+            e.attributes
+                .get(key)
+                .and_then(|v| v.parse().ok())
+                .ok_or_else(|| format!("metadata element without {}", key))
+        };
+        let fields = class
+            .children
+            .iter()
+            .filter(|e| e.name == "field")
+            .map(|e| {
+                Ok(Field {
+                    name: e.attributes.get("name").cloned().unwrap_or_default(),
+                    type_id: number(e, "class")?,
+                    constant_pool: e.attributes.get("constantPool").map(|v| v.as_str())
+                        == Some("true"),
+                    array: e.attributes.get("dimension").map(|v| v.as_str()) == Some("1"),
+                })
+            })
+            .collect::<Result<Vec<Field>, String>>()?;
+        let name = class.attributes.get("name").cloned().unwrap_or_default();
+        let id = number(class, "id")?;
+        chunk.type_ids.insert(name.clone(), id);
+        chunk.types.insert(id, Type { name, fields });
+    }
+    Ok(())
+}
+
+fn parse_checkpoint(cursor: &mut Cursor, chunk: &mut Chunk) -> Result<(), String> {
+    // The start time, duration and offset of the previous checkpoint.
+    for _ in 0..3 {
+        cursor.long()?;
+    }
+    // The kind of checkpoint (or whether it is a flush before JDK 14).
+    cursor.u8()?;
+    for _ in 0..cursor.int()? {
+        let type_id = cursor.long()?;
+        for _ in 0..cursor.int()? {
+            let key = cursor.long()?;
+            let value = chunk.parse_value(cursor, type_id)?;
+            chunk.pools.insert((type_id, key), value);
+        }
+    }
+    Ok(())
+}
+
+//
+// Parses the chunks of a recording, keeping the events whose type is one of
+// `wanted` (e.g. "jdk.ObjectAllocationSample").
+//
+pub fn parse(data: &[u8], wanted: &[&str]) -> Result<Vec<Chunk>, String> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < data.len() {
+        let mut header = Cursor {
+            data: &data[start..],
+            pos: 0,
+            compressed: false,
+        };
+        if header.bytes(4)? != MAGIC {
+            return Err(format!("no chunk at offset {}", start));
+        }
+        header.fixed(4)?;
+        let size = header.fixed(8)? as usize;
+        header.fixed(8)?;
+        let metadata = header.fixed(8)? as usize;
+        header.fixed(8)?;
+        let duration_nanos = header.fixed(8)?;
+        header.bytes(16)?;
+        let features = header.fixed(4)? as u32;
+        if size < HEADER_SIZE || start + size > data.len() {
+            return Err(format!("bad size of the chunk at offset {}", start));
+        }
+
+        let mut chunk = Chunk {
+            types: HashMap::new(),
+            type_ids: HashMap::new(),
+            pools: HashMap::new(),
+            events: Vec::new(),
+            duration_nanos,
+        };
+        let mut cursor = Cursor {
+            data: &data[start..start + size],
+            pos: metadata,
+            compressed: features & COMPRESSED_INTS != 0,
+        };
+        parse_metadata(&mut cursor, &mut chunk)?;
+        let wanted: HashMap<u64, &str> = wanted
+            .iter()
+            .filter_map(|&name| Some((chunk.type_named(name)?, name)))
+            .collect();
+
+        cursor.pos = HEADER_SIZE;
+        while cursor.pos < size {
+            let event = cursor.pos;
+            let event_size = cursor.int()? as usize;
+            if event_size == 0 {
+                return Err(format!("empty event at offset {}", start + event));
+            }
+            match cursor.long()? {
+                METADATA => {}
+                CHECKPOINT => parse_checkpoint(&mut cursor, &mut chunk)?,
+                type_id => {
+                    if let Some(name) = wanted.get(&type_id) {
+                        let value = chunk.parse_value(&mut cursor, type_id)?;
+                        chunk.events.push((name.to_string(), value));
+                    }
+                }
+            }
+            cursor.pos = event + event_size;
+        }
+        chunks.push(chunk);
+        start += size;
+    }
+    Ok(chunks)
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ClassAllocations {
+    pub samples: u64,
+    // The bytes the samples stand for.
+    pub bytes: u64,
+    pub old_objects: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Allocations {
+    pub duration_nanos: u64,
+    // By class name, as in the dump (java.lang.String, byte[]).
+    pub classes: HashMap<String, ClassAllocations>,
+    // The addresses of the old object samples.
+    pub old_objects: Vec<u64>,
+}
+
+// The event types read by allocations().
+pub const ALLOCATION_EVENTS: &[&str] = &[
+    "jdk.ObjectAllocationSample",
+    "jdk.ObjectAllocationInNewTLAB",
+    "jdk.ObjectAllocationOutsideTLAB",
+    "jdk.OldObjectSample",
+];
+
+//
+// The allocations of a recording by class. JDK 16 and later sample them
+// as jdk.ObjectAllocationSample events weighted by the bytes allocated
+// since the previous sample; before, each new TLAB (standing for its size)
+// and each allocation outside of one was an event.
+//
+pub fn allocations(data: &[u8]) -> Result<Allocations, String> {
+    let mut allocations = Allocations::default();
+    for chunk in parse(data, ALLOCATION_EVENTS)? {
+        allocations.duration_nanos += chunk.duration_nanos;
+        for (name, event) in &chunk.events {
+            let (class, bytes): (&[&str], _) = match name.as_str() {
+                "jdk.OldObjectSample" => (&["object", "type", "name"], None),
+                "jdk.ObjectAllocationSample" => (&["objectClass", "name"], Some("weight")),
+                "jdk.ObjectAllocationInNewTLAB" => (&["objectClass", "name"], Some("tlabSize")),
+                _ => (&["objectClass", "name"], Some("allocationSize")),
+            };
+            let class = match chunk.get(name, event, class).and_then(|v| chunk.text(v)) {
+                Some(class) => crate::signature::class_name(&class),
+                None => continue,
+            };
+            let totals = allocations.classes.entry(class).or_default();
+            match bytes {
+                Some(field) => {
+                    totals.samples += 1;
+                    if let Some(Value::Integer(n)) = chunk.get(name, event, &[field]) {
+                        totals.bytes += *n as u64;
+                    }
+                }
+                None => {
+                    totals.old_objects += 1;
+                    if let Some(Value::Integer(address)) =
+                        chunk.get(name, event, &["object", "address"])
+                    {
+                        allocations.old_objects.push(*address as u64);
+                    }
+                }
+            }
+        }
+    }
+    Ok(allocations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LONG: u64 = 20;
+    const STRING: u64 = 21;
+    const CLASS: u64 = 22;
+    const OLD_OBJECT: u64 = 23;
+    const ALLOCATION_SAMPLE: u64 = 100;
+    const OLD_OBJECT_SAMPLE: u64 = 101;
+
+    struct Writer {
+        out: Vec<u8>,
+        compressed: bool,
+    }
+
+    impl Writer {
+        fn integer(&mut self, n: u64, len: usize) {
+            if !self.compressed {
+                self.out.extend_from_slice(&n.to_be_bytes()[8 - len..]);
+                return;
+            }
+            let mut n = n;
+            for _ in 0..8 {
+                if n < 0x80 {
+                    self.out.push(n as u8);
+                    return;
+                }
+                self.out.push(n as u8 | 0x80);
+                n >>= 7;
+            }
+            self.out.push(n as u8);
+        }
+
+        fn int(&mut self, n: u64) {
+            self.integer(n, 4);
+        }
+
+        fn long(&mut self, n: u64) {
+            self.integer(n, 8);
+        }
+
+        fn string(&mut self, s: &str) {
+            self.out.push(3);
+            self.int(s.len() as u64);
+            self.out.extend_from_slice(s.as_bytes());
+        }
+
+        // An event: its size (padded to 4 bytes as the JDK writes it) then `body`.
+        fn event(&mut self, body: impl FnOnce(&mut Writer)) {
+            let mut event = Writer {
+                out: Vec::new(),
+                compressed: self.compressed,
+            };
+            body(&mut event);
+            let size = event.out.len() as u64 + 4;
+            if self.compressed {
+                for i in 0..3 {
+                    self.out.push((size >> (7 * i)) as u8 | 0x80);
+                }
+                self.out.push((size >> 21) as u8);
+            } else {
+                self.out.extend_from_slice(&(size as u32).to_be_bytes());
+            }
+            self.out.extend_from_slice(&event.out);
+        }
+    }
+
+    // A metadata element: name, attributes, children.
+    struct E(&'static str, Vec<(&'static str, String)>, Vec<E>);
+
+    fn class(name: &str, id: u64, fields: Vec<E>) -> E {
+        E(
+            "class",
+            vec![("name", name.to_string()), ("id", id.to_string())],
+            fields,
+        )
+    }
+
+    fn field(name: &str, type_id: u64, constant_pool: bool) -> E {
+        let mut attributes = vec![("name", name.to_string()), ("class", type_id.to_string())];
+        if constant_pool {
+            attributes.push(("constantPool", "true".to_string()));
+        }
+        E("field", attributes, Vec::new())
+    }
+
+    fn element(w: &mut Writer, e: &E, strings: &mut Vec<String>) {
+        let mut index = |s: &str| match strings.iter().position(|t| t == s) {
+            Some(i) => i as u64,
+            None => {
+                strings.push(s.to_string());
+                strings.len() as u64 - 1
+            }
+        };
+        w.int(index(e.0));
+        w.int(e.1.len() as u64);
+        for (key, value) in &e.1 {
+            let (key, value) = (index(key), index(value));
+            w.int(key);
+            w.int(value);
+        }
+        w.int(e.2.len() as u64);
+        for child in &e.2 {
+            element(w, child, strings);
+        }
+    }
+
+    // A chunk with two allocation samples of byte[] and an old String.
+    fn chunk(compressed: bool, duration_nanos: u64) -> Vec<u8> {
+        let mut w = Writer {
+            out: vec![0; HEADER_SIZE],
+            compressed,
+        };
+        w.event(|w| {
+            w.long(CHECKPOINT);
+            for _ in 0..3 {
+                w.long(0);
+            }
+            w.out.push(0);
+            w.int(1);
+            w.long(CLASS);
+            w.int(2);
+            for (key, name) in [(1, "[B"), (2, "java/lang/String")] {
+                w.long(key);
+                w.string(name);
+            }
+        });
+        for weight in [1000, 200] {
+            w.event(|w| {
+                w.long(ALLOCATION_SAMPLE);
+                w.long(1);
+                w.long(weight);
+            });
+        }
+        w.event(|w| {
+            w.long(OLD_OBJECT_SAMPLE);
+            w.long(0xdead0);
+            w.long(2);
+        });
+
+        let metadata = w.out.len();
+        let root = E(
+            "root",
+            Vec::new(),
+            vec![E(
+                "metadata",
+                Vec::new(),
+                vec![
+                    class("long", LONG, Vec::new()),
+                    class("java.lang.String", STRING, Vec::new()),
+                    class("java.lang.Class", CLASS, vec![field("name", STRING, false)]),
+                    class(
+                        "jdk.types.OldObject",
+                        OLD_OBJECT,
+                        vec![field("address", LONG, false), field("type", CLASS, true)],
+                    ),
+                    class(
+                        "jdk.ObjectAllocationSample",
+                        ALLOCATION_SAMPLE,
+                        vec![
+                            field("objectClass", CLASS, true),
+                            field("weight", LONG, false),
+                        ],
+                    ),
+                    class(
+                        "jdk.OldObjectSample",
+                        OLD_OBJECT_SAMPLE,
+                        vec![field("object", OLD_OBJECT, false)],
+                    ),
+                ],
+            )],
+        );
+        let mut strings = Vec::new();
+        let mut tree = Writer {
+            out: Vec::new(),
+            compressed,
+        };
+        element(&mut tree, &root, &mut strings);
+        w.event(|w| {
+            w.long(METADATA);
+            for _ in 0..3 {
+                w.long(0);
+            }
+            w.int(strings.len() as u64);
+            for s in &strings {
+                w.string(s);
+            }
+            w.out.extend_from_slice(&tree.out);
+        });
+
+        let size = w.out.len() as u64;
+        let mut header = Vec::new();
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&[0, 2, 0, 1]);
+        for value in [
+            size,
+            0,
+            metadata as u64,
+            0,
+            duration_nanos,
+            0,
+            1_000_000_000,
+        ] {
+            header.extend_from_slice(&value.to_be_bytes());
+        }
+        let features = if compressed { COMPRESSED_INTS } else { 0 };
+        header.extend_from_slice(&features.to_be_bytes());
+        w.out[..HEADER_SIZE].copy_from_slice(&header);
+        w.out
+    }
+
+    #[test]
+    fn allocations_by_class() {
+        for compressed in [true, false] {
+            let allocations = allocations(&chunk(compressed, 5_000)).unwrap();
+            assert_eq!(allocations.duration_nanos, 5_000);
+            let bytes = allocations.classes["byte[]"];
+            assert_eq!(
+                (bytes.samples, bytes.bytes, bytes.old_objects),
+                (2, 1200, 0)
+            );
+            let strings = allocations.classes["java.lang.String"];
+            assert_eq!(
+                (strings.samples, strings.bytes, strings.old_objects),
+                (0, 0, 1)
+            );
+            assert_eq!(allocations.old_objects, vec![0xdead0]);
+        }
+    }
+
+    #[test]
+    fn chunks_follow_each_other() {
+        let mut data = chunk(true, 5_000);
+        data.extend(chunk(false, 7_000));
+        let allocations = allocations(&data).unwrap();
+        assert_eq!(allocations.duration_nanos, 12_000);
+        assert_eq!(allocations.classes["byte[]"].samples, 4);
+        assert_eq!(allocations.old_objects.len(), 2);
+    }
+
+    #[test]
+    fn events_not_asked_for_are_skipped() {
+        let chunks = parse(&chunk(true, 0), &["jdk.OldObjectSample"]).unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].events.len(), 1);
+        let (name, event) = &chunks[0].events[0];
+        let class = chunks[0].get(name, event, &["object", "type", "name"]);
+        assert_eq!(
+            class.and_then(|v| chunks[0].text(v)).as_deref(),
+            Some("java/lang/String")
+        );
+    }
+
+    #[test]
+    fn bad_recordings() {
+        let data = chunk(true, 0);
+        assert!(allocations(b"not a recording").is_err());
+        for len in [10, HEADER_SIZE, data.len() - 1] {
+            assert!(allocations(&data[..len]).is_err(), "{} bytes", len);
+        }
+        let mut bad = data.clone();
+        bad[HEADER_SIZE..HEADER_SIZE + 4].copy_from_slice(&[0; 4]);
+        assert!(allocations(&bad).is_err());
+    }
+}
@@ -0,0 +1,701 @@
+//
+// JSON output: a minimal JSON value with a pretty-printer (and a parser for
+// the requests of serve), and the JSON rendering of an object graph. Objects are expanded recursively up to a
+// depth, arrays show their first elements, strings and boxed values are
+// rendered as values, what the decoders make of an object is added to its
+// fields (@summary, @fields, @sizes and the @entries, @elements or @text of
+// its contents) and objects already expanded elsewhere (including cycles)
+// are rendered as {"@ref": id}.
+//
+use crate::boxed::unbox;
+use crate::collections::Contents;
+use crate::decoders::{Decoded, Decoders};
+use crate::heap::{self, HeapObject, Value};
+use crate::snapshot::Snapshot;
+use crate::strings::as_string;
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    // A number, already formatted.
+    Number(String),
+    Str(String),
+    Array(Vec<Json>),
+    // Members in order.
+    Object(Vec<(String, Json)>),
+}
+
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(escaped, "\\u{:04x}", c as u32).unwrap(),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+impl Json {
+    pub fn number<T: ToString>(value: T) -> Json {
+        Json::Number(value.to_string())
+    }
+
+    // Floats that JSON can't represent (NaN, infinities) become strings.
+    pub fn float(value: f64) -> Json {
+        if value.is_finite() {
+            Json::Number(value.to_string())
+        } else {
+            Json::Str(value.to_string())
+        }
+    }
+
+    // The member `name` of an object.
+    pub fn get(&self, name: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(n, _)| n == name).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn pretty(&self) -> String {
+        let mut out = String::new();
+        self.write(&mut out, 0, true);
+        out
+    }
+
+    pub fn compact(&self) -> String {
+        let mut out = String::new();
+        self.write(&mut out, 0, false);
+        out
+    }
+
+    fn write(&self, out: &mut String, indent: usize, pretty: bool) {
+        let newline = |out: &mut String, indent: usize| {
+            if pretty {
+                out.push('\n');
+                out.extend(std::iter::repeat_n(' ', 2 * indent));
+            }
+        };
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+            Json::Number(n) => out.push_str(n),
+            Json::Str(s) => out.push_str(&escape(s)),
+            Json::Array(items) if items.is_empty() => out.push_str("[]"),
+            Json::Array(items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    newline(out, indent + 1);
+                    item.write(out, indent + 1, pretty);
+                }
+                newline(out, indent);
+                out.push(']');
+            }
+            Json::Object(members) if members.is_empty() => out.push_str("{}"),
+            Json::Object(members) => {
+                out.push('{');
+                for (i, (name, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    newline(out, indent + 1);
+                    out.push_str(&escape(name));
+                    out.push_str(if pretty { ": " } else { ":" });
+                    value.write(out, indent + 1, pretty);
+                }
+                newline(out, indent);
+                out.push('}');
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.text.len() && self.text[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn error<T>(&self, what: &str) -> Result<T, String> {
+        Err(format!("{} at offset {}", what, self.pos))
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        if self.text[self.pos..].starts_with(word.as_bytes()) {
+            self.pos += word.len();
+            Ok(value)
+        } else {
+            self.error("unexpected character")
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Json, String> {
+        if depth > 256 {
+            return self.error("nested too deeply");
+        }
+        self.skip_whitespace();
+        match self.text.get(self.pos) {
+            None => self.error("unexpected end"),
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'"') => Ok(Json::Str(self.string()?)),
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                self.skip_whitespace();
+                if self.text.get(self.pos) == Some(&b']') {
+                    self.pos += 1;
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value(depth + 1)?);
+                    self.skip_whitespace();
+                    match self.text.get(self.pos) {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => {
+                            self.pos += 1;
+                            return Ok(Json::Array(items));
+                        }
+                        _ => return self.error("expected , or ]"),
+                    }
+                }
+            }
+            Some(b'{') => {
+                self.pos += 1;
+                let mut members = Vec::new();
+                self.skip_whitespace();
+                if self.text.get(self.pos) == Some(&b'}') {
+                    self.pos += 1;
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    self.skip_whitespace();
+                    if self.text.get(self.pos) != Some(&b'"') {
+                        return self.error("expected a member name");
+                    }
+                    let name = self.string()?;
+                    self.skip_whitespace();
+                    if self.text.get(self.pos) != Some(&b':') {
+                        return self.error("expected :");
+                    }
+                    self.pos += 1;
+                    members.push((name, self.value(depth + 1)?));
+                    self.skip_whitespace();
+                    match self.text.get(self.pos) {
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => {
+                            self.pos += 1;
+                            return Ok(Json::Object(members));
+                        }
+                        _ => return self.error("expected , or }"),
+                    }
+                }
+            }
+            Some(b'-' | b'0'..=b'9') => {
+                let start = self.pos;
+                while self.pos < self.text.len()
+                    && matches!(
+                        self.text[self.pos],
+                        b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9'
+                    )
+                {
+                    self.pos += 1;
+                }
+                let number = std::str::from_utf8(&self.text[start..self.pos]).unwrap();
+                match number.parse::<f64>() {
+                    Ok(_) => Ok(Json::Number(number.to_string())),
+                    Err(_) => self.error("bad number"),
+                }
+            }
+            Some(_) => self.error("unexpected character"),
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self.text.get(self.pos..self.pos + 4);
+        match digits
+            .filter(|d| d.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|d| u32::from_str_radix(std::str::from_utf8(d).unwrap(), 16).ok())
+        {
+            Some(code) => {
+                self.pos += 4;
+                Ok(code)
+            }
+            None => self.error("bad \\u escape"),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.pos += 1;
+        let mut bytes = Vec::new();
+        loop {
+            match self.text.get(self.pos) {
+                None => return self.error("unterminated string"),
+                Some(b'"') => {
+                    self.pos += 1;
+                    return String::from_utf8(bytes).or_else(|_| self.error("invalid UTF-8"));
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let escape = match self.text.get(self.pos) {
+                        Some(&c) => c,
+                        None => return self.error("unterminated string"),
+                    };
+                    self.pos += 1;
+                    let c = match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let mut code = self.hex4()?;
+                            // A surrogate pair, else a lone surrogate
+                            // becomes U+FFFD.
+                            if (0xd800..0xdc00).contains(&code)
+                                && self.text[self.pos..].starts_with(b"\\u")
+                            {
+                                let high = self.pos;
+                                self.pos += 2;
+                                match self.hex4()? {
+                                    low @ 0xdc00..=0xdfff => {
+                                        code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00)
+                                    }
+                                    _ => self.pos = high,
+                                }
+                            }
+                            char::from_u32(code).unwrap_or('\u{fffd}')
+                        }
+                        _ => return self.error("bad escape"),
+                    };
+                    let mut buffer = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                }
+                Some(&b) => {
+                    bytes.push(b);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+}
+
+// Parses a JSON text. Numbers are kept as written.
+pub fn parse(text: &str) -> Result<Json, String> {
+    let mut parser = Parser {
+        text: text.as_bytes(),
+        pos: 0,
+    };
+    let value = parser.value(0)?;
+    parser.skip_whitespace();
+    if parser.pos < parser.text.len() {
+        return parser.error("trailing characters");
+    }
+    Ok(value)
+}
+
+pub fn value_json(value: Value) -> Json {
+    match value {
+        Value::Object(0) => Json::Null,
+        Value::Object(id) => Json::Str(format!("{:#x}", id)),
+        Value::Boolean(b) => Json::Bool(b),
+        Value::Char(c) => Json::Str(char::from_u32(c as u32).unwrap_or('\u{fffd}').to_string()),
+        Value::Float(v) => Json::float(v as f64),
+        Value::Double(v) => Json::float(v),
+        Value::Byte(v) => Json::number(v),
+        Value::Short(v) => Json::number(v),
+        Value::Int(v) => Json::number(v),
+        Value::Long(v) => Json::number(v),
+    }
+}
+
+struct Renderer<'a> {
+    snapshot: &'a Snapshot,
+    decoders: &'a Decoders,
+    max_depth: usize,
+    width: usize,
+    expanded: HashSet<u64>,
+}
+
+impl Renderer<'_> {
+    fn header(&self, object: &HeapObject) -> Vec<(String, Json)> {
+        vec![
+            (
+                "@id".to_string(),
+                Json::Str(format!("{:#x}", object.object_id())),
+            ),
+            (
+                "@class".to_string(),
+                Json::Str(self.snapshot.object_class_name(object)),
+            ),
+        ]
+    }
+
+    fn value(&mut self, value: Value, depth: usize) -> Json {
+        match value {
+            Value::Object(id) if id != 0 => match self.snapshot.object(id) {
+                Some(object) => self.object(object, depth),
+                None => Json::Object(vec![(
+                    "@missing".to_string(),
+                    Json::Str(format!("{:#x}", id)),
+                )]),
+            },
+            _ => value_json(value),
+        }
+    }
+
+    fn decoded(&mut self, members: &mut Vec<(String, Json)>, decoded: Decoded, depth: usize) {
+        if let Some(summary) = decoded.summary {
+            members.push(("@summary".to_string(), Json::Str(summary)));
+        }
+        if !decoded.fields.is_empty() {
+            let fields = decoded
+                .fields
+                .into_iter()
+                .map(|(name, value)| (name, self.value(value, depth + 1)))
+                .collect();
+            members.push(("@fields".to_string(), Json::Object(fields)));
+        }
+        if !decoded.sizes.is_empty() {
+            let sizes = decoded
+                .sizes
+                .into_iter()
+                .map(|(name, size)| (name.to_string(), Json::number(size)))
+                .collect();
+            members.push(("@sizes".to_string(), Json::Object(sizes)));
+        }
+        if let Some(contents) = decoded.contents {
+            members.push(self.contents(contents, depth));
+        }
+    }
+
+    fn contents(&mut self, contents: Contents, depth: usize) -> (String, Json) {
+        match contents {
+            Contents::Entries(entries) => {
+                let entries = entries
+                    .into_iter()
+                    .map(|(key, value)| {
+                        Json::Object(vec![
+                            ("key".to_string(), self.value(Value::Object(key), depth + 1)),
+                            (
+                                "value".to_string(),
+                                self.value(Value::Object(value), depth + 1),
+                            ),
+                        ])
+                    })
+                    .collect();
+                ("@entries".to_string(), Json::Array(entries))
+            }
+            Contents::Elements(elements) => {
+                let elements = elements
+                    .into_iter()
+                    .map(|e| self.value(Value::Object(e), depth + 1))
+                    .collect();
+                ("@elements".to_string(), Json::Array(elements))
+            }
+            Contents::Text(text) => ("@text".to_string(), Json::Str(text)),
+        }
+    }
+
+    fn object(&mut self, object: &HeapObject, depth: usize) -> Json {
+        let snapshot = self.snapshot;
+        if let Some(s) = as_string(snapshot, object) {
+            return Json::Str(s);
+        }
+        if let Some(value) = unbox(snapshot, object) {
+            return value_json(value);
+        }
+        let id = object.object_id();
+        if self.expanded.contains(&id) {
+            return Json::Object(vec![("@ref".to_string(), Json::Str(format!("{:#x}", id)))]);
+        }
+        let mut members = self.header(object);
+        if depth >= self.max_depth {
+            members.push(("@truncated".to_string(), Json::Bool(true)));
+            return Json::Object(members);
+        }
+        self.expanded.insert(id);
+        match object {
+            HeapObject::Class(c) => {
+                members.push((
+                    "@name".to_string(),
+                    Json::Str(snapshot.class_name(c.class_id)),
+                ));
+                for f in c.static_fields() {
+                    let value = self.value(f.value, depth + 1);
+                    members.push((snapshot.string(f.name_id).to_string(), value));
+                }
+            }
+            HeapObject::Instance(i) => {
+                let fields = snapshot.instance_fields(i);
+                for f in &fields {
+                    let name = snapshot.string(f.name_id);
+                    // Fields hidden by a subclass field of the same name.
+                    let name = if members.iter().any(|(n, _)| n == name) {
+                        format!("{}.{}", snapshot.class_name(f.class_id), name)
+                    } else {
+                        name.to_string()
+                    };
+                    let value = self.value(f.value, depth + 1);
+                    members.push((name, value));
+                }
+                if let Some(decoded) = self.decoders.decode(snapshot, object, self.width) {
+                    self.decoded(&mut members, decoded, depth);
+                }
+            }
+            HeapObject::ObjectArray(a) => {
+                members.push(("@length".to_string(), Json::number(a.elements.len())));
+                let elements = a
+                    .elements
+                    .iter()
+                    .take(self.width)
+                    .map(|&e| self.value(Value::Object(e), depth + 1))
+                    .collect();
+                members.push(("elements".to_string(), Json::Array(elements)));
+            }
+            HeapObject::PrimitiveArray(a) => {
+                members.push(("@length".to_string(), Json::number(a.length)));
+                let size = a.element_tag.size(snapshot.id_size()) as usize;
+                let elements = a
+                    .data
+                    .chunks(size)
+                    .take(self.width)
+                    .map(|mut chunk| {
+                        value_json(heap::read_value(
+                            &mut chunk,
+                            a.element_tag,
+                            snapshot.id_size(),
+                        ))
+                    })
+                    .collect();
+                members.push(("elements".to_string(), Json::Array(elements)));
+            }
+        }
+        Json::Object(members)
+    }
+}
+
+//
+// An object as JSON, with the objects it references expanded down to
+// `max_depth` levels and at most `width` elements of each array.
+//
+pub fn object_json(
+    snapshot: &Snapshot,
+    decoders: &Decoders,
+    object: &HeapObject,
+    max_depth: usize,
+    width: usize,
+) -> Json {
+    let mut renderer = Renderer {
+        snapshot,
+        decoders,
+        max_depth,
+        width,
+        expanded: HashSet::new(),
+    };
+    renderer.object(object, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::FieldTag;
+    use crate::testing::Dump;
+
+    fn string(text: &str) -> String {
+        match parse(text) {
+            Ok(Json::Str(s)) => s,
+            other => panic!("{}: {:?}", text, other),
+        }
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(
+            parse(r#" {"a": [1, -2.5e3, true, false, null], "b": {}, "c": []} "#),
+            Ok(Json::Object(vec![
+                (
+                    "a".to_string(),
+                    Json::Array(vec![
+                        Json::number(1),
+                        Json::Number("-2.5e3".to_string()),
+                        Json::Bool(true),
+                        Json::Bool(false),
+                        Json::Null,
+                    ])
+                ),
+                ("b".to_string(), Json::Object(Vec::new())),
+                ("c".to_string(), Json::Array(Vec::new())),
+            ]))
+        );
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(
+            string(r#""a\"b\\c\/d\b\f\n\r\t""#),
+            "a\"b\\c/d\u{8}\u{c}\n\r\t"
+        );
+        assert_eq!(string(r#""\u0041\u00e9\u20AC""#), "Aé€");
+        assert_eq!(string(r#""\ud83d\ude00""#), "😀");
+        assert_eq!(string(r#""caf\u00e9 ☕""#), "café ☕");
+        // Lone surrogates.
+        assert_eq!(string(r#""\ud83dx""#), "\u{fffd}x");
+        assert_eq!(string(r#""\ude00""#), "\u{fffd}");
+        assert_eq!(string(r#""\ud83d\u0041""#), "\u{fffd}A");
+    }
+
+    #[test]
+    fn escaped_round_trip() {
+        let s = "quote \" backslash \\ newline \n tab \t bell \u{7} é 😀";
+        assert_eq!(string(&escape(s)), s);
+        let value = Json::Object(vec![
+            ("s".to_string(), Json::Str(s.to_string())),
+            (
+                "n".to_string(),
+                Json::Array(vec![Json::number(-3), Json::Null]),
+            ),
+        ]);
+        assert_eq!(parse(&value.pretty()), Ok(value.clone()));
+        assert_eq!(parse(&value.compact()), Ok(value));
+    }
+
+    #[test]
+    fn nesting() {
+        let nested = |depth| "[".repeat(depth) + &"]".repeat(depth);
+        let mut value = parse(&nested(200)).unwrap();
+        for _ in 1..200 {
+            match value {
+                Json::Array(mut items) if items.len() == 1 => value = items.pop().unwrap(),
+                other => panic!("{:?}", other),
+            }
+        }
+        assert_eq!(value, Json::Array(Vec::new()));
+        assert_eq!(
+            parse(&nested(100_000)).unwrap_err(),
+            "nested too deeply at offset 257"
+        );
+    }
+
+    #[test]
+    fn malformed() {
+        for (text, error) in [
+            ("", "unexpected end at offset 0"),
+            ("[1, 2", "expected , or ] at offset 5"),
+            ("[1 2]", "expected , or ] at offset 3"),
+            ("{\"a\" 1}", "expected : at offset 5"),
+            ("{a: 1}", "expected a member name at offset 1"),
+            ("{\"a\": 1,}", "expected a member name at offset 8"),
+            ("nul", "unexpected character at offset 0"),
+            ("-", "bad number at offset 1"),
+            ("1e", "bad number at offset 2"),
+            ("\"abc", "unterminated string at offset 4"),
+            ("\"\\", "unterminated string at offset 2"),
+            ("\"\\x\"", "bad escape at offset 3"),
+            ("\"\\u12\"", "bad \\u escape at offset 3"),
+            ("\"\\u+041\"", "bad \\u escape at offset 3"),
+            ("1 2", "trailing characters at offset 2"),
+        ] {
+            assert_eq!(parse(text), Err(error.to_string()), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn objects_are_expanded_once_down_to_a_depth() {
+        let mut dump = Dump::new();
+        let integer = dump.class(
+            "java/lang/Integer",
+            dump.object,
+            &[("value", FieldTag::Int)],
+        );
+        let node = dump.class(
+            "test/Node",
+            dump.object,
+            &[
+                ("next", FieldTag::NormalObject),
+                ("name", FieldTag::NormalObject),
+                ("count", FieldTag::NormalObject),
+                ("data", FieldTag::ArrayObject),
+            ],
+        );
+        let data = dump.primitive_array(FieldTag::Int, &[0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3]);
+        let name = dump.string("a \"b\"");
+        let count = dump.instance(integer, &[Value::Int(5)]);
+        let ids = dump.next_ids(2);
+        let second = dump.instance(node, &[Value::Object(ids[1])]);
+        let first = dump.instance(
+            node,
+            &[
+                Value::Object(second),
+                Value::Object(name),
+                Value::Object(count),
+                Value::Object(data),
+            ],
+        );
+        let snapshot = dump.load();
+        let render = |depth| {
+            let object = snapshot.object(first).unwrap();
+            object_json(&snapshot, &Decoders::empty(), object, depth, 2).compact()
+        };
+
+        let header = |id: u64, class: &str| format!(r#""@id":"{:#x}","@class":"{}""#, id, class);
+        assert_eq!(
+            render(3),
+            format!(
+                concat!(
+                    r#"{{{},"next":{{{},"next":{{"@ref":"{:#x}"}},"name":null,"count":null,"#,
+                    r#""data":null}},"name":"a \"b\"","count":5,"#,
+                    r#""data":{{{},"@length":3,"elements":[1,2]}}}}"#
+                ),
+                header(first, "test.Node"),
+                header(second, "test.Node"),
+                first,
+                header(data, "int[]"),
+            )
+        );
+        assert_eq!(
+            render(1),
+            format!(
+                concat!(
+                    r#"{{{},"next":{{{},"@truncated":true}},"name":"a \"b\"","count":5,"#,
+                    r#""data":{{{},"@truncated":true}}}}"#
+                ),
+                header(first, "test.Node"),
+                header(second, "test.Node"),
+                header(data, "int[]"),
+            )
+        );
+    }
+}
@@ -0,0 +1,640 @@
+//
+// A heap that isn't loaded: the file is memory-mapped and the heap dump
+// segments are only indexed, with one small entry per object (where its
+// sub-record is, its class and its size in the dump), and objects are
+// decoded from the mapping when asked for. Commands that only look at a
+// few objects or at per-class totals don't pay for materializing the
+// fields and elements of every object, which Snapshot::load() does.
+//
+use crate::archive;
+use crate::heap::{
+    parse_object, parse_root, sub_record_bytes, DataDumpSubRecordTag, FieldTag, GcRoot, HeapObject,
+};
+use crate::histogram::HistogramEntry;
+use crate::idhash::IdMap;
+use crate::records::{
+    parse_header, parse_load_class_record, parse_record, parse_utf8_string_record, read_id,
+    read_u32, read_u8, Header, RecordTag,
+};
+use crate::signature;
+use crate::symbols::Symbols;
+use crate::timings;
+use crate::window::Window;
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::{Deref, Range};
+
+//
+// The bytes of a file, mapped read-only on 64-bit Linux, whose mmap(2) and
+// flags are declared below, and read into memory elsewhere.
+//
+pub struct Mapping {
+    #[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+    mapped: Option<(*const u8, usize)>,
+    read: Vec<u8>,
+    // The part of the file that is seen.
+    view: Range<usize>,
+}
+
+// The mapping is never written to.
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+mod sys {
+    use std::os::raw::{c_int, c_void};
+
+    pub const PROT_READ: c_int = 1;
+    pub const MAP_PRIVATE: c_int = 2;
+    pub const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+    extern "C" {
+        pub fn mmap(
+            addr: *mut c_void,
+            len: usize,
+            prot: c_int,
+            flags: c_int,
+            fd: c_int,
+            // An off_t, 64 bits on 64-bit Linux.
+            offset: i64,
+        ) -> *mut c_void;
+        pub fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    }
+}
+
+impl Mapping {
+    pub fn open(filename: &str) -> Mapping {
+        Mapping::open_range(filename, None)
+    }
+
+    // A dump by its spec, which may be of a member of an archive.
+    pub fn open_dump(spec: &str) -> Mapping {
+        let location = archive::locate(spec).unwrap_or_else(|e| panic!("{}: {}", spec, e));
+        Mapping::open_range(&location.path, location.range)
+    }
+
+    // The bytes of a range of a file, or all of them.
+    fn open_range(filename: &str, range: Option<Range<u64>>) -> Mapping {
+        let f = File::open(filename).unwrap_or_else(|e| panic!("{}: {}", filename, e));
+        let len = f.metadata().unwrap().len() as usize;
+        let view = range.map_or(0..len, |r| r.start as usize..r.end as usize);
+        assert!(view.end <= len, "{}: the dump goes past the end", filename);
+        #[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+        {
+            use std::os::unix::io::AsRawFd;
+            if len > 0 {
+                // SAFETY: a private read-only mapping of a file we keep open
+                // until it is mapped; it is unmapped when dropped.
+                let ptr = unsafe {
+                    sys::mmap(
+                        std::ptr::null_mut(),
+                        len,
+                        sys::PROT_READ,
+                        sys::MAP_PRIVATE,
+                        f.as_raw_fd(),
+                        0,
+                    )
+                };
+                if ptr != sys::MAP_FAILED {
+                    return Mapping {
+                        mapped: Some((ptr as *const u8, len)),
+                        read: Vec::new(),
+                        view,
+                    };
+                }
+            }
+        }
+        Mapping {
+            #[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+            mapped: None,
+            read: read_range(f, &view).unwrap_or_else(|e| panic!("{}: {}", filename, e)),
+            view: 0..view.len(),
+        }
+    }
+}
+
+fn read_range(mut f: File, range: &Range<usize>) -> std::io::Result<Vec<u8>> {
+    let mut read = vec![0; range.len()];
+    f.seek(SeekFrom::Start(range.start as u64))?;
+    f.read_exact(&mut read)?;
+    Ok(read)
+}
+
+impl Deref for Mapping {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        #[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+        if let Some((ptr, len)) = self.mapped {
+            // SAFETY: the mapping is valid for `len` bytes until dropped.
+            return unsafe { &std::slice::from_raw_parts(ptr, len)[self.view.clone()] };
+        }
+        &self.read[self.view.clone()]
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        #[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+        if let Some((ptr, len)) = self.mapped {
+            // SAFETY: mapped by open() and not used after this.
+            unsafe { sys::munmap(ptr as *mut _, len) };
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EntryKind {
+    Class,
+    Instance,
+    ObjectArray,
+    PrimitiveArray(FieldTag),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ObjectEntry {
+    pub object_id: u64,
+    // Where the sub-record starts in the file, at its tag.
+    pub offset: u64,
+    // The class of instances and object arrays, 0 for the others.
+    pub class_id: u64,
+    pub kind: EntryKind,
+    // The shallow size in the dump (the raw-hprof size model).
+    pub size: u64,
+}
+
+pub struct LazyHeap {
+    pub header: Header,
+    pub strings: Symbols,
+    // Class object id to the id of its name, from the LOAD_CLASS records.
+    pub class_names: IdMap<u64, u64>,
+    // Objects in the order they appear in the file, as in Snapshot::objects.
+    pub entries: Vec<ObjectEntry>,
+    pub object_index: IdMap<u64, u32>,
+    pub roots: Vec<GcRoot>,
+    // Where the object fields of the instances of each class are in their
+    // data, superclass fields included.
+    reference_offsets: IdMap<u64, Vec<u32>>,
+    mapping: Mapping,
+}
+
+// The position of a reader over the end of `data` that started at its
+// beginning.
+fn position(data: &[u8], reader: &[u8]) -> usize {
+    data.len() - reader.len()
+}
+
+fn index_segment(
+    data: &[u8],
+    id_size: u32,
+    entries: &mut Vec<ObjectEntry>,
+    roots: &mut Vec<GcRoot>,
+    base: usize,
+) {
+    let id = id_size as u64;
+    let mut reader = data;
+    while !reader.is_empty() {
+        let offset = (base + position(data, reader)) as u64;
+        let start = reader;
+        let raw_tag = read_u8(&mut reader);
+        let tag = match DataDumpSubRecordTag::try_from(raw_tag) {
+            Ok(tag) => tag,
+            Err(_) => {
+                eprintln!(
+                    "warning: unknown heap dump sub-record {:#x}, skipping {} bytes",
+                    raw_tag,
+                    reader.len()
+                );
+                break;
+            }
+        };
+        // What is read before the contents of objects, all of class dumps
+        // and roots.
+        let header = match tag {
+            DataDumpSubRecordTag::InstanceDump | DataDumpSubRecordTag::ObjectArrayDump => {
+                2 * id + 8
+            }
+            DataDumpSubRecordTag::PrimitiveArrayDump => id + 9,
+            _ => sub_record_bytes(start, id_size).map_or(u64::MAX, |b| b as u64 - 1),
+        };
+        if header > reader.len() as u64 {
+            eprintln!(
+                "warning: {:?} sub-record at {:#x} is cut short, skipping {} bytes",
+                tag,
+                offset,
+                reader.len()
+            );
+            break;
+        }
+        let (object_id, class_id, kind, size) = match tag {
+            DataDumpSubRecordTag::ClassDump => match parse_object(&mut reader, tag, id_size) {
+                Ok(HeapObject::Class(c)) => {
+                    let statics = c
+                        .static_fields()
+                        .iter()
+                        .map(|f| f.tag.size(id_size) as u64)
+                        .sum();
+                    (c.class_id, 0, EntryKind::Class, statics)
+                }
+                _ => unreachable!(),
+            },
+            DataDumpSubRecordTag::InstanceDump => {
+                let object_id = read_id(&mut reader, id_size);
+                let _strace_num = read_u32(&mut reader);
+                let class_id = read_id(&mut reader, id_size);
+                let bytes = read_u32(&mut reader) as usize;
+                reader = match reader.get(bytes..) {
+                    Some(rest) => rest,
+                    None => {
+                        eprintln!(
+                            "warning: instance {:#x} of {} bytes runs past the end of its record, skipping {} bytes",
+                            object_id,
+                            bytes,
+                            reader.len()
+                        );
+                        break;
+                    }
+                };
+                (
+                    object_id,
+                    class_id,
+                    EntryKind::Instance,
+                    2 * id + bytes as u64,
+                )
+            }
+            DataDumpSubRecordTag::ObjectArrayDump => {
+                let object_id = read_id(&mut reader, id_size);
+                let _strace_num = read_u32(&mut reader);
+                let length = read_u32(&mut reader) as u64;
+                let class_id = read_id(&mut reader, id_size);
+                reader = match reader.get((length * id) as usize..) {
+                    Some(rest) => rest,
+                    None => {
+                        eprintln!(
+                            "warning: object array {:#x} of {} elements runs past the end of its record, skipping {} bytes",
+                            object_id,
+                            length,
+                            reader.len()
+                        );
+                        break;
+                    }
+                };
+                let size = 2 * id + 4 + length * id;
+                (object_id, class_id, EntryKind::ObjectArray, size)
+            }
+            DataDumpSubRecordTag::PrimitiveArrayDump => {
+                let object_id = read_id(&mut reader, id_size);
+                let _strace_num = read_u32(&mut reader);
+                let length = read_u32(&mut reader) as u64;
+                let raw = read_u8(&mut reader);
+                let element_tag = FieldTag::try_from(raw)
+                    .unwrap_or_else(|_| panic!("unknown field type: {:#x}", raw));
+                let bytes = length * element_tag.size(id_size) as u64;
+                reader = match reader.get(bytes as usize..) {
+                    Some(rest) => rest,
+                    None => {
+                        eprintln!(
+                            "warning: primitive array {:#x} of {} elements runs past the end of its record, skipping {} bytes",
+                            object_id,
+                            length,
+                            reader.len()
+                        );
+                        break;
+                    }
+                };
+                let kind = EntryKind::PrimitiveArray(element_tag);
+                (object_id, 0, kind, 2 * id + 4 + bytes)
+            }
+            _ => {
+                roots.push(parse_root(&mut reader, tag, id_size));
+                continue;
+            }
+        };
+        entries.push(ObjectEntry {
+            object_id,
+            offset,
+            class_id,
+            kind,
+            size,
+        });
+    }
+}
+
+impl LazyHeap {
+    pub fn open(filename: &str, window: &Window) -> Result<LazyHeap, String> {
+        let mut scan = timings::start("scan");
+        let mapping = Mapping::open_dump(filename);
+        scan.add_bytes(mapping.len() as u64);
+        let data: &[u8] = &mapping;
+        let mut reader = data;
+        let header = parse_header(&mut reader).map_err(|e| format!("{}: {}", filename, e))?;
+        let id_size = header.identifier_size;
+        let mut strings = Symbols::new();
+        let mut class_names = IdMap::default();
+        let mut entries = Vec::new();
+        let mut roots = Vec::new();
+
+        loop {
+            let pos = position(data, reader);
+            let record = match parse_record(&mut reader) {
+                Ok(Some(record)) => record,
+                Ok(None) => break,
+                Err(_) => {
+                    eprintln!("warning: {}: the dump is cut short at {:#x}", filename, pos);
+                    break;
+                }
+            };
+            let bytes = record.bytes as usize;
+            let base = position(data, reader);
+            let (body, rest) = reader.split_at(bytes.min(reader.len()));
+            if window.excludes(&header, &record) {
+                reader = rest;
+                continue;
+            }
+            match record.tag {
+                Some(RecordTag::Utf8String) => {
+                    match parse_utf8_string_record(&mut &body[..], id_size, bytes) {
+                        Ok(r) => strings.insert(r.identifier, &r.value),
+                        Err(e) => eprintln!("warning: {}, skipping it", e),
+                    }
+                }
+                Some(RecordTag::LoadClass) => {
+                    let r = parse_load_class_record(&mut &body[..], id_size);
+                    class_names.insert(r.object_id, r.strname_id);
+                }
+                Some(RecordTag::HeapDump) | Some(RecordTag::HeapDumpSegment) => {
+                    index_segment(body, id_size, &mut entries, &mut roots, base);
+                }
+                _ => {}
+            }
+            reader = rest;
+        }
+
+        let mut object_index = IdMap::default();
+        object_index.reserve(entries.len());
+        for (i, entry) in entries.iter().enumerate() {
+            object_index.insert(entry.object_id, i as u32);
+        }
+        strings.finish();
+        let mut heap = LazyHeap {
+            header,
+            strings,
+            class_names,
+            entries,
+            object_index,
+            roots,
+            reference_offsets: IdMap::default(),
+            mapping,
+        };
+        heap.reference_offsets = heap.reference_offsets();
+        Ok(heap)
+    }
+
+    fn reference_offsets(&self) -> IdMap<u64, Vec<u32>> {
+        let id_size = self.header.identifier_size;
+        let mut classes = IdMap::default();
+        for (i, entry) in self.entries.iter().enumerate() {
+            if entry.kind == EntryKind::Class {
+                if let HeapObject::Class(c) = self.object(i as u32) {
+                    classes.insert(c.class_id, c);
+                }
+            }
+        }
+        classes
+            .keys()
+            .map(|&class_id| {
+                let mut offsets = Vec::new();
+                let mut offset = 0;
+                let mut current = class_id;
+                while let Some(class) = classes.get(&current) {
+                    for field in &class.instance_fields {
+                        if field.tag.is_object() {
+                            offsets.push(offset);
+                        }
+                        offset += field.tag.size(id_size);
+                    }
+                    current = class.super_class_id;
+                }
+                (class_id, offsets)
+            })
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // The object at an index, decoded from the file.
+    pub fn object(&self, index: u32) -> HeapObject {
+        let entry = &self.entries[index as usize];
+        let mut reader = &self.mapping[entry.offset as usize..];
+        let tag = DataDumpSubRecordTag::try_from(read_u8(&mut reader)).unwrap();
+        parse_object(&mut reader, tag, self.header.identifier_size).unwrap()
+    }
+
+    pub fn object_by_id(&self, object_id: u64) -> Option<HeapObject> {
+        Some(self.object(*self.object_index.get(&object_id)?))
+    }
+
+    // Like graph::outgoing_references(), without a Snapshot.
+    pub fn outgoing_references(&self, object: &HeapObject) -> Vec<u64> {
+        let id_size = self.header.identifier_size;
+        let mut refs = Vec::new();
+        match object {
+            HeapObject::Class(c) => {
+                refs.extend([
+                    c.super_class_id,
+                    c.class_loader_id,
+                    c.signers_id,
+                    c.protection_domain_id,
+                ]);
+                refs.extend(c.static_fields().iter().filter_map(|f| f.value.as_object()));
+                refs.extend(c.constant_pool().iter().filter_map(|(_, v)| v.as_object()));
+            }
+            HeapObject::Instance(i) => {
+                refs.push(i.class_id);
+                for &offset in self
+                    .reference_offsets
+                    .get(&i.class_id)
+                    .into_iter()
+                    .flatten()
+                {
+                    if let Some(mut field) = i.data.get(offset as usize..) {
+                        if field.len() >= id_size as usize {
+                            refs.push(read_id(&mut field, id_size));
+                        }
+                    }
+                }
+            }
+            HeapObject::ObjectArray(a) => {
+                refs.push(a.class_id);
+                refs.extend(&a.elements);
+            }
+            HeapObject::PrimitiveArray(_) => {}
+        }
+        refs.retain(|&target| target != 0);
+        refs
+    }
+
+    pub fn class_name(&self, class_id: u64) -> String {
+        match self.class_names.get(&class_id) {
+            Some(&name_id) => match self.strings.get(name_id) {
+                Some(name) => signature::class_name(name),
+                None => format!("<unknown class {:#x}>", class_id),
+            },
+            None => format!("<unknown class {:#x}>", class_id),
+        }
+    }
+
+    // Like Snapshot::object_class_name(), without decoding the object.
+    pub fn entry_class_name(&self, entry: &ObjectEntry) -> String {
+        match entry.kind {
+            EntryKind::Class => "java.lang.Class".to_string(),
+            EntryKind::Instance | EntryKind::ObjectArray => self.class_name(entry.class_id),
+            EntryKind::PrimitiveArray(tag) => format!("{}[]", tag.java_name()),
+        }
+    }
+
+    // The class histogram of the objects (by index) for which `filter`
+    // returns true, by size.
+    pub fn histogram<F: Fn(u32) -> bool>(&self, filter: F) -> Vec<HistogramEntry> {
+        let mut classes: IdMap<(u64, Option<FieldTag>), HistogramEntry> = IdMap::default();
+        for (i, entry) in self.entries.iter().enumerate() {
+            if !filter(i as u32) {
+                continue;
+            }
+            let key = match entry.kind {
+                EntryKind::Class => (0, None),
+                EntryKind::PrimitiveArray(tag) => (0, Some(tag)),
+                _ => (entry.class_id, None),
+            };
+            let histogram_entry = classes.entry(key).or_insert_with(|| HistogramEntry {
+                class_name: self.entry_class_name(entry),
+                instances: 0,
+                shallow: 0,
+            });
+            histogram_entry.instances += 1;
+            histogram_entry.shallow += entry.size;
+        }
+        let mut entries: Vec<HistogramEntry> = classes.into_values().collect();
+        entries.sort_by(|a, b| {
+            b.shallow
+                .cmp(&a.shallow)
+                .then_with(|| a.class_name.cmp(&b.class_name))
+        });
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::outgoing_references;
+    use crate::heap::{GcRootKind, Value};
+    use crate::snapshot::Snapshot;
+    use crate::testing::{Dump, TempFile};
+
+    #[test]
+    fn mappings_have_the_bytes_of_the_file() {
+        let bytes: Vec<u8> = (0..=255).cycle().take(10_000).collect();
+        let file = TempFile::new(&bytes);
+        assert_eq!(&Mapping::open(file.path())[..], &bytes[..]);
+        let range = Mapping::open_range(file.path(), Some(100..4_200));
+        assert_eq!(&range[..], &bytes[100..4_200]);
+        let empty = TempFile::new(&[]);
+        assert!(Mapping::open(empty.path()).is_empty());
+    }
+
+    #[test]
+    fn objects_decode_as_the_snapshot_has_them() {
+        let mut dump = Dump::new();
+        let node = dump.class(
+            "test/Node",
+            dump.object,
+            &[
+                ("next", FieldTag::NormalObject),
+                ("name", FieldTag::NormalObject),
+            ],
+        );
+        let mut next = 0;
+        for i in 0..100 {
+            let name = dump.string(&format!("node {}", i));
+            next = dump.instance(node, &[Value::Object(next), Value::Object(name)]);
+        }
+        let array = dump.object_array(dump.object_array, &[next, 0]);
+        dump.root(GcRootKind::JniGlobal, array);
+        let file = TempFile::new(&dump.bytes());
+        let heap = LazyHeap::open(file.path(), &Window::default()).unwrap();
+        let snapshot = Snapshot::load(file.path());
+        assert_eq!(heap.len(), snapshot.objects.len());
+        assert_eq!(heap.roots.len(), snapshot.roots.len());
+        for (i, (entry, object)) in heap.entries.iter().zip(&snapshot.objects).enumerate() {
+            assert_eq!(entry.object_id, object.object_id());
+            assert_eq!(entry.size, snapshot.shallow_size(object));
+            assert_eq!(
+                heap.entry_class_name(entry),
+                snapshot.object_class_name(object)
+            );
+            let decoded = heap.object(i as u32);
+            assert_eq!(
+                heap.outgoing_references(&decoded),
+                outgoing_references(&snapshot, object)
+            );
+        }
+        let histogram = heap.histogram(|i| i % 2 == 0);
+        let instances: u64 = histogram.iter().map(|e| e.instances).sum();
+        assert_eq!(instances, snapshot.objects.len().div_ceil(2) as u64);
+        assert!(histogram.windows(2).all(|w| w[0].shallow >= w[1].shallow));
+        assert!(heap.object_by_id(1).is_none());
+    }
+
+    #[test]
+    fn segments_stop_at_a_broken_object() {
+        // An INSTANCE_DUMP of id 0x10 and class 0x20 with `bytes` field
+        // bytes, of which there are 8.
+        let instance = |bytes: u32| {
+            let mut record = vec![DataDumpSubRecordTag::InstanceDump as u8];
+            record.extend(0x10u64.to_be_bytes());
+            record.extend(0u32.to_be_bytes());
+            record.extend(0x20u64.to_be_bytes());
+            record.extend(bytes.to_be_bytes());
+            record.extend([1; 8]);
+            record
+        };
+        let index = |data: &[u8]| {
+            let (mut entries, mut roots) = (Vec::new(), Vec::new());
+            index_segment(data, 8, &mut entries, &mut roots, 0);
+            entries.len()
+        };
+        let mut data = instance(8);
+        data.extend(instance(u32::MAX));
+        assert_eq!(index(&data), 1);
+
+        // An OBJECT_ARRAY_DUMP claiming more elements than there are.
+        let mut data = instance(8);
+        data.push(DataDumpSubRecordTag::ObjectArrayDump as u8);
+        data.extend(0x30u64.to_be_bytes());
+        data.extend(0u32.to_be_bytes());
+        data.extend(1_000u32.to_be_bytes());
+        data.extend(0x20u64.to_be_bytes());
+        data.extend(7u64.to_be_bytes());
+        assert_eq!(index(&data), 1);
+
+        // Sub-records cut short in their ids and counts.
+        let whole = instance(8);
+        for cut in 1..whole.len() - 8 {
+            let mut data = whole.clone();
+            data.extend(&whole[..cut]);
+            assert_eq!(index(&data), 1);
+        }
+        let mut data = whole.clone();
+        data.extend([DataDumpSubRecordTag::JniGlobal as u8, 0, 0, 0]);
+        assert_eq!(index(&data), 1);
+    }
+}
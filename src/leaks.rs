@@ -0,0 +1,531 @@
+//
+// Heuristics for the classic leaks of long running (application server
+// style) processes, where a stale class loader or thread local keeps a
+// whole application's worth of objects alive.
+//
+use crate::dominator::DominatorTree;
+use crate::graph::{references, Graph, Via};
+use crate::heap::{GcRootKind, HeapObject, InstanceDump};
+use crate::paths::{self, Hop};
+use crate::reference::{ReferenceKind, RetentionFilter};
+use crate::retained;
+use crate::snapshot::Snapshot;
+use crate::threads::ThreadInfo;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+// References that keep class loaders alive without the application meaning to.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum LeakEdge {
+    // A static field of a class defined by the loader itself.
+    OwnStaticField,
+    ContextClassLoader,
+    ThreadLocalValue,
+}
+
+impl LeakEdge {
+    pub fn name(self) -> &'static str {
+        match self {
+            LeakEdge::OwnStaticField => "static field of one of its classes",
+            LeakEdge::ContextClassLoader => "thread context class loader",
+            LeakEdge::ThreadLocalValue => "thread local value",
+        }
+    }
+}
+
+struct LeakEdges {
+    threads: HashSet<u64>,
+    entries: HashSet<u64>,
+    // Referents of java.lang.ref references never keep a loader alive for
+    // long (thread local map entries are weak references to their keys).
+    references: RetentionFilter,
+}
+
+impl LeakEdges {
+    fn new(snapshot: &Snapshot) -> LeakEdges {
+        LeakEdges {
+            threads: snapshot.subclasses("java.lang.Thread"),
+            entries: snapshot.subclasses("java.lang.ThreadLocal$ThreadLocalMap$Entry"),
+            references: RetentionFilter::new(snapshot, ReferenceKind::all()),
+        }
+    }
+
+    //
+    // Whether a reference is one of the leak edges. Static fields only count
+    // for classes defined by `loader` (or by any non-bootstrap loader if
+    // None).
+    //
+    fn classify(
+        &self,
+        snapshot: &Snapshot,
+        object: &HeapObject,
+        via: Via,
+        loader: Option<u64>,
+    ) -> Option<LeakEdge> {
+        match (object, via) {
+            (HeapObject::Class(c), Via::StaticField(_)) => match loader {
+                Some(loader) if c.class_loader_id == loader => Some(LeakEdge::OwnStaticField),
+                None if c.class_loader_id != 0 => Some(LeakEdge::OwnStaticField),
+                _ => None,
+            },
+            (HeapObject::Instance(i), Via::Field(_, name_id)) => {
+                let name = snapshot.string(name_id);
+                if name == "contextClassLoader" && self.threads.contains(&i.class_id) {
+                    Some(LeakEdge::ContextClassLoader)
+                } else if name == "value" && self.entries.contains(&i.class_id) {
+                    Some(LeakEdge::ThreadLocalValue)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn is_candidate(&self, object: &HeapObject) -> bool {
+        match object {
+            HeapObject::Instance(i) => {
+                self.threads.contains(&i.class_id)
+                    || self.entries.contains(&i.class_id)
+                    || self.references.excluded_kind(object).is_some()
+            }
+            _ => false,
+        }
+    }
+
+    //
+    // The objects reachable from the GC roots without following the leak
+    // edges of threads (static fields are followed).
+    //
+    fn reachable(&self, snapshot: &Snapshot, graph: &Graph) -> Vec<bool> {
+        let mut marked = vec![false; graph.len()];
+        let mut queue = VecDeque::new();
+        for &r in &graph.roots {
+            marked[r as usize] = true;
+            queue.push_back(r);
+        }
+        while let Some(node) = queue.pop_front() {
+            let object = &snapshot.objects[node as usize];
+            let next: Vec<u32> = if self.is_candidate(object) {
+                references(snapshot, object)
+                    .into_iter()
+                    .filter(|r| self.references.retains(snapshot, object, r.via))
+                    .filter(|r| {
+                        matches!(
+                            self.classify(snapshot, object, r.via, None),
+                            None | Some(LeakEdge::OwnStaticField)
+                        )
+                    })
+                    .filter_map(|r| snapshot.index_of(r.target))
+                    .collect()
+            } else {
+                graph.successors.neighbors(node).collect()
+            };
+            for s in next {
+                if !marked[s as usize] {
+                    marked[s as usize] = true;
+                    queue.push_back(s);
+                }
+            }
+        }
+        marked
+    }
+}
+
+#[derive(Debug)]
+pub struct LoaderLeak {
+    pub loader: u32,
+    // Number of classes defined by the loader.
+    pub classes: u64,
+    // The shortest path keeping the loader alive, from the loader to a root.
+    pub path: Vec<Hop>,
+    // The leak edges along that path.
+    pub edges: Vec<LeakEdge>,
+}
+
+//
+// Class loaders that are only (strongly) reachable through static fields of
+// their own classes, thread context class loaders or thread locals.
+//
+// A loader reachable at all is reachable without the static fields of its
+// own classes: the first of its classes on a path to it refers to it
+// directly. So a single search following every static field but none of
+// the other leak edges tells the leaked loaders apart, for all of them at
+// once.
+//
+pub fn classloader_leaks(snapshot: &Snapshot, graph: &Graph) -> Vec<LoaderLeak> {
+    let edges = LeakEdges::new(snapshot);
+    let loader_classes = snapshot.subclasses("java.lang.ClassLoader");
+    let parents = paths::bfs_parents(snapshot, graph, &edges.references);
+    let without_threads = edges.reachable(snapshot, graph);
+    let mut defined: HashMap<u64, u64> = HashMap::new();
+    for object in &snapshot.objects {
+        if let HeapObject::Class(c) = object {
+            *defined.entry(c.class_loader_id).or_default() += 1;
+        }
+    }
+
+    let mut leaks = Vec::new();
+    for (node, object) in snapshot.objects.iter().enumerate() {
+        let loader = match object {
+            HeapObject::Instance(i) if loader_classes.contains(&i.class_id) => i.object_id,
+            _ => continue,
+        };
+        if without_threads[node] || parents[node] == paths::NONE {
+            continue;
+        }
+        let path =
+            paths::path_from_parents(snapshot, &edges.references, &parents, node as u32).unwrap();
+        let mut found = Vec::new();
+        for hop in &path {
+            let from = &snapshot.objects[hop.node as usize];
+            if let Some(edge) = hop
+                .via
+                .and_then(|via| edges.classify(snapshot, from, via, Some(loader)))
+            {
+                if !found.contains(&edge) {
+                    found.push(edge);
+                }
+            }
+        }
+        leaks.push(LoaderLeak {
+            loader: node as u32,
+            classes: defined.get(&loader).copied().unwrap_or(0),
+            path,
+            edges: found,
+        });
+    }
+    leaks
+}
+
+#[derive(Debug)]
+pub struct ThreadLocalEntry {
+    // Index into the threads passed to thread_local_entries().
+    pub thread: usize,
+    pub entry: u32,
+    // The ThreadLocal, None if it was garbage collected (a stale entry
+    // whose value is only freed when the map gets cleaned up).
+    pub key: Option<u32>,
+    pub value: Option<u32>,
+    // Retained size of the value.
+    pub retained: u64,
+    // The loader of the class of the key, if it is neither the bootstrap
+    // loader nor the context class loader of the thread.
+    pub foreign_loader: Option<u64>,
+    // Whether the key or the value was loaded by a loader found by
+    // classloader_leaks().
+    pub leaked_loader: bool,
+}
+
+impl ThreadLocalEntry {
+    pub fn is_suspicious(&self) -> bool {
+        self.key.is_none() && self.value.is_some()
+            || self.foreign_loader.is_some()
+            || self.leaked_loader
+    }
+}
+
+fn class_loader_of(snapshot: &Snapshot, node: u32) -> u64 {
+    snapshot
+        .class_of(&snapshot.objects[node as usize])
+        .and_then(|class_id| snapshot.class_dump(class_id))
+        .map_or(0, |c| c.class_loader_id)
+}
+
+//
+// The entries of the threadLocals and inheritableThreadLocals maps of every
+// thread. `leaked_loaders` are the object ids of class loaders considered
+// leaked, `retained` the retained size of every object.
+//
+pub fn thread_local_entries(
+    snapshot: &Snapshot,
+    threads: &[ThreadInfo],
+    leaked_loaders: &HashSet<u64>,
+    retained: &[u64],
+) -> Vec<ThreadLocalEntry> {
+    let object_field = |instance: &InstanceDump, name: &str| -> Option<u32> {
+        snapshot
+            .field_value(instance, name)?
+            .as_object()
+            .and_then(|id| snapshot.index_of(id))
+    };
+    let mut entries = Vec::new();
+    for (t, thread) in threads.iter().enumerate() {
+        let thread_instance = match &snapshot.objects[thread.node as usize] {
+            HeapObject::Instance(i) => i,
+            _ => continue,
+        };
+        let context_loader = snapshot
+            .field_value(thread_instance, "contextClassLoader")
+            .and_then(|v| v.as_object())
+            .unwrap_or(0);
+        for map_field in &["threadLocals", "inheritableThreadLocals"] {
+            let map = match object_field(thread_instance, map_field)
+                .map(|m| &snapshot.objects[m as usize])
+            {
+                Some(HeapObject::Instance(map)) => map,
+                _ => continue,
+            };
+            let table = match object_field(map, "table").map(|t| &snapshot.objects[t as usize]) {
+                Some(HeapObject::ObjectArray(table)) => table,
+                _ => continue,
+            };
+            for &id in &table.elements {
+                let (node, entry) = match snapshot.index_of(id) {
+                    Some(node) => match &snapshot.objects[node as usize] {
+                        HeapObject::Instance(entry) => (node, entry),
+                        _ => continue,
+                    },
+                    None => continue,
+                };
+                let key = object_field(entry, "referent");
+                let value = object_field(entry, "value");
+                let key_loader = key.map_or(0, |k| class_loader_of(snapshot, k));
+                let value_loader = value.map_or(0, |v| class_loader_of(snapshot, v));
+                entries.push(ThreadLocalEntry {
+                    thread: t,
+                    entry: node,
+                    key,
+                    value,
+                    retained: value.map_or(0, |v| retained[v as usize]),
+                    foreign_loader: Some(key_loader).filter(|&l| l != 0 && l != context_loader),
+                    leaked_loader: leaked_loaders.contains(&key_loader)
+                        || leaked_loaders.contains(&value_loader),
+                });
+            }
+        }
+    }
+    entries
+}
+
+#[derive(Debug)]
+pub struct JniGlobals {
+    pub class_name: String,
+    // JNI_GLOBAL roots referencing instances of the class.
+    pub globals: u64,
+    pub objects: u64,
+    // Retained size of the objects together.
+    pub retained: u64,
+}
+
+//
+// JNI global references grouped by the class of what they reference. Native
+// code has to delete the globals it creates; those it forgets keep their
+// objects alive without any Java-side path explaining why.
+//
+pub fn jni_globals(snapshot: &Snapshot, tree: &DominatorTree, retained: &[u64]) -> Vec<JniGlobals> {
+    let mut by_class: HashMap<String, (u64, Vec<u32>)> = HashMap::new();
+    for root in &snapshot.roots {
+        if root.kind != GcRootKind::JniGlobal {
+            continue;
+        }
+        let node = match snapshot.index_of(root.object_id) {
+            Some(node) => node,
+            None => continue,
+        };
+        let group = by_class
+            .entry(snapshot.object_class_name(&snapshot.objects[node as usize]))
+            .or_default();
+        group.0 += 1;
+        group.1.push(node);
+    }
+    let mut report: Vec<JniGlobals> = by_class
+        .into_iter()
+        .map(|(class_name, (globals, mut nodes))| {
+            nodes.sort_unstable();
+            nodes.dedup();
+            JniGlobals {
+                class_name,
+                globals,
+                objects: nodes.len() as u64,
+                retained: retained::retained_by_set(tree, retained, &nodes),
+            }
+        })
+        .collect();
+    report.sort_by(|a, b| {
+        b.globals
+            .cmp(&a.globals)
+            .then_with(|| b.retained.cmp(&a.retained))
+            .then_with(|| a.class_name.cmp(&b.class_name))
+    });
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dominator;
+    use crate::heap::{FieldTag, Value};
+    use crate::retained::retained_sizes;
+    use crate::testing::Dump;
+    use crate::threads;
+
+    #[test]
+    fn loaders_held_by_threads_leak() {
+        let mut dump = Dump::new();
+        let base = dump.class("java/lang/ClassLoader", dump.object, &[]);
+        let loader_class = dump.class("test/AppLoader", base, &[]);
+        let thread_class = dump.class(
+            "java/lang/Thread",
+            dump.object,
+            &[("contextClassLoader", FieldTag::NormalObject)],
+        );
+        // Held by a thread and by the static field of its own class.
+        let leaked = dump.instance(loader_class, &[]);
+        dump.class_with("test/Leaked", dump.object, &[], &[("SELF", leaked)], leaked);
+        // Held by the static field of a class of another loader, which is
+        // needed by an instance of one of its classes.
+        let live = dump.instance(loader_class, &[]);
+        let held = dump.instance(loader_class, &[]);
+        let app = dump.class_with("test/App", dump.object, &[], &[("PLUGINS", held)], live);
+        let instance = dump.instance(app, &[]);
+        for loader in [leaked, live, held] {
+            let thread = dump.instance(thread_class, &[Value::Object(loader)]);
+            dump.root_of(GcRootKind::ThreadObject, thread, Some(1));
+        }
+        dump.root(GcRootKind::JniGlobal, instance);
+        let snapshot = dump.load();
+        let graph = Graph::build(&snapshot);
+
+        let leaks = classloader_leaks(&snapshot, &graph);
+        assert_eq!(leaks.len(), 1);
+        let leak = &leaks[0];
+        assert_eq!(snapshot.objects[leak.loader as usize].object_id(), leaked);
+        assert_eq!(leak.classes, 1);
+        assert_eq!(leak.edges, vec![LeakEdge::ContextClassLoader]);
+        assert_eq!(leak.path[0].node, leak.loader);
+    }
+
+    #[test]
+    fn stale_and_foreign_thread_locals_are_suspicious() {
+        let mut dump = Dump::new();
+        let object = dump.object;
+        let thread_class = dump.class(
+            "java/lang/Thread",
+            object,
+            &[
+                ("contextClassLoader", FieldTag::NormalObject),
+                ("threadLocals", FieldTag::NormalObject),
+            ],
+        );
+        let map_class = dump.class(
+            "java/lang/ThreadLocal$ThreadLocalMap",
+            object,
+            &[("table", FieldTag::ArrayObject)],
+        );
+        let entry_class = dump.class(
+            "java/lang/ThreadLocal$ThreadLocalMap$Entry",
+            object,
+            &[
+                ("value", FieldTag::NormalObject),
+                ("referent", FieldTag::NormalObject),
+            ],
+        );
+        let local = dump.class("java/lang/ThreadLocal", object, &[]);
+        let app_loader = dump.instance(object, &[]);
+        let plugin_loader = dump.instance(object, &[]);
+        let leaked_loader = dump.instance(object, &[]);
+        let app_local = dump.class_with("test/AppLocal", local, &[], &[], app_loader);
+        let plugin_local = dump.class_with("test/PluginLocal", local, &[], &[], plugin_loader);
+        let leaked_value = dump.class_with("test/Session", object, &[], &[], leaked_loader);
+
+        let entry = |dump: &mut Dump, key: u64, value: u64| {
+            dump.instance(entry_class, &[Value::Object(value), Value::Object(key)])
+        };
+        let stale_value = dump.string("stale");
+        let stale = entry(&mut dump, 0, stale_value);
+        let key = dump.instance(plugin_local, &[]);
+        let value = dump.instance(object, &[]);
+        let foreign = entry(&mut dump, key, value);
+        let key = dump.instance(app_local, &[]);
+        let value = dump.instance(leaked_value, &[]);
+        let leaked = entry(&mut dump, key, value);
+        let key = dump.instance(app_local, &[]);
+        let value = dump.instance(object, &[]);
+        let fine = entry(&mut dump, key, value);
+        let array = dump.object_array;
+        let table = dump.object_array(array, &[stale, 0, foreign, leaked, fine]);
+        let map = dump.instance(map_class, &[Value::Object(table)]);
+        let thread = dump.instance(
+            thread_class,
+            &[Value::Object(app_loader), Value::Object(map)],
+        );
+        dump.root_of(GcRootKind::ThreadObject, thread, Some(1));
+        let snapshot = dump.load();
+        let tree = dominator::build(&Graph::build(&snapshot));
+        let retained = retained_sizes(&snapshot, &tree);
+
+        let threads = threads::threads(&snapshot);
+        let leaked_loaders = HashSet::from([leaked_loader]);
+        let entries = thread_local_entries(&snapshot, &threads, &leaked_loaders, &retained);
+        let id = |node: u32| snapshot.objects[node as usize].object_id();
+        let found: Vec<(u64, Option<u64>, bool, bool)> = entries
+            .iter()
+            .map(|e| {
+                assert_eq!(e.thread, 0);
+                (
+                    id(e.entry),
+                    e.foreign_loader,
+                    e.leaked_loader,
+                    e.is_suspicious(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (stale, None, false, true),
+                (foreign, Some(plugin_loader), false, true),
+                (leaked, None, true, true),
+                (fine, None, false, false),
+            ]
+        );
+        let stale_node = snapshot.index_of(stale_value).unwrap();
+        assert_eq!(entries[0].key, None);
+        assert_eq!(entries[0].retained, retained[stale_node as usize]);
+    }
+
+    #[test]
+    fn jni_globals_are_grouped_by_class() {
+        let mut dump = Dump::new();
+        let callback = dump.class(
+            "test/Callback",
+            dump.object,
+            &[("payload", FieldTag::ArrayObject)],
+        );
+        let mut callbacks = Vec::new();
+        for _ in 0..2 {
+            let payload = dump.primitive_array(FieldTag::Byte, &[0; 100]);
+            callbacks.push(dump.instance(callback, &[Value::Object(payload)]));
+        }
+        dump.root(GcRootKind::JniGlobal, callbacks[0]);
+        dump.root(GcRootKind::JniGlobal, callbacks[0]);
+        dump.root(GcRootKind::JniGlobal, callbacks[1]);
+        let name = dump.string("name");
+        dump.root(GcRootKind::JniGlobal, name);
+        let pinned = dump.instance(callback, &[]);
+        dump.root(GcRootKind::StickyClass, pinned);
+        let snapshot = dump.load();
+        let tree = dominator::build(&Graph::build(&snapshot));
+        let retained = retained_sizes(&snapshot, &tree);
+
+        let globals = jni_globals(&snapshot, &tree, &retained);
+        let found: Vec<(&str, u64, u64, u64)> = globals
+            .iter()
+            .map(|g| (g.class_name.as_str(), g.globals, g.objects, g.retained))
+            .collect();
+        let size = |id| retained[snapshot.index_of(id).unwrap() as usize];
+        assert_eq!(
+            found,
+            vec![
+                (
+                    "test.Callback",
+                    3,
+                    2,
+                    size(callbacks[0]) + size(callbacks[1])
+                ),
+                ("java.lang.String", 1, 1, size(name)),
+            ]
+        );
+    }
+}
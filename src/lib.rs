@@ -0,0 +1,85 @@
+//
+// HPROF Reference Sources:
+//
+// [1] There is actual documentation on the HPROF format in the
+//     docs of OpenJDK version 6 to 7:
+//     http://hg.openjdk.java.net/jdk6/jdk6/jdk/raw-file/tip/src/share/demo/jvmti/hprof/manual.html
+//
+// [2] For OpenJDK 8 there is a header file provider under
+//     src/share/demo/jvmti/hprof/hprof_b_spec.h
+//
+// [3] Since the above can get ouf of date we look for updates
+//     in the format from the actual source code of the latest
+//     OpenJDK (version 9 to 14):
+//     https://github.com/openjdk/jdk/blob/master/src/hotspot/share/services/heapDumper.cpp
+//
+pub mod archive;
+pub mod arrays;
+pub mod boxed;
+pub mod cache;
+pub mod collections;
+pub mod cycles;
+pub mod decoders;
+pub mod diff;
+pub mod doctor;
+pub mod dominator;
+pub mod enums;
+pub mod external;
+pub mod extract;
+pub mod finalizers;
+pub mod follow;
+pub mod graph;
+pub mod graphql;
+pub mod heap;
+pub mod hierarchy;
+pub mod histogram;
+pub mod hpack;
+pub mod http;
+pub mod http2;
+pub mod idhash;
+pub mod inflate;
+pub mod jfr;
+pub mod json;
+pub mod lazy;
+pub mod leaks;
+pub mod locks;
+pub mod offheap;
+pub mod parallel;
+pub mod paths;
+pub mod phd;
+pub mod predicate;
+pub mod protobuf;
+pub mod reachability;
+pub mod readahead;
+pub mod records;
+pub mod reference;
+pub mod regex;
+pub mod repair;
+pub mod retained;
+pub mod rewrite;
+pub mod sampling;
+pub mod secrets;
+pub mod shard;
+pub mod signature;
+pub mod sizes;
+pub mod snapshot;
+pub mod split;
+pub mod strings;
+pub mod suspects;
+pub mod symbols;
+pub mod synthetic;
+// Dumps and files for the tests, of the binary too.
+#[doc(hidden)]
+pub mod testing;
+pub mod threaddump;
+pub mod threads;
+pub mod timings;
+pub mod units;
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64"),
+    feature = "io-uring"
+))]
+pub mod uring;
+pub mod window;
+pub mod writer;
@@ -0,0 +1,225 @@
+//
+// Monitors and locks at the time of the dump. MONITOR_USED and THREAD_BLOCK
+// roots mark the objects used as monitors; java.util.concurrent locks show
+// up instead as the `parkBlocker` of the threads parked on them, and the
+// exclusive ones record their owner, which gives a wait-for graph between
+// threads.
+//
+use crate::heap::{GcRootKind, HeapObject, InstanceDump};
+use crate::snapshot::Snapshot;
+use crate::threads::{self, ThreadInfo};
+
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Debug)]
+pub struct Monitor {
+    pub node: u32,
+    // Whether the object is in a MONITOR_USED root.
+    pub used: bool,
+    // Serial numbers of the threads blocked on it (THREAD_BLOCK roots).
+    pub blocked: Vec<u32>,
+    // Serial numbers of the threads whose frames refer to it.
+    pub referenced_by: Vec<u32>,
+}
+
+pub fn monitors(snapshot: &Snapshot, threads: &[ThreadInfo]) -> Vec<Monitor> {
+    let mut monitors: BTreeMap<u32, Monitor> = BTreeMap::new();
+    for root in &snapshot.roots {
+        if !matches!(root.kind, GcRootKind::MonitorUsed | GcRootKind::ThreadBlock) {
+            continue;
+        }
+        let node = match snapshot.index_of(root.object_id) {
+            Some(node) => node,
+            None => continue,
+        };
+        let monitor = monitors.entry(node).or_insert_with(|| Monitor {
+            node,
+            used: false,
+            blocked: Vec::new(),
+            referenced_by: Vec::new(),
+        });
+        match root.kind {
+            GcRootKind::MonitorUsed => monitor.used = true,
+            _ => monitor.blocked.extend(root.thread_serial_num),
+        }
+    }
+    for thread in threads {
+        for frame in threads::stack_with_locals(snapshot, thread) {
+            for local in frame.locals {
+                if let Some(monitor) = monitors.get_mut(&local) {
+                    monitor.referenced_by.push(thread.serial);
+                }
+            }
+        }
+    }
+    let mut monitors: Vec<Monitor> = monitors.into_values().collect();
+    for monitor in &mut monitors {
+        monitor.blocked.sort_unstable();
+        monitor.blocked.dedup();
+        monitor.referenced_by.sort_unstable();
+        monitor.referenced_by.dedup();
+    }
+    monitors
+}
+
+fn instance(snapshot: &Snapshot, node: u32) -> Option<&InstanceDump> {
+    match &snapshot.objects[node as usize] {
+        HeapObject::Instance(i) => Some(i),
+        _ => None,
+    }
+}
+
+fn object_field(snapshot: &Snapshot, node: u32, name: &str) -> Option<u32> {
+    let id = snapshot
+        .field_value(instance(snapshot, node)?, name)?
+        .as_object()?;
+    snapshot.index_of(id)
+}
+
+#[derive(Debug)]
+pub struct ParkedThread {
+    pub serial: u32,
+    // The object passed to LockSupport.park(): the lock, condition or
+    // queue the thread waits on.
+    pub blocker: u32,
+    // The thread holding the blocker, for exclusive locks
+    // (AbstractOwnableSynchronizer subclasses).
+    pub owner: Option<u32>,
+}
+
+pub fn parked_threads(snapshot: &Snapshot, threads: &[ThreadInfo]) -> Vec<ParkedThread> {
+    let serials: HashMap<u32, u32> = threads.iter().map(|t| (t.node, t.serial)).collect();
+    threads
+        .iter()
+        .filter_map(|thread| {
+            let blocker = object_field(snapshot, thread.node, "parkBlocker")?;
+            let owner = object_field(snapshot, blocker, "exclusiveOwnerThread")
+                .and_then(|owner| serials.get(&owner).copied());
+            Some(ParkedThread {
+                serial: thread.serial,
+                blocker,
+                owner,
+            })
+        })
+        .collect()
+}
+
+//
+// Cycles of threads each waiting for a lock owned by the next one, as
+// lists of serial numbers. A thread waits for at most one lock, so the
+// wait-for graph is followed from each thread until it ends or loops.
+//
+pub fn deadlocks(parked: &[ParkedThread]) -> Vec<Vec<u32>> {
+    let waits_for: HashMap<u32, u32> = parked
+        .iter()
+        .filter_map(|p| Some((p.serial, p.owner?)))
+        .collect();
+    let mut cycles: Vec<Vec<u32>> = Vec::new();
+    for &start in waits_for.keys() {
+        let mut chain = vec![start];
+        let mut current = start;
+        while let Some(&next) = waits_for.get(&current) {
+            if next == start {
+                // Report each cycle once, from its lowest serial number.
+                if chain.iter().all(|&s| s >= start) {
+                    cycles.push(chain);
+                }
+                break;
+            }
+            if chain.contains(&next) {
+                break;
+            }
+            chain.push(next);
+            current = next;
+        }
+    }
+    cycles.sort();
+    cycles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::{FieldTag, Value};
+    use crate::testing::Dump;
+
+    #[test]
+    fn deadlocks_are_reported_once_per_cycle() {
+        let waits = [
+            (1, Some(2)),
+            (2, Some(1)),
+            (3, Some(1)),
+            (4, Some(5)),
+            (5, Some(6)),
+            (6, Some(4)),
+            (7, None),
+        ];
+        let parked: Vec<ParkedThread> = waits
+            .iter()
+            .map(|&(serial, owner)| ParkedThread {
+                serial,
+                blocker: 0,
+                owner,
+            })
+            .collect();
+        assert_eq!(deadlocks(&parked), vec![vec![1, 2], vec![4, 5, 6]]);
+        assert!(deadlocks(&parked[2..3]).is_empty());
+    }
+
+    #[test]
+    fn threads_wait_for_the_owners_of_their_locks() {
+        let mut dump = Dump::new();
+        let thread = dump.class(
+            "java/lang/Thread",
+            dump.object,
+            &[
+                ("name", FieldTag::NormalObject),
+                ("parkBlocker", FieldTag::NormalObject),
+            ],
+        );
+        let lock = dump.class(
+            "test/Lock",
+            dump.object,
+            &[("exclusiveOwnerThread", FieldTag::NormalObject)],
+        );
+        let ids = dump.next_ids(6);
+        let (a, b, condition) = (ids[0], ids[1], ids[2]);
+        let (first, second, third) = (ids[3], ids[4], ids[5]);
+        dump.instance(lock, &[Value::Object(second)]);
+        dump.instance(lock, &[Value::Object(first)]);
+        dump.instance(dump.object, &[]);
+        for (serial, blocker) in [(1, a), (2, b), (3, condition)] {
+            let id = dump.instance(thread, &[Value::Object(0), Value::Object(blocker)]);
+            dump.root_of(GcRootKind::ThreadObject, id, Some(serial));
+        }
+        let monitor = dump.instance(dump.object, &[]);
+        dump.root(GcRootKind::MonitorUsed, monitor);
+        dump.root_of(GcRootKind::ThreadBlock, monitor, Some(3));
+        dump.root_of(GcRootKind::JavaFrame, monitor, Some(1));
+        dump.root_of(GcRootKind::JavaFrame, monitor, Some(1));
+        let snapshot = dump.load();
+        let threads = threads::threads(&snapshot);
+        let node = |id| snapshot.index_of(id).unwrap();
+        assert_eq!(threads[2].node, node(third));
+
+        let parked: Vec<(u32, u32, Option<u32>)> = parked_threads(&snapshot, &threads)
+            .iter()
+            .map(|p| (p.serial, p.blocker, p.owner))
+            .collect();
+        assert_eq!(
+            parked,
+            vec![
+                (1, node(a), Some(2)),
+                (2, node(b), Some(1)),
+                (3, node(condition), None),
+            ]
+        );
+
+        let monitors = monitors(&snapshot, &threads);
+        assert_eq!(monitors.len(), 1);
+        assert_eq!(monitors[0].node, node(monitor));
+        assert!(monitors[0].used);
+        assert_eq!(monitors[0].blocked, vec![3]);
+        assert_eq!(monitors[0].referenced_by, vec![1]);
+    }
+}
@@ -1,408 +1,606 @@
 //
-// HPROF Reference Sources:
+// Command line front-end. The parsing and analyses live in the library
+// (see lib.rs for the HPROF format references).
 //
-// [1] There is actual documentation on the HPROF format in the
-//     docs of OpenJDK version 6 to 7:
-//     http://hg.openjdk.java.net/jdk6/jdk6/jdk/raw-file/tip/src/share/demo/jvmti/hprof/manual.html
-//
-// [2] For OpenJDK 8 there is a header file provider under
-//     src/share/demo/jvmti/hprof/hprof_b_spec.h
-//
-// [3] Since the above can get ouf of date we look for updates
-//     in the format from the actual source code of the latest
-//     OpenJDK (version 9 to 14):
-//     https://github.com/openjdk/jdk/blob/master/src/hotspot/share/services/heapDumper.cpp
-//
-// Assumptions:
-// - For now we assume that all identifier sizes are 8 bytes (u64).
-//
-use num_enum::TryFromPrimitive;
-
-use std::collections::HashMap;
-use std::convert::TryFrom;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::mem;
-
-#[derive(Debug, Eq, PartialEq, TryFromPrimitive)]
-#[repr(u8)]
-enum RecordTag {
-    Utf8String = 0x01,
-    LoadClass = 0x02,
-    UnloadClass = 0x03,
-    StackFrame = 0x04,
-    StackTrace = 0x05,
-    AllocSites = 0x06,
-    HeapSummary = 0x07,
-    StartThread = 0x0A,
-    EndThread = 0x0B,
-    HeapDump = 0x0C,
-    CpuSamples = 0x0D,
-    ControlSettings = 0x0E,
-
-    // 1.0.2 Record Tags
-    HeapDumpSegment = 0x1C,
-    HeapDumpEnd = 0x2C,
-}
-
-// TODO
-//#[derive(Debug)]
-//enum FieldTag {
-//    ArrayObject = 0x01,
-//    NormalObject = 0x02,
-//    Boolean = 0x04,
-//    Char = 0x05,
-//    Float = 0x06,
-//    Double = 0x07,
-//    Byte = 0x08,
-//    Short = 0x09,
-//    Int = 0x0A,
-//    Long = 0x0B,
-//}
-
-// TODO
-//#[derive(Debug)]
-//enum DataDumpSubRecordTag {
-//    RootUnknown = 0xFF,
-//    JniGlobal = 0x01,
-//    JniLocal = 0x02,
-//    JavaFrame = 0x03,
-//    NativeStack = 0x04,
-//    StickyClass = 0x05,
-//    ThreadBlock = 0x06,
-//    MonitorUsed = 0x07,
-//    ThreadObject = 0x08,
-//    ClassDump = 0x20,
-//    InstanceDump = 0x21,
-//    ObjectArrayDump = 0x22,
-//    PrimitiveArrayDump = 0x23,
-//}
-
-#[derive(Debug)]
-struct Header {
-    format: String,
-    identifier_size: u32,
-    high_word_ms: u32,
-    low_word_ms: u32,
+mod cli;
+mod commands;
+
+use cli::Args;
+use commands::{
+    api, classes, daemon, dumps, leaks, objects, retention, strings, threads, traces, waste,
+};
+use hprof_cat::cache::Cache;
+use hprof_cat::follow;
+use hprof_cat::phd;
+use hprof_cat::sizes::SizeModel;
+use hprof_cat::snapshot::Snapshot;
+use hprof_cat::timings;
+use hprof_cat::window::Window;
+
+use std::time::Duration;
+
+fn usage(program: &str) {
+    println!("usage: {} <hprof dump>", program);
+    println!("       {} <command> <hprof dump> [options]", program);
+    println!(
+        "       {} daemon [<hprof dump>...] [--socket PATH | --grpc ADDR]",
+        program
+    );
+    println!(
+        "       {} gen [--classes N] [--objects N] [--seed N] [--shape S] -o FILE",
+        program
+    );
+    println!();
+    println!("All commands take --size-model raw-hprof|compressed-oops|64-bit|32-bit|auto");
+    println!("for the object sizes (raw-hprof, the sizes in the dump, by default), and");
+    println!("--cache to keep dominator trees, retained sizes and reverse references in");
+    println!("<dump>.hprof-cat for the next commands on the same dump (an interrupted");
+    println!("dominator tree resumes from its last checkpoint there). --timings prints");
+    println!("the time, bytes and peak memory of each phase of the analysis to stderr.");
+    println!("--follow reads a dump still being written, waiting at its end for more");
+    println!("until HEAP_DUMP_END, or until the file stops growing for --follow-timeout");
+    println!("seconds (60 by default). Dumps in zip or tar archives are given as");
+    println!("<archive>!<member>, or by the archive alone when it holds one .hprof file.");
+    println!("OpenJ9 portable heap dumps (.phd) are read as well, with what they lack:");
+    println!("primitive values, field names, GC roots, threads and stack traces.");
+    println!("--since and --until leave out the heap dumps, allocation sites and threads");
+    println!("recorded outside of a window of time, for dumps an agent wrote over a");
+    println!("session: +<duration> from the start of the dump (+90s, +1h30m, +250ms), or");
+    println!("epoch milliseconds or a UTC time (2024-05-01T12:30:00).");
+    println!("--units human|bytes|kib|mib gives the byte quantities of the reports in");
+    println!("bytes (by default), KiB, MiB or the largest unit that fits, with thousands");
+    println!("separators; json and the APIs always have bytes.");
+    println!("--names short leaves the packages out of the class names of retained,");
+    println!("unreachable, quick-histogram, merge-shards and histogram-diff (String[],");
+    println!("HashMap$Node); qualified, the default, tells apart those of two packages.");
+    println!();
+    println!("The daemon keeps dumps loaded and answers the queries of serve --api as");
+    println!("JSON-RPC 2.0, one request per line, on stdin or the unix socket --socket:");
+    println!("load {{dump, name, since, until}}, unload, snapshots, summary, histogram,");
+    println!("object, paths, query, graphql and shutdown, each on the snapshot given as");
+    println!("`snapshot`.");
+    println!("--grpc serves proto/heap_analysis.proto over plaintext HTTP/2 instead.");
+    println!();
+    println!("gen writes a synthetic dump, the same for the same options: --classes");
+    println!("generated classes (100), --objects objects (100k; 10M, 1G, ... also work),");
+    println!("of the --shape mixed, strings (mostly duplicated strings), deep (chains of");
+    println!("--depth nodes, 10000) or arrays (of up to a million elements), with");
+    println!("--id-size 4 or 8 byte identifiers (8).");
+    println!();
+    println!("commands:");
+    println!("    traces [--group]            print all the stack traces, or each distinct one");
+    println!("                                once with how many traces have it");
+    println!("    alloc-traces [--top N] [--frames N]");
+    println!("                                live objects by allocation stack trace");
+    println!("    alloc-threads [--top N] [--classes N]");
+    println!("                                live objects by allocating thread");
+    println!("    allocsites [--sort live|allocated|ratio] [--class S] [--frame S]");
+    println!("               [--top N] [--frames N]");
+    println!("                                allocation sites of HPROF agent dumps");
+    println!("    threads [--thread-dump FILE] [--thread <regex>|<serial>] [--no-fold]");
+    println!("                                threads with their stacks and locals, those of the");
+    println!("                                same stack folded into one");
+    println!("    thread-retained [--thread <regex>|<serial>]");
+    println!("                                memory held by each thread");
+    println!("    monitors [--thread-dump FILE]");
+    println!("                                monitors, parked threads, lock owners and deadlocks");
+    println!("    thread-states [--top N] [--thread-dump FILE]");
+    println!("                                threads by state and daemon flag, deepest stacks");
+    println!("        (all three take the states and locks of a jstack output or javacore");
+    println!("        taken next to the heap dump from --thread-dump)");
+    println!("    retained [--top N] [--supertype <type>,...]");
+    println!("                                retained size per class (or supertype)");
+    println!("    retained <object id>...     retained size of specific objects");
+    println!("    retained-estimate [--samples N] [--budget N] [--seed N] [--top N]");
+    println!("                                sampled retained size per class, for huge dumps");
+    println!("    packages [--depth N] [--top N]");
+    println!("                                retained size per package");
+    println!("    top-objects [--top N]       objects with the largest retained sizes");
+    println!("    root-retained               retained size per kind of GC root");
+    println!("    dominators <object id>...   dominator chain up to the GC roots");
+    println!("    dominator-tree [<object id>] [--depth N] [--width N]");
+    println!("                                browse the dominator tree");
+    println!("    dominator-diff <earlier dump> [--depth N] [--top N]");
+    println!("                                dominator subtrees that grew since");
+    println!("    statics [--top N]           retained size per static field");
+    println!("    retainers <class> [--top N] dominators of the instances of a class");
+    println!("    holders <class> [--top N]   root paths of the instances of a class, grouped");
+    println!("    unreachable [--top N] [--supertype <type>,...]");
+    println!("                                garbage objects per class (or supertype)");
+    println!("        (all twelve also take --exclude weak,soft,phantom,final|all)");
+    println!("    cycles [--top N] [--max-length N]");
+    println!("                                reference cycles by classes and fields");
+    println!("    components [--top N]        largest strongly connected components");
+    println!("    references [--top N]        java.lang.ref references and their referents");
+    println!("    path <object id>... [--exclude weak,soft,phantom,final|all]");
+    println!("                                shortest path to a GC root");
+    println!("    object <object id>... [--limit N]");
+    println!("                                fields and elements of objects");
+    println!("    incoming <object id>...     objects referencing the given ones");
+    println!("    json <id> [--depth N] [--width N]");
+    println!("                                an object and what it references as JSON");
+    println!("    deep-size <object id>... [--depth N] [--stop <class>,...]");
+    println!("                                bytes reachable from objects");
+    println!("    duplicate-strings [--top N] strings with identical contents");
+    println!("    interning [--min-count N] [--top N]");
+    println!("                                duplicated strings worth interning");
+    println!("    find --class C [--where EXPR] [--limit N]");
+    println!("                                instances whose fields match, e.g. \"id == 42 && name =~ 'x'\"");
+    println!("    search <regex> [--limit N]  regex search in strings, char[] and byte[]");
+    println!("    secrets [--reveal] [--exclude ...]");
+    println!("                                credentials found in strings and arrays");
+    println!("    high-entropy [--top N] [--exclude ...]");
+    println!("                                arrays that look like keys or tokens");
+    println!("    boxed                       boxed primitives and their overhead");
+    println!("    fill-ratio [--top N]        size vs. capacity of collections");
+    println!("    hash-buckets [--min-size N] [--top N]");
+    println!("                                hash map bucket spread and collisions");
+    println!("    empty-collections [--top N] empty collections by owner class");
+    println!("    large-arrays [--top N]      largest arrays by bytes and by length");
+    println!("    sparse-arrays [--min-length N] [--min-nulls PERCENT] [--top N]");
+    println!("                                mostly null object arrays");
+    println!("    zero-tails [--min-bytes N] [--top N]");
+    println!("                                arrays ending with runs of zeros");
+    println!("    leak-suspects [--threshold PERCENT]");
+    println!("                                objects and classes retaining most of the heap,");
+    println!("                                classes piling up in one place");
+    println!("    classloader-leaks           loaders only kept alive by typical leaks");
+    println!("    thread-locals [--min-bytes N] [--thread <regex>|<serial>]");
+    println!("                                thread local entries per thread");
+    println!("    direct-buffers [--top N]    native memory of direct and mapped buffers");
+    println!("    jni-globals [--top N] [--min-count N]");
+    println!("                                JNI global references by referenced class");
+    println!("    finalizers [--top N]        objects waiting for finalizers and cleaners");
+    println!("    hierarchy [<class>] [--depth N] [--all]");
+    println!("                                subclass tree with instance counts");
+    println!("    quick-histogram [--top N] [--live [--temp-dir DIR]]");
+    println!("                                instances and bytes per class, without loading");
+    println!("    shard --shards N --index K [--out FILE]");
+    println!("                                histogram and graph statistics of one of N runs");
+    println!("                                of heap dump segments, for merge-shards");
+    println!("    merge-shards <shard>... [--top N]");
+    println!("                                the whole heap from the files of all its shards");
+    println!("    rollup [--base <class>,...] [--depth N] [--top N]");
+    println!("                                histogram folded into superclasses");
+    println!("    histogram-diff <earlier dump or jmap -histo output> [--top N] [--live]");
+    println!("                                classes that grew since, by shallow size");
+    println!("    jfr-allocations <recording.jfr> [--sort live|allocated] [--top N]");
+    println!("                                live classes with their allocations in a recording");
+    println!("    layout <class>...           instance field offsets and sizes");
+    println!("    enums [--top N]             enum constants vs. instances");
+    println!("    merged-paths --class <name>|<object id>... [--exclude ...]");
+    println!("                 [--depth N] [--width N]");
+    println!("                                merged shortest paths to GC roots");
+    println!("    extract --class <regex> -o FILE");
+    println!("                                a dump of the matching objects, stubs of what they");
+    println!("                                reference and the classes both need; objects");
+    println!("                                that no kept GC root reaches get a ROOT_UNKNOWN");
+    println!(
+        "    split -o PREFIX             one dump per heap dump segment, PREFIX-<n>.hprof, each"
+    );
+    println!(
+        "                                with a copy of the strings, classes and stack traces"
+    );
+    println!("    join <piece>... -o FILE     the dump that split pieces came from");
+    println!(
+        "    repair -o FILE              a copy of a dump cut short that other tools open: the"
+    );
+    println!("                                partial last record trimmed, segment lengths fixed");
+    println!("                                and HEAP_DUMP_END appended");
+    println!("    doctor                      health report: unknown tags, length mismatches,");
+    println!("                                dangling ids, unresolved serials, where the dump");
+    println!("                                is cut short");
+    println!("    rewrite [--id-size 4|8] [--compact-symbols] -o FILE");
+    println!(
+        "                                a copy of the dump, with 4-byte identifiers renumbered"
+    );
+    println!(
+        "                                to fit (written in the order of HotSpot, from any dump)"
+    );
+    println!("                                and duplicate or unreferenced symbols dropped");
+    println!("    fingerprint                 a hash of the header, record counts, classes and GC");
+    println!("                                roots, the same for every copy of the dump");
+    println!("    serve --api [--listen ADDR] JSON over HTTP: /summary, /histogram, /object/<id>,");
+    println!("                                /paths/<id>, /query?class=C&where=EXPR, and GraphQL");
+    println!("                                at /graphql (schema at /graphql/schema)");
 }
 
-fn parse_header<R: BufRead>(reader: &mut R) -> Header {
-    let mut format_buf = [0u8; 19];
-    let mut u32_buf = [0u8; 4];
+//
+// The commands taking a dump, checked before it is loaded: the command
+// reading files or archives of its own first, then those of the snapshot.
+//
+const COMMANDS: &[&str] = &[
+    "merge-shards",
+    "join",
+    "quick-histogram",
+    "shard",
+    "split",
+    "doctor",
+    "repair",
+    "traces",
+    "alloc-threads",
+    "alloc-traces",
+    "allocsites",
+    "threads",
+    "thread-retained",
+    "monitors",
+    "thread-states",
+    "retained",
+    "retained-estimate",
+    "packages",
+    "top-objects",
+    "root-retained",
+    "dominator-tree",
+    "jfr-allocations",
+    "histogram-diff",
+    "dominator-diff",
+    "holders",
+    "statics",
+    "retainers",
+    "dominators",
+    "unreachable",
+    "path",
+    "object",
+    "json",
+    "deep-size",
+    "incoming",
+    "duplicate-strings",
+    "interning",
+    "serve",
+    "find",
+    "search",
+    "secrets",
+    "high-entropy",
+    "boxed",
+    "fill-ratio",
+    "hash-buckets",
+    "empty-collections",
+    "large-arrays",
+    "sparse-arrays",
+    "zero-tails",
+    "leak-suspects",
+    "classloader-leaks",
+    "thread-locals",
+    "cycles",
+    "components",
+    "references",
+    "jni-globals",
+    "direct-buffers",
+    "finalizers",
+    "hierarchy",
+    "rollup",
+    "enums",
+    "layout",
+    "extract",
+    "fingerprint",
+    "rewrite",
+    "merged-paths",
+];
 
-    reader.read_exact(&mut format_buf).unwrap();
-    let format = String::from_utf8_lossy(&format_buf).to_string();
-    reader.read_exact(&mut u32_buf).unwrap();
-    let identifier_size = u32::from_be_bytes(u32_buf);
-    reader.read_exact(&mut u32_buf).unwrap();
-    let high_word_ms = u32::from_be_bytes(u32_buf);
-    reader.read_exact(&mut u32_buf).unwrap();
-    let low_word_ms = u32::from_be_bytes(u32_buf);
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
 
-    Header {
-        format,
-        identifier_size,
-        high_word_ms,
-        low_word_ms,
+    // The daemon loads its dumps (if any) itself.
+    if args.get(1).map(String::as_str) == Some("daemon") {
+        daemon::run(&Args::parse(&args[2..], &["--socket", "--grpc"]));
+        return;
     }
-}
-
-#[derive(Debug)]
-struct Record {
-    tag: RecordTag,
-    time: u32,
-    bytes: u32,
-}
-
-fn parse_record<R: BufRead>(
-    reader: &mut R,
-    string_table: &mut HashMap<u64, String>,
-    frame_table: &mut HashMap<u64, StackFrameRecord>,
-    class_table: &mut HashMap<u32, LoadClassRecord>,
-) -> Record {
-    let mut tag_buf = [0u8; 1];
-    let mut u32_buf = [0u8; 4];
-
-    reader.read_exact(&mut tag_buf).unwrap();
-    let tag = RecordTag::try_from(tag_buf[0]).unwrap();
-    reader.read_exact(&mut u32_buf).unwrap();
-    let time = u32::from_be_bytes(u32_buf);
-    reader.read_exact(&mut u32_buf).unwrap();
-    let bytes = u32::from_be_bytes(u32_buf);
-
-    match tag {
-        RecordTag::Utf8String => {
-            let r: Utf8StringRecord = parse_utf8_string_record(reader, bytes as usize);
-            string_table.insert(r.identifier, r.value); // XXX
-        }
-        RecordTag::LoadClass => {
-            let r: LoadClassRecord = parse_load_class_record(reader);
-            class_table.insert(r.serial_num, r);
-        }
-        RecordTag::UnloadClass => {
-            // TODO:
-            // These currently seem to be non-existent. Once you finish
-            // reading the rest of the dump data, if you still don't see
-            // such entries then check the C++ Dumper code to see if they
-            // are mentioned at all. You probably still want to leave the
-            // parsing code here for completeness but should be ok to
-            // leave things simplified.
-            let _r: UnloadClassRecord = parse_unload_class_record(reader);
+    if args.get(1).map(String::as_str) == Some("gen") {
+        dumps::generate(&Args::parse(
+            &args[2..],
+            &[
+                "--classes",
+                "--objects",
+                "--seed",
+                "--shape",
+                "--id-size",
+                "--depth",
+                "-o",
+                "--out",
+            ],
+        ));
+        return;
+    }
+    match args.len() {
+        1 => {
+            usage(&args[0]);
         }
-        RecordTag::StackFrame => {
-            let r: StackFrameRecord = parse_stack_frame_record(reader);
-            frame_table.insert(r.frame_id, r); // XXX
+        2 => {
+            println!("Analyzing {} ...", args[1]);
+            let prepared = cli::prepare(&args[1]);
+            let snapshot = Snapshot::load_metadata(&prepared.spec, &Window::default())
+                .unwrap_or_else(|e| cli::die(&e));
+            traces::print_stack_traces(&snapshot, &Args::parse(&[], &[]));
         }
-        RecordTag::StackTrace => {
-            let r: StackTraceRecord = parse_stack_trace_record(reader);
-            println!("Thread {}:", r.thread_serial_num);
-            for frame_id in r.frame_ids {
-                let frame = frame_table.get(&frame_id).unwrap();
+        _ => {
+            let command = args[1].as_str();
+            if !COMMANDS.contains(&command) {
+                usage(&args[0]);
+                return;
+            }
+            let mut rest = args[3..].to_vec();
+            let size_model = cli::take_option(&mut rest, "--size-model");
+            let cache = cli::take_flag(&mut rest, "--cache");
+            if cli::take_flag(&mut rest, "--timings") {
+                timings::enable();
+            }
+            let quiet = cli::take_option(&mut rest, "--follow-timeout");
+            if cli::take_flag(&mut rest, "--follow") {
+                follow::enable(quiet.map_or(follow::DEFAULT_QUIET, |s| {
+                    Duration::from_secs(
+                        s.parse()
+                            .unwrap_or_else(|_| cli::die(&format!("bad --follow-timeout: {}", s))),
+                    )
+                }));
+            }
+            let common = Args::parse(&rest, &[]);
+            // Checked here for the commands without byte quantities.
+            common.units();
+            let window = common.window();
+            if window.enabled()
+                && matches!(
+                    command,
+                    "merge-shards" | "join" | "split" | "repair" | "doctor"
+                )
+            {
+                cli::die(&format!(
+                    "{} reads whole files, without --since and --until",
+                    command
+                ));
+            }
+            let rest = &rest[..];
+            if command == "merge-shards" {
+                let args = Args::parse(&args[2..], &["--top"]);
+                classes::print_merged_shards(&args.positional, &args);
+                return;
+            }
+            if command == "join" {
+                dumps::join(&Args::parse(&args[2..], &["-o", "--out"]));
+                return;
+            }
+            let prepared = cli::prepare(&args[2]);
+            let dump = prepared.spec.as_str();
+            // Commands reading the file without loading the heap.
+            if matches!(
+                command,
+                "quick-histogram" | "shard" | "split" | "repair" | "doctor"
+            ) && phd::is_phd(dump)
+            {
+                cli::die(&format!("{} needs an HPROF dump", command));
+            }
+            if window.enabled() && phd::is_phd(dump) {
+                cli::die("portable heap dumps have no record times for --since and --until");
+            }
 
-                let class = class_table.get(&frame.class_serial_num).unwrap();
-                //
-                // For whatever reason class names read from the HPROF use slashes (/)
-                // instead of dots (.) for their classpath [e.g. java/lang/Thread.run()
-                // instead of java.lang.Thread.run()].
-                //
-                let class_name = string_table
-                    .get(&class.strname_id)
-                    .unwrap()
-                    .replace("/", ".");
-                let method_name = string_table.get(&frame.method_name_id).unwrap();
-                if frame.source_name_id != 0 {
-                    println!(
-                        "\t{}.{}() [{}:{}]",
-                        class_name,
-                        method_name,
-                        string_table.get(&frame.source_name_id).unwrap(),
-                        frame.line_num
-                    );
-                } else if frame.line_num == -1 {
-                    println!("\t{}.{}() [Unknown]", class_name, method_name);
-                } else if frame.line_num == -2 {
-                    // XXX: Haven't seen that yet, potentially unimplemented
-                    println!("\t{}.{}() [Compiled]", class_name, method_name);
-                    println!("{:?}", frame);
-                } else if frame.line_num == -3 {
-                    // XXX: Haven't seen that yet, potentially unimplemented
-                    println!("\t{}.{}() [Native]", class_name, method_name);
-                    println!("{:?}", frame);
-                } else {
-                    // XXX: skip here maybe with a debug msg
-                    println!("{:?}", frame);
+            if command == "quick-histogram" {
+                if follow::enabled() {
+                    follow::wait_for_end(dump).unwrap_or_else(|e| cli::die(&e));
                 }
+                classes::print_quick_histogram(dump, &Args::parse(rest, &["--top", "--temp-dir"]));
+                if timings::enabled() {
+                    timings::report();
+                }
+                return;
             }
-            println!();
-        }
-        _ => {
-            println!("tag: {:?} of size {:?} bytes", tag, bytes);
-        }
-    }
-
-    // XXX: For Testing
-    Record { tag, time, bytes }
-}
-
-#[derive(Debug)]
-struct Utf8StringRecord {
-    // XXX: Assumption
-    identifier: u64,
-    value: String,
-}
-
-fn parse_utf8_string_record<R: BufRead>(reader: &mut R, bytes: usize) -> Utf8StringRecord {
-    let mut u64_buf = [0u8; 8];
-    reader.read_exact(&mut u64_buf).unwrap();
-    let identifier = u64::from_be_bytes(u64_buf);
-
-    let mut value_buf = vec![0; bytes - mem::size_of::<u64>()];
-    reader.read_exact(&mut value_buf).unwrap();
-    let value = String::from_utf8_lossy(&value_buf).to_string();
-
-    Utf8StringRecord { identifier, value }
-}
-
-#[derive(Debug)]
-struct LoadClassRecord {
-    serial_num: u32,
-    // XXX: Assumption?
-    object_id: u64,
-    strace_num: u32,
-    // XXX: Assumption?
-    strname_id: u64,
-}
-
-fn parse_load_class_record<R: BufRead>(reader: &mut R) -> LoadClassRecord {
-    let mut u32_buf = [0u8; 4];
-    let mut u64_buf = [0u8; 8];
-
-    reader.read_exact(&mut u32_buf).unwrap();
-    let serial_num = u32::from_be_bytes(u32_buf);
-    reader.read_exact(&mut u64_buf).unwrap();
-    let object_id = u64::from_be_bytes(u64_buf);
-    reader.read_exact(&mut u32_buf).unwrap();
-    let strace_num = u32::from_be_bytes(u32_buf);
-    reader.read_exact(&mut u64_buf).unwrap();
-    let strname_id = u64::from_be_bytes(u64_buf);
-
-    LoadClassRecord {
-        serial_num,
-        object_id,
-        strace_num,
-        strname_id,
-    }
-}
-
-#[derive(Debug)]
-struct UnloadClassRecord {
-    serial_num: u32,
-}
-
-fn parse_unload_class_record<R: BufRead>(reader: &mut R) -> UnloadClassRecord {
-    let mut u32_buf = [0u8; 4];
-    reader.read_exact(&mut u32_buf).unwrap();
-    let serial_num = u32::from_be_bytes(u32_buf);
-    UnloadClassRecord { serial_num }
-}
-
-#[derive(Debug)]
-struct StackFrameRecord {
-    frame_id: u64,       // XXX: Assumption
-    method_name_id: u64, // XXX: Assumption
-    method_sign_id: u64, // XXX: Assumption
-    source_name_id: u64, // XXX: Assumption
-    class_serial_num: u32,
-    line_num: i32,
-}
-
-fn parse_stack_frame_record<R: BufRead>(reader: &mut R) -> StackFrameRecord {
-    let mut u32_buf = [0u8; 4];
-    let mut u64_buf = [0u8; 8];
-
-    reader.read_exact(&mut u64_buf).unwrap();
-    let frame_id = u64::from_be_bytes(u64_buf);
-    reader.read_exact(&mut u64_buf).unwrap();
-    let method_name_id = u64::from_be_bytes(u64_buf);
-    reader.read_exact(&mut u64_buf).unwrap();
-    let method_sign_id = u64::from_be_bytes(u64_buf);
-    reader.read_exact(&mut u64_buf).unwrap();
-    let source_name_id = u64::from_be_bytes(u64_buf);
-
-    reader.read_exact(&mut u32_buf).unwrap();
-    let class_serial_num = u32::from_be_bytes(u32_buf);
-    reader.read_exact(&mut u32_buf).unwrap();
-    let line_num = i32::from_be_bytes(u32_buf);
-
-    StackFrameRecord {
-        frame_id,
-        method_name_id,
-        method_sign_id,
-        source_name_id,
-        class_serial_num,
-        line_num,
-    }
-}
-
-#[derive(Debug)]
-struct StackTraceRecord {
-    serial_num: u32,
-    thread_serial_num: u32,
-    nframes: u32,
-    frame_ids: Vec<u64>, // XXX: Assumption
-}
-
-fn parse_stack_trace_record<R: BufRead>(reader: &mut R) -> StackTraceRecord {
-    let mut u32_buf = [0u8; 4];
-
-    reader.read_exact(&mut u32_buf).unwrap();
-    let serial_num = u32::from_be_bytes(u32_buf);
-    reader.read_exact(&mut u32_buf).unwrap();
-    let thread_serial_num = u32::from_be_bytes(u32_buf);
-    reader.read_exact(&mut u32_buf).unwrap();
-    let nframes = u32::from_be_bytes(u32_buf);
-
-    let mut frame_ids = vec![0u64; nframes as usize];
-    for n in 0..nframes {
-        let mut u64_buf = [0u8; 8];
-        reader.read_exact(&mut u64_buf).unwrap();
-        frame_ids[n as usize] = u64::from_be_bytes(u64_buf);
-    }
-
-    StackTraceRecord {
-        serial_num,
-        thread_serial_num,
-        nframes,
-        frame_ids,
-    }
-}
-
-fn parse_hprof_file(filename: &String) {
-    let f = File::open(&filename).expect("XXX: file not found?");
-    let mut reader = BufReader::new(f);
-    let _header: Header = parse_header(&mut reader);
-
-    // XXX: Debug
-    let mut i: u64 = 0;
-    let mut j: u64 = 0;
-    let mut k: u64 = 0;
-    let mut l: u64 = 0;
-    let mut m: u64 = 0;
-
-    // XXX: Put on their own struct
-    let mut string_table = HashMap::new();
-    let mut frame_table = HashMap::new();
-    let mut class_table = HashMap::new();
-
-    loop {
-        let record: Record = parse_record(
-            &mut reader,
-            &mut string_table,
-            &mut frame_table,
-            &mut class_table,
-        );
-        match record.tag {
-            RecordTag::Utf8String => {
-                i += 1;
+            if command == "shard" {
+                classes::print_shard(dump, &Args::parse(rest, &["--shards", "--index", "--out"]));
+                return;
             }
-            RecordTag::LoadClass => {
-                j += 1;
+            if command == "split" {
+                dumps::split(dump, &Args::parse(rest, &["-o", "--out"]));
+                return;
             }
-            RecordTag::UnloadClass => {
-                k += 1;
+            if command == "doctor" {
+                dumps::print_doctor(dump, &Args::parse(rest, &[]));
+                return;
             }
-            RecordTag::StackFrame => {
-                l += 1;
+            if command == "repair" {
+                dumps::repair(dump, &Args::parse(rest, &["-o", "--out"]));
+                return;
             }
-            RecordTag::StackTrace => {
-                m += 1;
+            // Commands only looking at the top-level records, not the heap.
+            let mut snapshot = match command {
+                "traces" | "allocsites" => Snapshot::load_metadata(dump, &window),
+                _ => Snapshot::load_window(dump, &window),
             }
-            _ => {
-                break;
+            .unwrap_or_else(|e| cli::die(&e));
+            if let Some(name) = size_model {
+                let model = match name.as_str() {
+                    "auto" => snapshot.detect_size_model(),
+                    _ => SizeModel::parse(&name)
+                        .unwrap_or_else(|| cli::die(&format!("unknown size model: {}", name))),
+                };
+                snapshot.set_size_model(model);
+            }
+            if cache {
+                snapshot.cache = Some(Cache::open(&prepared.name, &snapshot));
+            }
+            match command {
+                "traces" => {
+                    if Args::parse(rest, &[]).flag("--group") {
+                        traces::print_grouped_traces(&snapshot);
+                    } else {
+                        traces::print_stack_traces(&snapshot, &Args::parse(rest, &[]));
+                    }
+                }
+                "alloc-threads" => traces::print_allocating_threads(
+                    &snapshot,
+                    &Args::parse(rest, &["--top", "--classes"]),
+                ),
+                "alloc-traces" => traces::print_allocation_traces(
+                    &snapshot,
+                    &Args::parse(rest, &["--top", "--frames"]),
+                ),
+                "allocsites" => traces::print_alloc_sites(
+                    &snapshot,
+                    &Args::parse(rest, &["--sort", "--class", "--frame", "--top", "--frames"]),
+                ),
+                "threads" => threads::print_threads(
+                    &snapshot,
+                    &Args::parse(rest, &["--thread-dump", "--thread"]),
+                ),
+                "thread-retained" => {
+                    threads::print_thread_retained(&snapshot, &Args::parse(rest, &["--thread"]))
+                }
+                "monitors" => {
+                    threads::print_monitors(&snapshot, &Args::parse(rest, &["--thread-dump"]))
+                }
+                "thread-states" => threads::print_thread_states(
+                    &snapshot,
+                    &Args::parse(rest, &["--top", "--thread-dump"]),
+                ),
+                "retained" => retention::print_retained(
+                    &snapshot,
+                    &Args::parse(rest, &["--top", "--exclude", "--supertype"]),
+                ),
+                "retained-estimate" => retention::print_retained_estimate(
+                    &snapshot,
+                    &Args::parse(
+                        rest,
+                        &["--samples", "--budget", "--seed", "--top", "--exclude"],
+                    ),
+                ),
+                "packages" => retention::print_packages(
+                    &snapshot,
+                    &Args::parse(rest, &["--depth", "--top", "--exclude"]),
+                ),
+                "top-objects" => retention::print_top_objects(
+                    &snapshot,
+                    &Args::parse(rest, &["--top", "--exclude"]),
+                ),
+                "root-retained" => {
+                    retention::print_root_retained(&snapshot, &Args::parse(rest, &["--exclude"]))
+                }
+                "dominator-tree" => retention::print_dominator_tree(
+                    &snapshot,
+                    &Args::parse(rest, &["--depth", "--width", "--exclude"]),
+                ),
+                "jfr-allocations" => classes::print_jfr_allocations(
+                    &snapshot,
+                    &Args::parse(rest, &["--top", "--sort"]),
+                ),
+                "histogram-diff" => {
+                    classes::print_histogram_diff(&snapshot, &Args::parse(rest, &["--top"]))
+                }
+                "dominator-diff" => retention::print_dominator_diff(
+                    &snapshot,
+                    &Args::parse(rest, &["--depth", "--top", "--exclude"]),
+                ),
+                "holders" => {
+                    retention::print_holders(&snapshot, &Args::parse(rest, &["--top", "--exclude"]))
+                }
+                "statics" => {
+                    retention::print_statics(&snapshot, &Args::parse(rest, &["--top", "--exclude"]))
+                }
+                "retainers" => retention::print_retainers(
+                    &snapshot,
+                    &Args::parse(rest, &["--top", "--exclude"]),
+                ),
+                "dominators" => {
+                    retention::print_dominators(&snapshot, &Args::parse(rest, &["--exclude"]))
+                }
+                "unreachable" => retention::print_unreachable(
+                    &snapshot,
+                    &Args::parse(rest, &["--top", "--exclude", "--supertype"]),
+                ),
+                "path" => retention::print_path(&snapshot, &Args::parse(rest, &["--exclude"])),
+                "object" => objects::print_object(&snapshot, &Args::parse(rest, &["--limit"])),
+                "json" => {
+                    objects::print_json(&snapshot, &Args::parse(rest, &["--depth", "--width"]))
+                }
+                "deep-size" => {
+                    objects::print_deep_size(&snapshot, &Args::parse(rest, &["--depth", "--stop"]))
+                }
+                "incoming" => retention::print_incoming(&snapshot, &Args::parse(rest, &[])),
+                "duplicate-strings" => {
+                    strings::print_duplicate_strings(&snapshot, &Args::parse(rest, &["--top"]))
+                }
+                "interning" => strings::print_interning(
+                    &snapshot,
+                    &Args::parse(rest, &["--top", "--min-count"]),
+                ),
+                "serve" => api::serve(snapshot, &Args::parse(rest, &["--listen"])),
+                "find" => objects::print_find(
+                    &snapshot,
+                    &Args::parse(rest, &["--class", "--where", "--limit"]),
+                ),
+                "search" => strings::print_search(&snapshot, &Args::parse(rest, &["--limit"])),
+                "secrets" => strings::print_secrets(&snapshot, &Args::parse(rest, &["--exclude"])),
+                "high-entropy" => strings::print_high_entropy(
+                    &snapshot,
+                    &Args::parse(rest, &["--top", "--exclude"]),
+                ),
+                "boxed" => waste::print_boxed(&snapshot, &Args::parse(rest, &[])),
+                "fill-ratio" => waste::print_fill_ratio(&snapshot, &Args::parse(rest, &["--top"])),
+                "hash-buckets" => waste::print_hash_buckets(
+                    &snapshot,
+                    &Args::parse(rest, &["--min-size", "--top"]),
+                ),
+                "empty-collections" => {
+                    waste::print_empty_collections(&snapshot, &Args::parse(rest, &["--top"]))
+                }
+                "large-arrays" => {
+                    waste::print_large_arrays(&snapshot, &Args::parse(rest, &["--top"]))
+                }
+                "sparse-arrays" => waste::print_sparse_arrays(
+                    &snapshot,
+                    &Args::parse(rest, &["--top", "--min-length", "--min-nulls"]),
+                ),
+                "zero-tails" => waste::print_zero_tails(
+                    &snapshot,
+                    &Args::parse(rest, &["--min-bytes", "--top"]),
+                ),
+                "leak-suspects" => {
+                    leaks::print_leak_suspects(&snapshot, &Args::parse(rest, &["--threshold"]))
+                }
+                "classloader-leaks" => leaks::print_classloader_leaks(&snapshot),
+                "thread-locals" => leaks::print_thread_local_leaks(
+                    &snapshot,
+                    &Args::parse(rest, &["--min-bytes", "--thread"]),
+                ),
+                "cycles" => retention::print_cycles(
+                    &snapshot,
+                    &Args::parse(rest, &["--top", "--max-length"]),
+                ),
+                "components" => {
+                    retention::print_components(&snapshot, &Args::parse(rest, &["--top"]))
+                }
+                "references" => {
+                    retention::print_references(&snapshot, &Args::parse(rest, &["--top"]))
+                }
+                "jni-globals" => leaks::print_jni_globals(
+                    &snapshot,
+                    &Args::parse(rest, &["--top", "--min-count"]),
+                ),
+                "direct-buffers" => {
+                    leaks::print_direct_buffers(&snapshot, &Args::parse(rest, &["--top"]))
+                }
+                "finalizers" => leaks::print_finalizers(&snapshot, &Args::parse(rest, &["--top"])),
+                "hierarchy" => {
+                    classes::print_hierarchy(&snapshot, &Args::parse(rest, &["--depth"]))
+                }
+                "rollup" => classes::print_rollup(
+                    &snapshot,
+                    &Args::parse(rest, &["--base", "--depth", "--top"]),
+                ),
+                "enums" => classes::print_enums(&snapshot, &Args::parse(rest, &["--top"])),
+                "layout" => classes::print_layout(&snapshot, &Args::parse(rest, &[])),
+                "extract" => {
+                    dumps::extract(&snapshot, &Args::parse(rest, &["--class", "-o", "--out"]))
+                }
+                "fingerprint" => dumps::print_fingerprint(&snapshot, &args[2]),
+                "rewrite" => {
+                    dumps::rewrite(&snapshot, &Args::parse(rest, &["--id-size", "-o", "--out"]))
+                }
+                "merged-paths" => retention::print_merged_paths(
+                    &snapshot,
+                    &Args::parse(rest, &["--class", "--exclude", "--depth", "--width"]),
+                ),
+                _ => unreachable!("{} is in COMMANDS", command),
+            }
+            if timings::enabled() {
+                timings::report();
             }
-        }
-    }
-
-    // XXX: Debug
-    println!(
-        "entries: {} string {} load {} unload {} frame {} trace",
-        i, j, k, l, m
-    );
-}
-
-fn main() {
-    let args: Vec<String> = std::env::args().collect();
-
-    match args.len() {
-        1 => {
-            println!("usage: {} <hprof dump>", args[0]);
-        }
-        2 => {
-            println!("Analyzing {} ...", args[1]);
-            parse_hprof_file(&args[1]);
-        }
-        _ => {
-            println!("usage: {} <hprof dump>", args[0]);
         }
     }
 }
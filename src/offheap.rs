@@ -0,0 +1,124 @@
+//
+// Native memory referenced from the heap by direct and mapped NIO buffers.
+// The memory itself isn't in the dump, but the buffer objects record its
+// size, and it is only freed once they are collected.
+//
+use crate::heap::{HeapObject, InstanceDump, Value};
+use crate::snapshot::Snapshot;
+
+#[derive(Debug)]
+pub struct DirectBuffer {
+    pub node: u32,
+    pub capacity: u64,
+    // Whether the memory is a mapped file rather than allocated memory.
+    pub mapped: bool,
+    // Slices and duplicates share the memory of the buffer they were
+    // created from (their `att`) and don't own any.
+    pub view: bool,
+}
+
+fn int_field(snapshot: &Snapshot, instance: &InstanceDump, name: &str) -> Option<i64> {
+    match snapshot.field_value(instance, name)? {
+        Value::Int(v) => Some(v as i64),
+        Value::Long(v) => Some(v),
+        _ => None,
+    }
+}
+
+fn is_null(snapshot: &Snapshot, instance: &InstanceDump, name: &str) -> bool {
+    snapshot
+        .field_value(instance, name)
+        .and_then(|v| v.as_object())
+        .is_none_or(|id| id == 0)
+}
+
+// Every direct buffer of the heap (DirectByteBuffer extends MappedByteBuffer).
+pub fn direct_buffers(snapshot: &Snapshot) -> Vec<DirectBuffer> {
+    let classes = snapshot.subclasses("java.nio.MappedByteBuffer");
+    let mut buffers = Vec::new();
+    for (node, object) in snapshot.objects.iter().enumerate() {
+        let instance = match object {
+            HeapObject::Instance(i) if classes.contains(&i.class_id) => i,
+            _ => continue,
+        };
+        // Heap buffers wrapped as direct ones don't have an address.
+        if int_field(snapshot, instance, "address") == Some(0) {
+            continue;
+        }
+        buffers.push(DirectBuffer {
+            node: node as u32,
+            capacity: int_field(snapshot, instance, "capacity")
+                .unwrap_or(0)
+                .max(0) as u64,
+            mapped: !is_null(snapshot, instance, "fd"),
+            view: !is_null(snapshot, instance, "att"),
+        });
+    }
+    buffers.sort_by(|a, b| {
+        b.capacity
+            .cmp(&a.capacity)
+            .then_with(|| a.node.cmp(&b.node))
+    });
+    buffers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::FieldTag;
+    use crate::testing::Dump;
+
+    #[test]
+    fn buffers_are_told_apart_by_their_fields() {
+        let mut dump = Dump::new();
+        let base = dump.class(
+            "java/nio/Buffer",
+            dump.object,
+            &[("address", FieldTag::Long), ("capacity", FieldTag::Int)],
+        );
+        let mapped = dump.class(
+            "java/nio/MappedByteBuffer",
+            base,
+            &[("fd", FieldTag::NormalObject)],
+        );
+        let direct = dump.class(
+            "java/nio/DirectByteBuffer",
+            mapped,
+            &[("att", FieldTag::NormalObject)],
+        );
+        let fd = dump.instance(dump.object, &[]);
+        let mut buffer = |att, fd, address, capacity| {
+            dump.instance(
+                direct,
+                &[
+                    Value::Object(att),
+                    Value::Object(fd),
+                    Value::Long(address),
+                    Value::Int(capacity),
+                ],
+            )
+        };
+        let plain = buffer(0, 0, 0x1000, 4096);
+        let slice = buffer(plain, 0, 0x1400, 1024);
+        let file = buffer(0, fd, 0x8000, 8192);
+        // A heap buffer passed off as a direct one.
+        buffer(0, 0, 0, 99);
+        // Not a direct buffer at all.
+        dump.instance(base, &[Value::Long(0x9000), Value::Int(512)]);
+        let snapshot = dump.load();
+
+        let found: Vec<(u32, u64, bool, bool)> = direct_buffers(&snapshot)
+            .iter()
+            .map(|b| (b.node, b.capacity, b.mapped, b.view))
+            .collect();
+        let node = |id| snapshot.index_of(id).unwrap();
+        assert_eq!(
+            found,
+            vec![
+                (node(file), 8192, true, false),
+                (node(plain), 4096, false, false),
+                (node(slice), 1024, false, true),
+            ]
+        );
+    }
+}
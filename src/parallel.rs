@@ -0,0 +1,86 @@
+//
+// Work split over the cores with scoped threads: a range of dense indices
+// is cut into one contiguous chunk per thread and the results come back in
+// chunk order, so that callers get the same output as a sequential loop.
+// Ranges too small to be worth a thread are handled on the calling one.
+//
+use std::ops::Range;
+use std::thread;
+
+// Items below which a chunk isn't worth its own thread.
+const MIN_CHUNK: usize = 4096;
+
+pub fn threads() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+// 0..len cut into at most `threads` contiguous chunks.
+pub fn chunks(len: usize, threads: usize) -> Vec<Range<usize>> {
+    let count = threads.min(len.div_ceil(MIN_CHUNK)).max(1);
+    let size = len.div_ceil(count);
+    (0..count)
+        .map(|i| (i * size).min(len)..((i + 1) * size).min(len))
+        .filter(|r| !r.is_empty())
+        .collect()
+}
+
+// The `index`th of `count` contiguous parts of 0..len, as even as possible.
+pub fn chunk(len: usize, index: usize, count: usize) -> Range<usize> {
+    len * index / count..len * (index + 1) / count
+}
+
+// `f` applied to chunks of 0..len on all the cores, in chunk order.
+pub fn map_chunks<T, F>(len: usize, f: F) -> Vec<T>
+where
+    T: Send,
+    F: Fn(Range<usize>) -> T + Sync,
+{
+    let ranges = chunks(len, threads());
+    if ranges.len() <= 1 {
+        return ranges.into_iter().map(f).collect();
+    }
+    let f = &f;
+    thread::scope(|scope| {
+        let workers: Vec<_> = ranges
+            .into_iter()
+            .map(|range| scope.spawn(move || f(range)))
+            .collect();
+        workers.into_iter().map(|w| w.join().unwrap()).collect()
+    })
+}
+
+// Like map_chunks() for functions returning the items of their chunk.
+pub fn map_indices<T, F>(len: usize, f: F) -> Vec<T>
+where
+    T: Send,
+    F: Fn(usize) -> T + Sync,
+{
+    let mut items = Vec::with_capacity(len);
+    for chunk in map_chunks(len, |range| range.map(&f).collect::<Vec<T>>()) {
+        items.extend(chunk);
+    }
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_cover_the_range_in_order() {
+        assert!(chunks(0, 4).is_empty());
+        assert_eq!(chunks(10, 4), vec![0..10]);
+        assert_eq!(chunks(10_000, 4), vec![0..3334, 3334..6668, 6668..10_000]);
+        assert_eq!(chunks(100_000, 2), vec![0..50_000, 50_000..100_000]);
+        assert_eq!(chunks(100_000, 0), vec![0..100_000]);
+    }
+
+    #[test]
+    fn results_come_back_in_order() {
+        let squares = map_indices(50_000, |i| i * i);
+        assert!(squares.iter().enumerate().all(|(i, &s)| s == i * i));
+        let sums: usize = map_chunks(50_000, |range| range.len()).into_iter().sum();
+        assert_eq!(sums, 50_000);
+        assert!(map_indices(0, |i| i).is_empty());
+    }
+}
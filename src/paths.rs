@@ -0,0 +1,397 @@
+//
+// Paths from objects to the GC roots keeping them alive.
+//
+use crate::graph::{references, Adjacency, Graph, Via};
+use crate::heap::HeapObject;
+use crate::reference::RetentionFilter;
+use crate::snapshot::Snapshot;
+
+use std::collections::{HashMap, VecDeque};
+
+pub const NONE: u32 = u32::MAX;
+// Parent of the GC roots in the trees returned by bfs_parents().
+pub const ROOT: u32 = u32::MAX - 1;
+
+// One step of a path: an object and how it refers to the previous one.
+#[derive(Debug, Clone, Copy)]
+pub struct Hop {
+    pub node: u32,
+    pub via: Option<Via>,
+}
+
+// How `from` refers to `to`, considering only retaining references.
+pub fn retaining_via(
+    snapshot: &Snapshot,
+    filter: &RetentionFilter,
+    from: u32,
+    to: u32,
+) -> Option<Via> {
+    let object = &snapshot.objects[from as usize];
+    let target = snapshot.objects[to as usize].object_id();
+    references(snapshot, object)
+        .into_iter()
+        .find(|r| r.target == target && filter.retains(snapshot, object, r.via))
+        .map(|r| r.via)
+}
+
+//
+// Breadth-first search backwards from `target` over the predecessors of
+// each object until a GC root is found. The returned path starts at the
+// target and ends at the root. Returns None if the target is unreachable
+// (or only reachable through references excluded by the filter).
+//
+pub fn shortest_path_to_root(
+    snapshot: &Snapshot,
+    graph: &Graph,
+    preds: &Adjacency,
+    filter: &RetentionFilter,
+    target: u32,
+) -> Option<Vec<Hop>> {
+    let mut is_root = vec![false; graph.len()];
+    for &r in &graph.roots {
+        is_root[r as usize] = true;
+    }
+
+    let mut next = vec![NONE; graph.len()];
+    let mut visited = vec![false; graph.len()];
+    let mut queue = VecDeque::new();
+    visited[target as usize] = true;
+    queue.push_back(target);
+
+    let mut found = None;
+    while let Some(node) = queue.pop_front() {
+        if is_root[node as usize] {
+            found = Some(node);
+            break;
+        }
+        for p in preds.neighbors(node) {
+            if visited[p as usize] {
+                continue;
+            }
+            if !filter.is_empty() && retaining_via(snapshot, filter, p, node).is_none() {
+                continue;
+            }
+            visited[p as usize] = true;
+            next[p as usize] = node;
+            queue.push_back(p);
+        }
+    }
+
+    //
+    // Walk back from the root to the target, then reverse so that the path
+    // reads like a chain of references ending at the root.
+    //
+    let mut node = found?;
+    let mut path = Vec::new();
+    while node != target {
+        let child = next[node as usize];
+        path.push(Hop {
+            node,
+            via: retaining_via(snapshot, filter, node, child),
+        });
+        node = child;
+    }
+    path.push(Hop {
+        node: target,
+        via: None,
+    });
+    path.reverse();
+    Some(path)
+}
+
+//
+// Breadth-first search forward from all the GC roots at once. Following
+// the returned parents from any reachable object leads to a GC root along
+// one of the shortest paths, so this answers the question for every object
+// in a single pass over the graph. Unreachable objects have NONE as their
+// parent and the roots themselves have ROOT.
+//
+pub fn bfs_parents(snapshot: &Snapshot, graph: &Graph, filter: &RetentionFilter) -> Vec<u32> {
+    let mut parents = vec![NONE; graph.len()];
+    let mut queue = VecDeque::new();
+    for &r in &graph.roots {
+        parents[r as usize] = ROOT;
+        queue.push_back(r);
+    }
+    while let Some(node) = queue.pop_front() {
+        let object = &snapshot.objects[node as usize];
+        let retained: Vec<u32> = if filter.excluded_kind(object).is_some() {
+            references(snapshot, object)
+                .into_iter()
+                .filter(|r| filter.retains(snapshot, object, r.via))
+                .filter_map(|r| snapshot.index_of(r.target))
+                .collect()
+        } else {
+            graph.successors.neighbors(node).collect()
+        };
+        for s in retained {
+            if parents[s as usize] == NONE {
+                parents[s as usize] = node;
+                queue.push_back(s);
+            }
+        }
+    }
+    parents
+}
+
+// The path from an object to its GC root in a tree returned by bfs_parents().
+pub fn path_from_parents(
+    snapshot: &Snapshot,
+    filter: &RetentionFilter,
+    parents: &[u32],
+    target: u32,
+) -> Option<Vec<Hop>> {
+    if parents[target as usize] == NONE {
+        return None;
+    }
+    let mut path = vec![Hop {
+        node: target,
+        via: None,
+    }];
+    let mut node = target;
+    while parents[node as usize] != ROOT {
+        let parent = parents[node as usize];
+        path.push(Hop {
+            node: parent,
+            via: retaining_via(snapshot, filter, parent, node),
+        });
+        node = parent;
+    }
+    Some(path)
+}
+
+#[derive(Debug)]
+pub struct MergedNode {
+    pub node: u32,
+    // Number and shallow bytes of the target objects below this node.
+    pub objects: u64,
+    pub bytes: u64,
+    pub children: Vec<usize>,
+}
+
+//
+// Merges the paths from a set of objects to their GC roots into a single
+// tree (like MAT's "merge shortest paths to GC roots"). Element 0 of the
+// result is a virtual node whose children are the GC roots; the children
+// of every node are sorted by the number of objects below them.
+//
+pub fn merge_paths(snapshot: &Snapshot, parents: &[u32], targets: &[u32]) -> Vec<MergedNode> {
+    let mut tree = vec![MergedNode {
+        node: NONE,
+        objects: 0,
+        bytes: 0,
+        children: Vec::new(),
+    }];
+    //
+    // The parents form a tree already, so each object has one node: a
+    // target only adds the part of its path not merged yet, and the counts
+    // are summed up from the nodes of the targets at the end.
+    //
+    let mut index: HashMap<u32, usize> = HashMap::new();
+    let mut up = vec![0];
+    let mut path = Vec::new();
+
+    for &target in targets {
+        if parents[target as usize] == NONE {
+            continue;
+        }
+        let mut node = target;
+        let mut current = 0;
+        loop {
+            if let Some(&known) = index.get(&node) {
+                current = known;
+                break;
+            }
+            path.push(node);
+            match parents[node as usize] {
+                ROOT => break,
+                parent => node = parent,
+            }
+        }
+        for &node in path.iter().rev() {
+            let child = tree.len();
+            tree.push(MergedNode {
+                node,
+                objects: 0,
+                bytes: 0,
+                children: Vec::new(),
+            });
+            tree[current].children.push(child);
+            index.insert(node, child);
+            up.push(current);
+            current = child;
+        }
+        path.clear();
+
+        tree[current].objects += 1;
+        tree[current].bytes += snapshot.shallow_size(&snapshot.objects[target as usize]);
+    }
+
+    // Children come after their parent.
+    for i in (1..tree.len()).rev() {
+        let (objects, bytes) = (tree[i].objects, tree[i].bytes);
+        tree[up[i]].objects += objects;
+        tree[up[i]].bytes += bytes;
+    }
+    for i in 0..tree.len() {
+        let mut children = std::mem::take(&mut tree[i].children);
+        children.sort_by_key(|&c| std::cmp::Reverse(tree[c].objects));
+        tree[i].children = children;
+    }
+    tree
+}
+
+//
+// Renders a path (as returned by shortest_path_to_root()) as a single
+// chain of field accesses from the root, e.g. `Leak.CACHE.table[13].value`.
+// Paths starting at a class begin with its name, other roots with their id.
+//
+pub fn render_chain(snapshot: &Snapshot, path: &[Hop]) -> String {
+    let mut chain = match path.last() {
+        Some(root) => match &snapshot.objects[root.node as usize] {
+            HeapObject::Class(c) => snapshot.class_name(c.class_id),
+            object => format!("{:#x}", object.object_id()),
+        },
+        None => return String::new(),
+    };
+    for hop in path.iter().rev() {
+        match hop.via {
+            Some(Via::Field(_, name_id)) | Some(Via::StaticField(name_id)) => {
+                chain.push('.');
+                chain.push_str(snapshot.string(name_id));
+            }
+            Some(Via::Element(n)) => chain.push_str(&format!("[{}]", n)),
+            Some(via) => {
+                chain.push('.');
+                chain.push_str(&crate::graph::via_name(snapshot, via));
+            }
+            None => {}
+        }
+    }
+    chain
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::{FieldTag, GcRootKind, Value};
+    use crate::reference::ReferenceKind;
+    use crate::synthetic::{self, Options, Shape};
+    use crate::testing::{Dump, TempFile};
+
+    fn merged(snapshot: &Snapshot, class: &str) -> Vec<MergedNode> {
+        let graph = Graph::build(snapshot);
+        let filter = RetentionFilter::new(snapshot, Vec::new());
+        let parents = bfs_parents(snapshot, &graph, &filter);
+        merge_paths(snapshot, &parents, &snapshot.objects_of_class(class))
+    }
+
+    #[test]
+    fn merged_paths_share_their_prefix() {
+        // A root holding two lists, one of them with a node in the middle.
+        let mut dump = Dump::new();
+        let node = dump.class(
+            "test/Node",
+            dump.object,
+            &[("next", FieldTag::NormalObject)],
+        );
+        let last = dump.instance(node, &[]);
+        let middle = dump.instance(node, &[Value::Object(last)]);
+        let first = dump.instance(node, &[Value::Object(middle)]);
+        let other = dump.instance(node, &[]);
+        let array = dump.object_array(dump.object_array, &[first, other]);
+        dump.root(GcRootKind::JniGlobal, array);
+        dump.instance(node, &[]);
+        let snapshot = dump.load();
+        let size =
+            snapshot.shallow_size(&snapshot.objects[snapshot.index_of(last).unwrap() as usize]);
+
+        let tree = merged(&snapshot, "test.Node");
+        let id = |i: usize| snapshot.objects[tree[i].node as usize].object_id();
+        let counts = |i: usize| (id(i), tree[i].objects, tree[i].bytes);
+        assert_eq!((tree[0].objects, tree[0].bytes), (4, 4 * size));
+        assert_eq!(tree[0].children.len(), 1);
+        let root = tree[0].children[0];
+        assert_eq!((id(root), tree[root].objects), (array, 4));
+        let lists = &tree[root].children;
+        assert_eq!(counts(lists[0]), (first, 3, 3 * size));
+        assert_eq!(counts(lists[1]), (other, 1, size));
+        let second = tree[lists[0]].children[0];
+        assert_eq!(counts(second), (middle, 2, 2 * size));
+        assert_eq!(counts(tree[second].children[0]), (last, 1, size));
+        assert_eq!(tree.len(), 6);
+    }
+
+    #[test]
+    fn merged_paths_of_deep_chains() {
+        let options = Options {
+            classes: 0,
+            objects: 40_000,
+            shape: Shape::Deep,
+            depth: 20_000,
+            ..Options::default()
+        };
+        let file = TempFile::new(&synthetic::generate(&options, Vec::new()).unwrap());
+        let snapshot = Snapshot::load(file.path());
+        let tree = merged(&snapshot, "synthetic.Node");
+        // One node for each object on the paths: the nodes and their roots.
+        let roots = tree[0].children.len();
+        assert_eq!(tree.len() as u64, 1 + roots as u64 + tree[0].objects);
+        for node in &tree[1..] {
+            let below: u64 = node.children.iter().map(|&c| tree[c].objects).sum();
+            assert!(node.objects == below || node.objects == below + 1);
+        }
+    }
+
+    // A target held through a weak reference and, further away, by a list.
+    fn weakly_held() -> (Snapshot, u64) {
+        let mut dump = Dump::new();
+        let reference = dump.class(
+            "java/lang/ref/Reference",
+            dump.object,
+            &[("referent", FieldTag::NormalObject)],
+        );
+        let weak = dump.class("java/lang/ref/WeakReference", reference, &[]);
+        let node = dump.class(
+            "test/Node",
+            dump.object,
+            &[("next", FieldTag::NormalObject)],
+        );
+        let target = dump.instance(dump.object, &[]);
+        let weak = dump.instance(weak, &[Value::Object(target)]);
+        let next = dump.instance(node, &[Value::Object(target)]);
+        let head = dump.instance(node, &[Value::Object(next)]);
+        dump.root(GcRootKind::JniGlobal, weak);
+        dump.root(GcRootKind::JniGlobal, head);
+        (dump.load(), target)
+    }
+
+    #[test]
+    fn shortest_paths_skip_the_excluded_references() {
+        let (snapshot, target) = weakly_held();
+        let graph = Graph::build(&snapshot);
+        let preds = graph.predecessors();
+        let target = snapshot.index_of(target).unwrap();
+        let chain = |excluded: Vec<ReferenceKind>| {
+            let filter = RetentionFilter::new(&snapshot, excluded);
+            let path = shortest_path_to_root(&snapshot, &graph, &preds, &filter, target).unwrap();
+            assert_eq!(path[0].node, target);
+            render_chain(&snapshot, &path)
+        };
+        let root = |class: &str| {
+            let node = snapshot.objects_of_class(class)[0];
+            format!("{:#x}", snapshot.objects[node as usize].object_id())
+        };
+        assert_eq!(
+            chain(Vec::new()),
+            format!("{}.referent", root("java.lang.ref.WeakReference"))
+        );
+        let head = snapshot.objects_of_class("test.Node")[1];
+        let head = format!("{:#x}", snapshot.objects[head as usize].object_id());
+        assert_eq!(
+            chain(vec![ReferenceKind::Weak]),
+            format!("{}.next.next", head)
+        );
+        assert_eq!(chain(vec![ReferenceKind::Soft]), chain(Vec::new()));
+    }
+}
@@ -0,0 +1,782 @@
+//
+// IBM/OpenJ9 portable heap dumps (PHD), read into the same Snapshot as
+// HPROF dumps. A PHD file only has the address of each object, its class
+// and the objects it refers to, from which the snapshot is made up:
+//
+// - Classes get the name, superclass and instance size of their record,
+//   and the references of the record as static fields.
+// - The references of an instance, in the order of the dump, go to object
+//   fields named ref0, ref1, ... of its class and superclasses, which are
+//   given as many as the instances of the class have references at most.
+//   Primitive fields are not in the dump, nor the contents of primitive
+//   arrays, which are zero-filled.
+// - Object arrays are given by the class of their elements: they get the
+//   array class of that name, made up when the dump has none.
+// - There are no GC roots in a PHD file: the classes and the objects that
+//   nothing refers to stand for them, as roots of unknown kind.
+//
+// The format is big-endian; gaps between the addresses of objects and to
+// those they refer to are counted in 4-byte words.
+//
+use crate::archive;
+use crate::heap::{
+    ClassDump, FieldDescriptor, FieldTag, GcRoot, GcRootKind, HeapObject, InstanceDump,
+    ObjectArrayDump, PrimitiveArrayDump,
+};
+use crate::records::{read_u16, read_u32, read_u64, read_u8, Header, LoadClassRecord};
+use crate::snapshot::Snapshot;
+
+use std::collections::{HashMap, HashSet};
+use std::io::{BufReader, Read};
+
+const MAGIC: &[u8] = b"portable heap dump";
+
+// Header flags.
+const IS_64_BIT: u32 = 0x1;
+const ALL_HASHED: u32 = 0x2;
+
+const START_OF_HEADER: u8 = 1;
+const END_OF_HEADER: u8 = 2;
+const FULL_VERSION: u8 = 4;
+
+const START_OF_DUMP: u8 = 2;
+const END_OF_DUMP: u8 = 3;
+const LONG_OBJECT: u8 = 4;
+const CLASS: u8 = 6;
+const LONG_PRIMITIVE_ARRAY: u8 = 7;
+const OBJECT_ARRAY: u8 = 8;
+
+// In the flags of the long records: the hash code follows the class.
+const HASHED_AND_MOVED: u8 = 0x2;
+
+// Primitive array types by their code in the dump.
+const PRIMITIVES: [FieldTag; 8] = [
+    FieldTag::Boolean,
+    FieldTag::Char,
+    FieldTag::Float,
+    FieldTag::Double,
+    FieldTag::Byte,
+    FieldTag::Short,
+    FieldTag::Int,
+    FieldTag::Long,
+];
+
+// Whether a dump (or archive member) is a PHD file rather than an HPROF one.
+pub fn is_phd(spec: &str) -> bool {
+    let mut start = [0u8; 20];
+    match archive::open(spec) {
+        Ok((mut f, _)) => {
+            f.read_exact(&mut start).is_ok() && start[..2] == [0, 18] && &start[2..] == MAGIC
+        }
+        Err(_) => false,
+    }
+}
+
+struct Class {
+    address: u64,
+    super_address: u64,
+    name: String,
+    instance_size: u32,
+    references: Vec<u64>,
+}
+
+enum Record {
+    Instance {
+        address: u64,
+        class: u64,
+        references: Vec<u64>,
+    },
+    ObjectArray {
+        address: u64,
+        element_class: u64,
+        elements: Vec<u64>,
+        length: usize,
+    },
+    PrimitiveArray {
+        address: u64,
+        tag: FieldTag,
+        length: u64,
+    },
+}
+
+struct Reader<R> {
+    input: R,
+    version: u32,
+    // The size of an address, 4 or 8.
+    word: u32,
+    all_hashed: bool,
+    // The address of the last object read.
+    address: u64,
+    // The classes the short object records refer to, filled in turn.
+    class_cache: [u64; 4],
+    next_cached: usize,
+}
+
+impl<R: Read> Reader<R> {
+    // A signed value of 1, 2, 4 or 8 bytes, by its size code 0 to 3.
+    fn signed(&mut self, code: u8) -> i64 {
+        match code {
+            0 => read_u8(&mut self.input) as i8 as i64,
+            1 => read_u16(&mut self.input) as i16 as i64,
+            2 => read_u32(&mut self.input) as i32 as i64,
+            _ => read_u64(&mut self.input) as i64,
+        }
+    }
+
+    fn word_code(&self) -> u8 {
+        if self.word == 8 {
+            3
+        } else {
+            2
+        }
+    }
+
+    fn word(&mut self) -> u64 {
+        match self.word {
+            8 => read_u64(&mut self.input),
+            _ => read_u32(&mut self.input) as u64,
+        }
+    }
+
+    fn utf(&mut self) -> String {
+        let mut bytes = vec![0; read_u16(&mut self.input) as usize];
+        self.input.read_exact(&mut bytes).unwrap();
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    // The address of the next object, given by its gap to the last one.
+    fn address(&mut self, code: u8) -> u64 {
+        let gap = self.signed(code);
+        self.address = self.address.wrapping_add((gap * 4) as u64);
+        self.address
+    }
+
+    fn references(&mut self, count: usize, code: u8) -> Vec<u64> {
+        (0..count)
+            .map(|_| self.address.wrapping_add((self.signed(code) * 4) as u64))
+            .collect()
+    }
+
+    fn cache(&mut self, class: u64) {
+        if !self.class_cache.contains(&class) {
+            self.class_cache[self.next_cached] = class;
+            self.next_cached = (self.next_cached + 1) % self.class_cache.len();
+        }
+    }
+
+    // The hash code of the long records, which nothing needs.
+    fn skip_hash(&mut self, flags: u8) {
+        if self.all_hashed {
+            read_u16(&mut self.input);
+        } else if flags & HASHED_AND_MOVED != 0 {
+            read_u32(&mut self.input);
+        }
+    }
+
+    // Reads the object records up to the end of the dump.
+    fn records(&mut self, classes: &mut Vec<Class>, objects: &mut Vec<Record>) {
+        loop {
+            let tag = read_u8(&mut self.input);
+            if tag & 0x80 != 0 {
+                // A short object record, of a class in the cache.
+                let class = self.class_cache[(tag >> 5) as usize & 3];
+                let address = self.address(if tag & 0x4 != 0 { 1 } else { 0 });
+                if self.all_hashed {
+                    read_u16(&mut self.input);
+                }
+                let references = self.references((tag >> 3) as usize & 3, tag & 0x3);
+                objects.push(Record::Instance {
+                    address,
+                    class,
+                    references,
+                });
+            } else if tag & 0x40 != 0 {
+                let address = self.address(if tag & 0x4 != 0 { 1 } else { 0 });
+                let class = self.word();
+                self.cache(class);
+                if self.all_hashed {
+                    read_u16(&mut self.input);
+                }
+                let references = self.references((tag >> 3) as usize & 7, tag & 0x3);
+                objects.push(Record::Instance {
+                    address,
+                    class,
+                    references,
+                });
+            } else if tag & 0x20 != 0 {
+                let code = tag & 0x3;
+                let address = self.address(code);
+                let length = self.signed(code) as u64;
+                if self.all_hashed {
+                    read_u16(&mut self.input);
+                }
+                if self.version >= 6 {
+                    let _words = read_u32(&mut self.input);
+                }
+                objects.push(Record::PrimitiveArray {
+                    address,
+                    tag: PRIMITIVES[(tag >> 2) as usize & 7],
+                    length,
+                });
+            } else {
+                match tag {
+                    LONG_OBJECT | OBJECT_ARRAY => {
+                        let flags = read_u8(&mut self.input);
+                        let address = self.address(flags >> 6);
+                        let class = self.word();
+                        self.cache(class);
+                        self.skip_hash(flags);
+                        let count = read_u32(&mut self.input) as usize;
+                        let references = self.references(count, (flags >> 4) & 0x3);
+                        if tag == LONG_OBJECT {
+                            objects.push(Record::Instance {
+                                address,
+                                class,
+                                references,
+                            });
+                            continue;
+                        }
+                        // Null elements are left out, but for the length.
+                        let length = if self.version >= 6 {
+                            read_u32(&mut self.input) as usize
+                        } else {
+                            count
+                        };
+                        objects.push(Record::ObjectArray {
+                            address,
+                            element_class: class,
+                            elements: references,
+                            length,
+                        });
+                    }
+                    CLASS => {
+                        let flags = read_u8(&mut self.input);
+                        let address = self.address(flags >> 6);
+                        let instance_size = read_u32(&mut self.input);
+                        self.skip_hash(flags);
+                        let super_address = self.word();
+                        let name = self.utf();
+                        let count = read_u32(&mut self.input) as usize;
+                        let references = self.references(count, (flags >> 4) & 0x3);
+                        classes.push(Class {
+                            address,
+                            super_address,
+                            name,
+                            instance_size,
+                            references,
+                        });
+                    }
+                    LONG_PRIMITIVE_ARRAY => {
+                        let flags = read_u8(&mut self.input);
+                        let code = if flags & 0x10 != 0 {
+                            self.word_code()
+                        } else {
+                            0
+                        };
+                        let address = self.address(code);
+                        let length = self.signed(code) as u64;
+                        self.skip_hash(flags);
+                        let _words = read_u32(&mut self.input);
+                        objects.push(Record::PrimitiveArray {
+                            address,
+                            tag: PRIMITIVES[(flags >> 5) as usize & 7],
+                            length,
+                        });
+                    }
+                    END_OF_DUMP => return,
+                    _ => panic!("unknown PHD record tag: {:#x}", tag),
+                }
+            }
+        }
+    }
+}
+
+// The symbols of the snapshot being made: class and field names.
+struct Names {
+    next_id: u64,
+    fields: Vec<u64>,
+}
+
+impl Names {
+    fn add(&mut self, snapshot: &mut Snapshot, name: &str) -> u64 {
+        self.next_id += 1;
+        snapshot.strings.insert(self.next_id, name);
+        self.next_id
+    }
+
+    // The name id of the synthetic field (or static) `i`.
+    fn field(&mut self, snapshot: &mut Snapshot, i: usize) -> u64 {
+        while self.fields.len() <= i {
+            let id = self.add(snapshot, &format!("ref{}", self.fields.len()));
+            self.fields.push(id);
+        }
+        self.fields[i]
+    }
+}
+
+fn array_class_name(element: &str) -> String {
+    if element.starts_with('[') {
+        format!("[{}", element)
+    } else {
+        format!("[L{};", element)
+    }
+}
+
+//
+// How many of the synthetic fields the instances of a class have, its own
+// then those of its superclasses: the first ones it needs are inherited.
+//
+fn slots(
+    class: u64,
+    supers: &HashMap<u64, u64>,
+    references: &HashMap<u64, usize>,
+    own: &mut HashMap<u64, (usize, usize)>,
+) -> usize {
+    let mut chain = Vec::new();
+    let mut current = class;
+    while current != 0 && !own.contains_key(&current) && !chain.contains(&current) {
+        chain.push(current);
+        current = supers.get(&current).copied().unwrap_or(0);
+    }
+    let mut inherited = own.get(&current).map_or(0, |&(_, total)| total);
+    for &c in chain.iter().rev() {
+        let fields = references
+            .get(&c)
+            .copied()
+            .unwrap_or(0)
+            .saturating_sub(inherited);
+        inherited += fields;
+        own.insert(c, (fields, inherited));
+    }
+    own.get(&class).map_or(0, |&(_, total)| total)
+}
+
+fn build(word: u32, version: u32, mut classes: Vec<Class>, objects: Vec<Record>) -> Snapshot {
+    let mut snapshot = Snapshot::new(Header {
+        format: format!("portable heap dump {}", version),
+        identifier_size: word,
+        high_word_ms: 0,
+        low_word_ms: 0,
+    });
+    let mut names = Names {
+        next_id: 0,
+        fields: Vec::new(),
+    };
+
+    // Classes that instances have and the dump doesn't, and array classes.
+    let known: HashSet<u64> = classes.iter().map(|c| c.address).collect();
+    let mut by_name: HashMap<String, u64> = classes
+        .iter()
+        .map(|c| (c.name.clone(), c.address))
+        .collect();
+    let object_class = by_name.get("java/lang/Object").copied().unwrap_or(0);
+    let mut array_classes: HashMap<u64, u64> = HashMap::new();
+    let mut missing = HashSet::new();
+    for object in &objects {
+        match object {
+            Record::Instance { class, .. } if !known.contains(class) && missing.insert(*class) => {
+                classes.push(Class {
+                    address: *class,
+                    super_address: object_class,
+                    name: format!("<unknown class {:#x}>", class),
+                    instance_size: 0,
+                    references: Vec::new(),
+                });
+            }
+            Record::ObjectArray { element_class, .. }
+                if !array_classes.contains_key(element_class) =>
+            {
+                let element = classes
+                    .iter()
+                    .find(|c| c.address == *element_class)
+                    .map_or("java/lang/Object", |c| c.name.as_str());
+                let name = array_class_name(element);
+                let id = match by_name.get(&name) {
+                    Some(&id) => id,
+                    None => {
+                        // Odd, so not the address of an object.
+                        let id = element_class | 1;
+                        by_name.insert(name.clone(), id);
+                        classes.push(Class {
+                            address: id,
+                            super_address: object_class,
+                            name,
+                            instance_size: 0,
+                            references: Vec::new(),
+                        });
+                        id
+                    }
+                };
+                array_classes.insert(*element_class, id);
+            }
+            _ => {}
+        }
+    }
+
+    let supers: HashMap<u64, u64> = classes
+        .iter()
+        .map(|c| (c.address, c.super_address))
+        .collect();
+    let mut references: HashMap<u64, usize> = HashMap::new();
+    for object in &objects {
+        if let Record::Instance {
+            class,
+            references: r,
+            ..
+        } = object
+        {
+            let most = references.entry(*class).or_default();
+            *most = (*most).max(r.len());
+        }
+    }
+    let mut own = HashMap::new();
+    let mut referenced: HashSet<u64> = HashSet::new();
+    for (i, class) in classes.iter().enumerate() {
+        let total = slots(class.address, &supers, &references, &mut own);
+        let (fields, _) = own[&class.address];
+        let instance_fields = (total - fields..total)
+            .map(|i| FieldDescriptor {
+                name_id: names.field(&mut snapshot, i),
+                tag: FieldTag::NormalObject,
+            })
+            .collect();
+        let statics: Vec<(u64, u64)> = class
+            .references
+            .iter()
+            .enumerate()
+            .map(|(i, &r)| (names.field(&mut snapshot, i), r))
+            .collect();
+        referenced.extend(&class.references);
+        let serial_num = i as u32 + 1;
+        let strname_id = names.add(&mut snapshot, &class.name);
+        snapshot.classes.insert(
+            serial_num,
+            LoadClassRecord {
+                serial_num,
+                object_id: class.address,
+                strace_num: 0,
+                strname_id,
+            },
+        );
+        snapshot.class_serials.insert(class.address, serial_num);
+        let instance_size = class.instance_size.max(total as u32 * word);
+        snapshot.objects.push(HeapObject::Class(ClassDump::new(
+            class.address,
+            class.super_address,
+            instance_size,
+            instance_fields,
+            &statics,
+            word,
+        )));
+        snapshot.roots.push(GcRoot {
+            kind: GcRootKind::Unknown,
+            object_id: class.address,
+            thread_serial_num: None,
+            frame_num: None,
+            strace_num: None,
+        });
+    }
+
+    let id_bytes = |id: u64| match word {
+        8 => id.to_be_bytes().to_vec(),
+        _ => (id as u32).to_be_bytes().to_vec(),
+    };
+    for object in objects {
+        snapshot.objects.push(match object {
+            Record::Instance {
+                address,
+                class,
+                references: r,
+            } => {
+                let total = own.get(&class).map_or(0, |&(_, total)| total);
+                let size = classes[snapshot.class_serials[&class] as usize - 1].instance_size;
+                let mut data: Vec<u8> = r.iter().flat_map(|&id| id_bytes(id)).collect();
+                data.resize((size as usize).max(total * word as usize), 0);
+                referenced.extend(&r);
+                HeapObject::Instance(InstanceDump {
+                    object_id: address,
+                    strace_num: 0,
+                    class_id: class,
+                    data,
+                })
+            }
+            Record::ObjectArray {
+                address,
+                element_class,
+                mut elements,
+                length,
+            } => {
+                referenced.extend(&elements);
+                if length > elements.len() {
+                    elements.resize(length, 0);
+                }
+                HeapObject::ObjectArray(ObjectArrayDump {
+                    object_id: address,
+                    strace_num: 0,
+                    class_id: array_classes[&element_class],
+                    elements,
+                })
+            }
+            Record::PrimitiveArray {
+                address,
+                tag,
+                length,
+            } => HeapObject::PrimitiveArray(PrimitiveArrayDump {
+                object_id: address,
+                strace_num: 0,
+                element_tag: tag,
+                length: length as u32,
+                data: vec![0; length as usize * tag.size(word) as usize],
+            }),
+        });
+    }
+
+    for (i, object) in snapshot.objects.iter().enumerate() {
+        snapshot.object_index.insert(object.object_id(), i as u32);
+    }
+    let unreferenced: Vec<u64> = snapshot.objects[classes.len()..]
+        .iter()
+        .map(|o| o.object_id())
+        .filter(|id| !referenced.contains(id))
+        .collect();
+    snapshot
+        .roots
+        .extend(unreferenced.into_iter().map(|object_id| GcRoot {
+            kind: GcRootKind::Unknown,
+            object_id,
+            thread_serial_num: None,
+            frame_num: None,
+            strace_num: None,
+        }));
+    snapshot.strings.finish();
+    snapshot
+}
+
+// Loads a PHD file (see is_phd()).
+pub fn load(spec: &str) -> Snapshot {
+    let (f, location) = archive::open(spec).unwrap_or_else(|e| panic!("{}: {}", spec, e));
+    let len = location.range.map_or(u64::MAX, |r| r.end - r.start);
+    read(BufReader::with_capacity(1 << 20, f.take(len)), spec)
+}
+
+// Reads a PHD file from its first byte, `spec` naming it in errors.
+fn read<R: Read>(mut input: R, spec: &str) -> Snapshot {
+    let mut magic = [0u8; 20];
+    input.read_exact(&mut magic).unwrap();
+    let version = read_u32(&mut input);
+    let flags = read_u32(&mut input);
+    let mut reader = Reader {
+        input,
+        version,
+        word: if flags & IS_64_BIT != 0 { 8 } else { 4 },
+        all_hashed: flags & ALL_HASHED != 0,
+        address: 0,
+        class_cache: [0; 4],
+        next_cached: 0,
+    };
+    if read_u8(&mut reader.input) != START_OF_HEADER {
+        panic!("{}: no PHD header", spec);
+    }
+    loop {
+        match read_u8(&mut reader.input) {
+            END_OF_HEADER => break,
+            FULL_VERSION => {
+                reader.utf();
+            }
+            tag => panic!("{}: unknown PHD header tag: {:#x}", spec, tag),
+        }
+    }
+    if read_u8(&mut reader.input) != START_OF_DUMP {
+        panic!("{}: no start of dump", spec);
+    }
+    let (mut classes, mut objects) = (Vec::new(), Vec::new());
+    reader.records(&mut classes, &mut objects);
+    build(reader.word, version, classes, objects)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::Value;
+
+    // A dump being written, with the address of the last object as readers track it.
+    struct Writer {
+        out: Vec<u8>,
+        address: u64,
+    }
+
+    impl Writer {
+        fn u8(&mut self, n: u8) {
+            self.out.push(n);
+        }
+
+        fn u32(&mut self, n: u32) {
+            self.out.extend_from_slice(&n.to_be_bytes());
+        }
+
+        fn utf(&mut self, s: &str) {
+            self.out.extend_from_slice(&(s.len() as u16).to_be_bytes());
+            self.out.extend_from_slice(s.as_bytes());
+        }
+
+        // The gap in words from the last object to the next one.
+        fn gap(&mut self, address: u64) -> i64 {
+            let gap = (address as i64 - self.address as i64) / 4;
+            self.address = address;
+            gap
+        }
+
+        fn address(&mut self, address: u64) {
+            let gap = self.gap(address);
+            self.u32(gap as u32);
+        }
+
+        fn references(&mut self, references: &[u64]) {
+            self.u32(references.len() as u32);
+            for &r in references {
+                self.u32(((r as i64 - self.address as i64) / 4) as u32);
+            }
+        }
+
+        fn class(&mut self, address: u64, super_address: u64, name: &str, statics: &[u64]) {
+            self.u8(CLASS);
+            self.u8(0xa0);
+            self.address(address);
+            self.u32(16);
+            self.u32(super_address as u32);
+            self.utf(name);
+            self.references(statics);
+        }
+
+        fn object(&mut self, tag: u8, address: u64, class: u64, references: &[u64]) {
+            self.u8(tag);
+            self.u8(0xa0);
+            self.address(address);
+            self.u32(class as u32);
+            self.references(references);
+        }
+    }
+
+    const OBJECT: u64 = 0x1000;
+    const NODE: u64 = 0x1100;
+    const HEAD: u64 = 0x2000;
+    const SECOND: u64 = 0x2010;
+    const THIRD: u64 = 0x2020;
+    const ARRAY: u64 = 0x2030;
+    const BYTES: u64 = 0x2040;
+
+    // Three nodes of a list and an array of them, along with a byte[].
+    fn dump() -> Vec<u8> {
+        let mut w = Writer {
+            out: vec![0, 18],
+            address: 0,
+        };
+        w.out.extend_from_slice(MAGIC);
+        w.u32(6);
+        w.u32(0);
+        w.u8(START_OF_HEADER);
+        w.u8(FULL_VERSION);
+        w.utf("JRE 17 test");
+        w.u8(END_OF_HEADER);
+        w.u8(START_OF_DUMP);
+        w.class(OBJECT, 0, "java/lang/Object", &[]);
+        w.class(NODE, OBJECT, "com/example/Node", &[HEAD]);
+        w.object(LONG_OBJECT, HEAD, NODE, &[SECOND, BYTES]);
+        // A short record of the class cached by the one before, with one
+        // reference and one-byte gaps.
+        w.u8(0x80 | 1 << 3);
+        let gap = w.gap(SECOND);
+        w.u8(gap as u8);
+        w.u8(((THIRD - SECOND) / 4) as u8);
+        // A medium record naming its class, no references.
+        w.u8(0x40);
+        let gap = w.gap(THIRD);
+        w.u8(gap as u8);
+        w.u32(NODE as u32);
+        w.object(OBJECT_ARRAY, ARRAY, NODE, &[HEAD, THIRD]);
+        w.u32(5);
+        // A short primitive array record: byte[10] with one-byte gaps.
+        w.u8(0x20 | 4 << 2);
+        let gap = w.gap(BYTES);
+        w.u8(gap as u8);
+        w.u8(10);
+        w.u32(3);
+        w.u8(END_OF_DUMP);
+        w.out
+    }
+
+    fn references(snapshot: &Snapshot, id: u64) -> Vec<(String, u64)> {
+        match snapshot.object(id) {
+            Some(HeapObject::Instance(i)) => snapshot
+                .instance_fields(i)
+                .iter()
+                .map(|f| match f.value {
+                    Value::Object(id) => (snapshot.string(f.name_id).to_string(), id),
+                    _ => panic!("not a reference"),
+                })
+                .collect(),
+            _ => panic!("no instance {:#x}", id),
+        }
+    }
+
+    #[test]
+    fn reads_classes_and_objects() {
+        let snapshot = read(&dump()[..], "test.phd");
+        assert_eq!(snapshot.header.format, "portable heap dump 6");
+        assert_eq!(snapshot.id_size(), 4);
+        assert_eq!(snapshot.class_name(NODE), "com.example.Node");
+        // Three classes (one the array class made up), then the objects.
+        let classes = snapshot
+            .objects
+            .iter()
+            .filter(|o| matches!(o, HeapObject::Class(_)))
+            .count();
+        assert_eq!(classes, 3);
+        assert_eq!(snapshot.objects.len(), 8);
+
+        assert_eq!(
+            references(&snapshot, HEAD),
+            vec![("ref0".to_string(), SECOND), ("ref1".to_string(), BYTES)]
+        );
+        assert_eq!(
+            references(&snapshot, SECOND),
+            vec![("ref0".to_string(), THIRD), ("ref1".to_string(), 0)]
+        );
+        assert_eq!(
+            references(&snapshot, THIRD),
+            vec![("ref0".to_string(), 0), ("ref1".to_string(), 0)]
+        );
+        match snapshot.object(ARRAY) {
+            Some(HeapObject::ObjectArray(a)) => {
+                assert_eq!(a.elements, vec![HEAD, THIRD, 0, 0, 0]);
+                assert_eq!(snapshot.class_name(a.class_id), "com.example.Node[]");
+            }
+            _ => panic!("no array"),
+        }
+        match snapshot.object(BYTES) {
+            Some(HeapObject::PrimitiveArray(a)) => {
+                assert_eq!((a.element_tag, a.length), (FieldTag::Byte, 10));
+            }
+            _ => panic!("no byte[]"),
+        }
+        match snapshot.class_dump(NODE) {
+            Some(class) => assert_eq!(class.static_fields()[0].value, Value::Object(HEAD)),
+            None => panic!("no class dump"),
+        }
+    }
+
+    #[test]
+    fn roots_are_the_classes_and_what_nothing_refers_to() {
+        let snapshot = read(&dump()[..], "test.phd");
+        let mut roots: Vec<u64> = snapshot.roots.iter().map(|r| r.object_id).collect();
+        roots.sort_unstable();
+        assert_eq!(roots, vec![OBJECT, NODE, NODE | 1, ARRAY]);
+        assert!(snapshot.roots.iter().all(|r| r.kind == GcRootKind::Unknown));
+    }
+
+    #[test]
+    fn array_class_names() {
+        assert_eq!(array_class_name("java/lang/String"), "[Ljava/lang/String;");
+        assert_eq!(array_class_name("[I"), "[[I");
+    }
+}
@@ -0,0 +1,468 @@
+//
+// Predicates on the fields of instances, like
+// `userId == 42 && (name =~ "^adm" || lastAccess < 1700000000)`. Fields are
+// named by paths through object fields (`session.user.id`), and compare
+// against numbers, quoted strings, true, false and null; `=~` matches a
+// string against a regex. Strings and boxed values are compared by value.
+// A comparison with a missing field or a value of another type is false.
+//
+use crate::boxed::unbox;
+use crate::heap::{HeapObject, InstanceDump, Value};
+use crate::regex::Regex;
+use crate::snapshot::Snapshot;
+use crate::strings::as_string;
+
+use std::cmp::Ordering;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Matches,
+}
+
+#[derive(Debug)]
+pub enum Literal {
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Bool(bool),
+    Null,
+}
+
+#[derive(Debug)]
+pub enum Predicate {
+    And(Box<Predicate>, Box<Predicate>),
+    Or(Box<Predicate>, Box<Predicate>),
+    Not(Box<Predicate>),
+    Compare {
+        path: Vec<String>,
+        op: Op,
+        literal: Literal,
+        // The compiled literal of `=~`.
+        regex: Option<Regex>,
+    },
+}
+
+// The value of a field as far as comparisons go.
+#[derive(Debug)]
+pub enum FieldValue {
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Bool(bool),
+    Null,
+    Object(u64),
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Literal(String, bool),
+    Op(Op),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let two = |t: Token| (t, 2);
+        let (token, length) = match (c, next) {
+            _ if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ('&', Some('&')) => two(Token::And),
+            ('|', Some('|')) => two(Token::Or),
+            ('=', Some('=')) => two(Token::Op(Op::Eq)),
+            ('=', Some('~')) => two(Token::Op(Op::Matches)),
+            ('!', Some('=')) => two(Token::Op(Op::Ne)),
+            ('<', Some('=')) => two(Token::Op(Op::Le)),
+            ('>', Some('=')) => two(Token::Op(Op::Ge)),
+            ('<', _) => (Token::Op(Op::Lt), 1),
+            ('>', _) => (Token::Op(Op::Gt), 1),
+            ('!', _) => (Token::Not, 1),
+            ('(', _) => (Token::Open, 1),
+            (')', _) => (Token::Close, 1),
+            ('"', _) | ('\'', _) => {
+                let mut value = String::new();
+                let mut j = i + 1;
+                loop {
+                    match chars.get(j) {
+                        None => return Err(format!("unterminated string at {}", i)),
+                        Some(&q) if q == c => break,
+                        Some('\\') if j + 1 < chars.len() => {
+                            value.push(chars[j + 1]);
+                            j += 2;
+                        }
+                        Some(&ch) => {
+                            value.push(ch);
+                            j += 1;
+                        }
+                    }
+                }
+                (Token::Literal(value, true), j + 1 - i)
+            }
+            _ if c.is_alphanumeric() || matches!(c, '_' | '$' | '-' | '.') => {
+                let length = chars[i..]
+                    .iter()
+                    .take_while(|&&ch| ch.is_alphanumeric() || matches!(ch, '_' | '$' | '-' | '.'))
+                    .count();
+                let word: String = chars[i..i + length].iter().collect();
+                let token = if c.is_ascii_digit() || c == '-' || c == '.' {
+                    Token::Literal(word, false)
+                } else {
+                    Token::Ident(word)
+                };
+                (token, length)
+            }
+            _ => return Err(format!("unexpected '{}' at {}", c, i)),
+        };
+        tokens.push(token);
+        i += length;
+    }
+    Ok(tokens)
+}
+
+fn parse_literal(text: String, quoted: bool) -> Result<Literal, String> {
+    if quoted {
+        return Ok(Literal::Str(text));
+    }
+    match text.as_str() {
+        "true" => return Ok(Literal::Bool(true)),
+        "false" => return Ok(Literal::Bool(false)),
+        "null" => return Ok(Literal::Null),
+        _ => {}
+    }
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text.as_str()),
+    };
+    let int = match digits.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => digits.parse::<i64>().ok(),
+    };
+    match int {
+        Some(v) => Ok(Literal::Int(if negative { -v } else { v })),
+        None => text
+            .parse::<f64>()
+            .map(Literal::Float)
+            .map_err(|_| format!("not a value: {}", text)),
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get_mut(self.position)?;
+        self.position += 1;
+        Some(std::mem::replace(token, Token::Close))
+    }
+
+    fn or(&mut self) -> Result<Predicate, String> {
+        let mut left = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.position += 1;
+            left = Predicate::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Predicate, String> {
+        let mut left = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.position += 1;
+            left = Predicate::And(Box::new(left), Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Predicate, String> {
+        match self.next() {
+            Some(Token::Not) => Ok(Predicate::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let inner = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(inner),
+                    _ => Err("missing )".to_string()),
+                }
+            }
+            Some(Token::Ident(field)) => {
+                let op = match self.next() {
+                    Some(Token::Op(op)) => op,
+                    _ => return Err(format!("expected a comparison after {}", field)),
+                };
+                let literal = match self.next() {
+                    Some(Token::Literal(text, quoted)) => parse_literal(text, quoted)?,
+                    Some(Token::Ident(word)) => parse_literal(word, false)?,
+                    _ => return Err(format!("expected a value after {}", field)),
+                };
+                let regex = match (op, &literal) {
+                    (Op::Matches, Literal::Str(pattern)) => Some(Regex::new(pattern)?),
+                    (Op::Matches, _) => return Err("=~ takes a quoted regex".to_string()),
+                    _ => None,
+                };
+                Ok(Predicate::Compare {
+                    path: field.split('.').map(str::to_string).collect(),
+                    op,
+                    literal,
+                    regex,
+                })
+            }
+            _ => Err("expected a field name".to_string()),
+        }
+    }
+}
+
+fn field_value(snapshot: &Snapshot, value: Value) -> FieldValue {
+    match value {
+        Value::Boolean(v) => FieldValue::Bool(v),
+        Value::Char(v) => FieldValue::Str(char::from_u32(v as u32).unwrap_or('?').to_string()),
+        Value::Byte(v) => FieldValue::Int(v as i64),
+        Value::Short(v) => FieldValue::Int(v as i64),
+        Value::Int(v) => FieldValue::Int(v as i64),
+        Value::Long(v) => FieldValue::Int(v),
+        Value::Float(v) => FieldValue::Float(v as f64),
+        Value::Double(v) => FieldValue::Float(v),
+        Value::Object(0) => FieldValue::Null,
+        Value::Object(id) => match snapshot.object(id) {
+            Some(object) => {
+                if let Some(s) = as_string(snapshot, object) {
+                    FieldValue::Str(s)
+                } else if let Some(value) = unbox(snapshot, object) {
+                    field_value(snapshot, value)
+                } else {
+                    FieldValue::Object(id)
+                }
+            }
+            None => FieldValue::Object(id),
+        },
+    }
+}
+
+// The value at the end of a path of fields, None if a field is missing.
+pub fn resolve(
+    snapshot: &Snapshot,
+    instance: &InstanceDump,
+    path: &[String],
+) -> Option<FieldValue> {
+    let mut current = instance;
+    for (i, name) in path.iter().enumerate() {
+        let value = snapshot.field_value(current, name)?;
+        if i + 1 == path.len() {
+            return Some(field_value(snapshot, value));
+        }
+        current = match snapshot.object(value.as_object()?)? {
+            HeapObject::Instance(next) => next,
+            _ => return None,
+        };
+    }
+    None
+}
+
+fn compare(value: &FieldValue, literal: &Literal) -> Option<Ordering> {
+    match (value, literal) {
+        (FieldValue::Int(a), Literal::Int(b)) => Some(a.cmp(b)),
+        (FieldValue::Int(a), Literal::Float(b)) => (*a as f64).partial_cmp(b),
+        (FieldValue::Float(a), Literal::Int(b)) => a.partial_cmp(&(*b as f64)),
+        (FieldValue::Float(a), Literal::Float(b)) => a.partial_cmp(b),
+        (FieldValue::Str(a), Literal::Str(b)) => Some(a.as_str().cmp(b.as_str())),
+        (FieldValue::Bool(a), Literal::Bool(b)) => Some(a.cmp(b)),
+        (FieldValue::Null, Literal::Null) => Some(Ordering::Equal),
+        (FieldValue::Object(_), Literal::Null) => Some(Ordering::Greater),
+        _ => None,
+    }
+}
+
+impl Predicate {
+    pub fn parse(text: &str) -> Result<Predicate, String> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            position: 0,
+        };
+        let predicate = parser.or()?;
+        if parser.position < parser.tokens.len() {
+            return Err(format!(
+                "unexpected token {:?}",
+                parser.tokens[parser.position]
+            ));
+        }
+        Ok(predicate)
+    }
+
+    pub fn matches(&self, snapshot: &Snapshot, instance: &InstanceDump) -> bool {
+        match self {
+            Predicate::And(a, b) => a.matches(snapshot, instance) && b.matches(snapshot, instance),
+            Predicate::Or(a, b) => a.matches(snapshot, instance) || b.matches(snapshot, instance),
+            Predicate::Not(a) => !a.matches(snapshot, instance),
+            Predicate::Compare {
+                path,
+                op,
+                literal,
+                regex,
+            } => {
+                let value = match resolve(snapshot, instance, path) {
+                    Some(value) => value,
+                    None => return false,
+                };
+                if let Some(regex) = regex {
+                    return matches!(&value, FieldValue::Str(s) if regex.is_match(s));
+                }
+                let ordering = match compare(&value, literal) {
+                    Some(ordering) => ordering,
+                    None => return false,
+                };
+                match op {
+                    Op::Eq => ordering == Ordering::Equal,
+                    Op::Ne => ordering != Ordering::Equal,
+                    Op::Lt => ordering == Ordering::Less,
+                    Op::Le => ordering != Ordering::Greater,
+                    Op::Gt => ordering == Ordering::Greater,
+                    Op::Ge => ordering != Ordering::Less,
+                    Op::Matches => false,
+                }
+            }
+        }
+    }
+
+    // The field paths the predicate looks at, in order of appearance.
+    pub fn paths(&self) -> Vec<&[String]> {
+        match self {
+            Predicate::And(a, b) | Predicate::Or(a, b) => {
+                let mut paths = a.paths();
+                for path in b.paths() {
+                    if !paths.contains(&path) {
+                        paths.push(path);
+                    }
+                }
+                paths
+            }
+            Predicate::Not(a) => a.paths(),
+            Predicate::Compare { path, .. } => vec![path.as_slice()],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::FieldTag;
+    use crate::testing::Dump;
+
+    #[test]
+    fn literals_and_errors() {
+        let literal = |text: &str| parse_literal(text.to_string(), false);
+        assert!(matches!(literal("0x1f"), Ok(Literal::Int(31))));
+        assert!(matches!(literal("-3"), Ok(Literal::Int(-3))));
+        assert!(matches!(literal("2.5"), Ok(Literal::Float(v)) if v == 2.5));
+        assert!(matches!(literal("null"), Ok(Literal::Null)));
+        assert!(literal("1x").is_err());
+
+        let error = |text: &str| Predicate::parse(text).unwrap_err();
+        assert_eq!(error("a =="), "expected a value after a");
+        assert_eq!(error("(a == 1"), "missing )");
+        assert_eq!(error("a =~ 1"), "=~ takes a quoted regex");
+        assert_eq!(error("a == \"x"), "unterminated string at 5");
+        assert_eq!(error("a == 1 b"), "unexpected token Ident(\"b\")");
+        assert_eq!(error("a # 1"), "unexpected '#' at 2");
+    }
+
+    #[test]
+    fn predicates_follow_paths_and_compare_by_value() {
+        let mut dump = Dump::new();
+        let integer = dump.class(
+            "java/lang/Integer",
+            dump.object,
+            &[("value", FieldTag::Int)],
+        );
+        let user = dump.class(
+            "test/User",
+            dump.object,
+            &[("id", FieldTag::Long), ("admin", FieldTag::Boolean)],
+        );
+        let session = dump.class(
+            "test/Session",
+            dump.object,
+            &[
+                ("userId", FieldTag::Int),
+                ("name", FieldTag::NormalObject),
+                ("lastAccess", FieldTag::Long),
+                ("user", FieldTag::NormalObject),
+                ("count", FieldTag::NormalObject),
+            ],
+        );
+        let admin = dump.instance(user, &[Value::Long(7), Value::Boolean(true)]);
+        let name = dump.string("admin");
+        let count = dump.instance(integer, &[Value::Int(3)]);
+        let first = dump.instance(
+            session,
+            &[
+                Value::Int(42),
+                Value::Object(name),
+                Value::Long(2_000_000_000),
+                Value::Object(admin),
+                Value::Object(count),
+            ],
+        );
+        let name = dump.string("bob");
+        let second = dump.instance(
+            session,
+            &[Value::Int(43), Value::Object(name), Value::Long(5)],
+        );
+        let snapshot = dump.load();
+
+        let instances: Vec<&InstanceDump> = [first, second]
+            .iter()
+            .map(|&id| match snapshot.object(id) {
+                Some(HeapObject::Instance(i)) => i,
+                _ => unreachable!(),
+            })
+            .collect();
+        let matching = |text: &str| -> Vec<bool> {
+            let predicate = Predicate::parse(text).unwrap();
+            instances
+                .iter()
+                .map(|i| predicate.matches(&snapshot, i))
+                .collect()
+        };
+        assert_eq!(
+            matching("userId == 42 && (name =~ \"^adm\" || lastAccess < 1700000000)"),
+            vec![true, false]
+        );
+        assert_eq!(matching("userId > 42 || name =~ 'x'"), vec![false, true]);
+        assert_eq!(matching("user == null"), vec![false, true]);
+        assert_eq!(matching("user != null"), vec![true, false]);
+        assert_eq!(matching("user.admin == true"), vec![true, false]);
+        assert_eq!(matching("!(user.admin == true)"), vec![false, true]);
+        assert_eq!(matching("user.id >= 6.5"), vec![true, false]);
+        assert_eq!(matching("name >= \"b\""), vec![false, true]);
+        assert_eq!(matching("count == 3"), vec![true, false]);
+        // Values of other types and missing fields never compare.
+        assert_eq!(matching("userId == \"42\""), vec![false, false]);
+        assert_eq!(matching("missing != 1"), vec![false, false]);
+
+        let predicate = Predicate::parse("a == 1 || !(b.c < 2 && a > 0)").unwrap();
+        let paths: Vec<String> = predicate.paths().iter().map(|p| p.join(".")).collect();
+        assert_eq!(paths, vec!["a", "b.c"]);
+    }
+}
@@ -0,0 +1,299 @@
+//
+// The protocol buffers wire format, for the messages of the gRPC service
+// (proto/heap_analysis.proto). Messages are read into their fields by
+// number and written field by field; as in proto3, scalars holding their
+// default value are left out.
+//
+use std::convert::TryInto;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Wire<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+// A message read from the wire: its fields in order.
+#[derive(Debug, Default)]
+pub struct Message<'a> {
+    pub fields: Vec<(u32, Wire<'a>)>,
+}
+
+fn varint(bytes: &[u8], pos: &mut usize) -> Result<u64, String> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes.get(*pos).ok_or("truncated varint")?;
+        *pos += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err("varint too long".to_string())
+}
+
+fn take<'a>(bytes: &'a [u8], pos: &mut usize, len: usize) -> Result<&'a [u8], String> {
+    let end = pos.checked_add(len).filter(|&end| end <= bytes.len());
+    let slice = &bytes[*pos..end.ok_or("truncated field")?];
+    *pos += len;
+    Ok(slice)
+}
+
+impl<'a> Message<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Message<'a>, String> {
+        let mut message = Message::default();
+        let mut pos = 0;
+        while pos < bytes.len() {
+            let key = varint(bytes, &mut pos)?;
+            let number = match key >> 3 {
+                0 => return Err("field number 0".to_string()),
+                n if n >= 1 << 29 => return Err(format!("field number {} too large", n)),
+                n => n as u32,
+            };
+            let value = match key & 7 {
+                0 => Wire::Varint(varint(bytes, &mut pos)?),
+                1 => Wire::Fixed64(u64::from_le_bytes(
+                    take(bytes, &mut pos, 8)?.try_into().unwrap(),
+                )),
+                2 => {
+                    let len = varint(bytes, &mut pos)? as usize;
+                    Wire::Bytes(take(bytes, &mut pos, len)?)
+                }
+                5 => Wire::Fixed32(u32::from_le_bytes(
+                    take(bytes, &mut pos, 4)?.try_into().unwrap(),
+                )),
+                wire => return Err(format!("unsupported wire type {}", wire)),
+            };
+            message.fields.push((number, value));
+        }
+        Ok(message)
+    }
+
+    // The last value of a field, as proto3 parsers take it.
+    fn last(&self, number: u32) -> Option<Wire<'a>> {
+        self.fields
+            .iter()
+            .rev()
+            .find(|(n, _)| *n == number)
+            .map(|(_, v)| *v)
+    }
+
+    pub fn uint(&self, number: u32) -> Result<u64, String> {
+        match self.last(number) {
+            None => Ok(0),
+            Some(Wire::Varint(v)) | Some(Wire::Fixed64(v)) => Ok(v),
+            Some(Wire::Fixed32(v)) => Ok(v as u64),
+            Some(Wire::Bytes(_)) => Err(format!("field {} is not an integer", number)),
+        }
+    }
+
+    pub fn bool(&self, number: u32) -> Result<bool, String> {
+        Ok(self.uint(number)? != 0)
+    }
+
+    pub fn string(&self, number: u32) -> Result<String, String> {
+        match self.last(number) {
+            None => Ok(String::new()),
+            Some(Wire::Bytes(bytes)) => String::from_utf8(bytes.to_vec())
+                .map_err(|_| format!("field {} is not UTF-8", number)),
+            Some(_) => Err(format!("field {} is not a string", number)),
+        }
+    }
+
+    pub fn strings(&self, number: u32) -> Result<Vec<String>, String> {
+        self.fields
+            .iter()
+            .filter(|(n, _)| *n == number)
+            .map(|(_, v)| match v {
+                Wire::Bytes(bytes) => String::from_utf8(bytes.to_vec())
+                    .map_err(|_| format!("field {} is not UTF-8", number)),
+                _ => Err(format!("field {} is not a string", number)),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Writer {
+    pub bytes: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Writer {
+        Writer::default()
+    }
+
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.bytes.push((value & 0x7f) as u8 | 0x80);
+            value >>= 7;
+        }
+        self.bytes.push(value as u8);
+    }
+
+    fn key(&mut self, number: u32, wire: u8) {
+        self.varint(((number as u64) << 3) | wire as u64);
+    }
+
+    pub fn uint(&mut self, number: u32, value: u64) -> &mut Writer {
+        if value != 0 {
+            self.key(number, 0);
+            self.varint(value);
+        }
+        self
+    }
+
+    // An int64: negative values take ten bytes, as in the wire format.
+    pub fn int(&mut self, number: u32, value: i64) -> &mut Writer {
+        self.uint(number, value as u64)
+    }
+
+    pub fn bool(&mut self, number: u32, value: bool) -> &mut Writer {
+        self.uint(number, value as u64)
+    }
+
+    pub fn string(&mut self, number: u32, value: &str) -> &mut Writer {
+        if !value.is_empty() {
+            self.bytes_field(number, value.as_bytes());
+        }
+        self
+    }
+
+    fn bytes_field(&mut self, number: u32, bytes: &[u8]) {
+        self.key(number, 2);
+        self.varint(bytes.len() as u64);
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    // An embedded message, written even if empty (it is then present).
+    pub fn message(&mut self, number: u32, message: &Writer) -> &mut Writer {
+        self.bytes_field(number, &message.bytes);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut inner = Writer::new();
+        inner.string(1, "java.lang.String").uint(2, 300);
+        let mut empty = Writer::new();
+        empty.uint(1, 0).string(2, "").bool(3, false);
+        assert!(empty.bytes.is_empty());
+        let mut writer = Writer::new();
+        writer
+            .uint(1, 150)
+            .int(2, -2)
+            .bool(3, true)
+            .string(4, "héllo")
+            .string(5, "a")
+            .string(5, "b")
+            .message(6, &inner)
+            .message(7, &empty)
+            .uint(8, u64::MAX)
+            .uint(1_000_000, 1);
+        assert_eq!(writer.bytes[..3], [0x08, 0x96, 0x01]);
+
+        let message = Message::parse(&writer.bytes).unwrap();
+        assert_eq!(message.uint(1), Ok(150));
+        assert_eq!(message.uint(2).map(|v| v as i64), Ok(-2));
+        assert_eq!(message.bool(3), Ok(true));
+        assert_eq!(message.string(4), Ok("héllo".to_string()));
+        assert_eq!(
+            message.strings(5),
+            Ok(vec!["a".to_string(), "b".to_string()])
+        );
+        assert_eq!(message.string(5), Ok("b".to_string()));
+        match message.last(6) {
+            Some(Wire::Bytes(bytes)) => {
+                let inner = Message::parse(bytes).unwrap();
+                assert_eq!(inner.string(1), Ok("java.lang.String".to_string()));
+                assert_eq!(inner.uint(2), Ok(300));
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(message.last(7), Some(Wire::Bytes(b)) if b.is_empty()));
+        assert_eq!(message.uint(8), Ok(u64::MAX));
+        assert_eq!(message.uint(1_000_000), Ok(1));
+        // Absent fields have their default values.
+        assert_eq!(message.uint(9), Ok(0));
+        assert_eq!(message.string(9), Ok(String::new()));
+        assert_eq!(message.strings(9), Ok(Vec::new()));
+        // And fields of the wrong type are errors.
+        assert_eq!(
+            message.uint(4),
+            Err("field 4 is not an integer".to_string())
+        );
+        assert_eq!(
+            message.string(1),
+            Err("field 1 is not a string".to_string())
+        );
+    }
+
+    #[test]
+    fn fixed_fields() {
+        let bytes = [
+            0x09, 1, 0, 0, 0, 0, 0, 0, 0x80, 0x15, 0xff, 0xff, 0xff, 0xff,
+        ];
+        let message = Message::parse(&bytes).unwrap();
+        assert_eq!(message.uint(1), Ok(1 << 63 | 1));
+        assert_eq!(message.uint(2), Ok(0xffff_ffff));
+    }
+
+    #[test]
+    fn malformed_messages() {
+        let mut writer = Writer::new();
+        writer.string(1, "hello").uint(2, 1 << 40);
+        for len in 1..writer.bytes.len() {
+            if len == 7 {
+                // Between the two fields.
+                continue;
+            }
+            assert!(
+                Message::parse(&writer.bytes[..len]).is_err(),
+                "{} bytes",
+                len
+            );
+        }
+        for (bytes, error) in [
+            (&[0x0a, 0x80][..], "truncated varint"),
+            (&[0x0a, 0x05, b'a'], "truncated field"),
+            (
+                &[0x0a, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f],
+                "truncated field",
+            ),
+            (&[0x09, 1, 2, 3], "truncated field"),
+            (&[0x0d, 1, 2, 3], "truncated field"),
+            (&[0x08; 11], "truncated varint"),
+            (
+                &[
+                    0x08, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01,
+                ],
+                "varint too long",
+            ),
+            (&[0x00, 0x01], "field number 0"),
+            (
+                &[0x80, 0x80, 0x80, 0x80, 0x10, 0x00],
+                "field number 536870912 too large",
+            ),
+            (&[0x0b], "unsupported wire type 3"),
+            (&[0x0c], "unsupported wire type 4"),
+        ] {
+            assert_eq!(
+                Message::parse(bytes).map(|_| ()),
+                Err(error.to_string()),
+                "{:?}",
+                bytes
+            );
+        }
+        let not_utf8 = [0x0a, 0x02, 0xc3, 0x28];
+        assert_eq!(
+            Message::parse(&not_utf8).unwrap().string(1),
+            Err("field 1 is not UTF-8".to_string())
+        );
+    }
+}
@@ -0,0 +1,182 @@
+//
+// Mark phase over the object graph. Unless the dump was taken with
+// `live=true` (which forces a full GC first), HPROF files also contain all
+// the garbage that was on the heap at the time, so it is useful to tell the
+// two apart before drawing any conclusions from sizes.
+//
+// Also a cheaper alternative to retained sizes for one object: the size of
+// everything reachable from it.
+//
+use crate::graph::{outgoing_references, Graph};
+use crate::heap::HeapObject;
+use crate::parallel;
+use crate::snapshot::Snapshot;
+use crate::timings;
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+//
+// Returns for each object whether it is reachable from any GC root. The
+// graph is walked breadth-first, each level split over the cores, with the
+// marks set atomically so that every object is queued only once.
+//
+pub fn mark(graph: &Graph) -> Vec<bool> {
+    let _phase = timings::start("mark");
+    let marked: Vec<AtomicBool> = (0..graph.len()).map(|_| AtomicBool::new(false)).collect();
+    let visit = |node: u32| {
+        let mark = &marked[node as usize];
+        !mark.load(Ordering::Relaxed) && !mark.swap(true, Ordering::Relaxed)
+    };
+    let mut frontier: Vec<u32> = graph.roots.iter().copied().filter(|&r| visit(r)).collect();
+    while !frontier.is_empty() {
+        let next = parallel::map_chunks(frontier.len(), |range| {
+            let mut next = Vec::new();
+            for &node in &frontier[range] {
+                next.extend(graph.successors.neighbors(node).filter(|&s| visit(s)));
+            }
+            next
+        });
+        frontier = next.concat();
+    }
+    marked.into_iter().map(AtomicBool::into_inner).collect()
+}
+
+#[derive(Debug, Default)]
+pub struct DeepSize {
+    pub objects: u64,
+    pub bytes: u64,
+    // Objects of the stop classes that were reached but not counted.
+    pub stopped: u64,
+    // Whether the depth limit cut the traversal short.
+    pub truncated: bool,
+}
+
+//
+// The objects and bytes reachable from one object, without building the
+// whole graph: a breadth-first walk that doesn't enter class objects (or
+// all the statics of the heap would be counted), nor objects of the
+// `stop_classes`, and goes at most `max_depth` references deep.
+//
+pub fn deep_size(
+    snapshot: &Snapshot,
+    start: u32,
+    max_depth: usize,
+    stop_classes: &HashSet<u64>,
+) -> DeepSize {
+    let mut size = DeepSize::default();
+    let mut visited = HashSet::from([start]);
+    let mut current = vec![start];
+    let mut depth = 0;
+    while !current.is_empty() {
+        let mut next = Vec::new();
+        for &node in &current {
+            let object = &snapshot.objects[node as usize];
+            size.objects += 1;
+            size.bytes += snapshot.shallow_size(object);
+            for target in outgoing_references(snapshot, object) {
+                let index = match snapshot.index_of(target) {
+                    Some(index) if !visited.contains(&index) => index,
+                    _ => continue,
+                };
+                let target = &snapshot.objects[index as usize];
+                if let HeapObject::Class(_) = target {
+                    continue;
+                }
+                visited.insert(index);
+                if snapshot
+                    .class_of(target)
+                    .is_some_and(|c| stop_classes.contains(&c))
+                {
+                    size.stopped += 1;
+                } else if depth == max_depth {
+                    size.truncated = true;
+                } else {
+                    next.push(index);
+                }
+            }
+        }
+        current = next;
+        depth += 1;
+    }
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::{FieldTag, GcRootKind, Value};
+    use crate::histogram;
+    use crate::testing::Dump;
+
+    #[test]
+    fn garbage_is_not_marked() {
+        let mut dump = Dump::new();
+        let node = dump.class(
+            "test/Node",
+            dump.object,
+            &[("next", FieldTag::NormalObject)],
+        );
+        let tail = dump.instance(node, &[]);
+        let head = dump.instance(node, &[Value::Object(tail)]);
+        // A cycle nothing refers to, the first node referring to the next
+        // object made.
+        let a = dump.instance(node, &[Value::Object(head + 16)]);
+        dump.instance(node, &[Value::Object(a)]);
+        dump.root(GcRootKind::JniGlobal, head);
+        let snapshot = dump.load();
+        let marked = mark(&Graph::build(&snapshot));
+
+        let live: Vec<u64> = (0..snapshot.objects.len())
+            .filter(|&n| marked[n])
+            .map(|n| snapshot.objects[n].object_id())
+            .collect();
+        assert!(live.contains(&head) && live.contains(&tail));
+        // The classes have no roots in these dumps.
+        let garbage = histogram::histogram(&snapshot, |n| {
+            !marked[n as usize] && snapshot.class_of(&snapshot.objects[n as usize]).is_some()
+        });
+        assert_eq!(garbage.len(), 1);
+        assert_eq!(garbage[0].class_name, "test.Node");
+        assert_eq!(garbage[0].instances, 2);
+    }
+
+    #[test]
+    fn deep_sizes_stop_at_depth_and_classes() {
+        let mut dump = Dump::new();
+        let node = dump.class(
+            "test/Node",
+            dump.object,
+            &[
+                ("next", FieldTag::NormalObject),
+                ("name", FieldTag::NormalObject),
+            ],
+        );
+        let string = dump.string;
+        let bytes = dump.primitive_array(FieldTag::Byte, b"name");
+        let name = dump.instance(string, &[Value::Object(bytes)]);
+        let mut next = 0;
+        let mut nodes = Vec::new();
+        for i in 0..4 {
+            let name = if i == 2 { name } else { 0 };
+            next = dump.instance(node, &[Value::Object(next), Value::Object(name)]);
+            nodes.push(next);
+        }
+        let snapshot = dump.load();
+        let head = snapshot.index_of(next).unwrap();
+        let size = |id| snapshot.shallow_size(snapshot.object(id).unwrap());
+        let nodes_size: u64 = nodes.iter().map(|&n| size(n)).sum();
+        let name_size = size(name) + size(bytes);
+
+        let all = deep_size(&snapshot, head, usize::MAX, &HashSet::new());
+        assert_eq!((all.objects, all.bytes), (6, nodes_size + name_size));
+        assert_eq!((all.stopped, all.truncated), (0, false));
+
+        let stopped = deep_size(&snapshot, head, usize::MAX, &HashSet::from([string]));
+        assert_eq!((stopped.objects, stopped.bytes), (4, nodes_size));
+        assert_eq!((stopped.stopped, stopped.truncated), (1, false));
+
+        let shallow = deep_size(&snapshot, head, 1, &HashSet::new());
+        assert_eq!((shallow.objects, shallow.truncated), (2, true));
+    }
+}
@@ -0,0 +1,546 @@
+//
+// Top-level HPROF records (everything outside of the heap dump bodies).
+// See the references at the top of lib.rs for the format documentation.
+//
+use num_enum::TryFromPrimitive;
+
+use crate::idhash::IdMap;
+
+use std::convert::TryInto;
+use std::io::{self, BufRead, BufReader, Read, Seek};
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, TryFromPrimitive)]
+#[repr(u8)]
+pub enum RecordTag {
+    Utf8String = 0x01,
+    LoadClass = 0x02,
+    UnloadClass = 0x03,
+    StackFrame = 0x04,
+    StackTrace = 0x05,
+    AllocSites = 0x06,
+    HeapSummary = 0x07,
+    StartThread = 0x0A,
+    EndThread = 0x0B,
+    HeapDump = 0x0C,
+    CpuSamples = 0x0D,
+    ControlSettings = 0x0E,
+
+    // 1.0.2 Record Tags
+    HeapDumpSegment = 0x1C,
+    HeapDumpEnd = 0x2C,
+}
+
+pub fn read_u8<R: Read>(reader: &mut R) -> u8 {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf).unwrap();
+    buf[0]
+}
+
+pub fn read_u16<R: Read>(reader: &mut R) -> u16 {
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf).unwrap();
+    u16::from_be_bytes(buf)
+}
+
+pub fn read_u32<R: Read>(reader: &mut R) -> u32 {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf).unwrap();
+    u32::from_be_bytes(buf)
+}
+
+pub fn read_u64<R: Read>(reader: &mut R) -> u64 {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf).unwrap();
+    u64::from_be_bytes(buf)
+}
+
+//
+// Identifiers are either 4 or 8 bytes depending on the identifier size
+// from the header. They are always widened to u64 in memory.
+//
+pub fn read_id<R: Read>(reader: &mut R, id_size: u32) -> u64 {
+    match id_size {
+        4 => read_u32(reader) as u64,
+        8 => read_u64(reader),
+        _ => panic!("unsupported identifier size: {}", id_size),
+    }
+}
+
+// The most identifiers read_ids() reads at once.
+const ID_CHUNK: usize = 1 << 16;
+
+//
+// `count` identifiers in a row, as in object arrays and stack traces, read
+// with a read_exact() per ID_CHUNK of them and byte-swapped afterwards in a
+// loop that the compiler vectorizes. 8-byte ids are read straight into the
+// result. The count comes from the dump, so the result only grows as the
+// ids are there: a garbage count fails once the reader runs out.
+//
+pub fn read_ids<R: Read>(reader: &mut R, id_size: u32, count: usize) -> io::Result<Vec<u64>> {
+    let mut ids: Vec<u64> = Vec::with_capacity(count.min(ID_CHUNK));
+    let mut buf = Vec::new();
+    while ids.len() < count {
+        let start = ids.len();
+        let n = (count - start).min(ID_CHUNK);
+        match id_size {
+            4 => {
+                buf.resize(4 * n, 0);
+                reader.read_exact(&mut buf)?;
+                ids.extend(
+                    buf.chunks_exact(4)
+                        .map(|c| u32::from_be_bytes(c.try_into().unwrap()) as u64),
+                );
+            }
+            8 => {
+                ids.resize(start + n, 0);
+                // SAFETY: any bytes are a valid u64, and the slice covers
+                // exactly the memory of the new ids, which outlive it.
+                let bytes = unsafe {
+                    std::slice::from_raw_parts_mut(ids[start..].as_mut_ptr() as *mut u8, 8 * n)
+                };
+                reader.read_exact(bytes)?;
+                for id in ids[start..].iter_mut() {
+                    *id = u64::from_be(*id);
+                }
+            }
+            _ => panic!("unsupported identifier size: {}", id_size),
+        }
+    }
+    Ok(ids)
+}
+
+#[derive(Debug)]
+pub struct Header {
+    pub format: String,
+    pub identifier_size: u32,
+    pub high_word_ms: u32,
+    pub low_word_ms: u32,
+}
+
+impl Header {
+    // Milliseconds since the epoch when the dump was taken.
+    pub fn timestamp_ms(&self) -> u64 {
+        ((self.high_word_ms as u64) << 32) | self.low_word_ms as u64
+    }
+}
+
+//
+// The header of an HPROF dump: a NUL-terminated "JAVA PROFILE 1.0.x", kept
+// with its NUL, then the identifier size and the time it was taken. Anything
+// else is not a dump we can read, an InvalidData error.
+//
+pub fn parse_header<R: BufRead>(reader: &mut R) -> io::Result<Header> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let mut format_buf = [0u8; 19];
+    let mut u32_buf = [0u8; 4];
+
+    let magic = reader.read_exact(&mut format_buf);
+    if magic.is_err() || !format_buf.starts_with(b"JAVA PROFILE 1.0.") || format_buf[18] != 0 {
+        return Err(invalid("not an HPROF dump".to_string()));
+    }
+    let format = String::from_utf8_lossy(&format_buf).to_string();
+    let mut read_u32 = || {
+        reader
+            .read_exact(&mut u32_buf)
+            .map_err(|_| invalid("the HPROF header is cut short".to_string()))?;
+        Ok::<_, io::Error>(u32::from_be_bytes(u32_buf))
+    };
+    let identifier_size = read_u32()?;
+    let high_word_ms = read_u32()?;
+    let low_word_ms = read_u32()?;
+    if identifier_size != 4 && identifier_size != 8 {
+        return Err(invalid(format!(
+            "unsupported identifier size: {}",
+            identifier_size
+        )));
+    }
+
+    Ok(Header {
+        format,
+        identifier_size,
+        high_word_ms,
+        low_word_ms,
+    })
+}
+
+//
+// The common prefix of every top-level record. The tag is kept raw so that
+// records we don't know about can still be skipped over using their length.
+//
+#[derive(Debug)]
+pub struct Record {
+    pub tag: Option<RecordTag>,
+    pub raw_tag: u8,
+    pub time: u32,
+    pub bytes: u32,
+}
+
+// None at the end of the input, an UnexpectedEof error for a header cut short.
+pub fn parse_record<R: BufRead>(reader: &mut R) -> io::Result<Option<Record>> {
+    let mut buf = [0u8; 9];
+    if reader.read(&mut buf[..1])? == 0 {
+        return Ok(None);
+    }
+    reader.read_exact(&mut buf[1..])?;
+    let raw_tag = buf[0];
+    Ok(Some(Record {
+        tag: RecordTag::try_from_primitive(raw_tag).ok(),
+        raw_tag,
+        time: u32::from_be_bytes(buf[1..5].try_into().unwrap()),
+        bytes: u32::from_be_bytes(buf[5..9].try_into().unwrap()),
+    }))
+}
+
+//
+// The number of top-level records of each (raw) tag from the first record
+// on, and the bytes of their bodies, looking at their headers only.
+//
+pub fn census<R: Read + Seek>(reader: &mut BufReader<R>) -> IdMap<u8, (u64, u64)> {
+    let mut counts: IdMap<u8, (u64, u64)> = IdMap::default();
+    // A dump cut short is found so by the reader of its records.
+    while let Ok(Some(record)) = parse_record(reader) {
+        let entry = counts.entry(record.raw_tag).or_insert((0, 0));
+        entry.0 += 1;
+        entry.1 += record.bytes as u64;
+        reader.seek_relative(record.bytes as i64).unwrap();
+    }
+    counts
+}
+
+pub fn skip_bytes<R: BufRead>(reader: &mut R, bytes: u64) {
+    let skipped = std::io::copy(&mut reader.take(bytes), &mut std::io::sink()).unwrap();
+    assert_eq!(skipped, bytes, "unexpected end of file");
+}
+
+#[derive(Debug)]
+pub struct Utf8StringRecord {
+    pub identifier: u64,
+    pub value: String,
+}
+
+// A record shorter than its id is an error, before anything of it is read.
+pub fn parse_utf8_string_record<R: BufRead>(
+    reader: &mut R,
+    id_size: u32,
+    bytes: usize,
+) -> Result<Utf8StringRecord, String> {
+    let len = bytes
+        .checked_sub(id_size as usize)
+        .ok_or_else(|| format!("string record of {} bytes, shorter than an id", bytes))?;
+    let identifier = read_id(reader, id_size);
+
+    let mut value_buf = vec![0; len];
+    reader.read_exact(&mut value_buf).unwrap();
+    let value = String::from_utf8_lossy(&value_buf).to_string();
+
+    Ok(Utf8StringRecord { identifier, value })
+}
+
+#[derive(Debug)]
+pub struct LoadClassRecord {
+    pub serial_num: u32,
+    pub object_id: u64,
+    pub strace_num: u32,
+    pub strname_id: u64,
+}
+
+pub fn parse_load_class_record<R: BufRead>(reader: &mut R, id_size: u32) -> LoadClassRecord {
+    let serial_num = read_u32(reader);
+    let object_id = read_id(reader, id_size);
+    let strace_num = read_u32(reader);
+    let strname_id = read_id(reader, id_size);
+
+    LoadClassRecord {
+        serial_num,
+        object_id,
+        strace_num,
+        strname_id,
+    }
+}
+
+#[derive(Debug)]
+pub struct UnloadClassRecord {
+    pub serial_num: u32,
+}
+
+pub fn parse_unload_class_record<R: BufRead>(reader: &mut R) -> UnloadClassRecord {
+    let serial_num = read_u32(reader);
+    UnloadClassRecord { serial_num }
+}
+
+#[derive(Debug)]
+pub struct StackFrameRecord {
+    pub frame_id: u64,
+    pub method_name_id: u64,
+    pub method_sign_id: u64,
+    pub source_name_id: u64,
+    pub class_serial_num: u32,
+    pub line_num: i32,
+}
+
+pub fn parse_stack_frame_record<R: BufRead>(reader: &mut R, id_size: u32) -> StackFrameRecord {
+    let frame_id = read_id(reader, id_size);
+    let method_name_id = read_id(reader, id_size);
+    let method_sign_id = read_id(reader, id_size);
+    let source_name_id = read_id(reader, id_size);
+    let class_serial_num = read_u32(reader);
+    let line_num = read_u32(reader) as i32;
+
+    StackFrameRecord {
+        frame_id,
+        method_name_id,
+        method_sign_id,
+        source_name_id,
+        class_serial_num,
+        line_num,
+    }
+}
+
+#[derive(Debug)]
+pub struct StackTraceRecord {
+    pub serial_num: u32,
+    pub thread_serial_num: u32,
+    pub nframes: u32,
+    pub frame_ids: Vec<u64>,
+}
+
+pub fn parse_stack_trace_record<R: BufRead>(reader: &mut R, id_size: u32) -> StackTraceRecord {
+    let serial_num = read_u32(reader);
+    let thread_serial_num = read_u32(reader);
+    let nframes = read_u32(reader);
+
+    let frame_ids = read_ids(reader, id_size, nframes as usize).unwrap();
+
+    StackTraceRecord {
+        serial_num,
+        thread_serial_num,
+        nframes,
+        frame_ids,
+    }
+}
+
+//
+// ALLOC_SITES records are only written by the old HPROF agent (with
+// heap=sites), HotSpot's dumper never emits them.
+//
+#[derive(Debug)]
+pub struct AllocSitesRecord {
+    pub flags: u16,
+    pub cutoff_ratio: f32,
+    pub total_live_bytes: u32,
+    pub total_live_instances: u32,
+    pub total_bytes_allocated: u64,
+    pub total_instances_allocated: u64,
+    pub sites: Vec<AllocSite>,
+}
+
+#[derive(Debug)]
+pub struct AllocSite {
+    // 0 for normal objects, otherwise the element type of arrays.
+    pub array_indicator: u8,
+    pub class_serial_num: u32,
+    pub strace_num: u32,
+    pub live_bytes: u32,
+    pub live_instances: u32,
+    pub bytes_allocated: u32,
+    pub instances_allocated: u32,
+}
+
+pub fn parse_alloc_sites_record<R: BufRead>(reader: &mut R) -> AllocSitesRecord {
+    let flags = read_u16(reader);
+    let cutoff_ratio = f32::from_bits(read_u32(reader));
+    let total_live_bytes = read_u32(reader);
+    let total_live_instances = read_u32(reader);
+    let total_bytes_allocated = read_u64(reader);
+    let total_instances_allocated = read_u64(reader);
+    let nsites = read_u32(reader);
+
+    let sites = (0..nsites)
+        .map(|_| AllocSite {
+            array_indicator: read_u8(reader),
+            class_serial_num: read_u32(reader),
+            strace_num: read_u32(reader),
+            live_bytes: read_u32(reader),
+            live_instances: read_u32(reader),
+            bytes_allocated: read_u32(reader),
+            instances_allocated: read_u32(reader),
+        })
+        .collect();
+
+    AllocSitesRecord {
+        flags,
+        cutoff_ratio,
+        total_live_bytes,
+        total_live_instances,
+        total_bytes_allocated,
+        total_instances_allocated,
+        sites,
+    }
+}
+
+//
+// Older JDKs (and some agents) emit START_THREAD records. Recent HotSpot
+// versions only describe threads through ROOT_THREAD_OBJECT sub-records.
+//
+#[derive(Debug)]
+pub struct StartThreadRecord {
+    pub thread_serial_num: u32,
+    pub thread_object_id: u64,
+    pub strace_num: u32,
+    pub thread_name_id: u64,
+    pub group_name_id: u64,
+    pub parent_group_name_id: u64,
+}
+
+pub fn parse_start_thread_record<R: BufRead>(reader: &mut R, id_size: u32) -> StartThreadRecord {
+    let thread_serial_num = read_u32(reader);
+    let thread_object_id = read_id(reader, id_size);
+    let strace_num = read_u32(reader);
+    let thread_name_id = read_id(reader, id_size);
+    let group_name_id = read_id(reader, id_size);
+    let parent_group_name_id = read_id(reader, id_size);
+
+    StartThreadRecord {
+        thread_serial_num,
+        thread_object_id,
+        strace_num,
+        thread_name_id,
+        group_name_id,
+        parent_group_name_id,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::GcRootKind;
+    use crate::snapshot::Snapshot;
+    use crate::testing::{Dump, TempFile};
+    use std::io::Cursor;
+
+    #[test]
+    fn alloc_sites_are_parsed_site_by_site() {
+        let mut body = Vec::new();
+        body.extend(1u16.to_be_bytes());
+        body.extend(0.5f32.to_bits().to_be_bytes());
+        body.extend(600u32.to_be_bytes());
+        body.extend(6u32.to_be_bytes());
+        body.extend(1000u64.to_be_bytes());
+        body.extend(10u64.to_be_bytes());
+        body.extend(2u32.to_be_bytes());
+        for (indicator, class, trace, live) in [(0u8, 3u32, 7u32, 400u32), (8, 0, 9, 200)] {
+            body.push(indicator);
+            for value in [class, trace, live, 4, live * 2, 8] {
+                body.extend(value.to_be_bytes());
+            }
+        }
+
+        let record = parse_alloc_sites_record(&mut &body[..]);
+        assert_eq!(record.flags, 1);
+        assert_eq!(record.cutoff_ratio, 0.5);
+        assert_eq!(
+            (record.total_live_bytes, record.total_live_instances),
+            (600, 6)
+        );
+        assert_eq!(record.total_bytes_allocated, 1000);
+        assert_eq!(record.total_instances_allocated, 10);
+        let sites: Vec<(u8, u32, u32, u32, u32, u32, u32)> = record
+            .sites
+            .iter()
+            .map(|s| {
+                (
+                    s.array_indicator,
+                    s.class_serial_num,
+                    s.strace_num,
+                    s.live_bytes,
+                    s.live_instances,
+                    s.bytes_allocated,
+                    s.instances_allocated,
+                )
+            })
+            .collect();
+        assert_eq!(
+            sites,
+            vec![(0, 3, 7, 400, 4, 800, 8), (8, 0, 9, 200, 4, 400, 8)]
+        );
+    }
+
+    #[test]
+    fn string_records_shorter_than_an_id_are_refused() {
+        let bytes = [0u8, 0, 0, 0, 0, 0, 0, 1, b'a'];
+        let r = parse_utf8_string_record(&mut &bytes[..], 8, 9).unwrap();
+        assert_eq!((r.identifier, r.value.as_str()), (1, "a"));
+        assert_eq!(
+            parse_utf8_string_record(&mut &bytes[..], 8, 4).unwrap_err(),
+            "string record of 4 bytes, shorter than an id"
+        );
+    }
+
+    #[test]
+    fn ids_read_in_bulk_as_one_by_one() {
+        let bytes: Vec<u8> = (0..=255u8).cycle().take(8 * 100 + 3).collect();
+        for id_size in [4, 8] {
+            let count = 100;
+            let mut reader = &bytes[..];
+            let bulk = read_ids(&mut reader, id_size, count).unwrap();
+            assert_eq!(reader.len(), bytes.len() - id_size as usize * count);
+            let mut reader = &bytes[..];
+            let single: Vec<u64> = (0..count).map(|_| read_id(&mut reader, id_size)).collect();
+            assert_eq!(bulk, single);
+        }
+        assert_eq!(read_ids(&mut &[0u8, 0, 0, 1][..], 4, 1).unwrap(), vec![1]);
+        assert_eq!(read_ids(&mut &[][..], 8, 0).unwrap(), Vec::<u64>::new());
+        // More than there are, in several chunks.
+        let error = read_ids(&mut &bytes[..], 8, 3 * ID_CHUNK).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn census_counts_the_records_of_each_tag() {
+        let mut dump = Dump::new();
+        dump.set_segment_bytes(256);
+        let frame = dump.frame(dump.object, "run", "()V", 1);
+        dump.trace(1, &[frame]);
+        for i in 0..20 {
+            let id = dump.string(&format!("text {}", i));
+            dump.root(GcRootKind::JniGlobal, id);
+        }
+        let bytes = dump.bytes();
+        let snapshot = Snapshot::load(TempFile::new(&bytes).path());
+        let mut reader = BufReader::new(Cursor::new(&bytes[..]));
+        parse_header(&mut reader).unwrap();
+
+        let census = census(&mut reader);
+        let counts: IdMap<u8, u64> = census.iter().map(|(&tag, c)| (tag, c.0)).collect();
+        assert_eq!(counts, snapshot.record_counts);
+        assert!(counts[&(RecordTag::HeapDumpSegment as u8)] > 1);
+        let strings = census[&(RecordTag::Utf8String as u8)];
+        let text: u64 = snapshot.strings.iter().map(|(_, s)| s.len() as u64).sum();
+        assert_eq!(strings.1, text + 8 * strings.0);
+    }
+
+    #[test]
+    fn headers_of_other_files_are_errors() {
+        let bytes = Dump::new().bytes();
+        let header = parse_header(&mut &bytes[..]).unwrap();
+        assert_eq!(header.format, "JAVA PROFILE 1.0.2\0");
+        assert_eq!(header.identifier_size, 8);
+
+        let error = |bytes: &[u8]| parse_header(&mut &bytes[..]).unwrap_err().to_string();
+        assert_eq!(
+            error(b"not a dump at all, just some notes"),
+            "not an HPROF dump"
+        );
+        assert_eq!(error(&bytes[..12]), "not an HPROF dump");
+        assert_eq!(error(&bytes[..25]), "the HPROF header is cut short");
+        let mut wide = bytes[..31].to_vec();
+        wide[22] = 16;
+        assert_eq!(error(&wide), "unsupported identifier size: 16");
+
+        // A record header cut short.
+        let mut reader = &bytes[31..35];
+        let error = parse_record(&mut reader).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(parse_record(&mut &[][..]).unwrap().is_none());
+    }
+}
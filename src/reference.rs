@@ -0,0 +1,284 @@
+//
+// Support for java.lang.ref.Reference and its subclasses. The `referent`
+// field of a Reference does not keep its target alive the way a normal
+// field does, so analyses can choose to ignore those edges.
+//
+use crate::graph::{Graph, Via};
+use crate::heap::HeapObject;
+use crate::reachability;
+use crate::snapshot::Snapshot;
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum ReferenceKind {
+    Weak,
+    Soft,
+    Phantom,
+    // FinalReference, i.e. java.lang.ref.Finalizer.
+    Final,
+}
+
+impl ReferenceKind {
+    pub fn parse(name: &str) -> Option<ReferenceKind> {
+        match name {
+            "weak" => Some(ReferenceKind::Weak),
+            "soft" => Some(ReferenceKind::Soft),
+            "phantom" => Some(ReferenceKind::Phantom),
+            "final" | "finalizer" => Some(ReferenceKind::Final),
+            _ => None,
+        }
+    }
+
+    pub fn all() -> Vec<ReferenceKind> {
+        vec![
+            ReferenceKind::Weak,
+            ReferenceKind::Soft,
+            ReferenceKind::Phantom,
+            ReferenceKind::Final,
+        ]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ReferenceKind::Weak => "weak",
+            ReferenceKind::Soft => "soft",
+            ReferenceKind::Phantom => "phantom",
+            ReferenceKind::Final => "final",
+        }
+    }
+
+    fn from_class_name(name: &str) -> Option<ReferenceKind> {
+        match name {
+            "java.lang.ref.WeakReference" => Some(ReferenceKind::Weak),
+            "java.lang.ref.SoftReference" => Some(ReferenceKind::Soft),
+            "java.lang.ref.PhantomReference" => Some(ReferenceKind::Phantom),
+            "java.lang.ref.FinalReference" => Some(ReferenceKind::Final),
+            _ => None,
+        }
+    }
+}
+
+// The kind of reference for every class that extends one of the above.
+pub fn reference_classes(snapshot: &Snapshot) -> HashMap<u64, ReferenceKind> {
+    let mut classes = HashMap::new();
+    for object in &snapshot.objects {
+        if let HeapObject::Class(c) = object {
+            let mut class_id = c.class_id;
+            while let Some(class) = snapshot.class_dump(class_id) {
+                if let Some(kind) = ReferenceKind::from_class_name(&snapshot.class_name(class_id)) {
+                    classes.insert(c.class_id, kind);
+                    break;
+                }
+                class_id = class.super_class_id;
+            }
+        }
+    }
+    classes
+}
+
+//
+// Decides which references keep their targets alive, given the kinds of
+// java.lang.ref references that should be treated as not retaining.
+//
+pub struct RetentionFilter {
+    classes: HashMap<u64, ReferenceKind>,
+    excluded: Vec<ReferenceKind>,
+}
+
+impl RetentionFilter {
+    pub fn new(snapshot: &Snapshot, excluded: Vec<ReferenceKind>) -> RetentionFilter {
+        let classes = if excluded.is_empty() {
+            HashMap::new()
+        } else {
+            reference_classes(snapshot)
+        };
+        RetentionFilter { classes, excluded }
+    }
+
+    // The reference kind of an object if it is one of the excluded ones.
+    pub fn excluded_kind(&self, object: &HeapObject) -> Option<ReferenceKind> {
+        match object {
+            HeapObject::Instance(i) => self
+                .classes
+                .get(&i.class_id)
+                .copied()
+                .filter(|kind| self.excluded.contains(kind)),
+            _ => None,
+        }
+    }
+
+    pub fn retains(&self, snapshot: &Snapshot, object: &HeapObject, via: Via) -> bool {
+        match via {
+            Via::Field(_, name_id) => {
+                self.excluded_kind(object).is_none() || snapshot.string(name_id) != "referent"
+            }
+            _ => true,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.excluded.is_empty()
+    }
+}
+
+//
+// Parses a comma separated list of reference kinds (or `all`) as given to
+// the --exclude options of the commands.
+//
+pub fn parse_kinds(list: &str) -> Result<Vec<ReferenceKind>, String> {
+    if list == "all" {
+        return Ok(ReferenceKind::all());
+    }
+    list.split(',')
+        .map(|name| {
+            ReferenceKind::parse(name).ok_or_else(|| format!("unknown reference kind: {}", name))
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct ReferenceClass {
+    pub class_name: String,
+    pub kind: ReferenceKind,
+    pub instances: u64,
+    // Instances whose referent hasn't been cleared.
+    pub with_referent: u64,
+    pub shallow: u64,
+}
+
+// The instances of every Reference subclass, sorted by number of instances.
+pub fn reference_instances(snapshot: &Snapshot) -> Vec<ReferenceClass> {
+    let classes = reference_classes(snapshot);
+    let mut report: HashMap<u64, ReferenceClass> = HashMap::new();
+    for object in &snapshot.objects {
+        let instance = match object {
+            HeapObject::Instance(i) => i,
+            _ => continue,
+        };
+        let kind = match classes.get(&instance.class_id) {
+            Some(&kind) => kind,
+            None => continue,
+        };
+        let entry = report
+            .entry(instance.class_id)
+            .or_insert_with(|| ReferenceClass {
+                class_name: snapshot.class_name(instance.class_id),
+                kind,
+                instances: 0,
+                with_referent: 0,
+                shallow: 0,
+            });
+        entry.instances += 1;
+        entry.shallow += snapshot.shallow_size(object);
+        if snapshot
+            .field_value(instance, "referent")
+            .and_then(|v| v.as_object())
+            .is_some_and(|id| id != 0)
+        {
+            entry.with_referent += 1;
+        }
+    }
+    let mut report: Vec<ReferenceClass> = report.into_values().collect();
+    report.sort_by(|a, b| {
+        b.instances
+            .cmp(&a.instances)
+            .then_with(|| a.class_name.cmp(&b.class_name))
+    });
+    report
+}
+
+//
+// The number and shallow bytes of the objects that are reachable, but not
+// any more once the references of the given kinds stop retaining their
+// referents. `reachable` is the result of reachability::mark() on the full
+// graph.
+//
+pub fn only_reachable_through(
+    snapshot: &Snapshot,
+    reachable: &[bool],
+    kinds: Vec<ReferenceKind>,
+) -> (u64, u64) {
+    let filter = RetentionFilter::new(snapshot, kinds);
+    let strongly = reachability::mark(&Graph::build_filtered(snapshot, &filter));
+    let mut total = (0, 0);
+    for (node, object) in snapshot.objects.iter().enumerate() {
+        if reachable[node] && !strongly[node] {
+            total.0 += 1;
+            total.1 += snapshot.shallow_size(object);
+        }
+    }
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::{FieldTag, GcRootKind, Value};
+    use crate::testing::Dump;
+
+    #[test]
+    fn kinds_are_parsed_from_lists() {
+        assert_eq!(
+            parse_kinds("weak,finalizer"),
+            Ok(vec![ReferenceKind::Weak, ReferenceKind::Final])
+        );
+        assert_eq!(parse_kinds("all"), Ok(ReferenceKind::all()));
+        assert!(parse_kinds("weak,strong").is_err());
+    }
+
+    #[test]
+    fn references_are_counted_and_excluded_by_kind() {
+        let mut dump = Dump::new();
+        let reference = dump.class(
+            "java/lang/ref/Reference",
+            dump.object,
+            &[("referent", FieldTag::NormalObject)],
+        );
+        let weak = dump.class("java/lang/ref/WeakReference", reference, &[]);
+        let soft = dump.class("java/lang/ref/SoftReference", reference, &[]);
+        let cache = dump.class("test/CacheEntry", soft, &[]);
+        let weakly = dump.string("weakly held");
+        let strongly = dump.string("strongly held");
+        for (class, referent) in [(weak, weakly), (weak, 0), (cache, strongly)] {
+            let reference = dump.instance(class, &[Value::Object(referent)]);
+            dump.root(GcRootKind::JniGlobal, reference);
+        }
+        dump.root(GcRootKind::JniGlobal, strongly);
+        let snapshot = dump.load();
+
+        let classes = reference_classes(&snapshot);
+        assert_eq!(classes[&cache], ReferenceKind::Soft);
+        assert!(!classes.contains_key(&reference));
+        let report = reference_instances(&snapshot);
+        let found: Vec<(&str, ReferenceKind, u64, u64)> = report
+            .iter()
+            .map(|c| (c.class_name.as_str(), c.kind, c.instances, c.with_referent))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("java.lang.ref.WeakReference", ReferenceKind::Weak, 2, 1),
+                ("test.CacheEntry", ReferenceKind::Soft, 1, 1),
+            ]
+        );
+
+        // The weakly held String and its byte[].
+        let reachable = reachability::mark(&Graph::build(&snapshot));
+        let size = |id| snapshot.shallow_size(snapshot.object(id).unwrap());
+        let array = match snapshot.object(weakly) {
+            Some(HeapObject::Instance(i)) => snapshot.field_value(i, "value").unwrap(),
+            _ => unreachable!(),
+        }
+        .as_object()
+        .unwrap();
+        assert_eq!(
+            only_reachable_through(&snapshot, &reachable, ReferenceKind::all()),
+            (2, size(weakly) + size(array))
+        );
+        assert_eq!(
+            only_reachable_through(&snapshot, &reachable, vec![ReferenceKind::Soft]),
+            (0, 0)
+        );
+    }
+}
@@ -0,0 +1,650 @@
+//
+// A small regular expression engine for searching heap contents.
+//
+// Supported syntax: literals, `.`, character classes (`[a-z_]`, `[^0-9]`),
+// the escapes `\d \D \w \W \s \S \b` (plus escaped metacharacters), groups,
+// alternation, the quantifiers `* + ? {n} {n,} {n,m}` (and their lazy `?`
+// variants), the anchors `^ $` and a leading `(?i)` for case-insensitive
+// matching.
+//
+// Patterns are compiled to a small program that is run by a backtracking
+// matcher which remembers the (instruction, position) states that already
+// failed, so a search is linear in the size of the text times the size of
+// the program instead of exponential in the worst case. As these states take
+// a bit each, repetition counts and the size of the program are bounded.
+//
+
+// The largest count of a `{n,m}` repetition, and the largest program.
+const MAX_REPEAT: u32 = 1000;
+const MAX_PROGRAM: usize = 2048;
+
+#[derive(Debug, Clone)]
+enum Node {
+    Empty,
+    Char(char),
+    Any,
+    Class(Vec<(char, char)>, bool),
+    Bol,
+    Eol,
+    WordBoundary,
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat(Box<Node>, u32, Option<u32>, bool),
+}
+
+#[derive(Debug, Clone)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(Vec<(char, char)>, bool),
+    Bol,
+    Eol,
+    WordBoundary,
+    Split(usize, usize),
+    Jmp(usize),
+    Match,
+}
+
+#[derive(Debug, Clone)]
+pub struct Regex {
+    prog: Vec<Inst>,
+    case_insensitive: bool,
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+const DIGIT: &[(char, char)] = &[('0', '9')];
+const WORD: &[(char, char)] = &[('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')];
+const SPACE: &[(char, char)] = &[('\t', '\r'), (' ', ' ')];
+
+impl<'a> Parser<'a> {
+    fn parse_alternate(&mut self) -> Result<Node, String> {
+        let mut branches = vec![self.parse_concat()?];
+        while self.chars.peek() == Some(&'|') {
+            self.chars.next();
+            branches.push(self.parse_concat()?);
+        }
+        Ok(if branches.len() == 1 {
+            branches.pop().unwrap()
+        } else {
+            Node::Alternate(branches)
+        })
+    }
+
+    fn parse_concat(&mut self) -> Result<Node, String> {
+        let mut nodes = Vec::new();
+        while let Some(&c) = self.chars.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.parse_atom()?;
+            nodes.push(self.parse_quantifier(atom)?);
+        }
+        Ok(match nodes.len() {
+            0 => Node::Empty,
+            1 => nodes.pop().unwrap(),
+            _ => Node::Concat(nodes),
+        })
+    }
+
+    fn parse_number(&mut self) -> Option<u32> {
+        let mut digits = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if !c.is_ascii_digit() {
+                break;
+            }
+            digits.push(c);
+            self.chars.next();
+        }
+        digits.parse().ok()
+    }
+
+    fn parse_quantifier(&mut self, atom: Node) -> Result<Node, String> {
+        let (min, max) = match self.chars.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => {
+                self.chars.next();
+                let min = self.parse_number().ok_or("expected a number after '{'")?;
+                let max = match self.chars.next() {
+                    Some('}') => Some(min),
+                    Some(',') => {
+                        let max = self.parse_number();
+                        if self.chars.next() != Some('}') {
+                            return Err("expected '}'".to_string());
+                        }
+                        max
+                    }
+                    _ => return Err("expected ',' or '}'".to_string()),
+                };
+                if max.is_some_and(|max| max < min) {
+                    return Err("invalid repetition range".to_string());
+                }
+                if max.unwrap_or(min) > MAX_REPEAT {
+                    return Err(format!("repetition count over {}", MAX_REPEAT));
+                }
+                let greedy = !self.eat('?');
+                return self.parse_quantifier(Node::Repeat(Box::new(atom), min, max, greedy));
+            }
+            _ => return Ok(atom),
+        };
+        self.chars.next();
+        let greedy = !self.eat('?');
+        Ok(Node::Repeat(Box::new(atom), min, max, greedy))
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.chars.peek() == Some(&c) {
+            self.chars.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_atom(&mut self) -> Result<Node, String> {
+        match self.chars.next().unwrap() {
+            '(' => {
+                // Non-capturing groups are the same as groups here.
+                if self.eat('?') && !self.eat(':') {
+                    return Err("unsupported group syntax".to_string());
+                }
+                let node = self.parse_alternate()?;
+                if !self.eat(')') {
+                    return Err("missing ')'".to_string());
+                }
+                Ok(node)
+            }
+            '.' => Ok(Node::Any),
+            '^' => Ok(Node::Bol),
+            '$' => Ok(Node::Eol),
+            '[' => self.parse_class(),
+            '\\' => self.parse_escape(),
+            c @ ('*' | '+' | '?' | '{') => Err(format!("nothing to repeat before '{}'", c)),
+            c => Ok(Node::Char(c)),
+        }
+    }
+
+    fn parse_escape(&mut self) -> Result<Node, String> {
+        let c = self.chars.next().ok_or("trailing backslash")?;
+        Ok(match c {
+            'd' => Node::Class(DIGIT.to_vec(), false),
+            'D' => Node::Class(DIGIT.to_vec(), true),
+            'w' => Node::Class(WORD.to_vec(), false),
+            'W' => Node::Class(WORD.to_vec(), true),
+            's' => Node::Class(SPACE.to_vec(), false),
+            'S' => Node::Class(SPACE.to_vec(), true),
+            'b' => Node::WordBoundary,
+            'n' => Node::Char('\n'),
+            'r' => Node::Char('\r'),
+            't' => Node::Char('\t'),
+            c if c.is_ascii_alphanumeric() => return Err(format!("unknown escape \\{}", c)),
+            c => Node::Char(c),
+        })
+    }
+
+    fn parse_class_char(&mut self) -> Result<char, String> {
+        match self.chars.next() {
+            Some('\\') => match self.chars.next() {
+                Some('n') => Ok('\n'),
+                Some('r') => Ok('\r'),
+                Some('t') => Ok('\t'),
+                Some(c) => Ok(c),
+                None => Err("trailing backslash".to_string()),
+            },
+            Some(c) => Ok(c),
+            None => Err("missing ']'".to_string()),
+        }
+    }
+
+    fn parse_class(&mut self) -> Result<Node, String> {
+        let negated = self.eat('^');
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            match self.chars.peek() {
+                None => return Err("missing ']'".to_string()),
+                Some(']') if !first => {
+                    self.chars.next();
+                    break;
+                }
+                Some('\\') => {
+                    let mut lookahead = self.chars.clone();
+                    lookahead.next();
+                    let class = match lookahead.next() {
+                        Some('d') => Some(DIGIT),
+                        Some('w') => Some(WORD),
+                        Some('s') => Some(SPACE),
+                        _ => None,
+                    };
+                    if let Some(class) = class {
+                        self.chars.next();
+                        self.chars.next();
+                        ranges.extend_from_slice(class);
+                        first = false;
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+            first = false;
+            let lo = self.parse_class_char()?;
+            let mut lookahead = self.chars.clone();
+            if lookahead.next() == Some('-') && lookahead.peek().is_some_and(|&c| c != ']') {
+                self.chars.next();
+                let hi = self.parse_class_char()?;
+                if hi < lo {
+                    return Err("invalid class range".to_string());
+                }
+                ranges.push((lo, hi));
+            } else {
+                ranges.push((lo, lo));
+            }
+        }
+        Ok(Node::Class(ranges, negated))
+    }
+}
+
+// The number of instructions compile() makes of a node, saturating.
+fn program_size(node: &Node) -> usize {
+    match node {
+        Node::Empty => 0,
+        Node::Concat(nodes) => nodes
+            .iter()
+            .fold(0, |size, n| size.saturating_add(program_size(n))),
+        Node::Alternate(branches) => branches.iter().fold(2 * (branches.len() - 1), |size, n| {
+            size.saturating_add(program_size(n))
+        }),
+        Node::Repeat(inner, min, max, _) => {
+            let inner = program_size(inner);
+            let rest = match max {
+                None => inner.saturating_add(2),
+                Some(max) => ((max - min) as usize).saturating_mul(inner.saturating_add(1)),
+            };
+            (*min as usize).saturating_mul(inner).saturating_add(rest)
+        }
+        _ => 1,
+    }
+}
+
+fn compile(node: &Node, prog: &mut Vec<Inst>) {
+    match node {
+        Node::Empty => {}
+        Node::Char(c) => prog.push(Inst::Char(*c)),
+        Node::Any => prog.push(Inst::Any),
+        Node::Class(ranges, negated) => prog.push(Inst::Class(ranges.clone(), *negated)),
+        Node::Bol => prog.push(Inst::Bol),
+        Node::Eol => prog.push(Inst::Eol),
+        Node::WordBoundary => prog.push(Inst::WordBoundary),
+        Node::Concat(nodes) => {
+            for n in nodes {
+                compile(n, prog);
+            }
+        }
+        Node::Alternate(branches) => {
+            let mut jumps = Vec::new();
+            for (i, branch) in branches.iter().enumerate() {
+                if i + 1 < branches.len() {
+                    let split = prog.len();
+                    prog.push(Inst::Split(split + 1, 0));
+                    compile(branch, prog);
+                    jumps.push(prog.len());
+                    prog.push(Inst::Jmp(0));
+                    let next = prog.len();
+                    prog[split] = Inst::Split(split + 1, next);
+                } else {
+                    compile(branch, prog);
+                }
+            }
+            let end = prog.len();
+            for j in jumps {
+                prog[j] = Inst::Jmp(end);
+            }
+        }
+        Node::Repeat(inner, min, max, greedy) => {
+            let split = |prog: &mut Vec<Inst>, at: usize, body: usize, out: usize| {
+                prog[at] = if *greedy {
+                    Inst::Split(body, out)
+                } else {
+                    Inst::Split(out, body)
+                };
+            };
+            for _ in 0..*min {
+                compile(inner, prog);
+            }
+            match max {
+                None => {
+                    let start = prog.len();
+                    prog.push(Inst::Jmp(0));
+                    compile(inner, prog);
+                    prog.push(Inst::Jmp(start));
+                    let end = prog.len();
+                    split(prog, start, start + 1, end);
+                }
+                Some(max) => {
+                    let mut splits = Vec::new();
+                    for _ in *min..*max {
+                        splits.push(prog.len());
+                        prog.push(Inst::Jmp(0));
+                        compile(inner, prog);
+                    }
+                    let end = prog.len();
+                    for s in splits {
+                        split(prog, s, s + 1, end);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+//
+// Texts longer than this are searched in overlapping windows to bound the
+// memory used to remember failed states. Matches longer than the overlap
+// that cross a window boundary can be missed.
+//
+const WINDOW: usize = 1 << 20;
+const OVERLAP: usize = 1 << 16;
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, String> {
+        let (case_insensitive, pattern) = match pattern.strip_prefix("(?i)") {
+            Some(rest) => (true, rest),
+            None => (false, pattern),
+        };
+        let mut parser = Parser {
+            chars: pattern.chars().peekable(),
+        };
+        let node = parser.parse_alternate()?;
+        if parser.chars.next().is_some() {
+            return Err("unmatched ')'".to_string());
+        }
+        if program_size(&node) >= MAX_PROGRAM {
+            return Err(format!("pattern over {} instructions", MAX_PROGRAM));
+        }
+        let mut prog = Vec::new();
+        compile(&node, &mut prog);
+        prog.push(Inst::Match);
+        Ok(Regex {
+            prog,
+            case_insensitive,
+        })
+    }
+
+    fn char_matches(&self, c: char, expected: char) -> bool {
+        c == expected || (self.case_insensitive && c.to_lowercase().eq(expected.to_lowercase()))
+    }
+
+    fn class_matches(&self, c: char, ranges: &[(char, char)], negated: bool) -> bool {
+        let in_class = |c: char| ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi);
+        let mut found = in_class(c);
+        if !found && self.case_insensitive {
+            found = c.to_lowercase().any(in_class) || c.to_uppercase().any(in_class);
+        }
+        found != negated
+    }
+
+    //
+    // Runs the program from `start`, returning the end of the match. States
+    // that fail are recorded in `failed` (indexed by instruction and
+    // position relative to `base`) so that they're never explored again.
+    // The states explored by a search that succeeds are recorded in `trail`
+    // so that the caller can forget them.
+    //
+    fn run(
+        &self,
+        text: &[char],
+        base: usize,
+        start: usize,
+        failed: &mut [u64],
+        trail: &mut Vec<usize>,
+    ) -> Option<usize> {
+        let width = text.len() - base + 1;
+        let mut stack = vec![(0usize, start)];
+        while let Some((mut pc, mut pos)) = stack.pop() {
+            loop {
+                let state = pc * width + (pos - base);
+                if failed[state / 64] & (1 << (state % 64)) != 0 {
+                    break;
+                }
+                failed[state / 64] |= 1 << (state % 64);
+                trail.push(state);
+
+                let next = text.get(pos).copied();
+                match &self.prog[pc] {
+                    Inst::Match => return Some(pos),
+                    Inst::Char(expected) => match next {
+                        Some(c) if self.char_matches(c, *expected) => {
+                            pc += 1;
+                            pos += 1;
+                        }
+                        _ => break,
+                    },
+                    Inst::Any => match next {
+                        Some(c) if c != '\n' => {
+                            pc += 1;
+                            pos += 1;
+                        }
+                        _ => break,
+                    },
+                    Inst::Class(ranges, negated) => match next {
+                        Some(c) if self.class_matches(c, ranges, *negated) => {
+                            pc += 1;
+                            pos += 1;
+                        }
+                        _ => break,
+                    },
+                    Inst::Bol => {
+                        if pos == 0 || text[pos - 1] == '\n' {
+                            pc += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                    Inst::Eol => {
+                        if next.is_none_or(|c| c == '\n') {
+                            pc += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                    Inst::WordBoundary => {
+                        let before = pos > 0 && is_word(text[pos - 1]);
+                        let after = next.is_some_and(is_word);
+                        if before != after {
+                            pc += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                    Inst::Jmp(target) => pc = *target,
+                    Inst::Split(first, second) => {
+                        stack.push((*second, pos));
+                        pc = *first;
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    // All the non-overlapping matches in a text as (start, end) char offsets.
+    pub fn find_all(&self, text: &[char]) -> Vec<(usize, usize)> {
+        let mut matches = Vec::new();
+        let mut window_start = 0;
+        let mut next = 0;
+        loop {
+            let window_end = (window_start + WINDOW + OVERLAP).min(text.len());
+            let starts_end = (window_start + WINDOW).min(text.len());
+            let window = &text[..window_end];
+            let width = window_end - window_start + 1;
+            let mut failed = vec![0u64; (self.prog.len() * width).div_ceil(64)];
+            let mut trail = Vec::new();
+
+            let mut start = next.max(window_start);
+            while start <= starts_end {
+                trail.clear();
+                match self.run(window, window_start, start, &mut failed, &mut trail) {
+                    Some(end) => {
+                        for &state in &trail {
+                            failed[state / 64] &= !(1 << (state % 64));
+                        }
+                        matches.push((start, end));
+                        next = if end == start { end + 1 } else { end };
+                        start = next;
+                    }
+                    None => start += 1,
+                }
+            }
+            window_start += WINDOW;
+            if window_start >= text.len() {
+                break;
+            }
+        }
+        matches
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        !self.find_all(&chars).is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Regex;
+
+    fn find_all(pattern: &str, text: &str) -> Vec<(usize, usize)> {
+        let chars: Vec<char> = text.chars().collect();
+        Regex::new(pattern).unwrap().find_all(&chars)
+    }
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        Regex::new(pattern).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn literals_and_any() {
+        assert_eq!(find_all("abc", "xabcyabc"), vec![(1, 4), (5, 8)]);
+        assert_eq!(find_all("a.c", "abc a-c ac"), vec![(0, 3), (4, 7)]);
+        assert!(!matches("a.c", "ac"));
+        assert!(matches("a\\.c", "a.c"));
+        assert!(!matches("a\\.c", "abc"));
+    }
+
+    #[test]
+    fn alternation() {
+        assert_eq!(find_all("cat|dog", "cat dog cow"), vec![(0, 3), (4, 7)]);
+        assert_eq!(find_all("a(b|cd)e", "abe acde ace"), vec![(0, 3), (4, 8)]);
+        assert_eq!(find_all("(?:x|)y", "xy y"), vec![(0, 2), (3, 4)]);
+        // The first branch that matches wins, as in backtracking engines.
+        assert_eq!(find_all("a|ab", "ab"), vec![(0, 1)]);
+    }
+
+    #[test]
+    fn classes() {
+        assert_eq!(find_all("[a-c]+", "xxabcabdx"), vec![(2, 7)]);
+        assert_eq!(find_all("[^0-9]+", "12ab34"), vec![(2, 4)]);
+        assert_eq!(find_all("[]a]", "]a"), vec![(0, 1), (1, 2)]);
+        assert_eq!(find_all("[a-]", "-"), vec![(0, 1)]);
+        assert_eq!(find_all("[\\d_]+", "x1_2y"), vec![(1, 4)]);
+        assert_eq!(find_all("\\d+", "ab 123 4"), vec![(3, 6), (7, 8)]);
+        assert_eq!(find_all("\\w+", "foo, bar_1"), vec![(0, 3), (5, 10)]);
+        assert_eq!(find_all("\\s", "a b\tc"), vec![(1, 2), (3, 4)]);
+        assert_eq!(find_all("\\S+", " ab "), vec![(1, 3)]);
+        assert!(matches("(?i)[a-c]", "B"));
+        assert!(!matches("[a-c]", "B"));
+    }
+
+    #[test]
+    fn anchors() {
+        assert!(matches("^abc$", "abc"));
+        assert!(!matches("^abc$", "xabc"));
+        assert!(!matches("^abc$", "abcx"));
+        assert_eq!(find_all("^a", "aaa"), vec![(0, 1)]);
+        assert_eq!(find_all("a$", "aaa"), vec![(2, 3)]);
+        assert_eq!(find_all("\\bis\\b", "this is island"), vec![(5, 7)]);
+    }
+
+    #[test]
+    fn repetition() {
+        assert_eq!(find_all("ab*", "a ab abbb"), vec![(0, 1), (2, 4), (5, 9)]);
+        assert_eq!(find_all("ab+", "a ab abbb"), vec![(2, 4), (5, 9)]);
+        assert_eq!(find_all("colou?r", "color colour"), vec![(0, 5), (6, 12)]);
+        assert_eq!(find_all("a{3}", "aaaaaaa"), vec![(0, 3), (3, 6)]);
+        assert_eq!(find_all("a{2,}", "a aa aaaa"), vec![(2, 4), (5, 9)]);
+        assert_eq!(find_all("a{1,2}", "aaa"), vec![(0, 2), (2, 3)]);
+        assert_eq!(find_all("(ab){2}", "ababab"), vec![(0, 4)]);
+        // Lazy quantifiers take as little as they can.
+        assert_eq!(find_all("<.+?>", "<a><b>"), vec![(0, 3), (3, 6)]);
+        assert_eq!(find_all("<.+>", "<a><b>"), vec![(0, 6)]);
+        assert_eq!(find_all("a??", "a"), vec![(0, 0), (1, 1)]);
+    }
+
+    #[test]
+    fn backtracking() {
+        assert_eq!(find_all("a*ab", "aaab"), vec![(0, 4)]);
+        assert_eq!(find_all("(a|ab)c", "abc"), vec![(0, 3)]);
+        assert_eq!(find_all("x.*y", "x y y z"), vec![(0, 5)]);
+        assert!(!matches("a+b", "aaaa"));
+        // Exponential for a plain backtracking matcher.
+        let text = "a".repeat(5000);
+        assert!(!matches("(a*)*b", &text));
+        assert!(!matches("(a|a)+b", &text));
+        assert!(matches("(a|a)+$", &text));
+    }
+
+    #[test]
+    fn case_insensitive() {
+        assert_eq!(find_all("(?i)password", "PassWord"), vec![(0, 8)]);
+        assert!(!matches("password", "PassWord"));
+    }
+
+    #[test]
+    fn matches_across_windows() {
+        let mut text = " ".repeat(super::WINDOW - 2);
+        text.push_str("needle");
+        assert_eq!(
+            find_all("needle", &text),
+            vec![(super::WINDOW - 2, super::WINDOW + 4)]
+        );
+        assert_eq!(find_all("\\s+", &text).len(), 1);
+    }
+
+    #[test]
+    fn bad_patterns() {
+        for pattern in [
+            "(a", "a)", "[a", "[b-a]", "*a", "a{", "a{2", "a{2,1}", "a{x}", "\\", "\\q", "(?=a)",
+        ] {
+            assert!(Regex::new(pattern).is_err(), "{} compiled", pattern);
+        }
+    }
+
+    #[test]
+    fn repetitions_are_bounded() {
+        assert_eq!(find_all("x{1000}", &"x".repeat(1000)), vec![(0, 1000)]);
+        assert!(matches("\\d{2,1000}", "12"));
+        for (pattern, error) in [
+            ("x{100000}", "repetition count over 1000"),
+            ("x{1,1001}", "repetition count over 1000"),
+            ("x{99999999999}", "expected a number after '{'"),
+            ("(a{1000}){1000}", "pattern over 2048 instructions"),
+            ("((a{1000}){1000}){1000}", "pattern over 2048 instructions"),
+            ("(ab{100}|c{500}){4}", "pattern over 2048 instructions"),
+        ] {
+            assert_eq!(
+                Regex::new(pattern).err().as_deref(),
+                Some(error),
+                "{}",
+                pattern
+            );
+        }
+    }
+}
@@ -0,0 +1,211 @@
+//
+// Repairing dumps cut short or left inconsistent, by a JVM killed while
+// dumping or a copy that stopped early, into dumps other tools open: the
+// records are copied up to the first one that isn't whole, the heap dump
+// segments with the whole sub-records they have, and HEAP_DUMP_END is
+// appended. HotSpot writes the length of a segment once it is done (a 0
+// until then, in the JDKs before 17), so a segment whose length goes past
+// the end of the file or doesn't end on a sub-record gets the length of
+// the sub-records found from its start instead.
+//
+use crate::heap::sub_record_bytes;
+use crate::records::RecordTag;
+
+use std::convert::TryFrom;
+use std::io::{self, Write};
+
+// Tag, time and length.
+const RECORD_HEADER_BYTES: usize = 9;
+
+fn u32_at(data: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+}
+
+//
+// The bytes of the whole sub-records at the start of `body`, and whether
+// they end where `body` does.
+//
+fn sub_records(body: &[u8], id_size: u32) -> (usize, bool) {
+    let mut at = 0;
+    while at < body.len() {
+        match sub_record_bytes(&body[at..], id_size) {
+            Some(bytes) if bytes <= body.len() - at => at += bytes,
+            _ => return (at, false),
+        }
+    }
+    (at, true)
+}
+
+// Whether a record starts at `at`, judging by its tag.
+fn record_at(data: &[u8], at: usize) -> bool {
+    data.get(at)
+        .is_none_or(|&tag| RecordTag::try_from(tag).is_ok())
+}
+
+fn write_record<W: Write>(out: &mut W, tag: u8, time: &[u8], body: &[u8]) -> io::Result<()> {
+    out.write_all(&[tag])?;
+    out.write_all(time)?;
+    out.write_all(&(body.len() as u32).to_be_bytes())?;
+    out.write_all(body)
+}
+
+//
+// Writes the repaired dump of `data`, and tells what was changed (nothing
+// for a dump that is fine, which is copied as it is).
+//
+pub fn repair<W: Write>(data: &[u8], mut out: W) -> io::Result<(W, Vec<String>)> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let format = data
+        .iter()
+        .take(32)
+        .position(|&b| b == 0)
+        .filter(|&n| data[..n].starts_with(b"JAVA PROFILE "))
+        .ok_or_else(|| invalid("not an HPROF dump".to_string()))?;
+    let header = format + 1 + 12;
+    if data.len() < header {
+        return Err(invalid("the HPROF header is cut short".to_string()));
+    }
+    let id_size = u32_at(data, format + 1);
+    if id_size != 4 && id_size != 8 {
+        return Err(invalid(format!("unsupported identifier size: {}", id_size)));
+    }
+    out.write_all(&data[..header])?;
+
+    let mut notes = Vec::new();
+    let mut at = header;
+    let mut ended = false;
+    while at < data.len() {
+        if data.len() - at < RECORD_HEADER_BYTES {
+            notes.push(format!(
+                "dropped a partial record header of {} bytes at offset {}",
+                data.len() - at,
+                at
+            ));
+            break;
+        }
+        let raw_tag = data[at];
+        let time = &data[at + 1..at + 5];
+        let declared = u32_at(data, at + 5) as usize;
+        let body = at + RECORD_HEADER_BYTES;
+        let end = body + declared;
+        match RecordTag::try_from(raw_tag) {
+            Ok(RecordTag::HeapDumpEnd) => {
+                write_record(&mut out, raw_tag, time, &[])?;
+                ended = true;
+                if data.len() > body {
+                    notes.push(format!(
+                        "dropped {} bytes after HEAP_DUMP_END at offset {}",
+                        data.len() - body,
+                        at
+                    ));
+                }
+                break;
+            }
+            Ok(RecordTag::HeapDump) | Ok(RecordTag::HeapDumpSegment) => {
+                let whole = end <= data.len()
+                    && (declared > 0 || end == data.len())
+                    && sub_records(&data[body..end], id_size) == (declared, true)
+                    && record_at(data, end);
+                if whole {
+                    out.write_all(&data[at..end])?;
+                    at = end;
+                    continue;
+                }
+                let (bytes, _) = sub_records(&data[body..], id_size);
+                notes.push(format!(
+                    "fixed the length of the heap dump segment at offset {}: {} bytes, not {}",
+                    at, bytes, declared
+                ));
+                write_record(&mut out, raw_tag, time, &data[body..body + bytes])?;
+                at = body + bytes;
+                if !record_at(data, at) {
+                    notes.push(format!(
+                        "dropped the partial sub-record at offset {} ({} bytes, tag {:#x})",
+                        at,
+                        data.len() - at,
+                        data[at]
+                    ));
+                    break;
+                }
+            }
+            Ok(_) if end <= data.len() => {
+                out.write_all(&data[at..end])?;
+                at = end;
+            }
+            Ok(tag) => {
+                notes.push(format!(
+                    "dropped the partial {:?} record at offset {} ({} of its {} bytes)",
+                    tag,
+                    at,
+                    data.len() - body,
+                    declared
+                ));
+                break;
+            }
+            Err(_) => {
+                notes.push(format!(
+                    "dropped the {} bytes from offset {}, not a record (tag {:#x})",
+                    data.len() - at,
+                    at,
+                    raw_tag
+                ));
+                break;
+            }
+        }
+    }
+    if !ended {
+        write_record(&mut out, RecordTag::HeapDumpEnd as u8, &[0; 4], &[])?;
+        notes.push("appended HEAP_DUMP_END".to_string());
+    }
+    out.flush()?;
+    Ok((out, notes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::{FieldTag, Value};
+    use crate::snapshot::Snapshot;
+    use crate::testing::{Dump, TempFile};
+
+    #[test]
+    fn dumps_cut_short_reload_with_their_whole_objects() {
+        let mut dump = Dump::new();
+        dump.set_segment_bytes(256);
+        let item = dump.class("test/Item", dump.object, &[("value", FieldTag::Long)]);
+        let items: Vec<u64> = (0..20)
+            .map(|i| dump.instance(item, &[Value::Long(i)]))
+            .collect();
+        let bytes = dump.bytes();
+        let (copy, notes) = repair(&bytes, Vec::new()).unwrap();
+        assert_eq!((copy, notes), (bytes.clone(), Vec::new()));
+
+        // Where the INSTANCE_DUMP of each item starts, its tag before its id.
+        let starts: Vec<usize> = items
+            .iter()
+            .map(|id| {
+                let id = id.to_be_bytes();
+                bytes.windows(8).position(|w| w == id).unwrap() - 1
+            })
+            .collect();
+        let reload = |len: usize| {
+            let (repaired, notes) = repair(&bytes[..len], Vec::new()).unwrap();
+            let snapshot = Snapshot::load(TempFile::new(&repaired).path());
+            (snapshot.objects_of_class("test.Item").len(), notes)
+        };
+        for k in [3, 12, 19] {
+            // At the end of a sub-record, then in the middle of one.
+            let (count, notes) = reload(starts[k]);
+            assert_eq!(count, k);
+            assert!(notes[0].starts_with("fixed the length"), "{:?}", notes);
+            assert_eq!(notes.last().unwrap(), "appended HEAP_DUMP_END");
+            let (count, notes) = reload(starts[k] + 5);
+            assert_eq!(count, k);
+            assert!(
+                notes[1].starts_with("dropped the partial sub-record"),
+                "{:?}",
+                notes
+            );
+        }
+    }
+}
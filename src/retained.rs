@@ -0,0 +1,988 @@
+//
+// Retained sizes: the number of bytes that would be freed if an object
+// were garbage collected, i.e. its shallow size plus the shallow sizes of
+// all the objects it dominates.
+//
+use crate::dominator::{DominatorTree, NONE};
+use crate::graph;
+use crate::heap::{GcRootKind, HeapObject};
+use crate::parallel;
+use crate::paths;
+use crate::reference::RetentionFilter;
+use crate::snapshot::Snapshot;
+use crate::timings;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub fn retained_sizes(snapshot: &Snapshot, tree: &DominatorTree) -> Vec<u64> {
+    let _phase = timings::start("retained sizes");
+    let root = tree.virtual_root();
+    let retained: Vec<AtomicU64> = parallel::map_indices(snapshot.objects.len(), |node| {
+        if tree.idom[node] == NONE {
+            AtomicU64::new(0)
+        } else {
+            AtomicU64::new(snapshot.shallow_size(&snapshot.objects[node]))
+        }
+    });
+
+    //
+    // The objects by depth in the dominator tree (dominated objects come
+    // after their dominators in `order`). All the objects of a level can be
+    // added to their dominators at once, in parallel, once the deeper levels
+    // are done.
+    //
+    let mut depth = vec![0u32; tree.idom.len()];
+    let mut levels: Vec<Vec<u32>> = Vec::new();
+    for &node in &tree.order {
+        let idom = tree.idom[node as usize];
+        let d = if idom == root {
+            0
+        } else {
+            depth[idom as usize] + 1
+        };
+        depth[node as usize] = d;
+        if levels.len() <= d as usize {
+            levels.push(Vec::new());
+        }
+        levels[d as usize].push(node);
+    }
+    for level in levels.iter().skip(1).rev() {
+        parallel::map_chunks(level.len(), |range| {
+            for &node in &level[range] {
+                let size = retained[node as usize].load(Ordering::Relaxed);
+                retained[tree.idom[node as usize] as usize].fetch_add(size, Ordering::Relaxed);
+            }
+        });
+    }
+    retained.into_iter().map(AtomicU64::into_inner).collect()
+}
+
+#[derive(Debug)]
+pub struct RetainedGroup {
+    // A class name, package name, ... depending on how objects were grouped.
+    pub name: String,
+    pub instances: u64,
+    pub shallow: u64,
+    pub retained: u64,
+}
+
+pub fn retained_by_class(
+    snapshot: &Snapshot,
+    tree: &DominatorTree,
+    retained: &[u64],
+) -> Vec<RetainedGroup> {
+    retained_by(snapshot, tree, retained, |object| {
+        snapshot.object_class_name(object)
+    })
+}
+
+//
+// Like retained_by_class() but objects are grouped by package, keeping
+// the first `depth` components of the package name (0 for all of them).
+//
+pub fn retained_by_package(
+    snapshot: &Snapshot,
+    tree: &DominatorTree,
+    retained: &[u64],
+    depth: usize,
+) -> Vec<RetainedGroup> {
+    retained_by(snapshot, tree, retained, |object| {
+        package_name(&snapshot.object_class_name(object), depth)
+    })
+}
+
+//
+// The package of a class, shortened to `depth` components if non-zero.
+// Object arrays belong to the package of their element class; primitive
+// arrays and classes without a package get a group of their own.
+//
+pub fn package_name(class_name: &str, depth: usize) -> String {
+    let element = class_name.trim_end_matches("[]");
+    let primitive = matches!(
+        element,
+        "boolean" | "char" | "float" | "double" | "byte" | "short" | "int" | "long"
+    );
+    if primitive && element.len() < class_name.len() {
+        return "<primitive arrays>".to_string();
+    }
+    let package = match element.rfind('.') {
+        Some(dot) => &element[..dot],
+        None => return "<default package>".to_string(),
+    };
+    if depth == 0 {
+        return package.to_string();
+    }
+    package.split('.').take(depth).collect::<Vec<_>>().join(".")
+}
+
+//
+// The retained size of a group is the retained size of the set of all its
+// reachable instances. Summing per-instance retained sizes would count an
+// instance twice when it is dominated by another instance of the same group
+// (e.g. the nodes of a linked list), so only instances that don't have an
+// instance of their own group above them in the dominator tree contribute.
+//
+pub fn retained_by<F: Fn(&HeapObject) -> String>(
+    snapshot: &Snapshot,
+    tree: &DominatorTree,
+    retained: &[u64],
+    group_of: F,
+) -> Vec<RetainedGroup> {
+    let mut keys: HashMap<String, usize> = HashMap::new();
+    let mut groups: Vec<RetainedGroup> = Vec::new();
+    let mut group_index = vec![NONE; snapshot.objects.len()];
+    for (node, object) in snapshot.objects.iter().enumerate() {
+        if !tree.is_reachable(node as u32) {
+            continue;
+        }
+        let name = group_of(object);
+        let key = *keys.entry(name.clone()).or_insert_with(|| {
+            groups.push(RetainedGroup {
+                name,
+                instances: 0,
+                shallow: 0,
+                retained: 0,
+            });
+            groups.len() - 1
+        });
+        group_index[node] = key as u32;
+        groups[key].instances += 1;
+        groups[key].shallow += snapshot.shallow_size(object);
+    }
+    let sizes = retained_by_groups(tree, retained, &group_index, groups.len());
+    for (group, size) in groups.iter_mut().zip(sizes) {
+        group.retained = size;
+    }
+    groups.sort_by_key(|c| std::cmp::Reverse(c.retained));
+    groups
+}
+
+//
+// The retained size of each of `count` groups of objects, the group of
+// every object (by index) in `group_index`, NONE for those of none. Walks
+// the dominator tree depth-first keeping track of how many objects of each
+// group are on the current path, so that only the topmost count.
+//
+fn retained_by_groups(
+    tree: &DominatorTree,
+    retained: &[u64],
+    group_index: &[u32],
+    count: usize,
+) -> Vec<u64> {
+    let mut sizes = vec![0u64; count];
+    let children = dominator_children(tree);
+    let root = tree.virtual_root();
+    let mut active = vec![0u32; count];
+    let mut stack: Vec<(u32, bool)> = children[root as usize]
+        .iter()
+        .map(|&c| (c, false))
+        .collect();
+    while let Some((node, exiting)) = stack.pop() {
+        let key = group_index[node as usize];
+        if exiting {
+            active[key as usize] -= 1;
+            continue;
+        }
+        if key != NONE {
+            if active[key as usize] == 0 {
+                sizes[key as usize] += retained[node as usize];
+            }
+            active[key as usize] += 1;
+            stack.push((node, true));
+        }
+        stack.extend(children[node as usize].iter().map(|&c| (c, false)));
+    }
+    sizes
+}
+
+//
+// The retained size of a set of objects, counting the objects dominated by
+// more than one of them once. Objects reachable only through several of
+// them at once (but not dominated by a single one) are not included.
+//
+pub fn retained_by_set(tree: &DominatorTree, retained: &[u64], nodes: &[u32]) -> u64 {
+    let set: HashSet<u32> = nodes
+        .iter()
+        .copied()
+        .filter(|&n| tree.is_reachable(n))
+        .collect();
+
+    //
+    // Whether each dominator walked past has an object of the set above it
+    // (or is one), remembered so that no dominator is walked past twice.
+    //
+    let root = tree.virtual_root();
+    let mut above: HashMap<u32, bool> = HashMap::new();
+    let mut chain = Vec::new();
+    let mut total = 0;
+    for &node in &set {
+        let mut current = tree.idom[node as usize];
+        let found = loop {
+            if current == root {
+                break false;
+            }
+            if set.contains(&current) {
+                break true;
+            }
+            if let Some(&found) = above.get(&current) {
+                break found;
+            }
+            chain.push(current);
+            current = tree.idom[current as usize];
+        };
+        for d in chain.drain(..) {
+            above.insert(d, found);
+        }
+        if !found {
+            total += retained[node as usize];
+        }
+    }
+    total
+}
+
+#[derive(Debug)]
+pub struct RootKindRetained {
+    pub kind: GcRootKind,
+    // Distinct objects referenced by roots of this kind.
+    pub objects: u64,
+    pub retained: u64,
+}
+
+//
+// Retained size of the objects referenced by each kind of GC root. Objects
+// referenced by roots of several kinds are counted for each of them, so the
+// sizes don't add up to the size of the heap.
+//
+pub fn retained_by_root_kind(
+    snapshot: &Snapshot,
+    tree: &DominatorTree,
+    retained: &[u64],
+) -> Vec<RootKindRetained> {
+    let mut by_kind: HashMap<GcRootKind, Vec<u32>> = HashMap::new();
+    for root in &snapshot.roots {
+        if let Some(node) = snapshot.index_of(root.object_id) {
+            by_kind.entry(root.kind).or_default().push(node);
+        }
+    }
+    let mut report: Vec<RootKindRetained> = by_kind
+        .into_iter()
+        .map(|(kind, mut nodes)| {
+            nodes.sort_unstable();
+            nodes.dedup();
+            RootKindRetained {
+                kind,
+                objects: nodes.len() as u64,
+                retained: retained_by_set(tree, retained, &nodes),
+            }
+        })
+        .collect();
+    report.sort_by(|a, b| b.retained.cmp(&a.retained).then(a.kind.cmp(&b.kind)));
+    report
+}
+
+// The children of every node in the dominator tree, including the virtual root.
+pub fn dominator_children(tree: &DominatorTree) -> Vec<Vec<u32>> {
+    let mut children = vec![Vec::new(); tree.idom.len() + 1];
+    for &node in &tree.order {
+        children[tree.idom[node as usize] as usize].push(node);
+    }
+    children
+}
+
+// The children of a node in the dominator tree, largest retained size first.
+pub fn children_by_retained(children: &[Vec<u32>], retained: &[u64], node: u32) -> Vec<u32> {
+    let mut sorted = children[node as usize].clone();
+    sorted.sort_by_key(|&c| std::cmp::Reverse(retained[c as usize]));
+    sorted
+}
+
+#[derive(Debug)]
+pub struct Retainer {
+    // Label of the dominating object, "<GC root>" for the virtual root.
+    pub dominator: String,
+    // How the dominator refers to the instances when it does directly.
+    pub via: Option<String>,
+    pub instances: u64,
+    pub retained: u64,
+}
+
+//
+// The immediate dominators of a set of objects (typically all instances of
+// a class) grouped by class and field. Dominators from the set itself are
+// skipped, so that the nodes of a linked list are charged to what holds the
+// list, and so are objects of the set dominated by another one through other
+// objects: the retained sizes of these nested objects aren't counted twice.
+//
+pub fn retainers_of(
+    snapshot: &Snapshot,
+    tree: &DominatorTree,
+    retained: &[u64],
+    nodes: &[u32],
+) -> Vec<Retainer> {
+    let filter = RetentionFilter::new(snapshot, Vec::new());
+    let root = tree.virtual_root();
+
+    //
+    // The topmost instance dominating each node, in one pass: dominators come
+    // before what they dominate in the order. Other objects carry it down too,
+    // so that an instance dominated by another one through them is nested.
+    //
+    let mut top = vec![NONE; tree.idom.len()];
+    for &node in nodes {
+        top[node as usize] = node;
+    }
+    for &node in &tree.order {
+        let idom = tree.idom[node as usize];
+        if idom != root && top[idom as usize] != NONE {
+            top[node as usize] = top[idom as usize];
+        }
+    }
+
+    let mut groups: HashMap<(String, Option<String>), Retainer> = HashMap::new();
+    for &node in nodes.iter().filter(|&&n| tree.is_reachable(n)) {
+        let top = top[node as usize];
+        let idom = tree.idom[top as usize];
+        let (dominator, via) = if idom == root {
+            ("<GC root>".to_string(), None)
+        } else {
+            (
+                snapshot.object_label(&snapshot.objects[idom as usize]),
+                // All the elements of an array are retained the same way.
+                paths::retaining_via(snapshot, &filter, idom, top).map(|via| match via {
+                    graph::Via::Element(_) => "[]".to_string(),
+                    _ => graph::qualified_via_name(snapshot, via),
+                }),
+            )
+        };
+        let group = groups
+            .entry((dominator.clone(), via.clone()))
+            .or_insert(Retainer {
+                dominator,
+                via,
+                instances: 0,
+                retained: 0,
+            });
+        group.instances += 1;
+        if top == node {
+            group.retained += retained[node as usize];
+        }
+    }
+    let mut groups: Vec<Retainer> = groups.into_values().collect();
+    groups.sort_by(|a, b| {
+        b.retained
+            .cmp(&a.retained)
+            .then_with(|| b.instances.cmp(&a.instances))
+            .then_with(|| a.dominator.cmp(&b.dominator))
+    });
+    groups
+}
+
+#[derive(Debug)]
+pub struct PathGroup {
+    // The kinds of GC root at the end of the paths, like "jni-global,sticky-class".
+    pub root_kinds: String,
+    // The first object on the paths that isn't from the set, None when the
+    // objects are GC roots themselves.
+    pub holder: Option<String>,
+    pub via: Option<String>,
+    pub instances: u64,
+    pub shallow: u64,
+    pub retained: u64,
+}
+
+//
+// The shortest paths from the GC roots to a set of objects (parents from
+// paths::bfs_parents()) grouped by the kind of root they start from and by
+// the class and field holding the objects at their end. As for
+// retainers_of(), objects of the set holding other ones are walked past.
+//
+pub fn root_path_groups(
+    snapshot: &Snapshot,
+    filter: &RetentionFilter,
+    parents: &[u32],
+    tree: &DominatorTree,
+    retained: &[u64],
+    nodes: &[u32],
+) -> Vec<PathGroup> {
+    let mut member = vec![false; parents.len()];
+    for &node in nodes {
+        member[node as usize] = true;
+    }
+    let mut root_kinds: HashMap<u32, Vec<&str>> = HashMap::new();
+    for root in &snapshot.roots {
+        if let Some(node) = snapshot.index_of(root.object_id) {
+            let kinds = root_kinds.entry(node).or_default();
+            if !kinds.contains(&root.kind.name()) {
+                kinds.push(root.kind.name());
+            }
+        }
+    }
+    //
+    // The root at the end of the path of each object, and the first object
+    // on it not from the set (with the one it refers to), following each
+    // parent once: an object takes them from its parent.
+    //
+    let mut ends = vec![NONE; parents.len()];
+    let mut holders = vec![(NONE, NONE); parents.len()];
+    let mut path = Vec::new();
+    for &node in nodes {
+        let mut current = node;
+        while ends[current as usize] == NONE && parents[current as usize] != paths::NONE {
+            match parents[current as usize] {
+                paths::ROOT => ends[current as usize] = current,
+                parent => {
+                    path.push(current);
+                    current = parent;
+                }
+            }
+        }
+        if ends[current as usize] != NONE {
+            for &below in path.iter().rev() {
+                let parent = parents[below as usize];
+                ends[below as usize] = ends[parent as usize];
+                holders[below as usize] = match member[parent as usize] {
+                    true => holders[parent as usize],
+                    false => (parent, below),
+                };
+            }
+        }
+        path.clear();
+    }
+    // By root kinds, holder and field.
+    type Key = (String, Option<String>, Option<String>);
+    let mut keys: HashMap<Key, usize> = HashMap::new();
+    let mut groups: Vec<PathGroup> = Vec::new();
+    let mut group_index = vec![NONE; parents.len()];
+    for &node in nodes {
+        if ends[node as usize] == NONE || group_index[node as usize] != NONE {
+            continue;
+        }
+        let mut kinds = root_kinds
+            .get(&ends[node as usize])
+            .cloned()
+            .unwrap_or_default();
+        kinds.sort_unstable();
+        let (holder, via) = match holders[node as usize] {
+            (NONE, _) => (None, None),
+            (holder, below) => {
+                let object = &snapshot.objects[holder as usize];
+                let via = match object {
+                    HeapObject::ObjectArray(_) => Some("[]".to_string()),
+                    _ => paths::retaining_via(snapshot, filter, holder, below)
+                        .map(|via| graph::via_name(snapshot, via)),
+                };
+                (Some(snapshot.object_label(object)), via)
+            }
+        };
+        let key = (kinds.join(","), holder.clone(), via.clone());
+        let index = *keys.entry(key).or_insert_with(|| {
+            groups.push(PathGroup {
+                root_kinds: kinds.join(","),
+                holder,
+                via,
+                instances: 0,
+                shallow: 0,
+                retained: 0,
+            });
+            groups.len() - 1
+        });
+        group_index[node as usize] = index as u32;
+        groups[index].instances += 1;
+        groups[index].shallow += snapshot.shallow_size(&snapshot.objects[node as usize]);
+    }
+    let sizes = retained_by_groups(tree, retained, &group_index, groups.len());
+    for (group, size) in groups.iter_mut().zip(sizes) {
+        group.retained = size;
+    }
+    groups.sort_by(|a, b| {
+        b.instances
+            .cmp(&a.instances)
+            .then_with(|| b.retained.cmp(&a.retained))
+            .then_with(|| a.holder.cmp(&b.holder))
+            .then_with(|| a.via.cmp(&b.via))
+    });
+    groups
+}
+
+#[derive(Debug)]
+pub struct StaticFieldRetained {
+    pub class_id: u64,
+    pub name_id: u64,
+    pub target: u32,
+    pub retained: u64,
+    // Whether the class is the only way to the object (it dominates it), or
+    // the object would stay alive through other references without it.
+    pub exclusive: bool,
+}
+
+// The retained size of the object of every non-null static field.
+pub fn static_field_retained(
+    snapshot: &Snapshot,
+    tree: &DominatorTree,
+    retained: &[u64],
+) -> Vec<StaticFieldRetained> {
+    let mut fields = Vec::new();
+    for (node, object) in snapshot.objects.iter().enumerate() {
+        let class = match object {
+            HeapObject::Class(c) if tree.is_reachable(node as u32) => c,
+            _ => continue,
+        };
+        for field in class.static_fields() {
+            let target = match field.value.as_object().and_then(|id| snapshot.index_of(id)) {
+                Some(target) if tree.is_reachable(target) => target,
+                _ => continue,
+            };
+            fields.push(StaticFieldRetained {
+                class_id: class.class_id,
+                name_id: field.name_id,
+                target,
+                retained: retained[target as usize],
+                exclusive: tree.idom[target as usize] == node as u32,
+            });
+        }
+    }
+    fields.sort_by(|a, b| {
+        b.retained
+            .cmp(&a.retained)
+            .then_with(|| a.class_id.cmp(&b.class_id))
+            .then_with(|| a.name_id.cmp(&b.name_id))
+    });
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dominator;
+    use crate::graph::Graph;
+    use crate::heap::{FieldTag, Value};
+    use crate::synthetic::{self, Options, Shape};
+    use crate::testing::{Dump, TempFile};
+
+    fn analyzed(snapshot: &Snapshot) -> (DominatorTree, Vec<u64>) {
+        let tree = dominator::build(&Graph::build(snapshot));
+        let retained = retained_sizes(snapshot, &tree);
+        (tree, retained)
+    }
+
+    fn deep(objects: u64, depth: u64) -> Snapshot {
+        let options = Options {
+            classes: 0,
+            objects,
+            shape: Shape::Deep,
+            depth,
+            ..Options::default()
+        };
+        let file = TempFile::new(&synthetic::generate(&options, Vec::new()).unwrap());
+        Snapshot::load(file.path())
+    }
+
+    // A holder of a list of three nodes and a cache of one.
+    fn lists() -> Snapshot {
+        let mut dump = Dump::new();
+        let node = dump.class(
+            "test/Node",
+            dump.object,
+            &[("next", FieldTag::NormalObject), ("value", FieldTag::Long)],
+        );
+        let holder = dump.class(
+            "test/Holder",
+            dump.object,
+            &[("head", FieldTag::NormalObject)],
+        );
+        let cache = dump.class(
+            "test/Cache",
+            dump.object,
+            &[("entry", FieldTag::NormalObject)],
+        );
+        let mut next = 0;
+        for _ in 0..3 {
+            next = dump.instance(node, &[Value::Object(next)]);
+        }
+        let holder = dump.instance(holder, &[Value::Object(next)]);
+        let entry = dump.instance(node, &[]);
+        let cache = dump.instance(cache, &[Value::Object(entry)]);
+        dump.root(GcRootKind::JniGlobal, holder);
+        dump.root(GcRootKind::JniGlobal, cache);
+        dump.load()
+    }
+
+    #[test]
+    fn retainers_walk_past_the_instances() {
+        let snapshot = lists();
+        let (tree, retained) = analyzed(&snapshot);
+        let nodes = snapshot.objects_of_class("test.Node");
+        let groups = retainers_of(&snapshot, &tree, &retained, &nodes);
+        let node_size = snapshot.shallow_size(&snapshot.objects[nodes[0] as usize]);
+        let found: Vec<(&str, Option<&str>, u64, u64)> = groups
+            .iter()
+            .map(|g| {
+                (
+                    g.dominator.as_str(),
+                    g.via.as_deref(),
+                    g.instances,
+                    g.retained,
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("test.Holder", Some("test.Holder.head"), 3, 3 * node_size),
+                ("test.Cache", Some("test.Cache.entry"), 1, node_size),
+            ]
+        );
+    }
+
+    #[test]
+    fn elements_are_retained_by_their_array() {
+        let mut dump = Dump::new();
+        let strings: Vec<u64> = ["a", "b"].iter().map(|s| dump.string(s)).collect();
+        let array = dump.object_array(dump.object_array, &strings);
+        dump.root(GcRootKind::StickyClass, array);
+        let snapshot = dump.load();
+        let (tree, retained) = analyzed(&snapshot);
+        let strings = snapshot.objects_of_class("java.lang.String");
+        let groups = retainers_of(&snapshot, &tree, &retained, &strings);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].dominator, "java.lang.Object[]");
+        assert_eq!(groups[0].via.as_deref(), Some("[]"));
+        assert_eq!(groups[0].instances, 2);
+        let values: u64 = strings.iter().map(|&s| retained[s as usize]).sum();
+        assert_eq!(groups[0].retained, values);
+    }
+
+    #[test]
+    fn instances_nested_through_other_objects_count_once() {
+        // An item holding a link holding another item.
+        let mut dump = Dump::new();
+        let item = dump.class(
+            "test/Item",
+            dump.object,
+            &[("link", FieldTag::NormalObject)],
+        );
+        let link = dump.class(
+            "test/Link",
+            dump.object,
+            &[("item", FieldTag::NormalObject)],
+        );
+        let inner = dump.instance(item, &[Value::Object(0)]);
+        let link = dump.instance(link, &[Value::Object(inner)]);
+        let outer = dump.instance(item, &[Value::Object(link)]);
+        dump.root(GcRootKind::JniGlobal, outer);
+        let snapshot = dump.load();
+        let (tree, retained) = analyzed(&snapshot);
+        let items = snapshot.objects_of_class("test.Item");
+        let groups = retainers_of(&snapshot, &tree, &retained, &items);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].dominator, "<GC root>");
+        assert_eq!(groups[0].instances, 2);
+        let outer = snapshot.index_of(outer).unwrap();
+        assert_eq!(groups[0].retained, retained[outer as usize]);
+        assert_eq!(
+            groups[0].retained,
+            retained_by_set(&tree, &retained, &items)
+        );
+    }
+
+    #[test]
+    fn retainers_of_deep_chains() {
+        let snapshot = deep(40_000, 20_000);
+        let (tree, retained) = analyzed(&snapshot);
+        let nodes = snapshot.objects_of_class("synthetic.Node");
+        let live: Vec<u32> = nodes
+            .iter()
+            .copied()
+            .filter(|&n| tree.is_reachable(n))
+            .collect();
+        let shallow: u64 = live
+            .iter()
+            .map(|&n| snapshot.shallow_size(&snapshot.objects[n as usize]))
+            .sum();
+        assert!(live.len() > 10_000);
+        let groups = retainers_of(&snapshot, &tree, &retained, &nodes);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].dominator, "java.lang.Object[]");
+        assert_eq!(groups[0].via.as_deref(), Some("[]"));
+        assert_eq!(groups[0].instances, live.len() as u64);
+        assert_eq!(groups[0].retained, shallow);
+    }
+
+    #[test]
+    fn path_groups_by_holder() {
+        let snapshot = lists();
+        let graph = Graph::build(&snapshot);
+        let filter = RetentionFilter::new(&snapshot, Vec::new());
+        let parents = paths::bfs_parents(&snapshot, &graph, &filter);
+        let (tree, retained) = analyzed(&snapshot);
+        let nodes = snapshot.objects_of_class("test.Node");
+        let node_size = snapshot.shallow_size(&snapshot.objects[nodes[0] as usize]);
+        let groups = root_path_groups(&snapshot, &filter, &parents, &tree, &retained, &nodes);
+        let found: Vec<(&str, Option<&str>, u64, u64)> = groups
+            .iter()
+            .map(|g| {
+                (
+                    g.root_kinds.as_str(),
+                    g.via.as_deref(),
+                    g.instances,
+                    g.retained,
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("jni-global", Some("head"), 3, 3 * node_size),
+                ("jni-global", Some("entry"), 1, node_size),
+            ]
+        );
+        assert!(groups[0]
+            .holder
+            .as_deref()
+            .unwrap()
+            .starts_with("test.Holder"));
+        assert_eq!(retained_by_set(&tree, &retained, &nodes), 4 * node_size);
+    }
+
+    #[test]
+    fn path_groups_of_deep_chains() {
+        let snapshot = deep(40_000, 20_000);
+        let graph = Graph::build(&snapshot);
+        let filter = RetentionFilter::new(&snapshot, Vec::new());
+        let parents = paths::bfs_parents(&snapshot, &graph, &filter);
+        let (tree, retained) = analyzed(&snapshot);
+        let nodes = snapshot.objects_of_class("synthetic.Node");
+        let live = nodes.iter().filter(|&&n| tree.is_reachable(n)).count() as u64;
+        let groups = root_path_groups(&snapshot, &filter, &parents, &tree, &retained, &nodes);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].via.as_deref(), Some("[]"));
+        assert_eq!(groups[0].instances, live);
+        assert_eq!(groups[0].retained, groups[0].shallow);
+        assert_eq!(
+            retained_by_set(&tree, &retained, &nodes),
+            groups[0].retained
+        );
+    }
+
+    #[test]
+    fn retained_sizes_add_up_the_dominated() {
+        let snapshot = lists();
+        let (tree, retained) = analyzed(&snapshot);
+        let size = |node: u32| snapshot.shallow_size(&snapshot.objects[node as usize]);
+        let holder = snapshot.objects_of_class("test.Holder")[0];
+        let nodes = snapshot.objects_of_class("test.Node");
+        let node_size = size(nodes[0]);
+        assert_eq!(retained[holder as usize], size(holder) + 3 * node_size);
+        let total: u64 = snapshot
+            .objects
+            .iter()
+            .enumerate()
+            .filter(|&(n, _)| tree.is_reachable(n as u32))
+            .map(|(n, _)| size(n as u32))
+            .sum();
+        let tops: u64 = (0..snapshot.objects.len())
+            .filter(|&n| tree.idom[n] == tree.virtual_root())
+            .map(|n| retained[n])
+            .sum();
+        assert_eq!(tops, total);
+
+        let classes = retained_by_class(&snapshot, &tree, &retained);
+        let node = classes.iter().find(|c| c.name == "test.Node").unwrap();
+        assert_eq!((node.instances, node.shallow), (4, 4 * node_size));
+        // Not 3 + 2 + 1 nodes for the list, nor counted again for the cache.
+        assert_eq!(node.retained, 4 * node_size);
+    }
+
+    #[test]
+    fn package_names_are_cut_to_depth() {
+        assert_eq!(
+            package_name("com.example.cache.Entry", 0),
+            "com.example.cache"
+        );
+        assert_eq!(package_name("com.example.cache.Entry", 2), "com.example");
+        assert_eq!(
+            package_name("com.example.cache.Entry", 5),
+            "com.example.cache"
+        );
+        assert_eq!(package_name("com.example.Entry[][]", 0), "com.example");
+        assert_eq!(package_name("int[]", 0), "<primitive arrays>");
+        assert_eq!(package_name("Main", 0), "<default package>");
+    }
+
+    #[test]
+    fn packages_retain_their_classes_once() {
+        let mut dump = Dump::new();
+        let entry = dump.class(
+            "com/example/cache/Entry",
+            dump.object,
+            &[("key", FieldTag::NormalObject)],
+        );
+        let cache = dump.class(
+            "com/example/Cache",
+            dump.object,
+            &[("entry", FieldTag::NormalObject)],
+        );
+        let key = dump.string("key");
+        let entry = dump.instance(entry, &[Value::Object(key)]);
+        let cache = dump.instance(cache, &[Value::Object(entry)]);
+        dump.root(GcRootKind::JniGlobal, cache);
+        let snapshot = dump.load();
+        let (tree, retained) = analyzed(&snapshot);
+        let node = |id| snapshot.index_of(id).unwrap() as usize;
+
+        let packages = retained_by_package(&snapshot, &tree, &retained, 0);
+        let group = |name: &str| packages.iter().find(|g| g.name == name).unwrap();
+        assert_eq!(group("com.example").retained, retained[node(cache)]);
+        assert_eq!(group("com.example.cache").retained, retained[node(entry)]);
+
+        // The entry is within the cache's package at depth 2.
+        let packages = retained_by_package(&snapshot, &tree, &retained, 2);
+        let example = packages.iter().find(|g| g.name == "com.example").unwrap();
+        assert_eq!(example.instances, 2);
+        assert_eq!(example.retained, retained[node(cache)]);
+    }
+
+    #[test]
+    fn sets_retain_what_each_of_them_dominates() {
+        let mut dump = Dump::new();
+        let pair = dump.class(
+            "test/Pair",
+            dump.object,
+            &[
+                ("left", FieldTag::NormalObject),
+                ("right", FieldTag::NormalObject),
+            ],
+        );
+        let shared = dump.instance(dump.object, &[]);
+        let leaf = dump.instance(dump.object, &[]);
+        let inner = dump.instance(pair, &[Value::Object(leaf)]);
+        let first = dump.instance(pair, &[Value::Object(inner), Value::Object(shared)]);
+        let second = dump.instance(pair, &[Value::Object(shared)]);
+        dump.root(GcRootKind::JniGlobal, first);
+        dump.root(GcRootKind::JniGlobal, second);
+        let snapshot = dump.load();
+        let (tree, retained) = analyzed(&snapshot);
+        let node = |id| snapshot.index_of(id).unwrap();
+
+        // The inner pair is within the first, the shared object in neither.
+        let set = [node(first), node(inner), node(second)];
+        let expected = retained[node(first) as usize] + retained[node(second) as usize];
+        assert_eq!(retained_by_set(&tree, &retained, &set), expected);
+        let size = |id| snapshot.shallow_size(snapshot.object(id).unwrap());
+        assert_eq!(expected, 3 * size(first) + size(leaf));
+    }
+
+    #[test]
+    fn roots_retain_by_kind() {
+        let mut dump = Dump::new();
+        let big = dump.primitive_array(FieldTag::Byte, &[0; 1000]);
+        let small = dump.primitive_array(FieldTag::Byte, &[0; 10]);
+        let both = dump.instance(dump.object, &[]);
+        dump.root(GcRootKind::JniGlobal, big);
+        dump.root(GcRootKind::JniGlobal, big);
+        dump.root(GcRootKind::JniGlobal, both);
+        dump.root_of(GcRootKind::JavaFrame, small, Some(1));
+        dump.root_of(GcRootKind::JavaFrame, both, Some(1));
+        let snapshot = dump.load();
+        let (tree, retained) = analyzed(&snapshot);
+        let retained_of = |id| retained[snapshot.index_of(id).unwrap() as usize];
+
+        let found: Vec<(GcRootKind, u64, u64)> = retained_by_root_kind(&snapshot, &tree, &retained)
+            .iter()
+            .map(|r| (r.kind, r.objects, r.retained))
+            .collect();
+        // Objects are counted once per kind, and for every kind that holds them.
+        assert_eq!(
+            found,
+            vec![
+                (
+                    GcRootKind::JniGlobal,
+                    2,
+                    retained_of(big) + retained_of(both)
+                ),
+                (
+                    GcRootKind::JavaFrame,
+                    2,
+                    retained_of(small) + retained_of(both)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn dominator_children_by_retained_size() {
+        let snapshot = lists();
+        let (tree, retained) = analyzed(&snapshot);
+        let children = dominator_children(&tree);
+        assert_eq!(children.len(), snapshot.objects.len() + 1);
+        let holder = snapshot.objects_of_class("test.Holder")[0];
+        let cache = snapshot.objects_of_class("test.Cache")[0];
+        let root = children_by_retained(&children, &retained, tree.virtual_root());
+        let holder_at = root.iter().position(|&n| n == holder).unwrap();
+        let cache_at = root.iter().position(|&n| n == cache).unwrap();
+        assert!(holder_at < cache_at);
+        assert!(root
+            .windows(2)
+            .all(|w| retained[w[0] as usize] >= retained[w[1] as usize]));
+        // The head of the list, which dominates the rest of it, comes before
+        // the class of the holder.
+        let head = children_by_retained(&children, &retained, holder)[0];
+        assert!(snapshot.objects_of_class("test.Node").contains(&head));
+        let below: u64 = children[holder as usize]
+            .iter()
+            .map(|&c| retained[c as usize])
+            .sum();
+        let size = snapshot.shallow_size(&snapshot.objects[holder as usize]);
+        assert_eq!(retained[holder as usize], size + below);
+        for (node, kids) in children.iter().enumerate().take(snapshot.objects.len()) {
+            assert!(kids.iter().all(|&k| tree.idom[k as usize] == node as u32));
+        }
+    }
+
+    #[test]
+    fn statics_retain_what_only_they_hold() {
+        let mut dump = Dump::new();
+        let item = dump.class("test/Item", dump.object, &[("value", FieldTag::Long)]);
+        let items: Vec<u64> = (0..3).map(|_| dump.instance(item, &[])).collect();
+        let array = dump.object_array;
+        let cache = dump.object_array(array, &items);
+        let shared = dump.instance(item, &[]);
+        dump.root(GcRootKind::JniGlobal, shared);
+        let registry = dump.class_with(
+            "test/Registry",
+            dump.object,
+            &[],
+            &[("cache", cache), ("shared", shared), ("none", 0)],
+            0,
+        );
+        dump.root(GcRootKind::StickyClass, registry);
+        let snapshot = dump.load();
+        let (tree, retained) = analyzed(&snapshot);
+
+        let fields = static_field_retained(&snapshot, &tree, &retained);
+        let found: Vec<(String, &str, u32, bool)> = fields
+            .iter()
+            .map(|f| {
+                (
+                    snapshot.class_name(f.class_id),
+                    snapshot.string(f.name_id),
+                    f.target,
+                    f.exclusive,
+                )
+            })
+            .collect();
+        let node = |id| snapshot.index_of(id).unwrap();
+        assert_eq!(
+            found,
+            vec![
+                ("test.Registry".to_string(), "cache", node(cache), true),
+                ("test.Registry".to_string(), "shared", node(shared), false),
+            ]
+        );
+        let item_size = snapshot.shallow_size(&snapshot.objects[node(shared) as usize]);
+        let array_size = snapshot.shallow_size(&snapshot.objects[node(cache) as usize]);
+        assert_eq!(fields[0].retained, array_size + 3 * item_size);
+        assert_eq!(fields[1].retained, item_size);
+    }
+}
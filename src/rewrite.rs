@@ -0,0 +1,175 @@
+//
+// Renumberings for the dumps rewritten by writer::write_snapshot_with().
+// Narrowing identifiers to 4 bytes renumbers those that don't fit, by kind
+// (objects, symbols and stack frames), keeping their order: HotSpot dumps
+// of 64-bit JVMs have the addresses of objects and symbols as identifiers,
+// which usually only fit in 4 bytes for small heaps with compressed oops.
+//
+use crate::graph;
+use crate::heap::HeapObject;
+use crate::idhash::{IdMap, IdSet};
+use crate::snapshot::Snapshot;
+use crate::writer::Renumbering;
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+//
+// Every object id: of the objects, and of what is referenced without being
+// in the dump, which is then still referenced by the same new id.
+//
+fn object_ids(snapshot: &Snapshot) -> Vec<u64> {
+    let mut missing = IdSet::default();
+    let mut note = |id: u64| {
+        if id != 0 && snapshot.index_of(id).is_none() {
+            missing.insert(id);
+        }
+    };
+    for object in &snapshot.objects {
+        for target in graph::outgoing_references(snapshot, object) {
+            note(target);
+        }
+    }
+    for root in &snapshot.roots {
+        note(root.object_id);
+    }
+    for class in snapshot.classes.values() {
+        note(class.object_id);
+    }
+    for thread in &snapshot.threads {
+        note(thread.thread_object_id);
+    }
+    let mut ids: Vec<u64> = snapshot.objects.iter().map(HeapObject::object_id).collect();
+    ids.extend(missing);
+    ids
+}
+
+// The symbols referenced by classes, fields, stack frames and threads.
+fn referenced_symbols(snapshot: &Snapshot) -> Vec<u64> {
+    let mut ids: Vec<u64> = snapshot.classes.values().map(|c| c.strname_id).collect();
+    for frame in snapshot.frames.values() {
+        ids.extend([
+            frame.method_name_id,
+            frame.method_sign_id,
+            frame.source_name_id,
+        ]);
+    }
+    for thread in &snapshot.threads {
+        ids.extend([
+            thread.thread_name_id,
+            thread.group_name_id,
+            thread.parent_group_name_id,
+        ]);
+    }
+    for object in &snapshot.objects {
+        if let HeapObject::Class(class) = object {
+            ids.extend(class.static_fields().iter().map(|f| f.name_id));
+            ids.extend(class.instance_fields.iter().map(|f| f.name_id));
+        }
+    }
+    ids
+}
+
+fn symbol_ids(snapshot: &Snapshot) -> Vec<u64> {
+    let mut ids: Vec<u64> = snapshot.strings.iter().map(|(id, _)| id).collect();
+    ids.extend(referenced_symbols(snapshot));
+    ids
+}
+
+fn frame_ids(snapshot: &Snapshot) -> Vec<u64> {
+    let mut ids: Vec<u64> = snapshot.frames.keys().copied().collect();
+    for trace in &snapshot.traces {
+        ids.extend(&trace.frame_ids);
+    }
+    ids
+}
+
+//
+// New ids by rank when some don't fit in 4 bytes, `stride` apart if there
+// is room for it (1 otherwise), none when they all fit. 0 stays null.
+//
+fn ranks(mut ids: Vec<u64>, kind: &str, stride: u64) -> Result<IdMap<u64, u64>, String> {
+    ids.sort_unstable();
+    ids.dedup();
+    ids.retain(|&id| id != 0);
+    let mut map = IdMap::default();
+    if ids.last().is_none_or(|&id| u32::try_from(id).is_ok()) {
+        return Ok(map);
+    }
+    let count = ids.len() as u64;
+    let stride = if count * stride <= u32::MAX as u64 {
+        stride
+    } else if count <= u32::MAX as u64 {
+        1
+    } else {
+        return Err(format!(
+            "{} {} identifiers, more than 4 bytes can number",
+            count, kind
+        ));
+    };
+    map.reserve(ids.len());
+    for (rank, id) in ids.into_iter().enumerate() {
+        map.insert(id, (rank as u64 + 1) * stride);
+    }
+    Ok(map)
+}
+
+// What makes the identifiers of the snapshot fit in 4 bytes.
+pub fn narrow_ids(snapshot: &Snapshot) -> Result<Renumbering, String> {
+    Ok(Renumbering {
+        // Objects stay 8 bytes apart, as aligned addresses.
+        objects: ranks(object_ids(snapshot), "object", 8)?,
+        symbols: ranks(symbol_ids(snapshot), "symbol", 1)?,
+        frames: ranks(frame_ids(snapshot), "stack frame", 1)?,
+        dropped_symbols: IdSet::default(),
+    })
+}
+
+#[derive(Debug, Default)]
+pub struct Compaction {
+    // Symbols merged into another of the same text.
+    pub duplicates: usize,
+    pub unreferenced: usize,
+    // Bytes of the UTF8 records left out.
+    pub bytes: u64,
+}
+
+//
+// Merges the referenced symbols of the same text into the first of them,
+// and leaves out the symbols that nothing references, rewritten after any
+// renumbering already planned (see narrow_ids()). Generated classes (of
+// lambdas, proxies, serializers ...) bring lots of both.
+//
+pub fn compact_symbols(snapshot: &Snapshot, renumbering: &mut Renumbering) -> Compaction {
+    let mut referenced = referenced_symbols(snapshot);
+    referenced.sort_unstable();
+    referenced.dedup();
+    let record_bytes =
+        |id: u64| (9 + snapshot.id_size() as usize + snapshot.string(id).len()) as u64;
+
+    let mut compaction = Compaction::default();
+    let mut first: HashMap<&str, u64> = HashMap::new();
+    for &id in &referenced {
+        let text = match snapshot.strings.get(id) {
+            Some(text) => text,
+            // Dangling, kept as it is.
+            None => continue,
+        };
+        let canonical = *first.entry(text).or_insert(id);
+        if canonical != id {
+            let new_id = renumbering.symbol(canonical);
+            renumbering.symbols.insert(id, new_id);
+            compaction.duplicates += 1;
+            compaction.bytes += record_bytes(id);
+        }
+    }
+    let referenced: IdSet<u64> = referenced.into_iter().collect();
+    for (id, _) in snapshot.strings.iter() {
+        if !referenced.contains(&id) {
+            renumbering.dropped_symbols.insert(id);
+            compaction.unreferenced += 1;
+            compaction.bytes += record_bytes(id);
+        }
+    }
+    compaction
+}
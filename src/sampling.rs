@@ -0,0 +1,299 @@
+//
+// Approximate retained sizes for dumps too large for the dominator tree of
+// the whole heap. Live bytes are sampled with probability proportional to
+// their size; the dominators of each sample are those of the subgraph of
+// the objects that can reach it (paths from the roots to an object only go
+// through those), found by walking the references backwards from the
+// sample. A class retains about the share of the samples that it or one of
+// their dominators is an instance of, with a binomial confidence interval.
+// Samples whose ancestors exceed a budget are left unresolved and only
+// widen the interval.
+//
+use crate::dominator;
+use crate::graph::{Adjacency, Graph};
+use crate::snapshot::Snapshot;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+// xorshift64*, enough to pick samples reproducibly.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed.max(1))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // A number in 0..n, n > 0.
+    pub fn below(&mut self, n: u64) -> u64 {
+        ((self.next_u64() as u128 * n as u128) >> 64) as u64
+    }
+}
+
+//
+// The Wilson score interval of a proportion at 95% confidence, which
+// unlike the normal approximation stays within [0, 1] for rare classes.
+//
+pub fn wilson_interval(hits: u64, samples: u64) -> (f64, f64) {
+    if samples == 0 {
+        return (0.0, 1.0);
+    }
+    let z = 1.96f64;
+    let n = samples as f64;
+    let p = hits as f64 / n;
+    let center = p + z * z / (2.0 * n);
+    let spread = z * (p * (1.0 - p) / n + z * z / (4.0 * n * n)).sqrt();
+    let scale = 1.0 + z * z / n;
+    (
+        ((center - spread) / scale).max(0.0),
+        ((center + spread) / scale).min(1.0),
+    )
+}
+
+// The dominators of a node, closest first, None if it has more than
+// `budget` ancestors.
+pub fn local_dominators(
+    graph: &Graph,
+    preds: &Adjacency,
+    is_root: &[bool],
+    node: u32,
+    budget: usize,
+) -> Option<Vec<u32>> {
+    let mut local: HashMap<u32, u32> = HashMap::new();
+    let mut ancestors = vec![node];
+    local.insert(node, 0);
+    let mut queue = VecDeque::from([node]);
+    while let Some(n) = queue.pop_front() {
+        for p in preds.neighbors(n) {
+            if local.contains_key(&p) {
+                continue;
+            }
+            if ancestors.len() == budget {
+                return None;
+            }
+            local.insert(p, ancestors.len() as u32);
+            ancestors.push(p);
+            queue.push_back(p);
+        }
+    }
+    let subgraph = Graph {
+        successors: Adjacency::build(ancestors.len(), |i| {
+            let mut succ: Vec<u32> = graph
+                .successors
+                .neighbors(ancestors[i])
+                .filter_map(|s| local.get(&s).copied())
+                .collect();
+            succ.sort_unstable();
+            succ
+        }),
+        roots: ancestors
+            .iter()
+            .enumerate()
+            .filter(|&(_, &a)| is_root[a as usize])
+            .map(|(i, _)| i as u32)
+            .collect(),
+    };
+    let tree = dominator::build(&subgraph);
+    if !tree.is_reachable(0) {
+        return Some(Vec::new());
+    }
+    Some(
+        tree.dominators(0)
+            .into_iter()
+            .map(|d| ancestors[d as usize])
+            .collect(),
+    )
+}
+
+#[derive(Debug)]
+pub struct ClassEstimate {
+    pub class_name: String,
+    // Samples retained by instances of the class.
+    pub hits: u64,
+    pub bytes: u64,
+    // The 95% confidence interval, counting unresolved samples as possible
+    // hits for the upper bound.
+    pub low: u64,
+    pub high: u64,
+}
+
+#[derive(Debug)]
+pub struct SampledRetained {
+    pub samples: u64,
+    pub unresolved: u64,
+    // Shallow size of the reachable objects, which the samples are taken from.
+    pub live_bytes: u64,
+    pub classes: Vec<ClassEstimate>,
+}
+
+pub fn sampled_retained_by_class(
+    snapshot: &Snapshot,
+    graph: &Graph,
+    samples: u64,
+    budget: usize,
+    seed: u64,
+) -> SampledRetained {
+    let live = crate::reachability::mark(graph);
+    let mut nodes = Vec::new();
+    let mut cumulative = Vec::new();
+    let mut live_bytes = 0;
+    for (node, object) in snapshot.objects.iter().enumerate() {
+        let size = snapshot.shallow_size(object);
+        if live[node] && size > 0 {
+            live_bytes += size;
+            nodes.push(node as u32);
+            cumulative.push(live_bytes);
+        }
+    }
+    let mut report = SampledRetained {
+        samples,
+        unresolved: 0,
+        live_bytes,
+        classes: Vec::new(),
+    };
+    if live_bytes == 0 {
+        return report;
+    }
+
+    let preds = graph.predecessors();
+    let mut is_root = vec![false; graph.len()];
+    for &r in &graph.roots {
+        is_root[r as usize] = true;
+    }
+    let mut rng = Rng::new(seed);
+    let mut hits: HashMap<String, u64> = HashMap::new();
+    for _ in 0..samples {
+        let target = rng.below(live_bytes);
+        let node = nodes[cumulative.partition_point(|&c| c <= target)];
+        let dominators = match local_dominators(graph, &preds, &is_root, node, budget) {
+            Some(dominators) => dominators,
+            None => {
+                report.unresolved += 1;
+                continue;
+            }
+        };
+        let classes: HashSet<String> = std::iter::once(node)
+            .chain(dominators)
+            .map(|n| snapshot.object_class_name(&snapshot.objects[n as usize]))
+            .collect();
+        for class_name in classes {
+            *hits.entry(class_name).or_insert(0) += 1;
+        }
+    }
+
+    let scale = |fraction: f64| (fraction * live_bytes as f64).round() as u64;
+    report.classes = hits
+        .into_iter()
+        .map(|(class_name, hits)| ClassEstimate {
+            bytes: scale(hits as f64 / samples as f64),
+            low: scale(wilson_interval(hits, samples).0),
+            high: scale(wilson_interval(hits + report.unresolved, samples).1),
+            class_name,
+            hits,
+        })
+        .collect();
+    report.classes.sort_by(|a, b| {
+        b.hits
+            .cmp(&a.hits)
+            .then_with(|| a.class_name.cmp(&b.class_name))
+    });
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::{FieldTag, GcRootKind, Value};
+    use crate::testing::Dump;
+
+    #[test]
+    fn intervals_stay_within_bounds() {
+        assert_eq!(wilson_interval(0, 0), (0.0, 1.0));
+        let (low, high) = wilson_interval(50, 100);
+        assert!((low - 0.4038).abs() < 1e-3 && (high - 0.5962).abs() < 1e-3);
+        assert_eq!(wilson_interval(0, 10).0, 0.0);
+        assert!(wilson_interval(0, 10).1 > 0.0);
+        assert_eq!(wilson_interval(10, 10).1, 1.0);
+
+        let draws: Vec<u64> = {
+            let mut rng = Rng::new(7);
+            (0..100).map(|_| rng.below(10)).collect()
+        };
+        assert!(draws.iter().all(|&d| d < 10));
+        let mut rng = Rng::new(7);
+        assert_eq!(draws[0], rng.below(10));
+    }
+
+    // A diamond under a holder: holder -> a -> (b, c) -> d.
+    fn diamond() -> (Snapshot, Vec<u64>) {
+        let mut dump = Dump::new();
+        let node = dump.class(
+            "test/Node",
+            dump.object,
+            &[
+                ("left", FieldTag::NormalObject),
+                ("right", FieldTag::NormalObject),
+            ],
+        );
+        let holder = dump.class(
+            "test/Holder",
+            dump.object,
+            &[("data", FieldTag::NormalObject)],
+        );
+        let d = dump.primitive_array(FieldTag::Byte, &[0; 10_000]);
+        let b = dump.instance(node, &[Value::Object(d)]);
+        let c = dump.instance(node, &[Value::Object(d)]);
+        let a = dump.instance(node, &[Value::Object(b), Value::Object(c)]);
+        let holder = dump.instance(holder, &[Value::Object(a)]);
+        dump.root(GcRootKind::JniGlobal, holder);
+        (dump.load(), vec![holder, a, b, c, d])
+    }
+
+    #[test]
+    fn local_dominators_are_those_of_the_whole_heap() {
+        let (snapshot, ids) = diamond();
+        let graph = Graph::build(&snapshot);
+        let tree = dominator::build(&graph);
+        let preds = graph.predecessors();
+        let mut is_root = vec![false; graph.len()];
+        for &r in &graph.roots {
+            is_root[r as usize] = true;
+        }
+        for node in 0..graph.len() as u32 {
+            if tree.is_reachable(node) {
+                let local = local_dominators(&graph, &preds, &is_root, node, 100);
+                assert_eq!(local, Some(tree.dominators(node)));
+            }
+        }
+        let d = snapshot.index_of(ids[4]).unwrap();
+        assert_eq!(local_dominators(&graph, &preds, &is_root, d, 2), None);
+    }
+
+    #[test]
+    fn samples_land_in_what_retains_the_bytes() {
+        let (snapshot, _) = diamond();
+        let graph = Graph::build(&snapshot);
+        let report = sampled_retained_by_class(&snapshot, &graph, 200, 100, 1);
+        let live = crate::reachability::mark(&graph);
+        let live_bytes: u64 = (0..snapshot.objects.len())
+            .filter(|&n| live[n])
+            .map(|n| snapshot.shallow_size(&snapshot.objects[n]))
+            .sum();
+        assert_eq!((report.samples, report.unresolved), (200, 0));
+        assert_eq!(report.live_bytes, live_bytes);
+        let holder = &report.classes[0];
+        assert_eq!(holder.class_name, "test.Holder");
+        assert!(holder.hits > 190);
+        assert!(holder.low <= holder.bytes && holder.bytes <= holder.high);
+        assert!(holder.high <= live_bytes);
+
+        let unresolved = sampled_retained_by_class(&snapshot, &graph, 50, 1, 1);
+        assert!(unresolved.unresolved > 40);
+    }
+}
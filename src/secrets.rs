@@ -0,0 +1,242 @@
+//
+// Scanning of the character data in the heap for things that look like
+// credentials. The patterns are deliberately simple so that they can be
+// matched quickly against every String and char[]/byte[] in the dump;
+// false positives are expected and left to whoever reads the report.
+//
+use crate::heap::{FieldTag, HeapObject};
+use crate::regex::Regex;
+use crate::snapshot::Snapshot;
+use crate::strings::{for_each_text, TextSource};
+
+pub struct SecretPattern {
+    pub name: &'static str,
+    regex: Regex,
+}
+
+const PATTERNS: &[(&str, &str)] = &[
+    ("aws-access-key", r"\b(AKIA|ASIA)[0-9A-Z]{16}\b"),
+    (
+        "jwt",
+        r"\beyJ[A-Za-z0-9_-]{10,}\.eyJ[A-Za-z0-9_-]{10,}\.[A-Za-z0-9_-]{10,}",
+    ),
+    ("pem-private-key", r"-----BEGIN ([A-Z]+ )*PRIVATE KEY-----"),
+    (
+        "password-assignment",
+        r#"(?i)\b(password|passwd|pwd|secret|token|api_?key)\s*[=:]\s*[^\s&;,"']{3,}"#,
+    ),
+    ("github-token", r"\bgh[pousr]_[A-Za-z0-9]{36}\b"),
+    ("slack-token", r"\bxox[abprs]-[A-Za-z0-9-]{10,}"),
+];
+
+pub fn builtin_patterns() -> Vec<SecretPattern> {
+    PATTERNS
+        .iter()
+        .map(|&(name, pattern)| SecretPattern {
+            name,
+            regex: Regex::new(pattern).expect("invalid built-in pattern"),
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct Finding {
+    pub pattern: &'static str,
+    // The String or array containing the match.
+    pub node: u32,
+    pub source: TextSource,
+    pub text: String,
+}
+
+pub fn scan(snapshot: &Snapshot, patterns: &[SecretPattern]) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for_each_text(snapshot, |node, source, text| {
+        for pattern in patterns {
+            for (start, end) in pattern.regex.find_all(text) {
+                findings.push(Finding {
+                    pattern: pattern.name,
+                    node,
+                    source,
+                    text: text[start..end].iter().collect(),
+                });
+            }
+        }
+    });
+    findings
+}
+
+//
+// Hides most of a match so that reports can be shared: the first four
+// chars are kept, which is usually enough to tell the kind of secret apart.
+//
+pub fn mask(text: &str) -> String {
+    let shown: String = text.chars().take(4).collect();
+    let hidden = text.chars().count().saturating_sub(4);
+    format!("{}{}", shown, "*".repeat(hidden.min(16)))
+}
+
+// Sizes in bytes of common symmetric keys, HMAC secrets and hashes.
+const KEY_LENGTHS: &[usize] = &[16, 24, 32, 48, 64];
+const BASE64_LENGTHS: std::ops::RangeInclusive<usize> = 16..=128;
+
+#[derive(Debug)]
+pub struct EntropyFinding {
+    // The byte[] or char[] that looks like key material.
+    pub node: u32,
+    pub source: TextSource,
+    pub length: usize,
+    // Shannon entropy in bits per byte/char, and the maximum possible for
+    // data of this length (a random key gets close to it).
+    pub entropy: f64,
+    pub max_entropy: f64,
+}
+
+pub fn shannon_entropy<T: Copy + Into<u32>>(data: &[T]) -> f64 {
+    let mut counts = std::collections::HashMap::new();
+    for &x in data {
+        *counts.entry(x.into()).or_insert(0u32) += 1;
+    }
+    let n = data.len() as f64;
+    counts
+        .values()
+        .map(|&c| {
+            let p = c as f64 / n;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+fn is_base64(c: u32) -> bool {
+    match char::from_u32(c) {
+        Some(c) => c.is_ascii_alphanumeric() || "+/=-_".contains(c),
+        None => false,
+    }
+}
+
+//
+// Base64 text is only considered when it mixes at least two of upper
+// case, lower case and digits, which rules out most identifiers.
+//
+fn looks_like_base64(text: &[u32]) -> bool {
+    let has = |f: fn(&char) -> bool| {
+        text.iter()
+            .any(|&c| char::from_u32(c).is_some_and(|c| f(&c)))
+    };
+    BASE64_LENGTHS.contains(&text.len())
+        && text.iter().all(|&c| is_base64(c))
+        && [
+            has(char::is_ascii_uppercase),
+            has(char::is_ascii_lowercase),
+            has(char::is_ascii_digit),
+        ]
+        .iter()
+        .filter(|&&b| b)
+        .count()
+            >= 2
+}
+
+//
+// Arrays whose contents are close to random: byte[]s of a typical key
+// length, and byte[]s or char[]s of base64 text. The entropy has to be at
+// least 85% of the maximum for the length, e.g. 3.4 bits for 16 bytes.
+//
+pub fn high_entropy_arrays(snapshot: &Snapshot) -> Vec<EntropyFinding> {
+    let mut findings = Vec::new();
+    for (node, object) in snapshot.objects.iter().enumerate() {
+        let array = match object {
+            HeapObject::PrimitiveArray(a) => a,
+            _ => continue,
+        };
+        let (source, units): (TextSource, Vec<u32>) = match array.element_tag {
+            FieldTag::Byte => (
+                TextSource::ByteArray,
+                array.data.iter().map(|&b| b as u32).collect(),
+            ),
+            FieldTag::Char => (
+                TextSource::CharArray,
+                array
+                    .data
+                    .chunks_exact(2)
+                    .map(|c| u16::from_be_bytes([c[0], c[1]]) as u32)
+                    .collect(),
+            ),
+            _ => continue,
+        };
+        let alphabet = if looks_like_base64(&units) {
+            64
+        } else if source == TextSource::ByteArray
+            && KEY_LENGTHS.contains(&units.len())
+            && !units.iter().all(|&b| (0x20..0x7f).contains(&b))
+        {
+            256
+        } else {
+            continue;
+        };
+        let entropy = shannon_entropy(&units);
+        let max_entropy = (units.len().min(alphabet) as f64).log2();
+        if entropy >= 0.85 * max_entropy {
+            findings.push(EntropyFinding {
+                node: node as u32,
+                source,
+                length: units.len(),
+                entropy,
+                max_entropy,
+            });
+        }
+    }
+    findings.sort_by(|a, b| {
+        (b.entropy / b.max_entropy)
+            .partial_cmp(&(a.entropy / a.max_entropy))
+            .unwrap()
+    });
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Dump;
+
+    #[test]
+    fn entropy_is_in_bits() {
+        assert_eq!(shannon_entropy(&[7u8; 32]), 0.0);
+        assert_eq!(shannon_entropy(&(0..16u8).collect::<Vec<_>>()), 4.0);
+        assert_eq!(shannon_entropy(&[0u8, 1, 0, 1]), 1.0);
+    }
+
+    #[test]
+    fn random_arrays_look_like_keys() {
+        let mut dump = Dump::new();
+        let key: Vec<u8> = (0..32u32).map(|i| (i * 37 + 11) as u8).collect();
+        let key = dump.primitive_array(FieldTag::Byte, &key);
+        let base64: Vec<u8> = "q8Zr2XkP0vLw7TjN4bYc"
+            .encode_utf16()
+            .flat_map(|u| u.to_be_bytes())
+            .collect();
+        let base64 = dump.primitive_array(FieldTag::Char, &base64);
+        dump.primitive_array(FieldTag::Byte, &[0; 32]);
+        // Printable text of a key length, and base64-like identifiers.
+        dump.primitive_array(FieldTag::Byte, b"hello, world 123");
+        dump.primitive_array(FieldTag::Byte, b"someidentifiername");
+        let snapshot = dump.load();
+
+        let mut found: Vec<(u64, TextSource, usize)> = high_entropy_arrays(&snapshot)
+            .iter()
+            .map(|f| {
+                (
+                    snapshot.objects[f.node as usize].object_id(),
+                    f.source,
+                    f.length,
+                )
+            })
+            .collect();
+        found.sort_by_key(|f| f.0);
+        assert_eq!(
+            found,
+            vec![
+                (key, TextSource::ByteArray, 32),
+                (base64, TextSource::CharArray, 20)
+            ]
+        );
+    }
+}
@@ -0,0 +1,429 @@
+//
+// Sharded summaries of a dump, for heaps too large for one machine. Each
+// process parses one contiguous run of heap dump segments (see
+// Snapshot::load_shard()) and exports a shard: its class histogram, the id
+// range of its objects and a few graph statistics. Shards of the same dump
+// then merge into the figures of the whole heap.
+//
+// Shards are runs of segments rather than ranges of object ids: segments
+// carry their length and a process skips those of other shards unread, but
+// sub-records do not, so finding the objects of an id range would mean
+// parsing the whole heap in every process. The id ranges of the shards may
+// then overlap (dumps written by several threads interleave the heap), which
+// the merge does not mind: histograms and counts add up whatever the split.
+//
+// Shards are text, one `key value` line each and a `class` line per class:
+//
+//     hprof-cat-shard 2
+//     dump <fingerprint of the top-level records>
+//     shard <index> <count>
+//     segments <first> <end> <total>
+//     ids <lowest> <highest>
+//     objects <count>
+//     shallow <bytes>
+//     references <count>
+//     local <references to objects of the same shard>
+//     roots <count>
+//     class <instances> <shallow> <name>
+//
+// with the names of the classes as the JVM has them (`java/lang/String`,
+// `[B`), those of the reports being for display only.
+//
+use crate::graph;
+use crate::histogram::{self, HistogramEntry};
+use crate::idhash::IdHasher;
+use crate::signature;
+use crate::snapshot::{ShardExtent, Snapshot};
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::hash::{Hash, Hasher};
+
+const VERSION: u32 = 2;
+
+#[derive(Debug)]
+pub struct Shard {
+    pub dump: u64,
+    pub index: usize,
+    pub count: usize,
+    pub segments: (usize, usize),
+    pub total_segments: usize,
+    // The lowest and highest object ids of the shard, (0, 0) if it has none.
+    pub ids: (u64, u64),
+    pub objects: u64,
+    pub shallow: u64,
+    // Non-null references from the objects of the shard.
+    pub references: u64,
+    // Those to objects of the shard itself.
+    pub local: u64,
+    pub roots: u64,
+    pub histogram: Vec<HistogramEntry>,
+}
+
+//
+// A hash of the top-level records of a dump, which every shard of it reads
+// whole, to tell shards of different dumps apart.
+//
+pub fn dump_fingerprint(snapshot: &Snapshot, extent: &ShardExtent) -> u64 {
+    let mut hasher = IdHasher::default();
+    snapshot.header.format.hash(&mut hasher);
+    snapshot.header.timestamp_ms().hash(&mut hasher);
+    let mut counts: Vec<(u8, u64)> = snapshot
+        .record_counts
+        .iter()
+        .map(|(&t, &c)| (t, c))
+        .collect();
+    counts.sort_unstable();
+    counts.hash(&mut hasher);
+    snapshot.strings.len().hash(&mut hasher);
+    extent.total_segments.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl Shard {
+    // The shard of a snapshot loaded by Snapshot::load_shard().
+    pub fn build(snapshot: &Snapshot, extent: &ShardExtent, index: usize, count: usize) -> Shard {
+        let own = &snapshot.objects[..extent.objects];
+        let mut shard = Shard {
+            dump: dump_fingerprint(snapshot, extent),
+            index,
+            count,
+            segments: (extent.segments.start, extent.segments.end),
+            total_segments: extent.total_segments,
+            ids: (0, 0),
+            objects: own.len() as u64,
+            shallow: own.iter().map(|o| snapshot.shallow_size(o)).sum(),
+            references: 0,
+            local: 0,
+            roots: snapshot.roots.len() as u64,
+            histogram: histogram::histogram_by(
+                snapshot,
+                |i| (i as usize) < extent.objects,
+                |object| snapshot.object_jvm_class_name(object),
+            ),
+        };
+        let ids = own.iter().map(|o| o.object_id());
+        if let (Some(low), Some(high)) = (ids.clone().min(), ids.max()) {
+            shard.ids = (low, high);
+        }
+        for object in own {
+            for target in graph::outgoing_references(snapshot, object) {
+                shard.references += 1;
+                if snapshot
+                    .index_of(target)
+                    .is_some_and(|i| (i as usize) < extent.objects)
+                {
+                    shard.local += 1;
+                }
+            }
+        }
+        shard
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "hprof-cat-shard {}", VERSION).unwrap();
+        writeln!(out, "dump {:016x}", self.dump).unwrap();
+        writeln!(out, "shard {} {}", self.index, self.count).unwrap();
+        writeln!(
+            out,
+            "segments {} {} {}",
+            self.segments.0, self.segments.1, self.total_segments
+        )
+        .unwrap();
+        writeln!(out, "ids {:#x} {:#x}", self.ids.0, self.ids.1).unwrap();
+        writeln!(out, "objects {}", self.objects).unwrap();
+        writeln!(out, "shallow {}", self.shallow).unwrap();
+        writeln!(out, "references {}", self.references).unwrap();
+        writeln!(out, "local {}", self.local).unwrap();
+        writeln!(out, "roots {}", self.roots).unwrap();
+        for entry in &self.histogram {
+            writeln!(
+                out,
+                "class {} {} {}",
+                entry.instances, entry.shallow, entry.class_name
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    pub fn parse(text: &str) -> Result<Shard, String> {
+        let mut shard = Shard {
+            dump: 0,
+            index: 0,
+            count: 0,
+            segments: (0, 0),
+            total_segments: 0,
+            ids: (0, 0),
+            objects: 0,
+            shallow: 0,
+            references: 0,
+            local: 0,
+            roots: 0,
+            histogram: Vec::new(),
+        };
+        let mut lines = text.lines();
+        if lines.next() != Some(&format!("hprof-cat-shard {}", VERSION)) {
+            return Err("not a shard, or of another version".to_string());
+        }
+        for line in lines {
+            let (key, rest) = line.split_once(' ').unwrap_or((line, ""));
+            let values: Vec<&str> = rest.splitn(3, ' ').collect();
+            let number = |i: usize| -> Result<u64, String> {
+                let value = values.get(i).copied().unwrap_or("");
+                let parsed = match value.strip_prefix("0x") {
+                    Some(hex) => u64::from_str_radix(hex, 16),
+                    None => value.parse(),
+                };
+                parsed.map_err(|_| format!("bad line: {}", line))
+            };
+            match key {
+                "dump" => {
+                    shard.dump =
+                        u64::from_str_radix(rest, 16).map_err(|_| format!("bad line: {}", line))?
+                }
+                "shard" => {
+                    shard.index = number(0)? as usize;
+                    shard.count = number(1)? as usize;
+                }
+                "segments" => {
+                    shard.segments = (number(0)? as usize, number(1)? as usize);
+                    shard.total_segments = number(2)? as usize;
+                }
+                "ids" => shard.ids = (number(0)?, number(1)?),
+                "objects" => shard.objects = number(0)?,
+                "shallow" => shard.shallow = number(0)?,
+                "references" => shard.references = number(0)?,
+                "local" => shard.local = number(0)?,
+                "roots" => shard.roots = number(0)?,
+                "class" => shard.histogram.push(HistogramEntry {
+                    instances: number(0)?,
+                    shallow: number(1)?,
+                    class_name: values
+                        .get(2)
+                        .ok_or_else(|| format!("bad line: {}", line))?
+                        .to_string(),
+                }),
+                _ => return Err(format!("unknown line: {}", line)),
+            }
+        }
+        if shard.count == 0 || shard.index >= shard.count {
+            return Err("missing or bad shard line".to_string());
+        }
+        Ok(shard)
+    }
+}
+
+// The figures of a whole dump, from all its shards.
+#[derive(Debug)]
+pub struct Merged {
+    pub shards: usize,
+    pub objects: u64,
+    pub shallow: u64,
+    pub references: u64,
+    pub local: u64,
+    pub roots: u64,
+    pub histogram: Vec<HistogramEntry>,
+}
+
+//
+// Merges the shards of one dump, which must all be there exactly once. The
+// classes of the merged histogram are named in Java syntax.
+//
+pub fn merge(mut shards: Vec<Shard>) -> Result<Merged, String> {
+    let first = shards.first().ok_or("no shards")?;
+    let (dump, count) = (first.dump, first.count);
+    if let Some(other) = shards.iter().find(|s| s.dump != dump || s.count != count) {
+        return Err(format!(
+            "shard {} of {} is not from the same dump or split",
+            other.index, other.count
+        ));
+    }
+    shards.sort_by_key(|s| s.index);
+    for (i, shard) in shards.iter().enumerate() {
+        if shard.index != i {
+            return Err(format!("shard {} of {} is missing or repeated", i, count));
+        }
+    }
+    if shards.len() != count {
+        return Err(format!("{} shards of {}", shards.len(), count));
+    }
+
+    let mut merged = Merged {
+        shards: count,
+        objects: 0,
+        shallow: 0,
+        references: 0,
+        local: 0,
+        roots: 0,
+        histogram: Vec::new(),
+    };
+    let mut classes: HashMap<String, HistogramEntry> = HashMap::new();
+    for shard in shards {
+        merged.objects += shard.objects;
+        merged.shallow += shard.shallow;
+        merged.references += shard.references;
+        merged.local += shard.local;
+        merged.roots += shard.roots;
+        for entry in shard.histogram {
+            match classes.get_mut(&entry.class_name) {
+                Some(total) => {
+                    total.instances += entry.instances;
+                    total.shallow += entry.shallow;
+                }
+                None => {
+                    classes.insert(entry.class_name.clone(), entry);
+                }
+            }
+        }
+    }
+    merged.histogram = classes
+        .into_values()
+        .map(|entry| HistogramEntry {
+            class_name: signature::class_name(&entry.class_name),
+            ..entry
+        })
+        .collect();
+    merged.histogram.sort_by(|a, b| {
+        b.shallow
+            .cmp(&a.shallow)
+            .then_with(|| a.class_name.cmp(&b.class_name))
+    });
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::{FieldTag, GcRootKind, Value};
+    use crate::synthetic::{self, Options, Shape};
+    use crate::testing::{Dump, TempFile};
+    use crate::window::Window;
+    use std::fs;
+
+    // Shards have the names of the JVM, their merge those of the reports.
+    #[test]
+    fn shards_keep_jvm_names() {
+        let options = Options {
+            classes: 5,
+            objects: 500,
+            shape: Shape::Mixed,
+            ..Options::default()
+        };
+        let file = format!("hprof-cat-shard-{}.hprof", std::process::id());
+        let path = std::env::temp_dir().join(file);
+        let path = path.to_str().unwrap();
+        synthetic::generate(&options, fs::File::create(path).unwrap()).unwrap();
+        let snapshot = Snapshot::load(path);
+        let shards: Vec<Shard> = (0..2)
+            .map(|i| {
+                let (snapshot, extent) =
+                    Snapshot::load_shard(path, i, 2, &Window::default()).unwrap();
+                let text = Shard::build(&snapshot, &extent, i, 2).to_text();
+                Shard::parse(&text).unwrap()
+            })
+            .collect();
+        fs::remove_file(path).unwrap();
+
+        let names: Vec<&str> = shards
+            .iter()
+            .flat_map(|s| s.histogram.iter().map(|e| e.class_name.as_str()))
+            .collect();
+        // Shards keep the names the JVM has.
+        for name in ["java/lang/Class", "[B", "[Ljava/lang/Object;"] {
+            assert!(names.contains(&name), "{}", name);
+        }
+        assert!(names.iter().all(|n| !n.ends_with("[]")));
+
+        let merged = merge(shards).unwrap();
+        let whole = histogram::histogram(&snapshot, |_| true);
+        let entries = |h: &[HistogramEntry]| -> Vec<(String, u64, u64)> {
+            h.iter()
+                .map(|e| (e.class_name.clone(), e.instances, e.shallow))
+                .collect()
+        };
+        assert_eq!(entries(&merged.histogram), entries(&whole));
+    }
+
+    #[test]
+    fn shards_partition_the_dump() {
+        let mut dump = Dump::new();
+        dump.set_segment_bytes(512);
+        let node = dump.class(
+            "test/Node",
+            dump.object,
+            &[("next", FieldTag::NormalObject)],
+        );
+        let mut next = 0;
+        for _ in 0..300 {
+            next = dump.instance(node, &[Value::Object(next)]);
+        }
+        dump.root(GcRootKind::JniGlobal, next);
+        let file = TempFile::new(&dump.bytes());
+        let snapshot = Snapshot::load(file.path());
+        let shards: Vec<Shard> = (0..3)
+            .map(|i| {
+                let (snapshot, extent) =
+                    Snapshot::load_shard(file.path(), i, 3, &Window::default()).unwrap();
+                let text = Shard::build(&snapshot, &extent, i, 3).to_text();
+                let shard = Shard::parse(&text).unwrap();
+                assert_eq!(shard.to_text(), text);
+                shard
+            })
+            .collect();
+
+        assert_eq!(shards[0].segments.0, 0);
+        assert_eq!(shards[2].segments.1, shards[2].total_segments);
+        for pair in shards.windows(2) {
+            assert_eq!(pair[0].segments.1, pair[1].segments.0);
+            // Dump writes objects in id order, HotSpot need not.
+            assert!(pair[0].ids.1 < pair[1].ids.0);
+            assert_eq!(pair[0].dump, pair[1].dump);
+        }
+        let merged = merge(shards).unwrap();
+        assert_eq!(merged.shards, 3);
+        assert_eq!(merged.objects, snapshot.objects.len() as u64);
+        let references: usize = snapshot
+            .objects
+            .iter()
+            .map(|o| graph::outgoing_references(&snapshot, o).len())
+            .sum();
+        assert_eq!(merged.references, references as u64);
+        assert!(merged.local < merged.references);
+    }
+
+    #[test]
+    fn shards_merge_only_when_complete() {
+        let shard = |dump: u64, index: usize| {
+            let text = format!("hprof-cat-shard 2\ndump {:x}\nshard {} 2\n", dump, index);
+            Shard::parse(&text).unwrap()
+        };
+        let error = |shards: Vec<Shard>| merge(shards).unwrap_err();
+        assert_eq!(error(Vec::new()), "no shards");
+        assert_eq!(error(vec![shard(1, 0)]), "1 shards of 2");
+        assert_eq!(
+            error(vec![shard(1, 0), shard(1, 0)]),
+            "shard 1 of 2 is missing or repeated"
+        );
+        assert_eq!(
+            error(vec![shard(1, 0), shard(2, 1)]),
+            "shard 1 of 2 is not from the same dump or split"
+        );
+        assert!(merge(vec![shard(1, 1), shard(1, 0)]).is_ok());
+
+        assert!(Shard::parse("hprof-cat-shard 1\n").is_err());
+        assert_eq!(
+            Shard::parse("hprof-cat-shard 2\nshard 2 2\n").unwrap_err(),
+            "missing or bad shard line"
+        );
+        assert_eq!(
+            Shard::parse("hprof-cat-shard 2\nobjects x\n").unwrap_err(),
+            "bad line: objects x"
+        );
+        assert_eq!(
+            Shard::parse("hprof-cat-shard 2\ncolor blue\n").unwrap_err(),
+            "unknown line: color blue"
+        );
+    }
+}
@@ -0,0 +1,255 @@
+//
+// Java syntax for the type descriptors of the JVM (JVMS 4.3), in which
+// HPROF records the signatures of the methods of stack frames:
+// `(Ljava/lang/String;[I)V` is `void (String, int[])`. The types of
+// parameters and results go by their simple names, as in stack traces
+// (the class of the method says where it is).
+//
+// Class names are given the same way, the names of array classes being
+// descriptors as well: `[Ljava/lang/String;` is `java.lang.String[]` and
+// `[[B` is `byte[][]`. Nested classes keep the binary names the JVM gives
+// them, `java.util.HashMap$Node`, by which jmap and JFR name them too and
+// which unlike `java.util.HashMap.Node` don't read as packages. The class
+// tables of the reports can leave the packages out (see Names).
+//
+
+//
+// The type at the start of a field descriptor, and the rest after it.
+// Classes are named by their simple names, or whole if `qualified`.
+//
+fn field_type(descriptor: &str, qualified: bool) -> Option<(String, &str)> {
+    let dimensions = descriptor.bytes().take_while(|&b| b == b'[').count();
+    let rest = &descriptor[dimensions..];
+    let primitive = match rest.as_bytes().first()? {
+        b'B' => "byte",
+        b'C' => "char",
+        b'D' => "double",
+        b'F' => "float",
+        b'I' => "int",
+        b'J' => "long",
+        b'S' => "short",
+        b'Z' => "boolean",
+        b'L' => "",
+        _ => return None,
+    };
+    let (name, rest) = if primitive.is_empty() {
+        let end = rest.find(';')?;
+        let class = &rest[1..end];
+        let simple = class.rsplit(['/', '.']).next().unwrap_or(class);
+        if simple.is_empty() {
+            return None;
+        }
+        let name = if qualified {
+            class.replace('/', ".")
+        } else {
+            simple.to_string()
+        };
+        (name, &rest[end + 1..])
+    } else {
+        (primitive.to_string(), &rest[1..])
+    };
+    Some((format!("{}{}", name, "[]".repeat(dimensions)), rest))
+}
+
+// The type of a field descriptor such as `[[I` (`int[][]`).
+pub fn field(descriptor: &str) -> Option<String> {
+    match field_type(descriptor, false)? {
+        (name, "") => Some(name),
+        _ => None,
+    }
+}
+
+//
+// The name of a class as the JVM has it (`java/lang/Thread`, `[[B`), or
+// as jmap gives it (`java.lang.Thread`, `[Ljava.lang.String;`), in Java
+// syntax. Names that are that already are returned as they are. Names
+// stay qualified (`java.lang.String[]`, not `String[]`) so that classes
+// of different packages are told apart, unless a report is asked for
+// short names (see Names).
+//
+pub fn class_name(name: &str) -> String {
+    if name.starts_with('[') {
+        if let Some((name, "")) = field_type(name, true) {
+            return name;
+        }
+    }
+    name.replace('/', ".")
+}
+
+//
+// The name of a class as class_name() gives it without its package:
+// `String[]`, `HashMap$Node`. The package ends at the last dot before the
+// first `$`, so that hidden classes such as
+// `Foo$$Lambda$12/0x0000000800c0b000` keep their address.
+//
+pub fn short_class_name(name: &str) -> String {
+    let name = class_name(name);
+    let outer = name.find('$').unwrap_or(name.len());
+    match name[..outer].rfind('.') {
+        Some(dot) => name[dot + 1..].to_string(),
+        None => name,
+    }
+}
+
+//
+// How the class tables of the reports name classes, set by --names:
+// qualified (the default), which tells apart the classes of different
+// packages, or short, without their packages, for reading.
+//
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Names {
+    #[default]
+    Qualified,
+    Short,
+}
+
+impl Names {
+    pub fn parse(s: &str) -> Option<Names> {
+        match s {
+            "qualified" => Some(Names::Qualified),
+            "short" => Some(Names::Short),
+            _ => None,
+        }
+    }
+
+    // A class name (or one of class_name()) of a report in these names.
+    pub fn class(self, name: &str) -> String {
+        match self {
+            Names::Qualified => class_name(name),
+            Names::Short => short_class_name(name),
+        }
+    }
+}
+
+//
+// The method `name` (which may be qualified by its class) with the
+// parameters and result of its descriptor, `void Foo.bar(String, int[])`,
+// or None if the descriptor isn't one. Constructors and static
+// initializers have no result.
+//
+pub fn method(name: &str, descriptor: &str) -> Option<String> {
+    let mut rest = descriptor.strip_prefix('(')?;
+    let mut parameters = Vec::new();
+    while !rest.starts_with(')') {
+        let (parameter, after) = field_type(rest, false)?;
+        parameters.push(parameter);
+        rest = after;
+    }
+    let result = match &rest[1..] {
+        "V" => "void".to_string(),
+        result => field(result)?,
+    };
+    let parameters = parameters.join(", ");
+    if name.ends_with("<init>") || name.ends_with("<clinit>") {
+        Some(format!("{}({})", name, parameters))
+    } else {
+        Some(format!("{} {}({})", result, name, parameters))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn field_descriptors() {
+        let table = [
+            ("B", Some("byte")),
+            ("C", Some("char")),
+            ("D", Some("double")),
+            ("F", Some("float")),
+            ("I", Some("int")),
+            ("J", Some("long")),
+            ("S", Some("short")),
+            ("Z", Some("boolean")),
+            ("[[I", Some("int[][]")),
+            ("Ljava/lang/String;", Some("String")),
+            ("[Ljava/util/HashMap$Node;", Some("HashMap$Node[]")),
+            ("", None),
+            ("V", None),
+            ("Q", None),
+            ("[", None),
+            ("Ljava/lang/String", None),
+            ("L;", None),
+            ("II", None),
+        ];
+        for (descriptor, expected) in table {
+            assert_eq!(field(descriptor).as_deref(), expected, "{:?}", descriptor);
+        }
+    }
+
+    #[test]
+    fn method_descriptors() {
+        let table = [
+            ("run", "()V", Some("void run()")),
+            (
+                "Foo.bar",
+                "(Ljava/lang/String;[I)J",
+                Some("long Foo.bar(String, int[])"),
+            ),
+            (
+                "copy",
+                "([[BIZ)[Ljava/lang/Object;",
+                Some("Object[] copy(byte[][], int, boolean)"),
+            ),
+            ("Foo.<init>", "(DF)V", Some("Foo.<init>(double, float)")),
+            ("<clinit>", "()V", Some("<clinit>()")),
+            ("bad", "V", None),
+            ("bad", "(I", None),
+            ("bad", "(X)V", None),
+            ("bad", "()", None),
+            ("bad", "()VV", None),
+        ];
+        for (name, descriptor, expected) in table {
+            assert_eq!(
+                method(name, descriptor).as_deref(),
+                expected,
+                "{:?}",
+                descriptor
+            );
+        }
+    }
+
+    #[test]
+    fn class_names() {
+        let table = [
+            ("java/lang/Thread", "java.lang.Thread"),
+            ("java.lang.Thread", "java.lang.Thread"),
+            ("[[B", "byte[][]"),
+            ("[Ljava/lang/String;", "java.lang.String[]"),
+            ("[Ljava.lang.String;", "java.lang.String[]"),
+            ("java/util/HashMap$Node", "java.util.HashMap$Node"),
+            ("int[]", "int[]"),
+            ("[Q", "[Q"),
+        ];
+        for (name, expected) in table {
+            assert_eq!(class_name(name), expected, "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn short_class_names() {
+        let table = [
+            ("java/lang/Thread", "Thread"),
+            ("java.lang.String[]", "String[]"),
+            ("[Ljava/lang/String;", "String[]"),
+            ("[[B", "byte[][]"),
+            ("java/util/HashMap$Node", "HashMap$Node"),
+            ("[Ljava/util/HashMap$Node;", "HashMap$Node[]"),
+            (
+                "com/example/Foo$$Lambda$12/0x0000000800c0b000",
+                "Foo$$Lambda$12.0x0000000800c0b000",
+            ),
+            ("Foo", "Foo"),
+        ];
+        for (name, expected) in table {
+            assert_eq!(short_class_name(name), expected, "{:?}", name);
+        }
+        assert_eq!(Names::parse("short").unwrap().class("[I"), "int[]");
+        assert_eq!(
+            Names::default().class("java/lang/Thread"),
+            "java.lang.Thread"
+        );
+        assert_eq!(Names::parse("simple"), None);
+    }
+}
@@ -0,0 +1,136 @@
+//
+// Object size models. The dump records the payload of every object but not
+// how the JVM lays it out: the header size, the width of references and the
+// alignment depend on the JVM and its flags. By default shallow sizes are
+// those of the dump (two identifiers of header, identifier-sized
+// references, no padding), the other models estimate the JVM's sizes.
+//
+use crate::heap::FieldTag;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SizeModel {
+    RawHprof,
+    // 64-bit JVM with compressed oops and class pointers (heaps < 32 GB).
+    CompressedOops,
+    // 64-bit JVM with full-width references.
+    Bits64,
+    Bits32,
+}
+
+impl SizeModel {
+    pub fn parse(name: &str) -> Option<SizeModel> {
+        match name {
+            "raw-hprof" => Some(SizeModel::RawHprof),
+            "compressed-oops" => Some(SizeModel::CompressedOops),
+            "64-bit" => Some(SizeModel::Bits64),
+            "32-bit" => Some(SizeModel::Bits32),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SizeModel::RawHprof => "raw-hprof",
+            SizeModel::CompressedOops => "compressed-oops",
+            SizeModel::Bits64 => "64-bit",
+            SizeModel::Bits32 => "32-bit",
+        }
+    }
+
+    //
+    // Guesses the model of the JVM that wrote a dump: 4-byte identifiers
+    // come from 32-bit JVMs, and since identifiers are addresses, a 64-bit
+    // heap ending below 32 GB most likely used compressed oops (the default
+    // for such heaps).
+    //
+    pub fn detect(id_size: u32, max_object_id: u64) -> SizeModel {
+        if id_size == 4 {
+            SizeModel::Bits32
+        } else if max_object_id < 32 << 30 {
+            SizeModel::CompressedOops
+        } else {
+            SizeModel::Bits64
+        }
+    }
+
+    // (object header, array header, reference size, alignment) in bytes.
+    fn layout(self, id_size: u32) -> (u64, u64, u64, u64) {
+        let id_size = id_size as u64;
+        match self {
+            SizeModel::RawHprof => (2 * id_size, 2 * id_size + 4, id_size, 1),
+            SizeModel::CompressedOops => (12, 16, 4, 8),
+            SizeModel::Bits64 => (16, 24, 8, 8),
+            SizeModel::Bits32 => (8, 12, 4, 8),
+        }
+    }
+
+    pub fn field_size(self, tag: FieldTag, id_size: u32) -> u64 {
+        if tag.is_object() {
+            self.layout(id_size).2
+        } else {
+            tag.size(id_size) as u64
+        }
+    }
+
+    // Size of an instance whose fields take `fields` bytes in this model.
+    pub fn instance_size(self, fields: u64, id_size: u32) -> u64 {
+        let (header, _, _, align) = self.layout(id_size);
+        (header + fields).next_multiple_of(align)
+    }
+
+    pub fn array_size(self, length: u64, element: FieldTag, id_size: u32) -> u64 {
+        let (_, header, _, align) = self.layout(id_size);
+        (header + length * self.field_size(element, id_size)).next_multiple_of(align)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn models_are_named_and_detected() {
+        for model in [
+            SizeModel::RawHprof,
+            SizeModel::CompressedOops,
+            SizeModel::Bits64,
+            SizeModel::Bits32,
+        ] {
+            assert_eq!(SizeModel::parse(model.name()), Some(model));
+        }
+        assert_eq!(SizeModel::parse("16-bit"), None);
+        assert_eq!(SizeModel::detect(4, 1 << 40), SizeModel::Bits32);
+        assert_eq!(
+            SizeModel::detect(8, 0x7_0000_0000),
+            SizeModel::CompressedOops
+        );
+        assert_eq!(SizeModel::detect(8, 0x8_0000_0000), SizeModel::Bits64);
+    }
+
+    #[test]
+    fn sizes_are_aligned_per_model() {
+        // A reference and an int.
+        let fields = |model: SizeModel| {
+            model.field_size(FieldTag::NormalObject, 8) + model.field_size(FieldTag::Int, 8)
+        };
+        let sizes: Vec<(u64, u64, u64)> = [
+            SizeModel::RawHprof,
+            SizeModel::CompressedOops,
+            SizeModel::Bits64,
+            SizeModel::Bits32,
+        ]
+        .iter()
+        .map(|&model| {
+            (
+                model.instance_size(fields(model), 8),
+                model.array_size(3, FieldTag::Int, 8),
+                model.array_size(3, FieldTag::NormalObject, 8),
+            )
+        })
+        .collect();
+        assert_eq!(
+            sizes,
+            vec![(28, 32, 44), (24, 32, 32), (32, 40, 48), (16, 24, 24)]
+        );
+    }
+}
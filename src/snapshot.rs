@@ -0,0 +1,859 @@
+//
+// An in-memory model of a whole HPROF file: the symbol tables built from
+// the top-level records plus every object and GC root found in the heap
+// dump segments.
+//
+use crate::archive;
+use crate::cache::Cache;
+use crate::follow::{self, Follow};
+use crate::heap::{
+    parse_heap_dump_segment, read_value, ClassDump, FieldTag, GcRoot, HeapObject, InstanceDump,
+    Value,
+};
+use crate::idhash::IdMap;
+use crate::parallel;
+use crate::phd;
+use crate::readahead::ReadAhead;
+use crate::records::{
+    census, parse_alloc_sites_record, parse_header, parse_load_class_record, parse_record,
+    parse_stack_frame_record, parse_stack_trace_record, parse_start_thread_record,
+    parse_unload_class_record, parse_utf8_string_record, AllocSitesRecord, Header, LoadClassRecord,
+    RecordTag, StackFrameRecord, StackTraceRecord, StartThreadRecord,
+};
+use crate::signature;
+use crate::sizes::SizeModel;
+use crate::symbols::Symbols;
+use crate::timings;
+use crate::window::Window;
+
+use std::collections::HashSet;
+use std::io::{BufReader, Seek, SeekFrom};
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// A decoded instance field along with the class that declares it.
+#[derive(Debug, Clone, Copy)]
+pub struct FieldValue {
+    pub class_id: u64,
+    pub name_id: u64,
+    pub tag: FieldTag,
+    pub value: Value,
+}
+
+// Where an instance field is stored in instance dumps.
+#[derive(Debug, Clone, Copy)]
+pub struct FieldLayout {
+    pub class_id: u64,
+    pub name_id: u64,
+    pub tag: FieldTag,
+    pub offset: u32,
+}
+
+pub struct Snapshot {
+    pub header: Header,
+    pub strings: Symbols,
+    // LoadClass records by class serial number.
+    pub classes: IdMap<u32, LoadClassRecord>,
+    // Class object id to class serial number.
+    pub class_serials: IdMap<u64, u32>,
+    pub frames: IdMap<u64, StackFrameRecord>,
+    // Stack traces in the order they appear in the file.
+    pub traces: Vec<StackTraceRecord>,
+    pub trace_index: IdMap<u32, usize>,
+    pub threads: Vec<StartThreadRecord>,
+    pub alloc_sites: Vec<AllocSitesRecord>,
+    pub objects: Vec<HeapObject>,
+    // Object id to its index in `objects`.
+    pub object_index: IdMap<u64, u32>,
+    pub roots: Vec<GcRoot>,
+    // Number of top-level records seen per (raw) tag.
+    pub record_counts: IdMap<u8, u64>,
+    pub size_model: SizeModel,
+    // Where to save and find analysis results, None to always recompute.
+    pub cache: Option<Cache>,
+    // Instance sizes per class id when not using the raw HPROF model.
+    instance_sizes: IdMap<u64, u64>,
+}
+
+// What a snapshot loaded by Snapshot::load_shard() holds.
+#[derive(Debug, Clone)]
+pub struct ShardExtent {
+    // The heap dump segments of the shard, out of `total_segments`.
+    pub segments: Range<usize>,
+    pub total_segments: usize,
+    // The objects of these segments, at the start of `objects`; those after
+    // are the class dumps borrowed from the segments before.
+    pub objects: usize,
+}
+
+// A heap dump segment body: its offset in the file and its length.
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    offset: u64,
+    bytes: u64,
+}
+
+type SegmentContents = (usize, Vec<HeapObject>, Vec<GcRoot>);
+
+//
+// Parses the heap dump segments on `threads` threads, each with its own
+// reader on the file taking the next unparsed segment, and appends their
+// objects and roots in file order, as a single-threaded parse would. The
+// segments are read ahead of their parsing.
+//
+fn parse_segments(
+    filename: &str,
+    id_size: u32,
+    segments: &[Segment],
+    threads: usize,
+    objects: &mut Vec<HeapObject>,
+    roots: &mut Vec<GcRoot>,
+) {
+    let next = AtomicUsize::new(0);
+    let worker = || {
+        let (f, _) = archive::open(filename).unwrap_or_else(|e| panic!("{}: {}", filename, e));
+        let mut parsed: Vec<SegmentContents> = Vec::new();
+        loop {
+            let i = next.fetch_add(1, Ordering::Relaxed);
+            let segment = match segments.get(i) {
+                Some(segment) => segment,
+                None => return parsed,
+            };
+            let mut reader = ReadAhead::new(f.try_clone().unwrap(), segment.offset, segment.bytes);
+            let mut segment_objects = Vec::new();
+            let mut segment_roots = Vec::new();
+            parse_heap_dump_segment(
+                &mut reader,
+                id_size,
+                segment.bytes,
+                &mut segment_objects,
+                &mut segment_roots,
+            );
+            parsed.push((i, segment_objects, segment_roots));
+        }
+    };
+    let mut parsed: Vec<SegmentContents> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.clamp(1, segments.len().max(1)))
+            .map(|_| scope.spawn(worker))
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|w| w.join().unwrap())
+            .collect()
+    });
+    parsed.sort_by_key(|(i, _, _)| *i);
+    objects.reserve(parsed.iter().map(|(_, o, _)| o.len()).sum());
+    roots.reserve(parsed.iter().map(|(_, _, r)| r.len()).sum());
+    for (_, mut segment_objects, mut segment_roots) in parsed {
+        objects.append(&mut segment_objects);
+        roots.append(&mut segment_roots);
+    }
+}
+
+impl Snapshot {
+    // A snapshot with nothing in it yet but the header.
+    pub fn new(header: Header) -> Snapshot {
+        Snapshot {
+            header,
+            strings: Symbols::new(),
+            classes: IdMap::default(),
+            class_serials: IdMap::default(),
+            frames: IdMap::default(),
+            traces: Vec::new(),
+            trace_index: IdMap::default(),
+            threads: Vec::new(),
+            alloc_sites: Vec::new(),
+            objects: Vec::new(),
+            object_index: IdMap::default(),
+            roots: Vec::new(),
+            record_counts: IdMap::default(),
+            size_model: SizeModel::RawHprof,
+            cache: None,
+            instance_sizes: IdMap::default(),
+        }
+    }
+
+    // Loads a dump, parsing its heap dump segments on all the cores.
+    pub fn load(filename: &str) -> Snapshot {
+        Snapshot::load_window(filename, &Window::default()).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    // Loads the records of a dump within `window` (see window.rs).
+    pub fn load_window(filename: &str, window: &Window) -> Result<Snapshot, String> {
+        Snapshot::load_with_threads(filename, parallel::threads(), window)
+    }
+
+    //
+    // The top-level records are read in one pass that only notes where the
+    // heap dump segments are; their bodies don't depend on each other, so
+    // they are parsed afterwards on `threads` threads. A dump cut short in
+    // the middle of a record is an error.
+    //
+    pub fn load_with_threads(
+        filename: &str,
+        threads: usize,
+        window: &Window,
+    ) -> Result<Snapshot, String> {
+        if phd::is_phd(filename) {
+            return Ok(phd::load(filename));
+        }
+        let (mut snapshot, segments) = Snapshot::read_records(filename, window)?;
+        snapshot.parse_segments(filename, &segments, threads);
+        Ok(snapshot)
+    }
+
+    //
+    // Loads the top-level records and the `shard`th of `shards` contiguous
+    // runs of heap dump segments, for processes that each parse a part of
+    // the heap: a shard is a range of segments, not of object ids (see
+    // shard.rs). The fields of instances need the dumps of their classes,
+    // which HotSpot writes first: those of the segments before the shard
+    // are added after its objects, up to the first segment without any.
+    //
+    pub fn load_shard(
+        filename: &str,
+        shard: usize,
+        shards: usize,
+        window: &Window,
+    ) -> Result<(Snapshot, ShardExtent), String> {
+        if phd::is_phd(filename) {
+            panic!(
+                "{}: portable heap dumps have no segments to shard",
+                filename
+            );
+        }
+        let (mut snapshot, segments) = Snapshot::read_records(filename, window)?;
+        let range = parallel::chunk(segments.len(), shard, shards);
+        snapshot.parse_segments(filename, &segments[range.clone()], parallel::threads());
+        let extent = ShardExtent {
+            segments: range.clone(),
+            total_segments: segments.len(),
+            objects: snapshot.objects.len(),
+        };
+
+        let (f, _) = archive::open(filename).unwrap_or_else(|e| panic!("{}: {}", filename, e));
+        for segment in &segments[..range.start] {
+            let mut reader = ReadAhead::new(f.try_clone().unwrap(), segment.offset, segment.bytes);
+            let (mut objects, mut roots) = (Vec::new(), Vec::new());
+            let id_size = snapshot.id_size();
+            parse_heap_dump_segment(
+                &mut reader,
+                id_size,
+                segment.bytes,
+                &mut objects,
+                &mut roots,
+            );
+            let before = snapshot.objects.len();
+            snapshot.objects.extend(
+                objects
+                    .into_iter()
+                    .filter(|o| matches!(o, HeapObject::Class(_))),
+            );
+            if snapshot.objects.len() == before {
+                break;
+            }
+        }
+        for i in extent.objects..snapshot.objects.len() {
+            let id = snapshot.objects[i].object_id();
+            snapshot.object_index.insert(id, i as u32);
+        }
+        Ok((snapshot, extent))
+    }
+
+    fn parse_segments(&mut self, filename: &str, segments: &[Segment], threads: usize) {
+        let mut parse = timings::start("heap parse");
+        parse.add_bytes(segments.iter().map(|s| s.bytes).sum());
+        parse_segments(
+            filename,
+            self.id_size(),
+            segments,
+            threads,
+            &mut self.objects,
+            &mut self.roots,
+        );
+
+        self.object_index.reserve(self.objects.len());
+        for (i, object) in self.objects.iter().enumerate() {
+            self.object_index.insert(object.object_id(), i as u32);
+        }
+    }
+
+    //
+    // Loads the top-level records only (strings, classes, stack traces,
+    // threads and allocation sites) without any object or GC root, for the
+    // commands that don't look at the heap: the heap dump segments, most of
+    // the file, are seeked over. Portable heap dumps have no such records
+    // and are loaded whole.
+    //
+    pub fn load_metadata(filename: &str, window: &Window) -> Result<Snapshot, String> {
+        if phd::is_phd(filename) {
+            return Ok(phd::load(filename));
+        }
+        Ok(Snapshot::read_records(filename, window)?.0)
+    }
+
+    //
+    // The snapshot of the top-level records and where the segments are. The
+    // segments are seeked over, so this reads the file through a plain
+    // buffer, never through io_uring (see uring.rs): it reads little of it.
+    //
+    fn read_records(filename: &str, window: &Window) -> Result<(Snapshot, Vec<Segment>), String> {
+        let mut scan = timings::start("scan");
+        let f = Follow::open(filename).map_err(|e| format!("{}: {}", filename, e))?;
+        let mut reader = BufReader::new(f);
+        let header = parse_header(&mut reader).map_err(|e| format!("{}: {}", filename, e))?;
+        let id_size = header.identifier_size;
+
+        //
+        // A first look at the record headers, to size the tables once. Not
+        // of a dump being written, whose records are only read as they come.
+        //
+        let census = if follow::enabled() {
+            IdMap::default()
+        } else {
+            let start = reader.stream_position().unwrap();
+            let census = census(&mut reader);
+            reader.seek(SeekFrom::Start(start)).unwrap();
+            census
+        };
+        let count = |tag: RecordTag| census.get(&(tag as u8)).map_or(0, |c| c.0 as usize);
+
+        let mut snapshot = Snapshot::new(header);
+
+        let strings = census
+            .get(&(RecordTag::Utf8String as u8))
+            .map_or(0, |c| c.1);
+        snapshot.strings.reserve(
+            count(RecordTag::Utf8String),
+            (strings as usize).saturating_sub(count(RecordTag::Utf8String) * id_size as usize),
+        );
+        snapshot.classes.reserve(count(RecordTag::LoadClass));
+        snapshot.class_serials.reserve(count(RecordTag::LoadClass));
+        snapshot.frames.reserve(count(RecordTag::StackFrame));
+        snapshot.traces.reserve(count(RecordTag::StackTrace));
+        snapshot.trace_index.reserve(count(RecordTag::StackTrace));
+        snapshot.threads.reserve(count(RecordTag::StartThread));
+        let mut segments =
+            Vec::with_capacity(count(RecordTag::HeapDump) + count(RecordTag::HeapDumpSegment));
+        let (mut decoding, mut string_bytes) = (Duration::ZERO, 0);
+        // Where the dump ends, as far as it is known: looked at again only
+        // for a record past it, so once at the end of a dump being written.
+        let mut end = 0;
+        loop {
+            let pos = reader.stream_position().unwrap();
+            let record = match parse_record(&mut reader) {
+                Ok(Some(record)) => record,
+                Ok(None) => break,
+                Err(_) => return Err(follow::cut_short(filename, pos)),
+            };
+            let body = pos + 9 + record.bytes as u64;
+            if body > end {
+                end = reader
+                    .get_ref()
+                    .wait_for(body)
+                    .map_err(|e| format!("{}: {}", filename, e))?;
+                if body > end {
+                    return Err(follow::cut_short(filename, pos));
+                }
+            }
+            if window.excludes(&snapshot.header, &record) {
+                reader.seek_relative(record.bytes as i64).unwrap();
+                continue;
+            }
+            *snapshot.record_counts.entry(record.raw_tag).or_insert(0) += 1;
+            match record.tag {
+                Some(RecordTag::Utf8String) => {
+                    let start = timings::enabled().then(Instant::now);
+                    let r = parse_utf8_string_record(&mut reader, id_size, record.bytes as usize);
+                    if let Some(start) = start {
+                        decoding += start.elapsed();
+                        string_bytes += record.bytes as u64;
+                    }
+                    match r {
+                        Ok(r) => snapshot.strings.insert(r.identifier, &r.value),
+                        Err(e) => {
+                            eprintln!("warning: {}, skipping it", e);
+                            reader.seek_relative(record.bytes as i64).unwrap();
+                        }
+                    }
+                }
+                Some(RecordTag::LoadClass) => {
+                    let r = parse_load_class_record(&mut reader, id_size);
+                    snapshot.class_serials.insert(r.object_id, r.serial_num);
+                    snapshot.classes.insert(r.serial_num, r);
+                }
+                Some(RecordTag::UnloadClass) => {
+                    // These currently seem to be non-existent in HotSpot dumps.
+                    let _r = parse_unload_class_record(&mut reader);
+                }
+                Some(RecordTag::StackFrame) => {
+                    let r = parse_stack_frame_record(&mut reader, id_size);
+                    snapshot.frames.insert(r.frame_id, r);
+                }
+                Some(RecordTag::StackTrace) => {
+                    let r = parse_stack_trace_record(&mut reader, id_size);
+                    snapshot
+                        .trace_index
+                        .insert(r.serial_num, snapshot.traces.len());
+                    snapshot.traces.push(r);
+                }
+                Some(RecordTag::AllocSites) => {
+                    let r = parse_alloc_sites_record(&mut reader);
+                    snapshot.alloc_sites.push(r);
+                }
+                Some(RecordTag::StartThread) => {
+                    let r = parse_start_thread_record(&mut reader, id_size);
+                    snapshot.threads.push(r);
+                }
+                Some(RecordTag::HeapDump) | Some(RecordTag::HeapDumpSegment) => {
+                    segments.push(Segment {
+                        offset: reader.stream_position().unwrap(),
+                        bytes: record.bytes as u64,
+                    });
+                    reader.seek_relative(record.bytes as i64).unwrap();
+                }
+                // The last record HotSpot writes.
+                Some(RecordTag::HeapDumpEnd) => {
+                    reader.get_mut().stop();
+                    reader.seek_relative(record.bytes as i64).unwrap();
+                }
+                _ => {
+                    reader.seek_relative(record.bytes as i64).unwrap();
+                }
+            }
+        }
+        snapshot.strings.finish();
+        timings::record("string decode", decoding, string_bytes);
+        let heap_bytes: u64 = segments.iter().map(|s| s.bytes).sum();
+        scan.add_bytes(reader.stream_position().unwrap() - heap_bytes);
+        Ok((snapshot, segments))
+    }
+
+    // Switches the model used by shallow_size() (and all sizes after it).
+    pub fn set_size_model(&mut self, model: SizeModel) {
+        let id_size = self.id_size();
+        self.instance_sizes = self
+            .class_serials
+            .keys()
+            .map(|&class_id| {
+                let fields = self
+                    .field_layout(class_id)
+                    .iter()
+                    .map(|f| model.field_size(f.tag, id_size))
+                    .sum();
+                (class_id, model.instance_size(fields, id_size))
+            })
+            .collect();
+        self.size_model = model;
+    }
+
+    // The size model of the JVM that most likely wrote the dump.
+    pub fn detect_size_model(&self) -> SizeModel {
+        let max_id = self
+            .objects
+            .iter()
+            .map(|o| o.object_id())
+            .max()
+            .unwrap_or(0);
+        SizeModel::detect(self.id_size(), max_id)
+    }
+
+    pub fn id_size(&self) -> u32 {
+        self.header.identifier_size
+    }
+
+    pub fn string(&self, id: u64) -> &str {
+        self.strings.get(id).unwrap_or("<unknown>")
+    }
+
+    pub fn index_of(&self, object_id: u64) -> Option<u32> {
+        self.object_index.get(&object_id).copied()
+    }
+
+    pub fn object(&self, object_id: u64) -> Option<&HeapObject> {
+        self.index_of(object_id).map(|i| &self.objects[i as usize])
+    }
+
+    pub fn class_dump(&self, class_id: u64) -> Option<&ClassDump> {
+        match self.object(class_id) {
+            Some(HeapObject::Class(c)) => Some(c),
+            _ => None,
+        }
+    }
+
+    //
+    // The name of a class in Java syntax: HPROF has the names of the JVM,
+    // with slashes (/) for the dots (.) of packages and descriptors for
+    // arrays [e.g. java/lang/Thread and [Ljava/lang/Thread; for
+    // java.lang.Thread and java.lang.Thread[]], see signature::class_name().
+    //
+    pub fn class_name(&self, class_id: u64) -> String {
+        match self.class_serials.get(&class_id) {
+            Some(serial) => signature::class_name(self.string(self.classes[serial].strname_id)),
+            None => format!("<unknown class {:#x}>", class_id),
+        }
+    }
+
+    //
+    // The id of the (first) class with the given name, which may also be
+    // given the way the JVM has it.
+    //
+    pub fn find_class(&self, class_name: &str) -> Option<u64> {
+        let class_name = signature::class_name(class_name);
+        self.class_serials
+            .keys()
+            .copied()
+            .find(|&id| self.class_name(id) == class_name)
+    }
+
+    // The ids of the classes with the given name and of all their subclasses.
+    pub fn subclasses(&self, class_name: &str) -> HashSet<u64> {
+        let class_name = signature::class_name(class_name);
+        let mut found = HashSet::new();
+        for &class_id in self.class_serials.keys() {
+            let mut current = class_id;
+            while let Some(class) = self.class_dump(current) {
+                if self.class_name(current) == class_name {
+                    found.insert(class_id);
+                    break;
+                }
+                current = class.super_class_id;
+            }
+        }
+        found
+    }
+
+    // The class of an object, or None for primitive arrays which have none.
+    pub fn class_of(&self, object: &HeapObject) -> Option<u64> {
+        match object {
+            HeapObject::Class(_) => None,
+            HeapObject::Instance(i) => Some(i.class_id),
+            HeapObject::ObjectArray(a) => Some(a.class_id),
+            HeapObject::PrimitiveArray(_) => None,
+        }
+    }
+
+    pub fn object_class_name(&self, object: &HeapObject) -> String {
+        match object {
+            HeapObject::Class(_) => "java.lang.Class".to_string(),
+            HeapObject::Instance(i) => self.class_name(i.class_id),
+            HeapObject::ObjectArray(a) => self.class_name(a.class_id),
+            HeapObject::PrimitiveArray(a) => format!("{}[]", a.element_tag.java_name()),
+        }
+    }
+
+    //
+    // Like object_class_name() but the name the JVM has for the class
+    // (`java/lang/String`, `[B`), for what is stored or compared rather
+    // than shown.
+    //
+    pub fn object_jvm_class_name(&self, object: &HeapObject) -> String {
+        let class_id = match object {
+            HeapObject::Class(_) => return "java/lang/Class".to_string(),
+            HeapObject::Instance(i) => i.class_id,
+            HeapObject::ObjectArray(a) => a.class_id,
+            HeapObject::PrimitiveArray(a) => return format!("[{}", a.element_tag.descriptor()),
+        };
+        match self.class_serials.get(&class_id) {
+            Some(serial) => self.string(self.classes[serial].strname_id).to_string(),
+            None => format!("<unknown class {:#x}>", class_id),
+        }
+    }
+
+    // The indices of all the objects of the given class (by exact name).
+    pub fn objects_of_class(&self, class_name: &str) -> Vec<u32> {
+        let class_name = signature::class_name(class_name);
+        (0..self.objects.len() as u32)
+            .filter(|&i| self.object_class_name(&self.objects[i as usize]) == class_name)
+            .collect()
+    }
+
+    // Like object_class_name() but class objects are named after their class.
+    pub fn object_label(&self, object: &HeapObject) -> String {
+        match object {
+            HeapObject::Class(c) => format!("class {}", self.class_name(c.class_id)),
+            _ => self.object_class_name(object),
+        }
+    }
+
+    //
+    // The shallow size of an object as it appears in the dump: an object
+    // header of two identifiers, the array length for arrays, and the
+    // payload itself. Class objects are charged for their static fields.
+    // Other size models estimate the sizes in the JVM instead (see sizes.rs).
+    //
+    pub fn shallow_size(&self, object: &HeapObject) -> u64 {
+        let model = self.size_model;
+        if model != SizeModel::RawHprof {
+            let id_size = self.id_size();
+            return match object {
+                HeapObject::Class(c) => c
+                    .static_fields()
+                    .iter()
+                    .map(|f| model.field_size(f.tag, id_size))
+                    .sum(),
+                HeapObject::Instance(i) => match self.instance_sizes.get(&i.class_id) {
+                    Some(&size) => size,
+                    None => model.instance_size(i.data.len() as u64, id_size),
+                },
+                HeapObject::ObjectArray(a) => {
+                    model.array_size(a.elements.len() as u64, FieldTag::NormalObject, id_size)
+                }
+                HeapObject::PrimitiveArray(a) => {
+                    model.array_size(a.length as u64, a.element_tag, id_size)
+                }
+            };
+        }
+        let id_size = self.id_size() as u64;
+        match object {
+            HeapObject::Class(c) => c
+                .static_fields()
+                .iter()
+                .map(|f| f.tag.size(self.id_size()) as u64)
+                .sum(),
+            HeapObject::Instance(i) => 2 * id_size + i.data.len() as u64,
+            HeapObject::ObjectArray(a) => 2 * id_size + 4 + a.elements.len() as u64 * id_size,
+            HeapObject::PrimitiveArray(a) => 2 * id_size + 4 + a.data.len() as u64,
+        }
+    }
+
+    //
+    // The instance fields of a class and of its superclasses in the order
+    // they appear in instance dumps, with their offsets in the data.
+    //
+    pub fn field_layout(&self, class_id: u64) -> Vec<FieldLayout> {
+        let mut layout = Vec::new();
+        let mut offset = 0;
+        let mut current = class_id;
+        while let Some(class) = self.class_dump(current) {
+            for field in &class.instance_fields {
+                layout.push(FieldLayout {
+                    class_id: current,
+                    name_id: field.name_id,
+                    tag: field.tag,
+                    offset,
+                });
+                offset += field.tag.size(self.id_size());
+            }
+            current = class.super_class_id;
+        }
+        layout
+    }
+
+    //
+    // Decodes the field values of an instance. The instance data contains
+    // the fields of the object's class first, followed by the fields of its
+    // superclass and so on up the chain.
+    //
+    pub fn instance_fields(&self, instance: &InstanceDump) -> Vec<FieldValue> {
+        let mut fields = Vec::new();
+        let mut cursor = &instance.data[..];
+        let mut class_id = instance.class_id;
+        while let Some(class) = self.class_dump(class_id) {
+            for field in &class.instance_fields {
+                if cursor.len() < field.tag.size(self.id_size()) as usize {
+                    return fields;
+                }
+                let value = read_value(&mut cursor, field.tag, self.id_size());
+                fields.push(FieldValue {
+                    class_id,
+                    name_id: field.name_id,
+                    tag: field.tag,
+                    value,
+                });
+            }
+            class_id = class.super_class_id;
+        }
+        fields
+    }
+
+    // The value of the first field with the given name (closest class first).
+    pub fn field_value(&self, instance: &InstanceDump, name: &str) -> Option<Value> {
+        self.instance_fields(instance)
+            .into_iter()
+            .find(|f| self.string(f.name_id) == name)
+            .map(|f| f.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::GcRootKind;
+    use crate::testing::{Dump, TempFile};
+    use crate::writer::write_snapshot;
+    use std::io::Cursor;
+
+    #[test]
+    fn fields_are_laid_out_from_the_class_up() {
+        let mut dump = Dump::new();
+        let base = dump.class(
+            "test/Base",
+            dump.object,
+            &[("id", FieldTag::Long), ("flag", FieldTag::Boolean)],
+        );
+        let derived = dump.class(
+            "test/Derived",
+            base,
+            &[("next", FieldTag::NormalObject), ("count", FieldTag::Int)],
+        );
+        let snapshot = dump.load();
+
+        let layout: Vec<(u64, &str, FieldTag, u32)> = snapshot
+            .field_layout(derived)
+            .iter()
+            .map(|f| (f.class_id, snapshot.string(f.name_id), f.tag, f.offset))
+            .collect();
+        assert_eq!(
+            layout,
+            vec![
+                (derived, "next", FieldTag::NormalObject, 0),
+                (derived, "count", FieldTag::Int, 8),
+                (base, "id", FieldTag::Long, 12),
+                (base, "flag", FieldTag::Boolean, 20),
+            ]
+        );
+        assert!(snapshot.field_layout(0).is_empty());
+    }
+
+    #[test]
+    fn shallow_sizes_follow_the_size_model() {
+        let mut dump = Dump::new();
+        let class = dump.class(
+            "test/Pair",
+            dump.object,
+            &[
+                ("next", FieldTag::NormalObject),
+                ("count", FieldTag::Int),
+                ("flag", FieldTag::Boolean),
+            ],
+        );
+        let pair = dump.instance(class, &[]);
+        let bytes = dump.primitive_array(FieldTag::Byte, &[1, 2, 3]);
+        let mut snapshot = dump.load();
+        let sizes = |snapshot: &Snapshot| {
+            [pair, bytes].map(|id| snapshot.shallow_size(snapshot.object(id).unwrap()))
+        };
+
+        assert_eq!(snapshot.size_model, SizeModel::RawHprof);
+        assert_eq!(sizes(&snapshot), [16 + 13, 20 + 3]);
+        assert_eq!(snapshot.detect_size_model(), SizeModel::CompressedOops);
+        snapshot.set_size_model(SizeModel::CompressedOops);
+        assert_eq!(sizes(&snapshot), [24, 24]);
+        snapshot.set_size_model(SizeModel::Bits64);
+        assert_eq!(sizes(&snapshot), [32, 32]);
+    }
+
+    #[test]
+    fn segments_parse_the_same_on_any_number_of_threads() {
+        let mut dump = Dump::new();
+        dump.set_segment_bytes(256);
+        let node = dump.class(
+            "test/Node",
+            dump.object,
+            &[
+                ("next", FieldTag::NormalObject),
+                ("name", FieldTag::NormalObject),
+            ],
+        );
+        let mut next = 0;
+        for i in 0..200 {
+            let name = dump.string(&format!("node {}", i));
+            next = dump.instance(node, &[Value::Object(next), Value::Object(name)]);
+            if i % 50 == 0 {
+                dump.root(GcRootKind::JniGlobal, next);
+            }
+        }
+        let file = TempFile::new(&dump.bytes());
+        let window = Window::default();
+        assert!(
+            Snapshot::read_records(file.path(), &window)
+                .unwrap()
+                .1
+                .len()
+                > 10
+        );
+
+        let written: Vec<Vec<u8>> = [1, 4]
+            .iter()
+            .map(|&threads| {
+                let snapshot = Snapshot::load_with_threads(file.path(), threads, &window).unwrap();
+                assert_eq!(snapshot.objects.len(), 4 + 3 * 200);
+                assert_eq!(snapshot.roots.len(), 4);
+                for (i, object) in snapshot.objects.iter().enumerate() {
+                    assert_eq!(snapshot.index_of(object.object_id()), Some(i as u32));
+                }
+                write_snapshot(&snapshot, Vec::new()).unwrap()
+            })
+            .collect();
+        assert!(written[0] == written[1]);
+    }
+
+    #[test]
+    fn dumps_cut_short_are_errors() {
+        let mut dump = Dump::new();
+        dump.set_segment_bytes(256);
+        for i in 0..20 {
+            let id = dump.string(&format!("text {}", i));
+            dump.root(GcRootKind::JniGlobal, id);
+        }
+        let bytes = dump.bytes();
+        let file = TempFile::new(&bytes);
+        let mut reader = Cursor::new(&bytes[..]);
+        parse_header(&mut reader).unwrap();
+        let mut starts = vec![reader.position() as usize];
+        while let Some(record) = parse_record(&mut reader).unwrap() {
+            reader.seek_relative(record.bytes as i64).unwrap();
+            starts.push(reader.position() as usize);
+        }
+
+        let load = |len: usize| {
+            let cut = TempFile::new(&bytes[..len]);
+            Snapshot::load_window(cut.path(), &Window::default())
+                .map(|s| s.objects.len() + s.roots.len())
+        };
+        let whole = Snapshot::load(file.path());
+        let whole = whole.objects.len() + whole.roots.len();
+        // Without the last records, HEAP_DUMP_END and heap dump segments.
+        assert_eq!(load(starts[starts.len() - 2]), Ok(whole));
+        assert!(load(starts[starts.len() - 3]).unwrap() < whole);
+        for pair in starts.windows(2).skip(starts.len() - 4) {
+            for len in [pair[0] + 3, pair[1] - 1] {
+                let error = load(len).unwrap_err();
+                assert!(
+                    error.ends_with(&format!(
+                        "the dump is cut short in the record at {}",
+                        pair[0]
+                    )),
+                    "{}",
+                    error
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn metadata_loads_without_the_heap() {
+        let mut dump = Dump::new();
+        let worker = dump.class("test/Worker", dump.object, &[]);
+        let frame = dump.frame(worker, "run", "()V", 1);
+        dump.trace(1, &[frame]);
+        for _ in 0..100 {
+            let id = dump.string("text");
+            dump.root(GcRootKind::JniGlobal, id);
+        }
+        let file = TempFile::new(&dump.bytes());
+        let full = Snapshot::load(file.path());
+        let metadata = Snapshot::load_metadata(file.path(), &Window::default()).unwrap();
+
+        assert!(metadata.objects.is_empty() && metadata.roots.is_empty());
+        assert_eq!(full.objects.len(), 4 + 200);
+        assert_eq!(metadata.classes.len(), full.classes.len());
+        assert_eq!(metadata.class_name(worker), "test.Worker");
+        assert_eq!(metadata.traces.len(), 1);
+        assert_eq!(metadata.frames.len(), 1);
+        assert_eq!(metadata.record_counts, full.record_counts);
+    }
+}
@@ -0,0 +1,149 @@
+//
+// Splitting a dump into pieces of one heap dump segment each, every piece
+// a dump of its own with a copy of the records outside the heap dump (the
+// strings, classes, stack traces and threads), and joining pieces back
+// into the dump they came from. Records are copied as they are, without
+// being parsed.
+//
+use crate::records::{parse_header, parse_record, RecordTag};
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+
+// Tag, time and length.
+const RECORD_HEADER_BYTES: u64 = 9;
+
+//
+// A dump by its header and the records outside the heap dump, in bytes,
+// and where its heap dump segments are: (offset, bytes) of the whole
+// records, the record headers included.
+//
+struct Layout {
+    header: Vec<u8>,
+    metadata: Vec<u8>,
+    segments: Vec<(u64, u64)>,
+}
+
+fn open(path: &str) -> io::Result<BufReader<File>> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| named(path, e))
+}
+
+// The error with the path of the file it is about.
+fn named(path: &str, e: io::Error) -> io::Error {
+    io::Error::new(e.kind(), format!("{}: {}", path, e))
+}
+
+fn layout(reader: &mut BufReader<File>) -> io::Result<Layout> {
+    parse_header(reader)?;
+    let mut header = vec![0; reader.stream_position()? as usize];
+    reader.seek(SeekFrom::Start(0))?;
+    reader.read_exact(&mut header)?;
+
+    let mut metadata = Vec::new();
+    let mut segments = Vec::new();
+    loop {
+        let offset = reader.stream_position()?;
+        let record = match parse_record(reader)? {
+            Some(record) => record,
+            None => break,
+        };
+        let bytes = record.bytes as u64;
+        match record.tag {
+            Some(RecordTag::HeapDump) | Some(RecordTag::HeapDumpSegment) => {
+                segments.push((offset, RECORD_HEADER_BYTES + bytes));
+                reader.seek_relative(record.bytes as i64)?;
+            }
+            Some(RecordTag::HeapDumpEnd) => reader.seek_relative(record.bytes as i64)?,
+            _ => {
+                reader.seek(SeekFrom::Start(offset))?;
+                reader
+                    .by_ref()
+                    .take(RECORD_HEADER_BYTES + bytes)
+                    .read_to_end(&mut metadata)?;
+            }
+        }
+    }
+    Ok(Layout {
+        header,
+        metadata,
+        segments,
+    })
+}
+
+fn copy_segment<W: Write>(
+    reader: &mut BufReader<File>,
+    (offset, bytes): (u64, u64),
+    out: &mut W,
+) -> io::Result<()> {
+    reader.seek(SeekFrom::Start(offset))?;
+    let copied = io::copy(&mut reader.by_ref().take(bytes), out)?;
+    if copied != bytes {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "heap dump segment cut short",
+        ));
+    }
+    Ok(())
+}
+
+fn end<W: Write>(out: &mut W) -> io::Result<()> {
+    out.write_all(&[RecordTag::HeapDumpEnd as u8, 0, 0, 0, 0, 0, 0, 0, 0])?;
+    out.flush()
+}
+
+//
+// Writes the pieces of the dump as <prefix>-<n>.hprof, n from 1, and
+// gives their paths with the bytes of their segments.
+//
+pub fn split(path: &str, prefix: &str) -> io::Result<Vec<(String, u64)>> {
+    let mut reader = open(path)?;
+    let layout = layout(&mut reader).map_err(|e| named(path, e))?;
+    let width = layout.segments.len().to_string().len().max(2);
+    let mut pieces = Vec::with_capacity(layout.segments.len());
+    for (n, &segment) in layout.segments.iter().enumerate() {
+        let piece = format!("{}-{:0width$}.hprof", prefix, n + 1, width = width);
+        let file = File::create(&piece).map_err(|e| named(&piece, e))?;
+        let mut out = BufWriter::new(file);
+        out.write_all(&layout.header)?;
+        out.write_all(&layout.metadata)?;
+        copy_segment(&mut reader, segment, &mut out)?;
+        end(&mut out)?;
+        pieces.push((piece, segment.1 - RECORD_HEADER_BYTES));
+    }
+    Ok(pieces)
+}
+
+//
+// The dump of the given pieces, their segments in that order after the
+// records outside the heap dump, which must be the same in every piece.
+//
+pub fn join<W: Write>(pieces: &[String], mut out: W) -> io::Result<W> {
+    let mut first: Option<Layout> = None;
+    for piece in pieces {
+        let mut reader = open(piece)?;
+        let layout = layout(&mut reader).map_err(|e| named(piece, e))?;
+        match &first {
+            None => {
+                out.write_all(&layout.header)?;
+                out.write_all(&layout.metadata)?;
+            }
+            Some(first) if first.header != layout.header || first.metadata != layout.metadata => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: not a piece of the same dump as {}", piece, pieces[0]),
+                ));
+            }
+            Some(_) => {}
+        }
+        for &segment in &layout.segments {
+            copy_segment(&mut reader, segment, &mut out)?;
+        }
+        if first.is_none() {
+            first = Some(layout);
+        }
+    }
+    end(&mut out)?;
+    Ok(out)
+}
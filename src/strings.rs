@@ -0,0 +1,456 @@
+//
+// Decoding of java.lang.String contents and analyses built on top of it.
+//
+use crate::heap::{FieldTag, HeapObject, InstanceDump, PrimitiveArrayDump, Value};
+use crate::regex::Regex;
+use crate::snapshot::Snapshot;
+
+use std::collections::{HashMap, HashSet};
+
+//
+// How the characters of a String are stored:
+// - JDK 9+ (compact strings): a byte[] `value` plus a `coder` field that
+//   is 0 for Latin-1 and 1 for UTF-16. UTF-16 contents are copied straight
+//   from memory so they're in the byte order of the platform, which for
+//   all the dumps seen so far means little-endian (x86 and aarch64).
+// - Up to JDK 8: a char[] `value`. HPROF writes char arrays element by
+//   element in big-endian. JDK 6 also has `offset` and `count` fields for
+//   strings sharing a larger array (e.g. from substring()).
+//
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Encoding {
+    Latin1,
+    Utf16Le,
+    Chars,
+}
+
+const LATIN1: i8 = 0;
+const UTF16: i8 = 1;
+
+// The raw contents of a String: its backing array and how to decode it.
+pub struct StringContent<'a> {
+    pub array: &'a PrimitiveArrayDump,
+    pub encoding: Encoding,
+    // The slice of the array's data that makes up the string.
+    pub bytes: &'a [u8],
+}
+
+impl<'a> StringContent<'a> {
+    pub fn decode(&self) -> String {
+        match self.encoding {
+            Encoding::Latin1 => self.bytes.iter().map(|&b| b as char).collect(),
+            Encoding::Utf16Le => {
+                let units: Vec<u16> = self
+                    .bytes
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .collect();
+                String::from_utf16_lossy(&units)
+            }
+            Encoding::Chars => decode_chars(self.bytes),
+        }
+    }
+}
+
+// Decodes the data of a char[] as found in the dump.
+pub fn decode_chars(data: &[u8]) -> String {
+    let units: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|c| u16::from_be_bytes([c[0], c[1]]))
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+pub fn string_content<'a>(
+    snapshot: &'a Snapshot,
+    instance: &InstanceDump,
+) -> Option<StringContent<'a>> {
+    let mut array = None;
+    let mut coder = None;
+    let mut offset = None;
+    let mut count = None;
+    for f in snapshot.instance_fields(instance) {
+        match (snapshot.string(f.name_id), f.value) {
+            ("value", Value::Object(id)) => array = Some(id),
+            ("coder", Value::Byte(c)) => coder = Some(c),
+            ("offset", Value::Int(n)) => offset = Some(n.max(0) as usize),
+            ("count", Value::Int(n)) => count = Some(n.max(0) as usize),
+            _ => {}
+        }
+    }
+    let array = match snapshot.object(array?) {
+        Some(HeapObject::PrimitiveArray(a)) => a,
+        _ => return None,
+    };
+
+    match array.element_tag {
+        FieldTag::Byte => {
+            let encoding = match coder {
+                Some(UTF16) => Encoding::Utf16Le,
+                Some(LATIN1) | None => Encoding::Latin1,
+                Some(_) => return None,
+            };
+            Some(StringContent {
+                array,
+                encoding,
+                bytes: &array.data,
+            })
+        }
+        FieldTag::Char => {
+            let length = array.length as usize;
+            let start = offset.unwrap_or(0).min(length);
+            let end = match count {
+                Some(count) => (start + count).min(length),
+                None => length,
+            };
+            Some(StringContent {
+                array,
+                encoding: Encoding::Chars,
+                bytes: &array.data[2 * start..2 * end],
+            })
+        }
+        _ => None,
+    }
+}
+
+pub fn string_value(snapshot: &Snapshot, instance: &InstanceDump) -> Option<String> {
+    string_content(snapshot, instance).map(|c| c.decode())
+}
+
+// The contents of an object if it is a java.lang.String.
+pub fn as_string(snapshot: &Snapshot, object: &HeapObject) -> Option<String> {
+    match object {
+        HeapObject::Instance(i) if snapshot.class_name(i.class_id) == "java.lang.String" => {
+            string_value(snapshot, i)
+        }
+        _ => None,
+    }
+}
+
+#[derive(Debug)]
+pub struct DuplicateString {
+    pub value: String,
+    pub count: u64,
+    // Number of distinct backing arrays among the duplicates.
+    pub arrays: u64,
+    // Bytes that would be saved by keeping a single copy.
+    pub wasted: u64,
+    // Bytes that sharing a single backing array would save, which is what
+    // the GC does with -XX:+UseStringDeduplication.
+    pub dedup_savings: u64,
+    // Whether one of the instances is held by a static field, in which case
+    // it is most likely a literal, i.e. an interned string.
+    pub interned: bool,
+}
+
+//
+// Groups all the String instances by content, hashing the backing arrays
+// directly so that only one string per group needs to be decoded, and
+// returns the values with more than one instance sorted by wasted bytes.
+//
+pub fn duplicate_strings(snapshot: &Snapshot) -> Vec<DuplicateString> {
+    struct Group<'a> {
+        instances: Vec<&'a HeapObject>,
+        arrays: HashMap<u64, &'a HeapObject>,
+        content: StringContent<'a>,
+    }
+
+    let string_class = match snapshot.find_class("java.lang.String") {
+        Some(id) => id,
+        None => return Vec::new(),
+    };
+    let statics: HashSet<u64> = snapshot
+        .objects
+        .iter()
+        .filter_map(|o| match o {
+            HeapObject::Class(c) => Some(c),
+            _ => None,
+        })
+        .flat_map(|c| c.static_fields().iter().filter_map(|f| f.value.as_object()))
+        .collect();
+    let mut groups: HashMap<(Encoding, &[u8]), Group> = HashMap::new();
+    for object in &snapshot.objects {
+        let instance = match object {
+            HeapObject::Instance(i) if i.class_id == string_class => i,
+            _ => continue,
+        };
+        let content = match string_content(snapshot, instance) {
+            Some(content) => content,
+            None => continue,
+        };
+        let array = content.array;
+        let group = groups
+            .entry((content.encoding, content.bytes))
+            .or_insert_with(|| Group {
+                instances: Vec::new(),
+                arrays: HashMap::new(),
+                content,
+            });
+        group.instances.push(object);
+        group
+            .arrays
+            .entry(array.object_id)
+            .or_insert_with(|| snapshot.object(array.object_id).unwrap());
+    }
+
+    let mut duplicates: Vec<DuplicateString> = groups
+        .into_values()
+        .filter(|g| g.instances.len() > 1)
+        .map(|g| {
+            let strings: u64 = g.instances.iter().map(|&o| snapshot.shallow_size(o)).sum();
+            let arrays: u64 = g.arrays.values().map(|&a| snapshot.shallow_size(a)).sum();
+            let array = snapshot.shallow_size(snapshot.object(g.content.array.object_id).unwrap());
+            let keep = snapshot.shallow_size(g.instances[0]) + array;
+            DuplicateString {
+                value: g.content.decode(),
+                count: g.instances.len() as u64,
+                arrays: g.arrays.len() as u64,
+                wasted: strings + arrays - keep,
+                dedup_savings: arrays - array,
+                interned: g.instances.iter().any(|o| statics.contains(&o.object_id())),
+            }
+        })
+        .collect();
+    duplicates.sort_by(|a, b| b.wasted.cmp(&a.wasted).then(b.count.cmp(&a.count)));
+    duplicates
+}
+
+// What a piece of text passed to for_each_text() was decoded from.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TextSource {
+    String,
+    CharArray,
+    ByteArray,
+}
+
+impl TextSource {
+    pub fn name(self) -> &'static str {
+        match self {
+            TextSource::String => "String",
+            TextSource::CharArray => "char[]",
+            TextSource::ByteArray => "byte[]",
+        }
+    }
+}
+
+//
+// Calls `f` with the decoded contents of every String, then of every
+// char[] and byte[] that isn't the backing array of one of those Strings
+// (byte[]s are decoded as Latin-1 since their encoding isn't known). The
+// node passed along is the String or the array itself.
+//
+pub fn for_each_text<F>(snapshot: &Snapshot, mut f: F)
+where
+    F: FnMut(u32, TextSource, &[char]),
+{
+    let string_class = snapshot.find_class("java.lang.String");
+    let mut backing = vec![false; snapshot.objects.len()];
+    for (node, object) in snapshot.objects.iter().enumerate() {
+        let instance = match object {
+            HeapObject::Instance(i) if Some(i.class_id) == string_class => i,
+            _ => continue,
+        };
+        if let Some(content) = string_content(snapshot, instance) {
+            if let Some(array) = snapshot.index_of(content.array.object_id) {
+                backing[array as usize] = true;
+            }
+            let text: Vec<char> = content.decode().chars().collect();
+            f(node as u32, TextSource::String, &text);
+        }
+    }
+
+    for (node, object) in snapshot.objects.iter().enumerate() {
+        let array = match object {
+            HeapObject::PrimitiveArray(a) if !backing[node] => a,
+            _ => continue,
+        };
+        let (source, text): (TextSource, Vec<char>) = match array.element_tag {
+            FieldTag::Char => (
+                TextSource::CharArray,
+                decode_chars(&array.data).chars().collect(),
+            ),
+            FieldTag::Byte => (
+                TextSource::ByteArray,
+                array.data.iter().map(|&b| b as char).collect(),
+            ),
+            _ => continue,
+        };
+        f(node as u32, source, &text);
+    }
+}
+
+#[derive(Debug)]
+pub struct TextMatch {
+    pub node: u32,
+    pub source: TextSource,
+    // Char offset of the match in the decoded text.
+    pub offset: usize,
+    pub text: String,
+    // Up to 20 chars on either side of the match.
+    pub before: String,
+    pub after: String,
+}
+
+// Every match of a regex in the Strings, char[]s and byte[]s of the heap.
+pub fn search(snapshot: &Snapshot, regex: &Regex) -> Vec<TextMatch> {
+    const CONTEXT: usize = 20;
+    let mut matches = Vec::new();
+    for_each_text(snapshot, |node, source, text| {
+        for (start, end) in regex.find_all(text) {
+            matches.push(TextMatch {
+                node,
+                source,
+                offset: start,
+                text: text[start..end].iter().collect(),
+                before: text[start.saturating_sub(CONTEXT)..start].iter().collect(),
+                after: text[end..(end + CONTEXT).min(text.len())].iter().collect(),
+            });
+        }
+    });
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Dump;
+
+    #[test]
+    fn duplicates_are_grouped_by_content() {
+        let mut dump = Dump::new();
+        let string = dump.string;
+        let array = dump.primitive_array(FieldTag::Byte, b"abc");
+        let first = dump.instance(string, &[Value::Object(array)]);
+        // Another String sharing its array, as after deduplication.
+        dump.instance(string, &[Value::Object(array)]);
+        dump.string("abc");
+        dump.string("xyz");
+        let snapshot = dump.load();
+
+        let duplicates = duplicate_strings(&snapshot);
+        assert_eq!(duplicates.len(), 1);
+        let d = &duplicates[0];
+        assert_eq!((d.value.as_str(), d.count, d.arrays), ("abc", 3, 2));
+        let size = |id| snapshot.shallow_size(snapshot.object(id).unwrap());
+        assert_eq!(d.wasted, 2 * size(first) + size(array));
+        assert_eq!(d.dedup_savings, size(array));
+        assert!(!d.interned);
+    }
+
+    #[test]
+    fn strings_decode_in_every_layout() {
+        let mut dump = Dump::new();
+        let string = dump.string;
+        let latin1 = dump.string("caf\u{e9}");
+        let utf16: Vec<u8> = "\u{20ac}1"
+            .encode_utf16()
+            .flat_map(|u| u.to_le_bytes())
+            .collect();
+        let utf16 = dump.primitive_array(FieldTag::Byte, &utf16);
+        let utf16 = dump.instance(
+            string,
+            &[Value::Object(utf16), Value::Int(0), Value::Byte(1)],
+        );
+        // A JDK 6 String, a substring sharing a larger char[].
+        let old = dump.class(
+            "test/OldString",
+            dump.object,
+            &[
+                ("value", FieldTag::ArrayObject),
+                ("offset", FieldTag::Int),
+                ("count", FieldTag::Int),
+            ],
+        );
+        let chars: Vec<u8> = "hello w\u{f6}rld"
+            .encode_utf16()
+            .flat_map(|u| u.to_be_bytes())
+            .collect();
+        let chars = dump.primitive_array(FieldTag::Char, &chars);
+        let substring = dump.instance(old, &[Value::Object(chars), Value::Int(6), Value::Int(5)]);
+        let jdk8 = dump.class(
+            "test/Jdk8String",
+            dump.object,
+            &[("value", FieldTag::ArrayObject)],
+        );
+        let whole = dump.instance(jdk8, &[Value::Object(chars)]);
+        let snapshot = dump.load();
+
+        let decode = |id| match snapshot.object(id) {
+            Some(HeapObject::Instance(i)) => string_value(&snapshot, i),
+            _ => None,
+        };
+        assert_eq!(decode(latin1).as_deref(), Some("caf\u{e9}"));
+        assert_eq!(decode(utf16).as_deref(), Some("\u{20ac}1"));
+        assert_eq!(decode(substring).as_deref(), Some("w\u{f6}rld"));
+        // Without offset and count, as of JDK 7, the String is its whole array.
+        assert_eq!(decode(whole).as_deref(), Some("hello w\u{f6}rld"));
+    }
+
+    #[test]
+    fn search_finds_strings_and_loose_arrays() {
+        let mut dump = Dump::new();
+        let string = dump.string("user=admin password=hunter2");
+        let chars: Vec<u8> = "password=swordfish"
+            .encode_utf16()
+            .flat_map(|u| u.to_be_bytes())
+            .collect();
+        let chars = dump.primitive_array(FieldTag::Char, &chars);
+        dump.primitive_array(FieldTag::Byte, b"no secrets here");
+        let snapshot = dump.load();
+
+        let regex = Regex::new("password=\\w+").unwrap();
+        let found: Vec<(u64, TextSource, usize, String, String)> = search(&snapshot, &regex)
+            .into_iter()
+            .map(|m| {
+                let id = snapshot.objects[m.node as usize].object_id();
+                (id, m.source, m.offset, m.text, m.before)
+            })
+            .collect();
+        // The String's byte[] is only searched as part of the String.
+        assert_eq!(
+            found,
+            vec![
+                (
+                    string,
+                    TextSource::String,
+                    11,
+                    "password=hunter2".to_string(),
+                    "user=admin ".to_string()
+                ),
+                (
+                    chars,
+                    TextSource::CharArray,
+                    0,
+                    "password=swordfish".to_string(),
+                    String::new()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn strings_held_by_statics_are_interned() {
+        let mut dump = Dump::new();
+        let literal = dump.string("GET");
+        dump.class_with("test/Http", dump.object, &[], &[("METHOD", literal)], 0);
+        let string = dump.string;
+        let array = dump.primitive_array(FieldTag::Byte, b"GET");
+        for _ in 0..3 {
+            dump.instance(string, &[Value::Object(array)]);
+        }
+        dump.string("POST");
+        dump.string("POST");
+        let snapshot = dump.load();
+
+        let size = |id| snapshot.shallow_size(snapshot.object(id).unwrap());
+        let duplicates = duplicate_strings(&snapshot);
+        let found: Vec<(&str, u64, u64, bool)> = duplicates
+            .iter()
+            .map(|d| (d.value.as_str(), d.count, d.arrays, d.interned))
+            .collect();
+        assert_eq!(found, vec![("GET", 4, 2, true), ("POST", 2, 2, false)]);
+        // Deduplication only saves the second array, interning the Strings too.
+        assert_eq!(duplicates[0].dedup_savings, size(array));
+        assert_eq!(duplicates[0].wasted, 3 * size(literal) + size(array));
+    }
+}
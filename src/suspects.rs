@@ -0,0 +1,441 @@
+//
+// Automated leak suspects, in the spirit of Eclipse MAT's report: single
+// objects or groups of instances of one class that retain a large share of
+// the reachable heap, classes with far more instances than the others piling
+// up in one place, and the point in the dominator tree below them where the
+// memory actually accumulates.
+//
+use crate::dominator::DominatorTree;
+use crate::heap::HeapObject;
+use crate::retained::dominator_children;
+use crate::snapshot::Snapshot;
+
+use std::collections::HashMap;
+
+#[derive(Debug)]
+pub enum SuspectKind {
+    // A single object.
+    Object(u32),
+    // All the instances of a class, along with their common dominator if
+    // they have one.
+    Class {
+        name: String,
+        instances: u64,
+        common_dominator: Option<u32>,
+    },
+    // A class with many times the instances of the median class, `held` of
+    // them under the accumulation point, small as each of them may be.
+    Growth {
+        name: String,
+        instances: u64,
+        median: u64,
+        held: u64,
+    },
+}
+
+#[derive(Debug)]
+pub struct Suspect {
+    pub kind: SuspectKind,
+    pub retained: u64,
+    // The object where the memory accumulates: following the dominator tree
+    // down from the suspect as long as a single child retains most of it.
+    pub accumulation_point: Option<u32>,
+    // The classes with the most objects dominated by the accumulation point
+    // as (class name, objects, shallow bytes).
+    pub contents: Vec<(String, u64, u64)>,
+}
+
+// Share of its parent's retained size a child needs to continue the descent.
+const ACCUMULATION_SHARE: f64 = 0.8;
+
+// A class needs this many instances, and GROWTH_FACTOR times those of the
+// median class, for where they pile up to be looked for. Only the
+// GROWTH_CLASSES classes with the most are.
+const GROWTH_INSTANCES: u64 = 1000;
+const GROWTH_FACTOR: u64 = 10;
+const GROWTH_CLASSES: usize = 5;
+
+fn accumulation_point(children: &[Vec<u32>], retained: &[u64], start: u32) -> u32 {
+    let mut node = start;
+    loop {
+        let biggest = children[node as usize]
+            .iter()
+            .copied()
+            .max_by_key(|&c| retained[c as usize]);
+        match biggest {
+            Some(c)
+                if retained[c as usize] as f64
+                    >= ACCUMULATION_SHARE * retained[node as usize] as f64 =>
+            {
+                node = c
+            }
+            _ => return node,
+        }
+    }
+}
+
+//
+// Where the instances of a class pile up: following the dominator tree down
+// from the virtual root as long as a single child holds most of them, but
+// not into one of them, so that a linked list of them stops at its holder.
+// Gives the node with how many of the instances are under it.
+//
+fn growth_point(
+    tree: &DominatorTree,
+    children: &[Vec<u32>],
+    is_instance: impl Fn(u32) -> bool,
+) -> (u32, u64) {
+    let mut held = vec![0u64; tree.idom.len() + 1];
+    for &node in tree.order.iter().rev() {
+        if is_instance(node) {
+            held[node as usize] += 1;
+        }
+        held[tree.idom[node as usize] as usize] += held[node as usize];
+    }
+    let mut node = tree.virtual_root();
+    let instances = held[node as usize];
+    loop {
+        let biggest = children[node as usize]
+            .iter()
+            .copied()
+            .max_by_key(|&c| held[c as usize]);
+        match biggest {
+            Some(c)
+                if !is_instance(c)
+                    && held[c as usize] as f64 >= ACCUMULATION_SHARE * instances as f64 =>
+            {
+                node = c
+            }
+            _ => return (node, held[node as usize]),
+        }
+    }
+}
+
+// The classes of the objects dominated by a node, by number of objects.
+fn dominated_contents(
+    snapshot: &Snapshot,
+    children: &[Vec<u32>],
+    node: u32,
+    top: usize,
+) -> Vec<(String, u64, u64)> {
+    let mut classes: HashMap<String, (u64, u64)> = HashMap::new();
+    let mut stack = children[node as usize].clone();
+    while let Some(n) = stack.pop() {
+        let object = &snapshot.objects[n as usize];
+        let entry = classes
+            .entry(snapshot.object_class_name(object))
+            .or_insert((0, 0));
+        entry.0 += 1;
+        entry.1 += snapshot.shallow_size(object);
+        stack.extend(children[n as usize].iter().copied());
+    }
+    let mut contents: Vec<(String, u64, u64)> = classes
+        .into_iter()
+        .map(|(name, (objects, bytes))| (name, objects, bytes))
+        .collect();
+    contents.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(&b.0)));
+    contents.truncate(top);
+    contents
+}
+
+//
+// Objects retaining at least `threshold` (a fraction) of the reachable heap,
+// then classes whose instances together do, then classes with outlying
+// numbers of instances gathered under one object, sorted by retained size.
+//
+pub fn leak_suspects(
+    snapshot: &Snapshot,
+    tree: &DominatorTree,
+    retained: &[u64],
+    threshold: f64,
+) -> Vec<Suspect> {
+    let children = dominator_children(tree);
+    let root = tree.virtual_root();
+    let total: u64 = children[root as usize]
+        .iter()
+        .map(|&c| retained[c as usize])
+        .sum();
+    let minimum = (threshold * total as f64) as u64;
+
+    let mut suspects = Vec::new();
+    //
+    // Only the topmost big object of a chain is a suspect: a child of the
+    // virtual root retaining most of the heap makes all its big descendants
+    // big too.
+    //
+    let mut stack: Vec<u32> = children[root as usize].clone();
+    while let Some(node) = stack.pop() {
+        if retained[node as usize] < minimum {
+            continue;
+        }
+        let object = &snapshot.objects[node as usize];
+        // Static fields are what keeps most of the heap alive, so look
+        // below class objects for the actual culprit instead.
+        if let HeapObject::Class(_) = object {
+            stack.extend(children[node as usize].iter().copied());
+            continue;
+        }
+        let point = accumulation_point(&children, retained, node);
+        suspects.push(Suspect {
+            kind: SuspectKind::Object(node),
+            retained: retained[node as usize],
+            accumulation_point: Some(point),
+            contents: dominated_contents(snapshot, &children, point, 5),
+        });
+    }
+
+    //
+    // Instances of the same class under a suspect object are already
+    // explained by it, so classes are judged by what their instances retain
+    // outside of the suspects' subtrees only.
+    //
+    let mut outside: HashMap<String, (u64, u64, Vec<u32>)> = HashMap::new();
+    let mut active: HashMap<String, u32> = HashMap::new();
+    let mut stack: Vec<(u32, bool)> = children[root as usize]
+        .iter()
+        .map(|&c| (c, false))
+        .collect();
+    let suspect_nodes: Vec<u32> = suspects
+        .iter()
+        .filter_map(|s| match s.kind {
+            SuspectKind::Object(node) => Some(node),
+            _ => None,
+        })
+        .collect();
+    while let Some((node, exiting)) = stack.pop() {
+        let object = &snapshot.objects[node as usize];
+        let name = snapshot.object_class_name(object);
+        let count = active.entry(name.clone()).or_insert(0);
+        if exiting {
+            *count -= 1;
+            continue;
+        }
+        if suspect_nodes.contains(&node) {
+            continue;
+        }
+        let entry = outside.entry(name).or_insert((0, 0, Vec::new()));
+        entry.0 += 1;
+        entry.2.push(node);
+        if *count == 0 {
+            entry.1 += retained[node as usize];
+        }
+        *count += 1;
+        stack.push((node, true));
+        stack.extend(children[node as usize].iter().map(|&c| (c, false)));
+    }
+
+    for (name, (instances, class_retained, nodes)) in outside {
+        if class_retained < minimum || instances < 2 || name == "java.lang.Class" {
+            continue;
+        }
+        let first = tree.idom[nodes[0] as usize];
+        let common_dominator = Some(first)
+            .filter(|&d| d != root && nodes.iter().all(|&n| tree.dominators(n).contains(&d)));
+        suspects.push(Suspect {
+            kind: SuspectKind::Class {
+                name,
+                instances,
+                common_dominator,
+            },
+            retained: class_retained,
+            accumulation_point: None,
+            contents: Vec::new(),
+        });
+    }
+
+    //
+    // Classes with many times the instances of the median class, each of
+    // them possibly too small to matter, which pile up under one object:
+    // the elements of a collection that only grows. Those already suspects,
+    // or piling up where a suspect's memory does, are explained already.
+    //
+    let mut classes: HashMap<String, u32> = HashMap::new();
+    let mut counts: Vec<(String, u64)> = Vec::new();
+    let mut class_of = vec![u32::MAX; tree.idom.len()];
+    for &node in &tree.order {
+        let name = snapshot.object_class_name(&snapshot.objects[node as usize]);
+        if name == "java.lang.Class" {
+            continue;
+        }
+        let class = *classes.entry(name.clone()).or_insert_with(|| {
+            counts.push((name, 0));
+            counts.len() as u32 - 1
+        });
+        counts[class as usize].1 += 1;
+        class_of[node as usize] = class;
+    }
+    let mut sorted: Vec<u64> = counts.iter().map(|c| c.1).collect();
+    sorted.sort_unstable();
+    let median = sorted.get(sorted.len() / 2).copied().unwrap_or(0);
+    let mut candidates: Vec<u32> = (0..counts.len() as u32)
+        .filter(|&c| {
+            let instances = counts[c as usize].1;
+            instances >= GROWTH_INSTANCES && instances >= GROWTH_FACTOR * median.max(1)
+        })
+        .collect();
+    candidates.sort_by_key(|&c| std::cmp::Reverse(counts[c as usize].1));
+    candidates.truncate(GROWTH_CLASSES);
+    for class in candidates {
+        let (name, instances) = &counts[class as usize];
+        let explained = suspects.iter().any(|s| match &s.kind {
+            SuspectKind::Class { name: n, .. } => n == name,
+            _ => false,
+        });
+        if explained {
+            continue;
+        }
+        let (point, held) = growth_point(tree, &children, |n| class_of[n as usize] == class);
+        if point == root || suspects.iter().any(|s| s.accumulation_point == Some(point)) {
+            continue;
+        }
+        suspects.push(Suspect {
+            kind: SuspectKind::Growth {
+                name: name.clone(),
+                instances: *instances,
+                median,
+                held,
+            },
+            retained: retained[point as usize],
+            accumulation_point: Some(point),
+            contents: dominated_contents(snapshot, &children, point, 5),
+        });
+    }
+
+    suspects.sort_by_key(|s| std::cmp::Reverse(s.retained));
+    suspects
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dominator;
+    use crate::graph::Graph;
+    use crate::heap::{FieldTag, GcRootKind, Value};
+    use crate::retained::retained_sizes;
+    use crate::testing::Dump;
+
+    #[test]
+    fn suspects_are_big_objects_and_classes() {
+        let mut dump = Dump::new();
+        let object = dump.object;
+        let cache = dump.class("test/Cache", object, &[("entries", FieldTag::ArrayObject)]);
+        let entry = dump.class("test/Entry", object, &[("data", FieldTag::ArrayObject)]);
+        let session = dump.class("test/Session", object, &[("id", FieldTag::Long)]);
+        let mut held = Vec::new();
+        let mut data = Vec::new();
+        for _ in 0..10 {
+            data.push(dump.primitive_array(FieldTag::Long, &[0; 1000]));
+            held.push(dump.instance(entry, &[Value::Object(data[data.len() - 1])]));
+        }
+        let array = dump.object_array;
+        let entries = dump.object_array(array, &held);
+        let cache = dump.instance(cache, &[Value::Object(entries)]);
+        dump.root(GcRootKind::JniGlobal, cache);
+        // Many small objects, held separately.
+        for i in 0..500 {
+            let session = dump.instance(session, &[Value::Long(i)]);
+            dump.root(GcRootKind::JniGlobal, session);
+        }
+        let small = dump.instance(object, &[]);
+        dump.root(GcRootKind::JniGlobal, small);
+        let snapshot = dump.load();
+        let tree = dominator::build(&Graph::build(&snapshot));
+        let retained = retained_sizes(&snapshot, &tree);
+
+        let node = |id| snapshot.index_of(id).unwrap();
+        let suspects = leak_suspects(&snapshot, &tree, &retained, 0.2);
+        assert_eq!(suspects.len(), 2);
+        match &suspects[0].kind {
+            SuspectKind::Class {
+                name,
+                instances,
+                common_dominator,
+            } => {
+                assert_eq!((name.as_str(), *instances), ("test.Session", 500));
+                assert_eq!(*common_dominator, None);
+            }
+            kind => panic!("{:?}", kind),
+        }
+        let sessions: u64 = snapshot
+            .objects_of_class("test.Session")
+            .iter()
+            .map(|&n| retained[n as usize])
+            .sum();
+        assert_eq!(suspects[0].retained, sessions);
+
+        // The memory of the cache is in its array, but no single entry.
+        let suspect = &suspects[1];
+        assert!(matches!(suspect.kind, SuspectKind::Object(n) if n == node(cache)));
+        assert_eq!(suspect.retained, retained[node(cache) as usize]);
+        assert_eq!(suspect.accumulation_point, Some(node(entries)));
+        let size = |id| snapshot.shallow_size(snapshot.object(id).unwrap());
+        assert_eq!(
+            suspect.contents[0],
+            ("long[]".to_string(), 10, 10 * size(data[0]))
+        );
+        assert_eq!(
+            suspect.contents[1],
+            ("test.Entry".to_string(), 10, 10 * size(held[0]))
+        );
+    }
+
+    #[test]
+    fn suspects_include_classes_piling_up_in_one_place() {
+        let mut dump = Dump::new();
+        let object = dump.object;
+        let queue = dump.class("test/Queue", object, &[("items", FieldTag::ArrayObject)]);
+        let event = dump.class("test/Event", object, &[("time", FieldTag::Long)]);
+        let node = dump.class("test/Node", object, &[("next", FieldTag::NormalObject)]);
+        let list = dump.class("test/List", object, &[("head", FieldTag::NormalObject)]);
+        let mut events = Vec::new();
+        for i in 0..2000 {
+            events.push(dump.instance(event, &[Value::Long(i)]));
+        }
+        let array = dump.object_array;
+        let items = dump.object_array(array, &events);
+        let queue = dump.instance(queue, &[Value::Object(items)]);
+        dump.root(GcRootKind::JniGlobal, queue);
+        // As many, held in a list: they pile up in its head, not its tail.
+        let mut next = 0;
+        for _ in 0..1500 {
+            next = dump.instance(node, &[Value::Object(next)]);
+        }
+        let list = dump.instance(list, &[Value::Object(next)]);
+        dump.root(GcRootKind::JniGlobal, list);
+        // Far bigger than all of them together, so the only big object.
+        let big = dump.primitive_array(FieldTag::Long, &[0; 1_000_000]);
+        dump.root(GcRootKind::JniGlobal, big);
+        let snapshot = dump.load();
+        let tree = dominator::build(&Graph::build(&snapshot));
+        let retained = retained_sizes(&snapshot, &tree);
+
+        let node = |id| snapshot.index_of(id).unwrap();
+        let suspects = leak_suspects(&snapshot, &tree, &retained, 0.2);
+        assert_eq!(suspects.len(), 3);
+        assert!(matches!(suspects[0].kind, SuspectKind::Object(n) if n == node(big)));
+        let growth: Vec<_> = suspects[1..]
+            .iter()
+            .map(|s| match &s.kind {
+                SuspectKind::Growth {
+                    name,
+                    instances,
+                    median,
+                    held,
+                } => {
+                    assert_eq!(*median, 1);
+                    assert_eq!(s.retained, retained[s.accumulation_point.unwrap() as usize]);
+                    (name.as_str(), *instances, *held, s.accumulation_point)
+                }
+                kind => panic!("{:?}", kind),
+            })
+            .collect();
+        assert_eq!(
+            growth,
+            [
+                ("test.Event", 2000, 2000, Some(node(items))),
+                ("test.Node", 1500, 1500, Some(node(list))),
+            ]
+        );
+        assert_eq!(suspects[1].contents[0].0, "test.Event");
+    }
+}
@@ -0,0 +1,145 @@
+//
+// The UTF8String records of a dump, interned: many carry the same text
+// (method signatures, names repeated across classes, constant pool
+// entries), which is stored once in a single buffer that every id holding
+// it points into. The table finding the earlier copy of a text is only
+// kept while the records are being read.
+//
+use crate::idhash::IdMap;
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+// Where a string is in the buffer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Span {
+    start: u64,
+    len: u32,
+}
+
+#[derive(Default)]
+pub struct Symbols {
+    text: String,
+    spans: IdMap<u64, Span>,
+    // The spans of the distinct texts by hash, until finish().
+    distinct: IdMap<u64, Vec<Span>>,
+    unique: usize,
+    // Bytes of the duplicates, not stored again.
+    shared: u64,
+}
+
+impl Symbols {
+    pub fn new() -> Symbols {
+        Symbols::default()
+    }
+
+    fn span_text(&self, span: Span) -> &str {
+        &self.text[span.start as usize..span.start as usize + span.len as usize]
+    }
+
+    pub fn insert(&mut self, id: u64, value: &str) {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+        let existing = self
+            .distinct
+            .get(&hash)
+            .and_then(|spans| spans.iter().find(|&&s| self.span_text(s) == value))
+            .copied();
+        let span = match existing {
+            Some(span) => {
+                self.shared += value.len() as u64;
+                span
+            }
+            None => {
+                let span = Span {
+                    start: self.text.len() as u64,
+                    len: value.len() as u32,
+                };
+                self.text.push_str(value);
+                self.distinct.entry(hash).or_default().push(span);
+                self.unique += 1;
+                span
+            }
+        };
+        self.spans.insert(id, span);
+    }
+
+    // Makes room for `ids` strings of `bytes` bytes in all.
+    pub fn reserve(&mut self, ids: usize, bytes: usize) {
+        self.spans.reserve(ids);
+        self.distinct.reserve(ids);
+        self.text.reserve(bytes);
+    }
+
+    // Drops what is only needed to intern more strings.
+    pub fn finish(&mut self) {
+        self.distinct = IdMap::default();
+        self.text.shrink_to_fit();
+    }
+
+    pub fn get(&self, id: u64) -> Option<&str> {
+        self.spans.get(&id).map(|&span| self.span_text(span))
+    }
+
+    // Every id with its text, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &str)> + '_ {
+        self.spans
+            .iter()
+            .map(move |(&id, &span)| (id, self.span_text(span)))
+    }
+
+    // The number of ids.
+    pub fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+
+    // The number of distinct texts.
+    pub fn unique(&self) -> usize {
+        self.unique
+    }
+
+    // The bytes of text saved by storing the duplicates once.
+    pub fn shared_bytes(&self) -> u64 {
+        self.shared
+    }
+
+    // The bytes of text stored.
+    pub fn text_bytes(&self) -> u64 {
+        self.text.len() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_texts_are_stored_once() {
+        let mut symbols = Symbols::new();
+        symbols.insert(1, "java/lang/String");
+        symbols.insert(2, "()V");
+        symbols.insert(3, "java/lang/String");
+        symbols.insert(4, "");
+        symbols.insert(5, "()V");
+        symbols.finish();
+
+        assert_eq!(symbols.len(), 5);
+        assert_eq!(symbols.unique(), 3);
+        assert_eq!(symbols.text_bytes(), 19);
+        assert_eq!(symbols.shared_bytes(), 19);
+        assert_eq!(symbols.get(3), Some("java/lang/String"));
+        assert_eq!(symbols.get(5), Some("()V"));
+        assert_eq!(symbols.get(4), Some(""));
+        assert_eq!(symbols.get(6), None);
+
+        let mut all: Vec<(u64, &str)> = symbols.iter().collect();
+        all.sort();
+        assert_eq!(all[0], (1, "java/lang/String"));
+        assert_eq!(all[1], (2, "()V"));
+    }
+}
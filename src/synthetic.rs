@@ -0,0 +1,561 @@
+//
+// Synthetic heap dumps, for benchmarks, tests and checking other tools on
+// dumps of a known shape. Everything comes from a seeded generator, so the
+// same options always give the same bytes. Objects are written as they are
+// made: object `i` has id OBJECTS + 8 * i, so that references can point
+// to objects not written yet, and nothing but the classes is kept.
+//
+// Most objects are referred to by one written after them: the objects no
+// other refers to yet wait in a queue, taken from by the reference fields
+// and elements of the next ones (the others are null or refer to any
+// object). Those still waiting are put in Object[] held by the frames of a
+// "main" thread, except one in twenty left as garbage.
+//
+use crate::heap::{
+    ClassDump, FieldDescriptor, FieldTag, GcRoot, GcRootKind, HeapObject, InstanceDump,
+    ObjectArrayDump, PrimitiveArrayDump, Value,
+};
+use crate::records::{LoadClassRecord, StackFrameRecord, StackTraceRecord};
+use crate::sampling::Rng;
+use crate::writer::{put_value, Writer};
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Write};
+
+// Where the ids of classes and objects start.
+const CLASSES: u64 = 0x0100_0000;
+const OBJECTS: u64 = 0x1000_0000;
+
+// How many objects wait for a referrer at most, and how many go in each
+// Object[] holding them.
+const UNOWNED: usize = 1 << 16;
+const HOLDER: usize = 1024;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Shape {
+    // Instances of the generated classes with some strings and arrays.
+    Mixed,
+    // Mostly strings, many of them duplicates.
+    Strings,
+    // Long chains of nodes, for deep dominator trees and root paths.
+    Deep,
+    // Arrays of up to a million elements.
+    Arrays,
+}
+
+impl Shape {
+    pub fn parse(name: &str) -> Option<Shape> {
+        Some(match name {
+            "mixed" => Shape::Mixed,
+            "strings" => Shape::Strings,
+            "deep" => Shape::Deep,
+            "arrays" => Shape::Arrays,
+            _ => return None,
+        })
+    }
+
+    // The per-mille of instances, strings and object arrays, the rest
+    // being primitive arrays. The deep shape only has nodes.
+    fn weights(self) -> (u64, u64, u64) {
+        match self {
+            Shape::Mixed => (550, 250, 100),
+            Shape::Strings => (100, 850, 50),
+            Shape::Deep => (1000, 0, 0),
+            Shape::Arrays => (200, 100, 350),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    // The number of generated classes, besides those of the JDK.
+    pub classes: u64,
+    pub objects: u64,
+    pub seed: u64,
+    pub shape: Shape,
+    pub id_size: u32,
+    // The length of the chains of the deep shape.
+    pub depth: u64,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            classes: 100,
+            objects: 100_000,
+            seed: 1,
+            shape: Shape::Mixed,
+            id_size: 8,
+            depth: 10_000,
+        }
+    }
+}
+
+struct Class {
+    id: u64,
+    // The types of the fields of instances, superclass fields included.
+    layout: Vec<FieldTag>,
+}
+
+struct Generator<W: Write> {
+    writer: Writer<W>,
+    options: Options,
+    rng: Rng,
+    symbols: HashMap<String, u64>,
+    classes: Vec<Class>,
+    // The next object to write.
+    next: u64,
+    // Objects that nothing refers to yet, oldest first.
+    unowned: VecDeque<u64>,
+    // The class of Object[].
+    object_array: usize,
+}
+
+impl<W: Write> Generator<W> {
+    fn symbol(&mut self, text: &str) -> io::Result<u64> {
+        if let Some(&id) = self.symbols.get(text) {
+            return Ok(id);
+        }
+        let id = self.symbols.len() as u64 + 1;
+        self.writer.utf8_string(id, text)?;
+        self.symbols.insert(text.to_string(), id);
+        Ok(id)
+    }
+
+    // Declares a class, returning its index in `classes`.
+    fn class(
+        &mut self,
+        name: &str,
+        superclass: Option<usize>,
+        fields: &[(&str, FieldTag)],
+    ) -> io::Result<usize> {
+        let index = self.classes.len();
+        let id = CLASSES + 8 * index as u64;
+        let name_id = self.symbol(name)?;
+        self.writer.load_class(&LoadClassRecord {
+            serial_num: index as u32 + 1,
+            object_id: id,
+            strace_num: 0,
+            strname_id: name_id,
+        })?;
+        let mut descriptors = Vec::new();
+        let mut layout = Vec::new();
+        for &(field, tag) in fields {
+            descriptors.push(FieldDescriptor {
+                name_id: self.symbol(field)?,
+                tag,
+            });
+            layout.push(tag);
+        }
+        let super_id = match superclass {
+            Some(s) => {
+                layout.extend(self.classes[s].layout.iter().copied());
+                self.classes[s].id
+            }
+            None => 0,
+        };
+        let id_size = self.options.id_size;
+        let size = layout.iter().map(|t| t.size(id_size)).sum::<u32>();
+        let dump = ClassDump::new(id, super_id, size, descriptors, &[], id_size);
+        self.writer.object(&HeapObject::Class(dump))?;
+        self.classes.push(Class { id, layout });
+        Ok(index)
+    }
+
+    fn object_id(index: u64) -> u64 {
+        OBJECTS + 8 * index
+    }
+
+    // A reference for a field or element: null, the oldest object that
+    // nothing refers to yet or any object, 2:6:2.
+    fn reference(&mut self) -> u64 {
+        match self.rng.below(10) {
+            0 | 1 => 0,
+            2..=7 if !self.unowned.is_empty() => self.unowned.pop_front().unwrap(),
+            _ => Generator::<W>::object_id(self.rng.below(self.options.objects)),
+        }
+    }
+
+    fn value(&mut self, tag: FieldTag) -> Value {
+        let bits = self.rng.next_u64();
+        match tag {
+            FieldTag::ArrayObject | FieldTag::NormalObject => Value::Object(self.reference()),
+            FieldTag::Boolean => Value::Boolean(bits & 1 != 0),
+            FieldTag::Char => Value::Char(b'a' as u16 + (bits % 26) as u16),
+            FieldTag::Float => Value::Float((bits % 1000) as f32 / 10.0),
+            FieldTag::Double => Value::Double((bits % 100_000) as f64 / 100.0),
+            FieldTag::Byte => Value::Byte(bits as i8),
+            FieldTag::Short => Value::Short(bits as i16),
+            FieldTag::Int => Value::Int((bits % 1_000_000) as i32),
+            FieldTag::Long => Value::Long(bits as i64 >> 16),
+        }
+    }
+
+    // Writes the next object, an instance of `class` with `values` for its
+    // first fields and random values for the others.
+    fn instance(&mut self, class: usize, values: &[Value]) -> io::Result<u64> {
+        let id = Generator::<W>::object_id(self.next);
+        self.next += 1;
+        let mut data = Vec::new();
+        for i in 0..self.classes[class].layout.len() {
+            let value = match values.get(i) {
+                Some(&value) => value,
+                None => self.value(self.classes[class].layout[i]),
+            };
+            put_value(&mut data, value, self.options.id_size);
+        }
+        self.writer.instance_dump(&InstanceDump {
+            object_id: id,
+            strace_num: 0,
+            class_id: self.classes[class].id,
+            data,
+        })?;
+        Ok(id)
+    }
+
+    fn primitive_array(&mut self, element_tag: FieldTag, data: Vec<u8>) -> io::Result<u64> {
+        let id = Generator::<W>::object_id(self.next);
+        self.next += 1;
+        self.writer.primitive_array_dump(&PrimitiveArrayDump {
+            object_id: id,
+            strace_num: 0,
+            element_tag,
+            length: data.len() as u32 / element_tag.size(self.options.id_size),
+            data,
+        })?;
+        Ok(id)
+    }
+
+    fn object_array_dump(&mut self, elements: Vec<u64>) -> io::Result<u64> {
+        let id = Generator::<W>::object_id(self.next);
+        self.next += 1;
+        self.writer.object_array_dump(&ObjectArrayDump {
+            object_id: id,
+            strace_num: 0,
+            class_id: self.classes[self.object_array].id,
+            elements,
+        })?;
+        Ok(id)
+    }
+
+    // An object written that nothing refers to, unless it is left as garbage.
+    fn release(&mut self, id: u64) -> io::Result<()> {
+        if self.rng.below(20) != 0 {
+            self.unowned.push_back(id);
+        }
+        if self.unowned.len() > UNOWNED {
+            self.hold()?;
+        }
+        Ok(())
+    }
+
+    // Puts the oldest objects that nothing refers to in an Object[] held by
+    // the frame of main().
+    fn hold(&mut self) -> io::Result<()> {
+        let count = self.unowned.len().min(HOLDER);
+        let elements = self.unowned.drain(..count).collect();
+        let id = self.object_array_dump(elements)?;
+        self.writer.root(&GcRoot {
+            kind: GcRootKind::JavaFrame,
+            object_id: id,
+            thread_serial_num: Some(1),
+            frame_num: Some(2),
+            strace_num: None,
+        })
+    }
+
+    // A Latin-1 java.lang.String and its byte[], both written.
+    fn string(&mut self, string_class: usize, text: &str) -> io::Result<u64> {
+        let value = self.primitive_array(FieldTag::Byte, text.as_bytes().to_vec())?;
+        self.instance(
+            string_class,
+            &[Value::Object(value), Value::Int(0), Value::Byte(0)],
+        )
+    }
+
+    // A length up to `max`, most of them short.
+    fn length(&mut self, max: u64) -> u64 {
+        let bits = 64 - max.leading_zeros() as u64;
+        let scale = 1 << self.rng.below(bits);
+        (scale + self.rng.below(scale)).min(max)
+    }
+}
+
+// Writes a synthetic dump to `out`, which is given back.
+pub fn generate<W: Write>(options: &Options, out: W) -> io::Result<W> {
+    let id_size = options.id_size;
+    let mut generator = Generator {
+        writer: Writer::new(out, id_size, 0)?,
+        options: options.clone(),
+        rng: Rng::new(options.seed),
+        symbols: HashMap::new(),
+        classes: Vec::new(),
+        next: 0,
+        unowned: VecDeque::new(),
+        object_array: 0,
+    };
+    let object = generator.class("java/lang/Object", None, &[])?;
+    let string = generator.class(
+        "java/lang/String",
+        Some(object),
+        &[
+            ("value", FieldTag::NormalObject),
+            ("hash", FieldTag::Int),
+            ("coder", FieldTag::Byte),
+        ],
+    )?;
+    let thread = generator.class(
+        "java/lang/Thread",
+        Some(object),
+        &[
+            ("name", FieldTag::NormalObject),
+            ("tid", FieldTag::Long),
+            ("daemon", FieldTag::Boolean),
+            ("threadStatus", FieldTag::Int),
+        ],
+    )?;
+    generator.object_array = generator.class("[Ljava/lang/Object;", Some(object), &[])?;
+    for name in &["[B", "[I", "[J"] {
+        generator.class(name, Some(object), &[])?;
+    }
+    let node = generator.class(
+        "synthetic/Node",
+        Some(object),
+        &[("next", FieldTag::NormalObject), ("value", FieldTag::Int)],
+    )?;
+
+    let tags = [
+        FieldTag::NormalObject,
+        FieldTag::NormalObject,
+        FieldTag::Int,
+        FieldTag::Long,
+        FieldTag::Boolean,
+        FieldTag::Double,
+    ];
+    let first = generator.classes.len();
+    for k in 0..options.classes {
+        let superclass = match k {
+            _ if k == 0 || generator.rng.below(3) != 0 => object,
+            _ => first + generator.rng.below(k) as usize,
+        };
+        let fields: Vec<(String, FieldTag)> = (0..1 + generator.rng.below(5))
+            .map(|i| {
+                let tag = tags[generator.rng.below(tags.len() as u64) as usize];
+                let prefix = if tag.is_object() {
+                    "ref"
+                } else {
+                    tag.java_name()
+                };
+                (format!("{}{}", prefix, i), tag)
+            })
+            .collect();
+        let fields: Vec<(&str, FieldTag)> = fields.iter().map(|(n, t)| (n.as_str(), *t)).collect();
+        generator.class(
+            &format!("synthetic/p{}/Class{}", k % 16, k),
+            Some(superclass),
+            &fields,
+        )?;
+    }
+
+    // The main thread and its stack.
+    let signature = generator.symbol("()V")?;
+    let source = generator.symbol("Node.java")?;
+    let frames = [1, 2, 3];
+    for (&frame, method) in frames.iter().zip(&["visit", "walk", "main"]) {
+        let method_name_id = generator.symbol(method)?;
+        generator.writer.stack_frame(&StackFrameRecord {
+            frame_id: frame,
+            method_name_id,
+            method_sign_id: signature,
+            source_name_id: source,
+            class_serial_num: node as u32 + 1,
+            line_num: 10 * frame as i32,
+        })?;
+    }
+    generator.writer.stack_trace(&StackTraceRecord {
+        serial_num: 1,
+        thread_serial_num: 1,
+        nframes: frames.len() as u32,
+        frame_ids: frames.to_vec(),
+    })?;
+    let name = generator.string(string, "main")?;
+    let main = generator.instance(
+        thread,
+        &[
+            Value::Object(name),
+            Value::Long(1),
+            Value::Boolean(false),
+            Value::Int(0x5),
+        ],
+    )?;
+    generator.writer.root(&GcRoot {
+        kind: GcRootKind::ThreadObject,
+        object_id: main,
+        thread_serial_num: Some(1),
+        frame_num: None,
+        strace_num: Some(1),
+    })?;
+    for class in &generator.classes {
+        generator.writer.root(&GcRoot {
+            kind: GcRootKind::StickyClass,
+            object_id: class.id,
+            thread_serial_num: None,
+            frame_num: None,
+            strace_num: None,
+        })?;
+    }
+
+    let (instances, strings, object_arrays) = options.shape.weights();
+    let max_length = match options.shape {
+        Shape::Arrays => 1 << 20,
+        _ => 64,
+    };
+    let primitives = [FieldTag::Byte, FieldTag::Int, FieldTag::Long];
+    let generated = generator.classes.len() - first;
+    let mut chain = 0;
+    while generator.next < options.objects {
+        let index = generator.next;
+        if options.shape == Shape::Deep {
+            // The next node of the chain: the one before, except at its start.
+            let next = match chain {
+                0 => 0,
+                _ => Generator::<W>::object_id(index - 1),
+            };
+            chain = (chain + 1) % options.depth.max(1);
+            let id = generator.instance(node, &[Value::Object(next)])?;
+            if chain == 0 || generator.next == options.objects {
+                generator.release(id)?;
+            }
+            continue;
+        }
+        let kind = generator.rng.below(1000);
+        let id = if kind < instances {
+            let class = match generated {
+                0 => node,
+                _ => first + generator.rng.below(generated as u64) as usize,
+            };
+            generator.instance(class, &[])?
+        } else if kind < instances + strings && index + 2 <= options.objects {
+            // Strings repeat themselves in the strings shape.
+            let pool = match options.shape {
+                Shape::Strings => (options.objects / 20).max(1),
+                _ => options.objects,
+            };
+            let text = format!("string-{}", generator.rng.below(pool));
+            generator.string(string, &text)?
+        } else if kind < instances + strings + object_arrays {
+            let length = generator.length(max_length / 16);
+            let elements = (0..length).map(|_| generator.reference()).collect();
+            generator.object_array_dump(elements)?
+        } else {
+            let tag = primitives[generator.rng.below(primitives.len() as u64) as usize];
+            let bytes = generator.length(max_length) * tag.size(id_size) as u64;
+            let mut data = Vec::with_capacity(bytes as usize + 8);
+            while (data.len() as u64) < bytes {
+                data.extend_from_slice(&generator.rng.next_u64().to_be_bytes());
+            }
+            data.truncate(bytes as usize);
+            generator.primitive_array(tag, data)?
+        };
+        generator.release(id)?;
+    }
+    while !generator.unowned.is_empty() {
+        generator.hold()?;
+    }
+    generator.writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::{self, Graph};
+    use crate::reachability;
+    use crate::snapshot::Snapshot;
+    use crate::testing::TempFile;
+
+    #[test]
+    fn dumps_are_reproducible_and_whole() {
+        for (shape, id_size) in [(Shape::Mixed, 8), (Shape::Strings, 4)] {
+            let options = Options {
+                classes: 20,
+                objects: 5000,
+                shape,
+                id_size,
+                ..Options::default()
+            };
+            let bytes = generate(&options, Vec::new()).unwrap();
+            assert_eq!(generate(&options, Vec::new()).unwrap(), bytes);
+            let reseeded = Options { seed: 2, ..options };
+            assert_ne!(generate(&reseeded, Vec::new()).unwrap(), bytes);
+
+            let snapshot = Snapshot::load(TempFile::new(&bytes).path());
+            assert_eq!(snapshot.header.identifier_size, id_size);
+            // The classes of the JDK, then the generated ones.
+            assert_eq!(snapshot.class_serials.len(), 8 + 20);
+            // Named and sized as HotSpot has them.
+            let names: Vec<&str> = snapshot
+                .classes
+                .values()
+                .map(|c| snapshot.string(c.strname_id))
+                .collect();
+            for name in ["java/lang/String", "[Ljava/lang/Object;", "synthetic/Node"] {
+                assert!(names.contains(&name), "{}", name);
+            }
+            for object in &snapshot.objects {
+                if let HeapObject::Instance(i) = object {
+                    let class = snapshot.class_dump(i.class_id).unwrap();
+                    assert_eq!(class.instance_size as usize, i.data.len());
+                }
+            }
+            let generated = snapshot
+                .objects
+                .iter()
+                .filter(|o| (OBJECTS..OBJECTS + 8 * options.objects).contains(&o.object_id()))
+                .count();
+            assert_eq!(generated as u64, options.objects);
+            // No reference is left dangling, and one object in twenty or so
+            // is garbage.
+            for object in &snapshot.objects {
+                for target in graph::outgoing_references(&snapshot, object) {
+                    assert!(snapshot.index_of(target).is_some(), "{:#x}", target);
+                }
+            }
+            let marked = reachability::mark(&Graph::build(&snapshot));
+            let garbage = marked.iter().filter(|&&m| !m).count() as u64;
+            assert!(
+                garbage > options.objects / 100 && garbage < options.objects / 8,
+                "{:?}: {} unreachable",
+                shape,
+                garbage
+            );
+        }
+    }
+
+    #[test]
+    fn deep_dumps_are_chains_of_nodes() {
+        let options = Options {
+            classes: 0,
+            objects: 1000,
+            shape: Shape::Deep,
+            depth: 100,
+            ..Options::default()
+        };
+        let snapshot =
+            Snapshot::load(TempFile::new(&generate(&options, Vec::new()).unwrap()).path());
+        let nodes: Vec<&InstanceDump> = snapshot
+            .objects_of_class("synthetic.Node")
+            .into_iter()
+            .filter_map(|n| match &snapshot.objects[n as usize] {
+                HeapObject::Instance(i) => Some(i),
+                _ => None,
+            })
+            .collect();
+        // All but the name of main, its byte[] and its Thread.
+        assert_eq!(nodes.len(), 997);
+        let heads = nodes
+            .iter()
+            .filter(|i| snapshot.field_value(i, "next") == Some(Value::Object(0)))
+            .count();
+        assert_eq!(heads, 10);
+    }
+}
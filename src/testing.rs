@@ -0,0 +1,321 @@
+//
+// Dumps made to order for the tests: classes, instances, arrays and GC
+// roots written with the HPROF writer, then loaded back as a snapshot.
+// Class names are those of the JVM (java/lang/String), as HotSpot has them.
+//
+use crate::heap::{
+    ClassDump, FieldDescriptor, FieldTag, GcRoot, GcRootKind, HeapObject, InstanceDump,
+    ObjectArrayDump, PrimitiveArrayDump, Value,
+};
+use crate::records::{LoadClassRecord, StackFrameRecord, StackTraceRecord};
+use crate::snapshot::Snapshot;
+use crate::writer::{put_value, Writer};
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const CLASSES: u64 = 0x1000;
+const OBJECTS: u64 = 0x10_0000;
+
+pub struct Dump {
+    writer: Writer<Vec<u8>>,
+    symbols: HashMap<String, u64>,
+    // The field types of the instances of each class, superclasses' included.
+    layouts: HashMap<u64, Vec<FieldTag>>,
+    classes: u64,
+    objects: u64,
+    frames: u64,
+    traces: u32,
+    pub object: u64,
+    pub string: u64,
+    pub object_array: u64,
+}
+
+impl Dump {
+    // A dump with java.lang.Object, java.lang.String and Object[] already.
+    pub fn new() -> Dump {
+        let mut dump = Dump {
+            writer: Writer::new(Vec::new(), 8, 1_700_000_000_000).unwrap(),
+            symbols: HashMap::new(),
+            layouts: HashMap::new(),
+            classes: 0,
+            objects: 0,
+            frames: 0,
+            traces: 0,
+            object: 0,
+            string: 0,
+            object_array: 0,
+        };
+        dump.object = dump.class("java/lang/Object", 0, &[]);
+        dump.string = dump.class(
+            "java/lang/String",
+            dump.object,
+            &[
+                ("value", FieldTag::ArrayObject),
+                ("hash", FieldTag::Int),
+                ("coder", FieldTag::Byte),
+            ],
+        );
+        dump.object_array = dump.class("[Ljava/lang/Object;", dump.object, &[]);
+        dump
+    }
+
+    // Cuts the heap dump segments at about `bytes`, to have several.
+    pub fn set_segment_bytes(&mut self, bytes: usize) {
+        self.writer.set_segment_bytes(bytes);
+    }
+
+    pub fn symbol(&mut self, text: &str) -> u64 {
+        if let Some(&id) = self.symbols.get(text) {
+            return id;
+        }
+        let id = self.symbols.len() as u64 + 1;
+        self.writer.utf8_string(id, text).unwrap();
+        self.symbols.insert(text.to_string(), id);
+        id
+    }
+
+    // A class (0 for no superclass) with its instance fields, returning its id.
+    pub fn class(&mut self, name: &str, superclass: u64, fields: &[(&str, FieldTag)]) -> u64 {
+        self.class_with(name, superclass, fields, &[], 0)
+    }
+
+    // The same with static references, by name, and a class loader.
+    pub fn class_with(
+        &mut self,
+        name: &str,
+        superclass: u64,
+        fields: &[(&str, FieldTag)],
+        statics: &[(&str, u64)],
+        loader: u64,
+    ) -> u64 {
+        self.classes += 1;
+        let id = CLASSES + 8 * self.classes;
+        let strname_id = self.symbol(name);
+        self.writer
+            .load_class(&LoadClassRecord {
+                serial_num: self.classes as u32,
+                object_id: id,
+                strace_num: 0,
+                strname_id,
+            })
+            .unwrap();
+        let mut descriptors = Vec::new();
+        let mut layout = Vec::new();
+        for &(field, tag) in fields {
+            descriptors.push(FieldDescriptor {
+                name_id: self.symbol(field),
+                tag,
+            });
+            layout.push(tag);
+        }
+        layout.extend(self.layouts.get(&superclass).cloned().unwrap_or_default());
+        let statics: Vec<(u64, u64)> = statics
+            .iter()
+            .map(|&(field, target)| (self.symbol(field), target))
+            .collect();
+        let size = layout.iter().map(|t| t.size(8)).sum::<u32>();
+        let mut dump = ClassDump::new(id, superclass, size, descriptors, &statics, 8);
+        dump.class_loader_id = loader;
+        self.writer.object(&HeapObject::Class(dump)).unwrap();
+        self.layouts.insert(id, layout);
+        id
+    }
+
+    fn next_id(&mut self) -> u64 {
+        self.objects += 1;
+        OBJECTS + 8 * self.objects
+    }
+
+    // The ids the next `count` objects will get, to refer to them (cycles).
+    pub fn next_ids(&self, count: usize) -> Vec<u64> {
+        (1..=count as u64)
+            .map(|i| OBJECTS + 8 * (self.objects + i))
+            .collect()
+    }
+
+    //
+    // An instance with `values` for its first fields (its own, then those
+    // of its superclasses) and zeros or nulls for the others.
+    //
+    pub fn instance(&mut self, class: u64, values: &[Value]) -> u64 {
+        self.instance_at(class, values, 0)
+    }
+
+    // The same allocated at the stack trace of a serial.
+    pub fn instance_at(&mut self, class: u64, values: &[Value], strace_num: u32) -> u64 {
+        let id = self.next_id();
+        let mut data = Vec::new();
+        for (i, &tag) in self.layouts[&class].iter().enumerate() {
+            let value = match values.get(i) {
+                Some(&value) => value,
+                None => zero(tag),
+            };
+            put_value(&mut data, value, 8);
+        }
+        self.writer
+            .instance_dump(&InstanceDump {
+                object_id: id,
+                strace_num,
+                class_id: class,
+                data,
+            })
+            .unwrap();
+        id
+    }
+
+    pub fn object_array(&mut self, class: u64, elements: &[u64]) -> u64 {
+        let id = self.next_id();
+        self.writer
+            .object_array_dump(&ObjectArrayDump {
+                object_id: id,
+                strace_num: 0,
+                class_id: class,
+                elements: elements.to_vec(),
+            })
+            .unwrap();
+        id
+    }
+
+    // A primitive array of the big-endian bytes of its elements.
+    pub fn primitive_array(&mut self, element_tag: FieldTag, data: &[u8]) -> u64 {
+        let id = self.next_id();
+        self.writer
+            .primitive_array_dump(&PrimitiveArrayDump {
+                object_id: id,
+                strace_num: 0,
+                element_tag,
+                length: data.len() as u32 / element_tag.size(8),
+                data: data.to_vec(),
+            })
+            .unwrap();
+        id
+    }
+
+    // A Latin-1 java.lang.String (as of JDK 9) and its byte[].
+    pub fn string(&mut self, text: &str) -> u64 {
+        let bytes: Vec<u8> = text.chars().map(|c| c as u8).collect();
+        let value = self.primitive_array(FieldTag::Byte, &bytes);
+        let string = self.string;
+        self.instance(string, &[Value::Object(value)])
+    }
+
+    // A stack frame of a method of a class, returning its id.
+    pub fn frame(&mut self, class: u64, method: &str, signature: &str, line: i32) -> u64 {
+        self.frames += 1;
+        let frame = StackFrameRecord {
+            frame_id: self.frames,
+            method_name_id: self.symbol(method),
+            method_sign_id: self.symbol(signature),
+            source_name_id: 0,
+            class_serial_num: ((class - CLASSES) / 8) as u32,
+            line_num: line,
+        };
+        self.writer.stack_frame(&frame).unwrap();
+        self.frames
+    }
+
+    // The stack trace of a thread, innermost frame first, returning its serial.
+    pub fn trace(&mut self, thread: u32, frames: &[u64]) -> u32 {
+        self.traces += 1;
+        self.writer
+            .stack_trace(&StackTraceRecord {
+                serial_num: self.traces,
+                thread_serial_num: thread,
+                nframes: frames.len() as u32,
+                frame_ids: frames.to_vec(),
+            })
+            .unwrap();
+        self.traces
+    }
+
+    pub fn root(&mut self, kind: GcRootKind, object_id: u64) {
+        self.root_of(kind, object_id, None);
+    }
+
+    // A root of the given thread (for frames, locals and thread objects).
+    pub fn root_of(&mut self, kind: GcRootKind, object_id: u64, thread: Option<u32>) {
+        self.root_at(kind, object_id, thread, None, None);
+    }
+
+    //
+    // The same with the depth of the frame of a local or the stack trace of
+    // a thread object.
+    //
+    pub fn root_at(
+        &mut self,
+        kind: GcRootKind,
+        object_id: u64,
+        thread: Option<u32>,
+        frame_num: Option<u32>,
+        strace_num: Option<u32>,
+    ) {
+        self.writer
+            .root(&GcRoot {
+                kind,
+                object_id,
+                thread_serial_num: thread,
+                frame_num,
+                strace_num,
+            })
+            .unwrap();
+    }
+
+    pub fn bytes(self) -> Vec<u8> {
+        self.writer.finish().unwrap()
+    }
+
+    pub fn load(self) -> Snapshot {
+        let file = TempFile::new(&self.bytes());
+        Snapshot::load(file.path())
+    }
+}
+
+impl Default for Dump {
+    fn default() -> Dump {
+        Dump::new()
+    }
+}
+
+fn zero(tag: FieldTag) -> Value {
+    match tag {
+        FieldTag::ArrayObject | FieldTag::NormalObject => Value::Object(0),
+        FieldTag::Boolean => Value::Boolean(false),
+        FieldTag::Char => Value::Char(0),
+        FieldTag::Float => Value::Float(0.0),
+        FieldTag::Double => Value::Double(0.0),
+        FieldTag::Byte => Value::Byte(0),
+        FieldTag::Short => Value::Short(0),
+        FieldTag::Int => Value::Int(0),
+        FieldTag::Long => Value::Long(0),
+    }
+}
+
+// A file in the temporary directory, removed with it.
+pub struct TempFile(PathBuf);
+
+impl TempFile {
+    pub fn new(bytes: &[u8]) -> TempFile {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "hprof-cat-testing-{}-{}.hprof",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, bytes).unwrap();
+        TempFile(path)
+    }
+
+    pub fn path(&self) -> &str {
+        self.0.to_str().unwrap()
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
@@ -0,0 +1,618 @@
+//
+// Text thread dumps taken next to a heap dump: jstack (or jcmd Thread.print,
+// or kill -3 on HotSpot) and OpenJ9 javacores. They have what an HPROF dump
+// doesn't: the monitors each frame holds, what a thread waits for and why
+// it is in its state (sleeping, parking, in Object.wait()). Their threads
+// are tied to those of the heap dump by the address of the Thread object
+// (javacores), by thread id (the `#N` of jstack, the `tid` of the Thread
+// object) or else by name, and the addresses of their locks are those of
+// the heap dump as long as no GC ran in between.
+//
+use crate::heap::{HeapObject, Value};
+use crate::snapshot::Snapshot;
+use crate::threads::{ThreadInfo, ThreadState};
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LockRole {
+    // Entered by a frame (`- locked`, `entered lock:`), or an ownable
+    // synchronizer held by the thread.
+    Holds,
+    WaitingToLock,
+    // In Object.wait() on it.
+    WaitingOn,
+    Parked,
+}
+
+impl LockRole {
+    pub fn name(self) -> &'static str {
+        match self {
+            LockRole::Holds => "holds",
+            LockRole::WaitingToLock => "waiting to lock",
+            LockRole::WaitingOn => "waiting on",
+            LockRole::Parked => "parked on",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Lock {
+    pub role: LockRole,
+    pub address: u64,
+    pub class_name: String,
+}
+
+#[derive(Debug, Default)]
+pub struct DumpedThread {
+    pub name: String,
+    // The Java thread id (Thread.getId()).
+    pub id: Option<u64>,
+    // The address of the Thread object, in javacores.
+    pub object: Option<u64>,
+    pub daemon: Option<bool>,
+    pub state: Option<ThreadState>,
+    // What the thread is doing in that state: "sleeping", "parking", ...
+    pub detail: Option<String>,
+    // Innermost first, as written in the dump.
+    pub frames: Vec<String>,
+    pub locks: Vec<Lock>,
+}
+
+fn state_named(name: &str) -> Option<ThreadState> {
+    Some(match name {
+        "NEW" => ThreadState::New,
+        "RUNNABLE" => ThreadState::Runnable,
+        "BLOCKED" => ThreadState::Blocked,
+        "WAITING" => ThreadState::Waiting,
+        "TIMED_WAITING" => ThreadState::TimedWaiting,
+        "TERMINATED" => ThreadState::Terminated,
+        _ => return None,
+    })
+}
+
+fn parse_hex(s: &str) -> Option<u64> {
+    u64::from_str_radix(s.trim().strip_prefix("0x")?, 16).ok()
+}
+
+// The quoted name that starts `line`, and the rest of the line.
+fn quoted(line: &str) -> Option<(&str, &str)> {
+    let rest = line.strip_prefix('"')?;
+    let end = rest.rfind('"')?;
+    Some((&rest[..end], &rest[end + 1..]))
+}
+
+//
+// `<0x00000000a2310608> (a java.lang.Object)` in the lock lines of jstack,
+// as the address and the class name.
+//
+fn jstack_lock(text: &str) -> Option<(u64, String)> {
+    let text = text.trim_start();
+    let end = text.find('>')?;
+    let address = parse_hex(text.strip_prefix('<')?.get(..end - 1)?)?;
+    let class_name = text[end + 1..]
+        .trim()
+        .strip_prefix("(a ")
+        .and_then(|c| c.strip_suffix(')'))
+        .unwrap_or("?");
+    Some((address, class_name.to_string()))
+}
+
+//
+// A jstack thread is a block like
+//
+//     "worker-a" #12 daemon prio=5 os_prio=0 cpu=0.21ms ... tid=0x... nid=0x2820 ...
+//        java.lang.Thread.State: BLOCKED (on object monitor)
+//     	at Locks.lambda$main$0(Locks.java:6)
+//     	- waiting to lock <0x00000000a2310618> (a java.lang.Object)
+//     	- locked <0x00000000a2310608> (a java.lang.Object)
+//
+//        Locked ownable synchronizers:
+//     	- <0x00000000a2310a10> (a java.util.concurrent.locks.ReentrantLock$NonfairSync)
+//
+// The threads of the JVM itself (GC, compiler) have no Thread.State and
+// are left out, as are the deadlocks jstack reports after the threads.
+//
+fn parse_jstack(text: &str) -> Vec<DumpedThread> {
+    let mut threads = Vec::new();
+    let mut current: Option<DumpedThread> = None;
+    let mut synchronizers = false;
+    for line in text.lines().filter(|l| !l.is_empty()) {
+        if !line.starts_with(char::is_whitespace) {
+            threads.extend(current.take().filter(|t| t.state.is_some()));
+            let (name, rest) = match quoted(line) {
+                Some(header) if header.1.contains("prio=") => header,
+                _ => continue,
+            };
+            let words: Vec<&str> = rest.split_whitespace().collect();
+            current = Some(DumpedThread {
+                name: name.to_string(),
+                id: words.iter().find_map(|w| w.strip_prefix('#')?.parse().ok()),
+                daemon: Some(words.contains(&"daemon")),
+                ..DumpedThread::default()
+            });
+            synchronizers = false;
+            continue;
+        }
+        let thread = match current.as_mut() {
+            Some(thread) => thread,
+            None => continue,
+        };
+        let line = line.trim();
+        if let Some(state) = line.strip_prefix("java.lang.Thread.State:") {
+            let state = state.trim();
+            let (name, detail) = match state.split_once(' ') {
+                Some((name, detail)) => (name, Some(detail)),
+                None => (state, None),
+            };
+            thread.state = state_named(name);
+            thread.detail =
+                detail.map(|d| d.trim_start_matches('(').trim_end_matches(')').to_string());
+        } else if let Some(frame) = line.strip_prefix("at ") {
+            thread.frames.push(frame.to_string());
+        } else if line == "Locked ownable synchronizers:" {
+            synchronizers = true;
+        } else if let Some(lock) = line.strip_prefix("- ") {
+            let roles = [
+                ("locked ", LockRole::Holds),
+                ("waiting to lock ", LockRole::WaitingToLock),
+                ("waiting to re-lock in wait() ", LockRole::WaitingToLock),
+                ("waiting on ", LockRole::WaitingOn),
+                ("parking to wait for ", LockRole::Parked),
+            ];
+            let (role, lock) = match roles.iter().find(|r| lock.starts_with(r.0)) {
+                Some(&(prefix, role)) => (role, &lock[prefix.len()..]),
+                None if synchronizers => (LockRole::Holds, lock),
+                None => continue,
+            };
+            if let Some((address, class_name)) = jstack_lock(lock) {
+                thread.locks.push(Lock {
+                    role,
+                    address,
+                    class_name,
+                });
+            }
+        }
+    }
+    threads.extend(current.filter(|t| t.state.is_some()));
+    threads
+}
+
+// `java/lang/Object@0x00000000E0049E58` in javacores.
+fn javacore_lock(text: &str) -> Option<(u64, String)> {
+    let text = text.trim();
+    let end = text.find([',', ' ']).unwrap_or(text.len());
+    let (class_name, address) = text[..end].rsplit_once('@')?;
+    Some((parse_hex(address)?, class_name.replace('/', ".")))
+}
+
+//
+// The javacore state codes: R runnable, CW condition wait (sleeping or in
+// Object.wait()), P parked, B blocked on a monitor, S suspended, Z zombie.
+// Timed waits are not told apart from the others.
+//
+fn javacore_state(code: &str) -> (Option<ThreadState>, Option<&'static str>) {
+    match code {
+        "R" => (Some(ThreadState::Runnable), None),
+        "CW" => (Some(ThreadState::Waiting), Some("condition wait")),
+        "P" => (Some(ThreadState::Waiting), Some("parked")),
+        "B" => (Some(ThreadState::Blocked), Some("on object monitor")),
+        "S" => (Some(ThreadState::Runnable), Some("suspended")),
+        "Z" => (Some(ThreadState::Terminated), None),
+        _ => (None, None),
+    }
+}
+
+//
+// The threads of a javacore are in its THREADS section:
+//
+//     3XMTHREADINFO      "main" J9VMThread:0x..., omrthread_t:0x..., java/lang/Thread:0x00000000E0021A88, state:CW, prio=5
+//     3XMJAVALTHREAD            (java/lang/Thread getId:0x1, isDaemon:false)
+//     3XMTHREADBLOCK     Waiting on: java/lang/Object@0x00000000E0049E58 Owned by: <unowned>
+//     4XESTACKTRACE                at java/lang/Object.wait(Native Method)
+//     5XESTACKTRACE                   (entered lock: java/lang/Object@0x00000000E0049E58, entry count: 1)
+//
+// Native threads, attached to no Thread object, are left out.
+//
+fn parse_javacore(text: &str) -> Vec<DumpedThread> {
+    let mut threads = Vec::new();
+    let mut current: Option<DumpedThread> = None;
+    for line in text.lines() {
+        let (tag, rest) = match line.split_once(char::is_whitespace) {
+            Some((tag, rest)) => (tag, rest.trim()),
+            None => (line, ""),
+        };
+        if tag == "3XMTHREADINFO" {
+            threads.extend(current.take().filter(|t| t.object.is_some()));
+            let (name, rest) = match quoted(rest) {
+                Some(header) => header,
+                None => continue,
+            };
+            let mut thread = DumpedThread {
+                name: name.to_string(),
+                ..DumpedThread::default()
+            };
+            for field in rest.split(',').map(str::trim) {
+                if let Some(address) = field.strip_prefix("java/lang/Thread:") {
+                    thread.object = parse_hex(address).filter(|&a| a != 0);
+                } else if let Some(code) = field.strip_prefix("state:") {
+                    let (state, detail) = javacore_state(code);
+                    thread.state = state;
+                    thread.detail = detail.map(str::to_string);
+                }
+            }
+            current = Some(thread);
+            continue;
+        }
+        let thread = match current.as_mut() {
+            Some(thread) => thread,
+            None => continue,
+        };
+        match tag {
+            "3XMJAVALTHREAD" => {
+                for field in rest.trim_matches(['(', ')']).split(',') {
+                    let field = field.trim().trim_start_matches("java/lang/Thread ");
+                    if let Some(id) = field.strip_prefix("getId:") {
+                        thread.id = parse_hex(id);
+                    } else if let Some(daemon) = field.strip_prefix("isDaemon:") {
+                        thread.daemon = Some(daemon == "true");
+                    }
+                }
+            }
+            "3XMTHREADBLOCK" => {
+                let roles = [
+                    ("Blocked on:", LockRole::WaitingToLock),
+                    ("Waiting on:", LockRole::WaitingOn),
+                    ("Parked on:", LockRole::Parked),
+                ];
+                let lock = roles
+                    .iter()
+                    .find_map(|&(prefix, role)| Some((role, rest.strip_prefix(prefix)?)));
+                if let Some((role, lock)) = lock {
+                    if let Some((address, class_name)) = javacore_lock(lock) {
+                        thread.locks.push(Lock {
+                            role,
+                            address,
+                            class_name,
+                        });
+                    }
+                }
+            }
+            "4XESTACKTRACE" => {
+                let frame = rest.strip_prefix("at ").unwrap_or(rest);
+                thread.frames.push(frame.replace('/', "."));
+            }
+            "5XESTACKTRACE" => {
+                let lock = rest
+                    .trim_start_matches('(')
+                    .strip_prefix("entered lock:")
+                    .and_then(javacore_lock);
+                if let Some((address, class_name)) = lock {
+                    thread.locks.push(Lock {
+                        role: LockRole::Holds,
+                        address,
+                        class_name,
+                    });
+                }
+            }
+            // The end of the THREADS section.
+            _ if tag.starts_with("0SECTION") => {
+                threads.extend(current.take().filter(|t| t.object.is_some()));
+            }
+            _ => {}
+        }
+    }
+    threads.extend(current.filter(|t| t.object.is_some()));
+    threads
+}
+
+// The Java threads of a jstack output or a javacore.
+pub fn parse(text: &str) -> Result<Vec<DumpedThread>, String> {
+    let threads = if text.lines().any(|l| l.starts_with("3XMTHREADINFO")) {
+        parse_javacore(text)
+    } else {
+        parse_jstack(text)
+    };
+    if threads.is_empty() {
+        return Err("no threads found (not a jstack output or a javacore?)".to_string());
+    }
+    Ok(threads)
+}
+
+// The `tid` of a Thread object, the id given by Thread.getId().
+fn thread_id(snapshot: &Snapshot, node: u32) -> Option<u64> {
+    match &snapshot.objects[node as usize] {
+        HeapObject::Instance(i) => match snapshot.field_value(i, "tid")? {
+            Value::Long(tid) => Some(tid as u64),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+//
+// The dumped thread (by index in `dumped`) of each thread of the heap
+// dump, if any. Names are only used when they are unique on both sides,
+// since pools name their threads alike.
+//
+pub fn link(
+    snapshot: &Snapshot,
+    threads: &[ThreadInfo],
+    dumped: &[DumpedThread],
+) -> Vec<Option<usize>> {
+    let mut by_object = HashMap::new();
+    let mut by_id = HashMap::new();
+    let mut by_name: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, thread) in dumped.iter().enumerate() {
+        by_object.extend(thread.object.map(|o| (o, i)));
+        by_id.extend(thread.id.map(|id| (id, i)));
+        by_name.entry(&thread.name).or_default().push(i);
+    }
+    let mut heap_names: HashMap<&str, usize> = HashMap::new();
+    for thread in threads {
+        *heap_names.entry(&thread.name).or_insert(0) += 1;
+    }
+    let mut taken = vec![false; dumped.len()];
+    let mut links: Vec<Option<usize>> = threads
+        .iter()
+        .map(|thread| {
+            let id = snapshot.objects[thread.node as usize].object_id();
+            // Ids are reused once threads end: the names have to agree.
+            let linked = by_object
+                .get(&id)
+                .copied()
+                .or_else(|| {
+                    let i = *by_id.get(&thread_id(snapshot, thread.node)?)?;
+                    Some(i).filter(|&i| dumped[i].name == thread.name)
+                })
+                .filter(|&i| !taken[i]);
+            if let Some(i) = linked {
+                taken[i] = true;
+            }
+            linked
+        })
+        .collect();
+    for (thread, link) in threads.iter().zip(links.iter_mut()) {
+        if link.is_some() || heap_names[thread.name.as_str()] != 1 {
+            continue;
+        }
+        if let Some([i]) = by_name.get(thread.name.as_str()).map(Vec::as_slice) {
+            if !taken[*i] {
+                taken[*i] = true;
+                *link = Some(*i);
+            }
+        }
+    }
+    links
+}
+
+//
+// The object of the heap dump at a lock address, unless the address is
+// of another class there (the lock moved in a GC between the dumps).
+//
+pub fn lock_object(snapshot: &Snapshot, lock: &Lock) -> Option<u32> {
+    let node = snapshot.index_of(lock.address)?;
+    let object = &snapshot.objects[node as usize];
+    if snapshot.object_label(object) != lock.class_name {
+        return None;
+    }
+    Some(node)
+}
+
+//
+// The thread (by index) holding each lock. Threads in Object.wait() still
+// list the monitor they wait on as locked, but have released it (holds()).
+//
+pub fn holders(dumped: &[DumpedThread]) -> HashMap<u64, usize> {
+    let mut holders = HashMap::new();
+    for (i, thread) in dumped.iter().enumerate() {
+        for lock in thread.locks.iter().filter(|l| holds(thread, l)) {
+            holders.insert(lock.address, i);
+        }
+    }
+    holders
+}
+
+pub fn holds(thread: &DumpedThread, lock: &Lock) -> bool {
+    lock.role == LockRole::Holds
+        && !thread
+            .locks
+            .iter()
+            .any(|l| l.role == LockRole::WaitingOn && l.address == lock.address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::{FieldTag, GcRootKind};
+    use crate::testing::Dump;
+    use crate::threads::threads;
+
+    const JSTACK: &str = r#"2024-05-02 10:00:00
+Full thread dump OpenJDK 64-Bit Server VM (17.0.2+8 mixed mode):
+
+"main" #1 prio=5 os_prio=0 cpu=50.10ms elapsed=3.02s tid=0x00007f0c nid=0x2801 in Object.wait()  [0x00007f0c]
+   java.lang.Thread.State: WAITING (on object monitor)
+	at java.lang.Object.wait(java.base@17.0.2/Native Method)
+	- waiting on <0x00000000a2310608> (a java.lang.Object)
+	at Locks.main(Locks.java:12)
+	- locked <0x00000000a2310608> (a java.lang.Object)
+
+"worker-a" #12 daemon prio=5 os_prio=0 cpu=0.21ms elapsed=3.01s tid=0x00007f0d nid=0x2820 waiting for monitor entry  [0x00007f0d]
+   java.lang.Thread.State: BLOCKED (on object monitor)
+	at Locks.lambda$main$0(Locks.java:6)
+	- waiting to lock <0x00000000a2310618> (a java.lang.Object)
+	- locked <0x00000000a2310628> (a java.lang.Object)
+
+   Locked ownable synchronizers:
+	- <0x00000000a2310a10> (a java.util.concurrent.locks.ReentrantLock$NonfairSync)
+
+"GC Thread#0" os_prio=0 cpu=0.10ms elapsed=3.02s tid=0x00007f0e nid=0x2802 runnable
+
+JNI global refs: 6, weak refs: 0
+"#;
+
+    #[test]
+    fn jstack_threads_and_their_locks() {
+        let threads = parse(JSTACK).unwrap();
+        let summary: Vec<_> = threads
+            .iter()
+            .map(|t| (t.name.as_str(), t.id, t.daemon, t.state))
+            .collect();
+        // The GC thread has no Java state.
+        assert_eq!(
+            summary,
+            vec![
+                ("main", Some(1), Some(false), Some(ThreadState::Waiting)),
+                ("worker-a", Some(12), Some(true), Some(ThreadState::Blocked)),
+            ]
+        );
+        let main = &threads[0];
+        assert_eq!(main.detail.as_deref(), Some("on object monitor"));
+        assert_eq!(
+            main.frames,
+            vec![
+                "java.lang.Object.wait(java.base@17.0.2/Native Method)",
+                "Locks.main(Locks.java:12)"
+            ]
+        );
+        let locks: Vec<(LockRole, u64, &str)> = threads[1]
+            .locks
+            .iter()
+            .map(|l| (l.role, l.address, l.class_name.as_str()))
+            .collect();
+        assert_eq!(
+            locks,
+            vec![
+                (LockRole::WaitingToLock, 0xa2310618, "java.lang.Object"),
+                (LockRole::Holds, 0xa2310628, "java.lang.Object"),
+                (
+                    LockRole::Holds,
+                    0xa2310a10,
+                    "java.util.concurrent.locks.ReentrantLock$NonfairSync"
+                ),
+            ]
+        );
+
+        // main released the monitor it waits on.
+        let holders = holders(&threads);
+        assert_eq!(holders.len(), 2);
+        assert_eq!(holders[&0xa2310628], 1);
+        assert!(!holders.contains_key(&0xa2310608));
+    }
+
+    #[test]
+    fn javacore_threads_and_their_locks() {
+        let text = "\
+0SECTION       THREADS subcomponent dump routine
+1XMTHDINFO     Thread Details
+3XMTHREADINFO      \"main\" J9VMThread:0x0000000000021A00, omrthread_t:0x00007F0C, java/lang/Thread:0x00000000E0021A88, state:CW, prio=5
+3XMJAVALTHREAD            (java/lang/Thread getId:0x1, isDaemon:false)
+3XMTHREADBLOCK     Waiting on: java/lang/Object@0x00000000E0049E58 Owned by: <unowned>
+4XESTACKTRACE                at java/lang/Object.wait(Native Method)
+5XESTACKTRACE                   (entered lock: java/lang/Object@0x00000000E0049E58, entry count: 1)
+4XESTACKTRACE                at Locks.main(Locks.java:12)
+3XMTHREADINFO      \"JIT Compilation Thread-000\" J9VMThread:0x0000000000022B00, omrthread_t:0x00007F0D, java/lang/Thread:0x0000000000000000, state:R, prio=10
+3XMTHREADINFO      \"worker-a\" J9VMThread:0x0000000000023C00, omrthread_t:0x00007F0E, java/lang/Thread:0x00000000E0022B10, state:B, prio=5
+3XMJAVALTHREAD            (java/lang/Thread getId:0xC, isDaemon:true)
+3XMTHREADBLOCK     Blocked on: java/lang/Object@0x00000000E0049E68 Owned by: \"main\"
+4XESTACKTRACE                at Locks.lambda$main$0(Locks.java:6)
+0SECTION       CLASSES subcomponent dump routine
+";
+        let threads = parse(text).unwrap();
+        assert_eq!(threads.len(), 2);
+        let main = &threads[0];
+        assert_eq!(
+            (main.object, main.id, main.daemon, main.state),
+            (
+                Some(0xe0021a88),
+                Some(1),
+                Some(false),
+                Some(ThreadState::Waiting)
+            )
+        );
+        assert_eq!(main.detail.as_deref(), Some("condition wait"));
+        assert_eq!(
+            main.frames,
+            vec![
+                "java.lang.Object.wait(Native Method)",
+                "Locks.main(Locks.java:12)"
+            ]
+        );
+        let roles: Vec<(LockRole, u64)> = main.locks.iter().map(|l| (l.role, l.address)).collect();
+        assert_eq!(
+            roles,
+            vec![
+                (LockRole::WaitingOn, 0xe0049e58),
+                (LockRole::Holds, 0xe0049e58)
+            ]
+        );
+        let worker = &threads[1];
+        assert_eq!((worker.name.as_str(), worker.id), ("worker-a", Some(12)));
+        assert_eq!(worker.state, Some(ThreadState::Blocked));
+        assert_eq!(worker.locks[0].class_name, "java.lang.Object");
+        assert!(holders(&threads).is_empty());
+
+        assert!(parse("nothing to see\n").is_err());
+    }
+
+    #[test]
+    fn threads_are_linked_by_id_then_by_unique_name() {
+        let mut dump = Dump::new();
+        let thread_class = dump.class(
+            "java/lang/Thread",
+            dump.object,
+            &[("name", FieldTag::NormalObject), ("tid", FieldTag::Long)],
+        );
+        let thread = |dump: &mut Dump, serial: u32, name: &str, tid: i64| {
+            let name = dump.string(name);
+            let thread = dump.instance(thread_class, &[Value::Object(name), Value::Long(tid)]);
+            let trace = dump.trace(serial, &[]);
+            dump.root_at(
+                GcRootKind::ThreadObject,
+                thread,
+                Some(serial),
+                None,
+                Some(trace),
+            );
+        };
+        thread(&mut dump, 1, "main", 1);
+        thread(&mut dump, 2, "worker-a", 40);
+        // Same id as in the thread dump, another name: the id was reused.
+        thread(&mut dump, 3, "pool-1", 12);
+        thread(&mut dump, 4, "pool-1", 13);
+        let lock = dump.instance(dump.object, &[]);
+        let snapshot = dump.load();
+        let heap_threads = threads(&snapshot);
+
+        let dumped = parse(JSTACK).unwrap();
+        let names: Vec<(&str, Option<&str>)> = heap_threads
+            .iter()
+            .zip(link(&snapshot, &heap_threads, &dumped))
+            .map(|(t, l)| (t.name.as_str(), l.map(|i| dumped[i].name.as_str())))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("main", Some("main")),
+                ("worker-a", Some("worker-a")),
+                ("pool-1", None),
+                ("pool-1", None),
+            ]
+        );
+
+        let class_name = |name: &str| Lock {
+            role: LockRole::Holds,
+            address: lock,
+            class_name: name.to_string(),
+        };
+        assert_eq!(
+            lock_object(&snapshot, &class_name("java.lang.Object")),
+            snapshot.index_of(lock)
+        );
+        assert_eq!(
+            lock_object(&snapshot, &class_name("java.lang.String")),
+            None
+        );
+    }
+}
@@ -0,0 +1,415 @@
+//
+// The threads of the dumped process. Heap dumps written by the JVM itself
+// don't have START_THREAD records, so threads are found through their
+// ROOT_THREAD_OBJECT GC roots instead, which also give the serial numbers
+// used to tie other roots and stack traces to them.
+//
+use crate::heap::{GcRootKind, HeapObject, Value};
+use crate::records::StackFrameRecord;
+use crate::regex::Regex;
+use crate::signature;
+use crate::snapshot::Snapshot;
+use crate::strings::as_string;
+
+use std::collections::HashMap;
+
+#[derive(Debug)]
+pub struct ThreadInfo {
+    // The java.lang.Thread object.
+    pub node: u32,
+    pub serial: u32,
+    pub strace_num: u32,
+    pub name: String,
+}
+
+pub fn thread_name(snapshot: &Snapshot, node: u32) -> Option<String> {
+    match &snapshot.objects[node as usize] {
+        HeapObject::Instance(i) => {
+            let name = snapshot.field_value(i, "name")?.as_object()?;
+            as_string(snapshot, snapshot.object(name)?)
+        }
+        _ => None,
+    }
+}
+
+// All the threads, ordered by serial number.
+pub fn threads(snapshot: &Snapshot) -> Vec<ThreadInfo> {
+    let mut threads: Vec<ThreadInfo> = snapshot
+        .roots
+        .iter()
+        .filter(|r| r.kind == GcRootKind::ThreadObject)
+        .filter_map(|r| {
+            let node = snapshot.index_of(r.object_id)?;
+            Some(ThreadInfo {
+                node,
+                serial: r.thread_serial_num?,
+                strace_num: r.strace_num.unwrap_or(0),
+                name: thread_name(snapshot, node).unwrap_or_else(|| "<unnamed>".to_string()),
+            })
+        })
+        .collect();
+    threads.sort_by_key(|t| t.serial);
+    threads
+}
+
+//
+// Some of the threads, for applications with thousands of them: the one of
+// a serial number, or those whose names match a regex (`pool-3-`, `^grpc`).
+//
+pub enum ThreadSelector {
+    Serial(u32),
+    Name(Regex),
+}
+
+impl ThreadSelector {
+    pub fn parse(s: &str) -> Result<ThreadSelector, String> {
+        match s.parse() {
+            Ok(serial) => Ok(ThreadSelector::Serial(serial)),
+            Err(_) => Regex::new(s).map(ThreadSelector::Name),
+        }
+    }
+
+    // Whether it selects the thread of the serial number (if known) and name.
+    pub fn matches(&self, serial: Option<u32>, name: &str) -> bool {
+        match self {
+            ThreadSelector::Serial(n) => serial == Some(*n),
+            ThreadSelector::Name(regex) => regex.is_match(name),
+        }
+    }
+}
+
+// Thread names by serial number, from the thread objects and the
+// START_THREAD records of the dumps that have them.
+pub fn thread_names(snapshot: &Snapshot) -> HashMap<u32, String> {
+    let mut names: HashMap<u32, String> = snapshot
+        .threads
+        .iter()
+        .map(|t| {
+            (
+                t.thread_serial_num,
+                snapshot.string(t.thread_name_id).to_string(),
+            )
+        })
+        .collect();
+    for thread in threads(snapshot) {
+        names.insert(thread.serial, thread.name);
+    }
+    names
+}
+
+// A frame as `void Class.method(String, int) [File.java:42]`.
+pub fn describe_frame(snapshot: &Snapshot, frame: &StackFrameRecord) -> String {
+    let class_name = match snapshot.classes.get(&frame.class_serial_num) {
+        Some(class) => snapshot.class_name(class.object_id),
+        None => format!("<unknown class #{}>", frame.class_serial_num),
+    };
+    let location = match frame.line_num {
+        _ if frame.source_name_id != 0 && frame.line_num > 0 => format!(
+            "{}:{}",
+            snapshot.string(frame.source_name_id),
+            frame.line_num
+        ),
+        -2 => "Compiled".to_string(),
+        -3 => "Native".to_string(),
+        _ if frame.source_name_id != 0 => snapshot.string(frame.source_name_id).to_string(),
+        _ => "Unknown".to_string(),
+    };
+    format!(
+        "{} [{}]",
+        frame_method(snapshot, frame, &class_name),
+        location
+    )
+}
+
+//
+// The method of a frame with its signature, `void Class.method(String)`,
+// or `Class.method()` without one.
+//
+pub fn frame_method(snapshot: &Snapshot, frame: &StackFrameRecord, class_name: &str) -> String {
+    let method = format!("{}.{}", class_name, snapshot.string(frame.method_name_id));
+    signature::method(&method, snapshot.string(frame.method_sign_id))
+        .unwrap_or_else(|| format!("{}()", method))
+}
+
+#[derive(Debug)]
+pub struct Frame<'a> {
+    // None for the roots that aren't tied to a frame of the stack trace.
+    pub frame: Option<&'a StackFrameRecord>,
+    // The objects referenced by JAVA_FRAME and JNI_LOCAL roots of the frame.
+    pub locals: Vec<u32>,
+}
+
+//
+// The stack of a thread, innermost frame first, with the local variables
+// of each frame. JAVA_FRAME and JNI_LOCAL roots hold a thread serial number
+// and a depth in the thread's stack trace, -1 if unknown.
+//
+pub fn stack_with_locals<'a>(snapshot: &'a Snapshot, thread: &ThreadInfo) -> Vec<Frame<'a>> {
+    let mut frames: Vec<Frame> = match snapshot.trace_index.get(&thread.strace_num) {
+        Some(&trace) => snapshot.traces[trace]
+            .frame_ids
+            .iter()
+            .map(|id| Frame {
+                frame: snapshot.frames.get(id),
+                locals: Vec::new(),
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+    let mut unknown = Vec::new();
+    for root in &snapshot.roots {
+        if !matches!(root.kind, GcRootKind::JavaFrame | GcRootKind::JniLocal)
+            || root.thread_serial_num != Some(thread.serial)
+        {
+            continue;
+        }
+        let node = match snapshot.index_of(root.object_id) {
+            Some(node) => node,
+            None => continue,
+        };
+        match root.frame_num.map(|n| n as usize) {
+            Some(depth) if depth < frames.len() => frames[depth].locals.push(node),
+            _ => unknown.push(node),
+        }
+    }
+    if !unknown.is_empty() {
+        frames.push(Frame {
+            frame: None,
+            locals: unknown,
+        });
+    }
+    frames
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum ThreadState {
+    New,
+    Runnable,
+    Blocked,
+    Waiting,
+    TimedWaiting,
+    Terminated,
+}
+
+impl ThreadState {
+    pub fn name(self) -> &'static str {
+        match self {
+            ThreadState::New => "NEW",
+            ThreadState::Runnable => "RUNNABLE",
+            ThreadState::Blocked => "BLOCKED",
+            ThreadState::Waiting => "WAITING",
+            ThreadState::TimedWaiting => "TIMED_WAITING",
+            ThreadState::Terminated => "TERMINATED",
+        }
+    }
+
+    //
+    // The state of a `threadStatus` value, a set of JVMTI thread state bits,
+    // as computed by jdk.internal.misc.VM.toThreadState().
+    //
+    pub fn from_status(status: i32) -> ThreadState {
+        if status & 0x4 != 0 {
+            ThreadState::Runnable
+        } else if status & 0x400 != 0 {
+            ThreadState::Blocked
+        } else if status & 0x10 != 0 {
+            ThreadState::Waiting
+        } else if status & 0x20 != 0 {
+            ThreadState::TimedWaiting
+        } else if status & 0x2 != 0 {
+            ThreadState::Terminated
+        } else if status & 0x1 == 0 {
+            ThreadState::New
+        } else {
+            ThreadState::Runnable
+        }
+    }
+}
+
+//
+// A field of a Thread object. Since JDK 19 the status and daemon flag of
+// platform threads are in a FieldHolder referenced by `holder`.
+//
+fn thread_field(snapshot: &Snapshot, node: u32, name: &str) -> Option<Value> {
+    let instance = match &snapshot.objects[node as usize] {
+        HeapObject::Instance(i) => i,
+        _ => return None,
+    };
+    if let Some(value) = snapshot.field_value(instance, name) {
+        return Some(value);
+    }
+    match snapshot.object(snapshot.field_value(instance, "holder")?.as_object()?)? {
+        HeapObject::Instance(holder) => snapshot.field_value(holder, name),
+        _ => None,
+    }
+}
+
+pub fn thread_state(snapshot: &Snapshot, node: u32) -> Option<ThreadState> {
+    match thread_field(snapshot, node, "threadStatus")? {
+        Value::Int(status) => Some(ThreadState::from_status(status)),
+        _ => None,
+    }
+}
+
+pub fn is_daemon(snapshot: &Snapshot, node: u32) -> Option<bool> {
+    match thread_field(snapshot, node, "daemon")? {
+        Value::Boolean(daemon) => Some(daemon),
+        _ => None,
+    }
+}
+
+// Number of frames of the stack trace of a thread.
+pub fn stack_depth(snapshot: &Snapshot, thread: &ThreadInfo) -> usize {
+    snapshot
+        .trace_index
+        .get(&thread.strace_num)
+        .map(|&trace| snapshot.traces[trace].frame_ids.len())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::FieldTag;
+    use crate::testing::Dump;
+
+    #[test]
+    fn locals_are_tied_to_their_frames() {
+        let mut dump = Dump::new();
+        let thread_class = dump.class(
+            "java/lang/Thread",
+            dump.object,
+            &[("name", FieldTag::NormalObject)],
+        );
+        let worker = dump.class("test/Worker", dump.object, &[]);
+        let run = dump.frame(worker, "run", "()V", 42);
+        let call = dump.frame(worker, "call", "(Ljava/lang/String;I)J", -3);
+        let trace = dump.trace(7, &[call, run]);
+        let name = dump.string("worker-1");
+        let thread = dump.instance(thread_class, &[Value::Object(name)]);
+        dump.root_at(GcRootKind::ThreadObject, thread, Some(7), None, Some(trace));
+        let argument = dump.string("argument");
+        let local = dump.instance(worker, &[]);
+        let pinned = dump.instance(dump.object, &[]);
+        let other = dump.instance(dump.object, &[]);
+        dump.root_at(GcRootKind::JavaFrame, argument, Some(7), Some(0), None);
+        dump.root_at(GcRootKind::JniLocal, local, Some(7), Some(1), None);
+        dump.root_at(GcRootKind::JavaFrame, pinned, Some(7), None, None);
+        dump.root_at(GcRootKind::JavaFrame, other, Some(8), Some(0), None);
+        let snapshot = dump.load();
+
+        let threads = threads(&snapshot);
+        assert_eq!(threads.len(), 1);
+        let thread = &threads[0];
+        assert_eq!((thread.serial, thread.name.as_str()), (7, "worker-1"));
+        assert_eq!(stack_depth(&snapshot, thread), 2);
+        assert_eq!(thread_names(&snapshot)[&7], "worker-1");
+
+        let stack = stack_with_locals(&snapshot, thread);
+        let id = |node: u32| snapshot.objects[node as usize].object_id();
+        let frames: Vec<(Option<String>, Vec<u64>)> = stack
+            .iter()
+            .map(|f| {
+                let frame = f.frame.map(|frame| describe_frame(&snapshot, frame));
+                (frame, f.locals.iter().map(|&n| id(n)).collect())
+            })
+            .collect();
+        // Roots without a frame depth come last, those of other threads not at all.
+        assert_eq!(
+            frames,
+            vec![
+                (
+                    Some("long test.Worker.call(String, int) [Native]".to_string()),
+                    vec![argument]
+                ),
+                (
+                    Some("void test.Worker.run() [Unknown]".to_string()),
+                    vec![local]
+                ),
+                (None, vec![pinned]),
+            ]
+        );
+    }
+
+    #[test]
+    fn states_are_read_from_the_status_bits() {
+        let states: Vec<&str> = [0, 0x5, 0x401, 0x291, 0x2a1, 0x2, 0x1]
+            .iter()
+            .map(|&status| ThreadState::from_status(status).name())
+            .collect();
+        assert_eq!(
+            states,
+            vec![
+                "NEW",
+                "RUNNABLE",
+                "BLOCKED",
+                "WAITING",
+                "TIMED_WAITING",
+                "TERMINATED",
+                "RUNNABLE"
+            ]
+        );
+    }
+
+    #[test]
+    fn states_are_read_through_the_field_holder() {
+        let mut dump = Dump::new();
+        let old = dump.class(
+            "test/OldThread",
+            dump.object,
+            &[
+                ("threadStatus", FieldTag::Int),
+                ("daemon", FieldTag::Boolean),
+            ],
+        );
+        let holder = dump.class(
+            "java/lang/Thread$FieldHolder",
+            dump.object,
+            &[
+                ("threadStatus", FieldTag::Int),
+                ("daemon", FieldTag::Boolean),
+            ],
+        );
+        let new = dump.class(
+            "test/NewThread",
+            dump.object,
+            &[("holder", FieldTag::NormalObject)],
+        );
+        let old = dump.instance(old, &[Value::Int(0x401), Value::Boolean(true)]);
+        let fields = dump.instance(holder, &[Value::Int(0x2a1), Value::Boolean(false)]);
+        let new = dump.instance(new, &[Value::Object(fields)]);
+        let bare = dump.instance(dump.object, &[]);
+        let snapshot = dump.load();
+
+        let node = |id| snapshot.index_of(id).unwrap();
+        let state = |id| thread_state(&snapshot, node(id));
+        let daemon = |id| is_daemon(&snapshot, node(id));
+        assert_eq!(
+            (state(old), daemon(old)),
+            (Some(ThreadState::Blocked), Some(true))
+        );
+        assert_eq!(
+            (state(new), daemon(new)),
+            (Some(ThreadState::TimedWaiting), Some(false))
+        );
+        assert_eq!((state(bare), daemon(bare)), (None, None));
+    }
+
+    #[test]
+    fn threads_are_selected_by_serial_or_name() {
+        let serial = ThreadSelector::parse("12").unwrap();
+        assert!(serial.matches(Some(12), "main"));
+        assert!(!serial.matches(Some(1), "12"));
+        assert!(!serial.matches(None, "12"));
+
+        let name = ThreadSelector::parse("^pool-3-").unwrap();
+        assert!(name.matches(Some(4), "pool-3-thread-1"));
+        assert!(name.matches(None, "pool-3-thread-2"));
+        assert!(!name.matches(Some(5), "my-pool-3-thread-1"));
+        // Numbers out of range are names.
+        assert!(ThreadSelector::parse("99999999999")
+            .unwrap()
+            .matches(None, "worker-99999999999"));
+        assert!(ThreadSelector::parse("pool-(").is_err());
+    }
+}
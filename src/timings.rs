@@ -0,0 +1,167 @@
+//
+// Where the time goes on a dump: the phases of loading and analyzing it
+// (the scan of the top-level records, decoding their strings, parsing the
+// heap, building the reference graph and its reverse, the dominator tree
+// and retained sizes) time themselves when enabled, along with the bytes
+// they went through and the peak resident memory of the process after
+// them. Phases can repeat, e.g. graphs built with different exclusions,
+// and contain others: string decoding is part of the scan and the reverse
+// index part of the dominator tree.
+//
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static PHASES: Mutex<Vec<Timing>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone)]
+pub struct Timing {
+    pub phase: &'static str,
+    pub wall: Duration,
+    pub bytes: u64,
+    // VmHWM of the process when the phase ended, None where unknown.
+    pub peak_rss: Option<u64>,
+}
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// The high-water mark of the resident memory, from /proc on Linux.
+pub fn peak_rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmHWM:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+pub fn record(phase: &'static str, wall: Duration, bytes: u64) {
+    if !enabled() {
+        return;
+    }
+    PHASES.lock().unwrap().push(Timing {
+        phase,
+        wall,
+        bytes,
+        peak_rss: peak_rss(),
+    });
+}
+
+// A phase being timed, recorded when dropped.
+pub struct Phase {
+    phase: &'static str,
+    start: Option<Instant>,
+    bytes: u64,
+}
+
+pub fn start(phase: &'static str) -> Phase {
+    Phase {
+        phase,
+        start: if enabled() {
+            Some(Instant::now())
+        } else {
+            None
+        },
+        bytes: 0,
+    }
+}
+
+impl Phase {
+    pub fn add_bytes(&mut self, bytes: u64) {
+        self.bytes += bytes;
+    }
+}
+
+impl Drop for Phase {
+    fn drop(&mut self) {
+        if let Some(start) = self.start {
+            record(self.phase, start.elapsed(), self.bytes);
+        }
+    }
+}
+
+// The phases recorded so far, in the order they ended.
+pub fn timings() -> Vec<Timing> {
+    PHASES.lock().unwrap().clone()
+}
+
+fn megabytes(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+//
+// Prints the phases to stderr, to keep them apart from the output. Those
+// that ran several times, like the dominator trees of the samples of
+// retained-estimate, are added up on one line with their count.
+//
+pub fn report() {
+    let mut merged: Vec<(Timing, usize)> = Vec::new();
+    for t in timings() {
+        match merged.iter_mut().find(|(m, _)| m.phase == t.phase) {
+            Some((m, count)) => {
+                m.wall += t.wall;
+                m.bytes += t.bytes;
+                m.peak_rss = m.peak_rss.max(t.peak_rss);
+                *count += 1;
+            }
+            None => merged.push((t, 1)),
+        }
+    }
+    eprintln!(
+        "{:<22} {:>10} {:>12} {:>12} {:>12}",
+        "phase", "wall", "bytes", "throughput", "peak RSS"
+    );
+    for (t, count) in merged {
+        let phase = match count {
+            1 => t.phase.to_string(),
+            _ => format!("{} (x{})", t.phase, count),
+        };
+        let seconds = t.wall.as_secs_f64();
+        let throughput = if t.bytes > 0 && seconds > 0.0 {
+            format!("{}/s", megabytes((t.bytes as f64 / seconds) as u64))
+        } else {
+            "-".to_string()
+        };
+        eprintln!(
+            "{:<22} {:>9.3}s {:>12} {:>12} {:>12}",
+            phase,
+            seconds,
+            if t.bytes > 0 {
+                megabytes(t.bytes)
+            } else {
+                "-".to_string()
+            },
+            throughput,
+            t.peak_rss.map_or("-".to_string(), megabytes)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phases_are_recorded_once_enabled() {
+        drop(start("timings test, disabled"));
+        enable();
+        let mut phase = start("timings test");
+        phase.add_bytes(10);
+        phase.add_bytes(5);
+        drop(phase);
+
+        let recorded = timings();
+        assert!(!recorded.iter().any(|t| t.phase == "timings test, disabled"));
+        let timing = recorded.iter().find(|t| t.phase == "timings test").unwrap();
+        assert_eq!(timing.bytes, 15);
+        if cfg!(target_os = "linux") {
+            assert!(timing.peak_rss.unwrap() > 0);
+        }
+        assert_eq!(megabytes(1536 * 1024), "1.5 MB");
+    }
+}
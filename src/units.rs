@@ -0,0 +1,110 @@
+//
+// How the text reports print byte quantities, set by --units: as they are
+// (bytes, the default, for scripts reading the reports), in KiB or MiB, or
+// human, in the largest unit of which there is at least one. Other units
+// than bytes have a decimal and thousands separators: 1,843.2 MiB. JSON
+// and the other formats meant for programs always have bytes. The units
+// are those of each report, given with its other options.
+//
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Units {
+    #[default]
+    Bytes,
+    Human,
+    KiB,
+    MiB,
+}
+
+const NAMES: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+// 1234567 as 1,234,567.
+fn separated(n: u64) -> String {
+    let digits = n.to_string();
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(digit);
+    }
+    out
+}
+
+// `n` bytes in the unit of 1024^`power` bytes, with one decimal.
+fn scaled(n: u64, power: u32) -> String {
+    let tenths = (n as u128 * 10 + (1u128 << (10 * power)) / 2) >> (10 * power);
+    format!(
+        "{}.{} {}",
+        separated((tenths / 10) as u64),
+        tenths % 10,
+        NAMES[power as usize]
+    )
+}
+
+impl Units {
+    pub fn parse(s: &str) -> Option<Units> {
+        match s {
+            "bytes" => Some(Units::Bytes),
+            "human" => Some(Units::Human),
+            "kib" => Some(Units::KiB),
+            "mib" => Some(Units::MiB),
+            _ => None,
+        }
+    }
+
+    // A number of bytes in these units.
+    pub fn bytes(self, n: u64) -> String {
+        match self {
+            Units::Bytes => n.to_string(),
+            Units::KiB => scaled(n, 1),
+            Units::MiB => scaled(n, 2),
+            Units::Human => {
+                let power = (1..NAMES.len() as u32)
+                    .rev()
+                    .find(|&p| n >> (10 * p) > 0)
+                    .unwrap_or(0);
+                match power {
+                    0 => format!("{} B", n),
+                    p => scaled(n, p),
+                }
+            }
+        }
+    }
+
+    // The same for text, where bytes as they are are followed by "bytes".
+    pub fn amount(self, n: u64) -> String {
+        match self {
+            Units::Bytes => format!("{} bytes", n),
+            _ => self.bytes(n),
+        }
+    }
+
+    // A difference in bytes, with its sign.
+    pub fn signed_bytes(self, n: i64) -> String {
+        match n {
+            n if n < 0 => format!("-{}", self.bytes(n.unsigned_abs())),
+            n => format!("+{}", self.bytes(n as u64)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_report_has_its_units() {
+        let n = 1_932_735_283;
+        assert_eq!(Units::Bytes.bytes(n), "1932735283");
+        assert_eq!(Units::Bytes.amount(n), "1932735283 bytes");
+        assert_eq!(Units::KiB.bytes(n), "1,887,436.8 KiB");
+        assert_eq!(Units::MiB.bytes(n), "1,843.2 MiB");
+        assert_eq!(Units::Human.bytes(n), "1.8 GiB");
+        assert_eq!(Units::Human.bytes(1023), "1023 B");
+        assert_eq!(Units::Human.amount(0), "0 B");
+        assert_eq!(Units::MiB.signed_bytes(-(1 << 20)), "-1.0 MiB");
+        assert_eq!(Units::Bytes.signed_bytes(12), "+12");
+        assert_eq!(Units::parse("kib"), Some(Units::KiB));
+        assert_eq!(Units::parse("KiB"), None);
+    }
+}
@@ -0,0 +1,485 @@
+//
+// Reads through io_uring on Linux (the `io-uring` feature): the read-ahead
+// thread keeps several reads of a range of the file in flight at once and
+// hands their buffers on in file order, which is what keeps NVMe drives
+// busy on dumps of a hundred gigabytes and more. Only the heap dump segments
+// are read this way: the scan of the top-level records seeks over them, so
+// it reads little of the file, a record header at a time, and is bound by
+// the latency of its reads rather than by their throughput. Setting up the
+// ring fails on kernels without io_uring (ENOSYS) or where it is disabled
+// or filtered out (EPERM), and the caller then falls back to plain reads.
+//
+// The ring is set up by hand, without liburing: the layouts below are
+// those of <linux/io_uring.h>, and the system call numbers and mmap(2)
+// flags those of x86_64 and aarch64, the only targets this is built for.
+//
+use std::fs::File;
+use std::io;
+use std::os::raw::{c_int, c_long, c_void};
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+const SYS_IO_URING_SETUP: c_long = 425;
+const SYS_IO_URING_ENTER: c_long = 426;
+const IORING_OFF_SQ_RING: i64 = 0;
+const IORING_OFF_CQ_RING: i64 = 0x800_0000;
+const IORING_OFF_SQES: i64 = 0x1000_0000;
+const IORING_ENTER_GETEVENTS: u32 = 1;
+const IORING_OP_READ: u8 = 22;
+const PROT_READ_WRITE: c_int = 3;
+const MAP_SHARED_POPULATE: c_int = 0x1 | 0x8000;
+const EPERM: i32 = 1;
+const EINTR: i32 = 4;
+const EAGAIN: i32 = 11;
+const ENOSYS: i32 = 38;
+const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+// Reads kept in flight.
+pub const QUEUE_DEPTH: usize = 4;
+
+extern "C" {
+    fn syscall(number: c_long, ...) -> c_long;
+    fn mmap(
+        addr: *mut c_void,
+        len: usize,
+        prot: c_int,
+        flags: c_int,
+        fd: c_int,
+        off: i64,
+    ) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    fn close(fd: c_int) -> c_int;
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct SqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqRingOffsets,
+    cq_off: CqRingOffsets,
+}
+
+#[repr(C)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    rw_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    addr3: u64,
+    pad: u64,
+}
+
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+// A region shared with the kernel, unmapped when dropped.
+struct Region {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Region {
+    fn map(fd: c_int, len: usize, offset: i64) -> io::Result<Region> {
+        // SAFETY: a new shared mapping of the ring, checked below.
+        let ptr = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                len,
+                PROT_READ_WRITE,
+                MAP_SHARED_POPULATE,
+                fd,
+                offset,
+            )
+        };
+        if ptr == MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Region {
+            ptr: ptr as *mut u8,
+            len,
+        })
+    }
+
+    // The u32 the kernel shares at `offset`.
+    fn atomic(&self, offset: u32) -> &AtomicU32 {
+        // SAFETY: the offsets come from the kernel and are aligned u32s
+        // inside the region.
+        unsafe { &*(self.ptr.add(offset as usize) as *const AtomicU32) }
+    }
+}
+
+impl Drop for Region {
+    fn drop(&mut self) {
+        // SAFETY: mapped by map() and not used after this.
+        unsafe { munmap(self.ptr as *mut c_void, self.len) };
+    }
+}
+
+pub struct Ring {
+    fd: c_int,
+    sq: Region,
+    cq: Region,
+    sqes: Region,
+    sq_off: SqRingOffsets,
+    cq_off: CqRingOffsets,
+    sq_entries: u32,
+}
+
+impl Ring {
+    pub fn new(entries: u32) -> io::Result<Ring> {
+        let mut params = Params::default();
+        // SAFETY: io_uring_setup(2) only writes to `params`.
+        let fd = unsafe { syscall(SYS_IO_URING_SETUP, entries, &mut params as *mut Params) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = fd as c_int;
+        let regions = (|| {
+            let sq = Region::map(
+                fd,
+                params.sq_off.array as usize + 4 * params.sq_entries as usize,
+                IORING_OFF_SQ_RING,
+            )?;
+            let cq = Region::map(
+                fd,
+                params.cq_off.cqes as usize
+                    + std::mem::size_of::<Cqe>() * params.cq_entries as usize,
+                IORING_OFF_CQ_RING,
+            )?;
+            let sqes = Region::map(
+                fd,
+                std::mem::size_of::<Sqe>() * params.sq_entries as usize,
+                IORING_OFF_SQES,
+            )?;
+            Ok((sq, cq, sqes))
+        })();
+        match regions {
+            Ok((sq, cq, sqes)) => Ok(Ring {
+                fd,
+                sq,
+                cq,
+                sqes,
+                sq_entries: params.sq_entries,
+                sq_off: params.sq_off,
+                cq_off: params.cq_off,
+            }),
+            Err(e) => {
+                // SAFETY: the ring isn't used after this.
+                unsafe { close(fd) };
+                Err(e)
+            }
+        }
+    }
+
+    //
+    // Queues a read of `len` bytes at `offset` of the file into `buf`.
+    // Returns false if the submission queue is full.
+    //
+    // SAFETY: `buf` must stay valid for `len` bytes until the completion of
+    // the read has been popped.
+    //
+    unsafe fn push_read(
+        &mut self,
+        file: &File,
+        buf: *mut u8,
+        len: u32,
+        offset: u64,
+        tag: u64,
+    ) -> bool {
+        let head = self.sq.atomic(self.sq_off.head).load(Ordering::Acquire);
+        let tail = self.sq.atomic(self.sq_off.tail).load(Ordering::Relaxed);
+        if tail.wrapping_sub(head) == self.sq_entries {
+            return false;
+        }
+        // SAFETY: the mask is a u32 the kernel set up inside the ring, and
+        // masked indexes are in the `sq_entries` entries of both arrays.
+        let index = tail & *(self.sq.ptr.add(self.sq_off.ring_mask as usize) as *const u32);
+        let sqe = (self.sqes.ptr as *mut Sqe).add(index as usize);
+        // SAFETY: the entry between head and tail isn't the kernel's until
+        // the tail moves past it.
+        sqe.write(Sqe {
+            opcode: IORING_OP_READ,
+            flags: 0,
+            ioprio: 0,
+            fd: file.as_raw_fd(),
+            off: offset,
+            addr: buf as u64,
+            len,
+            rw_flags: 0,
+            user_data: tag,
+            buf_index: 0,
+            personality: 0,
+            splice_fd_in: 0,
+            addr3: 0,
+            pad: 0,
+        });
+        // SAFETY: as for the entry, in the array of indexes.
+        let array = self.sq.ptr.add(self.sq_off.array as usize) as *mut u32;
+        *array.add(index as usize) = index;
+        self.sq
+            .atomic(self.sq_off.tail)
+            .store(tail.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    // Submits `submit` queued reads and waits for at least `wait` completions.
+    fn enter(&mut self, submit: u32, wait: u32) -> io::Result<()> {
+        loop {
+            // SAFETY: io_uring_enter(2) on our ring, without a signal mask.
+            let ret = unsafe {
+                syscall(
+                    SYS_IO_URING_ENTER,
+                    self.fd,
+                    submit,
+                    wait,
+                    if wait > 0 { IORING_ENTER_GETEVENTS } else { 0 },
+                    std::ptr::null::<c_void>(),
+                    0usize,
+                )
+            };
+            if ret >= 0 {
+                return Ok(());
+            }
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() != Some(EINTR) {
+                return Err(e);
+            }
+        }
+    }
+
+    // The tag and result of the next completed read.
+    fn pop(&mut self) -> Option<(u64, i32)> {
+        let head = self.cq.atomic(self.cq_off.head).load(Ordering::Relaxed);
+        let tail = self.cq.atomic(self.cq_off.tail).load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+        // SAFETY: entries between head and tail are written by the kernel.
+        let cqe = unsafe {
+            let mask = *(self.cq.ptr.add(self.cq_off.ring_mask as usize) as *const u32);
+            let cqes = self.cq.ptr.add(self.cq_off.cqes as usize) as *const Cqe;
+            cqes.add((head & mask) as usize).read()
+        };
+        self.cq
+            .atomic(self.cq_off.head)
+            .store(head.wrapping_add(1), Ordering::Release);
+        Some((cqe.user_data, cqe.res))
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        // SAFETY: the regions are unmapped right after, nothing uses the fd.
+        unsafe { close(self.fd) };
+    }
+}
+
+//
+// Decides on an error setting up a ring: the read-ahead thread reads
+// without one either way, but only a kernel without io_uring or one that
+// doesn't let this process use it is expected, anything else (running out
+// of locked memory, of files) is worth a warning, once.
+//
+pub fn fall_back(e: &io::Error) {
+    static WARNED: AtomicBool = AtomicBool::new(false);
+    let expected = matches!(e.raw_os_error(), Some(ENOSYS) | Some(EPERM));
+    if !expected && !WARNED.swap(true, Ordering::Relaxed) {
+        eprintln!("warning: io_uring setup failed ({}), using plain reads", e);
+    }
+}
+
+// A read of the range being filled.
+struct Slot {
+    buf: Vec<u8>,
+    offset: u64,
+    filled: usize,
+}
+
+//
+// Reads `len` bytes of `file` from `offset` in buffers of `chunk` bytes
+// (taken from `buffer`), QUEUE_DEPTH at a time, passing them to `send` in
+// file order until it returns false. Short reads are resubmitted for what
+// is left of their buffer.
+//
+pub fn read_range(
+    ring: &mut Ring,
+    file: &File,
+    offset: u64,
+    len: u64,
+    chunk: usize,
+    mut buffer: impl FnMut() -> Vec<u8>,
+    mut send: impl FnMut(io::Result<Vec<u8>>) -> bool,
+) {
+    let mut slots: Vec<Option<Slot>> = (0..QUEUE_DEPTH).map(|_| None).collect();
+    let mut in_flight = 0;
+    // The next buffer to hand on and the next one to read, by sequence.
+    let (mut next_send, mut next_read) = (0usize, 0usize);
+    let mut next_offset = offset;
+    let end = offset + len;
+    let mut failed = false;
+
+    loop {
+        let mut submit = 0;
+        while !failed && next_offset < end && next_read - next_send < QUEUE_DEPTH {
+            let slot = next_read % QUEUE_DEPTH;
+            let mut buf = buffer();
+            buf.resize(chunk.min((end - next_offset) as usize), 0);
+            let s = slots[slot].insert(Slot {
+                buf,
+                offset: next_offset,
+                filled: 0,
+            });
+            // SAFETY: the buffer stays in its slot until its read completes.
+            let queued = unsafe {
+                ring.push_read(
+                    file,
+                    s.buf.as_mut_ptr(),
+                    s.buf.len() as u32,
+                    s.offset,
+                    slot as u64,
+                )
+            };
+            assert!(queued, "io_uring submission queue full");
+            next_offset += s.buf.len() as u64;
+            next_read += 1;
+            in_flight += 1;
+            submit += 1;
+        }
+        if in_flight == 0 {
+            return;
+        }
+        if let Err(e) = ring.enter(submit, 1) {
+            // Nothing was submitted, so no read is left to wait for.
+            in_flight -= submit;
+            failed = true;
+            send(Err(e));
+            continue;
+        }
+        while let Some((tag, res)) = ring.pop() {
+            in_flight -= 1;
+            let s = slots[tag as usize].as_mut().unwrap();
+            let error = match res {
+                0 => Some(io::ErrorKind::UnexpectedEof.into()),
+                r if r == -EINTR || r == -EAGAIN => None,
+                r if r < 0 => Some(io::Error::from_raw_os_error(-r)),
+                r => {
+                    s.filled += r as usize;
+                    None
+                }
+            };
+            if let Some(e) = error {
+                if !failed {
+                    failed = true;
+                    send(Err(e));
+                }
+                continue;
+            }
+            if s.filled < s.buf.len() && !failed {
+                // SAFETY: as above, for the rest of the buffer.
+                let queued = unsafe {
+                    ring.push_read(
+                        file,
+                        s.buf.as_mut_ptr().add(s.filled),
+                        (s.buf.len() - s.filled) as u32,
+                        s.offset + s.filled as u64,
+                        tag,
+                    )
+                };
+                assert!(queued, "io_uring submission queue full");
+                in_flight += 1;
+                if let Err(e) = ring.enter(1, 0) {
+                    in_flight -= 1;
+                    failed = true;
+                    send(Err(e));
+                }
+            }
+        }
+        // Buffers can only be handed on once no read targets them.
+        while !failed && next_send < next_read {
+            let slot = next_send % QUEUE_DEPTH;
+            match &slots[slot] {
+                Some(s) if s.filled == s.buf.len() => {}
+                _ => break,
+            }
+            let s = slots[slot].take().unwrap();
+            next_send += 1;
+            if !send(Ok(s.buf)) {
+                failed = true;
+            }
+        }
+        if failed && in_flight == 0 {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempFile;
+
+    #[test]
+    fn reads_come_back_in_file_order() {
+        let mut ring = match Ring::new(QUEUE_DEPTH as u32) {
+            Ok(ring) => ring,
+            // Nothing to test without io_uring.
+            Err(e) => return fall_back(&e),
+        };
+        let bytes: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let file = TempFile::new(&bytes);
+        let file = File::open(file.path()).unwrap();
+        let mut read = Vec::new();
+        read_range(&mut ring, &file, 10, 90_000, 4096, Vec::new, |buf| {
+            read.extend(buf.unwrap());
+            true
+        });
+        assert_eq!(read, &bytes[10..90_010]);
+    }
+}
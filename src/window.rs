@@ -0,0 +1,214 @@
+//
+// The window of time of the records the commands look at, for dumps that
+// an agent wrote over a profiling session (the HPROF agent of JDK 8 and
+// before interleaves heap dumps, allocation sites and threads with the
+// rest). The time of a record is its offset in microseconds from the time
+// of the header, which is when the dump started. --since and --until bound
+// it, either absolutely or from the start of the dump; the records that
+// others refer to (strings, classes, stack frames and traces) are read
+// whatever their time. The window is given to the loaders of each dump
+// (see Snapshot::load_window()), so that dumps loaded side by side can
+// have different ones.
+//
+use crate::records::{Header, Record, RecordTag};
+
+use std::convert::TryFrom;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+    // Microseconds since the epoch.
+    Absolute(u64),
+    // Microseconds from the start of the dump.
+    Relative(u64),
+}
+
+// The bounds of the window, both inclusive; without any, all the records.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Window {
+    pub since: Option<Bound>,
+    pub until: Option<Bound>,
+}
+
+fn micros(bound: Bound, header: &Header) -> u64 {
+    match bound {
+        Bound::Absolute(us) => us,
+        Bound::Relative(us) => header.timestamp_ms() * 1000 + us,
+    }
+}
+
+impl Window {
+    pub fn enabled(&self) -> bool {
+        *self != Window::default()
+    }
+
+    //
+    // Whether the record is left out, being of a kind that is filtered by
+    // time and outside the window.
+    //
+    pub fn excludes(&self, header: &Header, record: &Record) -> bool {
+        match record.tag {
+            Some(RecordTag::Utf8String)
+            | Some(RecordTag::LoadClass)
+            | Some(RecordTag::UnloadClass)
+            | Some(RecordTag::StackFrame)
+            | Some(RecordTag::StackTrace)
+            | Some(RecordTag::HeapDumpEnd) => return false,
+            _ => {}
+        }
+        let time = header.timestamp_ms() * 1000 + record.time as u64;
+        self.since.is_some_and(|b| time < micros(b, header))
+            || self.until.is_some_and(|b| time > micros(b, header))
+    }
+}
+
+// Microseconds of a duration like 90s, 5m, 1h30m or 250ms.
+fn duration(s: &str) -> Option<u64> {
+    if s.is_empty() {
+        return None;
+    }
+    let mut total = 0u64;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let n: u64 = rest[..digits].parse().ok()?;
+        rest = &rest[digits..];
+        let unit = rest.bytes().take_while(u8::is_ascii_alphabetic).count();
+        let scale = match &rest[..unit] {
+            "us" => 1,
+            "ms" => 1000,
+            "s" => 1_000_000,
+            "m" => 60_000_000,
+            "h" => 3_600_000_000,
+            _ => return None,
+        };
+        rest = &rest[unit..];
+        total = total.checked_add(n.checked_mul(scale)?)?;
+    }
+    Some(total)
+}
+
+// Days from 1970-01-01 to the date (of the proleptic Gregorian calendar).
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// Microseconds since the epoch of YYYY-MM-DD[THH:MM[:SS[.fff]]][Z], in UTC.
+fn date_time(s: &str) -> Option<u64> {
+    let s = s.strip_suffix('Z').unwrap_or(s);
+    let (date, time) = match s.split_once(['T', ' ']) {
+        Some((date, time)) => (date, time),
+        None => (s, "00:00"),
+    };
+    let number = |s: &str| -> Option<i64> {
+        if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        s.parse().ok()
+    };
+    let date: Vec<&str> = date.split('-').collect();
+    let (year, month, day) = match date[..] {
+        [y, m, d] if y.len() == 4 => (number(y)?, number(m)?, number(d)?),
+        _ => return None,
+    };
+    let (time, fraction) = match time.split_once('.') {
+        Some((time, fraction)) => (time, fraction),
+        None => (time, ""),
+    };
+    let time: Vec<&str> = time.split(':').collect();
+    let (hour, minute, second) = match time[..] {
+        [h, m] => (number(h)?, number(m)?, 0),
+        [h, m, s] => (number(h)?, number(m)?, number(s)?),
+        _ => return None,
+    };
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+        || fraction.len() > 6
+    {
+        return None;
+    }
+    let micros = match fraction {
+        "" => 0,
+        f => number(f)? * 10i64.pow(6 - f.len() as u32),
+    };
+    let seconds = days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second;
+    u64::try_from(seconds * 1_000_000 + micros).ok()
+}
+
+//
+// A bound of the window: +<duration> from the start of the dump (+90s,
+// +5m, +1h30m, +250ms), or a time in milliseconds since the epoch or as
+// an ISO 8601 date and time in UTC (2024-05-01T12:30:00).
+//
+pub fn parse_bound(s: &str) -> Result<Bound, String> {
+    let parsed = match s.strip_prefix('+') {
+        Some(d) => duration(d).map(Bound::Relative),
+        None if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) => s
+            .parse::<u64>()
+            .ok()
+            .and_then(|ms| ms.checked_mul(1000))
+            .map(Bound::Absolute),
+        None => date_time(s).map(Bound::Absolute),
+    };
+    parsed.ok_or_else(|| {
+        format!(
+            "bad time: {} (+<duration> like +90s or +1h30m, epoch milliseconds or 2024-05-01T12:30:00)",
+            s
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(tag: RecordTag, time: u32) -> Record {
+        Record {
+            tag: Some(tag),
+            raw_tag: tag as u8,
+            time,
+            bytes: 0,
+        }
+    }
+
+    #[test]
+    fn windows_are_independent() {
+        let header = Header {
+            format: "JAVA PROFILE 1.0.2".to_string(),
+            identifier_size: 8,
+            high_word_ms: 0,
+            low_word_ms: 1_700_000_000,
+        };
+        let early = Window {
+            since: None,
+            until: Some(parse_bound("+1s").unwrap()),
+        };
+        let late = Window {
+            since: Some(parse_bound("+1s").unwrap()),
+            until: None,
+        };
+        let dump = record(RecordTag::HeapDumpSegment, 1_500_000);
+        assert!(early.excludes(&header, &dump));
+        assert!(!late.excludes(&header, &dump));
+        assert!(!Window::default().excludes(&header, &dump));
+        assert!(!Window::default().enabled() && early.enabled());
+
+        // Records others refer to are kept whatever their time.
+        let string = record(RecordTag::Utf8String, 1_500_000);
+        assert!(!early.excludes(&header, &string));
+
+        let absolute = Window {
+            since: Some(parse_bound("1700000500").unwrap()),
+            until: Some(parse_bound("+2s").unwrap()),
+        };
+        assert!(!absolute.excludes(&header, &dump));
+        assert!(absolute.excludes(&header, &record(RecordTag::HeapDumpSegment, 0)));
+    }
+}
@@ -0,0 +1,727 @@
+//
+// Writing HPROF 1.0.2: the header, the top-level records, and the heap
+// dump sub-records (GC roots, class, instance and array dumps), which are
+// gathered into HEAP_DUMP_SEGMENT records of about `segment_bytes` each.
+// Dumps are written record by record, to make synthetic ones or to
+// transform others as they are read, or from a whole Snapshot.
+//
+use crate::heap::{
+    ClassDump, DataDumpSubRecordTag, FieldTag, GcRoot, GcRootKind, HeapObject, InstanceDump,
+    ObjectArrayDump, PrimitiveArrayDump, Value,
+};
+use crate::idhash::{IdMap, IdSet};
+use crate::records::{
+    LoadClassRecord, RecordTag, StackFrameRecord, StackTraceRecord, StartThreadRecord,
+};
+use crate::snapshot::Snapshot;
+
+use std::convert::TryFrom;
+use std::io::{self, Write};
+
+pub const FORMAT: &str = "JAVA PROFILE 1.0.2";
+
+//
+// HotSpot has segments of up to 1GB, but the segments are what the loading
+// is parallelized over (see Snapshot::load_with_threads()).
+//
+const SEGMENT_BYTES: usize = 8 << 20;
+
+pub fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_be_bytes());
+}
+
+pub fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_be_bytes());
+}
+
+pub fn put_u64(buf: &mut Vec<u8>, value: u64) {
+    buf.extend_from_slice(&value.to_be_bytes());
+}
+
+pub fn put_id(buf: &mut Vec<u8>, id: u64, id_size: u32) {
+    match id_size {
+        4 => {
+            let id = u32::try_from(id)
+                .unwrap_or_else(|_| panic!("identifier too large for 4 bytes: {:#x}", id));
+            put_u32(buf, id);
+        }
+        8 => put_u64(buf, id),
+        _ => panic!("unsupported identifier size: {}", id_size),
+    }
+}
+
+pub fn put_value(buf: &mut Vec<u8>, value: Value, id_size: u32) {
+    match value {
+        Value::Object(id) => put_id(buf, id, id_size),
+        Value::Boolean(v) => buf.push(v as u8),
+        Value::Char(v) => put_u16(buf, v),
+        Value::Float(v) => put_u32(buf, v.to_bits()),
+        Value::Double(v) => put_u64(buf, v.to_bits()),
+        Value::Byte(v) => buf.push(v as u8),
+        Value::Short(v) => put_u16(buf, v as u16),
+        Value::Int(v) => put_u32(buf, v as u32),
+        Value::Long(v) => put_u64(buf, v as u64),
+    }
+}
+
+// The field type of a value, for the constant pool and static fields.
+pub fn value_tag(value: Value) -> FieldTag {
+    match value {
+        Value::Object(_) => FieldTag::NormalObject,
+        Value::Boolean(_) => FieldTag::Boolean,
+        Value::Char(_) => FieldTag::Char,
+        Value::Float(_) => FieldTag::Float,
+        Value::Double(_) => FieldTag::Double,
+        Value::Byte(_) => FieldTag::Byte,
+        Value::Short(_) => FieldTag::Short,
+        Value::Int(_) => FieldTag::Int,
+        Value::Long(_) => FieldTag::Long,
+    }
+}
+
+//
+// New identifiers for the objects, symbols (UTF8 strings) and stack frames
+// of a dump being written; those not in the maps are kept.
+//
+#[derive(Debug, Default)]
+pub struct Renumbering {
+    pub objects: IdMap<u64, u64>,
+    pub symbols: IdMap<u64, u64>,
+    pub frames: IdMap<u64, u64>,
+    // Symbols left out of a snapshot written whole.
+    pub dropped_symbols: IdSet<u64>,
+}
+
+impl Renumbering {
+    pub fn object(&self, id: u64) -> u64 {
+        self.objects.get(&id).copied().unwrap_or(id)
+    }
+
+    pub fn symbol(&self, id: u64) -> u64 {
+        self.symbols.get(&id).copied().unwrap_or(id)
+    }
+
+    pub fn frame(&self, id: u64) -> u64 {
+        self.frames.get(&id).copied().unwrap_or(id)
+    }
+}
+
+// Identifiers as written: renumbered, in `size` bytes.
+#[derive(Clone, Copy)]
+struct Ids<'a> {
+    size: u32,
+    renumbering: &'a Renumbering,
+}
+
+impl Ids<'_> {
+    fn object(self, buf: &mut Vec<u8>, id: u64) {
+        put_id(buf, self.renumbering.object(id), self.size);
+    }
+
+    fn symbol(self, buf: &mut Vec<u8>, id: u64) {
+        put_id(buf, self.renumbering.symbol(id), self.size);
+    }
+
+    fn frame(self, buf: &mut Vec<u8>, id: u64) {
+        put_id(buf, self.renumbering.frame(id), self.size);
+    }
+
+    fn value(self, buf: &mut Vec<u8>, value: Value) {
+        match value {
+            Value::Object(id) => self.object(buf, id),
+            _ => put_value(buf, value, self.size),
+        }
+    }
+}
+
+pub struct Writer<W: Write> {
+    out: W,
+    id_size: u32,
+    renumbering: Renumbering,
+    // The sub-records of the heap dump segment being written.
+    segment: Vec<u8>,
+    segment_bytes: usize,
+}
+
+impl<W: Write> Writer<W> {
+    // Starts a dump with `id_size`-byte identifiers, taken at `timestamp_ms`.
+    pub fn new(mut out: W, id_size: u32, timestamp_ms: u64) -> io::Result<Writer<W>> {
+        assert!(
+            id_size == 4 || id_size == 8,
+            "unsupported identifier size: {}",
+            id_size
+        );
+        let mut header = FORMAT.as_bytes().to_vec();
+        header.push(0);
+        put_u32(&mut header, id_size);
+        put_u64(&mut header, timestamp_ms);
+        out.write_all(&header)?;
+        Ok(Writer {
+            out,
+            id_size,
+            renumbering: Renumbering::default(),
+            segment: Vec::new(),
+            segment_bytes: SEGMENT_BYTES,
+        })
+    }
+
+    // Cuts the heap dump segments at about `bytes` instead.
+    pub fn set_segment_bytes(&mut self, bytes: usize) {
+        self.segment_bytes = bytes;
+    }
+
+    pub fn id_size(&self) -> u32 {
+        self.id_size
+    }
+
+    // Renumbers the identifiers of everything written from now on.
+    pub fn renumber(&mut self, renumbering: Renumbering) {
+        self.renumbering = renumbering;
+    }
+
+    fn ids(&self) -> Ids<'_> {
+        Ids {
+            size: self.id_size,
+            renumbering: &self.renumbering,
+        }
+    }
+
+    fn write_record(&mut self, tag: u8, body: &[u8]) -> io::Result<()> {
+        let bytes = u32::try_from(body.len()).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("record of {} bytes, more than 4GB", body.len()),
+            )
+        })?;
+        let mut header = vec![tag];
+        put_u32(&mut header, 0);
+        put_u32(&mut header, bytes);
+        self.out.write_all(&header)?;
+        self.out.write_all(body)
+    }
+
+    fn end_segment(&mut self) -> io::Result<()> {
+        if self.segment.is_empty() {
+            return Ok(());
+        }
+        let segment = std::mem::take(&mut self.segment);
+        self.write_record(RecordTag::HeapDumpSegment as u8, &segment)
+    }
+
+    //
+    // A top-level record of any (raw) tag with its body as is. It ends the
+    // segment being written, if any: records don't nest.
+    //
+    pub fn record(&mut self, tag: u8, body: &[u8]) -> io::Result<()> {
+        self.end_segment()?;
+        self.write_record(tag, body)
+    }
+
+    pub fn utf8_string(&mut self, id: u64, value: &str) -> io::Result<()> {
+        let mut body = Vec::with_capacity(8 + value.len());
+        self.ids().symbol(&mut body, id);
+        body.extend_from_slice(value.as_bytes());
+        self.record(RecordTag::Utf8String as u8, &body)
+    }
+
+    pub fn load_class(&mut self, class: &LoadClassRecord) -> io::Result<()> {
+        let ids = self.ids();
+        let mut body = Vec::new();
+        put_u32(&mut body, class.serial_num);
+        ids.object(&mut body, class.object_id);
+        put_u32(&mut body, class.strace_num);
+        ids.symbol(&mut body, class.strname_id);
+        self.record(RecordTag::LoadClass as u8, &body)
+    }
+
+    pub fn stack_frame(&mut self, frame: &StackFrameRecord) -> io::Result<()> {
+        let ids = self.ids();
+        let mut body = Vec::new();
+        ids.frame(&mut body, frame.frame_id);
+        for id in [
+            frame.method_name_id,
+            frame.method_sign_id,
+            frame.source_name_id,
+        ] {
+            ids.symbol(&mut body, id);
+        }
+        put_u32(&mut body, frame.class_serial_num);
+        put_u32(&mut body, frame.line_num as u32);
+        self.record(RecordTag::StackFrame as u8, &body)
+    }
+
+    pub fn stack_trace(&mut self, trace: &StackTraceRecord) -> io::Result<()> {
+        let ids = self.ids();
+        let mut body = Vec::new();
+        put_u32(&mut body, trace.serial_num);
+        put_u32(&mut body, trace.thread_serial_num);
+        put_u32(&mut body, trace.frame_ids.len() as u32);
+        for &id in &trace.frame_ids {
+            ids.frame(&mut body, id);
+        }
+        self.record(RecordTag::StackTrace as u8, &body)
+    }
+
+    pub fn start_thread(&mut self, thread: &StartThreadRecord) -> io::Result<()> {
+        let ids = self.ids();
+        let mut body = Vec::new();
+        put_u32(&mut body, thread.thread_serial_num);
+        ids.object(&mut body, thread.thread_object_id);
+        put_u32(&mut body, thread.strace_num);
+        for id in [
+            thread.thread_name_id,
+            thread.group_name_id,
+            thread.parent_group_name_id,
+        ] {
+            ids.symbol(&mut body, id);
+        }
+        self.record(RecordTag::StartThread as u8, &body)
+    }
+
+    //
+    // A sub-record of the heap dump, built by `f` after its tag. The
+    // segment is cut before it once full; sub-records are never split.
+    //
+    fn sub_record<F: FnOnce(&mut Vec<u8>, Ids)>(
+        &mut self,
+        tag: DataDumpSubRecordTag,
+        f: F,
+    ) -> io::Result<()> {
+        if self.segment.len() >= self.segment_bytes {
+            self.end_segment()?;
+        }
+        self.segment.push(tag as u8);
+        let ids = Ids {
+            size: self.id_size,
+            renumbering: &self.renumbering,
+        };
+        f(&mut self.segment, ids);
+        Ok(())
+    }
+
+    pub fn root(&mut self, root: &GcRoot) -> io::Result<()> {
+        let tag = root.kind.tag();
+        let thread = root.thread_serial_num.unwrap_or(0);
+        self.sub_record(tag, |buf, ids| {
+            ids.object(buf, root.object_id);
+            match root.kind {
+                // The JNI global reference itself isn't kept.
+                GcRootKind::JniGlobal => put_id(buf, 0, ids.size),
+                GcRootKind::JniLocal | GcRootKind::JavaFrame => {
+                    put_u32(buf, thread);
+                    put_u32(buf, root.frame_num.unwrap_or(u32::MAX));
+                }
+                GcRootKind::NativeStack | GcRootKind::ThreadBlock => put_u32(buf, thread),
+                GcRootKind::ThreadObject => {
+                    put_u32(buf, thread);
+                    put_u32(buf, root.strace_num.unwrap_or(0));
+                }
+                _ => {}
+            }
+        })
+    }
+
+    pub fn class_dump(&mut self, class: &ClassDump) -> io::Result<()> {
+        self.sub_record(DataDumpSubRecordTag::ClassDump, |buf, ids| {
+            ids.object(buf, class.class_id);
+            put_u32(buf, class.strace_num);
+            for id in [
+                class.super_class_id,
+                class.class_loader_id,
+                class.signers_id,
+                class.protection_domain_id,
+                0,
+                0,
+            ] {
+                ids.object(buf, id);
+            }
+            put_u32(buf, class.instance_size);
+            let pool = class.constant_pool();
+            put_u16(buf, pool.len() as u16);
+            for &(index, value) in pool {
+                put_u16(buf, index);
+                buf.push(value_tag(value) as u8);
+                ids.value(buf, value);
+            }
+            let statics = class.static_fields();
+            put_u16(buf, statics.len() as u16);
+            for field in statics {
+                ids.symbol(buf, field.name_id);
+                buf.push(field.tag as u8);
+                ids.value(buf, field.value);
+            }
+            put_u16(buf, class.instance_fields.len() as u16);
+            for field in &class.instance_fields {
+                ids.symbol(buf, field.name_id);
+                buf.push(field.tag as u8);
+            }
+        })
+    }
+
+    //
+    // The field values of an instance are written as they are, in the
+    // layout given by the dumps of its class and superclasses, so neither
+    // renumbered nor resized: see instance_dump_values() for that.
+    //
+    pub fn instance_dump(&mut self, instance: &InstanceDump) -> io::Result<()> {
+        self.sub_record(DataDumpSubRecordTag::InstanceDump, |buf, ids| {
+            ids.object(buf, instance.object_id);
+            put_u32(buf, instance.strace_num);
+            ids.object(buf, instance.class_id);
+            put_u32(buf, instance.data.len() as u32);
+            buf.extend_from_slice(&instance.data);
+        })
+    }
+
+    // An instance with the given field values instead of its data.
+    pub fn instance_dump_values(
+        &mut self,
+        instance: &InstanceDump,
+        values: &[Value],
+    ) -> io::Result<()> {
+        self.sub_record(DataDumpSubRecordTag::InstanceDump, |buf, ids| {
+            ids.object(buf, instance.object_id);
+            put_u32(buf, instance.strace_num);
+            ids.object(buf, instance.class_id);
+            let length = buf.len();
+            put_u32(buf, 0);
+            for &value in values {
+                ids.value(buf, value);
+            }
+            let bytes = (buf.len() - length - 4) as u32;
+            buf[length..length + 4].copy_from_slice(&bytes.to_be_bytes());
+        })
+    }
+
+    pub fn object_array_dump(&mut self, array: &ObjectArrayDump) -> io::Result<()> {
+        self.sub_record(DataDumpSubRecordTag::ObjectArrayDump, |buf, ids| {
+            ids.object(buf, array.object_id);
+            put_u32(buf, array.strace_num);
+            put_u32(buf, array.elements.len() as u32);
+            ids.object(buf, array.class_id);
+            for &element in &array.elements {
+                ids.object(buf, element);
+            }
+        })
+    }
+
+    pub fn primitive_array_dump(&mut self, array: &PrimitiveArrayDump) -> io::Result<()> {
+        self.sub_record(DataDumpSubRecordTag::PrimitiveArrayDump, |buf, ids| {
+            ids.object(buf, array.object_id);
+            put_u32(buf, array.strace_num);
+            put_u32(buf, array.length);
+            buf.push(array.element_tag as u8);
+            buf.extend_from_slice(&array.data);
+        })
+    }
+
+    pub fn object(&mut self, object: &HeapObject) -> io::Result<()> {
+        match object {
+            HeapObject::Class(c) => self.class_dump(c),
+            HeapObject::Instance(i) => self.instance_dump(i),
+            HeapObject::ObjectArray(a) => self.object_array_dump(a),
+            HeapObject::PrimitiveArray(a) => self.primitive_array_dump(a),
+        }
+    }
+
+    // Writes the last segment and HEAP_DUMP_END, and gives the output back.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.record(RecordTag::HeapDumpEnd as u8, &[])?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+//
+// A whole snapshot: its strings, classes, threads and stack traces, then
+// the class dumps, the GC roots and the other objects, the order in which
+// HotSpot writes them. ALLOC_SITES records, only found in the dumps of the
+// old HPROF agent, are left out, and strings are written as decoded, with
+// what wasn't valid UTF-8 (the modified UTF-8 of Java) replaced.
+//
+pub fn write_snapshot<W: Write>(snapshot: &Snapshot, out: W) -> io::Result<W> {
+    write_snapshot_with(snapshot, out, snapshot.id_size(), Renumbering::default())
+}
+
+//
+// The same with `id_size`-byte identifiers, renumbered. Instances are then
+// written from their decoded fields, what their data holds beyond the
+// fields of their classes being dropped, and symbols renumbered to the
+// same identifier are only written once (the first by new identifier).
+//
+pub fn write_snapshot_with<W: Write>(
+    snapshot: &Snapshot,
+    out: W,
+    id_size: u32,
+    renumbering: Renumbering,
+) -> io::Result<W> {
+    let as_is = id_size == snapshot.id_size() && renumbering.objects.is_empty();
+    let mut writer = Writer::new(out, id_size, snapshot.header.timestamp_ms())?;
+
+    let mut strings: Vec<(u64, u64, &str)> = snapshot
+        .strings
+        .iter()
+        .filter(|(id, _)| !renumbering.dropped_symbols.contains(id))
+        .map(|(id, value)| (renumbering.symbol(id), id, value))
+        .collect();
+    strings.sort_unstable();
+    strings.dedup_by_key(|s| s.0);
+    let mut serials: Vec<&u32> = snapshot.classes.keys().collect();
+    serials.sort_unstable();
+    let mut frames: Vec<&StackFrameRecord> = snapshot.frames.values().collect();
+    frames.sort_unstable_by_key(|f| renumbering.frame(f.frame_id));
+    writer.renumber(renumbering);
+
+    for (_, id, value) in strings {
+        writer.utf8_string(id, value)?;
+    }
+    for serial in serials {
+        writer.load_class(&snapshot.classes[serial])?;
+    }
+    for thread in &snapshot.threads {
+        writer.start_thread(thread)?;
+    }
+    for frame in frames {
+        writer.stack_frame(frame)?;
+    }
+    for trace in &snapshot.traces {
+        writer.stack_trace(trace)?;
+    }
+
+    let is_class = |o: &&HeapObject| matches!(o, HeapObject::Class(_));
+    for object in snapshot.objects.iter().filter(is_class) {
+        writer.object(object)?;
+    }
+    for root in &snapshot.roots {
+        writer.root(root)?;
+    }
+    for object in snapshot.objects.iter().filter(|o| !is_class(o)) {
+        match object {
+            HeapObject::Instance(i) if !as_is => {
+                let values: Vec<Value> = snapshot
+                    .instance_fields(i)
+                    .into_iter()
+                    .map(|f| f.value)
+                    .collect();
+                writer.instance_dump_values(i, &values)?;
+            }
+            _ => writer.object(object)?,
+        }
+    }
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache;
+    use crate::graph::Graph;
+    use crate::retained;
+    use crate::rewrite;
+    use crate::sizes::SizeModel;
+    use crate::split;
+    use crate::synthetic::{self, Options, Shape};
+
+    use std::fs;
+
+    fn temp(name: &str) -> String {
+        let file = format!("hprof-cat-writer-{}-{}.hprof", std::process::id(), name);
+        std::env::temp_dir()
+            .join(file)
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    // A dump written to a file and loaded back.
+    fn reload(bytes: &[u8], name: &str) -> Snapshot {
+        let path = temp(name);
+        fs::write(&path, bytes).unwrap();
+        let snapshot = Snapshot::load(&path);
+        fs::remove_file(&path).unwrap();
+        snapshot
+    }
+
+    fn generated(shape: Shape, seed: u64) -> Vec<u8> {
+        let options = Options {
+            classes: 20,
+            objects: 3000,
+            seed,
+            shape,
+            depth: 100,
+            ..Options::default()
+        };
+        synthetic::generate(&options, Vec::new()).unwrap()
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Summary {
+        // The counts of the records outside the heap dump segments.
+        records: Vec<(u8, u64)>,
+        objects: usize,
+        roots: usize,
+        // By class: instances, shallow and retained bytes.
+        classes: Vec<(String, u64, u64, u64)>,
+        retained: Vec<u64>,
+    }
+
+    // What a rewrite must leave as it is, whatever the ids. Sizes are
+    // estimated with compressed oops, which don't depend on the ids.
+    fn summary(mut snapshot: Snapshot) -> Summary {
+        snapshot.set_size_model(SizeModel::CompressedOops);
+        let graph = Graph::build(&snapshot);
+        let (tree, retained) = cache::retained_sizes(&snapshot, &graph);
+        let mut classes: Vec<_> = retained::retained_by_class(&snapshot, &tree, &retained)
+            .into_iter()
+            .map(|g| (g.name, g.instances, g.shallow, g.retained))
+            .collect();
+        classes.sort();
+        let mut records: Vec<(u8, u64)> = snapshot
+            .record_counts
+            .iter()
+            .map(|(&tag, &count)| (tag, count))
+            .filter(|&(tag, _)| {
+                tag != RecordTag::HeapDump as u8
+                    && tag != RecordTag::HeapDumpSegment as u8
+                    && tag != RecordTag::HeapDumpEnd as u8
+            })
+            .collect();
+        records.sort();
+        let mut retained = retained;
+        retained.sort_unstable();
+        Summary {
+            records,
+            objects: snapshot.objects.len(),
+            roots: snapshot.roots.len(),
+            classes,
+            retained,
+        }
+    }
+
+    // Ids from 2^40 up, as the addresses of a large heap.
+    fn widened(snapshot: &Snapshot) -> Vec<u8> {
+        let wide =
+            |ids: &mut dyn Iterator<Item = u64>| ids.map(|id| (id, (1 << 40) + id)).collect();
+        let renumbering = Renumbering {
+            objects: wide(&mut snapshot.objects.iter().map(HeapObject::object_id)),
+            symbols: wide(&mut snapshot.strings.iter().map(|(id, _)| id)),
+            frames: wide(&mut snapshot.frames.keys().copied()),
+            dropped_symbols: IdSet::default(),
+        };
+        write_snapshot_with(snapshot, Vec::new(), 8, renumbering).unwrap()
+    }
+
+    #[test]
+    fn written_dumps_load_the_same() {
+        for (shape, seed) in [(Shape::Mixed, 1), (Shape::Strings, 2), (Shape::Deep, 3)] {
+            let original = reload(&generated(shape, seed), "original");
+            let copy = write_snapshot(&original, Vec::new()).unwrap();
+            let wide = widened(&original);
+            let expected = summary(original);
+            assert!(expected.objects > 3000 && expected.retained.last() > Some(&0));
+
+            assert_eq!(summary(reload(&copy, "copy")), expected);
+            let wide = reload(&wide, "wide");
+            assert!(wide.objects.iter().all(|o| o.object_id() > 1 << 40));
+            assert_eq!(summary(wide), expected);
+        }
+    }
+
+    #[test]
+    fn narrowed_dumps_load_the_same() {
+        for (shape, seed) in [(Shape::Mixed, 1), (Shape::Strings, 2), (Shape::Deep, 3)] {
+            let original = reload(
+                &widened(&reload(&generated(shape, seed), "generated")),
+                "wide",
+            );
+            let renumbering = rewrite::narrow_ids(&original).unwrap();
+            assert!(!renumbering.objects.is_empty() && !renumbering.symbols.is_empty());
+            let narrowed = write_snapshot_with(&original, Vec::new(), 4, renumbering).unwrap();
+            let expected = summary(original);
+
+            let narrowed = reload(&narrowed, "narrowed");
+            assert_eq!(narrowed.id_size(), 4);
+            assert!(narrowed
+                .objects
+                .iter()
+                .all(|o| o.object_id() <= u32::MAX as u64));
+            assert_eq!(summary(narrowed), expected);
+        }
+    }
+
+    #[test]
+    fn compacted_symbols_load_the_same() {
+        let mut original = reload(&generated(Shape::Mixed, 4), "original");
+        let records = original.record_counts[&(RecordTag::Utf8String as u8)];
+        // A symbol that nothing refers to, and one with the text of
+        // another, that a class is renamed to.
+        let mut serials: Vec<u32> = original.classes.keys().copied().collect();
+        serials.sort_unstable();
+        let named = original.classes[&serials[serials.len() - 2]].strname_id;
+        let renamed = *serials.last().unwrap();
+        let name = original.string(named).to_string();
+        original.strings.insert(1 << 40, &name);
+        original.strings.insert((1 << 40) + 1, "unreferenced");
+        original.classes.get_mut(&renamed).unwrap().strname_id = 1 << 40;
+
+        let mut renumbering = Renumbering::default();
+        let compaction = rewrite::compact_symbols(&original, &mut renumbering);
+        // The old name of the renamed class isn't referenced any more.
+        assert_eq!((compaction.duplicates, compaction.unreferenced), (1, 2));
+        let compacted = write_snapshot_with(&original, Vec::new(), 8, renumbering).unwrap();
+        let compacted = reload(&compacted, "compacted");
+        assert_eq!(compacted.classes[&renamed].strname_id, named);
+        assert!(compacted.strings.get(1 << 40).is_none());
+        assert!(compacted.strings.get((1 << 40) + 1).is_none());
+
+        let expected = summary(original);
+        let mut compacted = summary(compacted);
+        for (tag, count) in &mut compacted.records {
+            if *tag == RecordTag::Utf8String as u8 {
+                assert_eq!(*count, records + 2 - 3);
+                *count = records;
+            }
+        }
+        assert_eq!(compacted, expected);
+    }
+
+    fn split_pieces(path: &str, prefix: &str) -> Vec<String> {
+        split::split(path, prefix.trim_end_matches(".hprof"))
+            .unwrap()
+            .into_iter()
+            .map(|(piece, _)| piece)
+            .collect()
+    }
+
+    #[test]
+    fn split_dumps_join_back() {
+        let bytes = generated(Shape::Mixed, 5);
+        let path = temp("whole");
+        fs::write(&path, &bytes).unwrap();
+        let prefix = temp("piece");
+        let pieces = split_pieces(&path, &prefix);
+        assert!(pieces.len() > 1);
+
+        let whole = Snapshot::load(&path);
+        let mut objects = 0;
+        for piece in &pieces {
+            let piece = Snapshot::load(piece);
+            assert_eq!(piece.strings.len(), whole.strings.len());
+            assert_eq!(piece.classes.len(), whole.classes.len());
+            objects += piece.objects.len();
+        }
+        assert_eq!(objects, whole.objects.len());
+
+        // The records outside the heap dump are gathered before the segments.
+        let joined = split::join(&pieces, Vec::new()).unwrap();
+        assert_eq!(joined.len(), bytes.len());
+        assert_eq!(summary(reload(&joined, "joined")), summary(whole));
+        fs::write(&path, &joined).unwrap();
+        let pieces = split_pieces(&path, &prefix);
+        assert!(split::join(&pieces, Vec::new()).unwrap() == joined);
+        fs::remove_file(&path).unwrap();
+        for piece in &pieces {
+            fs::remove_file(piece).unwrap();
+        }
+    }
+}